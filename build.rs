@@ -0,0 +1,50 @@
+//! Embeds the `up.sql` of every migration as plain text.
+//!
+//! `diesel_migrations::embed_migrations!` embeds migrations for *running* them, but doesn't expose
+//! their SQL. The CLI needs the exact text to export it for DBA review and hash-compare it later,
+//! so we generate a `(name, sql)` table here from the same directory.
+//!
+//! It also passes the target triple and profile on as `BUILD_TARGET`/`BUILD_PROFILE`, the build
+//! info recorded in export manifests.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+    println!(
+        "cargo:rustc-env=BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap()
+    );
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let migrations_dir = Path::new(&manifest_dir).join("db/migrations");
+    println!("cargo:rerun-if-changed={}", migrations_dir.display());
+
+    let mut entries: Vec<_> = fs::read_dir(&migrations_dir)
+        .expect("db/migrations must exist")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("up.sql").is_file())
+        .collect();
+    entries.sort();
+
+    let mut generated = String::from("pub static EMBEDDED_SQL: &[(&str, &str)] = &[\n");
+    for path in entries {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let up = path.join("up.sql");
+        println!("cargo:rerun-if-changed={}", up.display());
+        generated.push_str(&format!(
+            "    ({:?}, include_str!({:?})),\n",
+            name,
+            up.display().to_string()
+        ));
+    }
+    generated.push_str("];\n");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations_sql.rs");
+    fs::write(out, generated).unwrap();
+}
@@ -0,0 +1,398 @@
+//! Request and response bodies of the listener's HTTP API.
+//!
+//! Both the server (`voting-dapp-listener`) and the client (`voting-dapp-client`) use these
+//! types, so the two can't drift apart. Pubkeys are base58 strings, timestamps RFC 3339.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Query string of `GET /polls`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PageParams {
+    /// Page size, 50 by default and at most 500.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    /// Only polls of this program (base58).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+}
+
+/// Query string of `GET /polls/{poll_id}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProgramParams {
+    /// Required when the same `poll_id` is indexed for several programs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// Include the factors behind the poll's completeness score.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub explain: Option<bool>,
+}
+
+/// Query string of `GET /search`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchParams {
+    /// Words to look for in poll names and descriptions, in web search syntax
+    /// (`"exact phrase"`, `or`, `-excluded`).
+    pub q: String,
+    /// Only polls of this program (base58); all programs by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// `poll`, `candidate` or `any` (the default). Only polls are indexed for now, so
+    /// `candidate` is refused.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Number of hits, 20 by default and at most 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+/// Query string of `GET /feed.atom` and `GET /polls/{poll_id}/feed.atom`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeedParams {
+    /// Only events of this program (base58); required for a poll feed when the same `poll_id`
+    /// is indexed for several programs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+    /// Number of entries, at most the listener's `--feed-max-entries` (also the default).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+/// Response body of `GET /search`: the best hits first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+}
+
+/// A poll matching a search.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// What matched; always `poll` until candidates are indexed.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Relevance, higher is better; only meaningful relative to the other hits of a search.
+    pub score: f64,
+    pub poll: Poll,
+}
+
+/// Query string of `GET /debug/pprof/profile` (listener built with the `profiling` feature and
+/// started with `--enable-profiling`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileParams {
+    /// How long to sample, 30 by default and at most 300.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<u64>,
+    /// `pprof` (protobuf, the default) or `flamegraph` (SVG).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+/// Response body of `GET /polls`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollPage {
+    pub limit: i64,
+    pub offset: i64,
+    pub polls: Vec<Poll>,
+}
+
+/// An indexed poll, as returned by `GET /polls` and `GET /polls/{poll_id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub poll_id: i64,
+    pub poll_owner: String,
+    pub poll_name: String,
+    pub poll_description: String,
+    pub poll_start: i64,
+    pub poll_end: i64,
+    pub candidate_amount: i64,
+    pub candidate_winner: String,
+    /// `draft`, `upcoming`, `active`, `ended`, `finalized` or `closed`.
+    pub lifecycle: String,
+    pub last_slot: i64,
+    /// `None` for polls indexed before program IDs were tracked.
+    pub program_id: Option<String>,
+    /// The poll account; `None` for polls indexed before it was tracked.
+    pub account_pubkey: Option<String>,
+    /// When the poll was first indexed.
+    pub first_seen_at: DateTime<Utc>,
+    /// When an account update was last written to the poll.
+    pub last_updated_at: DateTime<Utc>,
+    /// Open operator annotations; absent when the server hides them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
+    /// How far the indexed data can be trusted; only on `GET /polls/{poll_id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completeness: Option<Completeness>,
+}
+
+/// A poll's completeness score: 100 when no signal casts doubt on its data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completeness {
+    pub score: u8,
+    /// What lowered the score, largest penalty first; only with `?explain=true`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub factors: Option<Vec<CompletenessFactor>>,
+}
+
+/// One signal that lowered a completeness score.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletenessFactor {
+    /// `checksum_mismatch`, `no_checksum`, `legacy_row`, `critical_annotation`,
+    /// `warning_annotation` or `anomaly`.
+    pub factor: String,
+    pub penalty: u32,
+    pub detail: String,
+}
+
+/// An operator note on a poll.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: i32,
+    pub program_id: Option<String>,
+    pub poll_id: i64,
+    pub author: String,
+    pub text: String,
+    /// `info`, `warning` or `critical`.
+    pub severity: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` while the annotation is open.
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// A candidate of a poll, read from its account with the program's IDL.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub name: String,
+    pub votes: u64,
+    /// The candidate account.
+    pub account: String,
+    pub last_slot: i64,
+}
+
+/// Response body of `GET /polls/{poll_id}/candidates`: most votes first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollCandidates {
+    pub poll_id: i64,
+    pub program_id: Option<String>,
+    pub candidates: Vec<Candidate>,
+}
+
+/// Response body of `GET /polls/{poll_id}/results`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollResults {
+    pub poll_id: i64,
+    pub program_id: Option<String>,
+    /// The poll's lifecycle, see [`Poll::lifecycle`].
+    pub lifecycle: String,
+    pub total_votes: u64,
+    /// Most votes first.
+    pub candidates: Vec<CandidateResult>,
+    /// Accounts of the candidates with the most votes: several on a tie, none before the
+    /// first vote.
+    pub leaders: Vec<String>,
+    /// Account of the declared winner, once the program has set one.
+    pub winner: Option<String>,
+    /// Number of candidates the poll account declares; more than `candidates` holds when some
+    /// aren't indexed.
+    pub candidate_amount: i64,
+}
+
+/// A candidate's votes in [`PollResults`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CandidateResult {
+    pub name: String,
+    pub account: String,
+    pub votes: u64,
+    /// Share of the poll's votes, in percent (0 before the first vote).
+    pub percentage: f64,
+}
+
+/// Query string of `GET /live`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LiveParams {
+    /// Only updates of this program (base58); all programs by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+}
+
+/// A text message of the `GET /live` websocket: an account the listener just ingested, sent
+/// before it's necessarily written to the database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveUpdate {
+    /// A poll account's new state.
+    Poll(LivePoll),
+    /// A Delegation account's new state (program v3).
+    Delegation(LiveDelegation),
+    /// An account decoded with its program's IDL (`--idl`): candidates, votes, ...
+    Account(LiveAccount),
+    /// The connection fell behind: `missed` updates were dropped for it.
+    Lagged { missed: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LivePoll {
+    pub program_id: Option<String>,
+    pub account: Option<String>,
+    pub poll_id: i64,
+    pub poll_owner: String,
+    pub poll_name: String,
+    pub poll_description: String,
+    pub poll_start: i64,
+    pub poll_end: i64,
+    pub candidate_amount: i64,
+    pub candidate_winner: String,
+    pub last_slot: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveDelegation {
+    pub program_id: Option<String>,
+    pub account: Option<String>,
+    pub poll_id: i64,
+    pub delegator: String,
+    pub delegate: String,
+    /// Unix timestamp after which the delegation no longer applies; `None` when it doesn't
+    /// expire.
+    pub expiry: Option<i64>,
+    pub last_slot: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveAccount {
+    pub program_id: Option<String>,
+    pub account: Option<String>,
+    /// The account's name in the IDL (e.g. `Candidate`, `Vote`).
+    pub account_type: String,
+    /// The account's fields, decoded as described by the IDL.
+    pub data: serde_json::Value,
+    pub last_slot: i64,
+}
+
+/// Response body of `GET /health` (status 200 when `ok`, 503 when `degraded`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// `ok` or `degraded`.
+    pub status: String,
+    /// The listener runs with `--read-only`: it writes nothing to the database.
+    #[serde(default)]
+    pub read_only: bool,
+    pub websocket: WebsocketHealth,
+    pub database: DatabaseHealth,
+    pub lifecycle: LifecycleHealth,
+    /// Warm-up validation of each program's subscriptions, by program ID.
+    pub warmup: BTreeMap<String, WarmupReport>,
+    #[serde(default)]
+    pub rpc_filters: RpcFilterHealth,
+    /// Usage of every table and program with a storage quota (`--quota-rows`, `--quota-bytes`),
+    /// as of the last check.
+    #[serde(default)]
+    pub storage_quotas: Vec<StorageQuotaHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebsocketHealth {
+    pub connected: bool,
+    pub last_slot: u64,
+    /// The configured endpoints (`--ws-url`, then `--fallback-ws-urls`), in that order.
+    #[serde(default)]
+    pub endpoints: Vec<WebsocketEndpointHealth>,
+}
+
+/// A websocket endpoint the listener can fail over to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebsocketEndpointHealth {
+    /// The URL without its query string.
+    pub endpoint: String,
+    /// Whether the listener is subscribed through it (or was, when disconnected).
+    pub current: bool,
+    /// Disconnections and failed subscriptions in the 10 minutes before the listener last
+    /// connected or failed over.
+    pub recent_failures: usize,
+    /// Unix time until which it's skipped after a failure.
+    pub cooldown_until: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseHealth {
+    pub reachable: bool,
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LifecycleHealth {
+    /// Lifecycle flaps suppressed by the clock skew tolerance since startup.
+    pub suppressed_flaps: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpcFilterHealth {
+    /// The websocket endpoint found ignoring the `--only` memcmp filters, if any. Every message
+    /// is then checked client-side.
+    pub ignored_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageQuotaHealth {
+    /// `polls`, `delegations` or `events`.
+    pub table: String,
+    /// Program ID (base58).
+    pub program: String,
+    pub rows: i64,
+    /// The program's share of the table's on-disk size, estimated from its share of the rows.
+    pub estimated_bytes: i64,
+    pub max_rows: Option<i64>,
+    pub max_bytes: Option<i64>,
+    pub exceeded: bool,
+    /// `warn`, `stop` or `prune`, what happens while the quota is exceeded.
+    pub action: String,
+    /// Updates of this table are dropped for this program (`stop`) until it's back under quota.
+    pub indexing_stopped: bool,
+}
+
+/// Outcome of a warm-up phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WarmupState {
+    /// Still evaluating the first messages.
+    Pending,
+    Passed,
+    /// Too few messages were recognized: most likely the program ID points at another program.
+    Failed,
+    /// The window elapsed without any message, so there is nothing to judge.
+    Inconclusive,
+}
+
+/// A discriminator seen during warm-up and how many messages carried it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscriminatorCount {
+    pub discriminator: [u8; 8],
+    pub count: usize,
+}
+
+/// Result of a warm-up phase, logged and reported by `/health`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmupReport {
+    pub state: WarmupState,
+    pub messages: usize,
+    /// Messages with a known discriminator that decoded successfully.
+    pub recognized: usize,
+    pub ratio: f64,
+    /// Most common discriminators among the unrecognized messages, most frequent first.
+    pub unrecognized_discriminators: Vec<DiscriminatorCount>,
+}
+
+/// Body of every error response (4xx and 5xx).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub error: String,
+    /// Stable catalog code, e.g. `E0402_POLL_NOT_FOUND` (`cli explain <CODE>` describes it).
+    /// Missing from servers older than the catalog.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
@@ -0,0 +1,35 @@
+//! Prints the listener's health and every indexed poll.
+//!
+//! ```bash
+//! cargo run -p voting-dapp-client --example poll_report -- http://localhost:8080
+//! ```
+
+use futures::TryStreamExt;
+use voting_dapp_client::{Client, ClientError, RetryPolicy};
+
+#[tokio::main]
+async fn main() -> Result<(), ClientError> {
+    let base_url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "http://localhost:8080".to_string());
+    let client = Client::new(base_url)?.with_retry(RetryPolicy::default());
+
+    let health = client.health().await?;
+    println!(
+        "status: {} (websocket connected: {}, last slot: {}{})",
+        health.status,
+        health.websocket.connected,
+        health.websocket.last_slot,
+        if health.read_only { ", read-only" } else { "" }
+    );
+
+    let polls: Vec<_> = client.all_polls(None).try_collect().await?;
+    for poll in &polls {
+        println!(
+            "#{:<6} {:<10} {} ({} candidates)",
+            poll.poll_id, poll.lifecycle, poll.poll_name, poll.candidate_amount
+        );
+    }
+    println!("{} polls", polls.len());
+    Ok(())
+}
@@ -0,0 +1,442 @@
+//! Typed async client for the listener's read-only HTTP API.
+//!
+//! Request and response bodies are the server's own types (re-exported from
+//! `voting-dapp-api-types`), so a client built from this workspace always matches the server.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), voting_dapp_client::ClientError> {
+//! use voting_dapp_client::{Client, RetryPolicy};
+//!
+//! let client = Client::new("http://localhost:8080")?.with_retry(RetryPolicy::default());
+//! let poll = client.get_poll(1, None).await?;
+//! println!("{} is {}", poll.poll_name, poll.lifecycle);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use futures::stream::{self, Stream};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+pub use voting_dapp_api_types as types;
+pub use voting_dapp_api_types::{
+    Annotation, Candidate, CandidateResult, FeedParams, HealthReport, LiveUpdate, PageParams, Poll,
+    PollCandidates, PollPage, PollResults, ProfileParams, SearchParams, SearchResults,
+};
+use voting_dapp_api_types::{ErrorBody, ProgramParams};
+
+/// Page size used by [`Client::all_polls`] (the server's maximum).
+const ALL_POLLS_PAGE_SIZE: i64 = 500;
+
+/// Errors returned by [`Client`].
+#[derive(Debug)]
+pub enum ClientError {
+    /// The server answered with an error status. `message` is the `error` field of its body,
+    /// or the raw body when it wasn't the API's error format (e.g. from a proxy); `code` is the
+    /// error's stable catalog code, when the server sent one.
+    Api {
+        status: StatusCode,
+        code: Option<String>,
+        message: String,
+    },
+    /// The request couldn't be sent or the response couldn't be read.
+    Http(reqwest::Error),
+    /// The response body didn't match the expected type.
+    Decode(reqwest::Error),
+    /// The base URL is invalid.
+    InvalidUrl(String),
+    /// The `/live` websocket couldn't be opened, or failed.
+    WebSocket(Box<tungstenite::Error>),
+    /// A `/live` message wasn't a [`LiveUpdate`].
+    InvalidUpdate(serde_json::Error),
+}
+
+impl ClientError {
+    /// The HTTP status of an [`ClientError::Api`] error.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// The catalog code of an [`ClientError::Api`] error, e.g. `E0402_POLL_NOT_FOUND`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            ClientError::Api { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the server reported that the resource doesn't exist (404).
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(StatusCode::NOT_FOUND)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Api {
+                status,
+                code: Some(code),
+                message,
+            } => write!(f, "API error {} ({}): {}", status, code, message),
+            ClientError::Api {
+                status,
+                code: None,
+                message,
+            } => write!(f, "API error {}: {}", status, message),
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Decode(e) => write!(f, "unexpected response body: {}", e),
+            ClientError::InvalidUrl(url) => write!(f, "invalid base URL '{}'", url),
+            ClientError::WebSocket(e) => write!(f, "live updates failed: {}", e),
+            ClientError::InvalidUpdate(e) => write!(f, "unexpected live update: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Http(e) | ClientError::Decode(e) => Some(e),
+            ClientError::WebSocket(e) => Some(e.as_ref()),
+            ClientError::InvalidUpdate(e) => Some(e),
+            ClientError::InvalidUrl(_) | ClientError::Api { .. } => None,
+        }
+    }
+}
+
+/// Retries of failed requests. Only transport errors (connection refused, timeouts) and
+/// `502`/`503`/`504` are retried; every API endpoint is a read, so that's always safe.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled after each one.
+    pub initial_backoff: Duration,
+    /// Upper bound of the wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A single attempt per request (what [`Client::new`] uses).
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Client of the listener's HTTP API (`--http-port`).
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// Creates a client for the API served at `base_url` (e.g. `http://indexer:8080`).
+    pub fn new(base_url: impl Into<String>) -> Result<Self, ClientError> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Like [`Client::new`], with a preconfigured `reqwest` client (timeouts, proxies, ...).
+    pub fn with_http_client(
+        base_url: impl Into<String>,
+        http: reqwest::Client,
+    ) -> Result<Self, ClientError> {
+        let base_url = base_url.into();
+        if reqwest::Url::parse(&base_url).is_err() {
+            return Err(ClientError::InvalidUrl(base_url));
+        }
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http,
+            retry: RetryPolicy::none(),
+        })
+    }
+
+    /// Retries failed requests according to `retry`.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// `GET /polls`: one page of polls, ordered by `poll_id`.
+    pub async fn list_polls(&self, params: &PageParams) -> Result<PollPage, ClientError> {
+        self.get_json(|http| http.get(self.url("/polls")).query(params))
+            .await
+    }
+
+    /// `GET /search`: polls whose name or description match `params.q`, best hits first.
+    pub async fn search(&self, params: &SearchParams) -> Result<SearchResults, ClientError> {
+        self.get_json(|http| http.get(self.url("/search")).query(params))
+            .await
+    }
+
+    /// `GET /polls/{poll_id}`. `program` (base58) is required when the same `poll_id` is
+    /// indexed for several programs; a poll that isn't indexed is a 404 (see
+    /// [`ClientError::is_not_found`]).
+    pub async fn get_poll(&self, poll_id: i64, program: Option<&str>) -> Result<Poll, ClientError> {
+        self.get_poll_json(&format!("/polls/{}", poll_id), program)
+            .await
+    }
+
+    /// `GET /polls/{poll_id}/candidates`: the poll's indexed candidates, most votes first.
+    /// `program` works like in [`Client::get_poll`].
+    pub async fn candidates(
+        &self,
+        poll_id: i64,
+        program: Option<&str>,
+    ) -> Result<PollCandidates, ClientError> {
+        self.get_poll_json(&format!("/polls/{}/candidates", poll_id), program)
+            .await
+    }
+
+    /// `GET /polls/{poll_id}/results`: vote shares, leaders and the declared winner.
+    /// `program` works like in [`Client::get_poll`].
+    pub async fn results(
+        &self,
+        poll_id: i64,
+        program: Option<&str>,
+    ) -> Result<PollResults, ClientError> {
+        self.get_poll_json(&format!("/polls/{}/results", poll_id), program)
+            .await
+    }
+
+    /// Every indexed poll (optionally of one program), fetched page by page as the stream is
+    /// consumed. Polls indexed while iterating may be missed or seen twice.
+    pub fn all_polls(
+        &self,
+        program: Option<String>,
+    ) -> impl Stream<Item = Result<Poll, ClientError>> + '_ {
+        let pages = stream::try_unfold(Some(0), move |offset| {
+            let program = program.clone();
+            async move {
+                let Some(offset) = offset else {
+                    return Ok(None);
+                };
+                let params = PageParams {
+                    limit: Some(ALL_POLLS_PAGE_SIZE),
+                    offset: Some(offset),
+                    program,
+                };
+                let page = self.list_polls(&params).await?;
+                let next = (page.polls.len() as i64 == page.limit)
+                    .then_some(offset + page.polls.len() as i64);
+                Ok(Some((page.polls, next)))
+            }
+        });
+        pages
+            .map_ok(|polls| stream::iter(polls.into_iter().map(Ok)))
+            .try_flatten()
+    }
+
+    /// `GET /health`. A degraded listener answers 503 with the same body, so that's returned
+    /// as a report too; check its `status`.
+    pub async fn health(&self) -> Result<HealthReport, ClientError> {
+        let response = self
+            .send(
+                |http| http.get(self.url("/health")),
+                &[StatusCode::SERVICE_UNAVAILABLE],
+            )
+            .await?;
+        response.json().await.map_err(ClientError::Decode)
+    }
+
+    /// `GET /metrics`: the Prometheus text exposition.
+    pub async fn metrics(&self) -> Result<String, ClientError> {
+        let response = self
+            .send(|http| http.get(self.url("/metrics")), &[])
+            .await?;
+        response.text().await.map_err(ClientError::Http)
+    }
+
+    /// `GET /feed.atom`, or `GET /polls/{poll_id}/feed.atom` with a `poll_id`: the Atom feed of
+    /// recent poll events, as XML.
+    pub async fn feed(
+        &self,
+        poll_id: Option<i64>,
+        params: &FeedParams,
+    ) -> Result<String, ClientError> {
+        let path = match poll_id {
+            Some(poll_id) => format!("/polls/{}/feed.atom", poll_id),
+            None => "/feed.atom".to_string(),
+        };
+        let response = self
+            .send(|http| http.get(self.url(&path)).query(params), &[])
+            .await?;
+        response.text().await.map_err(ClientError::Http)
+    }
+
+    /// `GET /programs/{program_id}/idl`: the latest IDL loaded for a program (base58), a 404 when
+    /// none was (see [`ClientError::is_not_found`]).
+    pub async fn idl(&self, program_id: &str) -> Result<serde_json::Value, ClientError> {
+        self.get_json(|http| http.get(self.url(&format!("/programs/{}/idl", program_id))))
+            .await
+    }
+
+    /// `GET /debug/pprof/profile`: samples the listener's CPU and returns the profile (pprof
+    /// protobuf, or an SVG flamegraph with `format: Some("flamegraph")`). The request lasts as
+    /// long as the sampling, so the HTTP client must not time out sooner.
+    ///
+    /// Only served by a listener built with the `profiling` feature and started with
+    /// `--enable-profiling` (404 otherwise); 429 while another profile is running.
+    pub async fn cpu_profile(&self, params: &ProfileParams) -> Result<Vec<u8>, ClientError> {
+        let response = self
+            .send(
+                |http| http.get(self.url("/debug/pprof/profile")).query(params),
+                &[],
+            )
+            .await?;
+        let body = response.bytes().await.map_err(ClientError::Http)?;
+        Ok(body.to_vec())
+    }
+
+    /// `GET /live`: opens the websocket and returns the updates the listener ingests (of one
+    /// program, base58, or all of them), as they happen. The stream ends when the listener stops
+    /// or the connection closes; a connection that fell behind gets a [`LiveUpdate::Lagged`].
+    ///
+    /// Not retried: a listener without live updates answers 503 (or 404 on older servers).
+    pub async fn live(
+        &self,
+        program: Option<&str>,
+    ) -> Result<impl Stream<Item = Result<LiveUpdate, ClientError>> + Send + 'static, ClientError>
+    {
+        let mut url = reqwest::Url::parse(&self.url("/live"))
+            .map_err(|_| ClientError::InvalidUrl(self.base_url.clone()))?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| ClientError::InvalidUrl(self.base_url.clone()))?;
+        if let Some(program) = program {
+            url.query_pairs_mut().append_pair("program", program);
+        }
+
+        let (socket, _) = match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok(connected) => connected,
+            Err(tungstenite::Error::Http(response)) => {
+                let status = StatusCode::from_u16(response.status().as_u16())
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                let body = response.body().as_deref().unwrap_or_default();
+                return Err(api_error(status, &String::from_utf8_lossy(body)));
+            }
+            Err(e) => return Err(ClientError::WebSocket(Box::new(e))),
+        };
+        Ok(stream::unfold(Some(socket), |socket| async move {
+            let mut socket = socket?;
+            loop {
+                match socket.next().await? {
+                    Ok(Message::Text(text)) => {
+                        let update =
+                            serde_json::from_str(&text).map_err(ClientError::InvalidUpdate);
+                        return Some((update, Some(socket)));
+                    }
+                    Ok(Message::Close(_)) => return None,
+                    // Pings are answered by tungstenite itself.
+                    Ok(_) => continue,
+                    Err(e) => return Some((Err(ClientError::WebSocket(Box::new(e))), None)),
+                }
+            }
+        }))
+    }
+
+    /// `GET path?program=` for the routes of a single poll.
+    async fn get_poll_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        program: Option<&str>,
+    ) -> Result<T, ClientError> {
+        let params = ProgramParams {
+            program: program.map(str::to_string),
+            explain: None,
+        };
+        self.get_json(|http| http.get(self.url(path)).query(&params))
+            .await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+    ) -> Result<T, ClientError> {
+        let response = self.send(request, &[]).await?;
+        response.json().await.map_err(ClientError::Decode)
+    }
+
+    /// Sends a request (rebuilt for every attempt), retrying per the policy. Error statuses,
+    /// except those in `accepted`, are turned into [`ClientError::Api`].
+    async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> RequestBuilder,
+        accepted: &[StatusCode],
+    ) -> Result<reqwest::Response, ClientError> {
+        let mut retry = 0;
+        loop {
+            let result = request(&self.http).send().await;
+            let retryable = match &result {
+                Ok(response) => {
+                    matches!(
+                        response.status(),
+                        StatusCode::BAD_GATEWAY
+                            | StatusCode::SERVICE_UNAVAILABLE
+                            | StatusCode::GATEWAY_TIMEOUT
+                    ) && !accepted.contains(&response.status())
+                }
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            if retryable && retry < self.retry.max_retries {
+                tokio::time::sleep(self.retry.backoff(retry)).await;
+                retry += 1;
+                continue;
+            }
+
+            let response = result.map_err(ClientError::Http)?;
+            let status = response.status();
+            if status.is_success() || accepted.contains(&status) {
+                return Ok(response);
+            }
+            let body = response.text().await.unwrap_or_default();
+            return Err(api_error(status, &body));
+        }
+    }
+}
+
+/// The [`ClientError::Api`] of an error response.
+fn api_error(status: StatusCode, body: &str) -> ClientError {
+    let (code, message) = match serde_json::from_str::<ErrorBody>(body) {
+        Ok(error) => (error.code, error.error),
+        Err(_) => (None, body.to_string()),
+    };
+    ClientError::Api {
+        status,
+        code,
+        message,
+    }
+}
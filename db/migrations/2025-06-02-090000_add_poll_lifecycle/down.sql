@@ -0,0 +1,2 @@
+DROP TABLE anomalies;
+ALTER TABLE polls DROP COLUMN lifecycle;
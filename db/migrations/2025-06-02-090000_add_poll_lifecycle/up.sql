@@ -0,0 +1,25 @@
+-- Persisted lifecycle state (see `PollLifecycle`), maintained by the listener's transition function.
+ALTER TABLE polls ADD COLUMN lifecycle VARCHAR(16) NOT NULL DEFAULT 'draft';
+
+-- Things that should never happen, e.g. an impossible lifecycle transition (closed -> active).
+CREATE TABLE anomalies (
+    id SERIAL PRIMARY KEY,
+    poll_id BIGINT NOT NULL,
+    kind VARCHAR(64) NOT NULL,
+    details TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX anomalies_poll_id_idx ON anomalies (poll_id);
+
+-- Backfill existing rows with the same rules as `LifecycleFacts::derive`.
+-- Timestamps >= 1e11 are milliseconds. Closure can't be known for existing rows.
+UPDATE polls SET lifecycle = CASE
+    WHEN candidate_winner <> decode(repeat('00', 32), 'hex') THEN 'finalized'
+    WHEN EXTRACT(EPOCH FROM NOW()) >=
+        CASE WHEN poll_end >= 100000000000 THEN poll_end / 1000 ELSE poll_end END THEN 'ended'
+    WHEN candidate_amount = 0 THEN 'draft'
+    WHEN EXTRACT(EPOCH FROM NOW()) >=
+        CASE WHEN poll_start >= 100000000000 THEN poll_start / 1000 ELSE poll_start END THEN 'active'
+    ELSE 'upcoming'
+END;
@@ -0,0 +1,4 @@
+-- Slot at which the stored account state was observed.
+-- Upserts only apply when the incoming slot is >= this one, so out-of-order updates can't
+-- overwrite newer state. Existing rows start at 0 so the next update always wins.
+ALTER TABLE polls ADD COLUMN last_slot BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Bytes received from each RPC endpoint per day, split by feature (RPC method).
+-- The listener adds its counters to the current day's row periodically.
+CREATE TABLE bandwidth_usage (
+    day DATE NOT NULL,
+    endpoint TEXT NOT NULL,
+    feature VARCHAR(64) NOT NULL,
+    bytes BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, endpoint, feature)
+);
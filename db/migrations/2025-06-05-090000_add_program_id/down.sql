@@ -0,0 +1,6 @@
+-- Fails if the same poll_id has been indexed for more than one program.
+ALTER TABLE anomalies DROP COLUMN program_id;
+ALTER TABLE polls DROP CONSTRAINT polls_program_id_poll_id_unique;
+ALTER TABLE polls ADD CONSTRAINT polls_poll_id_key UNIQUE (poll_id);
+ALTER TABLE polls ADD CONSTRAINT polls_poll_id_unique UNIQUE (poll_id);
+ALTER TABLE polls DROP COLUMN program_id;
@@ -0,0 +1,11 @@
+-- Program the poll account belongs to (raw 32 bytes), so one database can index several programs.
+-- Rows indexed before this column existed can't be attributed here; they start out empty and are
+-- claimed by the first listener started with a single program ID.
+ALTER TABLE polls ADD COLUMN program_id BYTEA NOT NULL DEFAULT ''::bytea;
+
+-- poll_id is only unique within a program.
+ALTER TABLE polls DROP CONSTRAINT IF EXISTS polls_poll_id_unique;
+ALTER TABLE polls DROP CONSTRAINT IF EXISTS polls_poll_id_key;
+ALTER TABLE polls ADD CONSTRAINT polls_program_id_poll_id_unique UNIQUE (program_id, poll_id);
+
+ALTER TABLE anomalies ADD COLUMN program_id BYTEA NOT NULL DEFAULT ''::bytea;
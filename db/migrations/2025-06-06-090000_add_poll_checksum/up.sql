@@ -0,0 +1,4 @@
+-- xxh3 of the decoded on-chain fields (see `poll_checksum` in src/db/models.rs), written in the
+-- same statement as the row. xxh3 isn't available in SQL, so existing rows start out NULL and
+-- are backfilled by the listener on startup.
+ALTER TABLE polls ADD COLUMN checksum BIGINT;
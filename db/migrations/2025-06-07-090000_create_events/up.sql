@@ -0,0 +1,14 @@
+-- Instruction-level events captured from `logsSubscribe` (`--with-logs`).
+-- `log_index` is the event's position within the transaction, so a redelivered notification
+-- doesn't insert the same event twice.
+CREATE TABLE events (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    signature VARCHAR(88) NOT NULL,
+    log_index INTEGER NOT NULL,
+    slot BIGINT NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT events_signature_log_index_unique UNIQUE (signature, log_index)
+);
@@ -0,0 +1,14 @@
+-- Operator notes attached to polls during incidents. Presentation only: the listener never
+-- reads them when indexing. A poll is identified by (program_id, poll_id), like in `polls`.
+CREATE TABLE annotations (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    poll_id BIGINT NOT NULL,
+    author VARCHAR(64) NOT NULL,
+    text TEXT NOT NULL,
+    severity VARCHAR(16) NOT NULL CHECK (severity IN ('info', 'warning', 'critical')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE INDEX annotations_program_id_poll_id_idx ON annotations (program_id, poll_id);
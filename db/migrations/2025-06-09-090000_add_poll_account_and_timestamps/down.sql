@@ -0,0 +1,3 @@
+ALTER TABLE polls DROP COLUMN last_updated_at;
+ALTER TABLE polls DROP COLUMN first_seen_at;
+ALTER TABLE polls DROP COLUMN account_pubkey;
@@ -0,0 +1,6 @@
+-- Which on-chain account a row was decoded from, and when the listener first saw it and last
+-- wrote an update to it. Rows indexed before these columns existed have no known account
+-- (empty) and get the migration time as both timestamps.
+ALTER TABLE polls ADD COLUMN account_pubkey BYTEA NOT NULL DEFAULT '';
+ALTER TABLE polls ADD COLUMN first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE polls ADD COLUMN last_updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,19 @@
+-- Delegation accounts of program v3: a wallet handing its vote on one poll to another wallet.
+-- One row per account, identified like polls by (program_id, account_pubkey); `last_slot`
+-- guards against out-of-order updates.
+CREATE TABLE delegations (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    account_pubkey BYTEA NOT NULL,
+    poll_id BIGINT NOT NULL,
+    delegator BYTEA NOT NULL,
+    delegate BYTEA NOT NULL,
+    -- Unix timestamp; NULL never expires.
+    expiry BIGINT,
+    last_slot BIGINT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT delegations_program_id_account_pubkey_unique UNIQUE (program_id, account_pubkey)
+);
+
+CREATE INDEX delegations_program_id_poll_id_idx ON delegations (program_id, poll_id);
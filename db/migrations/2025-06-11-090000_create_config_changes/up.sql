@@ -0,0 +1,11 @@
+-- Audit trail of the listener's effective configuration. Each row holds the options that changed
+-- (`{"option": {"old": ..., "new": ...}}`, secrets masked); the first row is diffed against
+-- nothing, so replaying the rows in order rebuilds the config at any point in time.
+CREATE TABLE config_changes (
+    id SERIAL PRIMARY KEY,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- What applied the config, e.g. `startup`.
+    source VARCHAR(32) NOT NULL,
+    changed_by VARCHAR(64) NOT NULL,
+    diff JSONB NOT NULL
+);
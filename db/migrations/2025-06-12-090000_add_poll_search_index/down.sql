@@ -0,0 +1 @@
+DROP INDEX polls_search_idx;
@@ -0,0 +1,7 @@
+-- Full-text search over poll names and descriptions, across programs (`cli search`,
+-- `GET /search`). Names weigh more than descriptions. Queries must repeat this exact expression
+-- (see `db::search_polls`) for the index to be used.
+CREATE INDEX polls_search_idx ON polls USING GIN ((
+    setweight(to_tsvector('english', poll_name), 'A')
+    || setweight(to_tsvector('english', poll_description), 'B')
+));
@@ -0,0 +1,12 @@
+-- IDLs loaded with `--idl`, one row per version: a row is only added when the content differs
+-- from the program's latest version, so the rows of a program are its IDL history.
+CREATE TABLE idls (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    -- sha256 of the IDL as stored (JSON with sorted keys).
+    content_hash BYTEA NOT NULL,
+    idl JSONB NOT NULL,
+    loaded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idls_program_id_idx ON idls (program_id, id);
@@ -0,0 +1,13 @@
+-- Every lifecycle state a poll moved to, written in the same transaction as the `lifecycle`
+-- column. Rows are never updated, so `id` is a stable identifier for public feeds.
+CREATE TABLE lifecycle_transitions (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    poll_id BIGINT NOT NULL,
+    -- NULL when the poll was first indexed.
+    from_state VARCHAR(16),
+    to_state VARCHAR(16) NOT NULL,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX lifecycle_transitions_poll_idx ON lifecycle_transitions (program_id, poll_id, id);
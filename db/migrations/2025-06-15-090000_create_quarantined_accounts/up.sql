@@ -0,0 +1,14 @@
+-- Accounts the writer stopped writing after repeated data errors (see `quarantine`).
+-- A row is removed when the account is released (`cli quarantine release`) or when changed
+-- data for it is written successfully.
+CREATE TABLE quarantined_accounts (
+    account_pubkey BYTEA PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    poll_id BIGINT NOT NULL,
+    -- Consecutive failed writes, including those before the quarantine.
+    failures INTEGER NOT NULL,
+    last_error TEXT NOT NULL,
+    -- Checksum of the data that failed: only different data is tried again.
+    data_checksum BIGINT NOT NULL,
+    quarantined_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
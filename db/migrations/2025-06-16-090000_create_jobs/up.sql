@@ -0,0 +1,17 @@
+-- Background jobs of the listener (see `scheduler`): their schedule, last run and the controls
+-- set with `cli jobs`. Rows are registered by the listener at startup.
+CREATE TABLE jobs (
+    name TEXT PRIMARY KEY,
+    -- Concurrency class: `db-heavy` or `rpc-heavy`.
+    class TEXT NOT NULL,
+    -- `every 60s` or a cron expression.
+    schedule TEXT NOT NULL,
+    paused BOOLEAN NOT NULL DEFAULT false,
+    next_run_at TIMESTAMPTZ,
+    last_started_at TIMESTAMPTZ,
+    last_finished_at TIMESTAMPTZ,
+    -- NULL when the last run succeeded.
+    last_error TEXT,
+    -- Set by `cli jobs run`, cleared once the listener started the run.
+    run_requested_at TIMESTAMPTZ
+);
@@ -0,0 +1 @@
+DROP TABLE idl_accounts;
@@ -0,0 +1,17 @@
+-- Accounts without a table of their own (candidates, votes, anything else a program defines),
+-- decoded from the program's IDL into JSON. One row per account, identified like polls by
+-- (program_id, account_pubkey); `last_slot` guards against out-of-order updates.
+CREATE TABLE idl_accounts (
+    id SERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    account_pubkey BYTEA NOT NULL,
+    -- The account's name in the IDL, e.g. `Candidate`.
+    account_type VARCHAR(64) NOT NULL,
+    data JSONB NOT NULL,
+    last_slot BIGINT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT idl_accounts_program_id_account_pubkey_unique UNIQUE (program_id, account_pubkey)
+);
+
+CREATE INDEX idl_accounts_program_id_account_type_idx ON idl_accounts (program_id, account_type);
@@ -0,0 +1 @@
+DROP TABLE listener_checkpoints;
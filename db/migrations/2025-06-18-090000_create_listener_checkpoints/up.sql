@@ -0,0 +1,7 @@
+-- The last slot up to which the listener has stored every account update of a program. On
+-- restart it only catches up on the accounts changed since, instead of a full backfill.
+CREATE TABLE listener_checkpoints (
+    program_id BYTEA PRIMARY KEY,
+    last_slot BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1 @@
+DROP TABLE decode_failures;
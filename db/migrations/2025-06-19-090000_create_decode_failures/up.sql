@@ -0,0 +1,20 @@
+-- Accounts the listener couldn't decode (a Poll or Delegation that doesn't parse, an account
+-- neither the built-in types nor the program's IDL describe), with their raw data, so nothing is
+-- lost: once the decoder is fixed, `cli decode-failures replay` writes them. One row per account,
+-- holding its newest failing state; replayed rows are removed.
+CREATE TABLE decode_failures (
+    account_pubkey BYTEA PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    slot BIGINT NOT NULL,
+    -- The first 8 bytes of the data (Anchor discriminator).
+    discriminator BYTEA NOT NULL,
+    -- The type the discriminator matched: `poll`, `delegation`, `candidate`, `vote` or `unknown`.
+    account_type VARCHAR(16) NOT NULL,
+    error TEXT NOT NULL,
+    -- The raw account data, base64.
+    data TEXT NOT NULL,
+    first_failed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX decode_failures_program_id_idx ON decode_failures (program_id);
@@ -0,0 +1,15 @@
+-- Every raw account update the listener processed, with `--archive-raw-accounts`, in the order
+-- it processed them. `cli replay` runs the decoder over it again to rebuild the derived tables
+-- after a schema or decoder change. Identical redeliveries are skipped before they get here.
+CREATE TABLE account_raw_history (
+    id BIGSERIAL PRIMARY KEY,
+    program_id BYTEA NOT NULL,
+    account_pubkey BYTEA NOT NULL,
+    slot BIGINT NOT NULL,
+    data BYTEA NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- The backfill and the websocket may both deliver an account at the same slot.
+    CONSTRAINT account_raw_history_account_pubkey_slot_unique UNIQUE (account_pubkey, slot)
+);
+
+CREATE INDEX account_raw_history_program_id_idx ON account_raw_history (program_id, id);
@@ -0,0 +1,3 @@
+ALTER TABLE idl_accounts DROP COLUMN deleted_at;
+ALTER TABLE delegations DROP COLUMN deleted_at;
+ALTER TABLE polls DROP COLUMN deleted_at;
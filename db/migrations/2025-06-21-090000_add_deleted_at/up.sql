@@ -0,0 +1,5 @@
+-- Set when the account behind the row was closed (an update with zero lamports or no data),
+-- instead of leaving its last state looking current. A re-created account clears it again.
+ALTER TABLE polls ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE delegations ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE idl_accounts ADD COLUMN deleted_at TIMESTAMPTZ;
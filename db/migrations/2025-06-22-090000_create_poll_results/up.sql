@@ -0,0 +1,14 @@
+-- Votes per candidate of every poll, derived from the indexed candidate accounts (see
+-- `candidates::poll_candidates`). Refreshed in the same transaction as every candidate or vote
+-- upsert, so reading a poll's results is a single indexed query.
+CREATE TABLE poll_results (
+    program_id BYTEA NOT NULL,
+    poll_id BIGINT NOT NULL,
+    candidate_account BYTEA NOT NULL,
+    candidate_name TEXT NOT NULL,
+    votes BIGINT NOT NULL,
+    -- Slot of the candidate account the count was read from.
+    last_slot BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (program_id, poll_id, candidate_account)
+);
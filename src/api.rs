@@ -0,0 +1,1264 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use hyper::upgrade::Upgraded;
+use hyper_util::rt::TokioIo;
+use solana_sdk::pubkey::Pubkey;
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::WebSocketStream;
+use tracing::{error, warn};
+
+use crate::candidates::{self, Tally};
+use crate::coalesce::SingleFlight;
+use crate::completeness::{self, CompletenessWeights, ScoreChanges};
+use crate::feed::{self, FeedEvent, FeedScope};
+
+use crate::db::db::{
+    check_poll_checksum, get_polls_by_id, get_polls_by_keys, get_results, latest_idl,
+    list_polls_page, open_annotations_for, recent_transitions, record_checksum_mismatches,
+    search_polls, PgPool, PollKey, SearchWeights,
+};
+use crate::db::models::{Annotation, Poll};
+use crate::errors::{self, ErrorCode};
+use crate::live::{self, LiveUpdates};
+use crate::metrics::Metrics;
+use crate::quota::StorageQuotas;
+use crate::warmup::WarmupReport;
+use voting_dapp_api_types::{
+    self as api_types, CandidateResult, DatabaseHealth, ErrorBody, FeedParams, HealthReport,
+    LifecycleHealth, LiveParams, PageParams, PollCandidates, PollPage, PollResults, ProgramParams,
+    RpcFilterHealth, SearchHit, SearchParams, SearchResults, WebsocketEndpointHealth,
+    WebsocketHealth,
+};
+
+/// Page size used when `?limit=` isn't given.
+pub(crate) const DEFAULT_PAGE_SIZE: i64 = 50;
+/// Largest page a client can ask for.
+pub(crate) const MAX_PAGE_SIZE: i64 = 500;
+/// Number of search hits returned when `?limit=` isn't given.
+const DEFAULT_SEARCH_HITS: i64 = 20;
+/// Most search hits a client can ask for.
+const MAX_SEARCH_HITS: i64 = 100;
+/// How long feed readers and proxies may reuse a feed before asking again (with its ETag).
+const FEED_MAX_AGE_SECS: u32 = 60;
+/// How long `/health` waits for a pooled connection before reporting the DB as unreachable.
+const HEALTH_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Live listener status reported by `/health`, updated by the listener loop.
+#[derive(Debug, Default)]
+pub struct ListenerHealth {
+    websocket_connected: AtomicBool,
+    last_slot: AtomicU64,
+    /// Latest warm-up result per program (base58).
+    warmups: Mutex<BTreeMap<String, WarmupReport>>,
+    /// Endpoint found ignoring the subscription filters (see `FilterGuard`).
+    filters_ignored_by: Mutex<Option<String>>,
+    /// Health of the websocket endpoints (see `failover::Endpoints`).
+    websocket_endpoints: Mutex<Vec<WebsocketEndpointHealth>>,
+}
+
+impl ListenerHealth {
+    pub fn set_websocket_connected(&self, connected: bool) {
+        self.websocket_connected.store(connected, Ordering::Relaxed);
+    }
+
+    /// Records the context slot of the latest websocket update.
+    pub fn record_slot(&self, slot: u64) {
+        self.last_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    /// Records the warm-up state of a program's subscriptions.
+    pub fn record_warmup(&self, program_id: &Pubkey, report: WarmupReport) {
+        self.warmups
+            .lock()
+            .unwrap()
+            .insert(program_id.to_string(), report);
+    }
+
+    /// Records the endpoint found ignoring the subscription filters, or `None` after
+    /// switching to another endpoint.
+    pub fn set_filters_ignored_by(&self, endpoint: Option<&str>) {
+        *self.filters_ignored_by.lock().unwrap() = endpoint.map(str::to_string);
+    }
+
+    /// Records the health of the websocket endpoints, after connecting or failing over.
+    pub fn set_websocket_endpoints(&self, endpoints: Vec<WebsocketEndpointHealth>) {
+        *self.websocket_endpoints.lock().unwrap() = endpoints;
+    }
+}
+
+/// Shared state of the HTTP handlers.
+#[derive(Clone)]
+pub struct ApiState {
+    pub pool: PgPool,
+    pub health: Arc<ListenerHealth>,
+    pub metrics: Arc<Metrics>,
+    /// The listener runs with `--read-only`, as reported by `/health`.
+    pub read_only: bool,
+    /// Recompute each returned poll's checksum and report mismatches (debug flag).
+    pub verify_checksums: bool,
+    /// Include open operator annotations in poll responses.
+    pub show_annotations: bool,
+    /// Penalties of the completeness score returned with single polls.
+    pub completeness_weights: CompletenessWeights,
+    /// Last completeness score served for each poll, to log those that changed.
+    pub completeness_changes: Arc<ScoreChanges>,
+    /// Most entries in an Atom feed, and the default number.
+    pub feed_max_entries: i64,
+    /// Shares the work of identical concurrent poll reads; `None` runs every read on its own.
+    pub coalescer: Option<Arc<ReadCoalescer>>,
+    /// Reported by `/health`; `None` when no storage quota is set.
+    pub storage_quotas: Option<Arc<StorageQuotas>>,
+    /// What `/live` streams; `None` leaves the route out.
+    pub live: Option<LiveUpdates>,
+    /// Serves `/debug/pprof/profile`; `None` leaves the route out.
+    #[cfg(feature = "profiling")]
+    pub profiler: Option<Arc<crate::profiling::Profiler>>,
+}
+
+/// A poll read, as far as coalescing is concerned: two requests with the same key get the same
+/// response.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ReadKey {
+    Page {
+        program: Option<Vec<u8>>,
+        limit: i64,
+        offset: i64,
+    },
+    Poll {
+        poll_id: i64,
+        program: Option<Vec<u8>>,
+    },
+}
+
+/// Coalesces identical concurrent poll reads (`GET /polls`, `GET /polls/{poll_id}`), so a burst
+/// of requests for a popular poll runs its queries once instead of once per request.
+/// See [`SingleFlight`]: nothing is cached past the shared execution.
+#[derive(Default)]
+pub struct ReadCoalescer(SingleFlight<ReadKey, Vec<api_types::Poll>>);
+
+/// Builds the read-only API router.
+///
+/// - `GET /polls?limit=&offset=&program=`: one page of polls, ordered by `poll_id`
+/// - `GET /polls/{poll_id}?program=`: a single poll, 404 when it isn't indexed; `program` is
+///   required (400 otherwise) when the same `poll_id` is indexed for several programs
+/// - `GET /polls/{poll_id}/candidates?program=`: the poll's candidates, most votes first;
+///   `GET /polls/{poll_id}/results?program=`: their vote shares, the leaders and the declared
+///   winner. Candidates are indexed only for programs loaded with `--idl`
+/// - `GET /search?q=&program=&type=&limit=`: polls whose name or description match `q`, across
+///   programs unless `program` is given, best hits first
+/// - Polls carry an `annotations` array of open operator notes, unless `show_annotations` is off
+/// - Single polls carry a `completeness` score; `?explain=true` adds the factors behind it
+/// - `GET /feed.atom?program=&limit=`: an Atom feed of recent poll events (created, started,
+///   ended, winner declared); `GET /polls/{poll_id}/feed.atom?program=&limit=` for one poll.
+///   Served with an `ETag`, 304 when it matches `If-None-Match`
+/// - `GET /programs/{program_id}/idl`: the latest IDL loaded with `--idl`, 404 when none was
+/// - `GET /live?program=`: a websocket pushing the polls, delegations and IDL-decoded accounts
+///   (candidates, votes) the listener ingests, as `LiveUpdate` JSON messages; only with live
+///   updates in the state
+/// - `GET /health`: websocket, database and storage quota status, 503 when degraded
+/// - `GET /metrics`: Prometheus metrics of the listener
+/// - `GET /debug/pprof/profile?seconds=&format=`: a CPU profile, only with the `profiling` feature
+///   and a profiler in the state; 429 while another profile runs
+/// - `/graphql`: the same data over GraphQL, with subscriptions to the live updates; only with
+///   the `graphql` feature (see `graphql::router`)
+pub fn router(state: ApiState) -> Router {
+    let router = Router::new()
+        .route("/polls", get(list_polls_handler))
+        .route("/polls/{poll_id}", get(get_poll_handler))
+        .route("/polls/{poll_id}/candidates", get(candidates_handler))
+        .route("/polls/{poll_id}/results", get(results_handler))
+        .route("/polls/{poll_id}/feed.atom", get(poll_feed_handler))
+        .route("/feed.atom", get(feed_handler))
+        .route("/search", get(search_handler))
+        .route("/programs/{program_id}/idl", get(idl_handler))
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler));
+    let router = match state.live {
+        Some(_) => router.route("/live", get(live_handler)),
+        None => router,
+    };
+    #[cfg(feature = "profiling")]
+    let router = match state.profiler {
+        Some(_) => router.route("/debug/pprof/profile", get(profile_handler)),
+        None => router,
+    };
+    #[cfg(feature = "graphql")]
+    let schema = crate::graphql::schema(state.pool.clone(), state.live.clone());
+    let router = router.with_state(state);
+    #[cfg(feature = "graphql")]
+    let router = router.merge(crate::graphql::router(schema));
+    router
+}
+
+/// Serves the API on `listener` until `shutdown` resolves, then finishes in-flight requests.
+pub async fn serve<F>(listener: TcpListener, state: ApiState, shutdown: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    axum::serve(listener, router(state))
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+/// Parses an optional `?program=` pubkey.
+fn parse_program(program: Option<&str>) -> Result<Option<Pubkey>, ApiError> {
+    program
+        .map(|program| {
+            program.parse::<Pubkey>().map_err(|_| {
+                ApiError::BadRequest(
+                    &errors::INVALID_PROGRAM_ID,
+                    format!("'{}' is not a valid program ID", program),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Parses an optional `?program=` pubkey into the raw bytes stored in the `program_id` column.
+fn program_filter(program: Option<&str>) -> Result<Option<Vec<u8>>, ApiError> {
+    Ok(parse_program(program)?.map(|pubkey| pubkey.to_bytes().to_vec()))
+}
+
+/// Converts `polls` to their API representation and attaches their open annotations
+/// (one extra query), or none when they're hidden.
+async fn with_annotations(
+    state: &ApiState,
+    polls: Vec<Poll>,
+) -> Result<Vec<api_types::Poll>, ApiError> {
+    let mut views = polls
+        .iter()
+        .map(Poll::to_dto)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(ApiError::Internal)?;
+    if !state.show_annotations {
+        return Ok(views);
+    }
+    let keys: Vec<PollKey> = polls
+        .iter()
+        .map(|p| (p.program_id.clone(), p.poll_id))
+        .collect();
+    let pool = state.pool.clone();
+    let mut notes = blocking(move || open_annotations_for(&pool, &keys)).await?;
+    for (poll, view) in polls.iter().zip(views.iter_mut()) {
+        let annotations = notes
+            .remove(&(poll.program_id.clone(), poll.poll_id))
+            .unwrap_or_default()
+            .iter()
+            .map(Annotation::to_dto)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(ApiError::Internal)?;
+        view.annotations = Some(annotations);
+    }
+    Ok(views)
+}
+
+async fn list_polls_handler(
+    State(state): State<ApiState>,
+    Query(params): Query<PageParams>,
+) -> Result<Json<PollPage>, ApiError> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let program = program_filter(params.program.as_deref())?;
+
+    let key = ReadKey::Page {
+        program,
+        limit,
+        offset,
+    };
+    let polls = read_polls(&state, key).await?;
+    Ok(Json(PollPage {
+        limit,
+        offset,
+        polls,
+    }))
+}
+
+async fn get_poll_handler(
+    State(state): State<ApiState>,
+    Path(poll_id): Path<i64>,
+    Query(params): Query<ProgramParams>,
+) -> Result<Json<api_types::Poll>, ApiError> {
+    let program = program_filter(params.program.as_deref())?;
+    let mut found = read_polls(&state, ReadKey::Poll { poll_id, program }).await?;
+    // Reads compute the factors either way, so explained and plain requests share one flight.
+    if !params.explain.unwrap_or(false) {
+        for poll in &mut found {
+            if let Some(completeness) = &mut poll.completeness {
+                completeness.factors = None;
+            }
+        }
+    }
+    match found.len() {
+        0 => Err(ApiError::NotFound(
+            &errors::POLL_NOT_FOUND,
+            format!("poll {} not found in the index", poll_id),
+        )),
+        1 => Ok(Json(found.remove(0))),
+        _ => Err(ApiError::BadRequest(
+            &errors::POLL_AMBIGUOUS,
+            format!(
+                "poll {} is indexed for several programs, pass ?program=",
+                poll_id
+            ),
+        )),
+    }
+}
+
+async fn candidates_handler(
+    State(state): State<ApiState>,
+    Path(poll_id): Path<i64>,
+    Query(params): Query<ProgramParams>,
+) -> Result<Json<PollCandidates>, ApiError> {
+    let (poll, tally) = poll_tally(&state, poll_id, params.program.as_deref()).await?;
+    let candidates = tally
+        .candidates
+        .iter()
+        .map(|c| api_types::Candidate {
+            name: c.name.clone(),
+            votes: c.votes,
+            account: c.account.to_string(),
+            last_slot: c.last_slot,
+        })
+        .collect();
+    Ok(Json(PollCandidates {
+        poll_id,
+        program_id: poll
+            .program_pubkey()
+            .map_err(ApiError::Internal)?
+            .map(|p| p.to_string()),
+        candidates,
+    }))
+}
+
+async fn results_handler(
+    State(state): State<ApiState>,
+    Path(poll_id): Path<i64>,
+    Query(params): Query<ProgramParams>,
+) -> Result<Json<PollResults>, ApiError> {
+    let (poll, tally) = poll_tally(&state, poll_id, params.program.as_deref()).await?;
+    let winner = poll.winner_pubkey().map_err(ApiError::Internal)?;
+    let candidates = tally
+        .candidates
+        .iter()
+        .map(|c| CandidateResult {
+            name: c.name.clone(),
+            account: c.account.to_string(),
+            votes: c.votes,
+            percentage: tally.percentage(c),
+        })
+        .collect();
+    Ok(Json(PollResults {
+        poll_id,
+        program_id: poll
+            .program_pubkey()
+            .map_err(ApiError::Internal)?
+            .map(|p| p.to_string()),
+        lifecycle: poll.lifecycle.clone(),
+        total_votes: tally.total_votes,
+        candidates,
+        leaders: tally
+            .leaders()
+            .iter()
+            .map(|c| c.account.to_string())
+            .collect(),
+        // The winner column is all zeros until the program declares one.
+        winner: tally
+            .candidates
+            .iter()
+            .any(|c| c.account == winner)
+            .then(|| winner.to_string()),
+        candidate_amount: poll.candidate_amount,
+    }))
+}
+
+/// Finds a poll like `GET /polls/{poll_id}` does (404 when it isn't indexed, 400 when
+/// `program` is needed) and tallies its stored results.
+async fn poll_tally(
+    state: &ApiState,
+    poll_id: i64,
+    program: Option<&str>,
+) -> Result<(Poll, Tally), ApiError> {
+    let program = program_filter(program)?;
+    let pool = state.pool.clone();
+    let mut found = blocking(move || get_polls_by_id(&pool, poll_id, program.as_deref())).await?;
+    let poll = match found.len() {
+        0 => {
+            return Err(ApiError::NotFound(
+                &errors::POLL_NOT_FOUND,
+                format!("poll {} not found in the index", poll_id),
+            ))
+        }
+        1 => found.remove(0),
+        _ => {
+            return Err(ApiError::BadRequest(
+                &errors::POLL_AMBIGUOUS,
+                format!(
+                    "poll {} is indexed for several programs, pass ?program=",
+                    poll_id
+                ),
+            ))
+        }
+    };
+    let pool = state.pool.clone();
+    let program_id = poll.program_id.clone();
+    let id_of_poll = poll.poll_id;
+    let results = blocking(move || get_results(&pool, id_of_poll, &program_id)).await?;
+    let tally = Tally::new(
+        results
+            .iter()
+            .filter_map(candidates::candidate_from_result)
+            .collect(),
+    );
+    Ok((poll, tally))
+}
+
+async fn search_handler(
+    State(state): State<ApiState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let query = params.q.trim().to_string();
+    if query.is_empty() {
+        return Err(ApiError::BadRequest(
+            &errors::INVALID_PARAMETER,
+            "q must not be empty".to_string(),
+        ));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_HITS)
+        .clamp(1, MAX_SEARCH_HITS);
+    match params.kind.as_deref() {
+        None | Some("poll") | Some("any") => {}
+        Some("candidate") => {
+            return Err(ApiError::BadRequest(
+                &errors::INVALID_PARAMETER,
+                "candidate accounts aren't indexed, only polls can be searched".to_string(),
+            ))
+        }
+        Some(other) => {
+            return Err(ApiError::BadRequest(
+                &errors::INVALID_PARAMETER,
+                format!("unknown type '{}', expected poll, candidate or any", other),
+            ))
+        }
+    }
+    let program = program_filter(params.program.as_deref())?;
+
+    let pool = state.pool.clone();
+    let found = blocking(move || {
+        search_polls(
+            &pool,
+            &query,
+            program.as_deref(),
+            limit,
+            SearchWeights::default(),
+            chrono::Utc::now(),
+        )
+    })
+    .await?;
+    let (polls, scores): (Vec<Poll>, Vec<f64>) =
+        found.into_iter().map(|hit| (hit.poll, hit.score)).unzip();
+    verify_on_read(&state, &polls).await;
+    let hits = with_annotations(&state, polls)
+        .await?
+        .into_iter()
+        .zip(scores)
+        .map(|(poll, score)| SearchHit {
+            kind: "poll".to_string(),
+            score,
+            poll,
+        })
+        .collect();
+    Ok(Json(SearchResults { hits }))
+}
+
+/// Runs a poll read, joining an identical read already in flight when coalescing is on.
+/// Every read is counted in `api_reads_total`, as `executed` or `coalesced`.
+async fn read_polls(state: &ApiState, key: ReadKey) -> Result<Vec<api_types::Poll>, ApiError> {
+    let Some(coalescer) = &state.coalescer else {
+        state
+            .metrics
+            .api_reads
+            .with_label_values(&["executed"])
+            .inc();
+        return load_polls(state.clone(), key).await;
+    };
+
+    let (result, joined) = coalescer
+        .0
+        .run(key.clone(), || {
+            let state = state.clone();
+            async move { load_polls(state, key).await.map_err(ApiError::into_anyhow) }
+        })
+        .await;
+    let outcome = if joined { "coalesced" } else { "executed" };
+    state.metrics.api_reads.with_label_values(&[outcome]).inc();
+    // The error is shared between the requests, so each gets a copy (keeping its code).
+    result.map_err(|e| {
+        ApiError::Internal(match errors::classify(&e) {
+            Some(code) => errors::coded(code, format!("{:#}", e)),
+            None => anyhow::anyhow!("{:#}", e),
+        })
+    })
+}
+
+/// Loads the polls of a read with their annotations (the actual database work of a read).
+async fn load_polls(state: ApiState, key: ReadKey) -> Result<Vec<api_types::Poll>, ApiError> {
+    let pool = state.pool.clone();
+    let polls = match key {
+        ReadKey::Page {
+            program,
+            limit,
+            offset,
+        } => blocking(move || list_polls_page(&pool, program.as_deref(), limit, offset)).await?,
+        ReadKey::Poll { poll_id, program } => {
+            let weights = state.completeness_weights;
+            let (polls, scores) = blocking(move || {
+                let polls = get_polls_by_id(&pool, poll_id, program.as_deref())?;
+                let scores = polls
+                    .iter()
+                    .map(|poll| completeness::assess(&pool, poll, &weights))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Ok((polls, scores))
+            })
+            .await?;
+            for (poll, score) in polls.iter().zip(&scores) {
+                state
+                    .completeness_changes
+                    .observe(&(poll.program_id.clone(), poll.poll_id), score);
+            }
+            verify_on_read(&state, &polls).await;
+            let mut views = with_annotations(&state, polls).await?;
+            for (view, score) in views.iter_mut().zip(scores) {
+                view.completeness = Some(score.to_dto(true));
+            }
+            return Ok(views);
+        }
+    };
+    verify_on_read(&state, &polls).await;
+    with_annotations(&state, polls).await
+}
+
+/// With `verify_checksums` on, recomputes the checksums of the polls about to be returned and
+/// reports mismatches (logged, and recorded as anomalies when the pool can write).
+/// The response itself is unaffected.
+async fn verify_on_read(state: &ApiState, polls: &[Poll]) {
+    if !state.verify_checksums {
+        return;
+    }
+    let mismatches: Vec<_> = polls.iter().filter_map(check_poll_checksum).collect();
+    if mismatches.is_empty() {
+        return;
+    }
+    for mismatch in &mismatches {
+        warn!(
+            poll_id = mismatch.poll_id,
+            stored = mismatch.stored,
+            recomputed = mismatch.recomputed,
+            "Checksum mismatch on read"
+        );
+    }
+    let pool = state.pool.clone();
+    // With the stdout sink the API pool is read-only, so this fails; the warning above remains.
+    if let Err(ApiError::Internal(e)) =
+        blocking(move || record_checksum_mismatches(&pool, &mismatches)).await
+    {
+        warn!(error = ?e, "Could not record checksum mismatches");
+    }
+}
+
+async fn feed_handler(
+    State(state): State<ApiState>,
+    Query(params): Query<FeedParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let program = parse_program(params.program.as_deref())?;
+    let scope = FeedScope {
+        program,
+        poll_id: None,
+    };
+    atom_feed(&state, scope, params.limit, &headers).await
+}
+
+async fn poll_feed_handler(
+    State(state): State<ApiState>,
+    Path(poll_id): Path<i64>,
+    Query(params): Query<FeedParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let program = parse_program(params.program.as_deref())?;
+    let pool = state.pool.clone();
+    let filter = program.map(|program| program.to_bytes().to_vec());
+    let found = blocking(move || get_polls_by_id(&pool, poll_id, filter.as_deref())).await?;
+    let poll = match found.as_slice() {
+        [] => {
+            return Err(ApiError::NotFound(
+                &errors::POLL_NOT_FOUND,
+                format!("poll {} not found in the index", poll_id),
+            ))
+        }
+        [poll] => poll,
+        _ => {
+            return Err(ApiError::BadRequest(
+                &errors::POLL_AMBIGUOUS,
+                format!(
+                    "poll {} is indexed for several programs, pass ?program=",
+                    poll_id
+                ),
+            ))
+        }
+    };
+    let scope = FeedScope {
+        program: poll.program_pubkey().map_err(ApiError::Internal)?,
+        poll_id: Some(poll_id),
+    };
+    atom_feed(&state, scope, params.limit, &headers).await
+}
+
+/// Renders the feed of `scope` and serves it with its ETag, or 304 when the client already has it.
+async fn atom_feed(
+    state: &ApiState,
+    scope: FeedScope,
+    limit: Option<i64>,
+    headers: &HeaderMap,
+) -> Result<Response, ApiError> {
+    let limit = limit
+        .unwrap_or(state.feed_max_entries)
+        .clamp(1, state.feed_max_entries);
+    let pool = state.pool.clone();
+    let body = blocking(move || {
+        let program = scope.program.map(|program| program.to_bytes().to_vec());
+        let transitions = recent_transitions(
+            &pool,
+            program.as_deref(),
+            scope.poll_id,
+            &FeedEvent::STATES,
+            limit,
+        )?;
+        let keys: Vec<PollKey> = transitions
+            .iter()
+            .map(|transition| (transition.program_id.clone(), transition.poll_id))
+            .collect();
+        let polls = get_polls_by_keys(&pool, &keys)?;
+        feed::render(scope, &transitions, &polls)
+    })
+    .await?;
+
+    let etag = feed::etag(&body);
+    let cache_control = format!("public, max-age={}", FEED_MAX_AGE_SECS);
+    let cached = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    if cached {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "application/atom+xml; charset=utf-8".to_string(),
+            ),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn idl_handler(
+    State(state): State<ApiState>,
+    Path(program_id): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let program = program_id
+        .parse::<Pubkey>()
+        .map_err(|_| {
+            ApiError::BadRequest(
+                &errors::INVALID_PROGRAM_ID,
+                format!("'{}' is not a valid program ID", program_id),
+            )
+        })?
+        .to_bytes()
+        .to_vec();
+    let pool = state.pool.clone();
+    match blocking(move || latest_idl(&pool, &program)).await? {
+        Some(idl) => Ok(Json(idl.idl)),
+        None => Err(ApiError::NotFound(
+            &errors::IDL_NOT_FOUND,
+            format!("no IDL loaded for program {}", program_id),
+        )),
+    }
+}
+
+async fn health_handler(State(state): State<ApiState>) -> (StatusCode, Json<HealthReport>) {
+    let pool = state.pool.clone();
+    // Checking out a connection runs the pool's liveness test, so this is a real round-trip.
+    let reachable =
+        tokio::task::spawn_blocking(move || pool.get_timeout(HEALTH_DB_TIMEOUT).is_ok())
+            .await
+            .unwrap_or(false);
+    let pool_state = state.pool.state();
+    let connected = state.health.websocket_connected.load(Ordering::Relaxed);
+
+    let healthy = connected && reachable;
+    let report = HealthReport {
+        status: if healthy { "ok" } else { "degraded" }.to_string(),
+        read_only: state.read_only,
+        websocket: WebsocketHealth {
+            connected,
+            last_slot: state.health.last_slot.load(Ordering::Relaxed),
+            endpoints: state.health.websocket_endpoints.lock().unwrap().clone(),
+        },
+        database: DatabaseHealth {
+            reachable,
+            connections: pool_state.connections,
+            idle_connections: pool_state.idle_connections,
+        },
+        lifecycle: LifecycleHealth {
+            suppressed_flaps: state.metrics.lifecycle_flaps_suppressed.get(),
+        },
+        warmup: state.health.warmups.lock().unwrap().clone(),
+        rpc_filters: RpcFilterHealth {
+            ignored_by: state.health.filters_ignored_by.lock().unwrap().clone(),
+        },
+        storage_quotas: state
+            .storage_quotas
+            .as_ref()
+            .map(|quotas| quotas.report())
+            .unwrap_or_default(),
+    };
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn metrics_handler(State(state): State<ApiState>) -> Result<Response, ApiError> {
+    let body = state.metrics.render().map_err(ApiError::Internal)?;
+    Ok((
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
+        .into_response())
+}
+
+/// Samples the CPU for `?seconds=` (30 by default) and returns the profile as pprof protobuf, or
+/// as an SVG flamegraph with `?format=flamegraph`.
+#[cfg(feature = "profiling")]
+async fn profile_handler(
+    State(state): State<ApiState>,
+    Query(params): Query<api_types::ProfileParams>,
+) -> Result<Response, ApiError> {
+    use crate::profiling::{ProfileFormat, MAX_PROFILE_DURATION};
+
+    let Some(profiler) = &state.profiler else {
+        return Err(ApiError::NotFound(
+            &errors::PROFILING_UNAVAILABLE,
+            "profiling is disabled".to_string(),
+        ));
+    };
+    let duration = Duration::from_secs(params.seconds.unwrap_or(30));
+    if duration.is_zero() || duration > MAX_PROFILE_DURATION {
+        return Err(ApiError::BadRequest(
+            &errors::INVALID_PARAMETER,
+            format!(
+                "seconds must be between 1 and {}",
+                MAX_PROFILE_DURATION.as_secs()
+            ),
+        ));
+    }
+    let (format, content_type) = match params.format.as_deref() {
+        None | Some("pprof") => (ProfileFormat::Pprof, "application/octet-stream"),
+        Some("flamegraph") => (ProfileFormat::Flamegraph, "image/svg+xml"),
+        Some(other) => {
+            return Err(ApiError::BadRequest(
+                &errors::INVALID_PARAMETER,
+                format!("unknown format '{}', expected pprof or flamegraph", other),
+            ))
+        }
+    };
+
+    match profiler
+        .cpu_profile(duration, format)
+        .await
+        .map_err(ApiError::Internal)?
+    {
+        Some(body) => {
+            Ok(([(axum::http::header::CONTENT_TYPE, content_type)], body).into_response())
+        }
+        None => Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(ErrorBody {
+                error: "a profile is already running".to_string(),
+                code: Some(errors::PROFILING_UNAVAILABLE.id.to_string()),
+            }),
+        )
+            .into_response()),
+    }
+}
+
+/// Upgrades `GET /live` to a websocket and streams the live updates to it (see `live::stream`).
+async fn live_handler(
+    State(state): State<ApiState>,
+    Query(params): Query<LiveParams>,
+    mut request: Request,
+) -> Result<Response, ApiError> {
+    let program = program_filter(params.program.as_deref())?;
+    let Some(key) = websocket_key(request.headers()) else {
+        return Err(ApiError::BadRequest(
+            &errors::LIVE_UNAVAILABLE,
+            "/live is a websocket, connect with a websocket client".to_string(),
+        ));
+    };
+    let Some(receiver) = state.live.as_ref().and_then(LiveUpdates::subscribe) else {
+        return Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorBody {
+                error: "the listener has stopped".to_string(),
+                code: Some(errors::LIVE_UNAVAILABLE.id.to_string()),
+            }),
+        )
+            .into_response());
+    };
+
+    // Subscribed before answering, so nothing ingested after the handshake is missed.
+    let metrics = state.metrics.clone();
+    Ok(upgrade_websocket(&mut request, &key, None, move |socket| {
+        live::stream(socket, receiver, program, metrics)
+    }))
+}
+
+/// The `Sec-WebSocket-Key` of a websocket upgrade request, `None` when the request isn't one.
+pub(crate) fn websocket_key(headers: &HeaderMap) -> Option<HeaderValue> {
+    let has_token = |name, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|part| part.trim().eq_ignore_ascii_case(token))
+    };
+    if !has_token(header::CONNECTION, "upgrade") || !has_token(header::UPGRADE, "websocket") {
+        return None;
+    }
+    headers.get(header::SEC_WEBSOCKET_KEY).cloned()
+}
+
+/// Accepts a websocket upgrade (agreeing on `protocol`, if any) and runs `serve` on the socket
+/// once the client has received the `101 Switching Protocols` this returns.
+///
+/// axum's own websockets need a newer tungstenite than the Solana crates pin, so the upgrade goes
+/// through hyper and the socket is tokio-tungstenite's.
+pub(crate) fn upgrade_websocket<F, Fut>(
+    request: &mut Request,
+    key: &HeaderValue,
+    protocol: Option<&'static str>,
+    serve: F,
+) -> Response
+where
+    F: FnOnce(WebSocketStream<TokioIo<Upgraded>>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let path = request.uri().path().to_string();
+    let upgrade = hyper::upgrade::on(request);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let socket =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None)
+                        .await;
+                serve(socket).await;
+            }
+            Err(e) => warn!(error = ?e, %path, "Could not upgrade a websocket connection"),
+        }
+    });
+
+    let accept = HeaderValue::from_str(&derive_accept_key(key.as_bytes()))
+        .expect("the accept key is base64");
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, HeaderValue::from_static("upgrade"))
+        .header(header::UPGRADE, HeaderValue::from_static("websocket"))
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept);
+    if let Some(protocol) = protocol {
+        response = response.header(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static(protocol),
+        );
+    }
+    response.body(Body::empty()).expect("the headers are valid")
+}
+
+/// Runs a synchronous Diesel query on a blocking thread.
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+        .map_err(ApiError::Internal)
+}
+
+/// Errors returned by the handlers, rendered as `{"error": "...", "code": "..."}` with the
+/// error's catalog code (see `errors`).
+enum ApiError {
+    BadRequest(&'static ErrorCode, String),
+    NotFound(&'static ErrorCode, String),
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    /// The error as an `anyhow::Error`, for results shared between coalesced requests.
+    /// Loading polls only fails with `Internal`; client errors are rejected before.
+    fn into_anyhow(self) -> anyhow::Error {
+        match self {
+            ApiError::BadRequest(code, message) | ApiError::NotFound(code, message) => {
+                errors::coded(code, message)
+            }
+            ApiError::Internal(e) => e,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code, message) = match self {
+            ApiError::BadRequest(code, message) => (StatusCode::BAD_REQUEST, code, message),
+            ApiError::NotFound(code, message) => (StatusCode::NOT_FOUND, code, message),
+            ApiError::Internal(e) => {
+                let code = errors::classify(&e).unwrap_or(&errors::INTERNAL);
+                // Details go to the log, not to the client.
+                error!(error = ?e, %code, "HTTP API request failed");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    code,
+                    code.message.to_string(),
+                )
+            }
+        };
+        let body = ErrorBody {
+            error: message,
+            code: Some(code.id.to_string()),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::PgConnection;
+    use futures::{StreamExt, TryStreamExt};
+    use voting_dapp_api_types::{LiveUpdate, PageParams};
+
+    use super::*;
+    use crate::db::db::{upsert_idl_account, upsert_poll};
+    use crate::db::models::NewIdlAccount;
+    use crate::db::test_support::{new_poll, test_pool, NOW};
+    use crate::live::LiveSink;
+    use crate::sink::{MemorySink, PollSink};
+
+    /// The state of a server without optional features, over `pool`.
+    fn test_state(pool: PgPool) -> ApiState {
+        ApiState {
+            pool,
+            health: Arc::default(),
+            metrics: Arc::new(Metrics::new().unwrap()),
+            read_only: false,
+            verify_checksums: false,
+            show_annotations: true,
+            completeness_weights: CompletenessWeights::default(),
+            completeness_changes: Arc::default(),
+            feed_max_entries: 50,
+            coalescer: None,
+            storage_quotas: None,
+            live: None,
+            #[cfg(feature = "profiling")]
+            profiler: None,
+        }
+    }
+
+    /// Serves `state` on an ephemeral local port, for the rest of the test; returns a client of it.
+    async fn spawn_server(state: ApiState) -> voting_dapp_client::Client {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, state, std::future::pending()));
+        voting_dapp_client::Client::new(format!("http://{address}")).unwrap()
+    }
+
+    /// A pool that never connects: nothing listens on port 1.
+    fn unreachable_pool() -> PgPool {
+        Pool::builder()
+            .connection_timeout(Duration::from_millis(200))
+            .build_unchecked(ConnectionManager::<PgConnection>::new(
+                "postgres://localhost:1/none",
+            ))
+    }
+
+    #[tokio::test]
+    async fn health_reports_read_only_mode() {
+        let state = ApiState {
+            read_only: true,
+            ..test_state(unreachable_pool())
+        };
+        state.health.set_websocket_connected(true);
+
+        let (status, Json(report)) = health_handler(State(state)).await;
+        assert!(report.read_only);
+        // The database is down, whatever the mode.
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.status, "degraded");
+        assert!(!report.database.reachable);
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint_serves_the_counters() {
+        let state = test_state(unreachable_pool());
+        state
+            .metrics
+            .messages_received
+            .with_label_values(&["poll"])
+            .inc_by(3);
+        state.metrics.decode_failures.inc();
+        state.metrics.last_processed_slot.set(1234);
+        let client = spawn_server(state).await;
+
+        let body = client.metrics().await.unwrap();
+        assert!(body.contains("voting_listener_messages_received_total{account_type=\"poll\"} 3"));
+        assert!(body.contains("voting_listener_decode_failures_total 1"));
+        assert!(body.contains("voting_listener_last_processed_slot 1234"));
+        for name in [
+            "voting_listener_duplicates_skipped_total",
+            "voting_listener_websocket_connected",
+            "voting_listener_writer_queue_depth",
+        ] {
+            assert!(body.contains(&format!("# TYPE {name} ")), "{name} missing");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_rejects_invalid_parameters_before_querying() {
+        // The pool never connects: these must fail on their parameters alone.
+        let client = spawn_server(test_state(unreachable_pool())).await;
+        let invalid = |q: &str, program: Option<&str>, kind: Option<&str>| SearchParams {
+            q: q.to_string(),
+            program: program.map(str::to_string),
+            kind: kind.map(str::to_string),
+            limit: None,
+        };
+        for (params, code) in [
+            (invalid("  ", None, None), &errors::INVALID_PARAMETER),
+            (
+                invalid("pizza", None, Some("candidate")),
+                &errors::INVALID_PARAMETER,
+            ),
+            (
+                invalid("pizza", None, Some("vote")),
+                &errors::INVALID_PARAMETER,
+            ),
+            (
+                invalid("pizza", Some("not-a-pubkey"), None),
+                &errors::INVALID_PROGRAM_ID,
+            ),
+        ] {
+            let err = client.search(&params).await.unwrap_err();
+            assert_eq!(err.status().map(|s| s.as_u16()), Some(400), "{:?}", params);
+            assert_eq!(err.code(), Some(code.id), "{:?}", params);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn client_reads_indexed_polls() {
+        let pool = test_pool();
+        let program = [0x20; 32];
+        for poll_id in [1, 2, 3] {
+            upsert_poll(&pool, &new_poll(&program, poll_id, 100), 0, NOW).unwrap();
+        }
+        let client = spawn_server(test_state(pool)).await;
+
+        let page = client
+            .list_polls(&PageParams {
+                limit: Some(2),
+                ..PageParams::default()
+            })
+            .await
+            .unwrap();
+        let ids: Vec<_> = page.polls.iter().map(|poll| poll.poll_id).collect();
+        assert_eq!(ids, [1, 2]);
+        let all: Vec<_> = client.all_polls(None).try_collect().await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        let poll = client.get_poll(2, None).await.unwrap();
+        assert_eq!(poll.poll_name, "Poll 2");
+        let err = client.get_poll(99, None).await.unwrap_err();
+        assert!(err.is_not_found());
+        assert_eq!(err.code(), Some(errors::POLL_NOT_FOUND.id));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn client_reads_candidates_and_results() {
+        let pool = test_pool();
+        let program = Pubkey::new_from_array([0x24; 32]);
+        upsert_poll(&pool, &new_poll(&program.to_bytes(), 1, 100), 0, NOW).unwrap();
+        // The same poll id in another program.
+        upsert_poll(&pool, &new_poll(&[0x25; 32], 1, 100), 0, NOW).unwrap();
+        for (name, votes) in [("Cy", 1), ("Bob", 2), ("Ada", 2)] {
+            let candidate = NewIdlAccount {
+                program_id: program.to_bytes().to_vec(),
+                account_pubkey: vec![name.as_bytes()[0]; 32],
+                account_type: candidates::CANDIDATE_ACCOUNT.to_string(),
+                data: serde_json::json!({ "poll_id": 1, "name": name, "votes": votes }),
+                last_slot: 100,
+            };
+            upsert_idl_account(&pool, &candidate).unwrap();
+        }
+        let client = spawn_server(test_state(pool)).await;
+        let program = program.to_string();
+        let account = |name: &str| Pubkey::new_from_array([name.as_bytes()[0]; 32]).to_string();
+
+        let listed = client.candidates(1, Some(&program)).await.unwrap();
+        let names: Vec<_> = listed.candidates.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Ada", "Bob", "Cy"]);
+        assert_eq!(listed.program_id.as_deref(), Some(program.as_str()));
+
+        let results = client.results(1, Some(&program)).await.unwrap();
+        assert_eq!(results.total_votes, 5);
+        let shares: Vec<_> = results
+            .candidates
+            .iter()
+            .map(|c| (c.name.as_str(), c.percentage))
+            .collect();
+        assert_eq!(shares, [("Ada", 40.0), ("Bob", 40.0), ("Cy", 20.0)]);
+        assert_eq!(results.leaders, [account("Ada"), account("Bob")]);
+        assert_eq!(results.winner, None);
+        assert_eq!(results.candidate_amount, 2);
+
+        // Without `program`, poll 1 is ambiguous; poll 99 isn't indexed.
+        let err = client.results(1, None).await.unwrap_err();
+        assert_eq!(err.code(), Some(errors::POLL_AMBIGUOUS.id));
+        let err = client.candidates(99, Some(&program)).await.unwrap_err();
+        assert!(err.is_not_found());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn identical_concurrent_reads_run_once() {
+        const REQUESTS: u64 = 20;
+        let pool = test_pool();
+        upsert_poll(&pool, &new_poll(&[0x23; 32], 1, 100), 0, NOW).unwrap();
+        let state = ApiState {
+            coalescer: Some(Arc::default()),
+            ..test_state(pool.clone())
+        };
+        let metrics = state.metrics.clone();
+        let client = spawn_server(state).await;
+
+        // Holding the pool's only connection keeps the first read in flight while the others
+        // arrive.
+        let connection = pool.get().unwrap();
+        let reads = futures::future::join_all((0..REQUESTS).map(|_| client.get_poll(1, None)));
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(connection);
+        };
+        let (polls, ()) = futures::join!(reads, release);
+        assert!(polls.iter().all(|poll| poll.as_ref().unwrap().poll_id == 1));
+
+        let executed = metrics.api_reads.with_label_values(&["executed"]).get();
+        let coalesced = metrics.api_reads.with_label_values(&["coalesced"]).get();
+        assert_eq!(executed + coalesced, REQUESTS);
+        assert!(
+            executed <= REQUESTS / 4,
+            "{executed} of {REQUESTS} reads executed"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_receives_live_updates() {
+        let (sink, updates) = LiveSink::new(Arc::new(MemorySink::default()));
+        let state = ApiState {
+            live: Some(updates),
+            ..test_state(unreachable_pool())
+        };
+        let client = spawn_server(state).await;
+        let program = [0x21; 32];
+        let mut every_program = Box::pin(client.live(None).await.unwrap());
+        let mut one_program = Box::pin(
+            client
+                .live(Some(&Pubkey::new_from_array(program).to_string()))
+                .await
+                .unwrap(),
+        );
+
+        sink.write_poll(new_poll(&[0x22; 32], 1, 100))
+            .await
+            .unwrap();
+        sink.write_poll(new_poll(&program, 2, 101)).await.unwrap();
+
+        let poll_id = |update: Option<Result<LiveUpdate, _>>| match update.unwrap().unwrap() {
+            LiveUpdate::Poll(poll) => poll.poll_id,
+            other => panic!("unexpected update {other:?}"),
+        };
+        assert_eq!(poll_id(every_program.next().await), 1);
+        assert_eq!(poll_id(every_program.next().await), 2);
+        assert_eq!(poll_id(one_program.next().await), 2);
+
+        // The listener stopping closes the connections.
+        drop(sink);
+        assert!(every_program.next().await.is_none());
+        assert!(one_program.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn client_reports_live_updates_unavailable() {
+        let (sink, updates) = LiveSink::new(Arc::new(MemorySink::default()));
+        drop(sink);
+        let state = ApiState {
+            live: Some(updates),
+            ..test_state(unreachable_pool())
+        };
+        let client = spawn_server(state).await;
+
+        let Err(err) = client.live(None).await else {
+            panic!("connected to a stopped listener");
+        };
+        assert_eq!(err.status().map(|status| status.as_u16()), Some(503));
+        assert_eq!(err.code(), Some(errors::LIVE_UNAVAILABLE.id));
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use solana_client::client_error::Result as ClientResult;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_rpc_client::http_sender::HttpSender;
+use solana_rpc_client::rpc_client::RpcClientConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+/// Feature label used for websocket notifications.
+pub const WEBSOCKET_FEATURE: &str = "programSubscribe";
+
+/// Fractions of the monthly budget at which an alert is raised, in percent.
+pub const BUDGET_ALERT_LEVELS: [u8; 2] = [80, 100];
+
+/// Running byte counters per `(endpoint, feature)`.
+///
+/// Every transport (the HTTP `RpcClient` and the websocket stream) reports into one shared
+/// meter, and a background task periodically drains the deltas into the `bandwidth_usage` table.
+/// The feature is the RPC method name (e.g. `getProgramAccounts` for the backfill,
+/// `getSlot`/`getBlockTime` for the slot clock), which is as close to a feature as the
+/// transport can attribute.
+#[derive(Default)]
+pub struct BandwidthMeter {
+    pending: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl BandwidthMeter {
+    /// Adds `bytes` received from `endpoint` on behalf of `feature`.
+    pub fn record(&self, endpoint: &str, feature: &str, bytes: u64) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending
+            .entry((endpoint.to_string(), feature.to_string()))
+            .or_default() += bytes;
+    }
+
+    /// Records the size of a websocket notification.
+    ///
+    /// `PubsubClient` doesn't expose the raw frames, so the parsed message is re-serialized
+    /// to JSON. This slightly undercounts (the JSON-RPC envelope isn't included).
+    pub fn record_message<T: Serialize>(&self, endpoint: &str, message: &T) {
+        let bytes = serde_json::to_vec(message).map(|v| v.len()).unwrap_or(0);
+        self.record(endpoint, WEBSOCKET_FEATURE, bytes as u64);
+    }
+
+    /// Takes the counters accumulated since the last call, resetting them to zero.
+    pub fn take(&self) -> Vec<(String, String, u64)> {
+        let mut pending = self.pending.lock().unwrap();
+        pending
+            .drain()
+            .map(|((endpoint, feature), bytes)| (endpoint, feature, bytes))
+            .collect()
+    }
+
+    /// Puts counters back after a failed flush so they're retried next time.
+    pub fn restore(&self, deltas: Vec<(String, String, u64)>) {
+        for (endpoint, feature, bytes) in deltas {
+            self.record(&endpoint, &feature, bytes);
+        }
+    }
+}
+
+/// `RpcSender` that wraps the regular `HttpSender` and counts response bytes into a meter.
+///
+/// Wrapping at the transport level means every caller of the `RpcClient` (backfill, slot clock,
+/// anything added later) is accounted for without having to remember to do it.
+/// The size is the JSON-encoded `result`, since `HttpSender` doesn't expose the raw body.
+pub struct CountingSender {
+    inner: HttpSender,
+    endpoint: String,
+    meter: Arc<BandwidthMeter>,
+}
+
+#[async_trait]
+impl RpcSender for CountingSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let response = self.inner.send(request, params).await?;
+        let bytes = serde_json::to_vec(&response).map(|v| v.len()).unwrap_or(0);
+        self.meter
+            .record(&self.endpoint, &request.to_string(), bytes as u64);
+        Ok(response)
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+/// Builds an HTTP `RpcClient` whose traffic is counted into `meter`.
+pub fn counting_rpc_client(
+    url: String,
+    commitment: CommitmentConfig,
+    meter: Arc<BandwidthMeter>,
+) -> RpcClient {
+    let sender = CountingSender {
+        inner: HttpSender::new(url.clone()),
+        endpoint: endpoint_label(&url),
+        meter,
+    };
+    RpcClient::new_sender(sender, RpcClientConfig::with_commitment(commitment))
+}
+
+/// The endpoint as stored in the database: the URL without its query string,
+/// since providers commonly put API keys there (`?api-key=...`).
+pub fn endpoint_label(url: &str) -> String {
+    url.split('?').next().unwrap_or(url).to_string()
+}
+
+/// The highest alert level in [`BUDGET_ALERT_LEVELS`] that `used` has reached, if any.
+pub fn budget_level(used: u64, budget: u64) -> Option<u8> {
+    if budget == 0 {
+        return None;
+    }
+    let percent = used.saturating_mul(100) / budget;
+    BUDGET_ALERT_LEVELS
+        .iter()
+        .rev()
+        .find(|level| percent >= u64::from(**level))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// The counters of `meter`, sorted.
+    fn taken(meter: &BandwidthMeter) -> Vec<(String, String, u64)> {
+        let mut deltas = meter.take();
+        deltas.sort();
+        deltas
+    }
+
+    #[test]
+    fn bytes_add_up_per_endpoint_and_feature() {
+        let meter = BandwidthMeter::default();
+        meter.record("https://a", "getSlot", 10);
+        meter.record("https://a", "getSlot", 5);
+        meter.record("https://a", "getProgramAccounts", 100);
+        meter.record("https://b", "getSlot", 1);
+        meter.record_message("wss://a", &json!({ "slot": 42 }));
+
+        assert_eq!(
+            taken(&meter),
+            [
+                ("https://a".into(), "getProgramAccounts".into(), 100),
+                ("https://a".into(), "getSlot".into(), 15),
+                ("https://b".into(), "getSlot".into(), 1),
+                ("wss://a".into(), WEBSOCKET_FEATURE.into(), 11),
+            ]
+        );
+        // Taking resets the counters.
+        assert!(meter.take().is_empty());
+    }
+
+    #[test]
+    fn restored_counters_add_to_new_ones() {
+        let meter = BandwidthMeter::default();
+        meter.record("https://a", "getSlot", 10);
+        let failed_flush = meter.take();
+        meter.record("https://a", "getSlot", 3);
+        meter.restore(failed_flush);
+
+        assert_eq!(taken(&meter), [("https://a".into(), "getSlot".into(), 13)]);
+    }
+
+    #[tokio::test]
+    async fn rpc_responses_are_counted_without_the_query_string() {
+        let app = Router::new().route(
+            "/",
+            post(|| async { Json(json!({ "jsonrpc": "2.0", "result": 12345, "id": 1 })) }),
+        );
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let meter = Arc::new(BandwidthMeter::default());
+        let client = counting_rpc_client(
+            format!("http://{address}/?api-key=secret"),
+            CommitmentConfig::confirmed(),
+            meter.clone(),
+        );
+        assert_eq!(client.get_slot().await.unwrap(), 12345);
+        client.get_slot().await.unwrap();
+
+        // The JSON of the result, `12345`, twice.
+        assert_eq!(
+            taken(&meter),
+            [(format!("http://{address}/"), "getSlot".into(), 10)]
+        );
+    }
+
+    #[test]
+    fn alerts_are_raised_at_80_and_100_percent() {
+        assert_eq!(budget_level(79, 100), None);
+        assert_eq!(budget_level(80, 100), Some(80));
+        assert_eq!(budget_level(99, 100), Some(80));
+        assert_eq!(budget_level(250, 100), Some(100));
+        assert_eq!(budget_level(u64::MAX, 100), Some(100));
+        // No budget, no alerts.
+        assert_eq!(budget_level(1_000, 0), None);
+    }
+}
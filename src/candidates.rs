@@ -0,0 +1,414 @@
+use serde_json::Value;
+use solana_sdk::pubkey::{Pubkey, MAX_SEED_LEN};
+
+use crate::db::models::{IdlAccount, Poll, PollResult};
+
+/// IDL name of the candidate accounts.
+pub const CANDIDATE_ACCOUNT: &str = "Candidate";
+/// IDL name of the vote accounts.
+pub const VOTE_ACCOUNT: &str = "Vote";
+
+/// Field names programs use for a candidate's name, its vote count and its poll, in order of
+/// preference. Matched regardless of case and underscores (`candidateName` too).
+const NAME_FIELDS: &[&str] = &["candidate_name", "name"];
+const VOTES_FIELDS: &[&str] = &["candidate_votes", "votes", "vote_count"];
+const POLL_FIELDS: &[&str] = &["poll_id", "poll"];
+
+/// A candidate of a poll, read from its IDL-decoded account (see `idl_decode`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub name: String,
+    pub votes: u64,
+    pub account: Pubkey,
+    pub last_slot: i64,
+}
+
+/// Reads a candidate from its account; `None` when it lacks a name or a vote count.
+pub fn candidate_from_account(account: &IdlAccount) -> Option<Candidate> {
+    Some(Candidate {
+        name: field(&account.data, NAME_FIELDS)?.as_str()?.to_string(),
+        votes: field(&account.data, VOTES_FIELDS)?.as_u64()?,
+        account: Pubkey::try_from(account.account_pubkey.as_slice()).ok()?,
+        last_slot: account.last_slot,
+    })
+}
+
+/// Reads a candidate back from its `poll_results` row; `None` when the stored account isn't a
+/// pubkey.
+pub fn candidate_from_result(row: &PollResult) -> Option<Candidate> {
+    Some(Candidate {
+        name: row.candidate_name.clone(),
+        votes: row.votes as u64,
+        account: Pubkey::try_from(row.candidate_account.as_slice()).ok()?,
+        last_slot: row.last_slot,
+    })
+}
+
+/// The candidates of `poll` among its program's candidate accounts, most votes first (then
+/// by name).
+///
+/// A candidate belongs to the poll when its account names the poll (by id, or by the poll
+/// account's address). Accounts that don't must be the PDA of `[poll_id (u64 LE), name]`, the
+/// seeds the voting program derives candidates from.
+pub fn poll_candidates(accounts: &[IdlAccount], poll: &Poll) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = accounts
+        .iter()
+        .filter(|account| account.program_id == poll.program_id)
+        .filter_map(|account| {
+            let candidate = candidate_from_account(account)?;
+            belongs_to(account, &candidate, poll).then_some(candidate)
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.votes.cmp(&a.votes).then_with(|| a.name.cmp(&b.name)));
+    candidates
+}
+
+fn belongs_to(account: &IdlAccount, candidate: &Candidate, poll: &Poll) -> bool {
+    if let Some(named) = names_poll(account, poll) {
+        return named;
+    }
+    let Ok(program_id) = Pubkey::try_from(poll.program_id.as_slice()) else {
+        return false;
+    };
+    // `find_program_address` panics on seeds the runtime would refuse.
+    if candidate.name.len() > MAX_SEED_LEN {
+        return false;
+    }
+    let seeds: [&[u8]; 2] = [
+        &(poll.poll_id as u64).to_le_bytes(),
+        candidate.name.as_bytes(),
+    ];
+    Pubkey::find_program_address(&seeds, &program_id).0 == candidate.account
+}
+
+/// The vote accounts of `poll` among its program's vote accounts, in the order given. Only
+/// votes naming their poll (by id or address) can be attributed; the others are left out.
+pub fn poll_votes<'a>(accounts: &'a [IdlAccount], poll: &Poll) -> Vec<&'a IdlAccount> {
+    accounts
+        .iter()
+        .filter(|account| account.program_id == poll.program_id)
+        .filter(|account| names_poll(account, poll) == Some(true))
+        .collect()
+}
+
+/// Whether the account changes the results of `poll`: a candidate of the poll (see
+/// [`poll_candidates`]) or a vote naming it (see [`poll_votes`]).
+pub fn counts_towards(account: &IdlAccount, poll: &Poll) -> bool {
+    if account.program_id != poll.program_id {
+        return false;
+    }
+    match account.account_type.as_str() {
+        CANDIDATE_ACCOUNT => candidate_from_account(account)
+            .is_some_and(|candidate| belongs_to(account, &candidate, poll)),
+        VOTE_ACCOUNT => names_poll(account, poll) == Some(true),
+        _ => false,
+    }
+}
+
+/// The poll id the account names in a poll field, if it names its poll by id.
+pub fn named_poll_id(account: &IdlAccount) -> Option<i64> {
+    field(&account.data, POLL_FIELDS)?.as_i64()
+}
+
+/// Whether the account names `poll` in a poll field; `None` when it has no such field.
+fn names_poll(account: &IdlAccount, poll: &Poll) -> Option<bool> {
+    match field(&account.data, POLL_FIELDS)? {
+        Value::Number(id) => Some(id.as_i64() == Some(poll.poll_id)),
+        Value::String(address) => Some(
+            Pubkey::try_from(poll.account_pubkey.as_slice())
+                .is_ok_and(|poll_account| poll_account.to_string() == *address),
+        ),
+        _ => None,
+    }
+}
+
+/// The first of `names` the object has, compared without case and underscores.
+fn field<'a>(data: &'a Value, names: &[&str]) -> Option<&'a Value> {
+    let object = data.as_object()?;
+    let normalize = |name: &str| name.replace('_', "").to_ascii_lowercase();
+    names.iter().find_map(|wanted| {
+        let wanted = normalize(wanted);
+        object
+            .iter()
+            .find(|(key, _)| normalize(key) == wanted)
+            .map(|(_, value)| value)
+    })
+}
+
+/// A poll's vote tally, see [`poll_candidates`].
+#[derive(Debug, Clone)]
+pub struct Tally {
+    /// Most votes first.
+    pub candidates: Vec<Candidate>,
+    pub total_votes: u64,
+}
+
+impl Tally {
+    pub fn new(candidates: Vec<Candidate>) -> Self {
+        let total_votes = candidates.iter().map(|c| c.votes).sum();
+        Self {
+            candidates,
+            total_votes,
+        }
+    }
+
+    /// The candidate's share of the votes, in percent (0 before the first vote).
+    pub fn percentage(&self, candidate: &Candidate) -> f64 {
+        if self.total_votes == 0 {
+            return 0.0;
+        }
+        candidate.votes as f64 * 100.0 / self.total_votes as f64
+    }
+
+    /// The candidates with the most votes: several on a tie, none before the first vote.
+    pub fn leaders(&self) -> &[Candidate] {
+        let Some(top) = self.candidates.first().filter(|c| c.votes > 0) else {
+            return &[];
+        };
+        let tied = self
+            .candidates
+            .iter()
+            .take_while(|c| c.votes == top.votes)
+            .count();
+        &self.candidates[..tied]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+    use crate::db::test_support::{new_poll, poll_row};
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([7; 32]);
+
+    fn poll(poll_id: i64) -> Poll {
+        poll_row(&new_poll(PROGRAM.as_ref(), poll_id, 10))
+    }
+
+    /// The candidate PDA of `[poll_id, name]`, as the voting program derives it.
+    fn pda(poll_id: u64, name: &str) -> Pubkey {
+        Pubkey::find_program_address(&[&poll_id.to_le_bytes(), name.as_bytes()], &PROGRAM).0
+    }
+
+    fn idl_account(account_type: &str, pubkey: Pubkey, data: Value) -> IdlAccount {
+        IdlAccount {
+            id: 1,
+            program_id: PROGRAM.to_bytes().to_vec(),
+            account_pubkey: pubkey.to_bytes().to_vec(),
+            account_type: account_type.to_string(),
+            data,
+            last_slot: 10,
+            first_seen_at: Utc::now(),
+            last_updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    fn candidate_account(pubkey: Pubkey, data: Value) -> IdlAccount {
+        idl_account(CANDIDATE_ACCOUNT, pubkey, data)
+    }
+
+    fn names(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_either_field_naming_convention() {
+        let snake = candidate_account(
+            Pubkey::new_unique(),
+            json!({ "candidate_name": "Ada", "candidate_votes": 3 }),
+        );
+        let camel = candidate_account(
+            Pubkey::new_unique(),
+            json!({ "candidateName": "Ada", "candidateVotes": 3 }),
+        );
+        let short = candidate_account(Pubkey::new_unique(), json!({ "name": "Ada", "votes": 3 }));
+        for account in [snake, camel, short] {
+            let candidate = candidate_from_account(&account).unwrap();
+            assert_eq!((candidate.name.as_str(), candidate.votes), ("Ada", 3));
+        }
+
+        let no_votes = candidate_account(Pubkey::new_unique(), json!({ "name": "Ada" }));
+        assert_eq!(candidate_from_account(&no_votes), None);
+        let negative =
+            candidate_account(Pubkey::new_unique(), json!({ "name": "Ada", "votes": -1 }));
+        assert_eq!(candidate_from_account(&negative), None);
+    }
+
+    #[test]
+    fn keeps_the_polls_candidates() {
+        let poll = poll(3);
+        let mut by_address = poll.clone();
+        by_address.account_pubkey = Pubkey::new_unique().to_bytes().to_vec();
+        let mut other_program =
+            candidate_account(pda(3, "Eve"), json!({ "name": "Eve", "votes": 1 }));
+        other_program.program_id = vec![8; 32];
+        let accounts = [
+            // Through its PDA.
+            candidate_account(pda(3, "Ada"), json!({ "name": "Ada", "votes": 1 })),
+            // Through a poll id field, whatever its address.
+            candidate_account(
+                Pubkey::new_unique(),
+                json!({ "pollId": 3, "name": "Bob", "votes": 1 }),
+            ),
+            // Not the poll's PDA.
+            candidate_account(Pubkey::new_unique(), json!({ "name": "Cy", "votes": 1 })),
+            // The PDA of another poll.
+            candidate_account(pda(4, "Dan"), json!({ "name": "Dan", "votes": 1 })),
+            // Names another poll, even though it is this poll's PDA.
+            candidate_account(
+                pda(3, "Fay"),
+                json!({ "poll_id": 4, "name": "Fay", "votes": 1 }),
+            ),
+            other_program,
+        ];
+        assert_eq!(names(&poll_candidates(&accounts, &poll)), ["Ada", "Bob"]);
+
+        // Through the poll account's address.
+        let address = Pubkey::try_from(by_address.account_pubkey.as_slice()).unwrap();
+        let accounts = [
+            candidate_account(
+                Pubkey::new_unique(),
+                json!({ "poll": address.to_string(), "name": "Gus", "votes": 1 }),
+            ),
+            candidate_account(
+                Pubkey::new_unique(),
+                json!({ "poll": Pubkey::new_unique().to_string(), "name": "Hal", "votes": 1 }),
+            ),
+        ];
+        assert_eq!(names(&poll_candidates(&accounts, &by_address)), ["Gus"]);
+    }
+
+    #[test]
+    fn sorts_by_votes_then_by_name() {
+        let accounts: Vec<_> = [("Cy", 2), ("Ada", 0), ("Bob", 5), ("Abe", 2), ("Dan", 5)]
+            .into_iter()
+            .map(|(name, votes)| {
+                candidate_account(
+                    Pubkey::new_unique(),
+                    json!({ "poll_id": 3, "name": name, "votes": votes }),
+                )
+            })
+            .collect();
+        let candidates = poll_candidates(&accounts, &poll(3));
+        assert_eq!(names(&candidates), ["Bob", "Dan", "Abe", "Cy", "Ada"]);
+    }
+
+    #[test]
+    fn skips_names_too_long_for_a_seed() {
+        let name = "x".repeat(MAX_SEED_LEN + 1);
+        let accounts = [candidate_account(
+            Pubkey::new_unique(),
+            json!({ "name": name, "votes": 1 }),
+        )];
+        assert!(poll_candidates(&accounts, &poll(3)).is_empty());
+    }
+
+    fn tally(votes: &[(&str, u64)]) -> Tally {
+        let accounts: Vec<_> = votes
+            .iter()
+            .map(|(name, votes)| {
+                candidate_account(
+                    Pubkey::new_unique(),
+                    json!({ "poll_id": 3, "name": name, "votes": votes }),
+                )
+            })
+            .collect();
+        Tally::new(poll_candidates(&accounts, &poll(3)))
+    }
+
+    #[test]
+    fn shares_add_up_to_the_total() {
+        let tally = tally(&[("Ada", 1), ("Bob", 3), ("Cy", 0)]);
+        assert_eq!(tally.total_votes, 4);
+        let shares: Vec<f64> = tally
+            .candidates
+            .iter()
+            .map(|c| tally.percentage(c))
+            .collect();
+        assert_eq!(shares, [75.0, 25.0, 0.0]);
+    }
+
+    #[test]
+    fn shares_are_zero_before_the_first_vote() {
+        let tally = tally(&[("Ada", 0), ("Bob", 0)]);
+        assert_eq!(tally.total_votes, 0);
+        for candidate in &tally.candidates {
+            assert_eq!(tally.percentage(candidate), 0.0);
+        }
+        assert!(tally.leaders().is_empty());
+        assert!(Tally::new(Vec::new()).leaders().is_empty());
+    }
+
+    #[test]
+    fn leads_with_the_most_votes_and_ties() {
+        assert_eq!(names(tally(&[("Ada", 1), ("Bob", 3)]).leaders()), ["Bob"]);
+        assert_eq!(
+            names(tally(&[("Cy", 3), ("Ada", 1), ("Bob", 3)]).leaders()),
+            ["Bob", "Cy"]
+        );
+        assert_eq!(
+            names(tally(&[("Ada", 2), ("Bob", 2), ("Cy", 2)]).leaders()),
+            ["Ada", "Bob", "Cy"]
+        );
+    }
+
+    #[test]
+    fn counts_the_polls_candidates_and_votes_only() {
+        let poll = poll(3);
+        let vote = |data| idl_account(VOTE_ACCOUNT, Pubkey::new_unique(), data);
+        assert!(counts_towards(
+            &candidate_account(pda(3, "Ada"), json!({ "name": "Ada", "votes": 0 })),
+            &poll
+        ));
+        assert!(counts_towards(&vote(json!({ "poll_id": 3 })), &poll));
+        assert!(!counts_towards(&vote(json!({ "poll_id": 4 })), &poll));
+        // A vote that doesn't name its poll can't be attributed.
+        assert!(!counts_towards(&vote(json!({ "voter": "x" })), &poll));
+        assert!(!counts_towards(
+            &idl_account("Config", Pubkey::new_unique(), json!({ "poll_id": 3 })),
+            &poll
+        ));
+        let mut other_program = vote(json!({ "poll_id": 3 }));
+        other_program.program_id = vec![8; 32];
+        assert!(!counts_towards(&other_program, &poll));
+
+        let votes = [
+            vote(json!({ "pollId": 3 })),
+            vote(json!({ "poll_id": 4 })),
+            vote(json!({})),
+        ];
+        assert_eq!(poll_votes(&votes, &poll).len(), 1);
+        assert_eq!(named_poll_id(&votes[0]), Some(3));
+        assert_eq!(named_poll_id(&votes[2]), None);
+    }
+
+    #[test]
+    fn reads_candidates_back_from_results() {
+        let poll = poll(3);
+        let candidate = Candidate {
+            name: "Ada".to_string(),
+            votes: 4,
+            account: Pubkey::new_unique(),
+            last_slot: 12,
+        };
+        let row = PollResult {
+            program_id: poll.program_id.clone(),
+            poll_id: poll.poll_id,
+            candidate_account: candidate.account.to_bytes().to_vec(),
+            candidate_name: candidate.name.clone(),
+            votes: 4,
+            last_slot: 12,
+            updated_at: Utc::now(),
+        };
+        assert_eq!(candidate_from_result(&row), Some(candidate));
+        let truncated = PollResult {
+            candidate_account: vec![1; 31],
+            ..row
+        };
+        assert_eq!(candidate_from_result(&truncated), None);
+    }
+}
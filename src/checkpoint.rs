@@ -0,0 +1,134 @@
+use std::collections::BTreeSet;
+
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status_client_types::option_serializer::OptionSerializer;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+
+use crate::fetch;
+
+/// Default of [`ListenerBuilder::catch_up_max_transactions`](crate::listener::ListenerBuilder::catch_up_max_transactions).
+pub const DEFAULT_CATCH_UP_MAX_TRANSACTIONS: usize = 1000;
+/// Signatures per `getSignaturesForAddress` call, the RPC's maximum.
+const SIGNATURES_PER_CALL: usize = 1000;
+
+/// The accounts the program's successful transactions since `since_slot` (included) may have
+/// written, newest transaction first; `None` when there are more than `max_transactions` of
+/// them, in which case a full backfill is cheaper.
+///
+/// Every transaction invoking the program lists it among its accounts, so
+/// `getSignaturesForAddress` on the program ID finds them all. Their writable accounts
+/// include those of other programs (fee payers, system accounts): only the caller can tell,
+/// once it fetched them, which ones the program owns.
+pub async fn changed_accounts(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+    since_slot: u64,
+    commitment: CommitmentConfig,
+    max_transactions: usize,
+) -> Result<Option<Vec<Pubkey>>> {
+    // Neither method accepts `processed`.
+    let commitment = if commitment.is_at_least_confirmed() {
+        commitment
+    } else {
+        CommitmentConfig::confirmed()
+    };
+
+    // Newest first, page by page, until the first transaction older than the checkpoint.
+    let mut signatures = Vec::new();
+    let mut before = None;
+    'pages: loop {
+        let page = rpc_client
+            .get_signatures_for_address_with_config(
+                program_id,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: Some(SIGNATURES_PER_CALL),
+                    commitment: Some(commitment),
+                },
+            )
+            .await
+            .context("Failed to fetch the program's signatures")?;
+        let last_page = page.len() < SIGNATURES_PER_CALL;
+        for status in page {
+            if status.slot < since_slot {
+                break 'pages;
+            }
+            let signature: Signature = status
+                .signature
+                .parse()
+                .with_context(|| format!("invalid signature {}", status.signature))?;
+            before = Some(signature);
+            // A failed transaction changed nothing.
+            if status.err.is_none() {
+                signatures.push(signature);
+                if signatures.len() > max_transactions {
+                    return Ok(None);
+                }
+            }
+        }
+        if last_page {
+            break;
+        }
+    }
+
+    let config = RpcTransactionConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        commitment: Some(commitment),
+        max_supported_transaction_version: Some(0),
+    };
+    let transactions: Vec<Vec<Pubkey>> = stream::iter(signatures)
+        .map(|signature| async move {
+            let transaction = rpc_client
+                .get_transaction_with_config(&signature, config)
+                .await
+                .with_context(|| format!("Failed to fetch transaction {}", signature))?;
+            let decoded = transaction
+                .transaction
+                .transaction
+                .decode()
+                .with_context(|| format!("Could not decode transaction {}", signature))?;
+            let mut written: Vec<Pubkey> = decoded
+                .message
+                .static_account_keys()
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| decoded.message.is_maybe_writable(*index, None))
+                .map(|(_, key)| *key)
+                .collect();
+            // Accounts loaded from address lookup tables are only listed in the metadata.
+            if let Some(OptionSerializer::Some(loaded)) = transaction
+                .transaction
+                .meta
+                .map(|meta| meta.loaded_addresses)
+            {
+                for address in loaded.writable {
+                    written.push(
+                        address
+                            .parse()
+                            .with_context(|| format!("invalid loaded address {}", address))?,
+                    );
+                }
+            }
+            anyhow::Ok(written)
+        })
+        .buffered(fetch::DEFAULT_PARALLELISM)
+        .try_collect()
+        .await?;
+
+    let mut seen = BTreeSet::new();
+    Ok(Some(
+        transactions
+            .into_iter()
+            .flatten()
+            .filter(|pubkey| pubkey != program_id && seen.insert(*pubkey))
+            .collect(),
+    ))
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+pub use tokio::time::Instant;
+
+/// Where the pipeline's timers and timestamps come from.
+///
+/// Every wait goes through [`Clock::sleep`] / [`Clock::sleep_until`] and every deadline is a
+/// tokio [`Instant`], so a test on a paused runtime (`#[tokio::test(start_paused = true)]`)
+/// drives all of them by advancing virtual time. [`Clock::now`] is the wall clock: the system's
+/// by default, or one derived from tokio's time with [`Clock::starting_at`], so timestamps
+/// don't depend on when a test runs either.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clock {
+    /// Wall time at `.1`, for a clock driven by tokio's time.
+    origin: Option<(DateTime<Utc>, Instant)>,
+}
+
+impl Clock {
+    /// The system clock.
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// A wall clock reading `start` now and advancing with tokio's (possibly paused) time.
+    pub fn starting_at(start: DateTime<Utc>) -> Self {
+        Self {
+            origin: Some((start, Instant::now())),
+        }
+    }
+
+    /// The current wall time.
+    pub fn now(&self) -> DateTime<Utc> {
+        match self.origin {
+            None => Utc::now(),
+            Some((start, at)) => start + at.elapsed(),
+        }
+    }
+
+    /// The current wall time in unix seconds, as lifecycles are evaluated at.
+    pub fn unix_now(&self) -> i64 {
+        self.now().timestamp()
+    }
+
+    /// The current monotonic time, for deadlines.
+    pub fn instant(&self) -> Instant {
+        Instant::now()
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    /// Waits until `deadline`, or forever without one.
+    pub async fn sleep_until(&self, deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Spreads retry delays, so clients failing together don't all retry at the same moment.
+///
+/// A small splitmix64 generator: seeded from the system by default, or with a fixed seed
+/// ([`Jitter::seeded`]) for the same delays on every run.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    state: u64,
+}
+
+impl Default for Jitter {
+    fn default() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Self::seeded(nanos ^ u64::from(std::process::id()).rotate_left(32))
+    }
+}
+
+impl Jitter {
+    pub fn seeded(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A delay between half of `delay` and `delay`.
+    pub fn spread(&mut self, delay: Duration) -> Duration {
+        // The top 53 bits make an f64 in [0, 1).
+        let share = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        delay.mul_f64(0.5 + share / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn a_started_clock_follows_paused_time() {
+        let start = Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap();
+        let clock = Clock::starting_at(start);
+        let deadline = clock.instant() + Duration::from_secs(90);
+
+        clock.sleep(Duration::from_secs(30)).await;
+        assert_eq!(clock.now(), start + Duration::from_secs(30));
+        assert_eq!(clock.unix_now(), start.timestamp() + 30);
+        clock.sleep_until(Some(deadline)).await;
+        assert_eq!(clock.now(), start + Duration::from_secs(90));
+    }
+
+    #[test]
+    fn seeded_jitter_repeats_and_stays_in_range() {
+        let delay = Duration::from_secs(1);
+        let spread = |seed| {
+            let mut jitter = Jitter::seeded(seed);
+            (0..100).map(|_| jitter.spread(delay)).collect::<Vec<_>>()
+        };
+
+        let delays = spread(7);
+        assert_eq!(delays, spread(7));
+        assert_ne!(delays, spread(8));
+        assert!(delays.iter().all(|d| *d >= delay / 2 && *d <= delay));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::future::{BoxFuture, FutureExt, Shared};
+
+/// Result shared by every caller of one flight. Errors are shared too, hence the `Arc`.
+pub type FlightResult<V> = Result<V, Arc<anyhow::Error>>;
+
+/// An execution in progress, tagged with an id so a finished flight never removes a newer one.
+type Flight<V> = (u64, Shared<BoxFuture<'static, FlightResult<V>>>);
+
+/// Single-flight execution: concurrent calls with the same key share one execution.
+///
+/// The first caller for a key starts the work; callers arriving while it runs wait for it and
+/// get a clone of its result instead of starting their own. The key is forgotten as soon as
+/// the work completes, so nothing is cached: a call arriving afterwards runs again.
+/// A caller that joins a flight may get a result computed from data read slightly before it
+/// arrived, never older than the flight's start.
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, Flight<V>>>,
+    next_id: AtomicU64,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Runs `work` for `key`, or joins the execution already running for it.
+    /// Returns the result and whether it was joined (`true`) rather than executed.
+    ///
+    /// `work` keeps running when every caller waiting on it is dropped; the next caller for
+    /// the key picks it up.
+    pub async fn run<F, Fut>(&self, key: K, work: F) -> (FlightResult<V>, bool)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<V>> + Send + 'static,
+    {
+        let (id, flight, joined) = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some((id, flight)) => (*id, flight.clone(), true),
+                None => {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    let flight = work()
+                        .map(|result| result.map_err(Arc::new))
+                        .boxed()
+                        .shared();
+                    in_flight.insert(key.clone(), (id, flight.clone()));
+                    (id, flight, false)
+                }
+            }
+        };
+
+        let result = flight.await;
+
+        // Whoever finishes first forgets the flight; a newer flight for the same key is kept.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(&key)
+            .is_some_and(|(current, _)| *current == id)
+        {
+            in_flight.remove(&key);
+        }
+        (result, joined)
+    }
+
+    /// Number of keys with an execution in progress.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use futures::future::join_all;
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    /// Work returning `value` once `release` is notified, counting its executions.
+    fn work(
+        executions: &Arc<AtomicUsize>,
+        release: &Arc<Notify>,
+        value: anyhow::Result<usize>,
+    ) -> impl Future<Output = anyhow::Result<usize>> + Send + 'static {
+        executions.fetch_add(1, Ordering::SeqCst);
+        let release = release.clone();
+        async move {
+            release.notified().await;
+            value
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_share_one_execution() {
+        let flights = SingleFlight::<&str, usize>::default();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let calls = join_all(
+            (0..10).map(|_| flights.run("poll-1", || work(&executions, &release, Ok(42)))),
+        );
+        let (results, ()) = futures::join!(calls, async {
+            // Every call has started by the time this runs.
+            assert_eq!(flights.in_flight(), 1);
+            release.notify_one();
+        });
+
+        assert_eq!(executions.load(Ordering::SeqCst), 1);
+        assert_eq!(results.iter().filter(|(_, joined)| *joined).count(), 9);
+        assert!(results
+            .iter()
+            .all(|(result, _)| *result.as_ref().unwrap() == 42));
+        assert_eq!(flights.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_cached_after_a_flight() {
+        let flights = SingleFlight::<&str, usize>::default();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        for _ in 0..2 {
+            release.notify_one();
+            let (result, joined) = flights
+                .run("poll-1", || work(&executions, &release, Ok(1)))
+                .await;
+            assert_eq!((result.unwrap(), joined), (1, false));
+        }
+        assert_eq!(executions.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn different_keys_run_separately() {
+        let flights = SingleFlight::<&str, usize>::default();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let (a, b, ()) = futures::join!(
+            flights.run("poll-1", || work(&executions, &release, Ok(1))),
+            flights.run("poll-2", || work(&executions, &release, Ok(2))),
+            async {
+                assert_eq!(flights.in_flight(), 2);
+                release.notify_waiters();
+            }
+        );
+        assert_eq!((a.0.unwrap(), a.1), (1, false));
+        assert_eq!((b.0.unwrap(), b.1), (2, false));
+        assert_eq!(executions.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn errors_are_shared_too() {
+        let flights = SingleFlight::<&str, usize>::default();
+        let executions = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let (a, b, ()) = futures::join!(
+            flights.run("poll-1", || work(
+                &executions,
+                &release,
+                Err(anyhow::anyhow!("database down"))
+            )),
+            flights.run("poll-1", || work(&executions, &release, Ok(1))),
+            async { release.notify_one() }
+        );
+        let (a, b) = (a.0.unwrap_err(), b.0.unwrap_err());
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.to_string(), "database down");
+        assert_eq!(executions.load(Ordering::SeqCst), 1);
+    }
+}
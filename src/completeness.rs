@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::db::db::{anomaly_counts, check_poll_checksum, list_annotations, PgPool, PollKey};
+use crate::db::models::{program_label, Poll};
+use voting_dapp_api_types as api_types;
+
+/// Points taken off a poll's completeness score by each signal (see [`score`]).
+///
+/// Configured as `name=points` pairs, e.g. `critical_annotation=60,anomaly=5`; options not
+/// given keep their default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompletenessWeights {
+    /// The row no longer matches its checksum: it was modified outside the listener.
+    pub checksum_mismatch: u32,
+    /// The row has no checksum yet, so it can't be verified.
+    pub no_checksum: u32,
+    /// The row predates program or account tracking.
+    pub legacy_row: u32,
+    /// Per open `critical` annotation.
+    pub critical_annotation: u32,
+    /// Per open `warning` annotation.
+    pub warning_annotation: u32,
+    /// Per anomaly recorded for the poll (rejected lifecycle transition, checksum mismatch).
+    pub anomaly: u32,
+}
+
+impl Default for CompletenessWeights {
+    fn default() -> Self {
+        Self {
+            checksum_mismatch: 50,
+            no_checksum: 10,
+            legacy_row: 15,
+            critical_annotation: 40,
+            warning_annotation: 15,
+            anomaly: 10,
+        }
+    }
+}
+
+impl CompletenessWeights {
+    fn fields(&self) -> [(&'static str, u32); 6] {
+        [
+            ("checksum_mismatch", self.checksum_mismatch),
+            ("no_checksum", self.no_checksum),
+            ("legacy_row", self.legacy_row),
+            ("critical_annotation", self.critical_annotation),
+            ("warning_annotation", self.warning_annotation),
+            ("anomaly", self.anomaly),
+        ]
+    }
+}
+
+impl FromStr for CompletenessWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, points) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a name=points pair", pair))?;
+            let points: u32 = points
+                .trim()
+                .parse()
+                .map_err(|_| format!("'{}' is not a number of points", points.trim()))?;
+            let field = match name.trim() {
+                "checksum_mismatch" => &mut weights.checksum_mismatch,
+                "no_checksum" => &mut weights.no_checksum,
+                "legacy_row" => &mut weights.legacy_row,
+                "critical_annotation" => &mut weights.critical_annotation,
+                "warning_annotation" => &mut weights.warning_annotation,
+                "anomaly" => &mut weights.anomaly,
+                other => return Err(format!("unknown completeness weight '{}'", other)),
+            };
+            *field = points;
+        }
+        Ok(weights)
+    }
+}
+
+impl fmt::Display for CompletenessWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|(name, points)| format!("{}={}", name, points))
+            .collect();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+/// What the index knows about one poll's checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumState {
+    Matches,
+    Mismatch,
+    Missing,
+}
+
+/// The signals a completeness score is computed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletenessInputs {
+    pub checksum: ChecksumState,
+    /// The row predates program or account tracking.
+    pub legacy_row: bool,
+    /// Severities of the poll's open annotations.
+    pub open_annotations: Vec<String>,
+    /// Anomalies recorded for the poll, by kind.
+    pub anomalies: BTreeMap<String, i64>,
+}
+
+/// One signal that lowered a score.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Factor {
+    /// Name of the weight applied, see [`CompletenessWeights`].
+    pub name: &'static str,
+    pub penalty: u32,
+    pub detail: String,
+}
+
+/// How far a poll's indexed data can be trusted, from 0 to 100, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completeness {
+    pub score: u8,
+    /// Signals that lowered the score, largest penalty first. Empty for a perfect score.
+    pub factors: Vec<Factor>,
+}
+
+impl Completeness {
+    /// The API representation; the factors are left out unless `explain`.
+    pub fn to_dto(&self, explain: bool) -> api_types::Completeness {
+        api_types::Completeness {
+            score: self.score,
+            factors: explain.then(|| {
+                self.factors
+                    .iter()
+                    .map(|factor| api_types::CompletenessFactor {
+                        factor: factor.name.to_string(),
+                        penalty: factor.penalty,
+                        detail: factor.detail.clone(),
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// Scores a poll: 100 minus the penalty of every signal present, floored at 0.
+pub fn score(inputs: &CompletenessInputs, weights: &CompletenessWeights) -> Completeness {
+    let mut factors = Vec::new();
+    let mut add = |name: &'static str, penalty: u32, detail: String| {
+        if penalty > 0 {
+            factors.push(Factor {
+                name,
+                penalty,
+                detail,
+            });
+        }
+    };
+
+    match inputs.checksum {
+        ChecksumState::Matches => {}
+        ChecksumState::Mismatch => add(
+            "checksum_mismatch",
+            weights.checksum_mismatch,
+            "row was modified outside the listener".to_string(),
+        ),
+        ChecksumState::Missing => add(
+            "no_checksum",
+            weights.no_checksum,
+            "row has no checksum to verify against".to_string(),
+        ),
+    }
+    if inputs.legacy_row {
+        add(
+            "legacy_row",
+            weights.legacy_row,
+            "indexed before program and account tracking".to_string(),
+        );
+    }
+    for (severity, name, weight) in [
+        (
+            "critical",
+            "critical_annotation",
+            weights.critical_annotation,
+        ),
+        ("warning", "warning_annotation", weights.warning_annotation),
+    ] {
+        let open = inputs
+            .open_annotations
+            .iter()
+            .filter(|s| s.as_str() == severity)
+            .count() as u32;
+        if open > 0 {
+            add(
+                name,
+                weight.saturating_mul(open),
+                format!("{} open {} annotation(s)", open, severity),
+            );
+        }
+    }
+    for (kind, count) in &inputs.anomalies {
+        let count = u32::try_from(*count).unwrap_or(u32::MAX);
+        add(
+            "anomaly",
+            weights.anomaly.saturating_mul(count),
+            format!("{} anomaly record(s) of kind {}", count, kind),
+        );
+    }
+
+    factors.sort_by(|a, b| b.penalty.cmp(&a.penalty).then(a.name.cmp(b.name)));
+    let total: u32 = factors
+        .iter()
+        .fold(0, |total, factor| total.saturating_add(factor.penalty));
+    Completeness {
+        score: 100u32.saturating_sub(total) as u8,
+        factors,
+    }
+}
+
+/// Default of `--completeness-event-threshold`.
+pub const DEFAULT_CHANGE_THRESHOLD: u8 = 10;
+
+/// Remembers the last score of each poll, to emit an event when one moves by at least a
+/// threshold. Scores are computed on demand, so a change is seen on the first read after it.
+#[derive(Debug)]
+pub struct ScoreChanges {
+    threshold: u8,
+    last: Mutex<HashMap<PollKey, u8>>,
+}
+
+impl ScoreChanges {
+    /// Emits an event for changes of at least `threshold` points (1 at least).
+    pub fn new(threshold: u8) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the poll's current score. When it moved by the threshold or more since the
+    /// previous one, logs the change with the factors behind the new score and returns the
+    /// previous score. The first score of a poll has nothing to compare with.
+    pub fn observe(&self, key: &PollKey, completeness: &Completeness) -> Option<u8> {
+        let previous = self
+            .last
+            .lock()
+            .unwrap()
+            .insert(key.clone(), completeness.score)?;
+        if previous.abs_diff(completeness.score) < self.threshold {
+            return None;
+        }
+        let factors: Vec<&str> = completeness.factors.iter().map(|f| f.name).collect();
+        info!(
+            program = %program_label(&key.0),
+            poll_id = key.1,
+            from = previous,
+            to = completeness.score,
+            factors = ?factors,
+            "Poll completeness score changed"
+        );
+        Some(previous)
+    }
+}
+
+impl Default for ScoreChanges {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHANGE_THRESHOLD)
+    }
+}
+
+/// Gathers the signals of a stored poll (two queries) and scores it.
+pub fn assess(pool: &PgPool, poll: &Poll, weights: &CompletenessWeights) -> Result<Completeness> {
+    let checksum = match (poll.checksum, check_poll_checksum(poll)) {
+        (None, _) => ChecksumState::Missing,
+        (Some(_), Some(_)) => ChecksumState::Mismatch,
+        (Some(_), None) => ChecksumState::Matches,
+    };
+    let key = (poll.program_id.as_slice(), poll.poll_id);
+    let open_annotations = list_annotations(pool, Some(key), false)?
+        .into_iter()
+        .map(|note| note.severity)
+        .collect();
+    let anomalies = anomaly_counts(pool, key)
+        .with_context(|| format!("Failed to count anomalies of poll {}", poll.poll_id))?;
+    let inputs = CompletenessInputs {
+        checksum,
+        legacy_row: poll.program_id.is_empty() || poll.account_pubkey.is_empty(),
+        open_annotations,
+        anomalies,
+    };
+    Ok(score(&inputs, weights))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(
+        checksum: ChecksumState,
+        legacy_row: bool,
+        annotations: &[&str],
+        anomalies: &[(&str, i64)],
+    ) -> CompletenessInputs {
+        CompletenessInputs {
+            checksum,
+            legacy_row,
+            open_annotations: annotations.iter().map(|s| s.to_string()).collect(),
+            anomalies: anomalies
+                .iter()
+                .map(|(kind, count)| (kind.to_string(), *count))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn scores_with_the_default_weights() {
+        use ChecksumState::*;
+        let weights = CompletenessWeights::default();
+        // (inputs, score, factors largest first)
+        let cases = [
+            // Nothing wrong.
+            (inputs(Matches, false, &[], &[]), 100, vec![]),
+            // Each signal alone.
+            (
+                inputs(Missing, false, &[], &[]),
+                90,
+                vec![("no_checksum", 10)],
+            ),
+            (
+                inputs(Mismatch, false, &[], &[]),
+                50,
+                vec![("checksum_mismatch", 50)],
+            ),
+            (
+                inputs(Matches, true, &[], &[]),
+                85,
+                vec![("legacy_row", 15)],
+            ),
+            // Annotations count per open one; other severities are free.
+            (
+                inputs(Matches, false, &["warning", "warning", "info"], &[]),
+                70,
+                vec![("warning_annotation", 30)],
+            ),
+            (
+                inputs(Matches, false, &[], &[("rejected_transition", 2)]),
+                80,
+                vec![("anomaly", 20)],
+            ),
+            // Partially filled: several signals add up.
+            (
+                inputs(Missing, true, &["critical"], &[("checksum_mismatch", 1)]),
+                25,
+                vec![
+                    ("critical_annotation", 40),
+                    ("legacy_row", 15),
+                    ("anomaly", 10),
+                    ("no_checksum", 10),
+                ],
+            ),
+            // Everything wrong: floored at 0.
+            (
+                inputs(
+                    Mismatch,
+                    true,
+                    &["critical", "critical", "warning"],
+                    &[("rejected_transition", i64::MAX)],
+                ),
+                0,
+                vec![
+                    ("anomaly", u32::MAX),
+                    ("critical_annotation", 80),
+                    ("checksum_mismatch", 50),
+                    ("legacy_row", 15),
+                    ("warning_annotation", 15),
+                ],
+            ),
+        ];
+        for (inputs, expected_score, expected_factors) in cases {
+            let completeness = score(&inputs, &weights);
+            let factors: Vec<(&str, u32)> = completeness
+                .factors
+                .iter()
+                .map(|factor| (factor.name, factor.penalty))
+                .collect();
+            assert_eq!(
+                (completeness.score, factors),
+                (expected_score, expected_factors),
+                "{:?}",
+                inputs
+            );
+        }
+    }
+
+    #[test]
+    fn scores_with_custom_weights() {
+        let weights: CompletenessWeights = "critical_annotation=60, anomaly=0".parse().unwrap();
+        assert_eq!(weights.critical_annotation, 60);
+        assert_eq!(weights.no_checksum, 10);
+
+        let completeness = score(
+            &inputs(
+                ChecksumState::Missing,
+                false,
+                &["critical"],
+                &[("rejected_transition", 3)],
+            ),
+            &weights,
+        );
+        // A weight of 0 drops its factor altogether.
+        assert_eq!(completeness.score, 30);
+        assert_eq!(completeness.factors.len(), 2);
+
+        let free: CompletenessWeights = "checksum_mismatch=0,no_checksum=0,legacy_row=0,\
+            critical_annotation=0,warning_annotation=0,anomaly=0"
+            .parse()
+            .unwrap();
+        let completeness = score(
+            &inputs(ChecksumState::Mismatch, true, &["critical"], &[("x", 1)]),
+            &free,
+        );
+        assert_eq!((completeness.score, completeness.factors.len()), (100, 0));
+    }
+
+    #[test]
+    fn weights_parse_and_print() {
+        let weights = CompletenessWeights::default();
+        assert_eq!(weights.to_string().parse(), Ok(weights));
+        assert_eq!("".parse(), Ok(weights));
+        assert!("anomaly".parse::<CompletenessWeights>().is_err());
+        assert!("anomaly=-1".parse::<CompletenessWeights>().is_err());
+        assert!("bogus=1".parse::<CompletenessWeights>().is_err());
+    }
+
+    #[test]
+    fn explain_controls_the_factors_of_the_dto() {
+        let completeness = score(
+            &inputs(ChecksumState::Missing, false, &[], &[]),
+            &CompletenessWeights::default(),
+        );
+        assert!(completeness.to_dto(false).factors.is_none());
+        let factors = completeness.to_dto(true).factors.unwrap();
+        assert_eq!(factors[0].factor, "no_checksum");
+    }
+
+    #[test]
+    fn only_changes_past_the_threshold_are_reported() {
+        let changes = ScoreChanges::new(10);
+        let key = (vec![1; 32], 7);
+        let at = |score| Completeness {
+            score,
+            factors: Vec::new(),
+        };
+
+        // The first score has nothing to compare with.
+        assert_eq!(changes.observe(&key, &at(100)), None);
+        assert_eq!(changes.observe(&key, &at(95)), None);
+        assert_eq!(changes.observe(&key, &at(85)), Some(95));
+        assert_eq!(changes.observe(&key, &at(100)), Some(85));
+        // Another poll has its own history.
+        assert_eq!(changes.observe(&(vec![1; 32], 8), &at(50)), None);
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use tracing::{info, warn};
+
+use crate::clock::Instant;
+
+/// Boxed future returned by a component's shutdown function.
+pub type ShutdownFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+type ShutdownFn = Box<dyn FnOnce() -> ShutdownFuture + Send>;
+
+/// A registered subsystem.
+struct Component {
+    name: &'static str,
+    depends_on: Vec<&'static str>,
+    timeout: Duration,
+    shutdown: ShutdownFn,
+}
+
+/// Registry of the listener's subsystems (writer, HTTP API, schedulers, ...) and what they depend on.
+///
+/// Shutdown stops components in reverse dependency order: a component is only stopped once
+/// everything that depends on it has stopped, so e.g. the database goes away only after the
+/// writer and the API are done with it. Each stop is bounded by the component's own timeout.
+#[derive(Default)]
+pub struct ComponentRegistry {
+    components: Vec<Component>,
+}
+
+/// What happened to one component during shutdown.
+#[derive(Debug)]
+pub enum StopOutcome {
+    Stopped,
+    Failed(String),
+    /// Its shutdown didn't finish within the timeout and was given up on.
+    TimedOut,
+}
+
+/// Final report of [`ComponentRegistry::shutdown`], in the order components were stopped.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub components: Vec<(&'static str, StopOutcome, Duration)>,
+}
+
+impl ShutdownReport {
+    /// Names of the components that timed out and were abandoned.
+    pub fn aborted(&self) -> Vec<&'static str> {
+        self.components
+            .iter()
+            .filter(|(_, outcome, _)| matches!(outcome, StopOutcome::TimedOut))
+            .map(|(name, _, _)| *name)
+            .collect()
+    }
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a component. `depends_on` lists components that must outlive it.
+    /// `shutdown` is called once, and is given at most `timeout` to finish.
+    pub fn register<F, Fut>(
+        &mut self,
+        name: &'static str,
+        depends_on: &[&'static str],
+        timeout: Duration,
+        shutdown: F,
+    ) where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.components.push(Component {
+            name,
+            depends_on: depends_on.to_vec(),
+            timeout,
+            shutdown: Box::new(move || Box::pin(shutdown())),
+        });
+    }
+
+    /// Names of the registered components, in registration order.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.components.iter().map(|c| c.name).collect()
+    }
+
+    /// Order in which components have to start: dependencies first.
+    /// Fails on unknown dependencies and dependency cycles.
+    pub fn startup_order(&self) -> Result<Vec<&'static str>> {
+        let index: HashMap<&str, usize> = self
+            .components
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.name, i))
+            .collect();
+        for c in &self.components {
+            for dep in &c.depends_on {
+                if !index.contains_key(dep) {
+                    bail!(
+                        "component '{}' depends on unknown component '{}'",
+                        c.name,
+                        dep
+                    );
+                }
+            }
+        }
+
+        // Depth-first topological sort; `visiting` detects cycles.
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            New,
+            Visiting,
+            Done,
+        }
+        fn visit(
+            i: usize,
+            components: &[Component],
+            index: &HashMap<&str, usize>,
+            marks: &mut [Mark],
+            order: &mut Vec<&'static str>,
+        ) -> Result<()> {
+            match marks[i] {
+                Mark::Done => return Ok(()),
+                Mark::Visiting => bail!("dependency cycle involving '{}'", components[i].name),
+                Mark::New => {}
+            }
+            marks[i] = Mark::Visiting;
+            for dep in &components[i].depends_on {
+                visit(index[dep], components, index, marks, order)?;
+            }
+            marks[i] = Mark::Done;
+            order.push(components[i].name);
+            Ok(())
+        }
+
+        let mut marks = vec![Mark::New; self.components.len()];
+        let mut order = Vec::with_capacity(self.components.len());
+        for i in 0..self.components.len() {
+            visit(i, &self.components, &index, &mut marks, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// Stops every component in reverse startup order, logging progress, and returns the report.
+    ///
+    /// A component that fails or times out doesn't block the rest: the next one is stopped anyway.
+    pub async fn shutdown(mut self) -> Result<ShutdownReport> {
+        let mut order = self.startup_order()?;
+        order.reverse();
+
+        let mut report = ShutdownReport::default();
+        for name in order {
+            let position = self
+                .components
+                .iter()
+                .position(|c| c.name == name)
+                .expect("startup_order only returns registered names");
+            let component = self.components.swap_remove(position);
+
+            info!(component = name, "Stopping component");
+            let started = Instant::now();
+            let outcome =
+                match tokio::time::timeout(component.timeout, (component.shutdown)()).await {
+                    Ok(Ok(())) => StopOutcome::Stopped,
+                    Ok(Err(e)) => {
+                        warn!(component = name, error = ?e, "Component failed to stop cleanly");
+                        StopOutcome::Failed(e.to_string())
+                    }
+                    Err(_) => {
+                        warn!(
+                            component = name,
+                            timeout_secs = component.timeout.as_secs_f64(),
+                            "Component did not stop in time, abandoning it"
+                        );
+                        StopOutcome::TimedOut
+                    }
+                };
+            let elapsed = started.elapsed();
+            if matches!(outcome, StopOutcome::Stopped) {
+                info!(
+                    component = name,
+                    elapsed_ms = elapsed.as_millis() as u64,
+                    "Component stopped"
+                );
+            }
+            report.components.push((name, outcome, elapsed));
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Registers a component that records its name in `stopped` when stopped.
+    fn register(
+        registry: &mut ComponentRegistry,
+        stopped: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+        depends_on: &[&'static str],
+    ) {
+        let stopped = stopped.clone();
+        registry.register(name, depends_on, TIMEOUT, move || async move {
+            stopped.lock().unwrap().push(name);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn starts_dependencies_first() {
+        let stopped = Arc::default();
+        let mut registry = ComponentRegistry::new();
+        register(&mut registry, &stopped, "api", &["database"]);
+        register(&mut registry, &stopped, "writer", &["database"]);
+        register(&mut registry, &stopped, "database", &[]);
+        register(&mut registry, &stopped, "listener", &["writer"]);
+
+        assert_eq!(
+            registry.startup_order().unwrap(),
+            ["database", "api", "writer", "listener"]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_dependencies_and_cycles() {
+        let stopped = Arc::default();
+        let mut registry = ComponentRegistry::new();
+        register(&mut registry, &stopped, "writer", &["database"]);
+        assert!(registry.startup_order().is_err());
+
+        let mut registry = ComponentRegistry::new();
+        register(&mut registry, &stopped, "a", &["b"]);
+        register(&mut registry, &stopped, "b", &["a"]);
+        let err = registry.startup_order().unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+    }
+
+    #[tokio::test]
+    async fn stops_dependents_first() {
+        let stopped = Arc::default();
+        let mut registry = ComponentRegistry::new();
+        register(&mut registry, &stopped, "database", &[]);
+        register(&mut registry, &stopped, "writer", &["database"]);
+        register(&mut registry, &stopped, "listener", &["writer"]);
+
+        let report = registry.shutdown().await.unwrap();
+        assert_eq!(*stopped.lock().unwrap(), ["listener", "writer", "database"]);
+        assert!(report.aborted().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_and_timeouts_dont_block_the_rest() {
+        let stopped = Arc::default();
+        let mut registry = ComponentRegistry::new();
+        register(&mut registry, &stopped, "database", &[]);
+        registry.register("writer", &["database"], TIMEOUT, || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        registry.register("api", &["database"], TIMEOUT, || async {
+            bail!("port already closed")
+        });
+
+        let report = registry.shutdown().await.unwrap();
+        assert_eq!(report.aborted(), ["writer"]);
+        assert!(report
+            .components
+            .iter()
+            .any(|(name, outcome, _)| *name == "api" && matches!(outcome, StopOutcome::Failed(_))));
+        assert_eq!(*stopped.lock().unwrap(), ["database"]);
+    }
+}
@@ -0,0 +1,254 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::Command;
+
+use crate::errors;
+
+/// The config file read from the working directory when `--config` isn't given.
+pub const DEFAULT_PATH: &str = "listener.toml";
+/// Environment variable naming the config file, like `--config`.
+pub const PATH_ENV: &str = "LISTENER_CONFIG";
+
+/// Finds the config file to read, before clap parses the arguments: `--config <path>` (or
+/// `--config=<path>`), then `LISTENER_CONFIG`, then `listener.toml` if it exists.
+pub fn resolve_path(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    if let Some(path) = std::env::var_os(PATH_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let default = Path::new(DEFAULT_PATH);
+    default.exists().then(|| default.to_path_buf())
+}
+
+/// Translates a config file into the environment variables of `command`'s options.
+///
+/// Keys are long flag names, with dashes or underscores (`ws_url = "..."` sets
+/// `SOLANA_WS_URL`, like `--ws-url`). Strings, numbers and booleans are used as they are;
+/// arrays are joined with commas, for the comma-separated options.
+pub fn parse(text: &str, command: &Command) -> Result<Vec<(String, String)>> {
+    let table: toml::Table = toml::from_str(text)
+        .map_err(|e| errors::coded(&errors::CONFIG_FILE_INVALID, e.to_string()))?;
+
+    let mut vars = Vec::new();
+    for (key, value) in table {
+        let flag = key.replace('_', "-");
+        let env = command
+            .get_arguments()
+            .find(|arg| {
+                arg.get_long() == Some(flag.as_str())
+                    || arg
+                        .get_all_aliases()
+                        .into_iter()
+                        .flatten()
+                        .any(|a| a == flag)
+            })
+            .and_then(|arg| arg.get_env())
+            .and_then(|env| env.to_str())
+            .ok_or_else(|| {
+                errors::coded(
+                    &errors::CONFIG_FILE_INVALID,
+                    format!("`{}` isn't a listener option", key),
+                )
+            })?;
+        let value = match value {
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| scalar(&key, item))
+                .collect::<Result<Vec<_>>>()?
+                .join(","),
+            value => scalar(&key, value)?,
+        };
+        vars.push((env.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// A string, number, boolean or date as the text clap would parse from the environment.
+fn scalar(key: &str, value: toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        toml::Value::Datetime(value) => Ok(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => Err(errors::coded(
+            &errors::CONFIG_FILE_INVALID,
+            format!(
+                "`{}` must be a string, number, boolean or a flat array",
+                key
+            ),
+        )),
+    }
+}
+
+/// Reads `path` and sets the variables it translates to, see [`parse`]. Variables that are
+/// already set (by the environment or `.env`) are kept, so flags override the environment,
+/// which overrides the file. Returns the names of the variables that were set.
+pub fn apply(path: &Path, command: &Command) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        errors::coded(
+            &errors::CONFIG_FILE_INVALID,
+            format!("Failed to read {}: {}", path.display(), e),
+        )
+    })?;
+    let vars = parse(&text, command)
+        .map_err(|e| e.context(format!("Invalid config file {}", path.display())))?;
+
+    let mut applied = Vec::new();
+    for (name, value) in vars {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(&name, value);
+            applied.push(name);
+        }
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction};
+
+    use super::*;
+
+    /// A few options shaped like the listener's, on variables no other test touches.
+    fn command() -> Command {
+        Command::new("listener")
+            .arg(Arg::new("ws_url").long("ws-url").env("CONFIG_TEST_WS_URL"))
+            .arg(
+                Arg::new("program_ids")
+                    .long("program-ids")
+                    .alias("program-id")
+                    .env("CONFIG_TEST_PROGRAM_IDS")
+                    .value_delimiter(','),
+            )
+            .arg(
+                Arg::new("dedup_max_entries")
+                    .long("dedup-max-entries")
+                    .env("CONFIG_TEST_DEDUP_MAX_ENTRIES"),
+            )
+            .arg(
+                Arg::new("log_json")
+                    .long("log-json")
+                    .env("CONFIG_TEST_LOG_JSON")
+                    .action(ArgAction::SetTrue),
+            )
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn keys_become_the_variables_of_their_flags() {
+        let vars = parse(
+            r#"
+            ws_url = "ws://localhost:8900"
+            program-id = ["A", "B"]
+            dedup_max_entries = 500
+            log_json = true
+            "#,
+            &command(),
+        )
+        .unwrap();
+        let mut vars: Vec<(&str, &str)> = vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        vars.sort();
+        assert_eq!(
+            vars,
+            [
+                ("CONFIG_TEST_DEDUP_MAX_ENTRIES", "500"),
+                ("CONFIG_TEST_LOG_JSON", "true"),
+                ("CONFIG_TEST_PROGRAM_IDS", "A,B"),
+                ("CONFIG_TEST_WS_URL", "ws://localhost:8900"),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_files_are_refused() {
+        for text in [
+            "ws_url = ",
+            "unknown_option = 1",
+            "ws_url = { host = \"localhost\" }",
+            "program_ids = [[\"A\"]]",
+        ] {
+            let err = parse(text, &command()).unwrap_err();
+            assert_eq!(
+                errors::classify(&err),
+                Some(&errors::CONFIG_FILE_INVALID),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_path_comes_from_the_flag_before_the_environment() {
+        assert_eq!(
+            resolve_path(args(&["--ws-url", "x", "--config", "a.toml"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            resolve_path(args(&["--config=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        // Past `--` it's not a flag anymore.
+        std::env::set_var(PATH_ENV, "c.toml");
+        assert_eq!(
+            resolve_path(args(&["--", "--config", "a.toml"])),
+            Some(PathBuf::from("c.toml"))
+        );
+        std::env::remove_var(PATH_ENV);
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        let path = std::env::temp_dir().join(format!("listener-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            ws_url = "ws://file:8900"
+            program_ids = ["A", "B"]
+            dedup_max_entries = 500
+            "#,
+        )
+        .unwrap();
+        std::env::set_var("CONFIG_TEST_DEDUP_MAX_ENTRIES", "7");
+
+        let applied = apply(&path, &command()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let matches = command()
+            .try_get_matches_from(["listener", "--ws-url", "ws://flag:8900"])
+            .unwrap();
+        let value = |id: &str| matches.get_one::<String>(id).unwrap().as_str();
+        assert_eq!(value("ws_url"), "ws://flag:8900");
+        assert_eq!(value("dedup_max_entries"), "7");
+        let program_ids: Vec<&String> = matches.get_many("program_ids").unwrap().collect();
+        assert_eq!(program_ids, ["A", "B"]);
+
+        let mut applied = applied;
+        applied.sort();
+        assert_eq!(applied, ["CONFIG_TEST_PROGRAM_IDS", "CONFIG_TEST_WS_URL"]);
+        for name in [
+            "CONFIG_TEST_WS_URL",
+            "CONFIG_TEST_PROGRAM_IDS",
+            "CONFIG_TEST_DEDUP_MAX_ENTRIES",
+        ] {
+            std::env::remove_var(name);
+        }
+    }
+}
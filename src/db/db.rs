@@ -1,10 +1,26 @@
-use super::models::Poll;
+use super::models::{
+    Annotation, BandwidthUsage, ConfigChange, DecodeFailure, Delegation, Idl, IdlAccount, JobRow,
+    LifecycleTransition, Poll, PollResult, QuarantinedAccount, RawAccount,
+};
 use super::schema::polls::dsl::*;
-use crate::db::models::NewPoll;
+use super::schema::{
+    account_raw_history, annotations, anomalies, bandwidth_usage, config_changes, decode_failures,
+    delegations, events, idl_accounts, idls, jobs, lifecycle_transitions, listener_checkpoints,
+    poll_results, quarantined_accounts,
+};
+use crate::candidates;
+use crate::db::models::{
+    NewAnnotation, NewAnomaly, NewConfigChange, NewDecodeFailure, NewDelegation, NewEvent, NewIdl,
+    NewIdlAccount, NewLifecycleTransition, NewPoll, NewPollResult, NewRawAccount,
+};
+use crate::state::lifecycle::{self, LifecycleFacts, PollLifecycle, Transition};
 use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use diesel::prelude::*;
-use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
 use dotenvy::dotenv;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::time::Duration;
 
@@ -19,23 +35,56 @@ use std::time::Duration;
 /// PgPool = Pool<ConnectionManager<PgConnection>>
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 
+/// Connection customizer that makes every pooled connection read-only.
+///
+/// Sets `default_transaction_read_only = on` as soon as a connection is opened, so Postgres
+/// itself rejects any INSERT/UPDATE/DELETE even if a code path forgot to check the read-only flag.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadOnlyCustomizer;
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for ReadOnlyCustomizer {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query("SET SESSION default_transaction_read_only = on")
+            .execute(conn)
+            .map_err(diesel::r2d2::Error::QueryError)?;
+        Ok(())
+    }
+}
+
 // Establishes a PostgreSQL connection pool using environment variables.
 ///
 /// This function loads the `.env` file to retrieve `DATABASE_URL`, creates a Diesel connection manager,
 /// and builds a pool with sensible defaults like max size, lifetime, and health checks.
 pub fn establish_pool() -> Result<PgPool> {
+    establish_pool_with(false)
+}
+
+/// Same as [`establish_pool`], optionally opening every connection in read-only mode.
+///
+/// With `read_only = true` the pool installs [`ReadOnlyCustomizer`] as a hard backstop,
+/// which is what `--read-only` uses when pointed at a production replica.
+pub fn establish_pool_with(read_only: bool) -> Result<PgPool> {
     // Loads variables from `.env` into the environment.
     dotenv().ok();
 
     let db_url = env::var("DATABASE_URL").context("DATABASE_URL must be set in .env")?;
+    establish_pool_for(&db_url, read_only)
+}
 
+/// Same as [`establish_pool_with`], for a database URL that doesn't come from the environment
+/// (`cli init` checks the URL it is about to write).
+pub fn establish_pool_for(db_url: &str, read_only: bool) -> Result<PgPool> {
     let manager = ConnectionManager::<PgConnection>::new(db_url);
 
     // Build the pool with configuration.
-    let pool = Pool::builder()
+    let mut builder = Pool::builder()
         .max_size(15) // max 15 concorrent connections
         .max_lifetime(Some(Duration::from_secs(300))) // drop idle conns after 5 min
-        .test_on_check_out(true) // Check if the connection is alive before handing it over.
+        .test_on_check_out(true); // Check if the connection is alive before handing it over.
+    if read_only {
+        builder = builder.connection_customizer(Box::new(ReadOnlyCustomizer));
+    }
+    let pool = builder
         .build(manager)
         .context("Failed to build connection pool")?;
 
@@ -61,45 +110,2146 @@ pub fn _insert_poll(pool: &PgPool, poll: NewPoll) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Inserts or updates a poll using its `poll_id` as the unique key.
+/// A poll's unique key: `(program_id, poll_id)`. Poll ids are only unique within a program.
+pub type PollKey = (Vec<u8>, i64);
+
+/// Result of [`upsert_poll`] / [`upsert_polls`] for a single poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    /// The row was inserted or updated; carries the resulting lifecycle transition.
+    Written(Transition),
+    /// The stored row was observed at a newer slot, so the update was dropped.
+    Stale {
+        incoming_slot: i64,
+        stored_slot: i64,
+    },
+}
+
+/// Inserts or updates a single poll. See [`upsert_polls`].
+pub fn upsert_poll(
+    pool: &PgPool,
+    poll: &NewPoll,
+    skew: i64,
+    now: i64,
+) -> anyhow::Result<UpsertOutcome> {
+    let outcomes = upsert_polls(pool, std::slice::from_ref(poll), skew, now)?;
+    outcomes
+        .into_iter()
+        .next()
+        .map(|(_, outcome)| outcome)
+        .context("Upsert returned no outcome")
+}
+
+/// Inserts or updates a batch of polls in one transaction, using `(program_id, poll_id)` as the unique key.
+///
+/// If a poll with the same key already exists, it will be updated
+/// with the new values, but only when the incoming `last_slot` is greater than or equal
+/// to the stored one. Updates can arrive out of order (e.g. a backfill snapshot racing the
+/// websocket), and an older account state must never overwrite a newer row.
+///
+/// Postgres can't update the same row twice in one `INSERT ... ON CONFLICT`, so when the batch
+/// holds several updates for one poll only the newest (highest slot, then latest in the batch)
+/// is written; the others are superseded and don't get an outcome.
 ///
-/// If a poll with the same `poll_id` already exists, it will be updated
-/// with the new values. This ensures we always have the latest on-chain state.
-pub fn upsert_poll(pool: &PgPool, poll: &NewPoll) -> anyhow::Result<()> {
+/// The lifecycle state is re-evaluated in the same transaction at unix time `now` (with `skew`
+/// seconds of clock skew tolerance, see [`lifecycle::transition`]), and the resulting
+/// transitions are returned (one per written or stale poll) so the caller can emit events.
+pub fn upsert_polls(
+    pool: &PgPool,
+    batch: &[NewPoll],
+    skew: i64,
+    now: i64,
+) -> anyhow::Result<Vec<(PollKey, UpsertOutcome)>> {
+    use diesel::upsert::excluded;
+
+    // Keep only the newest update per poll. BTreeMap keeps the keys sorted, which is also the
+    // order rows get locked in below (a consistent lock order avoids deadlocks).
+    let mut latest: BTreeMap<PollKey, &NewPoll> = BTreeMap::new();
+    for poll in batch {
+        match latest.entry((poll.program_id.clone(), poll.poll_id)) {
+            Entry::Vacant(entry) => {
+                entry.insert(poll);
+            }
+            Entry::Occupied(mut entry) => {
+                if poll.last_slot >= entry.get().last_slot {
+                    entry.insert(poll);
+                }
+            }
+        }
+    }
+    if latest.is_empty() {
+        return Ok(Vec::new());
+    }
+    let programs: BTreeSet<&[u8]> = latest
+        .keys()
+        .map(|(program, _)| program.as_slice())
+        .collect();
+    let ids: BTreeSet<i64> = latest.keys().map(|(_, id_of_poll)| *id_of_poll).collect();
+    let rows: Vec<NewPoll> = latest.values().map(|poll| (*poll).clone()).collect();
+
     // Get a database connection from the pool.
     let mut conn = pool
         .get()
         .context("Failed to get DB connection from pool")?;
 
-    // Perform an upsert: insert if not exists, update otherwise.
-    // Diesel requires the column in `on_conflict()` to have a UNIQUE constraint in the DB schema.
-    diesel::insert_into(polls)
-        .values(poll)
-        .on_conflict(poll_id) // Unique column (you must add UNIQUE constraint in schema)
-        .do_update()
-        .set((
-            poll_owner.eq(&poll.poll_owner),
-            poll_name.eq(&poll.poll_name),
-            poll_description.eq(&poll.poll_description),
-            poll_start.eq(poll.poll_start),
-            poll_end.eq(poll.poll_end),
-            candidate_amount.eq(poll.candidate_amount),
-            candidate_winner.eq(&poll.candidate_winner),
-        ))
-        .execute(&mut conn)?;
+    let outcomes = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        // Lock the existing rows (if any) so concurrent writers can't interleave transitions.
+        // The filter may match a few extra rows (other combinations of the same programs and
+        // ids); locking them too is harmless.
+        let current: HashMap<PollKey, (String, i64, bool)> = polls
+            .filter(program_id.eq_any(&programs))
+            .filter(poll_id.eq_any(&ids))
+            .order((program_id, poll_id))
+            .select((
+                program_id,
+                poll_id,
+                lifecycle,
+                last_slot,
+                deleted_at.is_not_null(),
+            ))
+            .for_update()
+            .load::<(Vec<u8>, i64, String, i64, bool)>(conn)?
+            .into_iter()
+            .map(|(program, id_of_poll, state, slot, deleted)| {
+                ((program, id_of_poll), (state, slot, deleted))
+            })
+            .collect();
 
-    Ok(())
+        // Perform a multi-row upsert: insert if not exists, update otherwise.
+        // Diesel requires the columns in `on_conflict()` to have a UNIQUE constraint in the DB schema.
+        // `excluded(...)` refers to the values proposed for each row, and the `filter` skips rows
+        // whose stored slot is newer; only the rows actually written are returned.
+        // The checksum is part of the same statement, so it can never disagree with the row it covers.
+        let values: Vec<_> = rows
+            .iter()
+            .map(|poll| (poll, checksum.eq(poll.checksum())))
+            .collect();
+        let upsert = diesel::insert_into(polls)
+            .values(values)
+            .on_conflict((program_id, poll_id)) // Unique pair (UNIQUE constraint in the schema)
+            .do_update()
+            .set((
+                poll_owner.eq(excluded(poll_owner)),
+                poll_name.eq(excluded(poll_name)),
+                poll_description.eq(excluded(poll_description)),
+                poll_start.eq(excluded(poll_start)),
+                poll_end.eq(excluded(poll_end)),
+                candidate_amount.eq(excluded(candidate_amount)),
+                candidate_winner.eq(excluded(candidate_winner)),
+                last_slot.eq(excluded(last_slot)),
+                checksum.eq(excluded(checksum)),
+                account_pubkey.eq(excluded(account_pubkey)),
+                // `first_seen_at` keeps its insert-time default.
+                last_updated_at.eq(diesel::dsl::now),
+                // An update of a closed poll means its account was created again.
+                deleted_at.eq(None::<DateTime<Utc>>),
+            ));
+        // Upsert statements only get `.filter()` through `FilterDsl`, not `QueryDsl`.
+        let written: HashSet<PollKey> = diesel::query_dsl::methods::FilterDsl::filter(
+            upsert,
+            last_slot.le(excluded(last_slot)),
+        )
+        .returning((program_id, poll_id))
+        .get_results::<PollKey>(conn)?
+        .into_iter()
+        .collect();
+
+        let mut outcomes = Vec::with_capacity(rows.len());
+        let mut created = Vec::new();
+        for poll in &rows {
+            let key = (poll.program_id.clone(), poll.poll_id);
+            let stored = current.get(&key);
+            let outcome = if written.contains(&key) {
+                if stored.is_none_or(|(_, _, deleted)| *deleted) {
+                    created.push(key.clone());
+                }
+                // A re-created poll starts its lifecycle over, like a new one.
+                let transition = apply_lifecycle_transition(
+                    conn,
+                    &poll.program_id,
+                    poll.poll_id,
+                    stored
+                        .filter(|(_, _, deleted)| !deleted)
+                        .map(|(state, _, _)| state.as_str()),
+                    &poll.lifecycle_facts(),
+                    now,
+                    skew,
+                )?;
+                UpsertOutcome::Written(transition)
+            } else {
+                UpsertOutcome::Stale {
+                    incoming_slot: poll.last_slot,
+                    stored_slot: stored.map(|(_, slot, _)| *slot).unwrap_or_default(),
+                }
+            };
+            outcomes.push((key, outcome));
+        }
+
+        // A poll indexed after some of its candidates gets their votes now; from then on the
+        // candidates' upserts keep its results current.
+        for (program, id_of_poll) in &created {
+            let poll = polls
+                .filter(program_id.eq(program.as_slice()))
+                .filter(poll_id.eq(*id_of_poll))
+                .first::<Poll>(conn)?;
+            refresh_poll_results(conn, &poll)?;
+        }
+        Ok(outcomes)
+    })?;
+
+    Ok(outcomes)
+}
+
+/// Evaluates and persists a poll's lifecycle transition. Must run inside a transaction.
+///
+/// This is the only place the `lifecycle` column is written: both the writer (`upsert_poll`)
+/// and the time-based scheduler (`advance_lifecycles`) go through it.
+/// Moves are also appended to `lifecycle_transitions`, the poll's history (see `feed`).
+/// Impossible transitions leave the stored state untouched and are recorded as anomalies.
+/// Suppressed flaps (clock skew near a boundary) leave it untouched without an anomaly.
+fn apply_lifecycle_transition(
+    conn: &mut PgConnection,
+    program: &[u8],
+    id_of_poll: i64,
+    current: Option<&str>,
+    facts: &LifecycleFacts,
+    now: i64,
+    skew: i64,
+) -> QueryResult<Transition> {
+    // An unrecognized stored value is treated like a new poll and simply overwritten.
+    let current = current.and_then(|state| state.parse::<PollLifecycle>().ok());
+    let transition = lifecycle::transition(current, facts, now, skew);
+
+    match transition {
+        Transition::Unchanged(_) | Transition::Suppressed { .. } => {}
+        Transition::Moved { from, to } => {
+            diesel::update(
+                polls
+                    .filter(program_id.eq(program))
+                    .filter(poll_id.eq(id_of_poll)),
+            )
+            .set(lifecycle.eq(to.as_str()))
+            .execute(conn)?;
+            diesel::insert_into(lifecycle_transitions::table)
+                .values(&NewLifecycleTransition {
+                    program_id: program.to_vec(),
+                    poll_id: id_of_poll,
+                    from_state: from.map(|state| state.to_string()),
+                    to_state: to.to_string(),
+                })
+                .execute(conn)?;
+        }
+        Transition::Rejected { from, to } => {
+            diesel::insert_into(anomalies::table)
+                .values(&NewAnomaly {
+                    program_id: program.to_vec(),
+                    poll_id: id_of_poll,
+                    kind: "lifecycle_transition_rejected".to_string(),
+                    details: format!("impossible lifecycle transition {} -> {}", from, to),
+                })
+                .execute(conn)?;
+        }
+    }
+
+    Ok(transition)
 }
 
-/// Fetches all stored polls from the database.
+/// Re-evaluates the lifecycle of every non-terminal poll at unix time `now`.
+///
+/// Time-driven transitions (upcoming → active → ended) happen without any on-chain update,
+/// so the listener runs this periodically, with the same `skew` tolerance as the writer.
+/// Returns only the polls whose state changed (or whose transition was rejected or suppressed).
+pub fn advance_lifecycles(
+    pool: &PgPool,
+    skew: i64,
+    now: i64,
+) -> anyhow::Result<Vec<(PollKey, Transition)>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let terminal: Vec<&str> = PollLifecycle::ALL
+        .iter()
+        .filter(|state| state.is_terminal())
+        .map(|state| state.as_str())
+        .collect();
+    let candidates = polls
+        .filter(lifecycle.ne_all(terminal))
+        .select((program_id, poll_id))
+        .load::<PollKey>(&mut conn)?;
+
+    let mut changed = Vec::new();
+    for (program, id_of_poll) in candidates {
+        let transition = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+            // Re-read under lock: the writer may have updated the row since the scan.
+            let row = polls
+                .filter(program_id.eq(&program))
+                .filter(poll_id.eq(id_of_poll))
+                .for_update()
+                .first::<Poll>(conn)?;
+            apply_lifecycle_transition(
+                conn,
+                &program,
+                id_of_poll,
+                Some(&row.lifecycle),
+                &row.lifecycle_facts(),
+                now,
+                skew,
+            )
+        })?;
+
+        if !matches!(transition, Transition::Unchanged(_)) {
+            changed.push(((program, id_of_poll), transition));
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Fetches all stored polls from the database, optionally only those of one program.
 ///
 /// Used in the CLI to display all indexed poll records.
 /// Returns a vector of `Poll` structs.
-pub fn list_polls(pool: &PgPool) -> anyhow::Result<Vec<Poll>> {
+pub fn list_polls(pool: &PgPool, program: Option<&[u8]>) -> anyhow::Result<Vec<Poll>> {
     // Get a connection from the pool.
     let mut conn = pool.get()?;
 
     // Load all rows from the `polls` table.
-    let results = polls.load::<Poll>(&mut conn)?;
+    let mut query = polls.order((poll_id, program_id)).into_boxed();
+    if let Some(program) = program {
+        query = query.filter(program_id.eq(program));
+    }
+    let results = query.load::<Poll>(&mut conn)?;
+    Ok(results)
+}
+
+/// Fetches the polls no account update was written to since `since`, optionally only those
+/// of one program. Used by `cli list-polls --stale` to find polls the listener lost track of.
+pub fn list_polls_not_updated_since(
+    pool: &PgPool,
+    program: Option<&[u8]>,
+    since: DateTime<Utc>,
+) -> anyhow::Result<Vec<Poll>> {
+    let mut conn = pool.get()?;
+
+    let mut query = polls
+        .filter(last_updated_at.lt(since))
+        .order((poll_id, program_id))
+        .into_boxed();
+    if let Some(program) = program {
+        query = query.filter(program_id.eq(program));
+    }
+    let results = query.load::<Poll>(&mut conn)?;
     Ok(results)
 }
+
+/// Fetches one page of polls ordered by `poll_id`, optionally only those of one program.
+///
+/// Used by the HTTP API, where loading the whole table at once isn't an option.
+pub fn list_polls_page(
+    pool: &PgPool,
+    program: Option<&[u8]>,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<Poll>> {
+    // Get a connection from the pool.
+    let mut conn = pool.get()?;
+
+    let mut query = polls.order((poll_id, program_id)).into_boxed();
+    if let Some(program) = program {
+        query = query.filter(program_id.eq(program));
+    }
+    let results = query.limit(limit).offset(offset).load::<Poll>(&mut conn)?;
+    Ok(results)
+}
+
+/// Fetches the polls with the given on-chain `poll_id`, optionally only the one of `program`.
+///
+/// Without a program this can return several polls (one per indexed program);
+/// an empty result means no such poll has been indexed yet.
+pub fn get_polls_by_id(
+    pool: &PgPool,
+    id_to_find: i64,
+    program: Option<&[u8]>,
+) -> anyhow::Result<Vec<Poll>> {
+    // Get a connection from the pool.
+    let mut conn = pool.get()?;
+
+    let mut query = polls
+        .filter(poll_id.eq(id_to_find))
+        .order(program_id)
+        .into_boxed();
+    if let Some(program) = program {
+        query = query.filter(program_id.eq(program));
+    }
+    let results = query.load::<Poll>(&mut conn)?;
+    Ok(results)
+}
+
+/// The polls with the given keys, by key. Keys that aren't indexed are left out.
+pub fn get_polls_by_keys(pool: &PgPool, keys: &[PollKey]) -> Result<HashMap<PollKey, Poll>> {
+    if keys.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    // Narrow down by poll id in SQL, then match the exact (program, poll) pairs here.
+    let ids: BTreeSet<i64> = keys.iter().map(|(_, key_poll_id)| *key_poll_id).collect();
+    let rows = polls
+        .filter(poll_id.eq_any(ids))
+        .load::<Poll>(&mut conn)
+        .context("Failed to load polls")?;
+    Ok(rows
+        .into_iter()
+        .map(|poll| ((poll.program_id.clone(), poll.poll_id), poll))
+        .filter(|(key, _)| keys.contains(key))
+        .collect())
+}
+
+/// Attributes rows indexed before program IDs were tracked (empty `program_id`) to `program`.
+///
+/// Only safe when the database has only ever indexed that one program, so the listener calls
+/// it only when started with a single program ID. Returns the number of polls claimed.
+pub fn claim_unattributed_polls(pool: &PgPool, program: &[u8]) -> anyhow::Result<usize> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let claimed = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        // The program is covered by the checksum: clear it so it's recomputed by the backfill.
+        let claimed = diesel::update(polls.filter(program_id.eq(&[] as &[u8])))
+            .set((program_id.eq(program), checksum.eq(None::<i64>)))
+            .execute(conn)?;
+        diesel::update(anomalies::table.filter(anomalies::program_id.eq(&[] as &[u8])))
+            .set(anomalies::program_id.eq(program))
+            .execute(conn)?;
+        // Annotations follow their poll.
+        diesel::update(annotations::table.filter(annotations::program_id.eq(&[] as &[u8])))
+            .set(annotations::program_id.eq(program))
+            .execute(conn)?;
+        Ok(claimed)
+    })?;
+    Ok(claimed)
+}
+
+/// Number of polls indexed before program IDs were tracked and not yet claimed.
+pub fn count_unattributed_polls(pool: &PgPool) -> anyhow::Result<i64> {
+    let mut conn = pool.get()?;
+    let count = polls
+        .filter(program_id.eq(&[] as &[u8]))
+        .count()
+        .get_result::<i64>(&mut conn)?;
+    Ok(count)
+}
+
+/// Attaches an operator annotation to a poll and returns the stored row.
+pub fn add_annotation(pool: &PgPool, annotation: &NewAnnotation) -> anyhow::Result<Annotation> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let stored = diesel::insert_into(annotations::table)
+        .values(annotation)
+        .get_result::<Annotation>(&mut conn)
+        .context("Failed to add annotation")?;
+    Ok(stored)
+}
+
+/// Lists annotations, oldest first: those of one poll (`(program_id, poll_id)`) or of every
+/// poll. Resolved annotations are only included with `include_resolved`.
+pub fn list_annotations(
+    pool: &PgPool,
+    poll: Option<(&[u8], i64)>,
+    include_resolved: bool,
+) -> anyhow::Result<Vec<Annotation>> {
+    let mut conn = pool.get()?;
+
+    let mut query = annotations::table
+        .order((annotations::created_at, annotations::id))
+        .into_boxed();
+    if let Some((program, annotated_poll)) = poll {
+        query = query
+            .filter(annotations::program_id.eq(program))
+            .filter(annotations::poll_id.eq(annotated_poll));
+    }
+    if !include_resolved {
+        query = query.filter(annotations::resolved_at.is_null());
+    }
+    let results = query.load::<Annotation>(&mut conn)?;
+    Ok(results)
+}
+
+/// Open annotations of the given polls, grouped by poll. Used to decorate poll responses.
+pub fn open_annotations_for(
+    pool: &PgPool,
+    keys: &[PollKey],
+) -> anyhow::Result<HashMap<PollKey, Vec<Annotation>>> {
+    let mut grouped: HashMap<PollKey, Vec<Annotation>> = HashMap::new();
+    if keys.is_empty() {
+        return Ok(grouped);
+    }
+    let mut conn = pool.get()?;
+
+    // Narrow down by poll id in SQL, then match the exact (program, poll) pairs here.
+    let poll_ids: Vec<i64> = keys.iter().map(|(_, key_poll_id)| *key_poll_id).collect();
+    let rows = annotations::table
+        .filter(annotations::poll_id.eq_any(poll_ids))
+        .filter(annotations::resolved_at.is_null())
+        .order((annotations::created_at, annotations::id))
+        .load::<Annotation>(&mut conn)?;
+    for row in rows {
+        let key = (row.program_id.clone(), row.poll_id);
+        if keys.contains(&key) {
+            grouped.entry(key).or_default().push(row);
+        }
+    }
+    Ok(grouped)
+}
+
+/// Marks an annotation as resolved. Returns `None` when no open annotation has this id.
+pub fn resolve_annotation(pool: &PgPool, annotation_id: i32) -> anyhow::Result<Option<Annotation>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let resolved = diesel::update(
+        annotations::table
+            .filter(annotations::id.eq(annotation_id))
+            .filter(annotations::resolved_at.is_null()),
+    )
+    .set(annotations::resolved_at.eq(diesel::dsl::now))
+    .get_result::<Annotation>(&mut conn)
+    .optional()
+    .context("Failed to resolve annotation")?;
+    Ok(resolved)
+}
+
+/// Stores events decoded from transaction logs. Events already stored (same signature and
+/// log index, e.g. a redelivered notification) are skipped. Returns the number inserted.
+pub fn record_events(pool: &PgPool, new_events: &[NewEvent]) -> anyhow::Result<usize> {
+    if new_events.is_empty() {
+        return Ok(0);
+    }
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let inserted = diesel::insert_into(events::table)
+        .values(new_events)
+        .on_conflict((events::signature, events::log_index))
+        .do_nothing()
+        .execute(&mut conn)
+        .context("Failed to record events")?;
+    Ok(inserted)
+}
+
+/// Inserts or updates a Delegation account, keyed by `(program_id, account_pubkey)`.
+///
+/// Like polls, an update older than the stored row (lower `last_slot`) is ignored.
+/// Returns whether the row was written.
+pub fn upsert_delegation(pool: &PgPool, delegation: &NewDelegation) -> anyhow::Result<bool> {
+    use diesel::upsert::excluded;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let upsert = diesel::insert_into(delegations::table)
+        .values(delegation)
+        .on_conflict((delegations::program_id, delegations::account_pubkey))
+        .do_update()
+        .set((
+            delegations::poll_id.eq(excluded(delegations::poll_id)),
+            delegations::delegator.eq(excluded(delegations::delegator)),
+            delegations::delegate.eq(excluded(delegations::delegate)),
+            delegations::expiry.eq(excluded(delegations::expiry)),
+            delegations::last_slot.eq(excluded(delegations::last_slot)),
+            delegations::last_updated_at.eq(diesel::dsl::now),
+            delegations::deleted_at.eq(None::<DateTime<Utc>>),
+        ));
+    let written = diesel::query_dsl::methods::FilterDsl::filter(
+        upsert,
+        delegations::last_slot.le(excluded(delegations::last_slot)),
+    )
+    .execute(&mut conn)
+    .context("Failed to upsert delegation")?;
+    Ok(written > 0)
+}
+
+/// Inserts or updates an IDL-decoded account, keyed by `(program_id, account_pubkey)`.
+///
+/// Like polls, an update older than the stored row (lower `last_slot`) is ignored.
+/// A written candidate or vote refreshes the results of its poll in the same transaction (see
+/// [`refresh_poll_results`]). Returns whether the row was written.
+pub fn upsert_idl_account(pool: &PgPool, account: &NewIdlAccount) -> anyhow::Result<bool> {
+    use diesel::upsert::excluded;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let written = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let upsert = diesel::insert_into(idl_accounts::table)
+                .values(account)
+                .on_conflict((idl_accounts::program_id, idl_accounts::account_pubkey))
+                .do_update()
+                .set((
+                    idl_accounts::account_type.eq(excluded(idl_accounts::account_type)),
+                    idl_accounts::data.eq(excluded(idl_accounts::data)),
+                    idl_accounts::last_slot.eq(excluded(idl_accounts::last_slot)),
+                    idl_accounts::last_updated_at.eq(diesel::dsl::now),
+                    idl_accounts::deleted_at.eq(None::<DateTime<Utc>>),
+                ));
+            let written = diesel::query_dsl::methods::FilterDsl::filter(
+                upsert,
+                idl_accounts::last_slot.le(excluded(idl_accounts::last_slot)),
+            )
+            .get_result::<IdlAccount>(conn)
+            .optional()?;
+            if let Some(written) = &written {
+                refresh_results_of(conn, written)?;
+            }
+            Ok(written.is_some())
+        })
+        .context("Failed to upsert IDL account")?;
+    Ok(written)
+}
+
+/// Recomputes the stored results of `poll` from its program's open candidate accounts (see
+/// [`candidates::poll_candidates`]). Must run inside a transaction.
+///
+/// Besides [`rebuild_poll_results`], this is the only place `poll_results` is written: candidate
+/// and vote upserts, closures and newly indexed polls all go through it.
+fn refresh_poll_results(conn: &mut PgConnection, poll: &Poll) -> QueryResult<()> {
+    let accounts = idl_accounts::table
+        .filter(idl_accounts::program_id.eq(poll.program_id.as_slice()))
+        .filter(idl_accounts::account_type.eq(candidates::CANDIDATE_ACCOUNT))
+        .filter(idl_accounts::deleted_at.is_null())
+        .order(idl_accounts::id)
+        .load::<IdlAccount>(conn)?;
+    let rows: Vec<NewPollResult> = candidates::poll_candidates(&accounts, poll)
+        .iter()
+        .map(|candidate| NewPollResult::from_candidate(poll, candidate))
+        .collect();
+
+    diesel::delete(
+        poll_results::table
+            .filter(poll_results::program_id.eq(poll.program_id.as_slice()))
+            .filter(poll_results::poll_id.eq(poll.poll_id)),
+    )
+    .execute(conn)?;
+    if !rows.is_empty() {
+        diesel::insert_into(poll_results::table)
+            .values(&rows)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Refreshes the results of every poll `account` counts towards (see
+/// [`candidates::counts_towards`]). Must run inside a transaction. Accounts other than
+/// candidates and votes count towards none.
+fn refresh_results_of(conn: &mut PgConnection, account: &IdlAccount) -> QueryResult<()> {
+    if account.account_type != candidates::CANDIDATE_ACCOUNT
+        && account.account_type != candidates::VOTE_ACCOUNT
+    {
+        return Ok(());
+    }
+    let mut query = polls
+        .filter(program_id.eq(account.program_id.as_slice()))
+        .into_boxed();
+    // Most accounts name their poll; the others are matched against every poll of the program.
+    if let Some(id_of_poll) = candidates::named_poll_id(account) {
+        query = query.filter(poll_id.eq(id_of_poll));
+    }
+    for poll in query.load::<Poll>(conn)? {
+        if candidates::counts_towards(account, &poll) {
+            refresh_poll_results(conn, &poll)?;
+        }
+    }
+    Ok(())
+}
+
+/// Recomputes every poll's results from scratch (see [`refresh_poll_results`]). The listener
+/// runs it at startup, so polls indexed before `poll_results` existed, or by a binary that
+/// didn't maintain it, get theirs. Returns the number of rows written.
+pub fn rebuild_poll_results(pool: &PgPool) -> anyhow::Result<usize> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let all_polls = polls.load::<Poll>(conn)?;
+        let mut accounts_by_program: HashMap<Vec<u8>, Vec<IdlAccount>> = HashMap::new();
+        for account in idl_accounts::table
+            .filter(idl_accounts::account_type.eq(candidates::CANDIDATE_ACCOUNT))
+            .filter(idl_accounts::deleted_at.is_null())
+            .order(idl_accounts::id)
+            .load::<IdlAccount>(conn)?
+        {
+            accounts_by_program
+                .entry(account.program_id.clone())
+                .or_default()
+                .push(account);
+        }
+        let mut rows = Vec::new();
+        for poll in &all_polls {
+            let Some(accounts) = accounts_by_program.get(&poll.program_id) else {
+                continue;
+            };
+            rows.extend(
+                candidates::poll_candidates(accounts, poll)
+                    .iter()
+                    .map(|candidate| NewPollResult::from_candidate(poll, candidate)),
+            );
+        }
+
+        diesel::delete(poll_results::table).execute(conn)?;
+        // Postgres takes at most 65535 bind parameters per statement, 6 per row here.
+        for chunk in rows.chunks(5000) {
+            diesel::insert_into(poll_results::table)
+                .values(chunk)
+                .execute(conn)?;
+        }
+        Ok(rows.len())
+    })
+    .context("Failed to rebuild poll results")
+}
+
+/// The stored results of a poll: the votes of each of its indexed candidates, most votes first
+/// (then by name). Kept current by every candidate and vote upsert.
+pub fn get_results(pool: &PgPool, id_of_poll: i64, program: &[u8]) -> Result<Vec<PollResult>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let rows = poll_results::table
+        .filter(poll_results::program_id.eq(program))
+        .filter(poll_results::poll_id.eq(id_of_poll))
+        .order((poll_results::votes.desc(), poll_results::candidate_name))
+        .load::<PollResult>(&mut conn)
+        .context("Failed to load poll results")?;
+    Ok(rows)
+}
+
+/// The rows [`mark_account_closed`] marked deleted.
+#[derive(Debug, Default)]
+pub struct ClosedRows {
+    /// The closed polls, each with its move to `closed`.
+    pub polls: Vec<(PollKey, Transition)>,
+    pub delegations: usize,
+    pub idl_accounts: usize,
+}
+
+impl ClosedRows {
+    pub fn is_empty(&self) -> bool {
+        self.polls.is_empty() && self.delegations == 0 && self.idl_accounts == 0
+    }
+}
+
+/// Marks the rows of an account closed at `slot` (an update with zero lamports or no data)
+/// deleted, instead of leaving its last state looking current. Polls also move to `closed`
+/// (the transition is evaluated at unix time `now`).
+///
+/// The rows stay, with `deleted_at` set, until the account is created again: the upserts clear
+/// it. Like upserts, a closure older than the stored row (lower `last_slot`) is ignored, and
+/// rows already marked keep their first `deleted_at`. The account's type isn't known (there's
+/// no data left to tell), so every table is looked at; most accounts match none.
+pub fn mark_account_closed(
+    pool: &PgPool,
+    program: &[u8],
+    account: &[u8],
+    slot: i64,
+    now: i64,
+) -> Result<ClosedRows> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let closed = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let closed_polls = diesel::update(
+            polls
+                .filter(program_id.eq(program))
+                .filter(account_pubkey.eq(account))
+                .filter(last_slot.le(slot))
+                .filter(deleted_at.is_null()),
+        )
+        .set((last_slot.eq(slot), deleted_at.eq(diesel::dsl::now)))
+        .get_results::<Poll>(conn)?;
+        let mut closed = ClosedRows::default();
+        for row in closed_polls {
+            // With `deleted_at` set, the row's facts say it's closed, whatever the time.
+            let transition = apply_lifecycle_transition(
+                conn,
+                program,
+                row.poll_id,
+                Some(&row.lifecycle),
+                &row.lifecycle_facts(),
+                now,
+                0,
+            )?;
+            closed
+                .polls
+                .push(((program.to_vec(), row.poll_id), transition));
+        }
+
+        closed.delegations = diesel::update(
+            delegations::table
+                .filter(delegations::program_id.eq(program))
+                .filter(delegations::account_pubkey.eq(account))
+                .filter(delegations::last_slot.le(slot))
+                .filter(delegations::deleted_at.is_null()),
+        )
+        .set((
+            delegations::last_slot.eq(slot),
+            delegations::deleted_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+
+        let closed_accounts = diesel::update(
+            idl_accounts::table
+                .filter(idl_accounts::program_id.eq(program))
+                .filter(idl_accounts::account_pubkey.eq(account))
+                .filter(idl_accounts::last_slot.le(slot))
+                .filter(idl_accounts::deleted_at.is_null()),
+        )
+        .set((
+            idl_accounts::last_slot.eq(slot),
+            idl_accounts::deleted_at.eq(diesel::dsl::now),
+        ))
+        .get_results::<IdlAccount>(conn)?;
+        // A closed candidate no longer counts towards its poll's results.
+        for closed_account in &closed_accounts {
+            refresh_results_of(conn, closed_account)?;
+        }
+        closed.idl_accounts = closed_accounts.len();
+        Ok(closed)
+    })?;
+    Ok(closed)
+}
+
+/// Archives a raw account update. Returns false when the account was already archived at
+/// that slot.
+pub fn archive_raw_account(pool: &PgPool, raw: &NewRawAccount) -> Result<bool> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let inserted = diesel::insert_into(account_raw_history::table)
+        .values(raw)
+        .on_conflict_do_nothing()
+        .execute(&mut conn)
+        .context("Failed to archive raw account")?;
+    Ok(inserted > 0)
+}
+
+/// Up to `limit` archived updates after the one with id `after`, in the order the listener
+/// processed them: of one program or of all, within a slot range (both ends included).
+pub fn list_raw_accounts(
+    pool: &PgPool,
+    program: Option<&[u8]>,
+    slots: (Option<i64>, Option<i64>),
+    after: i64,
+    limit: i64,
+) -> Result<Vec<RawAccount>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let mut query = account_raw_history::table
+        .filter(account_raw_history::id.gt(after))
+        .into_boxed();
+    if let Some(program) = program {
+        query = query.filter(account_raw_history::program_id.eq(program));
+    }
+    if let Some(from) = slots.0 {
+        query = query.filter(account_raw_history::slot.ge(from));
+    }
+    if let Some(to) = slots.1 {
+        query = query.filter(account_raw_history::slot.le(to));
+    }
+    query
+        .order(account_raw_history::id)
+        .limit(limit)
+        .load::<RawAccount>(&mut conn)
+        .context("Failed to load archived accounts")
+}
+
+/// Records an account that couldn't be decoded, replacing the account's previous failure
+/// unless that one is newer (higher `slot`).
+pub fn record_decode_failure(pool: &PgPool, failure: &NewDecodeFailure) -> Result<()> {
+    use diesel::upsert::excluded;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let upsert = diesel::insert_into(decode_failures::table)
+        .values(failure)
+        .on_conflict(decode_failures::account_pubkey)
+        .do_update()
+        .set((
+            decode_failures::program_id.eq(excluded(decode_failures::program_id)),
+            decode_failures::slot.eq(excluded(decode_failures::slot)),
+            decode_failures::discriminator.eq(excluded(decode_failures::discriminator)),
+            decode_failures::account_type.eq(excluded(decode_failures::account_type)),
+            decode_failures::error.eq(excluded(decode_failures::error)),
+            decode_failures::data.eq(excluded(decode_failures::data)),
+            decode_failures::last_failed_at.eq(diesel::dsl::now),
+        ));
+    diesel::query_dsl::methods::FilterDsl::filter(
+        upsert,
+        decode_failures::slot.le(excluded(decode_failures::slot)),
+    )
+    .execute(&mut conn)
+    .context("Failed to record decode failure")?;
+    Ok(())
+}
+
+/// The recorded decode failures, of one program or of all, most recent first.
+pub fn list_decode_failures(pool: &PgPool, program: Option<&[u8]>) -> Result<Vec<DecodeFailure>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let mut query = decode_failures::table.into_boxed();
+    if let Some(program) = program {
+        query = query.filter(decode_failures::program_id.eq(program));
+    }
+    query
+        .order(decode_failures::last_failed_at.desc())
+        .load::<DecodeFailure>(&mut conn)
+        .context("Failed to load decode failures")
+}
+
+/// Removes the decode failure of an account, unless a newer one (another slot) replaced it in
+/// the meantime. Returns whether it was removed.
+pub fn delete_decode_failure(pool: &PgPool, account: &[u8], slot: i64) -> Result<bool> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let deleted = diesel::delete(
+        decode_failures::table
+            .find(account)
+            .filter(decode_failures::slot.eq(slot)),
+    )
+    .execute(&mut conn)
+    .context("Failed to delete decode failure")?;
+    Ok(deleted > 0)
+}
+
+/// The checkpoint of a program: the last slot up to which every account update was stored.
+pub fn get_checkpoint(pool: &PgPool, program: &[u8]) -> Result<Option<i64>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    listener_checkpoints::table
+        .find(program)
+        .select(listener_checkpoints::last_slot)
+        .first::<i64>(&mut conn)
+        .optional()
+        .context("Failed to load listener checkpoint")
+}
+
+/// Moves the checkpoint of a program to `slot`; a checkpoint never goes back.
+pub fn save_checkpoint(pool: &PgPool, program: &[u8], slot: i64) -> Result<()> {
+    use diesel::upsert::excluded;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let upsert = diesel::insert_into(listener_checkpoints::table)
+        .values((
+            listener_checkpoints::program_id.eq(program),
+            listener_checkpoints::last_slot.eq(slot),
+        ))
+        .on_conflict(listener_checkpoints::program_id)
+        .do_update()
+        .set((
+            listener_checkpoints::last_slot.eq(excluded(listener_checkpoints::last_slot)),
+            listener_checkpoints::updated_at.eq(diesel::dsl::now),
+        ));
+    diesel::query_dsl::methods::FilterDsl::filter(
+        upsert,
+        listener_checkpoints::last_slot.le(excluded(listener_checkpoints::last_slot)),
+    )
+    .execute(&mut conn)
+    .context("Failed to save listener checkpoint")?;
+    Ok(())
+}
+
+/// The IDL-decoded accounts of one type (its IDL name, e.g. `Candidate`) of a program, in the
+/// order they were first seen. Closed accounts are left out.
+pub fn list_idl_accounts(
+    pool: &PgPool,
+    program: &[u8],
+    account_type: &str,
+) -> anyhow::Result<Vec<IdlAccount>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let rows = idl_accounts::table
+        .filter(idl_accounts::program_id.eq(program))
+        .filter(idl_accounts::account_type.eq(account_type))
+        .filter(idl_accounts::deleted_at.is_null())
+        .order(idl_accounts::id)
+        .load::<IdlAccount>(&mut conn)
+        .context("Failed to load IDL accounts")?;
+    Ok(rows)
+}
+
+/// Delegations of a poll (of one program, or of every program when `None`), expired ones
+/// included but closed ones left out, ordered by delegator.
+pub fn list_delegations(
+    pool: &PgPool,
+    program: Option<&[u8]>,
+    poll: i64,
+) -> anyhow::Result<Vec<Delegation>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let mut query = delegations::table
+        .filter(delegations::poll_id.eq(poll))
+        .filter(delegations::deleted_at.is_null())
+        .order((delegations::delegator, delegations::id))
+        .into_boxed();
+    if let Some(program) = program {
+        query = query.filter(delegations::program_id.eq(program));
+    }
+    let rows = query
+        .load::<Delegation>(&mut conn)
+        .context("Failed to load delegations")?;
+    Ok(rows)
+}
+
+/// Relative weights of the two components of a search score.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchWeights {
+    /// Weight of the full-text rank (`ts_rank`, names counting more than descriptions).
+    pub text: f64,
+    /// Weight of recency: 1 for a poll updated just now, 1/2 a day later, 1/(1 + days) after.
+    pub recency: f64,
+}
+
+impl Default for SearchWeights {
+    fn default() -> Self {
+        Self {
+            text: 1.0,
+            recency: 0.1,
+        }
+    }
+}
+
+/// A poll matching a search, with its score.
+#[derive(Debug)]
+pub struct PollSearchHit {
+    pub poll: Poll,
+    pub score: f64,
+}
+
+#[derive(QueryableByName)]
+struct SearchRow {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    poll_row_id: i32,
+    #[diesel(sql_type = diesel::sql_types::Double)]
+    score: f64,
+}
+
+/// Full-text search over the names and descriptions of polls, of one program or of all.
+///
+/// `query` uses web search syntax (`"exact phrase"`, `or`, `-excluded`). Hits are ranked by
+/// score (see [`SearchWeights`]), with recency measured at `now`; ties are broken by program and
+/// poll id, so the order is deterministic.
+pub fn search_polls(
+    pool: &PgPool,
+    query: &str,
+    program: Option<&[u8]>,
+    limit: i64,
+    weights: SearchWeights,
+    now: DateTime<Utc>,
+) -> Result<Vec<PollSearchHit>> {
+    use diesel::sql_types::{BigInt, Bytea, Double, Nullable, Text, Timestamptz};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    // The document expression must match `polls_search_idx` exactly.
+    let rows = diesel::sql_query(
+        "SELECT p.id AS poll_row_id, \
+             $3 * ts_rank(document, query)::float8 \
+             + $4 / (1.0 + GREATEST(EXTRACT(EPOCH FROM ($5 - p.last_updated_at)), 0) / 86400.0) \
+             AS score \
+         FROM polls p, \
+             LATERAL (SELECT setweight(to_tsvector('english', p.poll_name), 'A') \
+                 || setweight(to_tsvector('english', p.poll_description), 'B') AS document) d, \
+             websearch_to_tsquery('english', $1) query \
+         WHERE document @@ query AND ($2::bytea IS NULL OR p.program_id = $2) \
+         ORDER BY score DESC, p.program_id, p.poll_id \
+         LIMIT $6",
+    )
+    .bind::<Text, _>(query)
+    .bind::<Nullable<Bytea>, _>(program)
+    .bind::<Double, _>(weights.text)
+    .bind::<Double, _>(weights.recency)
+    .bind::<Timestamptz, _>(now)
+    .bind::<BigInt, _>(limit)
+    .load::<SearchRow>(&mut conn)
+    .context("Failed to search polls")?;
+
+    let ids: Vec<i32> = rows.iter().map(|row| row.poll_row_id).collect();
+    let mut found: HashMap<i32, Poll> = polls
+        .filter(id.eq_any(&ids))
+        .load::<Poll>(&mut conn)
+        .context("Failed to load matching polls")?
+        .into_iter()
+        .map(|poll| (poll.id, poll))
+        .collect();
+    // A poll deleted between the two queries is simply left out.
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            found.remove(&row.poll_row_id).map(|poll| PollSearchHit {
+                poll,
+                score: row.score,
+            })
+        })
+        .collect())
+}
+
+/// Records a change of the effective configuration.
+pub fn record_config_change(
+    pool: &PgPool,
+    change: &NewConfigChange,
+) -> anyhow::Result<ConfigChange> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let stored = diesel::insert_into(config_changes::table)
+        .values(change)
+        .get_result::<ConfigChange>(&mut conn)
+        .context("Failed to record config change")?;
+    Ok(stored)
+}
+
+/// Config changes in the order they were recorded, optionally only those up to `until`.
+pub fn list_config_changes(
+    pool: &PgPool,
+    until: Option<DateTime<Utc>>,
+) -> anyhow::Result<Vec<ConfigChange>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let mut query = config_changes::table.order(config_changes::id).into_boxed();
+    if let Some(until) = until {
+        query = query.filter(config_changes::changed_at.le(until));
+    }
+    let changes = query
+        .load::<ConfigChange>(&mut conn)
+        .context("Failed to load config changes")?;
+    Ok(changes)
+}
+
+/// Stores a new version of a program's IDL.
+pub fn insert_idl(pool: &PgPool, idl: &NewIdl) -> Result<Idl> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::insert_into(idls::table)
+        .values(idl)
+        .get_result::<Idl>(&mut conn)
+        .context("Failed to store IDL")
+}
+
+/// The IDL versions of a program, oldest first.
+pub fn list_idls(pool: &PgPool, program: &[u8]) -> Result<Vec<Idl>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    idls::table
+        .filter(idls::program_id.eq(program))
+        .order(idls::id)
+        .load::<Idl>(&mut conn)
+        .context("Failed to load IDLs")
+}
+
+/// The latest IDL version of a program, if any was loaded.
+pub fn latest_idl(pool: &PgPool, program: &[u8]) -> Result<Option<Idl>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    idls::table
+        .filter(idls::program_id.eq(program))
+        .order(idls::id.desc())
+        .first::<Idl>(&mut conn)
+        .optional()
+        .context("Failed to load IDL")
+}
+
+/// The latest lifecycle transitions, newest first: of one poll, of one program, or of all.
+///
+/// Only first indexings (no `from_state`) and moves to one of `to_states` are returned.
+pub fn recent_transitions(
+    pool: &PgPool,
+    program: Option<&[u8]>,
+    poll: Option<i64>,
+    to_states: &[&str],
+    limit: i64,
+) -> Result<Vec<LifecycleTransition>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let mut query = lifecycle_transitions::table
+        .filter(
+            lifecycle_transitions::from_state
+                .is_null()
+                .or(lifecycle_transitions::to_state.eq_any(to_states)),
+        )
+        .order(lifecycle_transitions::id.desc())
+        .limit(limit)
+        .into_boxed();
+    if let Some(program) = program {
+        query = query.filter(lifecycle_transitions::program_id.eq(program));
+    }
+    if let Some(poll) = poll {
+        query = query.filter(lifecycle_transitions::poll_id.eq(poll));
+    }
+    query
+        .load::<LifecycleTransition>(&mut conn)
+        .context("Failed to load lifecycle transitions")
+}
+
+/// Quarantines an account, or updates its quarantine (new failure count, error and data), and
+/// records an `account_quarantined` anomaly for its poll in the same transaction.
+pub fn quarantine_account(pool: &PgPool, account: &QuarantinedAccount) -> Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::insert_into(quarantined_accounts::table)
+            .values(account)
+            .on_conflict(quarantined_accounts::account_pubkey)
+            .do_update()
+            .set((
+                quarantined_accounts::failures.eq(account.failures),
+                quarantined_accounts::last_error.eq(&account.last_error),
+                quarantined_accounts::data_checksum.eq(account.data_checksum),
+                quarantined_accounts::quarantined_at.eq(account.quarantined_at),
+            ))
+            .execute(conn)?;
+        diesel::insert_into(anomalies::table)
+            .values(&NewAnomaly {
+                program_id: account.program_id.clone(),
+                poll_id: account.poll_id,
+                kind: "account_quarantined".to_string(),
+                details: format!(
+                    "writes stopped after {} failure(s): {}",
+                    account.failures, account.last_error
+                ),
+            })
+            .execute(conn)?;
+        Ok(())
+    })
+    .context("Failed to quarantine account")
+}
+
+/// Every quarantined account, oldest quarantine first.
+pub fn list_quarantined(pool: &PgPool) -> Result<Vec<QuarantinedAccount>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    quarantined_accounts::table
+        .order(quarantined_accounts::quarantined_at)
+        .load::<QuarantinedAccount>(&mut conn)
+        .context("Failed to load quarantined accounts")
+}
+
+/// Lifts an account's quarantine. Returns `None` when it wasn't quarantined.
+pub fn release_quarantine(pool: &PgPool, account: &[u8]) -> Result<Option<QuarantinedAccount>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::delete(quarantined_accounts::table.find(account))
+        .get_result::<QuarantinedAccount>(&mut conn)
+        .optional()
+        .context("Failed to release quarantined account")
+}
+
+/// Registers a job at startup, or updates its class and schedule if it already has a row.
+/// Its pause flag, last run and pending run request are kept.
+pub fn register_job(
+    pool: &PgPool,
+    name: &str,
+    class: &str,
+    schedule: &str,
+    next_run_at: DateTime<Utc>,
+) -> Result<JobRow> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::insert_into(jobs::table)
+        .values((
+            jobs::name.eq(name),
+            jobs::class.eq(class),
+            jobs::schedule.eq(schedule),
+            jobs::next_run_at.eq(next_run_at),
+        ))
+        .on_conflict(jobs::name)
+        .do_update()
+        .set((
+            jobs::class.eq(class),
+            jobs::schedule.eq(schedule),
+            jobs::next_run_at.eq(next_run_at),
+        ))
+        .get_result::<JobRow>(&mut conn)
+        .context("Failed to register job")
+}
+
+/// Every job, by name.
+pub fn list_jobs(pool: &PgPool) -> Result<Vec<JobRow>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    jobs::table
+        .order(jobs::name)
+        .load::<JobRow>(&mut conn)
+        .context("Failed to load jobs")
+}
+
+/// Records the start of a run. A run request older than `started_at` is considered served.
+pub fn record_job_start(pool: &PgPool, name: &str, started_at: DateTime<Utc>) -> Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(jobs::table.find(name))
+            .set(jobs::last_started_at.eq(started_at))
+            .execute(conn)?;
+        diesel::update(
+            jobs::table
+                .find(name)
+                .filter(jobs::run_requested_at.le(started_at)),
+        )
+        .set(jobs::run_requested_at.eq(None::<DateTime<Utc>>))
+        .execute(conn)?;
+        Ok(())
+    })
+    .context("Failed to record job start")
+}
+
+/// Records the end of a run (`error` is `None` when it succeeded) and when the next one is due.
+pub fn record_job_finish(
+    pool: &PgPool,
+    name: &str,
+    finished_at: DateTime<Utc>,
+    error: Option<&str>,
+    next_run_at: DateTime<Utc>,
+) -> Result<()> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::update(jobs::table.find(name))
+        .set((
+            jobs::last_finished_at.eq(finished_at),
+            jobs::last_error.eq(error),
+            jobs::next_run_at.eq(next_run_at),
+        ))
+        .execute(&mut conn)
+        .context("Failed to record job finish")?;
+    Ok(())
+}
+
+/// Pauses or resumes a job. Returns `None` when there is no such job.
+pub fn set_job_paused(pool: &PgPool, name: &str, paused: bool) -> Result<Option<JobRow>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::update(jobs::table.find(name))
+        .set(jobs::paused.eq(paused))
+        .get_result::<JobRow>(&mut conn)
+        .optional()
+        .context("Failed to update job")
+}
+
+/// Asks the listener to run a job now, even if it's paused. Returns `None` when there is no
+/// such job.
+pub fn request_job_run(pool: &PgPool, name: &str) -> Result<Option<JobRow>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    diesel::update(jobs::table.find(name))
+        .set(jobs::run_requested_at.eq(Utc::now()))
+        .get_result::<JobRow>(&mut conn)
+        .optional()
+        .context("Failed to request job run")
+}
+
+/// A poll whose stored checksum doesn't match its current fields.
+#[derive(Debug, Clone)]
+pub struct ChecksumMismatch {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub stored: i64,
+    pub recomputed: i64,
+}
+
+/// Result of [`verify_checksums`].
+#[derive(Debug, Default)]
+pub struct ChecksumReport {
+    /// Rows whose checksum was compared.
+    pub checked: usize,
+    /// Rows without a checksum yet (not backfilled), skipped.
+    pub missing: usize,
+    pub mismatches: Vec<ChecksumMismatch>,
+}
+
+/// Computes the checksum of rows that don't have one yet (indexed before the column existed,
+/// or whose program was just claimed). Returns the number of rows updated.
+///
+/// Rows are read and updated under lock in one transaction, so a concurrent writer can't slip a
+/// new version in between.
+pub fn backfill_checksums(pool: &PgPool) -> anyhow::Result<usize> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let updated = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let rows = polls
+            .filter(checksum.is_null())
+            .order(id)
+            .for_update()
+            .load::<Poll>(conn)?;
+        for row in &rows {
+            diesel::update(polls.filter(id.eq(row.id)))
+                .set(checksum.eq(row.computed_checksum()))
+                .execute(conn)?;
+        }
+        Ok(rows.len())
+    })?;
+    Ok(updated)
+}
+
+/// Recomputes every poll's checksum (optionally only one program's) and compares it with the
+/// stored one. Nothing is written; see [`record_checksum_mismatches`].
+pub fn verify_checksums(pool: &PgPool, program: Option<&[u8]>) -> anyhow::Result<ChecksumReport> {
+    let mut report = ChecksumReport::default();
+    for poll in list_polls(pool, program)? {
+        match check_poll_checksum(&poll) {
+            None if poll.checksum.is_none() => report.missing += 1,
+            None => report.checked += 1,
+            Some(mismatch) => {
+                report.checked += 1;
+                report.mismatches.push(mismatch);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Compares a loaded poll's stored checksum with its fields.
+/// `None` when they match, or when the row has no checksum yet.
+pub fn check_poll_checksum(poll: &Poll) -> Option<ChecksumMismatch> {
+    let stored = poll.checksum?;
+    let recomputed = poll.computed_checksum();
+    (stored != recomputed).then(|| ChecksumMismatch {
+        program_id: poll.program_id.clone(),
+        poll_id: poll.poll_id,
+        stored,
+        recomputed,
+    })
+}
+
+/// Counts the anomalies recorded for one poll (`(program_id, poll_id)`), by kind.
+pub fn anomaly_counts(pool: &PgPool, poll: (&[u8], i64)) -> Result<BTreeMap<String, i64>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    let (program, anomalous_poll) = poll;
+    let counts = anomalies::table
+        .filter(anomalies::program_id.eq(program))
+        .filter(anomalies::poll_id.eq(anomalous_poll))
+        .group_by(anomalies::kind)
+        .select((anomalies::kind, diesel::dsl::count_star()))
+        .load::<(String, i64)>(&mut conn)?;
+    Ok(counts.into_iter().collect())
+}
+
+/// Records checksum mismatches in the `anomalies` table, with the stored and recomputed values.
+pub fn record_checksum_mismatches(
+    pool: &PgPool,
+    mismatches: &[ChecksumMismatch],
+) -> anyhow::Result<()> {
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let rows: Vec<NewAnomaly> = mismatches
+        .iter()
+        .map(|mismatch| NewAnomaly {
+            program_id: mismatch.program_id.clone(),
+            poll_id: mismatch.poll_id,
+            kind: "checksum_mismatch".to_string(),
+            details: format!(
+                "stored checksum {} does not match recomputed {}",
+                mismatch.stored, mismatch.recomputed
+            ),
+        })
+        .collect();
+    diesel::insert_into(anomalies::table)
+        .values(&rows)
+        .execute(&mut conn)
+        .context("Failed to record checksum mismatches")?;
+    Ok(())
+}
+
+/// Adds byte counters to the `bandwidth_usage` rows of `day`.
+///
+/// `deltas` are `(endpoint, feature, bytes)` as drained from a `BandwidthMeter`.
+/// Existing rows are incremented rather than overwritten, so several flushes
+/// (or several listener processes) can report into the same day.
+pub fn record_bandwidth(
+    pool: &PgPool,
+    on_day: NaiveDate,
+    deltas: &[(String, String, u64)],
+) -> anyhow::Result<()> {
+    use bandwidth_usage::dsl as bw;
+    use diesel::upsert::excluded;
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+
+    let rows: Vec<BandwidthUsage> = deltas
+        .iter()
+        .map(|(endpoint, feature, bytes)| BandwidthUsage {
+            day: on_day,
+            endpoint: endpoint.clone(),
+            feature: feature.clone(),
+            bytes: *bytes as i64,
+        })
+        .collect();
+
+    diesel::insert_into(bw::bandwidth_usage)
+        .values(&rows)
+        .on_conflict((bw::day, bw::endpoint, bw::feature))
+        .do_update()
+        .set(bw::bytes.eq(bw::bytes + excluded(bw::bytes)))
+        .execute(&mut conn)
+        .context("Failed to record bandwidth usage")?;
+
+    Ok(())
+}
+
+/// Fetches the daily bandwidth rows from `since` (inclusive) onwards, oldest first.
+pub fn bandwidth_since(pool: &PgPool, since: NaiveDate) -> anyhow::Result<Vec<BandwidthUsage>> {
+    use bandwidth_usage::dsl as bw;
+
+    let mut conn = pool.get()?;
+    let rows = bw::bandwidth_usage
+        .filter(bw::day.ge(since))
+        .order((bw::day, bw::endpoint, bw::feature))
+        .load::<BandwidthUsage>(&mut conn)?;
+    Ok(rows)
+}
+
+/// Total bytes received (all endpoints and features) from `since` onwards.
+pub fn bandwidth_total_since(pool: &PgPool, since: NaiveDate) -> anyhow::Result<u64> {
+    let rows = bandwidth_since(pool, since)?;
+    Ok(rows.iter().map(|row| row.bytes.max(0) as u64).sum())
+}
+
+#[derive(QueryableByName)]
+struct ProgramRowCount {
+    #[diesel(sql_type = diesel::sql_types::Bytea)]
+    program: Vec<u8>,
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    rows: i64,
+}
+
+#[derive(QueryableByName)]
+struct RelationSize {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    bytes: i64,
+}
+
+/// Rows of `table` per `program_id`, for the storage quotas. `table` is interpolated into the
+/// query, so it must be one of our own table names, never user input.
+pub fn count_rows_by_program(pool: &PgPool, table: &str) -> Result<Vec<(Vec<u8>, i64)>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    let rows = diesel::sql_query(format!(
+        "SELECT program_id AS program, count(*) AS rows FROM {} GROUP BY program_id",
+        table
+    ))
+    .load::<ProgramRowCount>(&mut conn)
+    .with_context(|| format!("Failed to count the rows of {}", table))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.program, row.rows))
+        .collect())
+}
+
+/// On-disk size of `table` with its indexes and TOAST data (`pg_total_relation_size`).
+pub fn table_size_bytes(pool: &PgPool, table: &str) -> Result<i64> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    let size = diesel::sql_query("SELECT pg_total_relation_size($1::regclass) AS bytes")
+        .bind::<diesel::sql_types::Text, _>(table)
+        .get_result::<RelationSize>(&mut conn)
+        .with_context(|| format!("Failed to read the size of {}", table))?;
+    Ok(size.bytes)
+}
+
+/// Deletes up to `limit` of a program's ended polls, those that ended first, together with
+/// their delegations, lifecycle transitions, annotations, anomalies and results. Returns how many polls
+/// were deleted.
+pub fn prune_ended_polls(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    conn.transaction(|conn| {
+        let ids: Vec<i64> = polls
+            .filter(program_id.eq(program))
+            .filter(lifecycle.eq(PollLifecycle::Ended.as_str()))
+            .order((poll_end, poll_id))
+            .limit(limit)
+            .select(poll_id)
+            .load(conn)?;
+        diesel::delete(
+            delegations::table
+                .filter(delegations::program_id.eq(program))
+                .filter(delegations::poll_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            lifecycle_transitions::table
+                .filter(lifecycle_transitions::program_id.eq(program))
+                .filter(lifecycle_transitions::poll_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            annotations::table
+                .filter(annotations::program_id.eq(program))
+                .filter(annotations::poll_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            anomalies::table
+                .filter(anomalies::program_id.eq(program))
+                .filter(anomalies::poll_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            poll_results::table
+                .filter(poll_results::program_id.eq(program))
+                .filter(poll_results::poll_id.eq_any(&ids)),
+        )
+        .execute(conn)?;
+        diesel::delete(
+            polls
+                .filter(program_id.eq(program))
+                .filter(poll_id.eq_any(&ids)),
+        )
+        .execute(conn)
+    })
+    .context("Failed to prune ended polls")
+}
+
+/// Deletes up to `limit` of a program's delegations to polls that have ended, those of the
+/// polls that ended first. Returns how many were deleted.
+pub fn prune_ended_poll_delegations(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    use diesel::sql_types::{BigInt, Bytea, Text};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query(
+        "DELETE FROM delegations WHERE id IN ( \
+             SELECT d.id FROM delegations d \
+             JOIN polls p ON p.program_id = d.program_id AND p.poll_id = d.poll_id \
+             WHERE d.program_id = $1 AND p.lifecycle = $2 \
+             ORDER BY p.poll_end, d.id \
+             LIMIT $3)",
+    )
+    .bind::<Bytea, _>(program)
+    .bind::<Text, _>(PollLifecycle::Ended.as_str())
+    .bind::<BigInt, _>(limit)
+    .execute(&mut conn)
+    .context("Failed to prune delegations of ended polls")
+}
+
+/// Deletes up to `limit` of a program's events, oldest slot first. Events aren't tied to a
+/// poll, so age is all there is to go by. Returns how many were deleted.
+pub fn prune_oldest_events(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    use diesel::sql_types::{BigInt, Bytea};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query(
+        "DELETE FROM events WHERE id IN ( \
+             SELECT id FROM events WHERE program_id = $1 ORDER BY slot, id LIMIT $2)",
+    )
+    .bind::<Bytea, _>(program)
+    .bind::<BigInt, _>(limit)
+    .execute(&mut conn)
+    .context("Failed to prune the oldest events")
+}
+
+/// Deletes up to `limit` of a program's IDL accounts: closed ones first (the earliest closed
+/// first), then those updated least recently. Returns how many were deleted.
+pub fn prune_oldest_idl_accounts(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    use diesel::sql_types::{BigInt, Bytea};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query(
+        "DELETE FROM idl_accounts WHERE id IN ( \
+             SELECT id FROM idl_accounts WHERE program_id = $1 \
+             ORDER BY deleted_at IS NULL, deleted_at, last_updated_at, id LIMIT $2)",
+    )
+    .bind::<Bytea, _>(program)
+    .bind::<BigInt, _>(limit)
+    .execute(&mut conn)
+    .context("Failed to prune the oldest IDL accounts")
+}
+
+/// Deletes up to `limit` of a program's archived raw updates, oldest slot first. Returns how
+/// many were deleted.
+pub fn prune_oldest_raw_accounts(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    use diesel::sql_types::{BigInt, Bytea};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query(
+        "DELETE FROM account_raw_history WHERE id IN ( \
+             SELECT id FROM account_raw_history WHERE program_id = $1 \
+             ORDER BY slot, id LIMIT $2)",
+    )
+    .bind::<Bytea, _>(program)
+    .bind::<BigInt, _>(limit)
+    .execute(&mut conn)
+    .context("Failed to prune the oldest archived accounts")
+}
+
+/// Deletes up to `limit` of a program's decode failures, those that last failed first.
+/// Returns how many were deleted.
+pub fn prune_oldest_decode_failures(pool: &PgPool, program: &[u8], limit: i64) -> Result<usize> {
+    use diesel::sql_types::{BigInt, Bytea};
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query(
+        "DELETE FROM decode_failures WHERE account_pubkey IN ( \
+             SELECT account_pubkey FROM decode_failures WHERE program_id = $1 \
+             ORDER BY last_failed_at, account_pubkey LIMIT $2)",
+    )
+    .bind::<Bytea, _>(program)
+    .bind::<BigInt, _>(limit)
+    .execute(&mut conn)
+    .context("Failed to prune the oldest decode failures")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::{database_url, new_poll, test_pool, NOW};
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn read_only_pool_rejects_writes() {
+        let pool = establish_pool_for(&database_url(), true).unwrap();
+        let program = [0x06; 32];
+
+        // The backstop behind the CLI's refusal: Postgres itself rejects the write.
+        let err = save_checkpoint(&pool, &program, 1).unwrap_err();
+        assert!(format!("{:?}", err).contains("read-only transaction"));
+        assert_eq!(get_checkpoint(&pool, &program).unwrap(), None);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn writable_pool_accepts_the_same_write() {
+        let pool = test_pool();
+        let program = [0x06; 32];
+
+        save_checkpoint(&pool, &program, 1).unwrap();
+        assert_eq!(get_checkpoint(&pool, &program).unwrap(), Some(1));
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn stale_updates_never_overwrite_newer_rows() {
+        let pool = test_pool();
+        let program = [0x10; 32];
+        let newer = NewPoll {
+            poll_name: "newer".into(),
+            ..new_poll(&program, 1, 100)
+        };
+        let older = NewPoll {
+            poll_name: "older".into(),
+            ..new_poll(&program, 1, 90)
+        };
+
+        assert!(matches!(
+            upsert_poll(&pool, &newer, 0, NOW).unwrap(),
+            UpsertOutcome::Written(_)
+        ));
+        assert_eq!(
+            upsert_poll(&pool, &older, 0, NOW).unwrap(),
+            UpsertOutcome::Stale {
+                incoming_slot: 90,
+                stored_slot: 100
+            }
+        );
+        let stored = get_polls_by_id(&pool, 1, Some(&program)).unwrap();
+        assert_eq!(stored[0].poll_name, "newer");
+        assert_eq!(stored[0].last_slot, 100);
+
+        // The same slot again is written: a re-delivery of the state already stored.
+        assert!(matches!(
+            upsert_poll(&pool, &newer, 0, NOW).unwrap(),
+            UpsertOutcome::Written(_)
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn batches_keep_the_newest_update_of_each_poll() {
+        let pool = test_pool();
+        let program = [0x11; 32];
+        let batch = [
+            NewPoll {
+                poll_name: "newest".into(),
+                ..new_poll(&program, 1, 120)
+            },
+            new_poll(&program, 1, 110),
+            new_poll(&program, 2, 100),
+        ];
+
+        let outcomes = upsert_polls(&pool, &batch, 0, NOW).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        let stored = get_polls_by_id(&pool, 1, Some(&program)).unwrap();
+        assert_eq!(stored[0].poll_name, "newest");
+        assert_eq!(stored[0].last_slot, 120);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn verification_reports_rows_edited_behind_the_listener() {
+        let pool = test_pool();
+        let program = [0x12; 32];
+        upsert_poll(&pool, &new_poll(&program, 1, 100), 0, NOW).unwrap();
+        upsert_poll(&pool, &new_poll(&program, 2, 100), 0, NOW).unwrap();
+
+        let report = verify_checksums(&pool, Some(&program)).unwrap();
+        assert_eq!((report.checked, report.missing), (2, 0));
+        assert!(report.mismatches.is_empty());
+
+        let mut conn = pool.get().unwrap();
+        diesel::update(
+            polls
+                .filter(program_id.eq(program.as_slice()))
+                .filter(poll_id.eq(2)),
+        )
+        .set(poll_name.eq("edited"))
+        .execute(&mut conn)
+        .unwrap();
+        drop(conn);
+
+        let report = verify_checksums(&pool, Some(&program)).unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].poll_id, 2);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn backfill_fills_only_missing_checksums() {
+        let pool = test_pool();
+        let program = [0x13; 32];
+        upsert_poll(&pool, &new_poll(&program, 1, 100), 0, NOW).unwrap();
+        upsert_poll(&pool, &new_poll(&program, 2, 100), 0, NOW).unwrap();
+
+        let mut conn = pool.get().unwrap();
+        diesel::update(
+            polls
+                .filter(program_id.eq(program.as_slice()))
+                .filter(poll_id.eq(1)),
+        )
+        .set(checksum.eq(None::<i64>))
+        .execute(&mut conn)
+        .unwrap();
+        drop(conn);
+        assert_eq!(verify_checksums(&pool, Some(&program)).unwrap().missing, 1);
+
+        assert_eq!(backfill_checksums(&pool).unwrap(), 1);
+        let report = verify_checksums(&pool, Some(&program)).unwrap();
+        assert_eq!((report.checked, report.missing), (2, 0));
+        assert!(report.mismatches.is_empty());
+    }
+
+    fn new_delegation(program: &[u8], delegator: u8, slot: i64) -> NewDelegation {
+        NewDelegation {
+            program_id: program.to_vec(),
+            account_pubkey: vec![delegator + 100; 32],
+            poll_id: 1,
+            delegator: vec![delegator; 32],
+            delegate: vec![9; 32],
+            expiry: None,
+            last_slot: slot,
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn delegations_keep_the_newest_slot_and_leave_when_closed() {
+        let pool = test_pool();
+        let program = [0x14; 32];
+        let newer = NewDelegation {
+            delegate: vec![8; 32],
+            ..new_delegation(&program, 1, 100)
+        };
+        assert!(upsert_delegation(&pool, &newer).unwrap());
+        assert!(!upsert_delegation(&pool, &new_delegation(&program, 1, 90)).unwrap());
+        assert!(upsert_delegation(&pool, &new_delegation(&program, 2, 90)).unwrap());
+
+        let stored = list_delegations(&pool, Some(&program), 1).unwrap();
+        let pairs: Vec<_> = stored
+            .iter()
+            .map(|d| (d.delegator[0], d.delegate[0], d.last_slot))
+            .collect();
+        assert_eq!(pairs, [(1, 8, 100), (2, 9, 90)]);
+
+        let closed = mark_account_closed(&pool, &program, &[101; 32], 110, NOW).unwrap();
+        assert_eq!(closed.delegations, 1);
+        let stored = list_delegations(&pool, Some(&program), 1).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].delegator, [2; 32]);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn candidate_upserts_keep_poll_results_current() {
+        let pool = test_pool();
+        let program = [0x15; 32];
+        let candidate = |name: &str, votes: u64, slot: i64| NewIdlAccount {
+            program_id: program.to_vec(),
+            account_pubkey: vec![name.as_bytes()[0]; 32],
+            account_type: candidates::CANDIDATE_ACCOUNT.to_string(),
+            data: serde_json::json!({ "poll_id": 1, "name": name, "votes": votes }),
+            last_slot: slot,
+        };
+        let results = |pool: &PgPool| -> Vec<(String, i64)> {
+            get_results(pool, 1, &program)
+                .unwrap()
+                .into_iter()
+                .map(|row| (row.candidate_name, row.votes))
+                .collect()
+        };
+
+        // Candidates seen before their poll are picked up when it's indexed.
+        assert!(upsert_idl_account(&pool, &candidate("Ada", 2, 100)).unwrap());
+        assert!(results(&pool).is_empty());
+        upsert_poll(&pool, &new_poll(&program, 1, 100), 0, NOW).unwrap();
+        assert_eq!(results(&pool), [("Ada".to_string(), 2)]);
+
+        assert!(upsert_idl_account(&pool, &candidate("Bob", 5, 101)).unwrap());
+        assert!(upsert_idl_account(&pool, &candidate("Ada", 6, 102)).unwrap());
+        assert_eq!(
+            results(&pool),
+            [("Ada".to_string(), 6), ("Bob".to_string(), 5)]
+        );
+
+        // A stale update changes nothing; a closed candidate leaves the results.
+        assert!(!upsert_idl_account(&pool, &candidate("Ada", 1, 90)).unwrap());
+        mark_account_closed(&pool, &program, &[b'B'; 32], 110, NOW).unwrap();
+        assert_eq!(results(&pool), [("Ada".to_string(), 6)]);
+
+        assert_eq!(rebuild_poll_results(&pool).unwrap(), 1);
+        assert_eq!(results(&pool), [("Ada".to_string(), 6)]);
+    }
+
+    /// The (program, poll id) of each hit, in rank order.
+    fn ranked(hits: &[PollSearchHit]) -> Vec<(u8, i64)> {
+        hits.iter()
+            .map(|hit| (hit.poll.program_id[0], hit.poll.poll_id))
+            .collect()
+    }
+
+    fn titled(program: &[u8], id_of_poll: i64, name: &str, description: &str) -> NewPoll {
+        NewPoll {
+            poll_name: name.to_string(),
+            poll_description: description.to_string(),
+            ..new_poll(program, id_of_poll, 100)
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn search_ranks_names_above_descriptions_across_programs() {
+        let pool = test_pool();
+        let (a, b) = ([0x30; 32], [0x31; 32]);
+        let batch = [
+            titled(&a, 1, "Lunch menu", "Which pizza topping wins?"),
+            titled(&b, 2, "Pizza night", "Friday"),
+            titled(&a, 3, "Board election", "No food involved"),
+            titled(&b, 4, "Pizza or pasta", "Pizza, but not pineapple"),
+        ];
+        upsert_polls(&pool, &batch, 0, NOW).unwrap();
+        let now = Utc::now();
+        let weights = SearchWeights::default();
+
+        let hits = search_polls(&pool, "pizza", None, 10, weights, now).unwrap();
+        assert_eq!(ranked(&hits), [(0x31, 4), (0x31, 2), (0x30, 1)]);
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+
+        let hits = search_polls(&pool, "pizza", Some(&a), 10, weights, now).unwrap();
+        assert_eq!(ranked(&hits), [(0x30, 1)]);
+        let hits = search_polls(&pool, "pizza -pineapple", None, 10, weights, now).unwrap();
+        let mut found = ranked(&hits);
+        found.sort();
+        assert_eq!(found, [(0x30, 1), (0x31, 2)]);
+        let hits = search_polls(&pool, "pizza", None, 1, weights, now).unwrap();
+        assert_eq!(ranked(&hits), [(0x31, 4)]);
+        assert!(search_polls(&pool, "sushi", None, 10, weights, now)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn search_blends_recency_and_breaks_ties_by_program_and_poll() {
+        let pool = test_pool();
+        let (a, b) = ([0x32; 32], [0x33; 32]);
+        let batch = [
+            titled(&b, 1, "Team offsite", ""),
+            titled(&a, 2, "Team offsite", ""),
+            titled(&a, 1, "Team offsite", ""),
+        ];
+        upsert_polls(&pool, &batch, 0, NOW).unwrap();
+        let mut conn = pool.get().unwrap();
+        diesel::update(
+            polls
+                .filter(program_id.eq(a.as_slice()))
+                .filter(poll_id.eq(1)),
+        )
+        .set(last_updated_at.eq(Utc::now() - chrono::Duration::days(30)))
+        .execute(&mut conn)
+        .unwrap();
+        drop(conn);
+        let now = Utc::now();
+
+        // Equal scores without recency: program, then poll id.
+        let text_only = SearchWeights {
+            text: 1.0,
+            recency: 0.0,
+        };
+        let hits = search_polls(&pool, "offsite", None, 10, text_only, now).unwrap();
+        assert_eq!(ranked(&hits), [(0x32, 1), (0x32, 2), (0x33, 1)]);
+        // The same query always ranks the same.
+        let again = search_polls(&pool, "offsite", None, 10, text_only, now).unwrap();
+        assert_eq!(ranked(&again), ranked(&hits));
+
+        // With recency, the poll untouched for a month sinks.
+        let hits = search_polls(&pool, "offsite", None, 10, SearchWeights::default(), now).unwrap();
+        assert_eq!(ranked(&hits), [(0x32, 2), (0x33, 1), (0x32, 1)]);
+        let recent = hits[0].score - hits[2].score;
+        // 0.1 / (1 + 0) - 0.1 / (1 + 30), give or take the time the updates took.
+        assert!((recent - (0.1 - 0.1 / 31.0)).abs() < 1e-3, "{}", recent);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn search_uses_the_index_on_a_large_dataset() {
+        const POLLS: i64 = 20_000;
+        let pool = test_pool();
+        let program = [0x34; 32];
+        let batch: Vec<NewPoll> = (0..POLLS)
+            .map(|i| {
+                let topic = if i % 1000 == 0 { "budget" } else { "misc" };
+                titled(&program, i, &format!("Poll {} {}", i, topic), "Seeded")
+            })
+            .collect();
+        for chunk in batch.chunks(2000) {
+            upsert_polls(&pool, chunk, 0, NOW).unwrap();
+        }
+        let mut conn = pool.get().unwrap();
+        diesel::sql_query("ANALYZE polls")
+            .execute(&mut conn)
+            .unwrap();
+
+        #[derive(QueryableByName)]
+        struct PlanLine {
+            #[diesel(sql_type = diesel::sql_types::Text, column_name = "QUERY PLAN")]
+            line: String,
+        }
+        // The same document expression as `search_polls`.
+        let plan = diesel::sql_query(
+            "EXPLAIN SELECT p.id FROM polls p \
+             WHERE (setweight(to_tsvector('english', p.poll_name), 'A') \
+                 || setweight(to_tsvector('english', p.poll_description), 'B')) \
+                 @@ websearch_to_tsquery('english', 'budget')",
+        )
+        .load::<PlanLine>(&mut conn)
+        .unwrap();
+        drop(conn);
+        let plan: Vec<String> = plan.into_iter().map(|row| row.line).collect();
+        assert!(
+            plan.iter().any(|line| line.contains("polls_search_idx")),
+            "{:#?}",
+            plan
+        );
+
+        let started = std::time::Instant::now();
+        let hits = search_polls(
+            &pool,
+            "budget",
+            None,
+            100,
+            SearchWeights::default(),
+            Utc::now(),
+        )
+        .unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(hits.len(), (POLLS / 1000) as usize);
+        // Generous, to stay stable on a loaded machine; a sequential scan over every document
+        // is an order of magnitude slower.
+        assert!(
+            elapsed < std::time::Duration::from_millis(500),
+            "{:?}",
+            elapsed
+        );
+    }
+}
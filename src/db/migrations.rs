@@ -0,0 +1,251 @@
+use anyhow::{bail, Context, Result};
+use diesel::migration::MigrationSource;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use super::db::PgPool;
+
+/// All migrations from `db/migrations`, compiled into the binary.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
+
+// Generated by build.rs: `(migration directory name, up.sql contents)`.
+include!(concat!(env!("OUT_DIR"), "/migrations_sql.rs"));
+
+/// A migration embedded in the binary, together with its exact SQL.
+pub struct EmbeddedMigration {
+    /// Full directory name, e.g. `2025-05-20-131632_create_polls`.
+    pub name: String,
+    /// Version as recorded in `__diesel_schema_migrations`, e.g. `20250520131632`.
+    pub version: String,
+    pub up_sql: &'static str,
+}
+
+impl EmbeddedMigration {
+    /// File name used when exporting the SQL for review.
+    pub fn file_name(&self) -> String {
+        format!("{}.sql", self.name)
+    }
+
+    /// SHA-256 of the exact SQL the binary would run.
+    pub fn sql_hash(&self) -> String {
+        sql_hash(self.up_sql)
+    }
+}
+
+/// Outcome of comparing one exported SQL file with the embedded migration.
+pub enum ExportStatus {
+    Matches,
+    Differs { exported: String, embedded: String },
+    Missing,
+}
+
+#[derive(QueryableByName)]
+struct AppliedVersion {
+    #[diesel(sql_type = Text)]
+    version: String,
+}
+
+/// Hex-encoded SHA-256 of a SQL script.
+pub fn sql_hash(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Every migration embedded in the binary, oldest first.
+pub fn embedded_migrations() -> Result<Vec<EmbeddedMigration>> {
+    let migrations = MigrationSource::<Pg>::migrations(&MIGRATIONS)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to read embedded migrations")?;
+
+    let mut result = Vec::with_capacity(migrations.len());
+    for migration in migrations {
+        let name = migration.name().to_string();
+        let up_sql = EMBEDDED_SQL
+            .iter()
+            .find(|(embedded_name, _)| *embedded_name == name)
+            .map(|(_, sql)| *sql)
+            .with_context(|| format!("No embedded SQL for migration {}", name))?;
+        result.push(EmbeddedMigration {
+            version: migration.name().version().to_string(),
+            name,
+            up_sql,
+        });
+    }
+    result.sort_by(|a, b| a.version.cmp(&b.version));
+    Ok(result)
+}
+
+/// Versions recorded as applied in `__diesel_schema_migrations`.
+///
+/// Read with a plain SELECT (instead of diesel's harness, which creates the table if missing)
+/// so it also works on read-only connections.
+pub fn applied_versions(pool: &PgPool) -> Result<Vec<String>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    let rows = diesel::sql_query("SELECT version FROM __diesel_schema_migrations ORDER BY version")
+        .load::<AppliedVersion>(&mut conn)
+        .context("Failed to read __diesel_schema_migrations (has the database been set up?)")?;
+    Ok(rows.into_iter().map(|row| row.version).collect())
+}
+
+/// Embedded migrations not yet recorded as applied in the database.
+pub fn pending_migrations(pool: &PgPool) -> Result<Vec<EmbeddedMigration>> {
+    let applied = applied_versions(pool)?;
+    Ok(embedded_migrations()?
+        .into_iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect())
+}
+
+/// Compares previously exported SQL files in `dir` with what the binary embeds.
+pub fn verify_export(dir: &Path) -> Result<Vec<(EmbeddedMigration, ExportStatus)>> {
+    let mut result = Vec::new();
+    for migration in embedded_migrations()? {
+        let path = dir.join(migration.file_name());
+        let status = match fs::read_to_string(&path) {
+            Ok(exported) => {
+                let exported = sql_hash(&exported);
+                let embedded = migration.sql_hash();
+                if exported == embedded {
+                    ExportStatus::Matches
+                } else {
+                    ExportStatus::Differs { exported, embedded }
+                }
+            }
+            Err(_) => ExportStatus::Missing,
+        };
+        result.push((migration, status));
+    }
+    Ok(result)
+}
+
+/// Records a migration as applied without running it.
+///
+/// For DDL that a DBA applied out-of-band. Only a *pending* embedded version can be marked,
+/// so typos and double-marking are rejected instead of silently corrupting the bookkeeping.
+pub fn mark_applied(pool: &PgPool, version: &str) -> Result<()> {
+    let embedded = embedded_migrations()?;
+    if !embedded.iter().any(|m| m.version == version) {
+        bail!("{} is not a migration embedded in this binary", version);
+    }
+    if applied_versions(pool)?.iter().any(|v| v == version) {
+        bail!("Migration {} is already recorded as applied", version);
+    }
+
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ($1)")
+        .bind::<Text, _>(version)
+        .execute(&mut conn)
+        .context("Failed to record migration as applied")?;
+    Ok(())
+}
+
+/// Runs every pending migration, creating diesel's bookkeeping table on a fresh database.
+/// Returns the versions applied. Only `cli init` (after asking) and the listener with
+/// `--run-migrations` do this.
+pub fn run_pending(pool: &PgPool) -> Result<Vec<String>> {
+    let mut conn = pool
+        .get()
+        .context("Failed to get DB connection from pool")?;
+    let applied = conn
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to run pending migrations")?;
+    Ok(applied.iter().map(|version| version.to_string()).collect())
+}
+
+/// Schema consistency check used at startup.
+///
+/// The database may have been migrated by diesel or externally by a DBA (then recorded with
+/// `mark-applied`); either way every embedded migration must be recorded as applied.
+pub fn check_schema(pool: &PgPool) -> Result<()> {
+    let pending = pending_migrations(pool)?;
+    if !pending.is_empty() {
+        let names: Vec<&str> = pending.iter().map(|m| m.name.as_str()).collect();
+        bail!(
+            "Database schema is behind this binary, pending migrations: {}. \
+             Run `diesel migration run`, start with `--run-migrations` or review them with \
+             `cli migrations plan`",
+            names.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::test_pool;
+
+    #[test]
+    fn embeds_every_migration_with_its_sql() {
+        let embedded = embedded_migrations().unwrap();
+        let on_disk = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/db/migrations"))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().is_dir())
+            .count();
+
+        assert_eq!(embedded.len(), on_disk);
+        assert!(embedded.iter().all(|m| !m.up_sql.trim().is_empty()));
+        assert!(embedded.windows(2).all(|w| w[0].version < w[1].version));
+    }
+
+    #[test]
+    fn verify_export_compares_each_file() {
+        let dir = std::env::temp_dir().join(format!("migrations-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let embedded = embedded_migrations().unwrap();
+        for migration in &embedded {
+            fs::write(dir.join(migration.file_name()), migration.up_sql).unwrap();
+        }
+        let (edited, removed) = (&embedded[0], &embedded[1]);
+        fs::write(dir.join(edited.file_name()), "DROP TABLE polls;").unwrap();
+        fs::remove_file(dir.join(removed.file_name())).unwrap();
+
+        let statuses = verify_export(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        for (migration, status) in statuses {
+            match status {
+                ExportStatus::Differs { exported, embedded } => {
+                    assert_eq!(migration.name, edited.name);
+                    assert_eq!(exported, sql_hash("DROP TABLE polls;"));
+                    assert_eq!(embedded, edited.sql_hash());
+                }
+                ExportStatus::Missing => assert_eq!(migration.name, removed.name),
+                ExportStatus::Matches => {
+                    assert!(migration.name != edited.name && migration.name != removed.name)
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn mark_applied_only_records_pending_versions() {
+        let pool = test_pool();
+        check_schema(&pool).unwrap();
+        let latest = embedded_migrations().unwrap().pop().unwrap();
+
+        assert!(mark_applied(&pool, "19700101000000").is_err());
+        assert!(mark_applied(&pool, &latest.version).is_err());
+
+        // As if a DBA hadn't applied it yet.
+        diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = $1")
+            .bind::<Text, _>(&latest.version)
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        assert!(check_schema(&pool).is_err());
+        mark_applied(&pool, &latest.version).unwrap();
+        check_schema(&pool).unwrap();
+    }
+}
@@ -1,3 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod db;
+pub mod migrations;
 pub mod models;
 pub mod schema;
+#[cfg(test)]
+pub(crate) mod test_support;
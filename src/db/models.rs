@@ -1,4 +1,10 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, NaiveDate, Utc};
 use diesel::prelude::*;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::state::lifecycle::{LifecycleFacts, PollLifecycle};
+use voting_dapp_api_types as api_types;
 
 #[derive(Insertable, Clone)]
 #[diesel(table_name = crate::db::schema::polls)]
@@ -11,10 +17,86 @@ pub struct NewPoll {
     pub poll_end: i64,
     pub candidate_amount: i64,
     pub candidate_winner: Vec<u8>,
+    /// Slot at which this account state was observed.
+    pub last_slot: i64,
+    /// Program that owns the poll account; `poll_id` is only unique within it.
+    pub program_id: Vec<u8>,
+    /// The poll account this state was decoded from.
+    pub account_pubkey: Vec<u8>,
 }
 
-#[derive(Queryable, Debug)]
+impl NewPoll {
+    /// The row for a decoded Poll account observed at `slot`.
+    pub fn from_account(
+        poll: &crate::state::pool::Poll,
+        slot: u64,
+        program_id: &Pubkey,
+        account_pubkey: &Pubkey,
+    ) -> Self {
+        Self {
+            poll_id: poll.poll_id as i64, // Diesel uses i64 instead of u64
+            poll_owner: poll.poll_owner.to_bytes().to_vec(),
+            poll_name: poll.poll_name.clone(),
+            poll_description: poll.poll_description.clone(),
+            poll_start: poll.poll_start as i64,
+            poll_end: poll.poll_end as i64,
+            candidate_amount: poll.candidate_amount as i64,
+            candidate_winner: poll.candidate_winner.to_bytes().to_vec(),
+            last_slot: slot as i64,
+            program_id: program_id.to_bytes().to_vec(),
+            account_pubkey: account_pubkey.to_bytes().to_vec(),
+        }
+    }
+
+    /// The facts the lifecycle state machine needs from this update.
+    pub fn lifecycle_facts(&self) -> LifecycleFacts {
+        LifecycleFacts {
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            winner_declared: self.candidate_winner.iter().any(|b| *b != 0),
+            closed: false,
+        }
+    }
+
+    /// Checksum of the decoded on-chain fields, stored alongside the row (see [`ChecksumFields`]).
+    pub fn checksum(&self) -> i64 {
+        ChecksumFields {
+            program_id: &self.program_id,
+            poll_id: self.poll_id,
+            poll_owner: &self.poll_owner,
+            poll_name: &self.poll_name,
+            poll_description: &self.poll_description,
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            candidate_winner: &self.candidate_winner,
+        }
+        .checksum()
+    }
+
+    /// The update as pushed to `GET /live` subscribers (see `live`).
+    pub fn to_live(&self) -> Result<api_types::LiveUpdate> {
+        Ok(api_types::LiveUpdate::Poll(api_types::LivePoll {
+            program_id: optional_pubkey_string(&self.program_id)?,
+            account: optional_pubkey_string(&self.account_pubkey)?,
+            poll_id: self.poll_id,
+            poll_owner: pubkey_from_bytes(&self.poll_owner)?.to_string(),
+            poll_name: self.poll_name.clone(),
+            poll_description: self.poll_description.clone(),
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            candidate_winner: pubkey_from_bytes(&self.candidate_winner)?.to_string(),
+            last_slot: self.last_slot,
+        }))
+    }
+}
+
+/// A stored poll row. Exposed by the HTTP API as [`api_types::Poll`] (see [`Poll::to_dto`]).
+#[derive(Queryable, Debug, Clone)]
 pub struct Poll {
+    /// Internal surrogate key, not exposed.
     pub id: i32,
     pub poll_id: i64,
     pub poll_owner: Vec<u8>,
@@ -24,4 +106,650 @@ pub struct Poll {
     pub poll_end: i64,
     pub candidate_amount: i64,
     pub candidate_winner: Vec<u8>,
+    pub lifecycle: String,
+    pub last_slot: i64,
+    /// Empty for rows indexed before program IDs were tracked.
+    pub program_id: Vec<u8>,
+    /// Checksum written with the row; `None` until backfilled for rows older than the column.
+    pub checksum: Option<i64>,
+    /// The poll account; empty for rows indexed before it was tracked.
+    pub account_pubkey: Vec<u8>,
+    /// When the row was inserted.
+    pub first_seen_at: DateTime<Utc>,
+    /// When an account update was last written to the row (stale updates don't count).
+    pub last_updated_at: DateTime<Utc>,
+    /// When the poll account was closed; `None` while it exists.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::anomalies)]
+pub struct NewAnomaly {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub kind: String,
+    pub details: String,
+}
+
+/// An operator note on a poll (see `cli annotate`). Never affects indexed data.
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::annotations)]
+pub struct NewAnnotation {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub author: String,
+    pub text: String,
+    /// One of `info`, `warning`, `critical` (enforced by the table).
+    pub severity: String,
+}
+
+/// A stored annotation, exposed as an entry of a poll's `annotations` array
+/// (see [`Annotation::to_dto`]).
+#[derive(Queryable, Debug)]
+pub struct Annotation {
+    pub id: i32,
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub author: String,
+    pub text: String,
+    pub severity: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` while the annotation is open.
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+impl Annotation {
+    /// The API representation of this annotation.
+    pub fn to_dto(&self) -> Result<api_types::Annotation> {
+        Ok(api_types::Annotation {
+            id: self.id,
+            program_id: optional_pubkey_string(&self.program_id)?,
+            poll_id: self.poll_id,
+            author: self.author.clone(),
+            text: self.text.clone(),
+            severity: self.severity.clone(),
+            created_at: self.created_at,
+            resolved_at: self.resolved_at,
+        })
+    }
+}
+
+/// A program v3 Delegation account, as written by the listener.
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::db::schema::delegations)]
+pub struct NewDelegation {
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    pub poll_id: i64,
+    pub delegator: Vec<u8>,
+    pub delegate: Vec<u8>,
+    pub expiry: Option<i64>,
+    pub last_slot: i64,
+}
+
+impl NewDelegation {
+    /// The row for a decoded Delegation account observed at `slot`.
+    pub fn from_account(
+        delegation: &crate::state::delegation::Delegation,
+        slot: u64,
+        program_id: &Pubkey,
+        account_pubkey: &Pubkey,
+    ) -> Self {
+        Self {
+            program_id: program_id.to_bytes().to_vec(),
+            account_pubkey: account_pubkey.to_bytes().to_vec(),
+            poll_id: delegation.poll_id as i64,
+            delegator: delegation.delegator.to_bytes().to_vec(),
+            delegate: delegation.delegate.to_bytes().to_vec(),
+            expiry: delegation.expiry,
+            last_slot: slot as i64,
+        }
+    }
+
+    /// The update as pushed to `GET /live` subscribers (see `live`).
+    pub fn to_live(&self) -> Result<api_types::LiveUpdate> {
+        Ok(api_types::LiveUpdate::Delegation(
+            api_types::LiveDelegation {
+                program_id: optional_pubkey_string(&self.program_id)?,
+                account: optional_pubkey_string(&self.account_pubkey)?,
+                poll_id: self.poll_id,
+                delegator: pubkey_from_bytes(&self.delegator)?.to_string(),
+                delegate: pubkey_from_bytes(&self.delegate)?.to_string(),
+                expiry: self.expiry,
+                last_slot: self.last_slot,
+            },
+        ))
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct Delegation {
+    pub id: i32,
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    pub poll_id: i64,
+    pub delegator: Vec<u8>,
+    pub delegate: Vec<u8>,
+    /// Unix timestamp; `None` never expires.
+    pub expiry: Option<i64>,
+    pub last_slot: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_updated_at: DateTime<Utc>,
+    /// When the delegation account was closed; `None` while it exists.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Delegation {
+    /// Whether the delegation still applies at unix time `now`.
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expiry.is_none_or(|expiry| now < expiry)
+    }
+}
+
+/// A change of the listener's effective configuration (see `config_audit`).
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::config_changes)]
+pub struct NewConfigChange {
+    pub source: String,
+    pub changed_by: String,
+    /// A `config_audit::ConfigDiff`, secrets already masked.
+    pub diff: serde_json::Value,
+}
+
+#[derive(Queryable, Debug)]
+pub struct ConfigChange {
+    pub id: i32,
+    pub changed_at: DateTime<Utc>,
+    pub source: String,
+    pub changed_by: String,
+    pub diff: serde_json::Value,
+}
+
+/// An account decoded from its program's IDL (see `idl_decode`), as written by the listener.
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::db::schema::idl_accounts)]
+pub struct NewIdlAccount {
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    pub account_type: String,
+    pub data: serde_json::Value,
+    pub last_slot: i64,
+}
+
+impl NewIdlAccount {
+    /// The update as pushed to `GET /live` subscribers (see `live`).
+    pub fn to_live(&self) -> Result<api_types::LiveUpdate> {
+        Ok(api_types::LiveUpdate::Account(api_types::LiveAccount {
+            program_id: optional_pubkey_string(&self.program_id)?,
+            account: optional_pubkey_string(&self.account_pubkey)?,
+            account_type: self.account_type.clone(),
+            data: self.data.clone(),
+            last_slot: self.last_slot,
+        }))
+    }
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct IdlAccount {
+    pub id: i32,
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    /// The account's name in the IDL, e.g. `Candidate`.
+    pub account_type: String,
+    pub data: serde_json::Value,
+    pub last_slot: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_updated_at: DateTime<Utc>,
+    /// When the account was closed; `None` while it exists.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// The votes of one candidate of a poll, as stored in `poll_results` (see
+/// `db::refresh_poll_results`).
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::poll_results)]
+pub struct NewPollResult {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub candidate_account: Vec<u8>,
+    pub candidate_name: String,
+    pub votes: i64,
+    pub last_slot: i64,
+}
+
+impl NewPollResult {
+    /// The row of a candidate of `poll`.
+    pub fn from_candidate(poll: &Poll, candidate: &crate::candidates::Candidate) -> Self {
+        Self {
+            program_id: poll.program_id.clone(),
+            poll_id: poll.poll_id,
+            candidate_account: candidate.account.to_bytes().to_vec(),
+            candidate_name: candidate.name.clone(),
+            votes: candidate.votes as i64,
+            last_slot: candidate.last_slot,
+        }
+    }
+}
+
+/// A stored `poll_results` row.
+#[derive(Queryable, Debug, Clone)]
+pub struct PollResult {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub candidate_account: Vec<u8>,
+    pub candidate_name: String,
+    pub votes: i64,
+    pub last_slot: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A version of a program's IDL (see `idl`).
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::idls)]
+pub struct NewIdl {
+    pub program_id: Vec<u8>,
+    pub content_hash: Vec<u8>,
+    pub idl: serde_json::Value,
+}
+
+#[derive(Queryable, Debug)]
+pub struct Idl {
+    /// Also the version number shown by `cli idl history`.
+    pub id: i32,
+    pub program_id: Vec<u8>,
+    pub content_hash: Vec<u8>,
+    pub idl: serde_json::Value,
+    pub loaded_at: DateTime<Utc>,
+}
+
+/// A lifecycle state a poll moved to, recorded with the `lifecycle` column.
+#[derive(Insertable)]
+#[diesel(table_name = crate::db::schema::lifecycle_transitions)]
+pub struct NewLifecycleTransition {
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub from_state: Option<String>,
+    pub to_state: String,
+}
+
+#[derive(Queryable, Debug, Clone)]
+pub struct LifecycleTransition {
+    pub id: i32,
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    /// `None` when the poll was first indexed.
+    pub from_state: Option<String>,
+    pub to_state: String,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// A raw account update, as archived with `--archive-raw-accounts`.
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::db::schema::account_raw_history)]
+pub struct NewRawAccount {
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    pub slot: i64,
+    pub data: Vec<u8>,
+}
+
+/// An `account_raw_history` row.
+#[derive(Queryable, Debug, Clone)]
+#[diesel(table_name = crate::db::schema::account_raw_history)]
+pub struct RawAccount {
+    /// Increases in the order the listener processed the updates.
+    pub id: i64,
+    pub program_id: Vec<u8>,
+    pub account_pubkey: Vec<u8>,
+    pub slot: i64,
+    pub data: Vec<u8>,
+    pub received_at: DateTime<Utc>,
+}
+
+/// An account the listener couldn't decode, as recorded in `decode_failures`.
+#[derive(Insertable, Clone, Debug)]
+#[diesel(table_name = crate::db::schema::decode_failures)]
+pub struct NewDecodeFailure {
+    pub account_pubkey: Vec<u8>,
+    pub program_id: Vec<u8>,
+    pub slot: i64,
+    pub discriminator: Vec<u8>,
+    /// What the discriminator matched (`VotingAccountType::as_str`).
+    pub account_type: String,
+    pub error: String,
+    /// The raw account data, base64.
+    pub data: String,
+}
+
+/// A `decode_failures` row: the newest undecodable state of an account.
+#[derive(Queryable, Debug, Clone)]
+#[diesel(table_name = crate::db::schema::decode_failures)]
+pub struct DecodeFailure {
+    pub account_pubkey: Vec<u8>,
+    pub program_id: Vec<u8>,
+    pub slot: i64,
+    pub discriminator: Vec<u8>,
+    pub account_type: String,
+    pub error: String,
+    pub data: String,
+    pub first_failed_at: DateTime<Utc>,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+/// An account the writer stopped writing (see `quarantine`).
+#[derive(Insertable, Queryable, Debug, Clone)]
+#[diesel(table_name = crate::db::schema::quarantined_accounts)]
+pub struct QuarantinedAccount {
+    pub account_pubkey: Vec<u8>,
+    pub program_id: Vec<u8>,
+    pub poll_id: i64,
+    pub failures: i32,
+    pub last_error: String,
+    pub data_checksum: i64,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+/// A background job of the listener (see `scheduler`), with its last run.
+#[derive(Queryable, Debug, Clone)]
+#[diesel(table_name = crate::db::schema::jobs)]
+pub struct JobRow {
+    pub name: String,
+    pub class: String,
+    pub schedule: String,
+    pub paused: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub run_requested_at: Option<DateTime<Utc>>,
+}
+
+/// An event decoded from transaction logs (see `state::events`).
+#[derive(Insertable, Clone)]
+#[diesel(table_name = crate::db::schema::events)]
+pub struct NewEvent {
+    pub program_id: Vec<u8>,
+    /// Transaction signature, base58.
+    pub signature: String,
+    /// Position of the event within the transaction's logs.
+    pub log_index: i32,
+    pub slot: i64,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Insertable, Queryable, Debug)]
+#[diesel(table_name = crate::db::schema::bandwidth_usage)]
+pub struct BandwidthUsage {
+    pub day: NaiveDate,
+    pub endpoint: String,
+    pub feature: String,
+    pub bytes: i64,
+}
+
+impl Poll {
+    /// The API representation of this row, with pubkeys as base58 strings and without
+    /// annotations. Fails when a pubkey column doesn't hold 32 bytes (a corrupt row).
+    pub fn to_dto(&self) -> Result<api_types::Poll> {
+        Ok(api_types::Poll {
+            poll_id: self.poll_id,
+            poll_owner: self.owner_pubkey()?.to_string(),
+            poll_name: self.poll_name.clone(),
+            poll_description: self.poll_description.clone(),
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            candidate_winner: self.winner_pubkey()?.to_string(),
+            lifecycle: self.lifecycle.clone(),
+            last_slot: self.last_slot,
+            program_id: optional_pubkey_string(&self.program_id)?,
+            account_pubkey: optional_pubkey_string(&self.account_pubkey)?,
+            first_seen_at: self.first_seen_at,
+            last_updated_at: self.last_updated_at,
+            annotations: None,
+            completeness: None,
+        })
+    }
+
+    /// The poll owner as a `Pubkey` (stored as raw bytes in the DB).
+    pub fn owner_pubkey(&self) -> Result<Pubkey> {
+        pubkey_from_bytes(&self.poll_owner)
+    }
+
+    /// The declared winner as a `Pubkey` (all zeros until the poll is finalized on-chain).
+    pub fn winner_pubkey(&self) -> Result<Pubkey> {
+        pubkey_from_bytes(&self.candidate_winner)
+    }
+
+    /// The poll account, or `None` for rows indexed before it was tracked.
+    pub fn account_address(&self) -> Result<Option<Pubkey>> {
+        if self.account_pubkey.is_empty() {
+            return Ok(None);
+        }
+        pubkey_from_bytes(&self.account_pubkey).map(Some)
+    }
+
+    /// The program the poll belongs to, or `None` for rows indexed before program IDs were tracked.
+    pub fn program_pubkey(&self) -> Result<Option<Pubkey>> {
+        if self.program_id.is_empty() {
+            return Ok(None);
+        }
+        pubkey_from_bytes(&self.program_id).map(Some)
+    }
+
+    /// Recomputes the checksum from the stored fields. Differs from `checksum` when the row
+    /// was modified outside the writer (manual UPDATE, bad migration, ...).
+    pub fn computed_checksum(&self) -> i64 {
+        ChecksumFields {
+            program_id: &self.program_id,
+            poll_id: self.poll_id,
+            poll_owner: &self.poll_owner,
+            poll_name: &self.poll_name,
+            poll_description: &self.poll_description,
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            candidate_winner: &self.candidate_winner,
+        }
+        .checksum()
+    }
+
+    /// The persisted lifecycle state.
+    pub fn lifecycle_state(&self) -> Result<PollLifecycle> {
+        self.lifecycle.parse().map_err(anyhow::Error::msg)
+    }
+
+    /// The facts the lifecycle state machine needs from the stored row.
+    pub fn lifecycle_facts(&self) -> LifecycleFacts {
+        LifecycleFacts {
+            poll_start: self.poll_start,
+            poll_end: self.poll_end,
+            candidate_amount: self.candidate_amount,
+            winner_declared: self.candidate_winner.iter().any(|b| *b != 0),
+            closed: self.deleted_at.is_some() || self.lifecycle == PollLifecycle::Closed.as_str(),
+        }
+    }
+}
+
+/// The fields covered by the `polls.checksum` column: exactly the decoded on-chain data plus the
+/// owning program. Listener-maintained columns (`lifecycle`, `last_slot`) are not covered, and
+/// columns added later must not be added here, so existing checksums stay valid.
+///
+/// Each field is hashed in this order, integers as 8 little-endian bytes and byte/string fields
+/// with a 4-byte little-endian length prefix (so `("ab", "c")` and `("a", "bc")` differ).
+struct ChecksumFields<'a> {
+    program_id: &'a [u8],
+    poll_id: i64,
+    poll_owner: &'a [u8],
+    poll_name: &'a str,
+    poll_description: &'a str,
+    poll_start: i64,
+    poll_end: i64,
+    candidate_amount: i64,
+    candidate_winner: &'a [u8],
+}
+
+impl ChecksumFields<'_> {
+    /// xxh3-64 of the canonical encoding, stored as `BIGINT` (same bits, signed).
+    fn checksum(&self) -> i64 {
+        fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+            buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            buf.extend_from_slice(bytes);
+        }
+
+        let mut buf = Vec::with_capacity(512);
+        put_bytes(&mut buf, self.program_id);
+        buf.extend_from_slice(&self.poll_id.to_le_bytes());
+        put_bytes(&mut buf, self.poll_owner);
+        put_bytes(&mut buf, self.poll_name.as_bytes());
+        put_bytes(&mut buf, self.poll_description.as_bytes());
+        buf.extend_from_slice(&self.poll_start.to_le_bytes());
+        buf.extend_from_slice(&self.poll_end.to_le_bytes());
+        buf.extend_from_slice(&self.candidate_amount.to_le_bytes());
+        put_bytes(&mut buf, self.candidate_winner);
+        xxhash_rust::xxh3::xxh3_64(&buf) as i64
+    }
+}
+
+/// Converts a `bytea` column back into a `Pubkey`.
+///
+/// Pubkeys are stored as their raw 32 bytes, so anything else means the row is corrupt.
+pub fn pubkey_from_bytes(bytes: &[u8]) -> Result<Pubkey> {
+    let array: [u8; 32] = match bytes.try_into() {
+        Ok(array) => array,
+        Err(_) => bail!("Expected a 32-byte pubkey, got {} bytes", bytes.len()),
+    };
+    Ok(Pubkey::new_from_array(array))
+}
+
+/// Human readable form of a `program_id` column: base58, or `unknown` for rows indexed
+/// before program IDs were tracked.
+pub fn program_label(program_id: &[u8]) -> String {
+    match pubkey_from_bytes(program_id) {
+        Ok(pubkey) => pubkey.to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
+
+/// Base58 form of an optional pubkey column (`program_id`, `account_pubkey`), or `None` when
+/// it's unknown (empty).
+fn optional_pubkey_string(bytes: &[u8]) -> Result<Option<String>> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    pubkey_from_bytes(bytes).map(|pubkey| Some(pubkey.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::{new_poll, poll_row};
+
+    #[test]
+    fn checksum_is_stable() {
+        // Stored checksums must stay valid across releases: this value may never change.
+        assert_eq!(new_poll(&[7; 32], 21, 100).checksum(), CHECKSUM_OF_POLL_21);
+    }
+
+    #[test]
+    fn checksum_covers_every_on_chain_field() {
+        let poll = new_poll(&[7; 32], 21, 100);
+        let changed = [
+            NewPoll {
+                program_id: vec![8; 32],
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_id: 22,
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_owner: vec![2; 32],
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_name: "Other".into(),
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_description: "x".into(),
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_start: poll.poll_start + 1,
+                ..poll.clone()
+            },
+            NewPoll {
+                poll_end: poll.poll_end + 1,
+                ..poll.clone()
+            },
+            NewPoll {
+                candidate_amount: 3,
+                ..poll.clone()
+            },
+            NewPoll {
+                candidate_winner: vec![3; 32],
+                ..poll.clone()
+            },
+        ];
+        for other in &changed {
+            assert_ne!(other.checksum(), poll.checksum());
+        }
+
+        // Listener-maintained columns aren't covered.
+        let resent = NewPoll {
+            last_slot: 200,
+            account_pubkey: vec![9; 32],
+            ..poll.clone()
+        };
+        assert_eq!(resent.checksum(), poll.checksum());
+    }
+
+    #[test]
+    fn checksum_separates_adjacent_strings() {
+        let poll = new_poll(&[7; 32], 21, 100);
+        let a = NewPoll {
+            poll_name: "ab".into(),
+            poll_description: "c".into(),
+            ..poll.clone()
+        };
+        let b = NewPoll {
+            poll_name: "a".into(),
+            poll_description: "bc".into(),
+            ..poll
+        };
+        assert_ne!(a.checksum(), b.checksum());
+    }
+
+    #[test]
+    fn stored_rows_recompute_the_written_checksum() {
+        let mut row = poll_row(&new_poll(&[7; 32], 21, 100));
+        assert_eq!(Some(row.computed_checksum()), row.checksum);
+
+        row.poll_name.push('!');
+        assert_ne!(Some(row.computed_checksum()), row.checksum);
+    }
+
+    #[test]
+    fn delegations_apply_until_their_expiry() {
+        let now = Utc::now();
+        let delegation = |expiry| Delegation {
+            id: 1,
+            program_id: vec![7; 32],
+            account_pubkey: vec![3; 32],
+            poll_id: 1,
+            delegator: vec![1; 32],
+            delegate: vec![2; 32],
+            expiry,
+            last_slot: 100,
+            first_seen_at: now,
+            last_updated_at: now,
+            deleted_at: None,
+        };
+        assert!(delegation(None).is_active(i64::MAX));
+        assert!(delegation(Some(1_000)).is_active(999));
+        assert!(!delegation(Some(1_000)).is_active(1_000));
+    }
+
+    const CHECKSUM_OF_POLL_21: i64 = -9_005_754_495_251_964_938;
 }
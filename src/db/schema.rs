@@ -1,5 +1,183 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_raw_history (id) {
+        id -> Int8,
+        program_id -> Bytea,
+        account_pubkey -> Bytea,
+        slot -> Int8,
+        data -> Bytea,
+        received_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    annotations (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        poll_id -> Int8,
+        #[max_length = 64]
+        author -> Varchar,
+        text -> Text,
+        #[max_length = 16]
+        severity -> Varchar,
+        created_at -> Timestamptz,
+        resolved_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    anomalies (id) {
+        id -> Int4,
+        poll_id -> Int8,
+        #[max_length = 64]
+        kind -> Varchar,
+        details -> Text,
+        created_at -> Timestamptz,
+        program_id -> Bytea,
+    }
+}
+
+diesel::table! {
+    bandwidth_usage (day, endpoint, feature) {
+        day -> Date,
+        endpoint -> Text,
+        #[max_length = 64]
+        feature -> Varchar,
+        bytes -> Int8,
+    }
+}
+
+diesel::table! {
+    config_changes (id) {
+        id -> Int4,
+        changed_at -> Timestamptz,
+        #[max_length = 32]
+        source -> Varchar,
+        #[max_length = 64]
+        changed_by -> Varchar,
+        diff -> Jsonb,
+    }
+}
+
+diesel::table! {
+    decode_failures (account_pubkey) {
+        account_pubkey -> Bytea,
+        program_id -> Bytea,
+        slot -> Int8,
+        discriminator -> Bytea,
+        #[max_length = 16]
+        account_type -> Varchar,
+        error -> Text,
+        data -> Text,
+        first_failed_at -> Timestamptz,
+        last_failed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    delegations (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        account_pubkey -> Bytea,
+        poll_id -> Int8,
+        delegator -> Bytea,
+        delegate -> Bytea,
+        expiry -> Nullable<Int8>,
+        last_slot -> Int8,
+        first_seen_at -> Timestamptz,
+        last_updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    events (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        #[max_length = 88]
+        signature -> Varchar,
+        log_index -> Int4,
+        slot -> Int8,
+        #[max_length = 64]
+        event_type -> Varchar,
+        payload -> Jsonb,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    idl_accounts (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        account_pubkey -> Bytea,
+        #[max_length = 64]
+        account_type -> Varchar,
+        data -> Jsonb,
+        last_slot -> Int8,
+        first_seen_at -> Timestamptz,
+        last_updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    idls (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        content_hash -> Bytea,
+        idl -> Jsonb,
+        loaded_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    jobs (name) {
+        name -> Text,
+        class -> Text,
+        schedule -> Text,
+        paused -> Bool,
+        next_run_at -> Nullable<Timestamptz>,
+        last_started_at -> Nullable<Timestamptz>,
+        last_finished_at -> Nullable<Timestamptz>,
+        last_error -> Nullable<Text>,
+        run_requested_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    lifecycle_transitions (id) {
+        id -> Int4,
+        program_id -> Bytea,
+        poll_id -> Int8,
+        #[max_length = 16]
+        from_state -> Nullable<Varchar>,
+        #[max_length = 16]
+        to_state -> Varchar,
+        occurred_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    listener_checkpoints (program_id) {
+        program_id -> Bytea,
+        last_slot -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    poll_results (program_id, poll_id, candidate_account) {
+        program_id -> Bytea,
+        poll_id -> Int8,
+        candidate_account -> Bytea,
+        candidate_name -> Text,
+        votes -> Int8,
+        last_slot -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     polls (id) {
         id -> Int4,
@@ -13,5 +191,45 @@ diesel::table! {
         poll_end -> Int8,
         candidate_amount -> Int8,
         candidate_winner -> Bytea,
+        #[max_length = 16]
+        lifecycle -> Varchar,
+        last_slot -> Int8,
+        program_id -> Bytea,
+        checksum -> Nullable<Int8>,
+        account_pubkey -> Bytea,
+        first_seen_at -> Timestamptz,
+        last_updated_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    quarantined_accounts (account_pubkey) {
+        account_pubkey -> Bytea,
+        program_id -> Bytea,
+        poll_id -> Int8,
+        failures -> Int4,
+        last_error -> Text,
+        data_checksum -> Int8,
+        quarantined_at -> Timestamptz,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(
+    account_raw_history,
+    annotations,
+    anomalies,
+    bandwidth_usage,
+    config_changes,
+    decode_failures,
+    delegations,
+    events,
+    idl_accounts,
+    idls,
+    jobs,
+    lifecycle_transitions,
+    listener_checkpoints,
+    poll_results,
+    polls,
+    quarantined_accounts,
+);
@@ -0,0 +1,89 @@
+//! Fixtures of the tests of database code. Tests that need Postgres are `#[ignore]`d by default;
+//! run them with `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`.
+
+use std::env;
+use std::sync::Once;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+
+use super::db::{establish_pool_for, PgPool};
+use super::migrations;
+use super::models::{NewPoll, Poll};
+
+/// Keeps every connection of a test pool in a transaction that is never committed.
+#[derive(Debug)]
+struct RollbackCustomizer;
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for RollbackCustomizer {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.begin_test_transaction()
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// The URL of the test database, migrated once per test run.
+pub(crate) fn database_url() -> String {
+    static MIGRATED: Once = Once::new();
+
+    let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    MIGRATED.call_once(|| {
+        let pool = establish_pool_for(&url, false).expect("test database unreachable");
+        migrations::run_pending(&pool).expect("failed to migrate the test database");
+    });
+    url
+}
+
+/// A pool of a single connection whose writes are rolled back once the pool is dropped, so
+/// tests neither see each other's rows nor leave any behind.
+pub(crate) fn test_pool() -> PgPool {
+    Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(RollbackCustomizer))
+        .build(ConnectionManager::<PgConnection>::new(database_url()))
+        .expect("failed to build the test pool")
+}
+
+/// The unix time tests evaluate lifecycles at, mid-2025: [`new_poll`]s are upcoming then.
+pub(crate) const NOW: i64 = 1_750_000_000;
+
+/// An upcoming poll with two candidates, observed at `slot`.
+pub(crate) fn new_poll(program: &[u8], poll_id: i64, slot: i64) -> NewPoll {
+    NewPoll {
+        poll_id,
+        poll_owner: vec![1; 32],
+        poll_name: format!("Poll {}", poll_id),
+        poll_description: String::new(),
+        poll_start: 4_000_000_000,
+        poll_end: 4_000_086_400,
+        candidate_amount: 2,
+        candidate_winner: vec![0; 32],
+        last_slot: slot,
+        program_id: program.to_vec(),
+        account_pubkey: vec![poll_id as u8; 32],
+    }
+}
+
+/// The row `upsert_polls` would store for `poll`, checksum included, without a database.
+pub(crate) fn poll_row(poll: &NewPoll) -> Poll {
+    Poll {
+        id: 1,
+        poll_id: poll.poll_id,
+        poll_owner: poll.poll_owner.clone(),
+        poll_name: poll.poll_name.clone(),
+        poll_description: poll.poll_description.clone(),
+        poll_start: poll.poll_start,
+        poll_end: poll.poll_end,
+        candidate_amount: poll.candidate_amount,
+        candidate_winner: poll.candidate_winner.clone(),
+        lifecycle: "upcoming".to_string(),
+        last_slot: poll.last_slot,
+        program_id: poll.program_id.clone(),
+        checksum: Some(poll.checksum()),
+        account_pubkey: poll.account_pubkey.clone(),
+        first_seen_at: Utc::now(),
+        last_updated_at: Utc::now(),
+        deleted_at: None,
+    }
+}
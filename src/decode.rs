@@ -0,0 +1,91 @@
+use clap::ValueEnum;
+
+use crate::state::anchor::decode_borsh;
+use crate::state::delegation::Delegation;
+use crate::state::error::DecodeError;
+use crate::state::pool::Poll;
+
+// Descriminator obtained from the IDL
+pub const POLL_DISCRIMINATOR: [u8; 8] = [110, 234, 167, 188, 231, 136, 153, 111];
+pub const POOL_CANDIDATE_DISCRIMINATOR: [u8; 8] = [86, 69, 250, 96, 193, 10, 222, 123];
+pub const VOTE_DISCRIMINATOR: [u8; 8] = [241, 93, 35, 191, 254, 147, 17, 202];
+// Program v3 only; sha256("account:Delegation")[..8], as Anchor derives it
+pub const DELEGATION_DISCRIMINATOR: [u8; 8] = [237, 90, 140, 159, 124, 255, 243, 80];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VotingAccountType {
+    Poll,
+    Candidate,
+    Vote,
+    Delegation,
+    #[value(skip)]
+    Unknown,
+}
+
+impl VotingAccountType {
+    /// Label used in logs and metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VotingAccountType::Poll => "poll",
+            VotingAccountType::Candidate => "candidate",
+            VotingAccountType::Vote => "vote",
+            VotingAccountType::Delegation => "delegation",
+            VotingAccountType::Unknown => "unknown",
+        }
+    }
+
+    /// The Anchor discriminator identifying this account type, if it has one.
+    pub fn discriminator(&self) -> Option<[u8; 8]> {
+        match self {
+            VotingAccountType::Poll => Some(POLL_DISCRIMINATOR),
+            VotingAccountType::Candidate => Some(POOL_CANDIDATE_DISCRIMINATOR),
+            VotingAccountType::Vote => Some(VOTE_DISCRIMINATOR),
+            VotingAccountType::Delegation => Some(DELEGATION_DISCRIMINATOR),
+            VotingAccountType::Unknown => None,
+        }
+    }
+}
+
+/// Determines the account type from the Anchor discriminator (the first 8 bytes of `data`).
+pub fn match_voting_account_type(data: &[u8]) -> VotingAccountType {
+    if data.len() < 8 {
+        return VotingAccountType::Unknown;
+    }
+
+    let mut descriminator = [0u8; 8];
+    descriminator.copy_from_slice(&data[..8]);
+
+    match descriminator {
+        POLL_DISCRIMINATOR => VotingAccountType::Poll,
+        POOL_CANDIDATE_DISCRIMINATOR => VotingAccountType::Candidate,
+        VOTE_DISCRIMINATOR => VotingAccountType::Vote,
+        DELEGATION_DISCRIMINATOR => VotingAccountType::Delegation,
+        _ => VotingAccountType::Unknown,
+    }
+}
+
+/// Decodes a whole Poll account (discriminator included; it isn't checked here).
+pub fn decode_poll(data: &[u8]) -> Result<Poll, DecodeError> {
+    if data.len() < 8 {
+        return Err(DecodeError::Truncated {
+            needed: 8,
+            got: data.len(),
+        });
+    }
+
+    let (_discriminator, body) = data.split_at(8);
+    decode_borsh::<Poll>(body)
+}
+
+/// Decodes a whole Delegation account (discriminator included; it isn't checked here).
+pub fn decode_delegation(data: &[u8]) -> Result<Delegation, DecodeError> {
+    if data.len() < 8 {
+        return Err(DecodeError::Truncated {
+            needed: 8,
+            got: data.len(),
+        });
+    }
+
+    let (_discriminator, body) = data.split_at(8);
+    decode_borsh::<Delegation>(body)
+}
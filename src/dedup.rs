@@ -0,0 +1,133 @@
+use std::num::NonZeroUsize;
+
+use lru::LruCache;
+use solana_sdk::pubkey::Pubkey;
+
+/// Remembers the last account data seen for each pubkey, so redelivered identical states
+/// (the RPC often resends an account with the same data, sometimes at a bumped slot) can skip
+/// decoding and persistence.
+///
+/// Only a 64-bit xxh3 hash of the data is kept, together with what processing it produced
+/// (`T`), so a skipped update reports the same outcome as the original one. The cache is
+/// bounded: the least recently updated accounts are evicted first. It must be cleared whenever
+/// updates may have been missed (reconnect, backfill), otherwise a state that changed and then
+/// changed back while we weren't looking would be wrongly skipped.
+pub struct AccountDedup<T> {
+    /// `None` when deduplication is disabled (`max_entries == 0`).
+    cache: Option<LruCache<Pubkey, (u64, T)>>,
+    skipped: u64,
+}
+
+impl<T: Clone> AccountDedup<T> {
+    /// Creates a cache holding at most `max_entries` accounts; 0 disables deduplication.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            cache: NonZeroUsize::new(max_entries).map(LruCache::new),
+            skipped: 0,
+        }
+    }
+
+    /// Hash of raw account data, as stored in the cache.
+    pub fn hash(data: &[u8]) -> u64 {
+        xxhash_rust::xxh3::xxh3_64(data)
+    }
+
+    /// Returns the outcome recorded for `pubkey` if its last seen data had the same `hash`,
+    /// counting the update as skipped.
+    pub fn duplicate_of(&mut self, pubkey: &Pubkey, hash: u64) -> Option<T> {
+        let (last_hash, outcome) = self.cache.as_mut()?.get(pubkey)?;
+        if *last_hash != hash {
+            return None;
+        }
+        self.skipped += 1;
+        Some(outcome.clone())
+    }
+
+    /// Records the data hash of a processed update and its outcome.
+    pub fn remember(&mut self, pubkey: Pubkey, hash: u64, outcome: T) {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.put(pubkey, (hash, outcome));
+        }
+    }
+
+    /// Forgets every account, e.g. after a reconnect or backfill.
+    pub fn clear(&mut self) {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Number of updates skipped as duplicates since startup.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_identical_data_with_its_first_outcome() {
+        let mut dedup = AccountDedup::new(10);
+        let account = Pubkey::new_unique();
+        let hash = AccountDedup::<&str>::hash(b"poll v1");
+        assert_eq!(dedup.duplicate_of(&account, hash), None);
+        dedup.remember(account, hash, "decoded");
+
+        assert_eq!(dedup.duplicate_of(&account, hash), Some("decoded"));
+        assert_eq!(dedup.duplicate_of(&account, hash), Some("decoded"));
+        assert_eq!(dedup.skipped(), 2);
+
+        // Changed data, or another account with the same data, is processed.
+        let changed = AccountDedup::<&str>::hash(b"poll v2");
+        assert_ne!(changed, hash);
+        assert_eq!(dedup.duplicate_of(&account, changed), None);
+        assert_eq!(dedup.duplicate_of(&Pubkey::new_unique(), hash), None);
+        assert_eq!(dedup.skipped(), 2);
+    }
+
+    #[test]
+    fn an_update_not_remembered_is_processed_again() {
+        // What the listener does when the sink refused the first write.
+        let mut dedup = AccountDedup::<bool>::new(10);
+        let account = Pubkey::new_unique();
+        let hash = AccountDedup::<bool>::hash(b"poll v1");
+        assert_eq!(dedup.duplicate_of(&account, hash), None);
+        assert_eq!(dedup.duplicate_of(&account, hash), None);
+        dedup.remember(account, hash, true);
+        assert_eq!(dedup.duplicate_of(&account, hash), Some(true));
+    }
+
+    #[test]
+    fn evicts_the_least_recently_updated_account() {
+        let mut dedup = AccountDedup::new(2);
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        dedup.remember(a, 1, ());
+        dedup.remember(b, 2, ());
+        // Seeing `a` again makes `b` the least recent.
+        assert_eq!(dedup.duplicate_of(&a, 1), Some(()));
+        dedup.remember(c, 3, ());
+        assert_eq!(dedup.duplicate_of(&b, 2), None);
+        assert_eq!(dedup.duplicate_of(&a, 1), Some(()));
+        assert_eq!(dedup.duplicate_of(&c, 3), Some(()));
+    }
+
+    #[test]
+    fn clearing_or_disabling_skips_nothing() {
+        let account = Pubkey::new_unique();
+        let mut dedup = AccountDedup::new(10);
+        dedup.remember(account, 1, ());
+        dedup.clear();
+        assert_eq!(dedup.duplicate_of(&account, 1), None);
+
+        let mut disabled = AccountDedup::new(0);
+        disabled.remember(account, 1, ());
+        assert_eq!(disabled.duplicate_of(&account, 1), None);
+        assert_eq!(disabled.skipped(), 0);
+    }
+}
@@ -0,0 +1,460 @@
+use std::fmt;
+
+use diesel::result::Error as DieselError;
+
+use crate::quarantine::is_data_error;
+use crate::state::error::DecodeError;
+
+/// An entry of the error catalog: the same failure carries the same code in CLI output, API
+/// error bodies and logs, and `cli explain <CODE>` prints its explanation.
+///
+/// Codes are stable: once released, an id is never renamed, renumbered or reused. A retired
+/// error keeps its entry. The hundreds group them: 01xx config, 02xx decoding, 03xx database,
+/// 04xx lookups and requests, 09xx internal.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ErrorCode {
+    /// e.g. `E0203_DECODE_STRING_TOO_LONG`
+    pub id: &'static str,
+    /// One line, shown next to the code.
+    pub message: &'static str,
+    /// What happened and what to do about it, for `cli explain`.
+    pub explanation: &'static str,
+}
+
+impl ErrorCode {
+    /// The number part of the id, e.g. `E0203`.
+    pub fn short(&self) -> &'static str {
+        self.id.split('_').next().unwrap_or(self.id)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id)
+    }
+}
+
+pub static CONFIG_READ_ONLY_WRITER: ErrorCode = ErrorCode {
+    id: "E0101_CONFIG_READ_ONLY_WRITER",
+    message: "--read-only can't run the database writer",
+    explanation: "The listener was started with --read-only and the postgres sink. Read-only mode \
+                  is for pointing the binary at a replica, where nothing may be written.\n\n\
+                  Run it with --sink stdout, or drop --read-only.",
+};
+
+pub static CONFIG_PROFILING_UNAVAILABLE: ErrorCode = ErrorCode {
+    id: "E0102_CONFIG_PROFILING_UNAVAILABLE",
+    message: "--enable-profiling can't be served",
+    explanation: "CPU profiles are served on the HTTP API, by binaries built with the `profiling` \
+                  feature.\n\n\
+                  Build with `cargo build --features profiling` and set --http-port, or drop \
+                  --enable-profiling.",
+};
+
+pub static CONFIG_IDL_PROGRAM_MISMATCH: ErrorCode = ErrorCode {
+    id: "E0103_CONFIG_IDL_PROGRAM_MISMATCH",
+    message: "an --idl file can't be matched with an indexed program",
+    explanation: "Each --idl file must describe one of the indexed programs: the one in its \
+                  `address` (or `metadata.address`), or the only indexed program when it has \
+                  none.\n\n\
+                  Check that the IDL belongs to a program given with --program-ids. With \
+                  several programs, add the `address` field to IDLs lacking it.",
+};
+
+pub static CONFIG_WARMUP_FAILED: ErrorCode = ErrorCode {
+    id: "E0104_CONFIG_WARMUP_FAILED",
+    message: "too few updates after subscribing were voting accounts",
+    explanation: "The warm-up check found that less than --warmup-min-ratio of the first updates \
+                  of a program decoded as voting accounts, and --strict-warmup stopped the \
+                  listener. The program ID most likely points at another program.\n\n\
+                  Compare the unknown discriminators in the diagnostic with the IDL of the \
+                  program you meant to index, and fix --program-ids.",
+};
+
+pub static CONFIG_PROGRAM_NOT_FOUND: ErrorCode = ErrorCode {
+    id: "E0105_CONFIG_PROGRAM_NOT_FOUND",
+    message: "the program doesn't exist on this cluster",
+    explanation: "`cli init` looked the program ID up on the configured RPC endpoint and found no \
+                  account, or an account that isn't an executable program. Either the ID is \
+                  wrong, or the program is deployed on another cluster (devnet vs \
+                  mainnet-beta, or a local validator).\n\n\
+                  Check the program ID and the websocket/RPC URLs together.",
+};
+
+pub static CONFIG_FILE_INVALID: ErrorCode = ErrorCode {
+    id: "E0106_CONFIG_FILE_INVALID",
+    message: "the config file can't be used",
+    explanation: "The listener's TOML config file (--config, `listener.toml` by default) isn't \
+                  valid TOML, sets a key that isn't a listener option, or gives an option a \
+                  table where a string, number, boolean or array was expected.\n\n\
+                  Keys are the long flag names (`ws_url` or `ws-url` for --ws-url); check the \
+                  file against `--help`.",
+};
+
+pub static DECODE_TRUNCATED: ErrorCode = ErrorCode {
+    id: "E0201_DECODE_TRUNCATED",
+    message: "account data ended before a field",
+    explanation: "An account's data is shorter than its layout needs. Usually the account was \
+                  written by another version of the program, or isn't a voting account despite \
+                  its discriminator.\n\n\
+                  Compare the account (`solana account <PUBKEY>`) with the program's IDL; load \
+                  the current IDL with --idl to track layout changes.",
+};
+
+pub static DECODE_INVALID_UTF8: ErrorCode = ErrorCode {
+    id: "E0202_DECODE_INVALID_UTF8",
+    message: "a string field isn't valid UTF-8",
+    explanation: "A string field of an account holds bytes that aren't UTF-8. The program should \
+                  never write those, so the layout assumed for the account is likely wrong.\n\n\
+                  Check the account against the program's IDL.",
+};
+
+pub static DECODE_STRING_TOO_LONG: ErrorCode = ErrorCode {
+    id: "E0203_DECODE_STRING_TOO_LONG",
+    message: "a string field is longer than its on-chain maximum",
+    explanation: "A string's length prefix exceeds the field's `#[max_len]`. Either the program \
+                  raised the limit, or the data isn't laid out as expected.\n\n\
+                  If the program changed, update the limits in `src/state` (and the column \
+                  sizes) to match the new IDL.",
+};
+
+pub static DECODE_INVALID_OPTION_TAG: ErrorCode = ErrorCode {
+    id: "E0204_DECODE_INVALID_OPTION_TAG",
+    message: "an optional field has a tag other than 0 or 1",
+    explanation: "Borsh encodes `Option` with a 0 or 1 tag byte; anything else means the bytes \
+                  before it were read with the wrong layout.\n\n\
+                  Check the account against the program's IDL.",
+};
+
+pub static DB_UNAVAILABLE: ErrorCode = ErrorCode {
+    id: "E0301_DB_UNAVAILABLE",
+    message: "the database couldn't be reached",
+    explanation: "No connection could be made or kept: the database is down, unreachable, out \
+                  of connections, or a transaction was interrupted. Writes are retried; nothing \
+                  is quarantined for these errors.\n\n\
+                  Check DATABASE_URL, that Postgres is up, and its connection limit.",
+};
+
+pub static DB_DATA_REJECTED: ErrorCode = ErrorCode {
+    id: "E0302_DB_DATA_REJECTED",
+    message: "the database rejected the data",
+    explanation: "A write failed because of the data itself: a constraint violation, or a \
+                  value too long or out of range for its column. It will fail again with the \
+                  same data; after --quarantine-after failures the account is quarantined \
+                  (E0303).\n\n\
+                  Check the error for the column, and whether a migration is missing \
+                  (`cli migrations plan`).",
+};
+
+pub static DB_ACCOUNT_QUARANTINED: ErrorCode = ErrorCode {
+    id: "E0303_DB_ACCOUNT_QUARANTINED",
+    message: "an account's updates are skipped after repeated failed writes",
+    explanation: "The account's data failed to write --quarantine-after times in a row (E0302), \
+                  so its updates are skipped until its data changes on-chain.\n\n\
+                  See the last error with `cli quarantine list`. Once the cause is fixed, \
+                  `cli quarantine release <ACCOUNT>` lets the listener write it again.",
+};
+
+pub static DB_QUOTA_EXCEEDED: ErrorCode = ErrorCode {
+    id: "E0304_DB_QUOTA_EXCEEDED",
+    message: "a program went over a table's storage quota",
+    explanation: "The rows a program has in a table, or their estimated size on disk, went over \
+                  --quota-rows or --quota-bytes. Depending on --quota-action the listener only \
+                  warns, stops writing that table's updates for the program until it's back \
+                  under quota, or deletes the data of the program's polls that ended first.\n\n\
+                  Check the program's usage on `/health` (`storage_quotas`). If the growth is \
+                  legitimate, raise the quota; if the program is spamming, stop indexing it.",
+};
+
+pub static INVALID_PROGRAM_ID: ErrorCode = ErrorCode {
+    id: "E0401_INVALID_PROGRAM_ID",
+    message: "not a valid base58 program ID",
+    explanation: "Program IDs are base58 public keys, e.g. \
+                  HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh.\n\n\
+                  Check for a truncated or mistyped ID.",
+};
+
+pub static POLL_NOT_FOUND: ErrorCode = ErrorCode {
+    id: "E0402_POLL_NOT_FOUND",
+    message: "no such poll in the index",
+    explanation: "No indexed poll has this id (for this program). The poll may not exist, \
+                  belong to another program, or not have been picked up yet.\n\n\
+                  List what is indexed with `cli list-polls`. A poll created before the listener \
+                  started is picked up by the startup backfill.",
+};
+
+pub static POLL_AMBIGUOUS: ErrorCode = ErrorCode {
+    id: "E0403_POLL_AMBIGUOUS",
+    message: "the poll id exists in several programs",
+    explanation: "Poll ids are only unique within a program, and several indexed programs have a \
+                  poll with this id.\n\n\
+                  Pick one with --program (CLI) or ?program= (API).",
+};
+
+pub static INVALID_PARAMETER: ErrorCode = ErrorCode {
+    id: "E0404_INVALID_PARAMETER",
+    message: "a request parameter is invalid",
+    explanation: "A parameter is missing, empty, out of range or not one of the accepted values. \
+                  The message names it and what is expected.",
+};
+
+pub static IDL_NOT_FOUND: ErrorCode = ErrorCode {
+    id: "E0405_IDL_NOT_FOUND",
+    message: "no IDL loaded for the program",
+    explanation: "IDLs are stored when the listener is started with --idl.\n\n\
+                  Start the listener once with `--idl <FILE>` for this program.",
+};
+
+pub static PROFILING_UNAVAILABLE: ErrorCode = ErrorCode {
+    id: "E0406_PROFILING_UNAVAILABLE",
+    message: "profiling is disabled or busy",
+    explanation: "`/debug/pprof/profile` only answers when the listener runs with \
+                  --enable-profiling, and takes one profile at a time.\n\n\
+                  Enable it (see E0102), or wait for the running profile to finish.",
+};
+
+pub static READ_ONLY_REFUSED: ErrorCode = ErrorCode {
+    id: "E0407_READ_ONLY_REFUSED",
+    message: "the command writes and --read-only is set",
+    explanation: "With --read-only (or READ_ONLY), the CLI refuses every command that writes to \
+                  the database or sends a transaction, and opens its connections read-only.\n\n\
+                  Run the command without --read-only, against a writable database.",
+};
+
+pub static NOT_FOUND: ErrorCode = ErrorCode {
+    id: "E0408_NOT_FOUND",
+    message: "no such record",
+    explanation: "The annotation, job or quarantined account named doesn't exist.\n\n\
+                  List them with `cli annotations list --all`, `cli jobs list` or \
+                  `cli quarantine list`.",
+};
+
+pub static LIVE_UNAVAILABLE: ErrorCode = ErrorCode {
+    id: "E0409_LIVE_UNAVAILABLE",
+    message: "live updates are unavailable",
+    explanation: "`/live` is a websocket: it answers 400 to requests that don't ask to upgrade \
+                  the connection, and 503 once the listener has stopped ingesting.\n\n\
+                  Connect with a websocket client (`ws://host:port/live`), to a running \
+                  listener.",
+};
+
+pub static INTERNAL: ErrorCode = ErrorCode {
+    id: "E0901_INTERNAL",
+    message: "internal error",
+    explanation: "An unexpected failure. The details are in the logs (the API never returns \
+                  them).\n\n\
+                  Look for the error around the same time in the listener's logs, and report it \
+                  with them.",
+};
+
+/// Every code, in order. Add new codes at the end of their group; never remove one.
+pub static CATALOG: &[&ErrorCode] = &[
+    &CONFIG_READ_ONLY_WRITER,
+    &CONFIG_PROFILING_UNAVAILABLE,
+    &CONFIG_IDL_PROGRAM_MISMATCH,
+    &CONFIG_WARMUP_FAILED,
+    &CONFIG_PROGRAM_NOT_FOUND,
+    &CONFIG_FILE_INVALID,
+    &DECODE_TRUNCATED,
+    &DECODE_INVALID_UTF8,
+    &DECODE_STRING_TOO_LONG,
+    &DECODE_INVALID_OPTION_TAG,
+    &DB_UNAVAILABLE,
+    &DB_DATA_REJECTED,
+    &DB_ACCOUNT_QUARANTINED,
+    &DB_QUOTA_EXCEEDED,
+    &INVALID_PROGRAM_ID,
+    &POLL_NOT_FOUND,
+    &POLL_AMBIGUOUS,
+    &INVALID_PARAMETER,
+    &IDL_NOT_FOUND,
+    &PROFILING_UNAVAILABLE,
+    &READ_ONLY_REFUSED,
+    &NOT_FOUND,
+    &LIVE_UNAVAILABLE,
+    &INTERNAL,
+];
+
+/// Looks a code up by its full id or its number, case-insensitively (`E0203`, `e0203`,
+/// `E0203_DECODE_STRING_TOO_LONG`).
+pub fn find(code: &str) -> Option<&'static ErrorCode> {
+    CATALOG.iter().copied().find(|entry| {
+        entry.id.eq_ignore_ascii_case(code) || entry.short().eq_ignore_ascii_case(code)
+    })
+}
+
+/// An error with a catalog code, for failures that aren't one of the typed errors below.
+/// Displayed as `<id>: <message>`.
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: &'static ErrorCode,
+    pub message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// `message` as an `anyhow::Error` carrying `code`.
+pub fn coded(code: &'static ErrorCode, message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.into(),
+    }
+    .into()
+}
+
+impl DecodeError {
+    pub fn code(&self) -> &'static ErrorCode {
+        match self {
+            DecodeError::Truncated { .. } => &DECODE_TRUNCATED,
+            DecodeError::StringTooLong { .. } => &DECODE_STRING_TOO_LONG,
+            DecodeError::InvalidUtf8 { .. } => &DECODE_INVALID_UTF8,
+            DecodeError::InvalidOptionTag { .. } => &DECODE_INVALID_OPTION_TAG,
+        }
+    }
+}
+
+/// The catalog code of `error`, from the first error in its chain that has one: a
+/// [`CodedError`], a [`DecodeError`], or a database error (see [`is_data_error`]).
+/// `None` when nothing in the chain is known.
+pub fn classify(error: &anyhow::Error) -> Option<&'static ErrorCode> {
+    if is_data_error(error) {
+        return Some(&DB_DATA_REJECTED);
+    }
+    error.chain().find_map(|cause| {
+        if let Some(coded) = cause.downcast_ref::<CodedError>() {
+            Some(coded.code)
+        } else if let Some(decode) = cause.downcast_ref::<DecodeError>() {
+            Some(decode.code())
+        } else if cause.is::<diesel::r2d2::PoolError>() || cause.is::<diesel::ConnectionError>() {
+            Some(&DB_UNAVAILABLE)
+        } else {
+            // Data errors were caught above, so the remaining database errors are the
+            // connection's or the server's.
+            match cause.downcast_ref::<DieselError>() {
+                Some(DieselError::DatabaseError(..) | DieselError::BrokenTransactionManager) => {
+                    Some(&DB_UNAVAILABLE)
+                }
+                _ => None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use anyhow::Context;
+    use diesel::result::DatabaseErrorKind;
+
+    use super::*;
+
+    fn database_error(kind: DatabaseErrorKind) -> anyhow::Error {
+        DieselError::DatabaseError(kind, Box::new("boom".to_string())).into()
+    }
+
+    #[test]
+    fn every_code_is_in_the_catalog() {
+        let declared = include_str!("errors.rs")
+            .lines()
+            .filter(|line| {
+                line.starts_with("pub static ") && line.ends_with(": ErrorCode = ErrorCode {")
+            })
+            .count();
+        assert_eq!(CATALOG.len(), declared);
+    }
+
+    #[test]
+    fn codes_are_unique_well_formed_and_in_order() {
+        let mut ids = HashSet::new();
+        let mut shorts = HashSet::new();
+        for code in CATALOG {
+            assert!(ids.insert(code.id), "{} is listed twice", code.id);
+            assert!(shorts.insert(code.short()), "{} reuses its number", code.id);
+
+            let (number, name) = code.id.split_once('_').unwrap();
+            assert!(number.len() == 5 && number.starts_with('E'), "{}", code.id);
+            assert!(
+                number[1..].chars().all(|c| c.is_ascii_digit()),
+                "{}",
+                code.id
+            );
+            assert!(
+                name.chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'),
+                "{}",
+                code.id
+            );
+            let group = match &number[..3] {
+                "E01" => Some("CONFIG_"),
+                "E02" => Some("DECODE_"),
+                "E03" => Some("DB_"),
+                _ => None,
+            };
+            assert!(
+                group.is_none_or(|prefix| name.starts_with(prefix)),
+                "{}",
+                code.id
+            );
+
+            assert!(
+                !code.message.is_empty() && !code.message.contains('\n'),
+                "{}",
+                code.id
+            );
+            assert!(!code.explanation.is_empty(), "{}", code.id);
+        }
+        assert!(CATALOG.windows(2).all(|pair| pair[0].id < pair[1].id));
+    }
+
+    #[test]
+    fn codes_are_found_by_id_or_number() {
+        assert_eq!(find("E0203"), Some(&DECODE_STRING_TOO_LONG));
+        assert_eq!(find("e0203"), Some(&DECODE_STRING_TOO_LONG));
+        assert_eq!(
+            find("E0203_DECODE_STRING_TOO_LONG"),
+            Some(&DECODE_STRING_TOO_LONG)
+        );
+        assert_eq!(find("E0299"), None);
+        assert_eq!(find("DECODE_STRING_TOO_LONG"), None);
+    }
+
+    #[test]
+    fn errors_are_classified_through_their_chain() {
+        let coded_error = coded(&POLL_NOT_FOUND, "no poll 3");
+        assert_eq!(coded_error.to_string(), "E0402_POLL_NOT_FOUND: no poll 3");
+        assert_eq!(classify(&coded_error), Some(&POLL_NOT_FOUND));
+
+        let wrapped = Err::<(), _>(coded_error)
+            .context("Failed to show the poll")
+            .unwrap_err();
+        assert_eq!(classify(&wrapped), Some(&POLL_NOT_FOUND));
+
+        let decode = anyhow::Error::from(DecodeError::Truncated { needed: 8, got: 3 })
+            .context("Failed to decode poll");
+        assert_eq!(classify(&decode), Some(&DECODE_TRUNCATED));
+
+        assert_eq!(
+            classify(&database_error(DatabaseErrorKind::UniqueViolation)),
+            Some(&DB_DATA_REJECTED)
+        );
+        assert_eq!(
+            classify(&database_error(DatabaseErrorKind::ClosedConnection)),
+            Some(&DB_UNAVAILABLE)
+        );
+        let unreachable = diesel::ConnectionError::BadConnection("refused".to_string());
+        assert_eq!(
+            classify(&anyhow::Error::from(unreachable)),
+            Some(&DB_UNAVAILABLE)
+        );
+
+        assert_eq!(classify(&DieselError::NotFound.into()), None);
+        assert_eq!(classify(&anyhow::anyhow!("something else")), None);
+    }
+}
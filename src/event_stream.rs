@@ -0,0 +1,148 @@
+use std::pin::Pin;
+use std::task::{Context, Poll as TaskPoll};
+
+use futures::Stream;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc;
+
+use crate::state::delegation::Delegation;
+use crate::state::pool::Poll;
+
+/// Events buffered between the listener and an [`EventStream`]. Once it's full the listener
+/// waits for the consumer, like it waits for the database writer.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// A decoded account update, as yielded by [`Listener::events`](crate::listener::Listener::events).
+///
+/// Candidates and votes have no struct of their own: `fields` holds them as decoded from the
+/// program's IDL (see `ListenerBuilder::idl_decoders`), or is `None` without one. `data` is
+/// always the raw account data, discriminator included.
+#[derive(Debug, Clone)]
+pub enum VotingEvent {
+    PollUpdated {
+        program_id: Pubkey,
+        account: Pubkey,
+        slot: u64,
+        poll: Poll,
+    },
+    CandidateUpdated {
+        program_id: Pubkey,
+        account: Pubkey,
+        slot: u64,
+        data: Vec<u8>,
+        fields: Option<Value>,
+    },
+    VoteCast {
+        program_id: Pubkey,
+        account: Pubkey,
+        slot: u64,
+        data: Vec<u8>,
+        fields: Option<Value>,
+    },
+    /// Program v3 only: a wallet delegating its vote on a poll.
+    DelegationUpdated {
+        program_id: Pubkey,
+        account: Pubkey,
+        slot: u64,
+        delegation: Delegation,
+    },
+    /// An account whose discriminator isn't a voting account's. `fields` is set when the
+    /// program's IDL describes it.
+    Unknown {
+        program_id: Pubkey,
+        account: Pubkey,
+        slot: u64,
+        data: Vec<u8>,
+        fields: Option<Value>,
+    },
+}
+
+impl VotingEvent {
+    /// The program the account belongs to.
+    pub fn program_id(&self) -> &Pubkey {
+        match self {
+            VotingEvent::PollUpdated { program_id, .. }
+            | VotingEvent::CandidateUpdated { program_id, .. }
+            | VotingEvent::VoteCast { program_id, .. }
+            | VotingEvent::DelegationUpdated { program_id, .. }
+            | VotingEvent::Unknown { program_id, .. } => program_id,
+        }
+    }
+
+    /// The slot at which the RPC node observed the account state.
+    pub fn slot(&self) -> u64 {
+        match self {
+            VotingEvent::PollUpdated { slot, .. }
+            | VotingEvent::CandidateUpdated { slot, .. }
+            | VotingEvent::VoteCast { slot, .. }
+            | VotingEvent::DelegationUpdated { slot, .. }
+            | VotingEvent::Unknown { slot, .. } => *slot,
+        }
+    }
+}
+
+/// The decoded updates of a [`Listener`](crate::listener::Listener), in the order it processed
+/// them. Ends once the listener stopped and every event was read.
+pub struct EventStream(mpsc::Receiver<VotingEvent>);
+
+impl EventStream {
+    /// A stream and the sender the listener feeds it through.
+    pub(crate) fn channel() -> (mpsc::Sender<VotingEvent>, Self) {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        (sender, Self(receiver))
+    }
+}
+
+impl Stream for EventStream {
+    type Item = VotingEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn unknown(slot: u64) -> VotingEvent {
+        VotingEvent::Unknown {
+            program_id: Pubkey::new_from_array([7; 32]),
+            account: Pubkey::new_unique(),
+            slot,
+            data: vec![9; 8],
+            fields: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_yielded_in_order_until_the_sender_is_gone() {
+        let (sender, stream) = EventStream::channel();
+        for slot in [3, 1, 2] {
+            sender.send(unknown(slot)).await.unwrap();
+        }
+        drop(sender);
+
+        let events: Vec<VotingEvent> = stream.collect().await;
+        let slots: Vec<u64> = events.iter().map(VotingEvent::slot).collect();
+        assert_eq!(slots, [3, 1, 2]);
+        assert!(events
+            .iter()
+            .all(|event| *event.program_id() == Pubkey::new_from_array([7; 32])));
+    }
+
+    #[tokio::test]
+    async fn a_full_stream_holds_the_sender_back() {
+        let (sender, mut stream) = EventStream::channel();
+        for slot in 0..EVENT_BUFFER as u64 {
+            sender.try_send(unknown(slot)).unwrap();
+        }
+        assert!(sender.try_send(unknown(0)).is_err());
+
+        assert_eq!(stream.next().await.unwrap().slot(), 0);
+        sender.try_send(unknown(0)).unwrap();
+    }
+}
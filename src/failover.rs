@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use voting_dapp_api_types::WebsocketEndpointHealth;
+
+use crate::bandwidth;
+use crate::clock::Instant;
+
+/// Default time an endpoint is skipped after it failed.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+/// Failures older than this no longer count against an endpoint.
+const FAILURE_MEMORY: Duration = Duration::from_secs(10 * 60);
+/// The cooldown doubles with each failure in a row, up to this many times the base one.
+const MAX_COOLDOWN_FACTOR: u32 = 8;
+
+/// The websocket endpoints the listener subscribes through: the primary one, then the
+/// fallbacks, with the health of each.
+///
+/// An endpoint that disconnects, or that can't be subscribed through, is skipped for a cooldown,
+/// which doubles with each failure in a row. The listener then fails over to the healthiest
+/// endpoint out of cooldown: the one with the fewest failures in the last 10 minutes, the first
+/// configured on a tie. When every endpoint is cooling down there's nowhere left to go.
+#[derive(Debug)]
+pub struct Endpoints {
+    endpoints: Vec<Endpoint>,
+    current: usize,
+    cooldown: Duration,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    /// When it failed, oldest first, within `FAILURE_MEMORY`.
+    failures: VecDeque<Instant>,
+    /// Failures since it was last subscribed through.
+    consecutive: u32,
+    cooling_until: Option<Instant>,
+}
+
+impl Endpoints {
+    /// `urls` in order of preference; duplicates are ignored. Panics when it's empty.
+    pub fn new(urls: impl IntoIterator<Item = String>, cooldown: Duration) -> Self {
+        let mut endpoints: Vec<Endpoint> = Vec::new();
+        for url in urls {
+            if endpoints.iter().all(|endpoint| endpoint.url != url) {
+                endpoints.push(Endpoint {
+                    url,
+                    failures: VecDeque::new(),
+                    consecutive: 0,
+                    cooling_until: None,
+                });
+            }
+        }
+        assert!(!endpoints.is_empty(), "at least one websocket endpoint");
+        Self {
+            endpoints,
+            current: 0,
+            cooldown,
+        }
+    }
+
+    /// The URL of the endpoint in use (the primary one until the first failover).
+    pub fn current(&self) -> &str {
+        &self.endpoints[self.current].url
+    }
+
+    pub fn url(&self, index: usize) -> &str {
+        &self.endpoints[index].url
+    }
+
+    /// The endpoints worth trying at `now`, healthiest first. Those cooling down are left out.
+    pub fn candidates(&self, now: Instant) -> Vec<usize> {
+        let mut candidates: Vec<usize> = (0..self.endpoints.len())
+            .filter(|index| !self.endpoints[*index].is_cooling(now))
+            .collect();
+        // Stable: configured order on a tie.
+        candidates.sort_by_key(|index| self.endpoints[*index].recent_failures(now));
+        candidates
+    }
+
+    /// Records that the endpoint failed at `now`, which starts its cooldown.
+    pub fn failed(&mut self, index: usize, now: Instant) {
+        let cooldown = self.cooldown;
+        let endpoint = &mut self.endpoints[index];
+        endpoint.forget_failures(now);
+        endpoint.failures.push_back(now);
+        endpoint.consecutive = endpoint.consecutive.saturating_add(1);
+        let factor = 2u32
+            .saturating_pow(endpoint.consecutive - 1)
+            .min(MAX_COOLDOWN_FACTOR);
+        endpoint.cooling_until = Some(now + cooldown * factor);
+    }
+
+    /// Whether there's more than one endpoint to choose from.
+    pub fn has_fallbacks(&self) -> bool {
+        self.endpoints.len() > 1
+    }
+
+    /// Records that the endpoint in use failed at `now`, see [`Endpoints::failed`].
+    pub fn failed_current(&mut self, now: Instant) {
+        self.failed(self.current, now);
+    }
+
+    /// Records that the endpoint was subscribed through, and makes it the current one.
+    pub fn connected(&mut self, index: usize) {
+        let endpoint = &mut self.endpoints[index];
+        endpoint.consecutive = 0;
+        endpoint.cooling_until = None;
+        self.current = index;
+    }
+
+    /// The health of every endpoint at `now` (`unix_now` in wall time), as reported by
+    /// `GET /health`. URLs are labelled like in `bandwidth_usage`, without their query string.
+    pub fn report(&self, now: Instant, unix_now: i64) -> Vec<WebsocketEndpointHealth> {
+        self.endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| WebsocketEndpointHealth {
+                endpoint: bandwidth::endpoint_label(&endpoint.url),
+                current: index == self.current,
+                recent_failures: endpoint.recent_failures(now),
+                cooldown_until: endpoint
+                    .cooling_until
+                    .filter(|until| *until > now)
+                    .map(|until| unix_now + until.duration_since(now).as_secs() as i64),
+            })
+            .collect()
+    }
+}
+
+impl Endpoint {
+    fn is_cooling(&self, now: Instant) -> bool {
+        self.cooling_until.is_some_and(|until| now < until)
+    }
+
+    fn recent_failures(&self, now: Instant) -> usize {
+        self.failures
+            .iter()
+            .filter(|failed| now.saturating_duration_since(**failed) < FAILURE_MEMORY)
+            .count()
+    }
+
+    fn forget_failures(&mut self, now: Instant) {
+        while self
+            .failures
+            .front()
+            .is_some_and(|failed| now.saturating_duration_since(*failed) >= FAILURE_MEMORY)
+        {
+            self.failures.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::clock::Clock;
+
+    const COOLDOWN: Duration = Duration::from_secs(30);
+
+    fn endpoints() -> Endpoints {
+        Endpoints::new(
+            ["wss://a", "wss://b", "wss://a", "wss://c"].map(String::from),
+            COOLDOWN,
+        )
+    }
+
+    #[test]
+    fn keeps_the_configured_order_without_duplicates() {
+        let endpoints = endpoints();
+        assert_eq!(endpoints.current(), "wss://a");
+        assert!(endpoints.has_fallbacks());
+        let urls: Vec<&str> = endpoints
+            .candidates(Clock::system().instant())
+            .into_iter()
+            .map(|index| endpoints.url(index))
+            .collect();
+        assert_eq!(urls, ["wss://a", "wss://b", "wss://c"]);
+        assert!(!Endpoints::new(["wss://a".to_string()], COOLDOWN).has_fallbacks());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotates_past_failed_endpoints_until_their_cooldown_ends() {
+        let clock = Clock::system();
+        let mut endpoints = endpoints();
+
+        endpoints.failed_current(clock.instant());
+        assert_eq!(endpoints.candidates(clock.instant()), [1, 2]);
+        endpoints.connected(1);
+        assert_eq!(endpoints.current(), "wss://b");
+
+        endpoints.failed_current(clock.instant());
+        assert_eq!(endpoints.candidates(clock.instant()), [2]);
+        endpoints.failed(2, clock.instant());
+        // Everything is cooling down: nowhere left to go.
+        assert!(endpoints.candidates(clock.instant()).is_empty());
+
+        clock.sleep(COOLDOWN).await;
+        // Back, but each with a recent failure: configured order on the tie.
+        assert_eq!(endpoints.candidates(clock.instant()), [0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cooldown_doubles_with_failures_in_a_row_up_to_a_cap() {
+        let clock = Clock::system();
+        let mut endpoints = endpoints();
+        let cooling = |endpoints: &Endpoints, now| !endpoints.candidates(now).contains(&0);
+
+        for factor in [1, 2, 4, 8, 8] {
+            endpoints.failed(0, clock.instant());
+            clock
+                .sleep(COOLDOWN * factor - Duration::from_secs(1))
+                .await;
+            assert!(cooling(&endpoints, clock.instant()), "factor {}", factor);
+            clock.sleep(Duration::from_secs(1)).await;
+            assert!(!cooling(&endpoints, clock.instant()), "factor {}", factor);
+        }
+
+        // Subscribing through it resets the streak.
+        endpoints.connected(0);
+        endpoints.failed(0, clock.instant());
+        clock.sleep(COOLDOWN).await;
+        assert!(!cooling(&endpoints, clock.instant()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prefers_the_endpoint_with_the_fewest_recent_failures() {
+        let clock = Clock::system();
+        let mut endpoints = endpoints();
+        endpoints.failed(0, clock.instant());
+        endpoints.connected(0);
+        endpoints.failed(0, clock.instant());
+        endpoints.connected(0);
+        endpoints.failed(1, clock.instant());
+        endpoints.connected(1);
+        assert_eq!(endpoints.candidates(clock.instant()), [2, 1, 0]);
+
+        let report = endpoints.report(clock.instant(), clock.unix_now());
+        let failures: Vec<_> = report.iter().map(|e| e.recent_failures).collect();
+        assert_eq!(failures, [2, 1, 0]);
+        assert!(report[1].current && !report[0].current);
+        assert!(report.iter().all(|e| e.cooldown_until.is_none()));
+
+        // Failures are forgotten after 10 minutes.
+        clock.sleep(FAILURE_MEMORY).await;
+        assert_eq!(endpoints.candidates(clock.instant()), [0, 1, 2]);
+        assert!(endpoints
+            .report(clock.instant(), clock.unix_now())
+            .iter()
+            .all(|e| e.recent_failures == 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_when_a_cooldown_ends() {
+        let clock = Clock::starting_at(Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap());
+        let mut endpoints = endpoints();
+        endpoints.failed(2, clock.instant());
+        clock.sleep(Duration::from_secs(10)).await;
+        let report = endpoints.report(clock.instant(), clock.unix_now());
+        let until = report[2].cooldown_until.expect("cooling down");
+        assert_eq!(until, clock.unix_now() + 20);
+        assert_eq!(report[2].endpoint, bandwidth::endpoint_label("wss://c"));
+    }
+}
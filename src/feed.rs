@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use atom_syndication::{Category, Content, Entry, Feed, FixedDateTime, Generator, Person, Text};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::db::db::PollKey;
+use crate::db::models::{LifecycleTransition, Poll};
+use crate::state::lifecycle::PollLifecycle;
+
+/// Prefix of every id in the feeds. Ids never change, so feed readers don't show entries twice.
+const ID_PREFIX: &str = "urn:voting-dapp-listener";
+
+/// The poll events published in the Atom feeds, derived from lifecycle transitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedEvent {
+    /// The poll was first indexed.
+    Created,
+    /// Voting opened.
+    Started,
+    /// Voting closed, no winner declared yet.
+    Ended,
+    /// A winner was declared on-chain.
+    WinnerDeclared,
+}
+
+impl FeedEvent {
+    /// Lifecycle states whose transitions are published (besides first indexings).
+    pub const STATES: [&'static str; 3] = ["active", "ended", "finalized"];
+
+    /// The event a transition stands for, `None` for transitions that aren't published
+    /// (e.g. a poll getting its candidates, or its account being closed).
+    pub fn of(transition: &LifecycleTransition) -> Option<FeedEvent> {
+        if transition.from_state.is_none() {
+            return Some(FeedEvent::Created);
+        }
+        match transition.to_state.parse().ok()? {
+            PollLifecycle::Active => Some(FeedEvent::Started),
+            PollLifecycle::Ended => Some(FeedEvent::Ended),
+            PollLifecycle::Finalized => Some(FeedEvent::WinnerDeclared),
+            _ => None,
+        }
+    }
+
+    /// Category term of the entry.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FeedEvent::Created => "created",
+            FeedEvent::Started => "started",
+            FeedEvent::Ended => "ended",
+            FeedEvent::WinnerDeclared => "winner_declared",
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            FeedEvent::Created => "was created",
+            FeedEvent::Started => "started",
+            FeedEvent::Ended => "ended",
+            FeedEvent::WinnerDeclared => "has a winner",
+        }
+    }
+}
+
+/// Which feed is rendered: all polls, the polls of one program, or a single poll.
+#[derive(Debug, Clone, Copy)]
+pub struct FeedScope {
+    pub program: Option<Pubkey>,
+    pub poll_id: Option<i64>,
+}
+
+impl FeedScope {
+    fn id(&self) -> String {
+        match (self.program, self.poll_id) {
+            (None, None) => format!("{}:feed", ID_PREFIX),
+            (Some(program), None) => format!("{}:feed:{}", ID_PREFIX, program),
+            (None, Some(poll_id)) => format!("{}:feed:poll:{}", ID_PREFIX, poll_id),
+            (Some(program), Some(poll_id)) => {
+                format!("{}:feed:{}:poll:{}", ID_PREFIX, program, poll_id)
+            }
+        }
+    }
+
+    fn title(&self) -> String {
+        match (self.program, self.poll_id) {
+            (None, None) => "Poll activity".to_string(),
+            (Some(program), None) => format!("Poll activity of program {}", program),
+            (_, Some(poll_id)) => format!("Activity of poll {}", poll_id),
+        }
+    }
+}
+
+/// Renders an Atom feed of `transitions` (newest first), described with the polls they belong
+/// to. Transitions that aren't published are skipped.
+///
+/// Entry ids come from the transition ids and entry timestamps from when the transition was
+/// recorded, so the same transitions always render the same entries. The feed's `updated` is
+/// its newest entry, so the document only changes when an entry is added.
+pub fn render(
+    scope: FeedScope,
+    transitions: &[LifecycleTransition],
+    polls: &HashMap<PollKey, Poll>,
+) -> Result<String> {
+    let mut entries = Vec::with_capacity(transitions.len());
+    for transition in transitions {
+        let Some(event) = FeedEvent::of(transition) else {
+            continue;
+        };
+        let poll = polls.get(&(transition.program_id.clone(), transition.poll_id));
+        entries.push(entry(transition, event, poll)?);
+    }
+    let updated = entries
+        .first()
+        .map(|newest| newest.updated)
+        .unwrap_or_default();
+
+    let feed = Feed {
+        id: scope.id(),
+        title: Text::plain(scope.title()),
+        updated,
+        authors: vec![Person {
+            name: "voting-dapp-listener".to_string(),
+            ..Default::default()
+        }],
+        generator: Some(Generator {
+            value: "voting-dapp-listener".to_string(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            ..Default::default()
+        }),
+        entries,
+        ..Default::default()
+    };
+    Ok(feed.to_string())
+}
+
+fn entry(transition: &LifecycleTransition, event: FeedEvent, poll: Option<&Poll>) -> Result<Entry> {
+    let name = match poll {
+        Some(poll) if !poll.poll_name.is_empty() => format!("Poll \"{}\"", poll.poll_name),
+        _ => format!("Poll {}", transition.poll_id),
+    };
+    let mut details = vec![format!("Poll {} {}.", transition.poll_id, event.describe())];
+    if !transition.program_id.is_empty() {
+        let program = Pubkey::try_from(transition.program_id.as_slice())
+            .map_err(|_| anyhow::anyhow!("corrupt program_id of transition {}", transition.id))?;
+        details.push(format!("Program: {}.", program));
+    }
+    if let Some(poll) = poll {
+        match event {
+            FeedEvent::Created if !poll.poll_description.is_empty() => {
+                details.push(poll.poll_description.clone())
+            }
+            FeedEvent::WinnerDeclared => {
+                details.push(format!("Winner: {}.", poll.winner_pubkey()?))
+            }
+            _ => {}
+        }
+    }
+
+    let occurred_at = timestamp(transition.occurred_at);
+    Ok(Entry {
+        id: format!("{}:lifecycle-transition:{}", ID_PREFIX, transition.id),
+        title: Text::plain(format!("{} {}", name, event.describe())),
+        updated: occurred_at,
+        published: Some(occurred_at),
+        categories: vec![Category {
+            term: event.as_str().to_string(),
+            ..Default::default()
+        }],
+        content: Some(Content {
+            value: Some(details.join(" ")),
+            content_type: Some("text".to_string()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn timestamp(at: DateTime<Utc>) -> FixedDateTime {
+    at.fixed_offset()
+}
+
+/// Strong `ETag` of a rendered feed.
+pub fn etag(body: &str) -> String {
+    let digest = Sha256::digest(body.as_bytes());
+    let hex: String = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("\"{}\"", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::db::test_support::{new_poll, poll_row};
+
+    const PROGRAM: [u8; 32] = [7; 32];
+
+    fn transition(id: i32, poll_id: i64, from: Option<&str>, to: &str) -> LifecycleTransition {
+        LifecycleTransition {
+            id,
+            program_id: PROGRAM.to_vec(),
+            poll_id,
+            from_state: from.map(str::to_string),
+            to_state: to.to_string(),
+            occurred_at: Utc.timestamp_opt(1_700_000_000 + i64::from(id), 0).unwrap(),
+        }
+    }
+
+    fn polls(polls: &[Poll]) -> HashMap<PollKey, Poll> {
+        polls
+            .iter()
+            .map(|poll| ((poll.program_id.clone(), poll.poll_id), poll.clone()))
+            .collect()
+    }
+
+    fn scope() -> FeedScope {
+        FeedScope {
+            program: Some(Pubkey::new_from_array(PROGRAM)),
+            poll_id: None,
+        }
+    }
+
+    #[test]
+    fn only_published_transitions_are_events() {
+        let events: Vec<_> = [
+            transition(1, 1, None, "upcoming"),
+            transition(2, 1, Some("draft"), "upcoming"),
+            transition(3, 1, Some("upcoming"), "active"),
+            transition(4, 1, Some("active"), "ended"),
+            transition(5, 1, Some("ended"), "finalized"),
+            transition(6, 1, Some("finalized"), "closed"),
+            transition(7, 1, Some("ended"), "not-a-state"),
+        ]
+        .iter()
+        .map(FeedEvent::of)
+        .collect();
+
+        assert_eq!(
+            events,
+            [
+                Some(FeedEvent::Created),
+                None,
+                Some(FeedEvent::Started),
+                Some(FeedEvent::Ended),
+                Some(FeedEvent::WinnerDeclared),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn rendered_feeds_parse_as_atom() {
+        let mut finalized = poll_row(&new_poll(&PROGRAM, 2, 10));
+        finalized.candidate_winner = vec![9; 32];
+        let transitions = [
+            transition(3, 2, Some("ended"), "finalized"),
+            transition(2, 1, None, "upcoming"),
+            transition(1, 2, Some("draft"), "upcoming"),
+        ];
+        let body = render(
+            scope(),
+            &transitions,
+            &polls(&[poll_row(&new_poll(&PROGRAM, 1, 10)), finalized]),
+        )
+        .unwrap();
+
+        let feed = Feed::from_str(&body).unwrap();
+        assert_eq!(feed.id, scope().id());
+        assert_eq!(feed.updated, feed.entries[0].updated);
+        let entries: Vec<_> = feed
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.title.value.as_str(),
+                    entry.categories[0].term.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("Poll \"Poll 2\" has a winner", "winner_declared"),
+                ("Poll \"Poll 1\" was created", "created"),
+            ]
+        );
+        let winner = feed.entries[0].content.as_ref().unwrap().value.as_ref();
+        assert!(winner
+            .unwrap()
+            .contains(&Pubkey::new_from_array([9; 32]).to_string()));
+    }
+
+    #[test]
+    fn entries_keep_their_ids_as_the_feed_grows() {
+        let older = [transition(1, 1, None, "upcoming")];
+        let newer = [
+            transition(2, 1, Some("upcoming"), "active"),
+            transition(1, 1, None, "upcoming"),
+        ];
+        let polls = polls(&[poll_row(&new_poll(&PROGRAM, 1, 10))]);
+
+        let before = Feed::from_str(&render(scope(), &older, &polls).unwrap()).unwrap();
+        let after = Feed::from_str(&render(scope(), &newer, &polls).unwrap()).unwrap();
+        assert_eq!(after.entries[1], before.entries[0]);
+        assert_ne!(after.entries[0].id, after.entries[1].id);
+        assert!(after.updated > before.updated);
+    }
+
+    #[test]
+    fn polls_missing_from_the_index_are_named_by_id() {
+        let body = render(
+            scope(),
+            &[transition(1, 5, None, "upcoming")],
+            &HashMap::new(),
+        )
+        .unwrap();
+
+        let feed = Feed::from_str(&body).unwrap();
+        assert_eq!(feed.entries[0].title.value, "Poll 5 was created");
+    }
+
+    #[test]
+    fn etags_follow_the_body() {
+        let polls = polls(&[poll_row(&new_poll(&PROGRAM, 1, 10))]);
+        let first = render(scope(), &[transition(1, 1, None, "upcoming")], &polls).unwrap();
+        let again = render(scope(), &[transition(1, 1, None, "upcoming")], &polls).unwrap();
+        let grown = render(
+            scope(),
+            &[
+                transition(2, 1, Some("upcoming"), "active"),
+                transition(1, 1, None, "upcoming"),
+            ],
+            &polls,
+        )
+        .unwrap();
+
+        assert_eq!(first, again);
+        assert_eq!(etag(&first), etag(&again));
+        assert_ne!(etag(&first), etag(&grown));
+        assert!(etag(&first).starts_with('"') && etag(&first).ends_with('"'));
+        assert_eq!(etag(&first).len(), 34);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use tracing::warn;
+
+use crate::clock::Clock;
+
+/// Most accounts a `getMultipleAccounts` call may ask for on common providers.
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+/// Calls in flight at once by default.
+pub const DEFAULT_PARALLELISM: usize = 4;
+
+/// Chunking and concurrency of [`fetch_accounts`].
+#[derive(Debug, Clone, Copy)]
+pub struct FetchConfig {
+    /// Accounts per `getMultipleAccounts` call; the provider's maximum.
+    pub batch_size: usize,
+    /// Calls in flight at once.
+    pub parallelism: usize,
+    /// Times a failed call is retried before its accounts are reported as failed.
+    pub retries: u32,
+    /// Wait before the first retry, doubled on each following one.
+    pub retry_delay: Duration,
+    /// Clock the retries wait on.
+    pub clock: Clock,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            parallelism: DEFAULT_PARALLELISM,
+            retries: 2,
+            retry_delay: Duration::from_millis(500),
+            clock: Clock::system(),
+        }
+    }
+}
+
+/// What [`fetch_accounts`] found for one pubkey.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchedAccount {
+    /// The account exists; `slot` is the context slot of the call that returned it.
+    Found { account: Account, slot: u64 },
+    /// The RPC answered that no such account exists (e.g. it was closed).
+    Missing { slot: u64 },
+    /// The call covering this account kept failing; nothing is known about it.
+    Failed(String),
+}
+
+/// Fetches a known list of accounts with `getMultipleAccounts`, instead of one call per account
+/// or a whole `getProgramAccounts` snapshot.
+///
+/// The pubkeys are deduplicated and split into chunks of `batch_size`, fetched at most
+/// `parallelism` at a time. A failing chunk is retried; if it still fails, only its accounts
+/// are reported as [`FetchedAccount::Failed`] and the other chunks' results are kept. Every
+/// requested pubkey has an entry in the result.
+pub async fn fetch_accounts(
+    rpc_client: &RpcClient,
+    pubkeys: &[Pubkey],
+    commitment: CommitmentConfig,
+    config: FetchConfig,
+) -> HashMap<Pubkey, FetchedAccount> {
+    let mut unique = pubkeys.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+
+    let chunks: Vec<Vec<Pubkey>> = unique
+        .chunks(config.batch_size.max(1))
+        .map(<[Pubkey]>::to_vec)
+        .collect();
+    stream::iter(chunks)
+        .map(|chunk| fetch_chunk(rpc_client, chunk, commitment, config))
+        .buffer_unordered(config.parallelism.max(1))
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+/// Fetches one chunk, retrying failed calls.
+async fn fetch_chunk(
+    rpc_client: &RpcClient,
+    chunk: Vec<Pubkey>,
+    commitment: CommitmentConfig,
+    config: FetchConfig,
+) -> Vec<(Pubkey, FetchedAccount)> {
+    let mut delay = config.retry_delay;
+    let mut attempt = 0;
+    let error = loop {
+        match rpc_client
+            .get_multiple_accounts_with_commitment(&chunk, commitment)
+            .await
+        {
+            Ok(response) => {
+                let slot = response.context.slot;
+                // The RPC answers in request order, one entry per pubkey.
+                return chunk
+                    .into_iter()
+                    .zip(response.value)
+                    .map(|(pubkey, account)| {
+                        let fetched = match account {
+                            Some(account) => FetchedAccount::Found { account, slot },
+                            None => FetchedAccount::Missing { slot },
+                        };
+                        (pubkey, fetched)
+                    })
+                    .collect();
+            }
+            Err(e) if attempt < config.retries => {
+                attempt += 1;
+                warn!(accounts = chunk.len(), attempt, error = %e, "getMultipleAccounts failed, retrying");
+                config.clock.sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => break e.to_string(),
+        }
+    };
+    chunk
+        .into_iter()
+        .map(|pubkey| (pubkey, FetchedAccount::Failed(error.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use base64::Engine;
+    use serde_json::{json, Value};
+    use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+    use solana_client::rpc_request::RpcRequest;
+    use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+    use solana_rpc_client::rpc_client::RpcClientConfig;
+
+    use super::*;
+
+    const SLOT: u64 = 77;
+
+    /// Answers `getMultipleAccounts` from a fixed set of existing accounts. Calls asking for a
+    /// pubkey of `failing` fail while it has failures left.
+    #[derive(Default)]
+    struct ScriptedRpc {
+        existing: HashSet<Pubkey>,
+        failing: Mutex<HashMap<Pubkey, u32>>,
+        /// Size of every call, failed ones included.
+        calls: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl RpcSender for ScriptedRpc {
+        async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+            assert_eq!(request, RpcRequest::GetMultipleAccounts);
+            let pubkeys: Vec<Pubkey> = params[0]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|pubkey| pubkey.as_str().unwrap().parse().unwrap())
+                .collect();
+            self.calls.lock().unwrap().push(pubkeys.len());
+
+            let mut failing = self.failing.lock().unwrap();
+            for pubkey in &pubkeys {
+                if let Some(left @ 1..) = failing.get_mut(pubkey) {
+                    *left -= 1;
+                    return Err(ClientError::from(ClientErrorKind::Custom(
+                        "429 Too Many Requests".to_string(),
+                    )));
+                }
+            }
+            let accounts: Vec<Value> = pubkeys
+                .iter()
+                .map(|pubkey| {
+                    if !self.existing.contains(pubkey) {
+                        return Value::Null;
+                    }
+                    json!({
+                        "lamports": 1,
+                        "data": [
+                            base64::engine::general_purpose::STANDARD.encode(pubkey.to_bytes()),
+                            "base64"
+                        ],
+                        "owner": Pubkey::default().to_string(),
+                        "executable": false,
+                        "rentEpoch": 0,
+                        "space": 32,
+                    })
+                })
+                .collect();
+            Ok(json!({ "context": { "slot": SLOT }, "value": accounts }))
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            "scripted".to_string()
+        }
+    }
+
+    /// Runs [`fetch_accounts`] over `rpc`; returns the result and the size of every call.
+    async fn fetch(
+        rpc: ScriptedRpc,
+        pubkeys: &[Pubkey],
+        config: FetchConfig,
+    ) -> (HashMap<Pubkey, FetchedAccount>, Vec<usize>) {
+        let calls = rpc.calls.clone();
+        let client = RpcClient::new_sender(
+            rpc,
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        );
+        let fetched = fetch_accounts(&client, pubkeys, CommitmentConfig::confirmed(), config).await;
+        let mut calls = calls.lock().unwrap().clone();
+        calls.sort_unstable();
+        (fetched, calls)
+    }
+
+    fn pubkeys(count: usize) -> Vec<Pubkey> {
+        (0..count).map(|_| Pubkey::new_unique()).collect()
+    }
+
+    fn config(batch_size: usize) -> FetchConfig {
+        FetchConfig {
+            batch_size,
+            ..FetchConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn splits_into_chunks_of_the_batch_size() {
+        let pubkeys = pubkeys(250);
+        let rpc = ScriptedRpc {
+            existing: pubkeys.iter().copied().collect(),
+            ..ScriptedRpc::default()
+        };
+        // Duplicates are asked for once.
+        let requested: Vec<_> = pubkeys.iter().chain(&pubkeys[..10]).copied().collect();
+
+        let (fetched, calls) = fetch(rpc, &requested, config(100)).await;
+        assert_eq!(calls, [50, 100, 100]);
+        assert_eq!(fetched.len(), 250);
+        for pubkey in &pubkeys {
+            let Some(FetchedAccount::Found { account, slot }) = fetched.get(pubkey) else {
+                panic!("{pubkey} not found");
+            };
+            // Every account lands on its own pubkey.
+            assert_eq!((account.data.as_slice(), *slot), (pubkey.as_ref(), SLOT));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_chunk_exactly_the_batch_size_is_one_call() {
+        let (fetched, calls) = fetch(ScriptedRpc::default(), &pubkeys(100), config(100)).await;
+        assert_eq!(calls, [100]);
+        assert_eq!(fetched.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn closed_accounts_are_missing_not_failed() {
+        let pubkeys = pubkeys(3);
+        let rpc = ScriptedRpc {
+            existing: HashSet::from([pubkeys[0]]),
+            ..ScriptedRpc::default()
+        };
+        let (fetched, _) = fetch(rpc, &pubkeys, config(100)).await;
+        assert!(matches!(fetched[&pubkeys[0]], FetchedAccount::Found { .. }));
+        assert_eq!(fetched[&pubkeys[1]], FetchedAccount::Missing { slot: SLOT });
+        assert_eq!(fetched[&pubkeys[2]], FetchedAccount::Missing { slot: SLOT });
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_chunk_is_retried() {
+        let pubkeys = pubkeys(4);
+        let rpc = ScriptedRpc {
+            existing: pubkeys.iter().copied().collect(),
+            failing: Mutex::new(HashMap::from([(pubkeys[0], 2)])),
+            ..ScriptedRpc::default()
+        };
+        let (fetched, calls) = fetch(rpc, &pubkeys, config(2)).await;
+        // Two failures, then success, within the default two retries.
+        assert_eq!(calls, [2, 2, 2, 2]);
+        assert!(fetched
+            .values()
+            .all(|account| matches!(account, FetchedAccount::Found { .. })));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_chunk_failing_for_good_only_fails_its_accounts() {
+        let mut pubkeys = pubkeys(4);
+        pubkeys.sort_unstable();
+        let rpc = ScriptedRpc {
+            existing: pubkeys.iter().copied().collect(),
+            failing: Mutex::new(HashMap::from([(pubkeys[0], u32::MAX)])),
+            ..ScriptedRpc::default()
+        };
+        let (fetched, calls) = fetch(rpc, &pubkeys, config(2)).await;
+        assert_eq!(calls, [2, 2, 2, 2]);
+        for pubkey in &pubkeys[..2] {
+            assert!(
+                matches!(&fetched[pubkey], FetchedAccount::Failed(e) if e.contains("429")),
+                "{:?}",
+                fetched[pubkey]
+            );
+        }
+        for pubkey in &pubkeys[2..] {
+            assert!(matches!(fetched[pubkey], FetchedAccount::Found { .. }));
+        }
+    }
+}
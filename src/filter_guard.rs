@@ -0,0 +1,131 @@
+/// Default share of server-filtered messages whose discriminator is still checked.
+pub const DEFAULT_SAMPLE_RATE: f64 = 0.01;
+
+/// Spot-checks that the RPC provider honours the memcmp filters of `--only`.
+///
+/// Some providers accept the filters but ignore them and deliver every account of the program.
+/// Trusting the filter would then index e.g. a vote account as a poll. A sampled fraction of the
+/// filtered messages is therefore matched against its discriminator anyway; after the first
+/// mismatch the guard trips and every message is checked from then on.
+///
+/// The verdict belongs to an endpoint: [`FilterGuard::reset_for_endpoint`] starts over when the
+/// listener connects somewhere else, and keeps it when it reconnects to the same provider.
+#[derive(Debug)]
+pub struct FilterGuard {
+    /// Check one message out of this many; 0 never samples.
+    sample_every: u64,
+    seen: u64,
+    mismatches: u64,
+    endpoint: String,
+    tripped: bool,
+}
+
+impl FilterGuard {
+    /// `sample_rate` is the share (0-1) of messages checked while the filters are trusted.
+    pub fn new(sample_rate: f64, endpoint: &str) -> Self {
+        let sample_every = if sample_rate > 0.0 {
+            (1.0 / sample_rate.min(1.0)).round() as u64
+        } else {
+            0
+        };
+        Self {
+            sample_every,
+            seen: 0,
+            mismatches: 0,
+            endpoint: endpoint.to_string(),
+            tripped: false,
+        }
+    }
+
+    /// Whether the discriminator of the next filtered message must be checked.
+    pub fn should_verify(&mut self) -> bool {
+        if self.tripped {
+            return true;
+        }
+        self.seen += 1;
+        self.sample_every > 0 && self.seen.is_multiple_of(self.sample_every)
+    }
+
+    /// Records a message that didn't match its filter. Returns `true` when this trips the
+    /// guard, i.e. the first time for this endpoint.
+    pub fn record_mismatch(&mut self) -> bool {
+        self.mismatches += 1;
+        !std::mem::replace(&mut self.tripped, true)
+    }
+
+    /// Whether the filters were found ignored, so every message is checked client-side.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Mismatches seen since the last reset.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Forgets the verdict when (re)connecting to a different endpoint.
+    pub fn reset_for_endpoint(&mut self, endpoint: &str) {
+        if endpoint == self.endpoint {
+            return;
+        }
+        *self = Self {
+            sample_every: self.sample_every,
+            seen: 0,
+            mismatches: 0,
+            endpoint: endpoint.to_string(),
+            tripped: false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampled(guard: &mut FilterGuard, messages: usize) -> Vec<bool> {
+        (0..messages).map(|_| guard.should_verify()).collect()
+    }
+
+    #[test]
+    fn samples_one_message_in_n() {
+        let mut guard = FilterGuard::new(0.25, "a");
+        assert_eq!(
+            sampled(&mut guard, 8),
+            [false, false, false, true, false, false, false, true]
+        );
+
+        let mut never = FilterGuard::new(0.0, "a");
+        assert!(!sampled(&mut never, 1000).contains(&true));
+        let mut always = FilterGuard::new(2.0, "a");
+        assert!(!sampled(&mut always, 10).contains(&false));
+    }
+
+    #[test]
+    fn a_mismatch_checks_every_message_after_it() {
+        let mut guard = FilterGuard::new(0.0, "a");
+        assert!(guard.record_mismatch());
+        assert!(!guard.record_mismatch());
+        assert!(guard.is_tripped());
+        assert_eq!(guard.mismatches(), 2);
+        assert!(!sampled(&mut guard, 10).contains(&false));
+    }
+
+    #[test]
+    fn only_a_different_endpoint_resets_the_verdict() {
+        let mut guard = FilterGuard::new(0.5, "a");
+        guard.record_mismatch();
+
+        guard.reset_for_endpoint("a");
+        assert!(guard.is_tripped());
+
+        guard.reset_for_endpoint("b");
+        assert!(!guard.is_tripped());
+        assert_eq!((guard.mismatches(), guard.endpoint()), (0, "b"));
+        // Sampling starts over too.
+        assert_eq!(sampled(&mut guard, 2), [false, true]);
+    }
+}
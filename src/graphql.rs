@@ -0,0 +1,516 @@
+use std::future;
+use std::pin::pin;
+
+use async_graphql::http::{parse_query_string, WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{
+    Context, EmptyMutation, Error, ErrorExtensions, Json, Object, Result, Schema, SimpleObject,
+    Subscription,
+};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use futures::{SinkExt, Stream, StreamExt};
+use solana_sdk::pubkey::Pubkey;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::error;
+
+use crate::api::{upgrade_websocket, websocket_key, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::candidates::{self, Tally};
+use crate::db::db::{get_polls_by_id, list_idl_accounts, list_polls_page, PgPool};
+use crate::db::models::{IdlAccount, Poll};
+use crate::errors::{self, ErrorCode};
+use crate::live::{self, LiveUpdates};
+use voting_dapp_api_types::ErrorBody;
+
+/// Deepest query accepted; polls → candidates is 3 levels, this leaves room for fragments.
+const MAX_QUERY_DEPTH: usize = 8;
+
+/// The GraphQL schema over the indexed data, served on `/graphql` (see [`router`]).
+pub type ApiSchema = Schema<QueryRoot, EmptyMutation, SubscriptionRoot>;
+
+/// Builds the schema. Subscriptions stream what `live` streams to `/live`; without it they fail.
+pub fn schema(pool: PgPool, live: Option<LiveUpdates>) -> ApiSchema {
+    Schema::build(QueryRoot, EmptyMutation, SubscriptionRoot)
+        .data(pool)
+        .data(live)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// Routes of the GraphQL API:
+///
+/// - `POST /graphql`: a query as `{"query", "variables", "operationName"}`
+/// - `GET /graphql?query=&variables=`: the same in the query string
+/// - `GET /graphql` upgraded to a websocket: subscriptions, over the `graphql-transport-ws` or
+///   the older `graphql-ws` protocol
+pub fn router(schema: ApiSchema) -> Router {
+    Router::new()
+        .route("/graphql", get(get_handler).post(post_handler))
+        .with_state(schema)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Indexed polls ordered by poll id, one page at a time (50 by default, at most 500).
+    async fn polls(
+        &self,
+        ctx: &Context<'_>,
+        program: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<PollNode>> {
+        let program = program_filter(program.as_deref())?;
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let offset = offset.unwrap_or(0).max(0);
+        let pool = ctx.data_unchecked::<PgPool>().clone();
+        let polls =
+            blocking(move || list_polls_page(&pool, program.as_deref(), limit, offset)).await?;
+        Ok(polls.into_iter().map(PollNode).collect())
+    }
+
+    /// A poll, `null` when it isn't indexed. `program` is required when the same poll id is
+    /// indexed for several programs.
+    async fn poll(
+        &self,
+        ctx: &Context<'_>,
+        poll_id: i64,
+        program: Option<String>,
+    ) -> Result<Option<PollNode>> {
+        let program = program_filter(program.as_deref())?;
+        let pool = ctx.data_unchecked::<PgPool>().clone();
+        let mut found =
+            blocking(move || get_polls_by_id(&pool, poll_id, program.as_deref())).await?;
+        match found.len() {
+            0 => Ok(None),
+            1 => Ok(Some(PollNode(found.remove(0)))),
+            _ => Err(coded_error(
+                &errors::POLL_AMBIGUOUS,
+                format!(
+                    "poll {} is indexed for several programs, pass program",
+                    poll_id
+                ),
+            )),
+        }
+    }
+}
+
+/// An indexed poll. Its candidates and votes are read from the accounts decoded with the
+/// program's IDL, so programs started without `--idl` have none.
+pub struct PollNode(Poll);
+
+#[Object(name = "Poll")]
+impl PollNode {
+    async fn poll_id(&self) -> i64 {
+        self.0.poll_id
+    }
+
+    async fn poll_owner(&self) -> Result<String> {
+        Ok(self.0.owner_pubkey().map_err(internal)?.to_string())
+    }
+
+    async fn poll_name(&self) -> &str {
+        &self.0.poll_name
+    }
+
+    async fn poll_description(&self) -> &str {
+        &self.0.poll_description
+    }
+
+    async fn poll_start(&self) -> i64 {
+        self.0.poll_start
+    }
+
+    async fn poll_end(&self) -> i64 {
+        self.0.poll_end
+    }
+
+    async fn candidate_amount(&self) -> i64 {
+        self.0.candidate_amount
+    }
+
+    /// The declared winner (`11111111111111111111111111111111` until the poll is finalized).
+    async fn candidate_winner(&self) -> Result<String> {
+        Ok(self.0.winner_pubkey().map_err(internal)?.to_string())
+    }
+
+    /// `draft`, `upcoming`, `active`, `ended`, `finalized` or `closed`.
+    async fn lifecycle(&self) -> &str {
+        &self.0.lifecycle
+    }
+
+    async fn last_slot(&self) -> i64 {
+        self.0.last_slot
+    }
+
+    /// `null` for polls indexed before program IDs were tracked.
+    async fn program_id(&self) -> Result<Option<String>> {
+        Ok(self
+            .0
+            .program_pubkey()
+            .map_err(internal)?
+            .map(|p| p.to_string()))
+    }
+
+    /// The poll account; `null` for polls indexed before it was tracked.
+    async fn account(&self) -> Result<Option<String>> {
+        Ok(self
+            .0
+            .account_address()
+            .map_err(internal)?
+            .map(|p| p.to_string()))
+    }
+
+    /// The poll's candidates, most votes first.
+    async fn candidates(&self, ctx: &Context<'_>) -> Result<Vec<CandidateNode>> {
+        let accounts = self.accounts(ctx, candidates::CANDIDATE_ACCOUNT).await?;
+        let tally = Tally::new(candidates::poll_candidates(&accounts, &self.0));
+        Ok(tally
+            .candidates
+            .iter()
+            .map(|c| CandidateNode {
+                name: c.name.clone(),
+                votes: c.votes,
+                percentage: tally.percentage(c),
+                account: c.account.to_string(),
+                last_slot: c.last_slot,
+            })
+            .collect())
+    }
+
+    /// The vote accounts naming this poll (votes that don't name theirs can't be attributed).
+    async fn votes(&self, ctx: &Context<'_>) -> Result<Vec<VoteNode>> {
+        let accounts = self.accounts(ctx, candidates::VOTE_ACCOUNT).await?;
+        candidates::poll_votes(&accounts, &self.0)
+            .into_iter()
+            .map(|vote| {
+                Ok(VoteNode {
+                    account: Pubkey::try_from(vote.account_pubkey.as_slice())
+                        .map_err(|e| internal(e.into()))?
+                        .to_string(),
+                    data: Json(vote.data.clone()),
+                    last_slot: vote.last_slot,
+                })
+            })
+            .collect()
+    }
+}
+
+impl PollNode {
+    /// The IDL-decoded accounts of type `account_type` of the poll's program.
+    async fn accounts(&self, ctx: &Context<'_>, account_type: &str) -> Result<Vec<IdlAccount>> {
+        let pool = ctx.data_unchecked::<PgPool>().clone();
+        let program_id = self.0.program_id.clone();
+        let account_type = account_type.to_string();
+        blocking(move || list_idl_accounts(&pool, &program_id, &account_type)).await
+    }
+}
+
+/// A candidate of a poll.
+#[derive(SimpleObject)]
+#[graphql(name = "Candidate")]
+pub struct CandidateNode {
+    name: String,
+    votes: u64,
+    /// Share of the poll's votes, in percent (0 before the first vote).
+    percentage: f64,
+    /// The candidate account.
+    account: String,
+    last_slot: i64,
+}
+
+/// A vote account, with its fields as the program's IDL describes them.
+#[derive(SimpleObject)]
+#[graphql(name = "Vote")]
+pub struct VoteNode {
+    account: String,
+    data: Json<serde_json::Value>,
+    last_slot: i64,
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// What the listener ingests, as it's ingested: the messages of `/live`.
+    async fn updates(
+        &self,
+        ctx: &Context<'_>,
+        program: Option<String>,
+    ) -> Result<impl Stream<Item = LiveEvent>> {
+        let program = program_filter(program.as_deref())?;
+        let receiver = ctx
+            .data_unchecked::<Option<LiveUpdates>>()
+            .as_ref()
+            .and_then(LiveUpdates::subscribe)
+            .ok_or_else(|| coded_error(&errors::LIVE_UNAVAILABLE, "the listener has stopped"))?;
+        Ok(live::updates(receiver, program).map(|json| {
+            let update: serde_json::Value =
+                serde_json::from_str(&json).expect("live updates are JSON");
+            LiveEvent {
+                kind: update["type"].as_str().unwrap_or_default().to_string(),
+                update: Json(update),
+            }
+        }))
+    }
+}
+
+/// An update of the `updates` subscription.
+#[derive(SimpleObject)]
+pub struct LiveEvent {
+    /// `poll`, `delegation`, `account` or `lagged`.
+    #[graphql(name = "type")]
+    kind: String,
+    /// The whole update, as `/live` sends it.
+    update: Json<serde_json::Value>,
+}
+
+async fn post_handler(
+    State(schema): State<ApiSchema>,
+    axum::Json(request): axum::Json<async_graphql::Request>,
+) -> axum::Json<async_graphql::Response> {
+    axum::Json(schema.execute(request).await)
+}
+
+async fn get_handler(State(schema): State<ApiSchema>, mut request: Request) -> Response {
+    let Some(key) = websocket_key(request.headers()) else {
+        let query = request.uri().query().unwrap_or_default();
+        return match parse_query_string(query) {
+            Ok(query) => axum::Json(schema.execute(query).await).into_response(),
+            Err(e) => bad_request(format!("invalid GraphQL request: {}", e)),
+        };
+    };
+    // The first protocol offered that we speak.
+    let protocol = request
+        .headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|protocol| protocol.trim().parse::<WebSocketProtocols>().ok());
+    let Some(protocol) = protocol else {
+        return bad_request(
+            "subscriptions need the graphql-transport-ws or graphql-ws protocol".to_string(),
+        );
+    };
+    upgrade_websocket(
+        &mut request,
+        &key,
+        Some(protocol.sec_websocket_protocol()),
+        move |socket| serve_subscriptions(socket, schema, protocol),
+    )
+}
+
+/// Runs the subscriptions a client starts on its websocket until either side closes.
+async fn serve_subscriptions<S>(
+    socket: WebSocketStream<S>,
+    schema: ApiSchema,
+    protocol: WebSocketProtocols,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, stream) = socket.split();
+    // Reading answers pings; the client's close (or a broken connection) ends the session.
+    let incoming = stream
+        .take_while(|message| future::ready(matches!(message, Ok(m) if !m.is_close())))
+        .filter_map(|message| {
+            future::ready(match message {
+                Ok(Message::Text(text)) => Some(text.into_bytes()),
+                Ok(Message::Binary(bytes)) => Some(bytes),
+                _ => None,
+            })
+        });
+    let mut outgoing = pin!(WebSocket::new(schema, incoming, protocol));
+    while let Some(message) = outgoing.next().await {
+        let message = match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code: code.into(),
+                reason: reason.into(),
+            })),
+        };
+        if sink.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn bad_request(message: String) -> Response {
+    let body = ErrorBody {
+        error: message,
+        code: Some(errors::INVALID_PARAMETER.id.to_string()),
+    };
+    (StatusCode::BAD_REQUEST, axum::Json(body)).into_response()
+}
+
+/// Parses an optional `program` argument into the raw bytes stored in the `program_id` column.
+fn program_filter(program: Option<&str>) -> Result<Option<Vec<u8>>> {
+    program
+        .map(|program| {
+            program
+                .parse::<Pubkey>()
+                .map(|pubkey| pubkey.to_bytes().to_vec())
+                .map_err(|_| {
+                    coded_error(
+                        &errors::INVALID_PROGRAM_ID,
+                        format!("'{}' is not a valid program ID", program),
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// A GraphQL error with its catalog code (see `errors`) in `extensions.code`.
+fn coded_error(code: &'static ErrorCode, message: impl Into<String>) -> Error {
+    Error::new(message).extend_with(|_, extensions| extensions.set("code", code.id))
+}
+
+/// Like on the REST API, the details of an internal failure go to the log, not to the client.
+fn internal(e: anyhow::Error) -> Error {
+    let code = errors::classify(&e).unwrap_or(&errors::INTERNAL);
+    error!(error = ?e, %code, "GraphQL request failed");
+    coded_error(code, code.message)
+}
+
+/// Runs a synchronous Diesel query on a blocking thread.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+        .map_err(internal)
+}
+
+#[cfg(all(test, feature = "graphql"))]
+mod tests {
+    use std::time::Duration;
+
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::PgConnection;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::db::db::{upsert_idl_account, upsert_poll};
+    use crate::db::models::NewIdlAccount;
+    use crate::db::test_support::{new_poll, test_pool, NOW};
+
+    /// A pool that never connects: nothing listens on port 1.
+    fn unreachable_pool() -> PgPool {
+        Pool::builder()
+            .connection_timeout(Duration::from_millis(200))
+            .build_unchecked(ConnectionManager::<PgConnection>::new(
+                "postgres://localhost:1/none",
+            ))
+    }
+
+    async fn execute(schema: &ApiSchema, query: &str) -> Value {
+        serde_json::to_value(schema.execute(query).await).unwrap()
+    }
+
+    /// The `extensions.code` of each error of `response`.
+    fn error_codes(response: &Value) -> Vec<&str> {
+        response["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|error| error["extensions"]["code"].as_str().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn the_schema_exposes_polls_candidates_and_updates() {
+        let sdl = schema(unreachable_pool(), None).sdl();
+        for expected in [
+            "polls(program: String, limit: Int, offset: Int): [Poll!]!",
+            "poll(pollId: Int!, program: String): Poll",
+            "candidates: [Candidate!]!",
+            "votes: [Vote!]!",
+            "updates(program: String): LiveEvent!",
+        ] {
+            assert!(sdl.contains(expected), "{expected} missing from:\n{sdl}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_refused_before_querying() {
+        let schema = schema(unreachable_pool(), None);
+
+        let response = execute(&schema, r#"{ polls(program: "nope") { pollId } }"#).await;
+        assert_eq!(error_codes(&response), [errors::INVALID_PROGRAM_ID.id]);
+
+        // 9 levels of fields, one more than MAX_QUERY_DEPTH.
+        let deep = "{ __schema { types { fields { type { ofType { ofType { ofType { ofType { name } } } } } } } } }";
+        let response = execute(&schema, deep).await;
+        assert!(
+            response["errors"][0]["message"]
+                .as_str()
+                .unwrap()
+                .contains("nested too deep"),
+            "{response}"
+        );
+
+        let mut updates = schema.execute_stream("subscription { updates { type } }");
+        let response = serde_json::to_value(updates.next().await.unwrap()).unwrap();
+        assert_eq!(error_codes(&response), [errors::LIVE_UNAVAILABLE.id]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn polls_are_queried_with_their_candidates() {
+        let pool = test_pool();
+        let program = Pubkey::new_from_array([0x16; 32]);
+        upsert_poll(&pool, &new_poll(&program.to_bytes(), 1, 100), 0, NOW).unwrap();
+        for (name, votes) in [("Ada", 1), ("Bob", 3)] {
+            let candidate = NewIdlAccount {
+                program_id: program.to_bytes().to_vec(),
+                account_pubkey: vec![name.as_bytes()[0]; 32],
+                account_type: candidates::CANDIDATE_ACCOUNT.to_string(),
+                data: json!({ "poll_id": 1, "name": name, "votes": votes }),
+                last_slot: 100,
+            };
+            upsert_idl_account(&pool, &candidate).unwrap();
+        }
+        let schema = schema(pool, None);
+
+        let response = execute(
+            &schema,
+            &format!(
+                r#"{{ polls(program: "{program}") {{ pollId pollName programId
+                      candidates {{ name votes percentage }} }} }}"#
+            ),
+        )
+        .await;
+        assert_eq!(
+            response["data"],
+            json!({ "polls": [{
+                "pollId": 1,
+                "pollName": "Poll 1",
+                "programId": program.to_string(),
+                "candidates": [
+                    { "name": "Bob", "votes": 3, "percentage": 75.0 },
+                    { "name": "Ada", "votes": 1, "percentage": 25.0 },
+                ],
+            }] })
+        );
+
+        let response = execute(
+            &schema,
+            "{ poll(pollId: 1) { pollName } missing: poll(pollId: 2) { pollName } }",
+        )
+        .await;
+        assert_eq!(
+            response["data"],
+            json!({ "poll": { "pollName": "Poll 1" }, "missing": null })
+        );
+    }
+}
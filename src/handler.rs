@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::decode::VotingAccountType;
+use crate::event_stream::VotingEvent;
+
+/// Custom processing of decoded accounts, e.g. forwarding them to Kafka, registered with
+/// [`ListenerBuilder::handler`](crate::listener::ListenerBuilder::handler) for the
+/// discriminators it's interested in.
+///
+/// Handlers run after the account went to the sink, one after the other and awaited by the
+/// listener: a slow handler slows down ingestion, so hand long work off to a task or a channel.
+#[async_trait]
+pub trait AccountHandler: Send + Sync {
+    /// Handles one account update. `account_type` is what its discriminator matched
+    /// ([`VotingAccountType::Unknown`] for accounts that aren't voting accounts, e.g. those
+    /// only described by the program's IDL), `raw` its data, discriminator included.
+    ///
+    /// An error is logged and the update goes on to the next handler.
+    async fn handle(
+        &self,
+        account_type: VotingAccountType,
+        decoded: &VotingEvent,
+        raw: &[u8],
+        slot: u64,
+    ) -> Result<()>;
+}
+
+/// The [`AccountHandler`]s of a listener, keyed by the Anchor discriminator of the accounts
+/// they handle. Several handlers of one discriminator run in the order they were registered.
+#[derive(Clone, Default)]
+pub struct HandlerRegistry {
+    handlers: HashMap<[u8; 8], Vec<Arc<dyn AccountHandler>>>,
+}
+
+impl HandlerRegistry {
+    /// Runs `handler` for every account whose data starts with `discriminator`.
+    pub fn register(&mut self, discriminator: [u8; 8], handler: Arc<dyn AccountHandler>) {
+        self.handlers
+            .entry(discriminator)
+            .or_default()
+            .push(handler);
+    }
+
+    /// The handlers of `discriminator`, empty when there are none.
+    pub fn get(&self, discriminator: &[u8; 8]) -> &[Arc<dyn AccountHandler>] {
+        self.handlers
+            .get(discriminator)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use anyhow::bail;
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::decode::{DELEGATION_DISCRIMINATOR, POLL_DISCRIMINATOR};
+
+    /// Appends its name to a shared log, failing after it when `fails`.
+    struct Recorder {
+        name: &'static str,
+        fails: bool,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl AccountHandler for Recorder {
+        async fn handle(
+            &self,
+            _account_type: VotingAccountType,
+            _decoded: &VotingEvent,
+            _raw: &[u8],
+            _slot: u64,
+        ) -> Result<()> {
+            self.log.lock().unwrap().push(self.name);
+            if self.fails {
+                bail!("{} failed", self.name);
+            }
+            Ok(())
+        }
+    }
+
+    fn recorder(
+        name: &'static str,
+        fails: bool,
+        log: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> Arc<dyn AccountHandler> {
+        Arc::new(Recorder {
+            name,
+            fails,
+            log: log.clone(),
+        })
+    }
+
+    #[tokio::test]
+    async fn handlers_are_looked_up_by_discriminator_in_registration_order() {
+        let log = Arc::default();
+        let mut registry = HandlerRegistry::default();
+        registry.register(POLL_DISCRIMINATOR, recorder("first", false, &log));
+        registry.register(
+            DELEGATION_DISCRIMINATOR,
+            recorder("delegation", false, &log),
+        );
+        registry.register(POLL_DISCRIMINATOR, recorder("second", true, &log));
+
+        assert_eq!(registry.get(&POLL_DISCRIMINATOR).len(), 2);
+        assert_eq!(registry.get(&DELEGATION_DISCRIMINATOR).len(), 1);
+        assert!(registry.get(&[0; 8]).is_empty());
+
+        let event = VotingEvent::Unknown {
+            program_id: Pubkey::new_unique(),
+            account: Pubkey::new_unique(),
+            slot: 42,
+            data: POLL_DISCRIMINATOR.to_vec(),
+            fields: None,
+        };
+        for handler in registry.get(&POLL_DISCRIMINATOR) {
+            let _ = handler
+                .handle(VotingAccountType::Poll, &event, &POLL_DISCRIMINATOR, 42)
+                .await;
+        }
+        assert_eq!(*log.lock().unwrap(), ["first", "second"]);
+
+        // Clones share the handlers.
+        let cloned = registry.clone();
+        assert!(Arc::ptr_eq(
+            &cloned.get(&POLL_DISCRIMINATOR)[0],
+            &registry.get(&POLL_DISCRIMINATOR)[0]
+        ));
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::db::db::{insert_idl, latest_idl, PgPool};
+use crate::db::models::{Idl, NewIdl};
+
+/// Reads an Anchor IDL (JSON). Only the shape is checked: an object with an `accounts` array.
+pub fn read_idl(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read IDL {}", path.display()))?;
+    let idl: Value = serde_json::from_str(&text)
+        .with_context(|| format!("IDL {} is not valid JSON", path.display()))?;
+    if !idl.get("accounts").is_some_and(Value::is_array) {
+        bail!("IDL {} has no `accounts` array", path.display());
+    }
+    Ok(canonical(&idl))
+}
+
+/// The program the IDL describes: `address` (Anchor 0.30+) or `metadata.address` (older IDLs).
+pub fn idl_address(idl: &Value) -> Option<Pubkey> {
+    idl.get("address")
+        .or_else(|| idl.pointer("/metadata/address"))
+        .and_then(Value::as_str)
+        .and_then(|address| address.parse().ok())
+}
+
+/// sha256 of the IDL with its object keys sorted, so formatting doesn't make a new version.
+pub fn content_hash(idl: &Value) -> Vec<u8> {
+    let bytes = serde_json::to_vec(&canonical(idl)).expect("a JSON value always serializes");
+    Sha256::digest(bytes).to_vec()
+}
+
+/// Sorts object keys recursively.
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<&String, Value> = map
+                .iter()
+                .map(|(key, value)| (key, canonical(value)))
+                .collect();
+            Value::Object(
+                sorted
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), value))
+                    .collect::<Map<_, _>>(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonical).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Each account of an IDL with what defines its layout: its discriminator (when given) and its
+/// type, inline in older IDLs or in `types` since Anchor 0.30.
+pub fn account_layouts(idl: &Value) -> BTreeMap<String, Value> {
+    let types: BTreeMap<&str, &Value> = idl
+        .get("types")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|ty| Some((ty.get("name")?.as_str()?, ty.get("type")?)))
+        .collect();
+    idl.get("accounts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|account| {
+            let name = account.get("name")?.as_str()?;
+            let layout = account
+                .get("type")
+                .or_else(|| types.get(name).copied())
+                .cloned()
+                .unwrap_or(Value::Null);
+            let discriminator = account.get("discriminator").cloned().unwrap_or(Value::Null);
+            Some((
+                name.to_string(),
+                serde_json::json!({ "discriminator": discriminator, "type": layout }),
+            ))
+        })
+        .collect()
+}
+
+/// How the accounts of two IDL versions differ, by account name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountsDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Accounts whose discriminator or layout changed.
+    pub changed: Vec<String>,
+}
+
+impl AccountsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares the accounts of two IDLs.
+pub fn diff_accounts(old: &Value, new: &Value) -> AccountsDiff {
+    let (old, new) = (account_layouts(old), account_layouts(new));
+    let mut diff = AccountsDiff::default();
+    for (name, layout) in &new {
+        match old.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(previous) if previous != layout => diff.changed.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+    diff
+}
+
+/// Outcome of [`record_idl`].
+#[derive(Debug)]
+pub enum IdlLoad {
+    /// Same content as the program's latest version; nothing was stored.
+    Unchanged(Idl),
+    /// Stored as a new version. `accounts` compares it with the previous version, if any.
+    Stored {
+        version: Idl,
+        previous: Option<Idl>,
+        accounts: AccountsDiff,
+    },
+}
+
+/// Stores `idl` as the program's latest IDL version, unless it already is.
+pub fn record_idl(pool: &PgPool, program_id: &Pubkey, idl: &Value) -> Result<IdlLoad> {
+    let program = program_id.to_bytes().to_vec();
+    let hash = content_hash(idl);
+    match latest_idl(pool, &program)? {
+        Some(latest) if latest.content_hash == hash => Ok(IdlLoad::Unchanged(latest)),
+        previous => store(pool, program, hash, idl, previous),
+    }
+}
+
+fn store(
+    pool: &PgPool,
+    program_id: Vec<u8>,
+    content_hash: Vec<u8>,
+    idl: &Value,
+    previous: Option<Idl>,
+) -> Result<IdlLoad> {
+    let old = previous
+        .as_ref()
+        .map_or(&Value::Null, |previous| &previous.idl);
+    let accounts = diff_accounts(old, idl);
+    let version = insert_idl(
+        pool,
+        &NewIdl {
+            program_id,
+            content_hash,
+            idl: canonical(idl),
+        },
+    )?;
+    Ok(IdlLoad::Stored {
+        version,
+        previous,
+        accounts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::db::test_support::test_pool;
+
+    /// An Anchor 0.30 IDL with `Poll` and `Candidate`; `votes` is the type of the candidate's
+    /// vote count.
+    fn idl(votes: &str) -> Value {
+        json!({
+            "address": "HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh",
+            "accounts": [
+                { "name": "Poll", "discriminator": [1, 2, 3, 4, 5, 6, 7, 8] },
+                { "name": "Candidate", "discriminator": [8, 7, 6, 5, 4, 3, 2, 1] }
+            ],
+            "types": [
+                { "name": "Poll", "type": { "kind": "struct", "fields": [
+                    { "name": "poll_id", "type": "u64" }
+                ] } },
+                { "name": "Candidate", "type": { "kind": "struct", "fields": [
+                    { "name": "candidate_votes", "type": votes }
+                ] } }
+            ]
+        })
+    }
+
+    #[test]
+    fn the_hash_ignores_key_order() {
+        let reordered: Value = serde_json::from_str(
+            r#"{"types": [], "accounts": [{"discriminator": [1], "name": "A"}]}"#,
+        )
+        .unwrap();
+        let sorted = json!({ "accounts": [{ "name": "A", "discriminator": [1] }], "types": [] });
+        assert_eq!(content_hash(&reordered), content_hash(&sorted));
+        assert_ne!(content_hash(&idl("u64")), content_hash(&idl("u32")));
+    }
+
+    #[test]
+    fn reads_the_address_of_both_idl_formats() {
+        let program: Pubkey = "HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh"
+            .parse()
+            .unwrap();
+        assert_eq!(idl_address(&idl("u64")), Some(program));
+        let legacy = json!({ "metadata": { "address": program.to_string() }, "accounts": [] });
+        assert_eq!(idl_address(&legacy), Some(program));
+        assert_eq!(idl_address(&json!({ "accounts": [] })), None);
+    }
+
+    #[test]
+    fn layouts_come_from_types_or_inline() {
+        let layouts = account_layouts(&idl("u64"));
+        assert_eq!(layouts.keys().collect::<Vec<_>>(), ["Candidate", "Poll"]);
+        assert_eq!(
+            layouts["Poll"]["type"]["fields"][0]["name"],
+            json!("poll_id")
+        );
+
+        let legacy = json!({ "accounts": [
+            { "name": "Poll", "type": { "kind": "struct", "fields": [] } }
+        ] });
+        let layouts = account_layouts(&legacy);
+        assert_eq!(layouts["Poll"]["discriminator"], Value::Null);
+        assert_eq!(layouts["Poll"]["type"]["kind"], json!("struct"));
+    }
+
+    #[test]
+    fn diffs_added_removed_and_changed_accounts() {
+        // `Poll` dropped, `Candidate`'s vote count narrowed, `Vote` new.
+        let new = json!({
+            "accounts": [
+                { "name": "Candidate", "discriminator": [8, 7, 6, 5, 4, 3, 2, 1] },
+                { "name": "Vote", "discriminator": [9, 9, 9, 9, 9, 9, 9, 9] }
+            ],
+            "types": idl("u32")["types"]
+        });
+
+        let diff = diff_accounts(&idl("u64"), &new);
+        assert_eq!(
+            diff,
+            AccountsDiff {
+                added: vec!["Vote".into()],
+                removed: vec!["Poll".into()],
+                changed: vec!["Candidate".into()],
+            }
+        );
+        assert!(diff_accounts(&idl("u64"), &idl("u64")).is_empty());
+        // Against no previous version, everything is new.
+        assert_eq!(diff_accounts(&Value::Null, &idl("u64")).added.len(), 2);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn reloading_the_same_idl_stores_no_version() {
+        let pool = test_pool();
+        let program = Pubkey::new_from_array([0x30; 32]);
+
+        let IdlLoad::Stored { previous: None, .. } =
+            record_idl(&pool, &program, &idl("u64")).unwrap()
+        else {
+            panic!("the first load is stored");
+        };
+        // Reformatted, same content.
+        let reformatted: Value = serde_json::from_str(&idl("u64").to_string()).unwrap();
+        assert!(matches!(
+            record_idl(&pool, &program, &reformatted).unwrap(),
+            IdlLoad::Unchanged(_)
+        ));
+
+        let IdlLoad::Stored {
+            version,
+            previous: Some(previous),
+            accounts,
+        } = record_idl(&pool, &program, &idl("u32")).unwrap()
+        else {
+            panic!("a changed IDL is a new version");
+        };
+        assert_ne!(version.id, previous.id);
+        assert_eq!(accounts.changed, ["Candidate"]);
+        assert!(accounts.added.is_empty() && accounts.removed.is_empty());
+    }
+}
@@ -0,0 +1,648 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::decode::VotingAccountType;
+use crate::idl;
+use crate::state::anchor::AnchorReader;
+use crate::state::error::DecodeError;
+
+/// Deepest nesting of types followed while decoding; deeper means a recursive type.
+const MAX_DEPTH: usize = 32;
+
+/// An account an [`IdlDecoder`] recognized, with its fields as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedAccount {
+    /// The account's name in the IDL (e.g. `Candidate`).
+    pub name: String,
+    pub fields: Value,
+}
+
+/// Decodes any account an Anchor IDL describes into JSON, without Rust structs for it.
+///
+/// Polls and delegations are not decoded through it: their typed tables, the zero-allocation
+/// vote path and the derived `BorshDeserialize` all build on the typed structs and the built-in
+/// discriminators, so those stay. The IDL covers every other account, and
+/// [`IdlDecoder::mismatched_types`] checks the built-in discriminators against it.
+///
+/// Integers up to 64 bits become JSON numbers, 128-bit ones strings (JSON can't hold them
+/// exactly everywhere), public keys base58 strings and `bytes` base64 strings. Structs become
+/// objects (arrays for tuple structs), unit enum variants their name and other variants
+/// `{ "Variant": fields }`.
+#[derive(Debug, Clone)]
+pub struct IdlDecoder {
+    /// Name and type definition of each account, by discriminator.
+    accounts: BTreeMap<[u8; 8], (String, Value)>,
+    /// The IDL's `types`, by name.
+    types: BTreeMap<String, Value>,
+}
+
+impl IdlDecoder {
+    /// Reads the accounts of an IDL. Discriminators are taken from the IDL (Anchor 0.30+) or
+    /// derived like Anchor does (`sha256("account:<Name>")[..8]`) for older IDLs.
+    pub fn new(idl: &Value) -> Result<Self> {
+        let types = idl
+            .get("types")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|ty| {
+                Some((
+                    ty.get("name")?.as_str()?.to_string(),
+                    ty.get("type")?.clone(),
+                ))
+            })
+            .collect();
+
+        let mut accounts = BTreeMap::new();
+        for (name, layout) in idl::account_layouts(idl) {
+            let discriminator = match layout.get("discriminator") {
+                Some(Value::Array(bytes)) => parse_discriminator(bytes).with_context(|| {
+                    format!("IDL account `{}` has an invalid discriminator", name)
+                })?,
+                _ => account_discriminator(&name),
+            };
+            let ty = layout
+                .get("type")
+                .filter(|ty| !ty.is_null())
+                .cloned()
+                .with_context(|| format!("IDL account `{}` has no type definition", name))?;
+            if let Some((other, _)) = accounts.insert(discriminator, (name.clone(), ty)) {
+                bail!(
+                    "IDL accounts `{}` and `{}` share a discriminator",
+                    other,
+                    name
+                );
+            }
+        }
+        Ok(Self { accounts, types })
+    }
+
+    /// Number of account types the IDL describes.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// The built-in account types the IDL names (case-insensitively) with another
+    /// discriminator than the built-in one. Their accounts won't reach the typed tables.
+    pub fn mismatched_types(&self) -> Vec<(VotingAccountType, [u8; 8])> {
+        [
+            VotingAccountType::Poll,
+            VotingAccountType::Candidate,
+            VotingAccountType::Vote,
+            VotingAccountType::Delegation,
+        ]
+        .into_iter()
+        .filter_map(|account_type| {
+            let (discriminator, _) = self
+                .accounts
+                .iter()
+                .find(|(_, (name, _))| name.eq_ignore_ascii_case(account_type.as_str()))?;
+            (account_type.discriminator() != Some(*discriminator))
+                .then_some((account_type, *discriminator))
+        })
+        .collect()
+    }
+
+    /// Decodes a whole account (discriminator included). `None` when the IDL has no account
+    /// with its discriminator. Trailing bytes (`#[max_len]` space) are ignored.
+    pub fn decode(&self, data: &[u8]) -> Option<Result<DecodedAccount>> {
+        let discriminator: [u8; 8] = data.get(..8)?.try_into().ok()?;
+        let (name, ty) = self.accounts.get(&discriminator)?;
+        let mut reader = AnchorReader::new(&data[8..]);
+        let decoded = self
+            .decode_type_def(ty, &mut reader, 0)
+            .map(|fields| DecodedAccount {
+                name: name.clone(),
+                fields,
+            })
+            .with_context(|| format!("Could not decode {} account", name));
+        Some(decoded)
+    }
+
+    /// Decodes a type definition: `{ "kind": "struct" | "enum" | "type", ... }`.
+    fn decode_type_def(
+        &self,
+        def: &Value,
+        reader: &mut AnchorReader,
+        depth: usize,
+    ) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("types nested deeper than {} levels", MAX_DEPTH);
+        }
+        match def.get("kind").and_then(Value::as_str) {
+            Some("struct") => self.decode_fields(def.get("fields"), reader, depth),
+            Some("enum") => {
+                let variants = def
+                    .get("variants")
+                    .and_then(Value::as_array)
+                    .context("enum without variants")?;
+                let tag = reader.read_u8()?;
+                let variant = variants
+                    .get(tag as usize)
+                    .with_context(|| format!("invalid enum tag {}", tag))?;
+                let name = variant
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                match variant.get("fields") {
+                    None | Some(Value::Null) => Ok(Value::String(name)),
+                    fields => {
+                        let fields = self
+                            .decode_fields(fields, reader, depth)
+                            .with_context(|| format!("variant `{}`", name))?;
+                        Ok(Value::Object(Map::from_iter([(name, fields)])))
+                    }
+                }
+            }
+            // Type aliases (Anchor 0.30+).
+            Some("type") => {
+                let alias = def.get("alias").context("type alias without `alias`")?;
+                self.decode_type(alias, reader, depth)
+            }
+            other => bail!("unsupported type kind {:?}", other),
+        }
+    }
+
+    /// Named fields (`[{ "name", "type" }]`) become an object, tuple fields (`[type]`) an array.
+    fn decode_fields(
+        &self,
+        fields: Option<&Value>,
+        reader: &mut AnchorReader,
+        depth: usize,
+    ) -> Result<Value> {
+        let fields = match fields {
+            Some(Value::Array(fields)) => fields,
+            None | Some(Value::Null) => return Ok(Value::Object(Map::new())),
+            Some(other) => bail!("invalid fields {}", other),
+        };
+        let named = fields
+            .first()
+            .is_some_and(|field| field.get("name").is_some() && field.get("type").is_some());
+        if !named {
+            let items = fields
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| {
+                    self.decode_type(ty, reader, depth)
+                        .with_context(|| format!("field {}", i))
+                })
+                .collect::<Result<_>>()?;
+            return Ok(Value::Array(items));
+        }
+
+        let mut object = Map::new();
+        for field in fields {
+            let name = field
+                .get("name")
+                .and_then(Value::as_str)
+                .context("field without a name")?;
+            let ty = field.get("type").context("field without a type")?;
+            let value = self
+                .decode_type(ty, reader, depth)
+                .with_context(|| format!("field `{}`", name))?;
+            object.insert(name.to_string(), value);
+        }
+        Ok(Value::Object(object))
+    }
+
+    /// Decodes a type reference: a primitive name, or `vec`, `option`, `array` or `defined`.
+    fn decode_type(&self, ty: &Value, reader: &mut AnchorReader, depth: usize) -> Result<Value> {
+        let map = match ty {
+            Value::String(primitive) => return decode_primitive(primitive, reader),
+            Value::Object(map) => map,
+            other => bail!("invalid type {}", other),
+        };
+        if let Some(inner) = map.get("vec") {
+            let len = reader.read_u32()? as usize;
+            // Every element takes at least a byte in practice; this stops a garbage length
+            // from looping (or allocating) for billions of elements.
+            if len > reader.remaining() {
+                bail!(
+                    "vec length {} exceeds the remaining {} bytes",
+                    len,
+                    reader.remaining()
+                );
+            }
+            let items = (0..len)
+                .map(|_| self.decode_type(inner, reader, depth + 1))
+                .collect::<Result<_>>()?;
+            Ok(Value::Array(items))
+        } else if let Some(inner) = map.get("option") {
+            match reader.read_u8()? {
+                0 => Ok(Value::Null),
+                1 => self.decode_type(inner, reader, depth + 1),
+                tag => Err(DecodeError::InvalidOptionTag {
+                    field: "option",
+                    tag,
+                }
+                .into()),
+            }
+        } else if let Some(array) = map.get("array") {
+            let (inner, len) = match array.as_array().map(Vec::as_slice) {
+                Some([inner, len]) => (inner, len.as_u64().context("generic array length")?),
+                _ => bail!("invalid array type {}", array),
+            };
+            let items = (0..len)
+                .map(|_| self.decode_type(inner, reader, depth + 1))
+                .collect::<Result<_>>()?;
+            Ok(Value::Array(items))
+        } else if let Some(defined) = map.get("defined") {
+            // `"Name"` in older IDLs, `{ "name": "Name" }` since Anchor 0.30.
+            let name = defined
+                .as_str()
+                .or_else(|| defined.get("name").and_then(Value::as_str))
+                .with_context(|| format!("invalid defined type {}", defined))?;
+            let def = self
+                .types
+                .get(name)
+                .with_context(|| format!("type `{}` isn't in the IDL", name))?;
+            self.decode_type_def(def, reader, depth + 1)
+        } else {
+            bail!("unsupported type {}", ty)
+        }
+    }
+}
+
+/// Decodes a primitive Borsh type by its IDL name.
+fn decode_primitive(name: &str, reader: &mut AnchorReader) -> Result<Value> {
+    let value = match name {
+        "bool" => match reader.read_u8()? {
+            0 => Value::Bool(false),
+            1 => Value::Bool(true),
+            other => bail!("invalid bool {}", other),
+        },
+        "u8" => Value::from(reader.read_u8()?),
+        "i8" => Value::from(i8::from_le_bytes(le_bytes(reader)?)),
+        "u16" => Value::from(u16::from_le_bytes(le_bytes(reader)?)),
+        "i16" => Value::from(i16::from_le_bytes(le_bytes(reader)?)),
+        "u32" => Value::from(reader.read_u32()?),
+        "i32" => Value::from(i32::from_le_bytes(le_bytes(reader)?)),
+        "u64" => Value::from(reader.read_u64()?),
+        "i64" => Value::from(reader.read_i64()?),
+        "u128" => Value::String(u128::from_le_bytes(le_bytes(reader)?).to_string()),
+        "i128" => Value::String(i128::from_le_bytes(le_bytes(reader)?).to_string()),
+        // NaN and infinities have no JSON number; they become null.
+        "f32" => Value::from(f32::from_le_bytes(le_bytes(reader)?) as f64),
+        "f64" => Value::from(f64::from_le_bytes(le_bytes(reader)?)),
+        "pubkey" | "publicKey" => Value::String(reader.read_pubkey()?.to_string()),
+        "string" => {
+            let len = reader.read_u32()? as usize;
+            let bytes = reader.read_bytes(len)?;
+            let s = std::str::from_utf8(bytes)
+                .map_err(|_| DecodeError::InvalidUtf8 { field: "string" })?;
+            Value::String(s.to_string())
+        }
+        "bytes" => {
+            let len = reader.read_u32()? as usize;
+            Value::String(base64::engine::general_purpose::STANDARD.encode(reader.read_bytes(len)?))
+        }
+        other => bail!("unsupported type `{}`", other),
+    };
+    Ok(value)
+}
+
+/// The next `N` bytes, for `from_le_bytes`.
+fn le_bytes<const N: usize>(reader: &mut AnchorReader) -> Result<[u8; N]> {
+    Ok(reader
+        .read_bytes(N)?
+        .try_into()
+        .expect("read_bytes returns N bytes"))
+}
+
+/// Anchor's account discriminator: the first 8 bytes of `sha256("account:<Name>")`.
+pub fn account_discriminator(name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("account:{}", name));
+    hash[..8].try_into().expect("sha256 is 32 bytes")
+}
+
+fn parse_discriminator(bytes: &[Value]) -> Option<[u8; 8]> {
+    let bytes: Vec<u8> = bytes
+        .iter()
+        .map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+        .collect::<Option<_>>()?;
+    bytes.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::decode::{
+        DELEGATION_DISCRIMINATOR, POLL_DISCRIMINATOR, POOL_CANDIDATE_DISCRIMINATOR,
+        VOTE_DISCRIMINATOR,
+    };
+    use crate::state::anchor::{AnchorEncode, AnchorWriter};
+    use crate::state::delegation::Delegation;
+    use crate::state::pool::Poll;
+
+    /// The voting program's IDL (Anchor 0.30 format), with the built-in discriminators.
+    fn voting_idl() -> Value {
+        json!({
+            "address": "HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh",
+            "accounts": [
+                { "name": "Poll", "discriminator": POLL_DISCRIMINATOR },
+                { "name": "Candidate", "discriminator": POOL_CANDIDATE_DISCRIMINATOR },
+                { "name": "Vote", "discriminator": VOTE_DISCRIMINATOR },
+                { "name": "Delegation", "discriminator": DELEGATION_DISCRIMINATOR },
+            ],
+            "types": [
+                { "name": "Poll", "type": { "kind": "struct", "fields": [
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "poll_owner", "type": "pubkey" },
+                    { "name": "poll_name", "type": "string" },
+                    { "name": "poll_description", "type": "string" },
+                    { "name": "poll_start", "type": "u64" },
+                    { "name": "poll_end", "type": "u64" },
+                    { "name": "candidate_amount", "type": "u64" },
+                    { "name": "candidate_winner", "type": "pubkey" },
+                ] } },
+                { "name": "Candidate", "type": { "kind": "struct", "fields": [
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "candidate_name", "type": "string" },
+                    { "name": "candidate_votes", "type": "u64" },
+                ] } },
+                { "name": "Vote", "type": { "kind": "struct", "fields": [
+                    { "name": "voter", "type": "pubkey" },
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "candidate_name", "type": "string" },
+                ] } },
+                { "name": "Delegation", "type": { "kind": "struct", "fields": [
+                    { "name": "delegator", "type": "pubkey" },
+                    { "name": "delegate", "type": "pubkey" },
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "expiry", "type": { "option": "i64" } },
+                ] } },
+            ],
+        })
+    }
+
+    fn account(discriminator: [u8; 8], body: &[u8]) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// A one-account IDL whose account `Thing` has type `ty`, and its discriminator.
+    fn single(ty: Value, types: Value) -> (IdlDecoder, [u8; 8]) {
+        let idl = json!({ "accounts": [{ "name": "Thing", "type": ty }], "types": types });
+        (
+            IdlDecoder::new(&idl).unwrap(),
+            account_discriminator("Thing"),
+        )
+    }
+
+    #[test]
+    fn decodes_the_typed_accounts_like_their_structs() {
+        let decoder = IdlDecoder::new(&voting_idl()).unwrap();
+        assert_eq!(decoder.len(), 4);
+        assert!(decoder.mismatched_types().is_empty());
+
+        let poll = Poll {
+            poll_id: 21,
+            poll_owner: Pubkey::new_from_array([1; 32]),
+            poll_name: "Mascot".to_string(),
+            poll_description: "Pick one".to_string(),
+            poll_start: 1_700_000_000,
+            poll_end: 1_700_086_400,
+            candidate_amount: 2,
+            candidate_winner: Pubkey::default(),
+        };
+        let mut data = account(POLL_DISCRIMINATOR, &poll.encode_anchor_bytes());
+        // `#[max_len]` space after the fields.
+        data.resize(data.len() + 40, 0);
+        let decoded = decoder.decode(&data).unwrap().unwrap();
+        assert_eq!(decoded.name, "Poll");
+        assert_eq!(
+            decoded.fields,
+            json!({
+                "poll_id": 21,
+                "poll_owner": poll.poll_owner.to_string(),
+                "poll_name": "Mascot",
+                "poll_description": "Pick one",
+                "poll_start": 1_700_000_000u64,
+                "poll_end": 1_700_086_400u64,
+                "candidate_amount": 2,
+                "candidate_winner": Pubkey::default().to_string(),
+            })
+        );
+
+        for (expiry, expected) in [(None, Value::Null), (Some(-5), json!(-5))] {
+            let delegation = Delegation {
+                delegator: Pubkey::new_from_array([2; 32]),
+                delegate: Pubkey::new_from_array([3; 32]),
+                poll_id: 21,
+                expiry,
+            };
+            let data = account(DELEGATION_DISCRIMINATOR, &delegation.encode_anchor_bytes());
+            let decoded = decoder.decode(&data).unwrap().unwrap();
+            assert_eq!(decoded.name, "Delegation");
+            assert_eq!(decoded.fields["expiry"], expected);
+            assert_eq!(
+                decoded.fields["delegate"],
+                json!(delegation.delegate.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_idls_get_anchor_derived_discriminators() {
+        // No discriminators, `publicKey` and `defined` as a plain name (Anchor < 0.30).
+        let idl = json!({
+            "accounts": [
+                { "name": "Poll", "type": { "kind": "struct", "fields": [
+                    { "name": "poll_id", "type": "u64" },
+                ] } },
+                { "name": "Candidate", "type": { "kind": "struct", "fields": [
+                    { "name": "owner", "type": "publicKey" },
+                    { "name": "tally", "type": { "defined": "Tally" } },
+                ] } },
+            ],
+            "types": [
+                { "name": "Tally", "type": { "kind": "struct", "fields": [
+                    { "name": "votes", "type": "u64" },
+                ] } },
+            ],
+        });
+        let decoder = IdlDecoder::new(&idl).unwrap();
+        // The built-in discriminators are the ones Anchor derives.
+        assert_eq!(account_discriminator("Poll"), POLL_DISCRIMINATOR);
+        assert_eq!(
+            account_discriminator("Candidate"),
+            POOL_CANDIDATE_DISCRIMINATOR
+        );
+        assert_eq!(
+            account_discriminator("Delegation"),
+            DELEGATION_DISCRIMINATOR
+        );
+        assert!(decoder.mismatched_types().is_empty());
+
+        let mut writer = AnchorWriter::default();
+        writer.write_pubkey(&Pubkey::new_from_array([4; 32]));
+        writer.write_u64(7);
+        let data = account(POOL_CANDIDATE_DISCRIMINATOR, &writer.into_bytes());
+        assert_eq!(
+            decoder.decode(&data).unwrap().unwrap().fields,
+            json!({
+                "owner": Pubkey::new_from_array([4; 32]).to_string(),
+                "tally": { "votes": 7 },
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_or_short_accounts_are_not_decoded() {
+        let decoder = IdlDecoder::new(&voting_idl()).unwrap();
+        assert!(decoder.decode(&account([9; 8], &[0; 64])).is_none());
+        assert!(decoder.decode(&POLL_DISCRIMINATOR[..7]).is_none());
+        assert!(decoder.decode(&[]).is_none());
+    }
+
+    #[test]
+    fn truncated_and_invalid_data_fails() {
+        let decoder = IdlDecoder::new(&voting_idl()).unwrap();
+        let delegation = Delegation {
+            delegator: Pubkey::new_from_array([2; 32]),
+            delegate: Pubkey::new_from_array([3; 32]),
+            poll_id: 1,
+            expiry: Some(10),
+        };
+        let body = delegation.encode_anchor_bytes();
+        for len in 0..body.len() {
+            let data = account(DELEGATION_DISCRIMINATOR, &body[..len]);
+            assert!(decoder.decode(&data).unwrap().is_err(), "{} bytes", len);
+        }
+
+        let mut bad_option = body.clone();
+        bad_option[72] = 2;
+        let data = account(DELEGATION_DISCRIMINATOR, &bad_option);
+        assert!(decoder.decode(&data).unwrap().is_err());
+
+        // A string length far beyond the data.
+        let mut writer = AnchorWriter::default();
+        writer.write_pubkey(&Pubkey::default());
+        writer.write_u64(1);
+        writer.write_u32(u32::MAX);
+        let data = account(VOTE_DISCRIMINATOR, &writer.into_bytes());
+        assert!(decoder.decode(&data).unwrap().is_err());
+    }
+
+    #[test]
+    fn decodes_every_kind_of_type() {
+        let (decoder, discriminator) = single(
+            json!({ "kind": "struct", "fields": [
+                { "name": "flag", "type": "bool" },
+                { "name": "small", "type": "i16" },
+                { "name": "big", "type": "u128" },
+                { "name": "blob", "type": "bytes" },
+                { "name": "list", "type": { "vec": "u8" } },
+                { "name": "pair", "type": { "array": ["u32", 2] } },
+                { "name": "maybe", "type": { "option": "u8" } },
+                { "name": "unit", "type": { "defined": { "name": "Choice" } } },
+                { "name": "variant", "type": { "defined": { "name": "Choice" } } },
+                { "name": "tuple", "type": { "defined": { "name": "Point" } } },
+                { "name": "alias", "type": { "defined": { "name": "Amount" } } },
+            ] }),
+            json!([
+                { "name": "Choice", "type": { "kind": "enum", "variants": [
+                    { "name": "Empty" },
+                    { "name": "Named", "fields": [{ "name": "id", "type": "u8" }] },
+                ] } },
+                { "name": "Point", "type": { "kind": "struct", "fields": ["u8", "u8"] } },
+                { "name": "Amount", "type": { "kind": "type", "alias": "u64" } },
+            ]),
+        );
+
+        let mut data = discriminator.to_vec();
+        data.push(1);
+        data.extend_from_slice(&(-2i16).to_le_bytes());
+        data.extend_from_slice(&u128::MAX.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"abc");
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[7, 8]);
+        data.extend_from_slice(&5u32.to_le_bytes());
+        data.extend_from_slice(&6u32.to_le_bytes());
+        data.push(0);
+        data.push(0);
+        data.extend_from_slice(&[1, 9]);
+        data.extend_from_slice(&[10, 11]);
+        data.extend_from_slice(&42u64.to_le_bytes());
+
+        assert_eq!(
+            decoder.decode(&data).unwrap().unwrap().fields,
+            json!({
+                "flag": true,
+                "small": -2,
+                "big": u128::MAX.to_string(),
+                "blob": "YWJj",
+                "list": [7, 8],
+                "pair": [5, 6],
+                "maybe": null,
+                "unit": "Empty",
+                "variant": { "Named": { "id": 9 } },
+                "tuple": [10, 11],
+                "alias": 42,
+            })
+        );
+    }
+
+    #[test]
+    fn bad_idls_and_types_are_errors() {
+        let shared = json!({ "accounts": [
+            { "name": "A", "discriminator": [1, 1, 1, 1, 1, 1, 1, 1], "type": { "kind": "struct" } },
+            { "name": "B", "discriminator": [1, 1, 1, 1, 1, 1, 1, 1], "type": { "kind": "struct" } },
+        ] });
+        assert!(IdlDecoder::new(&shared).is_err());
+        let short = json!({ "accounts": [{ "name": "A", "discriminator": [1, 2] }] });
+        assert!(IdlDecoder::new(&short).is_err());
+        let untyped =
+            json!({ "accounts": [{ "name": "A", "discriminator": [1, 1, 1, 1, 1, 1, 1, 1] }] });
+        assert!(IdlDecoder::new(&untyped).is_err());
+
+        // A type containing itself would recurse forever.
+        let (decoder, discriminator) = single(
+            json!({ "kind": "struct", "fields": [
+                { "name": "next", "type": { "defined": "Node" } },
+            ] }),
+            json!([{ "name": "Node", "type": { "kind": "struct", "fields": [
+                { "name": "next", "type": { "defined": "Node" } },
+            ] } }]),
+        );
+        let mut data = discriminator.to_vec();
+        data.resize(64, 0);
+        assert!(decoder.decode(&data).unwrap().is_err());
+
+        let (decoder, discriminator) = single(
+            json!({ "kind": "struct", "fields": [{ "name": "x", "type": "f16" }] }),
+            json!([]),
+        );
+        assert!(decoder
+            .decode(&account(discriminator, &[0; 8]))
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn reports_built_in_types_with_another_discriminator() {
+        let idl = json!({ "accounts": [
+            { "name": "Poll", "discriminator": [1, 2, 3, 4, 5, 6, 7, 8], "type": { "kind": "struct" } },
+            { "name": "vote", "discriminator": VOTE_DISCRIMINATOR, "type": { "kind": "struct" } },
+        ] });
+        let decoder = IdlDecoder::new(&idl).unwrap();
+        assert_eq!(
+            decoder.mismatched_types(),
+            [(VotingAccountType::Poll, [1, 2, 3, 4, 5, 6, 7, 8])]
+        );
+    }
+}
@@ -0,0 +1,650 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_program;
+
+use crate::state::anchor::AnchorWriter;
+
+/// Genesis hash of mainnet-beta; transactions are refused there unless explicitly allowed.
+pub const MAINNET_GENESIS_HASH: &str = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+
+/// Fails when `rpc_client` points at mainnet-beta and `allow_mainnet` isn't set. These helpers
+/// are meant for devnet and local validators, where a mistake costs nothing.
+pub async fn ensure_not_mainnet(rpc_client: &RpcClient, allow_mainnet: bool) -> Result<()> {
+    let genesis = rpc_client
+        .get_genesis_hash()
+        .await
+        .context("Failed to fetch the cluster's genesis hash")?;
+    if genesis.to_string() == MAINNET_GENESIS_HASH && !allow_mainnet {
+        bail!("Refusing to send transactions to mainnet-beta without --i-know-what-im-doing");
+    }
+    Ok(())
+}
+
+/// An argument type of an IDL instruction. Only the types the voting program's instructions use
+/// are supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    I64,
+    String,
+    Pubkey,
+}
+
+impl ArgType {
+    fn parse(ty: &Value) -> Result<Self> {
+        Ok(match ty.as_str() {
+            Some("bool") => ArgType::Bool,
+            Some("u8") => ArgType::U8,
+            Some("u16") => ArgType::U16,
+            Some("u32") => ArgType::U32,
+            Some("u64") => ArgType::U64,
+            Some("i64") => ArgType::I64,
+            Some("string") => ArgType::String,
+            // `publicKey` before Anchor 0.30.
+            Some("pubkey") | Some("publicKey") => ArgType::Pubkey,
+            _ => bail!("unsupported argument type {}", ty),
+        })
+    }
+}
+
+/// A value given for an instruction argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+    Bool(bool),
+    Int(i128),
+    String(String),
+    Pubkey(Pubkey),
+}
+
+impl ArgValue {
+    /// Writes the value in Borsh layout as `ty`.
+    fn encode(&self, ty: ArgType, writer: &mut AnchorWriter) -> Result<()> {
+        match (ty, self) {
+            (ArgType::Bool, ArgValue::Bool(value)) => writer.write_u8(u8::from(*value)),
+            (ArgType::U8, ArgValue::Int(value)) => writer.write_u8(u8::try_from(*value)?),
+            (ArgType::U16, ArgValue::Int(value)) => {
+                for byte in u16::try_from(*value)?.to_le_bytes() {
+                    writer.write_u8(byte);
+                }
+            }
+            (ArgType::U32, ArgValue::Int(value)) => writer.write_u32(u32::try_from(*value)?),
+            (ArgType::U64, ArgValue::Int(value)) => writer.write_u64(u64::try_from(*value)?),
+            (ArgType::I64, ArgValue::Int(value)) => writer.write_i64(i64::try_from(*value)?),
+            (ArgType::String, ArgValue::String(value)) => writer.write_string(value),
+            (ArgType::Pubkey, ArgValue::Pubkey(value)) => writer.write_pubkey(value),
+            _ => bail!("a {:?} can't be passed as {:?}", self, ty),
+        }
+        Ok(())
+    }
+
+    /// The bytes Anchor uses when the value is a PDA seed: little-endian integers, raw strings.
+    fn seed_bytes(&self, ty: ArgType) -> Result<Vec<u8>> {
+        match self {
+            ArgValue::String(value) => Ok(value.as_bytes().to_vec()),
+            ArgValue::Pubkey(value) => Ok(value.to_bytes().to_vec()),
+            ArgValue::Bool(_) | ArgValue::Int(_) => {
+                let mut writer = AnchorWriter::default();
+                self.encode(ty, &mut writer)?;
+                Ok(writer.into_bytes())
+            }
+        }
+    }
+}
+
+/// One seed of a PDA account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Seed {
+    Const(Vec<u8>),
+    /// The value of an instruction argument.
+    Arg(String),
+    /// The address of another account of the instruction.
+    Account(String),
+}
+
+/// An account of an IDL instruction.
+#[derive(Debug, Clone)]
+pub struct IdlAccount {
+    pub name: String,
+    pub writable: bool,
+    pub signer: bool,
+    /// Fixed address (programs, sysvars), when the IDL gives one.
+    pub address: Option<Pubkey>,
+    /// Seeds of a PDA of the program, when the IDL gives them.
+    pub seeds: Option<Vec<Seed>>,
+}
+
+/// An instruction of an Anchor IDL, with everything needed to build it.
+#[derive(Debug, Clone)]
+pub struct IdlInstruction {
+    /// Snake case, whatever the IDL's convention.
+    pub name: String,
+    pub discriminator: [u8; 8],
+    pub args: Vec<(String, ArgType)>,
+    pub accounts: Vec<IdlAccount>,
+}
+
+/// Finds the first instruction of `idl` named like one of `names` (snake case).
+///
+/// Reads both IDL formats: Anchor 0.30+ (snake case, explicit discriminators, `writable`,
+/// `signer`, `address`) and older ones (camel case, `isMut`, `isSigner`, discriminators
+/// derived from the name).
+pub fn find_instruction(idl: &Value, names: &[&str]) -> Result<IdlInstruction> {
+    let instructions = idl
+        .get("instructions")
+        .and_then(Value::as_array)
+        .context("IDL has no `instructions` array")?;
+    let found = names.iter().find_map(|wanted| {
+        instructions.iter().find(|instruction| {
+            instruction
+                .get("name")
+                .and_then(Value::as_str)
+                .is_some_and(|name| snake_case(name) == *wanted)
+        })
+    });
+    let Some(instruction) = found else {
+        bail!("IDL has no instruction named {}", names.join(" or "));
+    };
+    parse_instruction(instruction)
+}
+
+fn parse_instruction(instruction: &Value) -> Result<IdlInstruction> {
+    let name = snake_case(instruction["name"].as_str().unwrap_or_default());
+    let discriminator = match instruction.get("discriminator") {
+        Some(bytes) => serde_json::from_value::<[u8; 8]>(bytes.clone())
+            .with_context(|| format!("bad discriminator of instruction {}", name))?,
+        None => {
+            let hash = Sha256::digest(format!("global:{}", name).as_bytes());
+            hash[..8].try_into().expect("sha256 is 32 bytes")
+        }
+    };
+
+    let mut args = Vec::new();
+    for arg in instruction
+        .get("args")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let arg_name = snake_case(arg["name"].as_str().unwrap_or_default());
+        let ty = ArgType::parse(&arg["type"])
+            .with_context(|| format!("argument {} of instruction {}", arg_name, name))?;
+        args.push((arg_name, ty));
+    }
+
+    let mut accounts = Vec::new();
+    for account in instruction
+        .get("accounts")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        accounts.push(
+            parse_account(account).with_context(|| format!("account of instruction {}", name))?,
+        );
+    }
+    Ok(IdlInstruction {
+        name,
+        discriminator,
+        args,
+        accounts,
+    })
+}
+
+fn parse_account(account: &Value) -> Result<IdlAccount> {
+    let flag = |new: &str, old: &str| {
+        account
+            .get(new)
+            .or_else(|| account.get(old))
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    };
+    let address = match account.get("address").and_then(Value::as_str) {
+        Some(address) => Some(address.parse().context("bad account address")?),
+        None => None,
+    };
+    let seeds = match account.pointer("/pda/seeds").and_then(Value::as_array) {
+        Some(seeds) => Some(seeds.iter().map(parse_seed).collect::<Result<_>>()?),
+        None => None,
+    };
+    Ok(IdlAccount {
+        name: snake_case(account["name"].as_str().unwrap_or_default()),
+        writable: flag("writable", "isMut"),
+        signer: flag("signer", "isSigner"),
+        address,
+        seeds,
+    })
+}
+
+fn parse_seed(seed: &Value) -> Result<Seed> {
+    let path = || snake_case(seed["path"].as_str().unwrap_or_default());
+    match seed["kind"].as_str() {
+        Some("const") => match &seed["value"] {
+            Value::String(text) => Ok(Seed::Const(text.as_bytes().to_vec())),
+            bytes => Ok(Seed::Const(
+                serde_json::from_value(bytes.clone()).context("bad const seed")?,
+            )),
+        },
+        Some("arg") => Ok(Seed::Arg(path())),
+        Some("account") => Ok(Seed::Account(path())),
+        _ => bail!("unsupported seed {}", seed),
+    }
+}
+
+impl IdlInstruction {
+    /// Builds the instruction for `program_id`.
+    ///
+    /// Each argument takes the value of `values` with the same name, or failing that the one
+    /// whose name is part of it (`poll_name` takes `name`), since programs name them differently. Accounts are, in order of precedence:
+    /// the address given in `overrides`, `signer` for signer accounts, the IDL's fixed address,
+    /// the PDA derived from the IDL's seeds, or the system program for `system_program`.
+    pub fn build(
+        &self,
+        program_id: &Pubkey,
+        values: &BTreeMap<&str, ArgValue>,
+        overrides: &BTreeMap<String, Pubkey>,
+        signer: &Pubkey,
+    ) -> Result<Instruction> {
+        let mut writer = AnchorWriter::default();
+        for byte in self.discriminator {
+            writer.write_u8(byte);
+        }
+        let mut args = BTreeMap::new();
+        for (name, ty) in &self.args {
+            let value = lookup(values, name)
+                .with_context(|| format!("no value for argument {} of {}", name, self.name))?;
+            value
+                .encode(*ty, &mut writer)
+                .with_context(|| format!("argument {} of {}", name, self.name))?;
+            args.insert(name.as_str(), (value, *ty));
+        }
+
+        let mut resolved: BTreeMap<&str, Pubkey> = BTreeMap::new();
+        // Seeds may refer to accounts listed later: resolve until nothing changes.
+        loop {
+            let before = resolved.len();
+            for account in &self.accounts {
+                if resolved.contains_key(account.name.as_str()) {
+                    continue;
+                }
+                if let Some(address) =
+                    self.resolve(account, program_id, &args, &resolved, overrides, signer)?
+                {
+                    resolved.insert(&account.name, address);
+                }
+            }
+            if resolved.len() == before {
+                break;
+            }
+        }
+
+        let mut metas = Vec::with_capacity(self.accounts.len());
+        for account in &self.accounts {
+            let Some(address) = resolved.get(account.name.as_str()) else {
+                bail!(
+                    "can't derive account {} of {} from the IDL; pass --account {}=<PUBKEY>",
+                    account.name,
+                    self.name,
+                    account.name
+                );
+            };
+            metas.push(if account.writable {
+                AccountMeta::new(*address, account.signer)
+            } else {
+                AccountMeta::new_readonly(*address, account.signer)
+            });
+        }
+        Ok(Instruction::new_with_bytes(
+            *program_id,
+            &writer.into_bytes(),
+            metas,
+        ))
+    }
+
+    /// The address of `account`, `None` while it depends on accounts not resolved yet.
+    fn resolve(
+        &self,
+        account: &IdlAccount,
+        program_id: &Pubkey,
+        args: &BTreeMap<&str, (&ArgValue, ArgType)>,
+        resolved: &BTreeMap<&str, Pubkey>,
+        overrides: &BTreeMap<String, Pubkey>,
+        signer: &Pubkey,
+    ) -> Result<Option<Pubkey>> {
+        if let Some(address) = overrides.get(&account.name) {
+            return Ok(Some(*address));
+        }
+        if account.signer {
+            return Ok(Some(*signer));
+        }
+        if let Some(address) = account.address {
+            return Ok(Some(address));
+        }
+        if let Some(seeds) = &account.seeds {
+            let mut bytes = Vec::with_capacity(seeds.len());
+            for seed in seeds {
+                bytes.push(match seed {
+                    Seed::Const(value) => value.clone(),
+                    Seed::Arg(name) => {
+                        let Some((value, ty)) = args.get(name.as_str()) else {
+                            bail!(
+                                "seed of {} refers to unknown argument {}",
+                                account.name,
+                                name
+                            );
+                        };
+                        value.seed_bytes(*ty)?
+                    }
+                    Seed::Account(name) => match resolved.get(name.as_str()) {
+                        Some(address) => address.to_bytes().to_vec(),
+                        None => return Ok(None),
+                    },
+                });
+            }
+            let seeds: Vec<&[u8]> = bytes.iter().map(Vec::as_slice).collect();
+            return Ok(Some(Pubkey::find_program_address(&seeds, program_id).0));
+        }
+        if account.name == "system_program" {
+            return Ok(Some(system_program::ID));
+        }
+        Ok(None)
+    }
+}
+
+/// The value for argument `name`: the one with the same name, or else the one whose name is
+/// part of it word for word (`start` for `poll_start` or `start_time`).
+fn lookup<'a>(values: &'a BTreeMap<&str, ArgValue>, name: &str) -> Result<&'a ArgValue> {
+    if let Some(value) = values.get(name) {
+        return Ok(value);
+    }
+    let words: Vec<&str> = name.split('_').collect();
+    let mut matches = values.iter().filter(|(key, _)| {
+        let key: Vec<&str> = key.split('_').collect();
+        words
+            .windows(key.len())
+            .any(|window| window == key.as_slice())
+    });
+    match (matches.next(), matches.next()) {
+        (Some((_, value)), None) => Ok(value),
+        (Some((first, _)), Some((second, _))) => {
+            bail!("both {} and {} could be meant", first, second)
+        }
+        (None, _) => bail!("none given"),
+    }
+}
+
+/// `initializePoll` → `initialize_poll`; snake case is left as is. Leading underscores (unused
+/// arguments in the program) are dropped.
+fn snake_case(name: &str) -> String {
+    let name = name.trim_start_matches('_');
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([5; 32]);
+    const SIGNER: Pubkey = Pubkey::new_from_array([6; 32]);
+
+    fn global_discriminator(name: &str) -> [u8; 8] {
+        Sha256::digest(format!("global:{}", name).as_bytes())[..8]
+            .try_into()
+            .unwrap()
+    }
+
+    /// The voting program's instructions (Anchor 0.30 format).
+    fn voting_idl() -> Value {
+        json!({ "instructions": [
+            {
+                "name": "initialize_poll",
+                "discriminator": global_discriminator("initialize_poll"),
+                "args": [
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "poll_name", "type": "string" },
+                    { "name": "poll_start", "type": "u64" },
+                ],
+                "accounts": [
+                    { "name": "signer", "writable": true, "signer": true },
+                    { "name": "poll", "writable": true, "pda": { "seeds": [
+                        { "kind": "arg", "path": "poll_id" },
+                    ] } },
+                    { "name": "system_program", "address": system_program::ID.to_string() },
+                ],
+            },
+            {
+                "name": "vote",
+                "discriminator": global_discriminator("vote"),
+                "args": [
+                    { "name": "_candidate_name", "type": "string" },
+                    { "name": "poll_id", "type": "u64" },
+                ],
+                "accounts": [
+                    { "name": "signer", "signer": true },
+                    // Derived from an account listed after it.
+                    { "name": "receipt", "writable": true, "pda": { "seeds": [
+                        { "kind": "const", "value": [114, 101, 99] },
+                        { "kind": "account", "path": "candidate" },
+                    ] } },
+                    { "name": "candidate", "writable": true, "pda": { "seeds": [
+                        { "kind": "arg", "path": "poll_id" },
+                        { "kind": "arg", "path": "candidate_name" },
+                    ] } },
+                ],
+            },
+        ] })
+    }
+
+    fn values(entries: Vec<(&'static str, ArgValue)>) -> BTreeMap<&'static str, ArgValue> {
+        entries.into_iter().collect()
+    }
+
+    fn pda(seeds: &[&[u8]]) -> Pubkey {
+        Pubkey::find_program_address(seeds, &PROGRAM).0
+    }
+
+    #[test]
+    fn builds_an_instruction_from_the_idl() {
+        let instruction = find_instruction(&voting_idl(), &["create_poll", "initialize_poll"])
+            .unwrap()
+            .build(
+                &PROGRAM,
+                &values(vec![
+                    ("poll_id", ArgValue::Int(7)),
+                    ("name", ArgValue::String("Mascot".to_string())),
+                    ("start", ArgValue::Int(1_700_000_000)),
+                ]),
+                &BTreeMap::new(),
+                &SIGNER,
+            )
+            .unwrap();
+
+        let mut data = global_discriminator("initialize_poll").to_vec();
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&6u32.to_le_bytes());
+        data.extend_from_slice(b"Mascot");
+        data.extend_from_slice(&1_700_000_000u64.to_le_bytes());
+        assert_eq!(instruction.program_id, PROGRAM);
+        assert_eq!(instruction.data, data);
+        assert_eq!(
+            instruction.accounts,
+            [
+                AccountMeta::new(SIGNER, true),
+                AccountMeta::new(pda(&[&7u64.to_le_bytes()]), false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_seeds_of_accounts_listed_later() {
+        let instruction = find_instruction(&voting_idl(), &["vote"])
+            .unwrap()
+            .build(
+                &PROGRAM,
+                &values(vec![
+                    ("candidate_name", ArgValue::String("Ada".to_string())),
+                    ("poll_id", ArgValue::Int(7)),
+                ]),
+                &BTreeMap::new(),
+                &SIGNER,
+            )
+            .unwrap();
+        let candidate = pda(&[&7u64.to_le_bytes(), b"Ada"]);
+        assert_eq!(
+            instruction.accounts,
+            [
+                AccountMeta::new_readonly(SIGNER, true),
+                AccountMeta::new(pda(&[b"rec", candidate.as_ref()]), false),
+                AccountMeta::new(candidate, false),
+            ]
+        );
+
+        // An override wins over the seeds, and the accounts derived from it follow.
+        let other = Pubkey::new_unique();
+        let instruction = find_instruction(&voting_idl(), &["vote"])
+            .unwrap()
+            .build(
+                &PROGRAM,
+                &values(vec![
+                    ("candidate_name", ArgValue::String("Ada".to_string())),
+                    ("poll_id", ArgValue::Int(7)),
+                ]),
+                &BTreeMap::from([("candidate".to_string(), other)]),
+                &SIGNER,
+            )
+            .unwrap();
+        assert_eq!(
+            instruction.accounts[1].pubkey,
+            pda(&[b"rec", other.as_ref()])
+        );
+        assert_eq!(instruction.accounts[2].pubkey, other);
+    }
+
+    #[test]
+    fn legacy_idls_derive_the_same_instruction() {
+        let legacy = json!({ "instructions": [{
+            "name": "initializePoll",
+            "args": [
+                { "name": "pollId", "type": "u64" },
+                { "name": "pollName", "type": "string" },
+                { "name": "pollStart", "type": "u64" },
+            ],
+            "accounts": [
+                { "name": "signer", "isMut": true, "isSigner": true },
+                { "name": "poll", "isMut": true },
+                { "name": "systemProgram", "isMut": false, "isSigner": false },
+            ],
+        }] });
+        let legacy = find_instruction(&legacy, &["initialize_poll"]).unwrap();
+        let current = find_instruction(&voting_idl(), &["initialize_poll"]).unwrap();
+        assert_eq!(legacy.discriminator, current.discriminator);
+        assert_eq!(legacy.args, current.args);
+
+        // Legacy IDLs don't give seeds: the PDA is passed in.
+        let poll = Pubkey::new_unique();
+        let values = values(vec![
+            ("poll_id", ArgValue::Int(7)),
+            ("name", ArgValue::String("Mascot".to_string())),
+            ("start", ArgValue::Int(1)),
+        ]);
+        let err = legacy
+            .build(&PROGRAM, &values, &BTreeMap::new(), &SIGNER)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("--account poll=<PUBKEY>"),
+            "{}",
+            err
+        );
+        let overrides = BTreeMap::from([("poll".to_string(), poll)]);
+        let built = legacy
+            .build(&PROGRAM, &values, &overrides, &SIGNER)
+            .unwrap();
+        let expected = current
+            .build(&PROGRAM, &values, &overrides, &SIGNER)
+            .unwrap();
+        assert_eq!(built, expected);
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_instructions() {
+        let err = find_instruction(&voting_idl(), &["close_poll"]).unwrap_err();
+        assert!(err.to_string().contains("close_poll"), "{}", err);
+        assert!(find_instruction(&json!({}), &["vote"]).is_err());
+
+        // A discriminator cut short, or out of byte range.
+        for discriminator in [json!([1, 2, 3]), json!([1, 2, 3, 4, 5, 6, 7, 256])] {
+            let idl = json!({ "instructions": [
+                { "name": "vote", "discriminator": discriminator, "args": [] },
+            ] });
+            assert!(find_instruction(&idl, &["vote"]).is_err());
+        }
+
+        let idl = json!({ "instructions": [
+            { "name": "vote", "args": [{ "name": "weights", "type": { "vec": "u8" } }] },
+        ] });
+        assert!(find_instruction(&idl, &["vote"]).is_err());
+        let idl = json!({ "instructions": [{ "name": "vote", "accounts": [
+            { "name": "poll", "pda": { "seeds": [{ "kind": "magic" }] } },
+        ] }] });
+        assert!(find_instruction(&idl, &["vote"]).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_mismatched_and_ambiguous_arguments() {
+        let instruction = find_instruction(&voting_idl(), &["initialize_poll"]).unwrap();
+        let build = |values| instruction.build(&PROGRAM, &values, &BTreeMap::new(), &SIGNER);
+
+        let complete = vec![
+            ("poll_id", ArgValue::Int(7)),
+            ("name", ArgValue::String("Mascot".to_string())),
+            ("start", ArgValue::Int(1)),
+        ];
+        assert!(build(values(complete.clone())).is_ok());
+        // Missing.
+        assert!(build(values(complete[..2].to_vec())).is_err());
+        // Out of range for a u64.
+        let mut negative = complete.clone();
+        negative[2].1 = ArgValue::Int(-1);
+        assert!(build(values(negative)).is_err());
+        // A string where a number is expected.
+        let mut mismatched = complete.clone();
+        mismatched[0].1 = ArgValue::String("7".to_string());
+        assert!(build(values(mismatched)).is_err());
+        // Both `poll` and `name` are part of `poll_name`.
+        let mut ambiguous = complete.clone();
+        ambiguous.push(("poll", ArgValue::String("Mascot".to_string())));
+        let err = build(values(ambiguous)).unwrap_err();
+        assert!(format!("{:#}", err).contains("could be meant"), "{:#}", err);
+    }
+
+    #[test]
+    fn converts_names_to_snake_case() {
+        assert_eq!(snake_case("initializePoll"), "initialize_poll");
+        assert_eq!(snake_case("initialize_poll"), "initialize_poll");
+        assert_eq!(snake_case("_candidateName"), "candidate_name");
+        assert_eq!(snake_case("Vote"), "vote");
+    }
+}
@@ -1,2 +1,43 @@
+pub mod api;
+pub mod bandwidth;
+pub mod candidates;
+pub mod checkpoint;
+pub mod clock;
+pub mod coalesce;
+pub mod completeness;
+pub mod components;
+pub mod config_audit;
+pub mod config_file;
 pub mod db;
+pub mod decode;
+pub mod dedup;
+pub mod errors;
+pub mod event_stream;
+pub mod failover;
+pub mod feed;
+pub mod fetch;
+pub mod filter_guard;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod handler;
+pub mod idl;
+pub mod idl_decode;
+#[cfg(feature = "writer-tools")]
+pub mod instructions;
+pub mod listener;
+pub mod live;
+pub mod manifest;
+pub mod metrics;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+pub mod quarantine;
+pub mod quota;
+pub mod replay;
+pub mod scheduler;
+pub mod setup;
+pub mod sink;
+pub mod slot_clock;
 pub mod state;
+pub mod storage;
+pub mod warmup;
+pub mod writer;
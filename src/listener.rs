@@ -0,0 +1,3576 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use clap::ValueEnum;
+use futures::future::{self, BoxFuture};
+use futures::stream::{self, BoxStream};
+use futures::StreamExt;
+use solana_account_decoder::{UiAccountData, UiAccountEncoding};
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::{
+        RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionLogsConfig,
+        RpcTransactionLogsFilter,
+    },
+    rpc_filter::{Memcmp, RpcFilterType},
+    rpc_response::{Response, RpcKeyedAccount, RpcLogsResponse},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use crate::api::ListenerHealth;
+use crate::bandwidth::{self, BandwidthMeter};
+use crate::checkpoint::{self, DEFAULT_CATCH_UP_MAX_TRANSACTIONS};
+use crate::clock::{Clock, Instant};
+use crate::db::db::PgPool;
+use crate::db::models::{
+    NewDecodeFailure, NewDelegation, NewEvent, NewIdlAccount, NewPoll, NewRawAccount,
+};
+use crate::decode::{decode_delegation, decode_poll, match_voting_account_type, VotingAccountType};
+use crate::dedup::AccountDedup;
+use crate::errors;
+use crate::event_stream::{EventStream, VotingEvent};
+use crate::failover::{self, Endpoints};
+use crate::fetch::{fetch_accounts, FetchConfig, FetchedAccount};
+use crate::filter_guard::{self, FilterGuard};
+use crate::handler::{AccountHandler, HandlerRegistry};
+use crate::idl_decode::IdlDecoder;
+use crate::metrics::Metrics;
+use crate::sink::{PollSink, PostgresSink, StdoutSink};
+use crate::slot_clock::SlotClock;
+use crate::state::events::parse_logs;
+use crate::state::lifecycle::{LifecycleFacts, PollLifecycle};
+use crate::state::pool::Poll;
+use crate::warmup::{Warmup, WarmupConfig, WarmupReport, WarmupState};
+use crate::writer::{self, WriterConfig};
+
+/// Default size of the duplicate-update cache (see [`AccountDedup`]).
+pub const DEFAULT_DEDUP_MAX_ENTRIES: usize = 10_000;
+/// Messages a websocket connection may forward ahead of the listener.
+const CONNECTION_BUFFER: usize = 1024;
+/// How long a refreshed connection may stay silent before the listener switches to it anyway.
+const REFRESH_SWITCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long closing a connection waits for the server to confirm the unsubscriptions.
+const UNSUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the programs' checkpoints move to the last slot processed.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the events of a slot wait for more logs of that slot before they're written, when
+/// no later slot's logs arrive first: about one slot.
+const SLOT_EVENTS_DELAY: Duration = Duration::from_millis(400);
+
+/// How the RPC encodes account data in subscription messages and backfill responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum AccountEncoding {
+    /// Plain base64.
+    #[default]
+    Base64,
+    /// Zstandard-compressed, then base64: less bandwidth for large accounts (like polls with a
+    /// long description), a little CPU to decompress.
+    #[value(name = "base64+zstd")]
+    Base64Zstd,
+}
+
+impl AccountEncoding {
+    /// Name used on the command line and in logs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountEncoding::Base64 => "base64",
+            AccountEncoding::Base64Zstd => "base64+zstd",
+        }
+    }
+
+    fn ui_encoding(self) -> UiAccountEncoding {
+        match self {
+            AccountEncoding::Base64 => UiAccountEncoding::Base64,
+            AccountEncoding::Base64Zstd => UiAccountEncoding::Base64Zstd,
+        }
+    }
+}
+
+/// Indexer for one or more voting programs: subscribes to their accounts (and optionally their
+/// transaction logs), backfills existing accounts, decodes everything and hands the result to a
+/// [`PollSink`].
+///
+/// It only needs a tokio runtime, so it can be embedded in another service:
+///
+/// ```no_run
+/// # async fn example(pool: voting_dapp_listener::db::db::PgPool) -> anyhow::Result<()> {
+/// use voting_dapp_listener::listener::Listener;
+///
+/// let listener = Listener::builder()
+///     .program_id("HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh".parse()?)
+///     .ws_url("wss://api.devnet.solana.com/")
+///     .db_pool(pool)
+///     .build()?;
+/// listener.run(tokio::signal::ctrl_c()).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Listener {
+    program_ids: Vec<Pubkey>,
+    /// The websocket endpoints, `ws_url` first, and their health (see [`Endpoints`]).
+    endpoints: Endpoints,
+    rpc_client: Arc<RpcClient>,
+    commitment: CommitmentConfig,
+    account_encoding: AccountEncoding,
+    only: Vec<VotingAccountType>,
+    with_logs: bool,
+    warmup: WarmupConfig,
+    strict_warmup: bool,
+    /// Decoders of the programs given an IDL, for the accounts without a table of their own.
+    idl_decoders: HashMap<Pubkey, IdlDecoder>,
+    sink: Arc<dyn PollSink>,
+    /// The writer spawned for [`ListenerBuilder::db_pool`], drained when `run` returns.
+    writer_task: Option<JoinHandle<()>>,
+    metrics: Arc<Metrics>,
+    health: Arc<ListenerHealth>,
+    meter: Arc<BandwidthMeter>,
+    /// See [`ListenerBuilder::slot_clock`].
+    slot_clock: Option<Arc<SlotClock>>,
+    /// See [`ListenerBuilder::clock`].
+    clock: Clock,
+    /// Data hashes of recently seen accounts; identical updates are skipped.
+    dedup: AccountDedup<ProcessedAccount>,
+    /// Spot-checks that the websocket endpoint honours the `only` filters.
+    filter_guard: FilterGuard,
+    /// Replace the websocket connection this long after it was opened (see
+    /// [`ListenerBuilder::subscription_refresh`]).
+    subscription_refresh: Option<Duration>,
+    /// Re-subscribe after this long without a message (see [`ListenerBuilder::stall_timeout`]).
+    stall_timeout: Option<Duration>,
+    /// See [`ListenerBuilder::catch_up_max_transactions`].
+    catch_up_max_transactions: usize,
+    /// See [`ListenerBuilder::archive_raw_accounts`].
+    archive_raw_accounts: bool,
+    /// Feeds the stream returned by [`Listener::events`], if it was called.
+    events: Option<mpsc::Sender<VotingEvent>>,
+    /// See [`ListenerBuilder::handler`].
+    handlers: HandlerRegistry,
+    /// Events of the slot whose logs are coming in, see [`Listener::handle_logs`].
+    slot_events: SlotEvents,
+}
+
+/// Builder of a [`Listener`], see [`Listener::builder`].
+pub struct ListenerBuilder {
+    program_ids: Vec<Pubkey>,
+    ws_url: Option<String>,
+    fallback_ws_urls: Vec<String>,
+    failover_cooldown: Duration,
+    rpc_url: Option<String>,
+    rpc_client: Option<Arc<RpcClient>>,
+    commitment: CommitmentConfig,
+    account_encoding: AccountEncoding,
+    only: Vec<VotingAccountType>,
+    with_logs: bool,
+    dedup_max_entries: usize,
+    filter_sample_rate: f64,
+    warmup: WarmupConfig,
+    strict_warmup: bool,
+    idl_decoders: HashMap<Pubkey, IdlDecoder>,
+    sink: Option<Arc<dyn PollSink>>,
+    db_pool: Option<PgPool>,
+    writer_config: WriterConfig,
+    metrics: Option<Arc<Metrics>>,
+    health: Option<Arc<ListenerHealth>>,
+    meter: Option<Arc<BandwidthMeter>>,
+    slot_clock: Option<Arc<SlotClock>>,
+    clock: Clock,
+    subscription_refresh: Option<Duration>,
+    stall_timeout: Option<Duration>,
+    catch_up_max_transactions: usize,
+    archive_raw_accounts: bool,
+    handlers: HandlerRegistry,
+}
+
+/// Why [`Listener::run`] returned.
+#[derive(Debug)]
+pub enum ListenerExit {
+    /// The `shutdown` future resolved.
+    Shutdown,
+    /// The server closed the websocket.
+    StreamClosed,
+    /// A program failed the warm-up check with strict warm-up enabled; carries the diagnostic.
+    WarmupFailed(String),
+}
+
+/// A message from one of the websocket subscriptions, tagged with the program it concerns.
+enum Update {
+    /// From `program_subscribe`, with the account type its filter guarantees (if any).
+    Account {
+        program_id: Pubkey,
+        known_type: Option<VotingAccountType>,
+        response: Response<RpcKeyedAccount>,
+    },
+    /// From `logs_subscribe` (`with_logs`).
+    Logs {
+        program_id: Pubkey,
+        response: Response<RpcLogsResponse>,
+    },
+}
+
+/// What `PubsubClient` returns to end a subscription.
+type Unsubscribe = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+/// A websocket connection with all its subscriptions, run by its own task.
+///
+/// The task owns the `PubsubClient` (the subscription streams borrow it) and forwards every
+/// message to `updates`, so the listener can hold two connections at once while refreshing its
+/// subscriptions.
+struct Connection {
+    /// Messages of every subscription, merged. Ends when the server closes the websocket, or
+    /// once the task has stopped after [`Connection::close`].
+    updates: mpsc::Receiver<Update>,
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Connects to `ws_url` and subscribes to the accounts of every program with each config of
+    /// `subscriptions`, and to their logs with `with_logs`. Fails if any subscription does.
+    async fn open(
+        ws_url: &str,
+        program_ids: Vec<Pubkey>,
+        subscriptions: Vec<(Option<VotingAccountType>, RpcProgramAccountsConfig)>,
+        with_logs: bool,
+        commitment: CommitmentConfig,
+    ) -> Result<Self> {
+        // Connect to Solana RPC WebSocket server using the async PubsubClient.
+        // This client manages a WebSocket connection to listen for events (e.g. account updates).
+        // Unlike the blocking version, this is fully async and cancelable
+        let client = PubsubClient::new(ws_url)
+            .await
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("Failed to connect to PubsubClient at {}", ws_url))?;
+
+        let (updates_tx, updates) = mpsc::channel(CONNECTION_BUFFER);
+        let (ready_tx, ready) = oneshot::channel::<Result<()>>();
+        let (stop, mut stop_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let (streams, unsubscribes) =
+                match subscribe_all(&client, &program_ids, &subscriptions, with_logs, commitment)
+                    .await
+                {
+                    Ok(subscribed) => {
+                        let _ = ready_tx.send(Ok(()));
+                        subscribed
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+
+            let mut stream = stream::select_all(streams);
+            let stopped = loop {
+                let update = tokio::select! {
+                    update = stream.next() => update,
+                    _ = &mut stop_rx => break true,
+                };
+                // `None`: the server closed the websocket.
+                let Some(update) = update else {
+                    break false;
+                };
+                if updates_tx.send(update).await.is_err() {
+                    break true;
+                }
+            };
+            // Ends `updates` as soon as the last message is read.
+            drop(updates_tx);
+
+            if stopped {
+                // Unsubscribing is a courtesy to the server: don't hang on it.
+                let unsubscribed = future::join_all(unsubscribes.into_iter().map(|u| u()));
+                if tokio::time::timeout(UNSUBSCRIBE_TIMEOUT, unsubscribed)
+                    .await
+                    .is_err()
+                {
+                    warn!("Server did not confirm the unsubscriptions in time");
+                }
+            }
+            // The streams borrow the client, so they go first.
+            drop(stream);
+            if let Err(e) = client.shutdown().await {
+                warn!(error = ?e, "Websocket client did not shut down cleanly");
+            }
+        });
+
+        match ready.await {
+            Ok(Ok(())) => Ok(Self {
+                updates,
+                stop: Some(stop),
+                task,
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => anyhow::bail!("websocket connection task stopped while subscribing"),
+        }
+    }
+
+    /// Asks the task to unsubscribe and disconnect. Messages it already forwarded can still be
+    /// received until `updates` ends.
+    fn close(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+
+    /// Closes the connection, dropping unread messages, and waits until it's disconnected.
+    async fn shut_down(mut self) {
+        self.close();
+        drop(self.updates);
+        if let Err(e) = self.task.await {
+            error!(error = ?e, "Websocket connection task panicked");
+        }
+    }
+}
+
+/// Subscribes to program-owned accounts using `program_subscribe`, once per program and
+/// subscription config, all on the same websocket connection, and with `with_logs` to the logs of
+/// every transaction mentioning each program.
+///
+/// Each call returns:
+/// - a `futures::Stream` of account changes (as `RpcResponse<RpcKeyedAccount>`)
+/// - a closure to unsubscribe (used when the connection is closed)
+///
+/// Every stream is tagged with its program and the account type its filter guarantees (if any),
+/// so the listener knows where an update came from and which decoder to use without re-matching.
+async fn subscribe_all<'a>(
+    client: &'a PubsubClient,
+    program_ids: &[Pubkey],
+    subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+    with_logs: bool,
+    commitment: CommitmentConfig,
+) -> Result<(Vec<BoxStream<'a, Update>>, Vec<Unsubscribe>)> {
+    let mut streams = Vec::with_capacity(program_ids.len() * (subscriptions.len() + 1));
+    let mut unsubscribes = Vec::with_capacity(streams.capacity());
+    for program_id in program_ids {
+        for (known_type, config) in subscriptions {
+            let (program_id, known_type) = (*program_id, *known_type);
+            let (stream, unsubscribe) = client
+                .program_subscribe(&program_id, Some(config.clone()))
+                .await
+                .map_err(anyhow::Error::from)
+                .with_context(|| format!("Failed to subscribe to program {}", program_id))?;
+            streams.push(
+                stream
+                    .map(move |response| Update::Account {
+                        program_id,
+                        known_type,
+                        response,
+                    })
+                    .boxed(),
+            );
+            unsubscribes.push(unsubscribe);
+        }
+    }
+
+    if with_logs {
+        for program_id in program_ids {
+            let program_id = *program_id;
+            let (stream, unsubscribe) = client
+                .logs_subscribe(
+                    RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+                    RpcTransactionLogsConfig {
+                        commitment: Some(commitment),
+                    },
+                )
+                .await
+                .map_err(anyhow::Error::from)
+                .with_context(|| {
+                    format!("Failed to subscribe to logs of program {}", program_id)
+                })?;
+            streams.push(
+                stream
+                    .map(move |response| Update::Logs {
+                        program_id,
+                        response,
+                    })
+                    .boxed(),
+            );
+            unsubscribes.push(unsubscribe);
+        }
+    }
+    Ok((streams, unsubscribes))
+}
+
+/// Receives the next message of `connection`; never resolves without one.
+async fn recv_from(connection: Option<&mut Connection>) -> Option<Update> {
+    match connection {
+        Some(connection) => connection.updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// What [`Listener::process_account`] made of an account.
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessedAccount {
+    pub account_type: VotingAccountType,
+    /// The first 8 bytes of the data, when there were that many.
+    pub discriminator: Option<[u8; 8]>,
+    /// Whether the account was recognized and decoded. Without an IDL, candidates and votes
+    /// aren't decoded, so they count as soon as their discriminator is recognized.
+    pub decoded: bool,
+    /// Whether every write it led to was accepted by the sink. The dedup cache only remembers
+    /// updates that were, so a redelivery of one whose write failed is processed again.
+    pub persisted: bool,
+}
+
+impl ProcessedAccount {
+    /// An update whose data couldn't even be read.
+    fn failed(known_type: Option<VotingAccountType>) -> Self {
+        Self {
+            account_type: known_type.unwrap_or(VotingAccountType::Unknown),
+            discriminator: None,
+            decoded: false,
+            persisted: true,
+        }
+    }
+
+    /// A closed account: there's nothing left to decode, but the update is understood.
+    fn closed(known_type: Option<VotingAccountType>, persisted: bool) -> Self {
+        Self {
+            account_type: known_type.unwrap_or(VotingAccountType::Unknown),
+            discriminator: None,
+            decoded: true,
+            persisted,
+        }
+    }
+}
+
+/// The events logged in one slot so far, written to the sink together once the slot is over.
+#[derive(Default)]
+struct SlotEvents {
+    events: Vec<NewEvent>,
+    /// When they're written even if no later slot's logs arrived by then.
+    due: Option<Instant>,
+}
+
+/// Per-type counters collected while backfilling.
+#[derive(Default)]
+struct BackfillSummary {
+    polls: usize,
+    candidates: usize,
+    votes: usize,
+    delegations: usize,
+    unknown: usize,
+    /// Accounts returned despite not matching the filter they were requested with.
+    filter_mismatches: usize,
+}
+
+impl BackfillSummary {
+    fn record(&mut self, account_type: VotingAccountType) {
+        match account_type {
+            VotingAccountType::Poll => self.polls += 1,
+            VotingAccountType::Candidate => self.candidates += 1,
+            VotingAccountType::Vote => self.votes += 1,
+            VotingAccountType::Delegation => self.delegations += 1,
+            VotingAccountType::Unknown => self.unknown += 1,
+        }
+    }
+}
+
+/// An account of the backfill snapshot, waiting for its turn to be processed.
+struct BackfillAccount {
+    pubkey: Pubkey,
+    data: Vec<u8>,
+    /// The slot the snapshot was requested at.
+    slot: u64,
+    /// The type its (verified) filter guarantees.
+    known_type: Option<VotingAccountType>,
+}
+
+impl BackfillAccount {
+    fn account_type(&self) -> VotingAccountType {
+        self.known_type
+            .unwrap_or_else(|| match_voting_account_type(&self.data))
+    }
+}
+
+/// Backfill order of a group of accounts, lowest first: active polls first, then polls that can
+/// still be voted on, then the past. Accounts not tied to a poll (`None`) come last.
+fn backfill_priority(group: Option<PollLifecycle>) -> u8 {
+    match group {
+        Some(PollLifecycle::Active) => 0,
+        Some(PollLifecycle::Upcoming) => 1,
+        Some(PollLifecycle::Draft) => 2,
+        Some(PollLifecycle::Ended) => 3,
+        Some(PollLifecycle::Finalized) => 4,
+        Some(PollLifecycle::Closed) => 5,
+        None => 6,
+    }
+}
+
+/// The lifecycle of a decoded poll at unix time `now`. The account exists, so it isn't closed.
+fn poll_lifecycle(poll: &Poll, now: i64) -> PollLifecycle {
+    LifecycleFacts {
+        poll_start: poll.poll_start as i64,
+        poll_end: poll.poll_end as i64,
+        candidate_amount: poll.candidate_amount as i64,
+        winner_declared: poll.candidate_winner != Pubkey::default(),
+        closed: false,
+    }
+    .derive(now)
+}
+
+/// The checkpoint of each program: its last slot processed, or the slot before the first
+/// update the sink refused if there was one. A program that can't checkpoint past its first
+/// slot keeps the checkpoint it had.
+fn checkpoint_slots(
+    last_slots: &HashMap<Pubkey, u64>,
+    refused_slots: &HashMap<Pubkey, u64>,
+) -> HashMap<Pubkey, u64> {
+    last_slots
+        .iter()
+        .filter_map(|(program_id, &slot)| match refused_slots.get(program_id) {
+            Some(&refused) => refused
+                .checked_sub(1)
+                .map(|before| (*program_id, slot.min(before))),
+            None => Some((*program_id, slot)),
+        })
+        .collect()
+}
+
+/// Saves the checkpoint of a program (see [`PollSink::save_checkpoint`]). A checkpoint that
+/// couldn't be saved only means a longer catch-up on the next start: it's logged and skipped.
+async fn save_checkpoint(sink: &dyn PollSink, program_id: &Pubkey, slot: u64) {
+    match sink.save_checkpoint(program_id, slot).await {
+        Ok(()) => debug!(%program_id, slot, "Checkpoint saved"),
+        Err(e) => warn!(%program_id, slot, error = ?e, "Failed to save the checkpoint"),
+    }
+}
+
+/// Returns the HTTP RPC URL matching a websocket URL.
+/// `wss://host/` becomes `https://host/` and `ws://host/` becomes `http://host/`.
+///
+/// A Solana node serves its websocket one port above its RPC port, so an explicit port is
+/// lowered by one: `ws://localhost:8900` (`solana-test-validator`) becomes
+/// `http://localhost:8899`. Ports 80 and 443 are kept, as behind a proxy both share them.
+/// Endpoints laid out otherwise need `rpc_url`.
+pub fn rpc_url_for(ws_url: &str) -> String {
+    let (scheme, rest) = if let Some(rest) = ws_url.strip_prefix("wss://") {
+        ("https", rest)
+    } else if let Some(rest) = ws_url.strip_prefix("ws://") {
+        ("http", rest)
+    } else {
+        return ws_url.to_string();
+    };
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    let authority = match authority.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) if port > 0 && port != 80 && port != 443 => {
+                format!("{}:{}", host, port - 1)
+            }
+            _ => authority.to_string(),
+        },
+        None => authority.to_string(),
+    };
+    format!("{}://{}{}", scheme, authority, path)
+}
+
+impl ListenerBuilder {
+    /// Adds a program whose accounts should be indexed. Duplicates are ignored.
+    pub fn program_id(mut self, program_id: Pubkey) -> Self {
+        if !self.program_ids.contains(&program_id) {
+            self.program_ids.push(program_id);
+        }
+        self
+    }
+
+    /// Adds several programs, see [`ListenerBuilder::program_id`].
+    pub fn program_ids(self, program_ids: impl IntoIterator<Item = Pubkey>) -> Self {
+        program_ids.into_iter().fold(self, Self::program_id)
+    }
+
+    /// Websocket endpoint used for the subscriptions. Required.
+    pub fn ws_url(mut self, ws_url: impl Into<String>) -> Self {
+        self.ws_url = Some(ws_url.into());
+        self
+    }
+
+    /// Websocket endpoints to fail over to when the one in use disconnects, in order of
+    /// preference. None by default: `run` then returns when the `ws_url` connection closes.
+    ///
+    /// The listener goes on through the healthiest endpoint out of cooldown (see
+    /// [`Endpoints`]), then catches up on what it may have missed like at startup. The HTTP
+    /// endpoint doesn't change.
+    pub fn fallback_ws_urls(mut self, urls: impl IntoIterator<Item = String>) -> Self {
+        self.fallback_ws_urls.extend(urls);
+        self
+    }
+
+    /// How long an endpoint that failed is skipped, doubling with each failure in a row
+    /// ([`failover::DEFAULT_COOLDOWN`] by default).
+    pub fn failover_cooldown(mut self, cooldown: Duration) -> Self {
+        self.failover_cooldown = cooldown;
+        self
+    }
+
+    /// HTTP endpoint used for the backfill, derived from the websocket URL if omitted.
+    pub fn rpc_url(mut self, rpc_url: impl Into<String>) -> Self {
+        self.rpc_url = Some(rpc_url.into());
+        self
+    }
+
+    /// Shares an existing HTTP client instead of building one from the RPC URL.
+    pub fn rpc_client(mut self, rpc_client: Arc<RpcClient>) -> Self {
+        self.rpc_client = Some(rpc_client);
+        self
+    }
+
+    /// Commitment of the subscriptions and the backfill (finalized by default).
+    pub fn commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Encoding of the account data sent by the RPC ([`AccountEncoding::Base64`] by default).
+    pub fn account_encoding(mut self, encoding: AccountEncoding) -> Self {
+        self.account_encoding = encoding;
+        self
+    }
+
+    /// Only receive these account types, filtered server-side. Empty (the default) means all.
+    pub fn only(mut self, only: impl IntoIterator<Item = VotingAccountType>) -> Self {
+        self.only = only.into_iter().collect();
+        self
+    }
+
+    /// Also subscribe to the programs' transaction logs and record their events.
+    pub fn with_logs(mut self, with_logs: bool) -> Self {
+        self.with_logs = with_logs;
+        self
+    }
+
+    /// Size of the duplicate-update cache; 0 disables it.
+    pub fn dedup_max_entries(mut self, max_entries: usize) -> Self {
+        self.dedup_max_entries = max_entries;
+        self
+    }
+
+    /// Share (0-1) of server-filtered messages whose discriminator is still checked, to catch
+    /// providers that ignore filters (see [`FilterGuard`]).
+    pub fn filter_sample_rate(mut self, rate: f64) -> Self {
+        self.filter_sample_rate = rate;
+        self
+    }
+
+    pub fn warmup(mut self, warmup: WarmupConfig) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Stop `run` with [`ListenerExit::WarmupFailed`] when a warm-up check fails.
+    pub fn strict_warmup(mut self, strict: bool) -> Self {
+        self.strict_warmup = strict;
+        self
+    }
+
+    /// Decodes the accounts of `program_id` that have no table of their own (candidates, votes
+    /// and any other account its IDL defines) with `decoder`, and writes them as JSON.
+    /// Without one they're only logged.
+    pub fn idl_decoder(mut self, program_id: Pubkey, decoder: IdlDecoder) -> Self {
+        self.idl_decoders.insert(program_id, decoder);
+        self
+    }
+
+    /// Adds several decoders, see [`ListenerBuilder::idl_decoder`].
+    pub fn idl_decoders(self, decoders: impl IntoIterator<Item = (Pubkey, IdlDecoder)>) -> Self {
+        decoders
+            .into_iter()
+            .fold(self, |builder, (program_id, decoder)| {
+                builder.idl_decoder(program_id, decoder)
+            })
+    }
+
+    /// Runs `handler` for every decoded account whose data starts with `discriminator`, after
+    /// the sink got it (see [`AccountHandler`]). For the voting accounts, use
+    /// [`VotingAccountType::discriminator`]; other accounts of a program given its IDL can be
+    /// handled by their own discriminator.
+    pub fn handler(mut self, discriminator: [u8; 8], handler: Arc<dyn AccountHandler>) -> Self {
+        self.handlers.register(discriminator, handler);
+        self
+    }
+
+    /// Where decoded accounts go. Defaults to [`StdoutSink`] unless a `db_pool` is given.
+    pub fn sink(mut self, sink: Arc<dyn PollSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Writes to this database through a batching writer owned by the listener.
+    /// The schema isn't checked here, see [`crate::db::migrations::check_schema`].
+    pub fn db_pool(mut self, pool: PgPool) -> Self {
+        self.db_pool = Some(pool);
+        self
+    }
+
+    /// Batching knobs of the writer spawned for `db_pool`.
+    pub fn writer_config(mut self, config: WriterConfig) -> Self {
+        self.writer_config = config;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Status shared with the HTTP API's `/health`.
+    pub fn health(mut self, health: Arc<ListenerHealth>) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Counts the bytes received from the RPC provider.
+    pub fn bandwidth_meter(mut self, meter: Arc<BandwidthMeter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Translates slots to wall time: the time of a backfill snapshot's slot classifies its
+    /// polls, and the `slot_lag_seconds` metric is the age of each update's slot. Without one,
+    /// snapshots are classified at the current time and the lag isn't measured.
+    pub fn slot_clock(mut self, clock: Arc<SlotClock>) -> Self {
+        self.slot_clock = Some(clock);
+        self
+    }
+
+    /// Where the listener's timers and wall time come from, the system's by default (see
+    /// [`Clock`]). Also the clock of the writer and sink spawned for `db_pool`.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Replaces the websocket connection this long after it was opened, for providers whose
+    /// subscriptions degrade silently after many hours. `None` (the default) never does.
+    ///
+    /// The new connection subscribes while the current one keeps running; the listener switches
+    /// once the new one delivers its first message (or after 30 seconds of silence), then reads
+    /// what the old one still had before closing it. Updates both delivered are skipped by the
+    /// duplicate-update cache, so there's no gap and no double write.
+    pub fn subscription_refresh(mut self, interval: Option<Duration>) -> Self {
+        self.subscription_refresh = interval;
+        self
+    }
+
+    /// Re-subscribes when no message arrived for this long, since a websocket can go stale
+    /// without ever closing. `None` (the default) never does.
+    ///
+    /// The stalled endpoint counts as failed: with [`ListenerBuilder::fallback_ws_urls`] the
+    /// listener fails over, otherwise (or when every other endpoint is cooling down) it
+    /// re-subscribes through the same endpoint. Either way it
+    /// then catches up on what it may have missed, like at startup. Programs can be quiet for a
+    /// while, so this should be well above the longest expected gap between updates.
+    pub fn stall_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// Most transactions since a program's checkpoint the listener catches up on at startup
+    /// (1000 by default); beyond that, or without a checkpoint, it backfills the program in
+    /// full. 0 always backfills in full.
+    ///
+    /// The sink keeps the checkpoint: the last slot up to which every update of the program was
+    /// stored (only [`PostgresSink`] and [`MemorySink`](crate::sink::MemorySink) keep one).
+    /// Catching up fetches the transactions since then and the accounts they wrote, instead of
+    /// every account of the program.
+    pub fn catch_up_max_transactions(mut self, max_transactions: usize) -> Self {
+        self.catch_up_max_transactions = max_transactions;
+        self
+    }
+
+    /// Also hands every raw account update to the sink before decoding it (see
+    /// [`PollSink::write_raw_account`]), so the derived tables can be rebuilt from the archive
+    /// after a schema or decoder change (`cli replay`). Off by default: the archive grows with
+    /// every update.
+    pub fn archive_raw_accounts(mut self, archive: bool) -> Self {
+        self.archive_raw_accounts = archive;
+        self
+    }
+
+    /// Validates the configuration. With `db_pool`, this spawns the writer task and must
+    /// be called from within a tokio runtime.
+    pub fn build(self) -> Result<Listener> {
+        if self.program_ids.is_empty() {
+            anyhow::bail!("at least one program ID is required");
+        }
+        let ws_url = self.ws_url.context("a websocket URL is required")?;
+        if let Some(program_id) = self
+            .idl_decoders
+            .keys()
+            .find(|program_id| !self.program_ids.contains(program_id))
+        {
+            anyhow::bail!(
+                "an IDL decoder was given for {}, which isn't indexed",
+                program_id
+            );
+        }
+        let metrics = match self.metrics {
+            Some(metrics) => metrics,
+            None => Arc::new(Metrics::new()?),
+        };
+        let meter = self.meter.unwrap_or_default();
+        let rpc_client = self.rpc_client.unwrap_or_else(|| {
+            let rpc_url = self.rpc_url.unwrap_or_else(|| rpc_url_for(&ws_url));
+            Arc::new(bandwidth::counting_rpc_client(
+                rpc_url,
+                self.commitment,
+                meter.clone(),
+            ))
+        });
+
+        let (sink, writer_task): (Arc<dyn PollSink>, _) = match (self.sink, self.db_pool) {
+            (Some(_), Some(_)) => anyhow::bail!("a listener takes either a sink or a db_pool"),
+            (Some(sink), None) => (sink, None),
+            (None, Some(pool)) => {
+                tokio::runtime::Handle::try_current()
+                    .context("Listener::build with a db_pool must run inside a tokio runtime")?;
+                let config = WriterConfig {
+                    clock: self.clock,
+                    ..self.writer_config
+                };
+                let (writer, task) =
+                    writer::spawn_poll_writer(pool.clone(), config, metrics.clone());
+                let sink = PostgresSink::new(pool, writer)
+                    .with_metrics(metrics.clone())
+                    .with_clock(self.clock);
+                (Arc::new(sink), Some(task))
+            }
+            (None, None) => (Arc::new(StdoutSink), None),
+        };
+
+        Ok(Listener {
+            program_ids: self.program_ids,
+            rpc_client,
+            commitment: self.commitment,
+            account_encoding: self.account_encoding,
+            only: self.only,
+            with_logs: self.with_logs,
+            warmup: WarmupConfig {
+                messages: self.warmup.messages.max(1),
+                ..self.warmup
+            },
+            strict_warmup: self.strict_warmup,
+            idl_decoders: self.idl_decoders,
+            sink,
+            writer_task,
+            metrics,
+            health: self.health.unwrap_or_default(),
+            meter,
+            slot_clock: self.slot_clock,
+            clock: self.clock,
+            dedup: AccountDedup::new(self.dedup_max_entries),
+            filter_guard: FilterGuard::new(
+                self.filter_sample_rate,
+                &bandwidth::endpoint_label(&ws_url),
+            ),
+            subscription_refresh: self.subscription_refresh,
+            stall_timeout: self.stall_timeout,
+            catch_up_max_transactions: self.catch_up_max_transactions,
+            archive_raw_accounts: self.archive_raw_accounts,
+            events: None,
+            handlers: self.handlers,
+            slot_events: SlotEvents::default(),
+            endpoints: Endpoints::new(
+                std::iter::once(ws_url).chain(self.fallback_ws_urls),
+                self.failover_cooldown,
+            ),
+        })
+    }
+}
+
+impl Listener {
+    pub fn builder() -> ListenerBuilder {
+        ListenerBuilder {
+            program_ids: Vec::new(),
+            ws_url: None,
+            fallback_ws_urls: Vec::new(),
+            failover_cooldown: failover::DEFAULT_COOLDOWN,
+            rpc_url: None,
+            rpc_client: None,
+            commitment: CommitmentConfig::finalized(),
+            account_encoding: AccountEncoding::default(),
+            only: Vec::new(),
+            with_logs: false,
+            dedup_max_entries: DEFAULT_DEDUP_MAX_ENTRIES,
+            filter_sample_rate: filter_guard::DEFAULT_SAMPLE_RATE,
+            warmup: WarmupConfig::default(),
+            strict_warmup: false,
+            idl_decoders: HashMap::new(),
+            sink: None,
+            db_pool: None,
+            writer_config: WriterConfig::default(),
+            metrics: None,
+            health: None,
+            meter: None,
+            slot_clock: None,
+            clock: Clock::system(),
+            subscription_refresh: None,
+            stall_timeout: None,
+            catch_up_max_transactions: DEFAULT_CATCH_UP_MAX_TRANSACTIONS,
+            archive_raw_accounts: false,
+            handlers: HandlerRegistry::default(),
+        }
+    }
+
+    /// Returns a stream of every account update the listener decodes, as [`VotingEvent`]s.
+    /// Call it before [`Listener::run`]; the stream ends once `run` returned.
+    ///
+    /// The events are yielded on top of what goes to the sink, so a listener built without
+    /// `sink` or `db_pool` needs no database at all:
+    ///
+    /// ```no_run
+    /// # async fn example() -> anyhow::Result<()> {
+    /// use futures::StreamExt;
+    /// use voting_dapp_listener::event_stream::VotingEvent;
+    /// use voting_dapp_listener::listener::Listener;
+    ///
+    /// let mut listener = Listener::builder()
+    ///     .program_id("HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh".parse()?)
+    ///     .ws_url("wss://api.devnet.solana.com/")
+    ///     .build()?;
+    /// let mut events = listener.events();
+    /// tokio::spawn(listener.run(tokio::signal::ctrl_c()));
+    /// while let Some(event) = events.next().await {
+    ///     if let VotingEvent::PollUpdated { poll, .. } = event {
+    ///         println!("{} ends at {}", poll.poll_name, poll.poll_end);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The stream is bounded ([`EVENT_BUFFER`](crate::event_stream::EVENT_BUFFER)): when the
+    /// consumer falls behind, the listener waits for it. Calling `events` again ends the
+    /// previous stream.
+    pub fn events(&mut self) -> EventStream {
+        let (sender, stream) = EventStream::channel();
+        self.events = Some(sender);
+        stream
+    }
+
+    /// Runs the listener until `shutdown` resolves, the server closes the websocket (with no
+    /// endpoint left to fail over to), or a strict warm-up check fails.
+    ///
+    /// Only connecting and subscribing can fail; once the listener runs, every error is logged
+    /// and the update skipped. On return every subscription is closed and the sink dropped;
+    /// a writer spawned for `db_pool` has flushed everything it was sent.
+    pub async fn run(mut self, shutdown: impl Future) -> Result<ListenerExit> {
+        // Step 1: Build the subscription configs for program accounts.
+        // Without `only` this is a single unfiltered subscription. With `only`, each selected
+        // account type gets its own subscription filtered server-side on its discriminator, so the
+        // RPC node never sends us accounts we don't care about.
+        // The same configs are reused for the startup backfill so both paths see identical data.
+        let subscriptions = subscription_plan(&self.only, self.commitment, self.account_encoding);
+
+        // Steps 2 and 3: Connect to the websocket and subscribe to every program (and to its logs
+        // with `with_logs`), see `Connection::open`. The connection runs in its own task and hands
+        // us the messages of all its subscriptions, merged, tagged with the program they concern.
+        // If connecting or subscribing fails (e.g. network issue, bad program ID) through every
+        // endpoint, so does `run`.
+        let program_ids = self.program_ids.clone();
+        let mut connection = self.connect(&subscriptions).await?;
+
+        // Every byte received from the RPC provider is counted per endpoint, since that's what we're billed on.
+        let mut ws_endpoint = bandwidth::endpoint_label(self.endpoints.current());
+
+        info!(
+            programs = ?program_ids.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            subscriptions = program_ids.len() * subscriptions.len(),
+            with_logs = self.with_logs,
+            "Listening for state changes"
+        );
+
+        // Step 4: Backfill accounts that already exist on-chain.
+        // The websocket only reports accounts that change *after* we subscribe, so polls created
+        // while the listener was offline would never reach the sink.
+        // The subscription above is opened first on purpose: any update that lands while the
+        // snapshot is being fetched is buffered by the connection and applied right after, so nothing is lost.
+        // A program with a recent checkpoint only catches up on the accounts changed since
+        // (see `catch_up`); the others, or when that fails, get the full snapshot.
+        self.sync(&subscriptions).await;
+
+        // Warm-up: the first messages of each program (or those of the first seconds) are checked
+        // strictly. If too few of them are decodable voting accounts, the program ID most likely
+        // points at some other program (see `report_warmup`).
+        let clock = self.clock;
+        let warmup_start = clock.instant();
+        let mut warmups: HashMap<Pubkey, Warmup> = program_ids
+            .iter()
+            .map(|program_id| (*program_id, Warmup::new(self.warmup, warmup_start)))
+            .collect();
+        for (program_id, warmup) in &warmups {
+            self.health.record_warmup(program_id, warmup.report());
+        }
+
+        // Identical redelivered account states skip decoding and persistence. The cache starts
+        // empty after the backfill above; it must be cleared again if updates could be missed.
+        self.dedup.clear();
+
+        // Subscription refresh (see `ListenerBuilder::subscription_refresh`): the connection that
+        // will replace the current one, until it takes over, then the replaced one, until it has
+        // handed over everything it received.
+        let mut incoming: Option<(Connection, Instant)> = None;
+        let mut outgoing: Option<Connection> = None;
+        let mut refresh_at = self.next_refresh();
+
+        // Stall watchdog (see `ListenerBuilder::stall_timeout`): when the last message of any
+        // connection arrived.
+        let mut last_message = Instant::now();
+
+        // The last slot processed of each program, saved as its checkpoint every
+        // `CHECKPOINT_INTERVAL` by a task of its own (saving waits for the writer), and once
+        // more on the way out. An update the sink refused holds its program's checkpoint below
+        // its slot for the rest of the run, so the next start catches up on it.
+        let mut last_slots: HashMap<Pubkey, u64> = HashMap::new();
+        let mut refused_slots: HashMap<Pubkey, u64> = HashMap::new();
+        let mut checkpoint_task: Option<JoinHandle<()>> = None;
+        let mut checkpoint_due = Instant::now() + CHECKPOINT_INTERVAL;
+
+        // Step 5: Use `tokio::select!` to wait for either:
+        // 1. The stream finishing (due to RPC server closing connection), or a failed strict
+        //    warm-up
+        // 2. The `shutdown` future resolving (e.g. Ctrl+C in the binary)
+        let exit = tokio::select! {
+            // Loop over incoming updates (stream is an async stream of account changes)
+            // As long as messages are coming in, this loop runs and processes them one by one.
+            // Until every warm-up has finished, the loop also wakes up at the warm-up deadline.
+            exit = async {
+                loop {
+                    let warmup_deadline = warmups
+                        .values()
+                        .filter(|warmup| !warmup.is_finished())
+                        .map(Warmup::deadline)
+                        .min();
+                    let switch_deadline = incoming.as_ref().map(|(_, deadline)| *deadline);
+                    let refresh_deadline = if incoming.is_none() && outgoing.is_none() {
+                        refresh_at
+                    } else {
+                        None
+                    };
+                    // A refresh in progress has its own timeout.
+                    let stall_deadline = match (self.stall_timeout, &incoming) {
+                        (Some(timeout), None) => Some(last_message + timeout),
+                        _ => None,
+                    };
+                    let slot_events_deadline = self.slot_events.due;
+                    let update = tokio::select! {
+                        update = connection.updates.recv() => match update {
+                            Some(update) => update,
+                            None => match incoming.take() {
+                                // Closed by the server mid-refresh: the new connection takes over.
+                                Some((next, _)) => {
+                                    outgoing = Some(self.switch_connection(&mut connection, next, "closed"));
+                                    refresh_at = self.next_refresh();
+                                    continue;
+                                }
+                                // Updates may have been missed until the next endpoint subscribed:
+                                // catch up on them like at startup, and forget what was seen.
+                                None => match self.fail_over(&subscriptions).await {
+                                    Some(next) => {
+                                        std::mem::replace(&mut connection, next).shut_down().await;
+                                        ws_endpoint = bandwidth::endpoint_label(self.endpoints.current());
+                                        self.sync(&subscriptions).await;
+                                        self.dedup.clear();
+                                        refresh_at = self.next_refresh();
+                                        last_message = Instant::now();
+                                        continue;
+                                    }
+                                    None => return ListenerExit::StreamClosed,
+                                },
+                            },
+                        },
+                        // Until the new connection delivers its first message, both run: whatever
+                        // they both deliver is skipped the second time by `dedup`.
+                        update = recv_from(incoming.as_mut().map(|(next, _)| next)) => {
+                            let (next, _) = incoming.take().expect("received from it");
+                            match update {
+                                Some(update) => {
+                                    outgoing = Some(self.switch_connection(&mut connection, next, "switched"));
+                                    refresh_at = self.next_refresh();
+                                    update
+                                }
+                                None => {
+                                    self.refresh_failed(anyhow::anyhow!("closed before its first message"));
+                                    refresh_at = self.next_refresh();
+                                    continue;
+                                }
+                            }
+                        }
+                        // A quiet program may send nothing for a while: the new connection is
+                        // subscribed, so switching without a message loses nothing either.
+                        _ = clock.sleep_until(switch_deadline) => {
+                            let (next, _) = incoming.take().expect("has a deadline");
+                            outgoing = Some(self.switch_connection(&mut connection, next, "timed_out"));
+                            refresh_at = self.next_refresh();
+                            continue;
+                        }
+                        // The replaced connection's messages are processed until it's closed,
+                        // so nothing it received before the switch is lost.
+                        update = recv_from(outgoing.as_mut()) => match update {
+                            Some(update) => update,
+                            None => {
+                                if let Some(replaced) = outgoing.take() {
+                                    replaced.shut_down().await;
+                                }
+                                debug!("Replaced websocket connection closed");
+                                continue;
+                            }
+                        },
+                        _ = clock.sleep_until(refresh_deadline) => {
+                            info!(event = "subscription_refresh_started", "Refreshing the subscriptions");
+                            match self.open_connection(&subscriptions).await {
+                                Ok(next) => {
+                                    incoming = Some((next, Instant::now() + REFRESH_SWITCH_TIMEOUT))
+                                }
+                                Err(e) => {
+                                    self.refresh_failed(e);
+                                    refresh_at = self.next_refresh();
+                                }
+                            }
+                            continue;
+                        }
+                        // Stale without closing: replace the connection the same way.
+                        _ = clock.sleep_until(stall_deadline) => {
+                            match self.resubscribe(&subscriptions, last_message.elapsed()).await {
+                                Ok(next) => {
+                                    std::mem::replace(&mut connection, next).shut_down().await;
+                                    ws_endpoint = bandwidth::endpoint_label(self.endpoints.current());
+                                    self.sync(&subscriptions).await;
+                                    self.dedup.clear();
+                                    refresh_at = self.next_refresh();
+                                }
+                                Err(e) => error!(
+                                    event = "websocket_resubscribe_failed",
+                                    error = ?e,
+                                    "Re-subscribing failed, keeping the stalled connection"
+                                ),
+                            }
+                            // Either way, the watchdog starts over.
+                            last_message = Instant::now();
+                            continue;
+                        }
+                        // No later slot's logs came: the slot is over all the same.
+                        _ = clock.sleep_until(slot_events_deadline) => {
+                            self.flush_slot_events().await;
+                            continue;
+                        }
+                        _ = clock.sleep_until(warmup_deadline) => {
+                            let now = Instant::now();
+                            for (program_id, warmup) in warmups.iter_mut() {
+                                if warmup.deadline() > now {
+                                    continue;
+                                }
+                                if let Some(report) = warmup.expire() {
+                                    if let Some(diagnostic) = self.report_warmup(program_id, report) {
+                                        return ListenerExit::WarmupFailed(diagnostic);
+                                    }
+                                }
+                            }
+                            continue;
+                        }
+                    };
+                    last_message = Instant::now();
+                    match update {
+                        Update::Account { program_id, known_type, response } => {
+                            let slot = response.context.slot;
+                            self.meter.record_message(&ws_endpoint, &response);
+                            self.health.record_slot(slot);
+                            self.metrics.last_processed_slot.set(slot as i64);
+                            if let Some(time) = self.slot_time(slot) {
+                                self.metrics
+                                    .slot_lag_seconds
+                                    .set(clock.unix_now() - time);
+                            }
+                            // Process each account update (e.g. decode poll state and print info)
+                            let processed =
+                                self.handle_response(response, &program_id, known_type).await;
+                            let last_slot = last_slots.entry(program_id).or_default();
+                            *last_slot = (*last_slot).max(slot);
+                            if !processed.persisted {
+                                let refused = refused_slots.entry(program_id).or_insert(slot);
+                                *refused = (*refused).min(slot);
+                            }
+                            let now = Instant::now();
+                            if now >= checkpoint_due
+                                && checkpoint_task.as_ref().is_none_or(|task| task.is_finished())
+                            {
+                                checkpoint_task = Some(
+                                    self.spawn_checkpoints(checkpoint_slots(&last_slots, &refused_slots)),
+                                );
+                                checkpoint_due = now + CHECKPOINT_INTERVAL;
+                            }
+                            let report = warmups.get_mut(&program_id).and_then(|warmup| {
+                                warmup.record(processed.discriminator, processed.decoded, Instant::now())
+                            });
+                            if let Some(report) = report {
+                                if let Some(diagnostic) = self.report_warmup(&program_id, report) {
+                                    return ListenerExit::WarmupFailed(diagnostic);
+                                }
+                            }
+                        }
+                        Update::Logs { program_id, response } => {
+                            self.meter.record_message(&ws_endpoint, &response);
+                            self.handle_logs(response, &program_id).await;
+                        }
+                    }
+                }
+            } => {
+                match &exit {
+                    ListenerExit::StreamClosed => warn!("Websocket stream closed by the server"),
+                    ListenerExit::WarmupFailed(_) => {
+                        error!("Warm-up failed with strict warm-up, shutting down...")
+                    }
+                    ListenerExit::Shutdown => {}
+                }
+                exit
+            }
+            // If shutdown is requested, we break the listener loop and begin shutdown.
+            _ = shutdown => ListenerExit::Shutdown,
+        };
+
+        // Step 6: Gracefully shut down the WebSocket connections: each task unsubscribes, drops
+        // its streams (they borrow its `PubsubClient`) and shuts the client down.
+        for connection in std::iter::once(connection)
+            .chain(incoming.map(|(next, _)| next))
+            .chain(outgoing)
+        {
+            connection.shut_down().await;
+        }
+        self.health.set_websocket_connected(false);
+        self.metrics.websocket_connected.set(0);
+        info!(
+            duplicates_skipped = self.dedup.skipped(),
+            "Identical account updates skipped"
+        );
+
+        // Everything processed has been handed to the sink: checkpoint it.
+        self.flush_slot_events().await;
+        if let Some(task) = checkpoint_task {
+            let _ = task.await;
+        }
+        for (program_id, slot) in checkpoint_slots(&last_slots, &refused_slots) {
+            save_checkpoint(self.sink.as_ref(), &program_id, slot).await;
+        }
+
+        // The source is stopped: drop the sink too, which closes the writer channel. A writer
+        // spawned by the builder is drained here; one owned by the caller is theirs to await.
+        let Listener {
+            sink, writer_task, ..
+        } = self;
+        drop(sink);
+        if let Some(task) = writer_task {
+            if let Err(e) = task.await {
+                error!(error = ?e, "Poll writer task panicked");
+            }
+        }
+        Ok(exit)
+    }
+
+    /// Opens a websocket connection with the listener's subscriptions, through the endpoint in
+    /// use.
+    async fn open_connection(
+        &self,
+        subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+    ) -> Result<Connection> {
+        Connection::open(
+            self.endpoints.current(),
+            self.program_ids.clone(),
+            subscriptions.to_vec(),
+            self.with_logs,
+            self.commitment,
+        )
+        .await
+    }
+
+    /// Subscribes through the healthiest endpoint out of cooldown, trying the others in turn
+    /// when that fails (see [`Endpoints`]). Fails with the last error when none accepts the
+    /// subscriptions.
+    async fn connect(
+        &mut self,
+        subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+    ) -> Result<Connection> {
+        let mut last_error = None;
+        for index in self.endpoints.candidates(Instant::now()) {
+            let ws_url = self.endpoints.url(index).to_string();
+            let connection = Connection::open(
+                &ws_url,
+                self.program_ids.clone(),
+                subscriptions.to_vec(),
+                self.with_logs,
+                self.commitment,
+            )
+            .await;
+            match connection {
+                Ok(connection) => {
+                    self.endpoints.connected(index);
+                    self.health.set_websocket_endpoints(
+                        self.endpoints
+                            .report(self.clock.instant(), self.clock.unix_now()),
+                    );
+                    info!(ws_url = %ws_url, "Connected to websocket");
+                    self.health.set_websocket_connected(true);
+                    self.metrics.websocket_connected.set(1);
+                    // Whether filters are honoured is a property of the provider: a verdict
+                    // about another endpoint doesn't apply here.
+                    let endpoint = bandwidth::endpoint_label(&ws_url);
+                    if self.filter_guard.endpoint() != endpoint {
+                        self.filter_guard.reset_for_endpoint(&endpoint);
+                        self.health.set_filters_ignored_by(None);
+                    }
+                    return Ok(connection);
+                }
+                Err(e) => {
+                    warn!(
+                        endpoint = %bandwidth::endpoint_label(&ws_url),
+                        error = ?e,
+                        "Could not subscribe through websocket endpoint"
+                    );
+                    self.endpoints.failed(index, Instant::now());
+                    last_error = Some(e);
+                }
+            }
+        }
+        self.health.set_websocket_endpoints(
+            self.endpoints
+                .report(self.clock.instant(), self.clock.unix_now()),
+        );
+        Err(last_error.unwrap_or_else(|| {
+            anyhow::anyhow!("every websocket endpoint is cooling down after a failure")
+        }))
+    }
+
+    /// Called when the endpoint in use closed the connection: marks it failed and subscribes
+    /// through another one (see [`Listener::connect`]). `None` when there's no fallback
+    /// endpoint, or none accepted the subscriptions.
+    async fn fail_over(
+        &mut self,
+        subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+    ) -> Option<Connection> {
+        self.endpoints.failed_current(Instant::now());
+        self.health.set_websocket_endpoints(
+            self.endpoints
+                .report(self.clock.instant(), self.clock.unix_now()),
+        );
+        if !self.endpoints.has_fallbacks() {
+            return None;
+        }
+        self.health.set_websocket_connected(false);
+        self.metrics.websocket_connected.set(0);
+        let failed = bandwidth::endpoint_label(self.endpoints.current());
+        warn!(
+            event = "websocket_failover_started",
+            endpoint = %failed,
+            "Websocket stream closed by the server, failing over"
+        );
+        match self.connect(subscriptions).await {
+            Ok(connection) => {
+                let endpoint = bandwidth::endpoint_label(self.endpoints.current());
+                self.metrics
+                    .websocket_failovers
+                    .with_label_values(&[&endpoint])
+                    .inc();
+                info!(
+                    event = "websocket_failover",
+                    from = %failed,
+                    to = %endpoint,
+                    "Failed over to another websocket endpoint"
+                );
+                Some(connection)
+            }
+            Err(e) => {
+                error!(
+                    event = "websocket_failover_failed",
+                    error = ?e,
+                    "No websocket endpoint to fail over to"
+                );
+                None
+            }
+        }
+    }
+
+    /// Replaces a connection that delivered nothing for `idle` (see
+    /// [`ListenerBuilder::stall_timeout`]). The endpoint counts as failed and the listener fails
+    /// over; with no endpoint to fail over to it subscribes through the same one again. On
+    /// failure the stalled connection is kept.
+    async fn resubscribe(
+        &mut self,
+        subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+        idle: Duration,
+    ) -> Result<Connection> {
+        let stalled = bandwidth::endpoint_label(self.endpoints.current());
+        self.metrics
+            .websocket_stalls
+            .with_label_values(&[&stalled])
+            .inc();
+        warn!(
+            event = "websocket_stalled",
+            endpoint = %stalled,
+            idle_secs = idle.as_secs(),
+            "No websocket message for too long, re-subscribing"
+        );
+        if self.endpoints.has_fallbacks() {
+            self.endpoints.failed_current(Instant::now());
+            match self.connect(subscriptions).await {
+                Ok(connection) => {
+                    let endpoint = bandwidth::endpoint_label(self.endpoints.current());
+                    self.metrics
+                        .websocket_failovers
+                        .with_label_values(&[&endpoint])
+                        .inc();
+                    info!(
+                        event = "websocket_failover",
+                        from = %stalled,
+                        to = %endpoint,
+                        "Failed over to another websocket endpoint"
+                    );
+                    return Ok(connection);
+                }
+                Err(e) => debug!(error = ?e, "No websocket endpoint to fail over to"),
+            }
+        }
+        // Nowhere else to go: the same endpoint again, cooldown or not.
+        self.open_connection(subscriptions).await
+    }
+
+    /// Brings every program up to date with the chain, for updates the subscriptions didn't
+    /// deliver: at startup and after a failover. A program with a recent checkpoint only
+    /// catches up on the accounts changed since (see `catch_up`); the others, or when that
+    /// fails, get the full snapshot.
+    async fn sync(&self, subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)]) {
+        for program_id in &self.program_ids {
+            let caught_up = self.catch_up(program_id).await.unwrap_or_else(|e| {
+                warn!(%program_id, error = ?e, "Catching up since the checkpoint failed, backfilling in full");
+                false
+            });
+            if caught_up {
+                continue;
+            }
+            match self.backfill(program_id, subscriptions).await {
+                Ok(slot) => save_checkpoint(self.sink.as_ref(), program_id, slot).await,
+                Err(e) => {
+                    warn!(%program_id, error = ?e, "Backfill failed, continuing with live updates only")
+                }
+            }
+        }
+    }
+
+    /// Saves the checkpoints of `slots` in the background, see [`PollSink::save_checkpoint`].
+    fn spawn_checkpoints(&self, slots: HashMap<Pubkey, u64>) -> JoinHandle<()> {
+        let sink = self.sink.clone();
+        tokio::spawn(async move {
+            for (program_id, slot) in &slots {
+                save_checkpoint(sink.as_ref(), program_id, *slot).await;
+            }
+        })
+    }
+
+    /// When the next subscription refresh is due, counting from now.
+    fn next_refresh(&self) -> Option<Instant> {
+        self.subscription_refresh
+            .map(|interval| Instant::now() + interval)
+    }
+
+    /// Reports a subscription refresh that didn't happen; the current connection is kept.
+    fn refresh_failed(&self, error: anyhow::Error) {
+        self.metrics
+            .subscription_refreshes
+            .with_label_values(&["failed"])
+            .inc();
+        warn!(
+            event = "subscription_refresh_failed",
+            error = ?error,
+            "Subscription refresh failed, keeping the current connection"
+        );
+    }
+
+    /// Makes `next` the current connection of a subscription refresh and closes the previous
+    /// one, which is returned so the messages it still holds get processed. `how` is the
+    /// `result` label of the refresh: `switched`, `timed_out` or `closed`.
+    fn switch_connection(
+        &self,
+        current: &mut Connection,
+        next: Connection,
+        how: &'static str,
+    ) -> Connection {
+        let mut previous = std::mem::replace(current, next);
+        previous.close();
+        self.metrics
+            .subscription_refreshes
+            .with_label_values(&[how])
+            .inc();
+        info!(
+            event = "subscription_refreshed",
+            result = how,
+            "Switched to the new websocket connection"
+        );
+        previous
+    }
+
+    /// Handles a single account update message received from the Solana websocket subscription.
+    /// This function:
+    /// 1. Decodes the raw account data (Base64 → bytes, decompressing Base64+Zstd).
+    /// 2. Parses the account pubkey the update belongs to.
+    /// 3. Hands both to `process_account`, which matches the discriminator, decodes and writes
+    ///    to the sink, unless the data is identical to the last update of that account.
+    ///
+    /// # Arguments
+    /// - `response`: A Solana `RpcResponse` containing the updated account state.
+    /// - `program_id`: The program whose subscription delivered the update.
+    /// - `known_type`: The account type guaranteed by the subscription filter, if any.
+    ///
+    /// Returns what was made of the account, for the warm-up check.
+    pub async fn handle_response(
+        &mut self,
+        response: Response<RpcKeyedAccount>,
+        program_id: &Pubkey,
+        known_type: Option<VotingAccountType>,
+    ) -> ProcessedAccount {
+        // The slot at which the RPC node observed this account state
+        let slot = response.context.slot;
+        // Extract the inner Solana account info
+        let account = response.value.account;
+        // Decode the account data (Base64 or Base64+Zstd → raw Vec<u8>)
+        let data = decode_account_data(&account.data);
+
+        // Only proceed if the decoding worked and we got a valid pubkey to attach the update to
+        let processed = match (data, response.value.pubkey.parse::<Pubkey>()) {
+            (Ok(acc_data), Ok(pubkey)) => {
+                let hash = AccountDedup::<ProcessedAccount>::hash(&acc_data);
+                match self.dedup.duplicate_of(&pubkey, hash) {
+                    // Same data as last time: nothing to decode or write, same outcome as before.
+                    Some(processed) => {
+                        self.metrics.duplicates_skipped.inc();
+                        debug!(%pubkey, slot, "Skipping identical account update");
+                        processed
+                    }
+                    // A closed account is delivered once more, with zero lamports and no data.
+                    None if account.lamports == 0 || acc_data.is_empty() => {
+                        let processed = self
+                            .process_closure(program_id, &pubkey, slot, known_type)
+                            .await;
+                        if processed.persisted {
+                            self.dedup.remember(pubkey, hash, processed);
+                        }
+                        processed
+                    }
+                    None => {
+                        let known_type =
+                            self.filter_checked_type(known_type, program_id, &pubkey, &acc_data);
+                        let processed = self
+                            .process_account(program_id, &pubkey, &acc_data, slot, known_type)
+                            .await;
+                        if processed.persisted {
+                            self.dedup.remember(pubkey, hash, processed);
+                        }
+                        processed
+                    }
+                }
+            }
+            (Err(e), _) => {
+                self.metrics.decode_failures.inc();
+                warn!(
+                    pubkey = %response.value.pubkey,
+                    slot,
+                    error = %e,
+                    "Could not decode account data"
+                );
+                ProcessedAccount::failed(known_type)
+            }
+            (_, Err(e)) => {
+                self.metrics.decode_failures.inc();
+                warn!(
+                    pubkey = %response.value.pubkey,
+                    slot,
+                    error = %e,
+                    "Invalid account pubkey in update"
+                );
+                ProcessedAccount::failed(known_type)
+            }
+        };
+        self.metrics
+            .messages_received
+            .with_label_values(&[processed.account_type.as_str()])
+            .inc();
+        processed
+    }
+
+    /// Handles a transaction's logs received from `logs_subscribe` (`with_logs`).
+    ///
+    /// Failed transactions are skipped: their state changes were rolled back, so the instructions
+    /// they log never happened. Recognized events are written to the sink; lines of our program
+    /// that match no known event are only counted.
+    /// The notification doesn't carry the signers, so events are keyed by transaction signature.
+    ///
+    /// Events are grouped by slot: those of a slot are kept until the logs of another slot
+    /// arrive, or [`SLOT_EVENTS_DELAY`] after the first of them, and then written in a single
+    /// [`PollSink::write_events`] call (one multi-row insert per program for Postgres), instead
+    /// of one per transaction. [`Listener::run`] writes what's left when it stops.
+    pub async fn handle_logs(&mut self, response: Response<RpcLogsResponse>, program_id: &Pubkey) {
+        let slot = response.context.slot;
+        let logs = response.value;
+        if logs.err.is_some() {
+            debug!(signature = %logs.signature, slot, "Skipping logs of failed transaction");
+            return;
+        }
+
+        let parsed = parse_logs(&program_id.to_string(), &logs.logs);
+        self.metrics
+            .log_lines_unmatched
+            .inc_by(parsed.unmatched as u64);
+        if parsed.events.is_empty() {
+            return;
+        }
+
+        let new_events: Vec<NewEvent> = parsed
+            .events
+            .iter()
+            .map(|(index, event)| NewEvent {
+                program_id: program_id.to_bytes().to_vec(),
+                signature: logs.signature.clone(),
+                log_index: *index as i32,
+                slot: slot as i64,
+                event_type: event.event_type(),
+                payload: event.payload(),
+            })
+            .collect();
+        for event in &new_events {
+            self.metrics
+                .events_recorded
+                .with_label_values(&[event.event_type.as_str()])
+                .inc();
+        }
+
+        if self
+            .slot_events
+            .events
+            .first()
+            .is_some_and(|event| event.slot != slot as i64)
+        {
+            self.flush_slot_events().await;
+        }
+        debug!(signature = %logs.signature, slot, count = new_events.len(), "Buffered events");
+        self.slot_events.events.extend(new_events);
+        self.slot_events
+            .due
+            .get_or_insert_with(|| Instant::now() + SLOT_EVENTS_DELAY);
+    }
+
+    /// Writes the events of the slot [`Listener::handle_logs`] kept, if any.
+    async fn flush_slot_events(&mut self) {
+        let events = std::mem::take(&mut self.slot_events).events;
+        let Some(slot) = events.first().map(|event| event.slot) else {
+            return;
+        };
+        let count = events.len();
+        match self.sink.write_events(events).await {
+            Ok(inserted) => debug!(slot, count, inserted, "Recorded events"),
+            Err(e) => error!(slot, count, error = %e, "Failed to record events"),
+        }
+    }
+
+    /// The estimated unix time of `slot`, from the slot clock if there is one with a sample.
+    fn slot_time(&self, slot: u64) -> Option<i64> {
+        let clock = self.slot_clock.as_ref()?;
+        Some(clock.estimate_time(slot)?.value)
+    }
+
+    /// Fetches every account currently owned by `program_id` and runs it through `process_account`.
+    ///
+    /// This is a one-shot HTTP snapshot (`getProgramAccounts`) used to catch up on state that
+    /// existed before the listener started. It uses the same configs (and filters) as the live
+    /// subscriptions. At the end a per-type summary is printed.
+    ///
+    /// The whole snapshot is fetched before anything is written, so it can be written in order of
+    /// importance rather than in the RPC's arbitrary order: polls grouped by lifecycle (see
+    /// [`backfill_priority`]), each group followed by the delegations of its polls, then every
+    /// account that can't be tied to a poll. Each group is logged as it starts.
+    ///
+    /// Returns the slot the snapshot is at least as new as, the program's next checkpoint.
+    async fn backfill(
+        &self,
+        program_id: &Pubkey,
+        subscriptions: &[(Option<VotingAccountType>, RpcProgramAccountsConfig)],
+    ) -> Result<u64> {
+        let mut summary = BackfillSummary::default();
+        let mut total = 0;
+        let mut fetched = Vec::new();
+        let mut snapshot_slot = u64::MAX;
+        for (known_type, config) in subscriptions {
+            // getProgramAccounts doesn't tell us which slot the snapshot was taken at, so take the
+            // current slot right before it. The snapshot is at least that new, which is enough to
+            // never let it overwrite a newer live update.
+            let commitment = config.account_config.commitment.unwrap_or_default();
+            let slot = self
+                .rpc_client
+                .get_slot_with_commitment(commitment)
+                .await
+                .map_err(anyhow::Error::from)
+                .with_context(|| "Failed to fetch the current slot for backfill")?;
+            snapshot_slot = snapshot_slot.min(slot);
+
+            let accounts = self
+                .rpc_client
+                .get_program_accounts_with_config(program_id, config.clone())
+                .await
+                .map_err(anyhow::Error::from)
+                .with_context(|| "Failed to fetch program accounts for backfill")?;
+
+            total += accounts.len();
+            for (pubkey, account) in accounts {
+                // The snapshot is one-off, so every account is checked against the filter
+                // rather than a sample; a mismatch falls back to the discriminator.
+                let verified_type = known_type.filter(|known| {
+                    account.data.len() < 8 || match_voting_account_type(&account.data) == *known
+                });
+                if known_type.is_some() && verified_type.is_none() {
+                    summary.filter_mismatches += 1;
+                }
+                fetched.push(BackfillAccount {
+                    pubkey,
+                    data: account.data,
+                    slot,
+                    known_type: verified_type,
+                });
+            }
+        }
+
+        // Classify the snapshot: the lifecycle of each poll at the snapshot's slot, then the
+        // group of every account. A stable sort keeps the RPC order within a group.
+        let now = self
+            .slot_time(snapshot_slot)
+            .unwrap_or_else(|| self.clock.unix_now());
+        let poll_states: HashMap<u64, PollLifecycle> = fetched
+            .iter()
+            .filter(|account| account.account_type() == VotingAccountType::Poll)
+            .filter_map(|account| decode_poll(&account.data).ok())
+            .map(|poll| (poll.poll_id, poll_lifecycle(&poll, now)))
+            .collect();
+        let mut ordered: Vec<((u8, u8), Option<PollLifecycle>, BackfillAccount)> = fetched
+            .into_iter()
+            .map(|account| {
+                let (group, type_order) = match account.account_type() {
+                    VotingAccountType::Poll => (
+                        decode_poll(&account.data)
+                            .ok()
+                            .map(|poll| poll_lifecycle(&poll, now)),
+                        0,
+                    ),
+                    VotingAccountType::Delegation => (
+                        decode_delegation(&account.data)
+                            .ok()
+                            .and_then(|delegation| poll_states.get(&delegation.poll_id).copied()),
+                        1,
+                    ),
+                    _ => (None, 2),
+                };
+                ((backfill_priority(group), type_order), group, account)
+            })
+            .collect();
+        ordered.sort_by_key(|(rank, _, _)| *rank);
+
+        let mut current_group = None;
+        for (index, ((priority, _), group, account)) in ordered.iter().enumerate() {
+            if current_group != Some(*priority) {
+                current_group = Some(*priority);
+                let accounts = ordered[index..]
+                    .iter()
+                    .take_while(|((other, _), _, _)| other == priority)
+                    .count();
+                info!(
+                    %program_id,
+                    group = group.map_or("other", |state| state.as_str()),
+                    accounts,
+                    "Backfilling group"
+                );
+            }
+            // HTTP responses are already decoded into raw bytes, so they can go straight to the shared path.
+            let processed = self
+                .process_account(
+                    program_id,
+                    &account.pubkey,
+                    &account.data,
+                    account.slot,
+                    account.known_type,
+                )
+                .await;
+            summary.record(processed.account_type);
+        }
+
+        if summary.filter_mismatches > 0 {
+            self.metrics
+                .filter_mismatches
+                .inc_by(summary.filter_mismatches as u64);
+            error!(
+                event = "rpc_filter_ignored",
+                endpoint = %self.rpc_client.url(),
+                %program_id,
+                mismatches = summary.filter_mismatches,
+                "RPC provider ignored the account filters of the backfill; mismatching accounts \
+                 were typed by their discriminator instead"
+            );
+        }
+        info!(
+            %program_id,
+            total,
+            polls = summary.polls,
+            candidates = summary.candidates,
+            votes = summary.votes,
+            delegations = summary.delegations,
+            unknown = summary.unknown,
+            "Backfill complete"
+        );
+        Ok(snapshot_slot)
+    }
+
+    /// Catches up on the accounts of `program_id` changed since its checkpoint, instead of a
+    /// full backfill: the program's transactions since then are fetched, then the accounts they
+    /// wrote (see [`checkpoint::changed_accounts`]), and those the program owns go through
+    /// `process_account`. Those found closed go through `process_closure`.
+    ///
+    /// Returns `false`, having written nothing, when there's no checkpoint or too many
+    /// transactions since (see [`ListenerBuilder::catch_up_max_transactions`]). On success the
+    /// checkpoint moves to the slot taken before looking for transactions.
+    async fn catch_up(&self, program_id: &Pubkey) -> Result<bool> {
+        if self.catch_up_max_transactions == 0 {
+            return Ok(false);
+        }
+        let Some(since) = self.sink.checkpoint(program_id).await? else {
+            return Ok(false);
+        };
+        // Every transaction up to this slot is found below, and the accounts are fetched after
+        // it, so whatever changed until then is covered.
+        let slot = self
+            .rpc_client
+            .get_slot_with_commitment(self.commitment)
+            .await
+            .context("Failed to fetch the current slot")?;
+        let Some(pubkeys) = checkpoint::changed_accounts(
+            &self.rpc_client,
+            program_id,
+            since,
+            self.commitment,
+            self.catch_up_max_transactions,
+        )
+        .await?
+        else {
+            info!(
+                %program_id,
+                checkpoint = since,
+                max_transactions = self.catch_up_max_transactions,
+                "Too many transactions since the checkpoint, backfilling in full"
+            );
+            return Ok(false);
+        };
+
+        let fetched = fetch_accounts(
+            &self.rpc_client,
+            &pubkeys,
+            self.commitment,
+            FetchConfig {
+                clock: self.clock,
+                ..FetchConfig::default()
+            },
+        )
+        .await;
+        if let Some((pubkey, FetchedAccount::Failed(error))) = fetched
+            .iter()
+            .find(|(_, account)| matches!(account, FetchedAccount::Failed(_)))
+        {
+            anyhow::bail!("could not fetch {}: {}", pubkey, error);
+        }
+        let mut summary = BackfillSummary::default();
+        for pubkey in &pubkeys {
+            let (account, slot) = match fetched.get(pubkey) {
+                Some(FetchedAccount::Found { account, slot }) => (account, slot),
+                // Most of these were never ours (e.g. a closed fee payer); those match no row.
+                Some(FetchedAccount::Missing { slot }) => {
+                    self.process_closure(program_id, pubkey, *slot, None).await;
+                    continue;
+                }
+                _ => continue,
+            };
+            if account.owner != *program_id {
+                continue;
+            }
+            // The subscriptions never deliver the types `only` leaves out: neither does this.
+            if !self.only.is_empty()
+                && !self
+                    .only
+                    .contains(&match_voting_account_type(&account.data))
+            {
+                continue;
+            }
+            let processed = self
+                .process_account(program_id, pubkey, &account.data, *slot, None)
+                .await;
+            summary.record(processed.account_type);
+        }
+        save_checkpoint(self.sink.as_ref(), program_id, slot).await;
+        info!(
+            %program_id,
+            checkpoint = since,
+            slot,
+            accounts = pubkeys.len(),
+            polls = summary.polls,
+            candidates = summary.candidates,
+            votes = summary.votes,
+            delegations = summary.delegations,
+            unknown = summary.unknown,
+            "Caught up since the checkpoint"
+        );
+        Ok(true)
+    }
+
+    /// Returns the account type the subscription filter guarantees, unless this update is
+    /// sampled and shows that the provider ignores the filter: `None` then lets the
+    /// discriminator decide, as it does for every later update (see [`FilterGuard`]).
+    fn filter_checked_type(
+        &mut self,
+        known_type: Option<VotingAccountType>,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        acc_data: &[u8],
+    ) -> Option<VotingAccountType> {
+        let known = known_type?;
+        if acc_data.len() < 8 || !self.filter_guard.should_verify() {
+            return Some(known);
+        }
+        let actual = match_voting_account_type(acc_data);
+        if actual == known {
+            return Some(known);
+        }
+
+        self.metrics.filter_mismatches.inc();
+        if self.filter_guard.record_mismatch() {
+            error!(
+                event = "rpc_filter_ignored",
+                endpoint = %self.filter_guard.endpoint(),
+                %program_id,
+                %pubkey,
+                expected = known.as_str(),
+                received = actual.as_str(),
+                "RPC provider ignores account filters; checking every update client-side from now on"
+            );
+            self.health
+                .set_filters_ignored_by(Some(self.filter_guard.endpoint()));
+        } else {
+            debug!(%pubkey, expected = known.as_str(), received = actual.as_str(), "Filter mismatch");
+        }
+        None
+    }
+
+    /// Marks a closed account's rows deleted (see [`PollSink::mark_closed`]). `known_type` is
+    /// the type its subscription filtered on, if any: the data is gone, so it can't be told.
+    async fn process_closure(
+        &self,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        slot: u64,
+        known_type: Option<VotingAccountType>,
+    ) -> ProcessedAccount {
+        let persisted = match self.sink.mark_closed(program_id, pubkey, slot).await {
+            Ok(true) => {
+                info!(%program_id, %pubkey, slot, "Account closed");
+                true
+            }
+            Ok(false) => {
+                debug!(%program_id, %pubkey, slot, "Closed account had no stored rows");
+                true
+            }
+            Err(e) => {
+                error!(%program_id, %pubkey, error = %e, "Account closure not persisted");
+                false
+            }
+        };
+        ProcessedAccount::closed(known_type, persisted)
+    }
+
+    /// Decodes and writes a single program account, regardless of where it came from.
+    ///
+    /// Both the websocket stream (`handle_response`) and the startup backfill call this,
+    /// so live updates and snapshots go through exactly the same logic.
+    /// `slot` is the context slot of a websocket update, or the slot the backfill snapshot
+    /// was requested at; it guards the DB write against out-of-order updates.
+    /// When the subscription already filtered on a discriminator, `known_type` is passed and
+    /// the discriminator isn't matched again.
+    /// `program_id` is the program whose subscription (or snapshot) delivered the account.
+    /// Returns the detected account type and whether it decoded, so callers can keep statistics.
+    async fn process_account(
+        &self,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        acc_data: &[u8],
+        slot: u64,
+        known_type: Option<VotingAccountType>,
+    ) -> ProcessedAccount {
+        // Cleared by any write the sink refuses.
+        let mut persisted = true;
+        // Archived before anything can go wrong, so a later decoder can do better.
+        if self.archive_raw_accounts {
+            let raw = NewRawAccount {
+                program_id: program_id.to_bytes().to_vec(),
+                account_pubkey: pubkey.to_bytes().to_vec(),
+                slot: slot as i64,
+                data: acc_data.to_vec(),
+            };
+            if let Err(e) = self.sink.write_raw_account(raw).await {
+                error!(%program_id, %pubkey, error = %e, "Raw account not archived");
+                persisted = false;
+            }
+        }
+        if acc_data.len() < 8 {
+            return ProcessedAccount {
+                persisted,
+                ..ProcessedAccount::failed(None)
+            };
+        }
+        let discriminator: [u8; 8] = acc_data[..8].try_into().unwrap();
+        let mut decoded = false;
+
+        // Determine the type of Solana account using the first 8 bytes (Anchor discriminator),
+        // unless the server-side filter already told us.
+        let account_type = known_type.unwrap_or_else(|| match_voting_account_type(acc_data));
+        match account_type {
+            VotingAccountType::Poll => {
+                // If it's a Poll account, try to deserialize the Poll struct
+                match decode_poll(acc_data) {
+                    Ok(poll) => {
+                        // Build a `NewPoll` struct that matches your SQL schema
+                        // This maps the on-chain Poll to a format Diesel understands
+                        let new_poll = NewPoll::from_account(&poll, slot, program_id, pubkey);
+
+                        // Hand the record to the sink. The Postgres sink queues it for the
+                        // batching writer, waiting for room (backpressure) instead of dropping it.
+                        if let Err(e) = self.sink.write_poll(new_poll).await {
+                            error!(%program_id, poll_id = poll.poll_id, error = %e, "Update not persisted");
+                            persisted = false;
+                        }
+
+                        // These logs are printed regardless of DB success (which is decoupled).
+                        info!(
+                            %program_id,
+                            %pubkey,
+                            slot,
+                            poll_id = poll.poll_id,
+                            owner = %poll.poll_owner,
+                            name = %poll.poll_name,
+                            description = %poll.poll_description,
+                            start = poll.poll_start,
+                            end = poll.poll_end,
+                            candidates = poll.candidate_amount,
+                            winner = %poll.candidate_winner,
+                            "Poll account updated"
+                        );
+                        self.emit(account_type, acc_data, slot, || VotingEvent::PollUpdated {
+                            program_id: *program_id,
+                            account: *pubkey,
+                            slot,
+                            poll,
+                        })
+                        .await;
+                        decoded = true;
+                    }
+                    Err(e) => {
+                        self.metrics.decode_failures.inc();
+                        warn!(%pubkey, slot, error = %e, code = %e.code(), "Could not decode Poll account");
+                        let error = format!("{}: {}", e.code(), e);
+                        persisted &= self
+                            .record_decode_failure(
+                                program_id,
+                                pubkey,
+                                acc_data,
+                                slot,
+                                account_type,
+                                error,
+                            )
+                            .await;
+                    }
+                }
+            }
+            // Candidates and votes have no table of their own: they're stored as JSON when the
+            // program was given its IDL, and only logged otherwise.
+            VotingAccountType::Candidate | VotingAccountType::Vote => {
+                let (ok, fields) = match self
+                    .process_idl_account(program_id, pubkey, acc_data, slot, &mut persisted)
+                    .await
+                {
+                    Some(Some(fields)) => (true, Some(fields)),
+                    Some(None) => (false, None),
+                    None => {
+                        debug!(%pubkey, slot, account_type = account_type.as_str(), "Account update");
+                        (true, None)
+                    }
+                };
+                decoded = ok;
+                if decoded {
+                    self.emit(account_type, acc_data, slot, || {
+                        let (program_id, account, data) = (*program_id, *pubkey, acc_data.to_vec());
+                        if account_type == VotingAccountType::Candidate {
+                            VotingEvent::CandidateUpdated {
+                                program_id,
+                                account,
+                                slot,
+                                data,
+                                fields,
+                            }
+                        } else {
+                            VotingEvent::VoteCast {
+                                program_id,
+                                account,
+                                slot,
+                                data,
+                                fields,
+                            }
+                        }
+                    })
+                    .await;
+                }
+            }
+            // Program v3 only: a wallet delegating its vote on a poll.
+            VotingAccountType::Delegation => match decode_delegation(acc_data) {
+                Ok(delegation) => {
+                    let new_delegation =
+                        NewDelegation::from_account(&delegation, slot, program_id, pubkey);
+                    if let Err(e) = self.sink.write_delegation(new_delegation).await {
+                        error!(%program_id, poll_id = delegation.poll_id, error = %e, "Delegation not persisted");
+                        persisted = false;
+                    }
+                    info!(
+                        %program_id,
+                        %pubkey,
+                        slot,
+                        poll_id = delegation.poll_id,
+                        delegator = %delegation.delegator,
+                        delegate = %delegation.delegate,
+                        expiry = ?delegation.expiry,
+                        "Delegation account updated"
+                    );
+                    self.emit(account_type, acc_data, slot, || {
+                        VotingEvent::DelegationUpdated {
+                            program_id: *program_id,
+                            account: *pubkey,
+                            slot,
+                            delegation,
+                        }
+                    })
+                    .await;
+                    decoded = true;
+                }
+                Err(e) => {
+                    self.metrics.decode_failures.inc();
+                    warn!(%pubkey, slot, error = %e, code = %e.code(), "Could not decode Delegation account");
+                    let error = format!("{}: {}", e.code(), e);
+                    persisted &= self
+                        .record_decode_failure(
+                            program_id,
+                            pubkey,
+                            acc_data,
+                            slot,
+                            account_type,
+                            error,
+                        )
+                        .await;
+                }
+            },
+            // Accounts the listener doesn't know may still be described by the program's IDL.
+            VotingAccountType::Unknown => {
+                let fields = match self
+                    .process_idl_account(program_id, pubkey, acc_data, slot, &mut persisted)
+                    .await
+                {
+                    Some(fields) => {
+                        decoded = fields.is_some();
+                        fields
+                    }
+                    None => {
+                        debug!(%pubkey, slot, "Unknown account type");
+                        let error = "Unknown account type".to_string();
+                        persisted &= self
+                            .record_decode_failure(
+                                program_id,
+                                pubkey,
+                                acc_data,
+                                slot,
+                                account_type,
+                                error,
+                            )
+                            .await;
+                        None
+                    }
+                };
+                self.emit(account_type, acc_data, slot, || VotingEvent::Unknown {
+                    program_id: *program_id,
+                    account: *pubkey,
+                    slot,
+                    data: acc_data.to_vec(),
+                    fields,
+                })
+                .await;
+            }
+        }
+
+        ProcessedAccount {
+            account_type,
+            discriminator: Some(discriminator),
+            decoded,
+            persisted,
+        }
+    }
+
+    /// Decodes an account with the IDL of its program and hands it to the sink as JSON.
+    ///
+    /// Returns its fields, `Some(None)` when it didn't decode, or `None` when the program has no
+    /// IDL or its IDL doesn't describe the account. Clears `persisted` when the sink refuses a
+    /// write.
+    async fn process_idl_account(
+        &self,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        acc_data: &[u8],
+        slot: u64,
+        persisted: &mut bool,
+    ) -> Option<Option<serde_json::Value>> {
+        let decoder = self.idl_decoders.get(program_id)?;
+        match decoder.decode(acc_data)? {
+            Ok(account) => {
+                debug!(%program_id, %pubkey, slot, account_type = %account.name, fields = %account.fields, "IDL account updated");
+                let new_account = NewIdlAccount {
+                    program_id: program_id.to_bytes().to_vec(),
+                    account_pubkey: pubkey.to_bytes().to_vec(),
+                    account_type: account.name,
+                    data: account.fields.clone(),
+                    last_slot: slot as i64,
+                };
+                if let Err(e) = self.sink.write_idl_account(new_account).await {
+                    error!(%program_id, %pubkey, error = %e, "IDL account not persisted");
+                    *persisted = false;
+                }
+                Some(Some(account.fields))
+            }
+            Err(e) => {
+                self.metrics.decode_failures.inc();
+                let code = errors::classify(&e).unwrap_or(&errors::INTERNAL);
+                warn!(%pubkey, slot, error = format!("{:#}", e), %code, "Could not decode account with the IDL");
+                let error = format!("{}: {:#}", code, e);
+                let account_type = match_voting_account_type(acc_data);
+                *persisted &= self
+                    .record_decode_failure(program_id, pubkey, acc_data, slot, account_type, error)
+                    .await;
+                Some(None)
+            }
+        }
+    }
+
+    /// Runs the handlers registered for the account's discriminator, then sends the event to
+    /// the stream of [`Listener::events`], waiting for room. `event` is only built when there is
+    /// a handler or a stream.
+    async fn emit(
+        &self,
+        account_type: VotingAccountType,
+        acc_data: &[u8],
+        slot: u64,
+        event: impl FnOnce() -> VotingEvent,
+    ) {
+        let discriminator: [u8; 8] = acc_data[..8].try_into().unwrap();
+        let handlers = self.handlers.get(&discriminator);
+        if handlers.is_empty() && self.events.is_none() {
+            return;
+        }
+        let event = event();
+        for handler in handlers {
+            if let Err(e) = handler.handle(account_type, &event, acc_data, slot).await {
+                error!(
+                    program_id = %event.program_id(),
+                    account_type = account_type.as_str(),
+                    slot,
+                    error = format!("{:#}", e),
+                    "Account handler failed"
+                );
+            }
+        }
+        if let Some(events) = &self.events {
+            // Fails only when the stream was dropped: nobody is listening anymore.
+            let _ = events.send(event).await;
+        }
+    }
+
+    /// Hands an account that couldn't be decoded to the sink with its raw data (see
+    /// [`PollSink::write_decode_failure`]). `account_type` is what its discriminator matched.
+    /// Returns whether the sink accepted it.
+    async fn record_decode_failure(
+        &self,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        acc_data: &[u8],
+        slot: u64,
+        account_type: VotingAccountType,
+        error: String,
+    ) -> bool {
+        let failure = NewDecodeFailure {
+            account_pubkey: pubkey.to_bytes().to_vec(),
+            program_id: program_id.to_bytes().to_vec(),
+            slot: slot as i64,
+            discriminator: acc_data[..8].to_vec(),
+            account_type: account_type.as_str().to_string(),
+            error,
+            data: base64::engine::general_purpose::STANDARD.encode(acc_data),
+        };
+        if let Err(e) = self.sink.write_decode_failure(failure).await {
+            error!(%program_id, %pubkey, error = %e, "Decode failure not persisted");
+            return false;
+        }
+        true
+    }
+
+    /// Logs a finished warm-up and publishes it on `/health`.
+    ///
+    /// A failure is logged as an error with `event = "warmup_failed"` so it stands out and can be
+    /// alerted on. Returns the diagnostic to stop with when it failed and warm-up is strict.
+    fn report_warmup(&self, program_id: &Pubkey, report: WarmupReport) -> Option<String> {
+        let ratio_pct = report.ratio * 100.0;
+        let diagnostic = match report.state {
+            WarmupState::Passed => {
+                info!(
+                    %program_id,
+                    messages = report.messages,
+                    recognized = report.recognized,
+                    "Warm-up passed"
+                );
+                None
+            }
+            WarmupState::Inconclusive | WarmupState::Pending => {
+                info!(%program_id, "Warm-up window elapsed without any account update");
+                None
+            }
+            WarmupState::Failed => {
+                let observed = report
+                    .unrecognized_discriminators
+                    .iter()
+                    .map(|d| format!("{:?} ×{}", d.discriminator, d.count))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                error!(
+                    event = "warmup_failed",
+                    %program_id,
+                    messages = report.messages,
+                    recognized = report.recognized,
+                    ratio_pct,
+                    observed_discriminators = %observed,
+                    "Warm-up failed: most updates aren't voting accounts, check the program ID"
+                );
+                Some(format!(
+                    "Warm-up failed for program {}: only {} of {} updates ({:.0}%) were decodable \
+                     voting accounts. Most common unknown discriminators: {}. Is this the right \
+                     program ID? Compare them with the account discriminators in its IDL.",
+                    program_id,
+                    report.recognized,
+                    report.messages,
+                    ratio_pct,
+                    if observed.is_empty() {
+                        "none".to_string()
+                    } else {
+                        observed
+                    }
+                ))
+            }
+        };
+        self.health.record_warmup(program_id, report);
+        diagnostic.filter(|_| self.strict_warmup)
+    }
+}
+
+/// Decodes the account data of a subscription message: base64, then zstd for
+/// [`AccountEncoding::Base64Zstd`]. Fails on any other encoding, which the listener never asks for.
+fn decode_account_data(data: &UiAccountData) -> Result<Vec<u8>> {
+    let UiAccountData::Binary(blob, encoding) = data else {
+        anyhow::bail!("account data isn't binary-encoded");
+    };
+    match encoding {
+        UiAccountEncoding::Base64 => Ok(base64::engine::general_purpose::STANDARD.decode(blob)?),
+        UiAccountEncoding::Base64Zstd => {
+            let compressed = base64::engine::general_purpose::STANDARD.decode(blob)?;
+            zstd::stream::decode_all(compressed.as_slice()).context("invalid zstd data")
+        }
+        other => anyhow::bail!("unexpected account encoding {:?}", other),
+    }
+}
+
+/// Builds the config shared by `program_subscribe` and `get_program_accounts`.
+///
+/// Without explicitly setting a binary encoding, account data may come back as "legacy" format,
+/// or be inconsistently decoded (leading to decode errors).
+/// The commitment decides how final the reported state must be (speed vs. reorg safety).
+/// An optional discriminator restricts results to a single account type (memcmp at offset 0).
+/// Other options (like context and sorting) are left default or None here.
+fn program_accounts_config(
+    commitment: CommitmentConfig,
+    encoding: AccountEncoding,
+    discriminator: Option<[u8; 8]>,
+) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        filters: discriminator
+            .map(|disc| vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &disc))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(encoding.ui_encoding()),
+            commitment: Some(commitment),
+            ..Default::default()
+        },
+        with_context: None,
+        sort_results: None,
+    }
+}
+
+/// Builds one `(known type, config)` pair per subscription.
+///
+/// An empty `only` means "all types": a single unfiltered subscription whose messages are
+/// matched client-side. Otherwise each selected type gets its own discriminator filter.
+fn subscription_plan(
+    only: &[VotingAccountType],
+    commitment: CommitmentConfig,
+    encoding: AccountEncoding,
+) -> Vec<(Option<VotingAccountType>, RpcProgramAccountsConfig)> {
+    if only.is_empty() {
+        return vec![(None, program_accounts_config(commitment, encoding, None))];
+    }
+
+    let mut plan: Vec<(Option<VotingAccountType>, RpcProgramAccountsConfig)> = Vec::new();
+    for account_type in only {
+        // Skip duplicates like `--only poll,poll`.
+        if plan.iter().any(|(known, _)| *known == Some(*account_type)) {
+            continue;
+        }
+        if let Some(discriminator) = account_type.discriminator() {
+            plan.push((
+                Some(*account_type),
+                program_accounts_config(commitment, encoding, Some(discriminator)),
+            ));
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    use chrono::{TimeZone, Utc};
+    use futures::SinkExt;
+    use serde_json::json;
+    use solana_account_decoder::UiAccount;
+    use solana_client::rpc_request::RpcRequest;
+    use solana_client::rpc_response::RpcResponseContext;
+
+    use super::*;
+    use crate::clock::Jitter;
+    use crate::decode::{DELEGATION_DISCRIMINATOR, POLL_DISCRIMINATOR, VOTE_DISCRIMINATOR};
+    use crate::sink::MemorySink;
+    use crate::slot_clock::SlotSample;
+    use crate::state::anchor::AnchorEncode;
+    use crate::state::delegation::Delegation;
+    use crate::warmup::DiscriminatorCount;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([7; 32]);
+
+    /// A listener of `PROGRAM` that never connects anywhere.
+    fn builder() -> ListenerBuilder {
+        Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url("ws://127.0.0.1:1")
+    }
+
+    /// A clock starting at noon, driven by tokio's time.
+    fn noon() -> Clock {
+        Clock::starting_at(Utc.with_ymd_and_hms(2025, 6, 1, 12, 0, 0).unwrap())
+    }
+
+    fn poll(poll_id: u64, poll_name: &str) -> Poll {
+        Poll {
+            poll_id,
+            poll_owner: Pubkey::new_from_array([1; 32]),
+            poll_name: poll_name.to_string(),
+            poll_description: "Best fruit".to_string(),
+            poll_start: 1_700_000_000,
+            poll_end: 1_700_086_400,
+            candidate_amount: 2,
+            candidate_winner: Pubkey::default(),
+        }
+    }
+
+    fn poll_data(poll: &Poll) -> Vec<u8> {
+        let mut data = POLL_DISCRIMINATOR.to_vec();
+        data.extend(poll.encode_anchor_bytes());
+        data
+    }
+
+    fn delegation_data(delegator: u8, poll_id: u64) -> Vec<u8> {
+        let delegation = Delegation {
+            delegator: Pubkey::new_from_array([delegator; 32]),
+            delegate: Pubkey::new_from_array([9; 32]),
+            poll_id,
+            expiry: None,
+        };
+        let mut data = DELEGATION_DISCRIMINATOR.to_vec();
+        data.extend(delegation.encode_anchor_bytes());
+        data
+    }
+
+    /// An account of `PROGRAM` as the RPC sends it (base64).
+    fn ui_account(data: &[u8], lamports: u64) -> UiAccount {
+        UiAccount {
+            lamports,
+            data: UiAccountData::Binary(
+                base64::engine::general_purpose::STANDARD.encode(data),
+                UiAccountEncoding::Base64,
+            ),
+            owner: PROGRAM.to_string(),
+            executable: false,
+            rent_epoch: 0,
+            space: Some(data.len() as u64),
+        }
+    }
+
+    /// A `program_subscribe` notification of `account`.
+    fn update(account: Pubkey, data: &[u8], lamports: u64, slot: u64) -> Response<RpcKeyedAccount> {
+        Response {
+            context: RpcResponseContext {
+                slot,
+                api_version: None,
+            },
+            value: RpcKeyedAccount {
+                pubkey: account.to_string(),
+                account: ui_account(data, lamports),
+            },
+        }
+    }
+
+    /// An RPC client answering `getSlot` with `slot` and `getProgramAccounts` with `accounts`,
+    /// once each.
+    fn snapshot_rpc(slot: u64, accounts: &[(Pubkey, Vec<u8>)]) -> Arc<RpcClient> {
+        let accounts: Vec<RpcKeyedAccount> = accounts
+            .iter()
+            .map(|(pubkey, data)| RpcKeyedAccount {
+                pubkey: pubkey.to_string(),
+                account: ui_account(data, 1),
+            })
+            .collect();
+        let mocks = HashMap::from([
+            (RpcRequest::GetSlot, json!(slot)),
+            (RpcRequest::GetProgramAccounts, json!(accounts)),
+        ]);
+        Arc::new(RpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            mocks,
+        ))
+    }
+
+    #[tokio::test]
+    async fn polls_are_decoded_into_the_sink() {
+        let sink = Arc::new(MemorySink::default());
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+        let mut events = listener.events();
+        let account = Pubkey::new_from_array([2; 32]);
+
+        let processed = listener
+            .handle_response(
+                update(account, &poll_data(&poll(3, "Fruit")), 1, 42),
+                &PROGRAM,
+                None,
+            )
+            .await;
+        assert_eq!(processed.account_type, VotingAccountType::Poll);
+        assert!(processed.decoded);
+
+        let polls = sink.polls();
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0].poll_id, 3);
+        assert_eq!(polls[0].poll_name, "Fruit");
+        assert_eq!(polls[0].last_slot, 42);
+        assert_eq!(polls[0].program_id, PROGRAM.to_bytes());
+        assert_eq!(polls[0].account_pubkey, account.to_bytes());
+
+        match events.next().await.unwrap() {
+            VotingEvent::PollUpdated { slot, poll, .. } => {
+                assert_eq!((slot, poll.poll_id), (42, 3));
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_updates_are_written_once() {
+        let sink = Arc::new(MemorySink::default());
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+        let account = Pubkey::new_from_array([2; 32]);
+        let data = poll_data(&poll(3, "Fruit"));
+
+        let first = listener
+            .handle_response(update(account, &data, 1, 42), &PROGRAM, None)
+            .await;
+        let again = listener
+            .handle_response(update(account, &data, 1, 43), &PROGRAM, None)
+            .await;
+        assert_eq!(
+            (again.account_type, again.decoded),
+            (first.account_type, first.decoded)
+        );
+        assert_eq!(sink.polls().len(), 1);
+
+        let renamed = poll_data(&poll(3, "Fruits"));
+        listener
+            .handle_response(update(account, &renamed, 1, 44), &PROGRAM, None)
+            .await;
+        assert_eq!(sink.polls().len(), 2);
+    }
+
+    /// A sink refusing its first `failures` polls.
+    #[derive(Default)]
+    struct FlakySink {
+        inner: MemorySink,
+        failures: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PollSink for FlakySink {
+        async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+            let left = self.failures.load(AtomicOrdering::SeqCst);
+            if left > 0 {
+                self.failures.store(left - 1, AtomicOrdering::SeqCst);
+                anyhow::bail!("writer stopped");
+            }
+            self.inner.write_poll(poll).await
+        }
+
+        async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+            self.inner.write_events(events).await
+        }
+    }
+
+    #[tokio::test]
+    async fn redeliveries_of_a_refused_update_are_written() {
+        let sink = Arc::new(FlakySink {
+            failures: AtomicUsize::new(1),
+            ..FlakySink::default()
+        });
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+        let account = Pubkey::new_from_array([2; 32]);
+        let data = poll_data(&poll(3, "Fruit"));
+
+        let first = listener
+            .handle_response(update(account, &data, 1, 42), &PROGRAM, None)
+            .await;
+        assert!(first.decoded && !first.persisted);
+        assert!(sink.inner.polls().is_empty());
+
+        // The same data again isn't a duplicate: nothing of it was stored.
+        let retried = listener
+            .handle_response(update(account, &data, 1, 43), &PROGRAM, None)
+            .await;
+        assert!(retried.persisted);
+        assert_eq!(sink.inner.polls().len(), 1);
+        // Once stored, it is.
+        listener
+            .handle_response(update(account, &data, 1, 44), &PROGRAM, None)
+            .await;
+        assert_eq!(sink.inner.polls().len(), 1);
+        assert_eq!(listener.dedup.skipped(), 1);
+    }
+
+    /// Counts the slots it handled, failing every time when `fails`.
+    #[derive(Default)]
+    struct CountingHandler {
+        fails: bool,
+        slots: std::sync::Mutex<Vec<u64>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AccountHandler for CountingHandler {
+        async fn handle(
+            &self,
+            _account_type: VotingAccountType,
+            _decoded: &VotingEvent,
+            _raw: &[u8],
+            slot: u64,
+        ) -> Result<()> {
+            self.slots.lock().unwrap().push(slot);
+            anyhow::ensure!(!self.fails, "handler failed");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_handler_blocks_neither_the_others_nor_the_stream() {
+        let failing = Arc::new(CountingHandler {
+            fails: true,
+            ..CountingHandler::default()
+        });
+        let polls = Arc::new(CountingHandler::default());
+        let delegations = Arc::new(CountingHandler::default());
+        let mut listener = builder()
+            .sink(Arc::new(MemorySink::default()))
+            .handler(POLL_DISCRIMINATOR, failing.clone())
+            .handler(POLL_DISCRIMINATOR, polls.clone())
+            .handler(DELEGATION_DISCRIMINATOR, delegations.clone())
+            .build()
+            .unwrap();
+        let events = listener.events();
+
+        let poll_account = Pubkey::new_from_array([2; 32]);
+        listener
+            .handle_response(
+                update(poll_account, &poll_data(&poll(3, "Fruit")), 1, 42),
+                &PROGRAM,
+                None,
+            )
+            .await;
+        listener
+            .handle_response(
+                update(
+                    Pubkey::new_from_array([3; 32]),
+                    &delegation_data(4, 3),
+                    1,
+                    43,
+                ),
+                &PROGRAM,
+                None,
+            )
+            .await;
+        drop(listener);
+
+        // Each discriminator reached its own handlers only, the failing one included.
+        assert_eq!(*failing.slots.lock().unwrap(), [42]);
+        assert_eq!(*polls.slots.lock().unwrap(), [42]);
+        assert_eq!(*delegations.slots.lock().unwrap(), [43]);
+        let slots: Vec<u64> = events.map(|event| event.slot()).collect().await;
+        assert_eq!(slots, [42, 43]);
+    }
+
+    /// The slots of the events of each `write_events` call.
+    #[derive(Default)]
+    struct BatchSink {
+        batches: std::sync::Mutex<Vec<Vec<i64>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl PollSink for BatchSink {
+        async fn write_poll(&self, _poll: NewPoll) -> Result<()> {
+            Ok(())
+        }
+
+        async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+            let slots: Vec<i64> = events.iter().map(|event| event.slot).collect();
+            self.batches.lock().unwrap().push(slots);
+            Ok(events.len())
+        }
+    }
+
+    /// The logs of a transaction of `PROGRAM` running `instructions`, at `slot`.
+    fn logs(signature: &str, slot: u64, instructions: &[&str]) -> Response<RpcLogsResponse> {
+        let mut lines = vec![format!("Program {PROGRAM} invoke [1]")];
+        lines.extend(
+            instructions
+                .iter()
+                .map(|name| format!("Program log: Instruction: {name}")),
+        );
+        lines.push(format!("Program {PROGRAM} success"));
+        Response {
+            context: RpcResponseContext {
+                slot,
+                api_version: None,
+            },
+            value: RpcLogsResponse {
+                signature: signature.to_string(),
+                err: None,
+                logs: lines,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn events_are_written_once_per_slot() {
+        let sink = Arc::new(BatchSink::default());
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+
+        listener
+            .handle_logs(logs("a", 42, &["Vote"]), &PROGRAM)
+            .await;
+        listener
+            .handle_logs(logs("b", 42, &["Vote", "Vote"]), &PROGRAM)
+            .await;
+        // Logs without events don't end the slot.
+        listener.handle_logs(logs("c", 43, &[]), &PROGRAM).await;
+        assert!(sink.batches.lock().unwrap().is_empty());
+
+        listener
+            .handle_logs(logs("d", 43, &["InitializePoll"]), &PROGRAM)
+            .await;
+        assert_eq!(*sink.batches.lock().unwrap(), [vec![42, 42, 42]]);
+
+        listener.flush_slot_events().await;
+        listener.flush_slot_events().await;
+        assert_eq!(*sink.batches.lock().unwrap(), [vec![42, 42, 42], vec![43]]);
+        assert!(listener.slot_events.due.is_none());
+    }
+
+    #[test]
+    fn rpc_urls_are_derived_from_websocket_urls() {
+        for (ws_url, rpc_url) in [
+            (
+                "wss://api.devnet.solana.com/",
+                "https://api.devnet.solana.com/",
+            ),
+            ("ws://127.0.0.1:8900", "http://127.0.0.1:8899"),
+            ("ws://localhost:8900/", "http://localhost:8899/"),
+            (
+                "wss://rpc.example.com:443/?api-key=k:1",
+                "https://rpc.example.com:443/?api-key=k:1",
+            ),
+            ("ws://[::1]:8900/", "http://[::1]:8899/"),
+            ("ws://[::1]/", "http://[::1]/"),
+            (
+                "ws://rpc.example.com:8080?k=1",
+                "http://rpc.example.com:8079?k=1",
+            ),
+            ("http://127.0.0.1:8899", "http://127.0.0.1:8899"),
+        ] {
+            assert_eq!(rpc_url_for(ws_url), rpc_url, "{ws_url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn undecodable_accounts_are_recorded_as_failures() {
+        let sink = Arc::new(MemorySink::default());
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+
+        let unknown = [9u8; 40];
+        let processed = listener
+            .handle_response(
+                update(Pubkey::new_unique(), &unknown, 1, 42),
+                &PROGRAM,
+                None,
+            )
+            .await;
+        assert_eq!(processed.account_type, VotingAccountType::Unknown);
+        assert_eq!(processed.discriminator, Some([9; 8]));
+        assert!(!processed.decoded);
+
+        // A poll cut short is a decode failure too.
+        let data = poll_data(&poll(3, "Fruit"));
+        let processed = listener
+            .handle_response(
+                update(Pubkey::new_unique(), &data[..20], 1, 42),
+                &PROGRAM,
+                None,
+            )
+            .await;
+        assert_eq!(processed.account_type, VotingAccountType::Poll);
+        assert!(!processed.decoded);
+
+        assert!(sink.polls().is_empty());
+        let failures: Vec<_> = sink
+            .decode_failures()
+            .into_iter()
+            .map(|failure| failure.account_type)
+            .collect();
+        assert_eq!(failures, ["unknown", "poll"]);
+    }
+
+    #[tokio::test]
+    async fn providers_ignoring_filters_index_nothing_mistyped() {
+        let sink = Arc::new(MemorySink::default());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let mut listener = builder()
+            .sink(sink.clone())
+            .metrics(metrics.clone())
+            .filter_sample_rate(0.5)
+            .build()
+            .unwrap();
+        let poll = poll_data(&poll(3, "Fruit"));
+        let mut vote = VOTE_DISCRIMINATOR.to_vec();
+        vote.extend([5; 80]);
+
+        // A provider ignoring the `--only poll` filter: every update claims to be a poll.
+        let updates = [poll.as_slice(), &vote, &vote, &vote, poll.as_slice()];
+        let mut types = Vec::new();
+        for (i, data) in updates.into_iter().enumerate() {
+            let account = Pubkey::new_from_array([i as u8 + 1; 32]);
+            let processed = listener
+                .handle_response(
+                    update(account, data, 1, 42),
+                    &PROGRAM,
+                    Some(VotingAccountType::Poll),
+                )
+                .await;
+            types.push(processed.account_type);
+        }
+
+        // The first sampled update tripped the guard: every later one was checked and counted.
+        use VotingAccountType::{Poll as P, Vote as V};
+        assert_eq!(types, [P, V, V, V, P]);
+        assert!(listener.filter_guard.is_tripped());
+        assert_eq!(metrics.filter_mismatches.get(), 3);
+        assert_eq!(sink.polls().len(), 2);
+        assert!(sink.decode_failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn backfill_writes_active_polls_first() {
+        let clock = noon();
+        let now = clock.unix_now() as u64;
+        let ended = Poll {
+            poll_start: now - 200,
+            poll_end: now - 100,
+            ..poll(1, "Ended")
+        };
+        let active = Poll {
+            poll_start: now - 100,
+            poll_end: now + 100,
+            ..poll(2, "Active")
+        };
+        let upcoming = Poll {
+            poll_start: now + 100,
+            poll_end: now + 200,
+            ..poll(3, "Upcoming")
+        };
+        // In the RPC's order, which is arbitrary.
+        let snapshot = [
+            poll_data(&ended),
+            delegation_data(11, 1),
+            [9u8; 16].to_vec(),
+            poll_data(&upcoming),
+            delegation_data(12, 2),
+            poll_data(&active),
+        ];
+        let snapshot: Vec<_> = snapshot
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| (Pubkey::new_from_array([i as u8 + 1; 32]), data))
+            .collect();
+
+        let sink = Arc::new(MemorySink::default());
+        let mut listener = builder()
+            .sink(sink.clone())
+            .rpc_client(snapshot_rpc(500, &snapshot))
+            .clock(clock)
+            .build()
+            .unwrap();
+        let events = listener.events();
+        let slot = listener
+            .backfill(&PROGRAM, &[(None, RpcProgramAccountsConfig::default())])
+            .await
+            .unwrap();
+        assert_eq!(slot, 500);
+        drop(listener);
+
+        // Each group of polls is followed by its delegations; unknown accounts come last.
+        let order: Vec<String> = events
+            .map(|event| match event {
+                VotingEvent::PollUpdated { poll, .. } => format!("poll {}", poll.poll_id),
+                VotingEvent::DelegationUpdated { delegation, .. } => {
+                    format!("delegation {}", delegation.poll_id)
+                }
+                VotingEvent::Unknown { .. } => "unknown".to_string(),
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect()
+            .await;
+        assert_eq!(
+            order,
+            [
+                "poll 2",
+                "delegation 2",
+                "poll 3",
+                "poll 1",
+                "delegation 1",
+                "unknown"
+            ]
+        );
+        assert!(sink.polls().iter().all(|poll| poll.last_slot == 500));
+    }
+
+    #[tokio::test]
+    async fn backfill_classifies_polls_at_the_snapshot_slot() {
+        let now = noon().unix_now();
+        let active_now = Poll {
+            poll_start: (now - 100) as u64,
+            poll_end: (now + 100) as u64,
+            ..poll(1, "Active now")
+        };
+        let active_at_snapshot = Poll {
+            poll_start: (now + 100) as u64,
+            poll_end: (now + 200) as u64,
+            ..poll(2, "Active at the snapshot")
+        };
+        let snapshot = [
+            (Pubkey::new_from_array([1; 32]), poll_data(&active_now)),
+            (
+                Pubkey::new_from_array([2; 32]),
+                poll_data(&active_at_snapshot),
+            ),
+        ];
+        // Slot 500 is 150 s from now, where only the second poll is active.
+        let slot_clock = Arc::new(SlotClock::default());
+        slot_clock.record(SlotSample {
+            slot: 100,
+            block_time: now - 10,
+        });
+        slot_clock.record(SlotSample {
+            slot: 500,
+            block_time: now + 150,
+        });
+
+        let mut listener = builder()
+            .sink(Arc::new(MemorySink::default()))
+            .rpc_client(snapshot_rpc(500, &snapshot))
+            .slot_clock(slot_clock)
+            .build()
+            .unwrap();
+        let events = listener.events();
+        listener
+            .backfill(&PROGRAM, &[(None, RpcProgramAccountsConfig::default())])
+            .await
+            .unwrap();
+        drop(listener);
+
+        let order: Vec<u64> = events
+            .filter_map(|event| async move {
+                match event {
+                    VotingEvent::PollUpdated { poll, .. } => Some(poll.poll_id),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(order, [2, 1]);
+    }
+
+    #[tokio::test]
+    async fn closed_accounts_are_recorded() {
+        let sink = Arc::new(MemorySink::default());
+        let mut listener = builder().sink(sink.clone()).build().unwrap();
+        let account = Pubkey::new_from_array([2; 32]);
+
+        listener
+            .handle_response(update(account, &[], 0, 50), &PROGRAM, None)
+            .await;
+        assert_eq!(sink.closed(), [(PROGRAM, account, 50)]);
+        assert!(sink.polls().is_empty());
+    }
+
+    fn warmup_report(state: WarmupState) -> WarmupReport {
+        WarmupReport {
+            state,
+            messages: 10,
+            recognized: 2,
+            ratio: 0.2,
+            unrecognized_discriminators: vec![DiscriminatorCount {
+                discriminator: [9; 8],
+                count: 8,
+            }],
+        }
+    }
+
+    #[test]
+    fn strict_warmup_stops_on_a_failure_only() {
+        let listener = builder().strict_warmup(true).build().unwrap();
+        let program_id = listener.program_ids[0];
+
+        let diagnostic = listener
+            .report_warmup(&program_id, warmup_report(WarmupState::Failed))
+            .expect("strict warm-up stops");
+        assert!(diagnostic.contains("only 2 of 10 updates (20%)"));
+        assert!(diagnostic.contains("[9, 9, 9, 9, 9, 9, 9, 9] ×8"));
+
+        for state in [WarmupState::Passed, WarmupState::Inconclusive] {
+            assert!(listener
+                .report_warmup(&program_id, warmup_report(state))
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn lenient_warmup_only_warns() {
+        let listener = builder().build().unwrap();
+        let program_id = listener.program_ids[0];
+        assert!(listener
+            .report_warmup(&program_id, warmup_report(WarmupState::Failed))
+            .is_none());
+    }
+
+    /// What the mock websocket server does on one connection, once it's subscribed.
+    enum Step {
+        Send(Response<RpcKeyedAccount>),
+        /// Waits until this many connections were accepted.
+        AwaitConnections(usize),
+        Pause(Duration),
+    }
+
+    /// A websocket server speaking just enough of the PubSub protocol: it confirms every
+    /// (un)subscription, and runs `scripts[i]` on the `i`th connection once it's subscribed.
+    /// Returns its URL and the number of unsubscriptions it confirmed.
+    async fn mock_pubsub(scripts: Vec<Vec<Step>>) -> (String, Arc<AtomicUsize>) {
+        use tokio_tungstenite::tungstenite::Message;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let unsubscribed = Arc::new(AtomicUsize::new(0));
+        let (accepted, _) = tokio::sync::watch::channel(0usize);
+        let server_unsubscribed = unsubscribed.clone();
+        tokio::spawn(async move {
+            for script in scripts {
+                let (socket, _) = listener.accept().await.unwrap();
+                let ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                accepted.send_modify(|count| *count += 1);
+                let (mut ws_tx, mut ws_rx) = ws.split();
+                let (out, mut out_rx) = mpsc::unbounded_channel::<serde_json::Value>();
+                tokio::spawn(async move {
+                    while let Some(message) = out_rx.recv().await {
+                        if ws_tx
+                            .send(Message::Text(message.to_string()))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+
+                let (unsubscribed, accepted) = (server_unsubscribed.clone(), accepted.subscribe());
+                let mut script = Some(script);
+                tokio::spawn(async move {
+                    while let Some(Ok(Message::Text(text))) = ws_rx.next().await {
+                        let request: serde_json::Value = serde_json::from_str(&text).unwrap();
+                        let method = request["method"].as_str().unwrap();
+                        if method.ends_with("Unsubscribe") {
+                            unsubscribed.fetch_add(1, AtomicOrdering::SeqCst);
+                            let _ = out.send(
+                                json!({"jsonrpc": "2.0", "result": true, "id": request["id"]}),
+                            );
+                            continue;
+                        }
+                        let _ =
+                            out.send(json!({"jsonrpc": "2.0", "result": 0, "id": request["id"]}));
+                        let Some(script) = script.take() else {
+                            continue;
+                        };
+                        let (out, mut accepted) = (out.clone(), accepted.clone());
+                        tokio::spawn(async move {
+                            for step in script {
+                                match step {
+                                    Step::Send(update) => {
+                                        let _ = out.send(json!({
+                                            "jsonrpc": "2.0",
+                                            "method": "programNotification",
+                                            "params": {"result": update, "subscription": 0},
+                                        }));
+                                    }
+                                    Step::AwaitConnections(count) => {
+                                        accepted
+                                            .wait_for(|accepted| *accepted >= count)
+                                            .await
+                                            .unwrap();
+                                    }
+                                    Step::Pause(pause) => tokio::time::sleep(pause).await,
+                                }
+                            }
+                        });
+                    }
+                });
+            }
+        });
+        (url, unsubscribed)
+    }
+
+    #[tokio::test]
+    async fn a_subscription_refresh_loses_and_repeats_nothing() {
+        let account = |poll_id: u8| Pubkey::new_from_array([poll_id; 32]);
+        let send = |poll_id: u8, slot: u64| {
+            let data = poll_data(&poll(poll_id.into(), "Fruit"));
+            Step::Send(update(account(poll_id), &data, 1, slot))
+        };
+        let (url, unsubscribed) = mock_pubsub(vec![
+            // Still delivering after the new connection subscribed.
+            vec![
+                send(1, 10),
+                send(2, 11),
+                Step::AwaitConnections(2),
+                send(3, 12),
+                send(5, 13),
+            ],
+            // Delivers one update the old connection delivered too.
+            vec![
+                Step::Pause(Duration::from_millis(100)),
+                send(3, 12),
+                send(4, 14),
+            ],
+        ])
+        .await;
+
+        let sink = Arc::new(MemorySink::default());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let listener = Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url(url)
+            .rpc_client(snapshot_rpc(1, &[]))
+            .sink(sink.clone())
+            .metrics(metrics.clone())
+            .subscription_refresh(Some(Duration::from_millis(500)))
+            .build()
+            .unwrap();
+        let done = Arc::new(tokio::sync::Notify::new());
+        let run = tokio::spawn({
+            let done = done.clone();
+            async move { listener.run(done.notified()).await }
+        });
+
+        let switched = metrics
+            .subscription_refreshes
+            .with_label_values(&["switched"]);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sink.polls().len() < 5 || switched.get() < 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("every update arrives after the refresh");
+        // Time for a repeated update to show up.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        done.notify_one();
+        run.await.unwrap().unwrap();
+
+        let mut written: Vec<_> = sink.polls().iter().map(|poll| poll.poll_id).collect();
+        written.sort();
+        assert_eq!(written, [1, 2, 3, 4, 5]);
+        assert_eq!(switched.get(), 1);
+        // The replaced connection, then the current one on the way out.
+        assert_eq!(unsubscribed.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn a_failed_refresh_keeps_the_current_connection() {
+        let data = |poll_id: u64| poll_data(&poll(poll_id, "Fruit"));
+        // A single connection is accepted: the refresh can't connect.
+        let (url, _) = mock_pubsub(vec![vec![
+            Step::Send(update(Pubkey::new_from_array([1; 32]), &data(1), 1, 10)),
+            Step::Pause(Duration::from_millis(400)),
+            Step::Send(update(Pubkey::new_from_array([2; 32]), &data(2), 1, 11)),
+        ]])
+        .await;
+
+        let sink = Arc::new(MemorySink::default());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let listener = Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url(url)
+            .rpc_client(snapshot_rpc(1, &[]))
+            .sink(sink.clone())
+            .metrics(metrics.clone())
+            .subscription_refresh(Some(Duration::from_millis(200)))
+            .build()
+            .unwrap();
+        let done = Arc::new(tokio::sync::Notify::new());
+        let run = tokio::spawn({
+            let done = done.clone();
+            async move { listener.run(done.notified()).await }
+        });
+
+        let failed = metrics
+            .subscription_refreshes
+            .with_label_values(&["failed"]);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sink.polls().len() < 2 || failed.get() < 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the current connection keeps delivering");
+        done.notify_one();
+        run.await.unwrap().unwrap();
+
+        let switched = metrics
+            .subscription_refreshes
+            .with_label_values(&["switched"]);
+        assert_eq!(switched.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_silent_connection_is_replaced() {
+        let data = |poll_id: u64| poll_data(&poll(poll_id, "Fruit"));
+        let (url, unsubscribed) = mock_pubsub(vec![
+            // Goes quiet without closing.
+            vec![
+                Step::Send(update(Pubkey::new_from_array([1; 32]), &data(1), 1, 10)),
+                Step::Pause(Duration::from_secs(60)),
+            ],
+            vec![Step::Send(update(
+                Pubkey::new_from_array([2; 32]),
+                &data(2),
+                1,
+                11,
+            ))],
+        ])
+        .await;
+
+        let sink = Arc::new(MemorySink::default());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let listener = Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url(url.clone())
+            .rpc_client(snapshot_rpc(1, &[]))
+            .sink(sink.clone())
+            .metrics(metrics.clone())
+            .stall_timeout(Some(Duration::from_millis(300)))
+            .build()
+            .unwrap();
+        let done = Arc::new(tokio::sync::Notify::new());
+        let run = tokio::spawn({
+            let done = done.clone();
+            async move { listener.run(done.notified()).await }
+        });
+
+        let stalls = metrics
+            .websocket_stalls
+            .with_label_values(&[&bandwidth::endpoint_label(&url)]);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sink.polls().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the new connection delivers");
+        done.notify_one();
+        run.await.unwrap().unwrap();
+
+        let written: Vec<_> = sink.polls().iter().map(|poll| poll.poll_id).collect();
+        assert_eq!(written, [1, 2]);
+        assert_eq!(stalls.get(), 1);
+        // The silent connection, then the new one on the way out.
+        assert_eq!(unsubscribed.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    /// A sink refusing every delegation.
+    #[derive(Default)]
+    struct NoDelegationSink {
+        inner: MemorySink,
+    }
+
+    #[async_trait::async_trait]
+    impl PollSink for NoDelegationSink {
+        async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+            self.inner.write_poll(poll).await
+        }
+
+        async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+            self.inner.write_events(events).await
+        }
+
+        async fn write_delegation(&self, _delegation: NewDelegation) -> Result<()> {
+            anyhow::bail!("delegations table missing")
+        }
+
+        async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+            self.inner.checkpoint(program_id).await
+        }
+
+        async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+            self.inner.save_checkpoint(program_id, slot).await
+        }
+    }
+
+    #[tokio::test]
+    async fn a_refused_update_holds_the_checkpoint_back() {
+        let (url, _) = mock_pubsub(vec![vec![
+            Step::Send(update(
+                Pubkey::new_from_array([1; 32]),
+                &poll_data(&poll(1, "Fruit")),
+                1,
+                10,
+            )),
+            Step::Send(update(
+                Pubkey::new_from_array([2; 32]),
+                &delegation_data(3, 1),
+                1,
+                20,
+            )),
+            Step::Send(update(
+                Pubkey::new_from_array([4; 32]),
+                &poll_data(&poll(2, "Fruit")),
+                1,
+                30,
+            )),
+        ]])
+        .await;
+
+        let sink = Arc::new(NoDelegationSink::default());
+        let listener = Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url(url)
+            .rpc_client(snapshot_rpc(1, &[]))
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let done = Arc::new(tokio::sync::Notify::new());
+        let run = tokio::spawn({
+            let done = done.clone();
+            async move { listener.run(done.notified()).await }
+        });
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sink.inner.polls().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("both polls are delivered");
+        done.notify_one();
+        run.await.unwrap().unwrap();
+
+        // The poll at slot 30 was written, but the delegation at slot 20 wasn't.
+        assert_eq!(sink.inner.checkpoints()[&PROGRAM], 19);
+    }
+
+    /// A sink taking a jittered time to store each poll, logging when it did by `clock`.
+    struct JitterySink {
+        inner: MemorySink,
+        clock: Clock,
+        jitter: std::sync::Mutex<Jitter>,
+        log: Arc<std::sync::Mutex<String>>,
+        /// Polls it was asked to store so far.
+        requested: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl PollSink for JitterySink {
+        async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+            self.requested.fetch_add(1, AtomicOrdering::SeqCst);
+            let took = self.jitter.lock().unwrap().spread(Duration::from_secs(4));
+            self.clock.sleep(took).await;
+            let stored = self.clock.now().format("%H:%M:%S%.3f");
+            writeln!(
+                self.log.lock().unwrap(),
+                "{} poll {} stored",
+                stored,
+                poll.poll_name
+            )
+            .unwrap();
+            self.inner.write_poll(poll).await
+        }
+
+        async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+            self.inner.write_events(events).await
+        }
+
+        async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+            self.inner.checkpoint(program_id).await
+        }
+
+        async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+            self.inner.save_checkpoint(program_id, slot).await
+        }
+    }
+
+    /// Updates of three polls through a mock websocket into a [`JitterySink`], on the
+    /// listener's injected clock; returns what was stored and emitted and when, the slot lag
+    /// and the checkpoint.
+    async fn simulated_pipeline(seed: u64) -> String {
+        let clock = noon();
+        let updates = (0..9u8)
+            .map(|i| {
+                let poll_id = u64::from(i % 3) + 1;
+                let data = poll_data(&poll(poll_id, &format!("{}.{}", poll_id, i / 3)));
+                let account = Pubkey::new_from_array([i % 3 + 1; 32]);
+                Step::Send(update(account, &data, 1, 100 + u64::from(i) * 10))
+            })
+            .collect();
+        let (url, _) = mock_pubsub(vec![updates]).await;
+        // Slot 0 a minute before noon, 400 ms a slot.
+        let slot_clock = Arc::new(SlotClock::default());
+        for (slot, block_time) in [(0, -60), (1_000, 340)] {
+            slot_clock.record(SlotSample {
+                slot,
+                block_time: clock.unix_now() + block_time,
+            });
+        }
+
+        let log = Arc::new(std::sync::Mutex::new(String::new()));
+        let sink = Arc::new(JitterySink {
+            inner: MemorySink::default(),
+            clock,
+            jitter: std::sync::Mutex::new(Jitter::seeded(seed)),
+            log: log.clone(),
+            requested: AtomicUsize::new(0),
+        });
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let mut listener = Listener::builder()
+            .program_id(PROGRAM)
+            .ws_url(url)
+            .rpc_client(snapshot_rpc(1, &[]))
+            .sink(sink.clone())
+            .metrics(metrics.clone())
+            .slot_clock(slot_clock)
+            .clock(clock)
+            .build()
+            .unwrap();
+        let mut events = listener.events();
+        let done = Arc::new(tokio::sync::Notify::new());
+        let run = tokio::spawn({
+            let done = done.clone();
+            async move { listener.run(done.notified()).await }
+        });
+        // Paused time jumps ahead whenever the runtime is idle, e.g. to the websocket client's
+        // ping timer while the connection is being set up: keep it busy until the first write.
+        while sink.requested.load(AtomicOrdering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        for _ in 0..9 {
+            match events.next().await.unwrap() {
+                VotingEvent::PollUpdated { slot, poll, .. } => {
+                    let emitted = clock.now().format("%H:%M:%S%.3f");
+                    let mut log = log.lock().unwrap();
+                    writeln!(
+                        log,
+                        "{} poll {} emitted at slot {}",
+                        emitted, poll.poll_name, slot
+                    )
+                    .unwrap();
+                }
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+        done.notify_one();
+        run.await.unwrap().unwrap();
+
+        let mut log = log.lock().unwrap();
+        writeln!(log, "slot lag {}s", metrics.slot_lag_seconds.get()).unwrap();
+        writeln!(log, "checkpoint {}", sink.inner.checkpoints()[&PROGRAM]).unwrap();
+        log.clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_simulated_pipeline_replays_identically() {
+        let first = simulated_pipeline(42).await;
+        assert_eq!(first, simulated_pipeline(42).await);
+        // The last update came in at 12:00:22.904, 10 s after its slot.
+        assert!(
+            first.contains("12:00:22.904 poll 2.2 emitted at slot 170\n"),
+            "{}",
+            first
+        );
+        assert!(
+            first.ends_with("slot lag 10s\ncheckpoint 180\n"),
+            "{}",
+            first
+        );
+        // The jitter changes how long each write takes, and only with another seed.
+        assert_ne!(first, simulated_pipeline(43).await);
+    }
+}
@@ -0,0 +1,284 @@
+use std::pin::pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{self, Stream};
+use futures::{SinkExt, StreamExt};
+use solana_sdk::pubkey::Pubkey;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::warn;
+
+use crate::db::models::{
+    NewDecodeFailure, NewDelegation, NewEvent, NewIdlAccount, NewPoll, NewRawAccount,
+};
+use crate::metrics::Metrics;
+use crate::sink::PollSink;
+use voting_dapp_api_types::LiveUpdate;
+
+/// Updates buffered for each `GET /live` connection. A connection further behind misses the
+/// oldest ones and gets a `lagged` message saying how many.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// An update on its way to the `GET /live` connections, serialized once for all of them.
+#[derive(Debug, Clone)]
+pub struct LiveMessage {
+    /// Raw program ID, for `?program=`.
+    pub program_id: Vec<u8>,
+    pub json: Arc<str>,
+}
+
+/// Wraps the listener's sink to push what it writes to the `GET /live` connections, so
+/// dashboards get updates as they're ingested instead of polling the database.
+///
+/// An update is pushed once the inner sink accepted it (for Postgres: once it's queued for the
+/// writer). Events pass through without being pushed.
+pub struct LiveSink {
+    inner: Arc<dyn PollSink>,
+    sender: broadcast::Sender<LiveMessage>,
+}
+
+impl LiveSink {
+    /// Wraps `inner`. The returned [`LiveUpdates`] subscribes connections until the sink is
+    /// dropped (the listener drops it when it stops).
+    pub fn new(inner: Arc<dyn PollSink>) -> (Self, LiveUpdates) {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let updates = LiveUpdates(sender.downgrade());
+        (Self { inner, sender }, updates)
+    }
+
+    fn publish(&self, program_id: &[u8], update: Result<LiveUpdate>) {
+        // Nobody listening: don't bother serializing.
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        let json = update.and_then(|update| Ok(serde_json::to_string(&update)?));
+        match json {
+            Ok(json) => {
+                // Fails only when every connection closed in the meantime.
+                let _ = self.sender.send(LiveMessage {
+                    program_id: program_id.to_vec(),
+                    json: json.into(),
+                });
+            }
+            Err(e) => warn!(error = ?e, "Could not push a live update"),
+        }
+    }
+}
+
+#[async_trait]
+impl PollSink for LiveSink {
+    async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+        let update = poll.to_live();
+        let program_id = poll.program_id.clone();
+        self.inner.write_poll(poll).await?;
+        self.publish(&program_id, update);
+        Ok(())
+    }
+
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+        self.inner.write_events(events).await
+    }
+
+    async fn write_delegation(&self, delegation: NewDelegation) -> Result<()> {
+        let update = delegation.to_live();
+        let program_id = delegation.program_id.clone();
+        self.inner.write_delegation(delegation).await?;
+        self.publish(&program_id, update);
+        Ok(())
+    }
+
+    async fn write_idl_account(&self, account: NewIdlAccount) -> Result<()> {
+        let update = account.to_live();
+        let program_id = account.program_id.clone();
+        self.inner.write_idl_account(account).await?;
+        self.publish(&program_id, update);
+        Ok(())
+    }
+
+    async fn write_decode_failure(&self, failure: NewDecodeFailure) -> Result<()> {
+        self.inner.write_decode_failure(failure).await
+    }
+
+    async fn write_raw_account(&self, raw: NewRawAccount) -> Result<()> {
+        self.inner.write_raw_account(raw).await
+    }
+
+    async fn mark_closed(&self, program_id: &Pubkey, account: &Pubkey, slot: u64) -> Result<bool> {
+        self.inner.mark_closed(program_id, account, slot).await
+    }
+
+    async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+        self.inner.checkpoint(program_id).await
+    }
+
+    async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+        self.inner.save_checkpoint(program_id, slot).await
+    }
+}
+
+/// Hands out subscriptions to a [`LiveSink`]'s updates, without keeping the channel open.
+#[derive(Clone)]
+pub struct LiveUpdates(broadcast::WeakSender<LiveMessage>);
+
+impl LiveUpdates {
+    /// A new subscription, or `None` once the listener has stopped.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<LiveMessage>> {
+        self.0.upgrade().map(|sender| sender.subscribe())
+    }
+}
+
+/// The updates of `receiver` (of one program, or every program when `None`) as JSON, with a
+/// `lagged` update where some were missed. Ends when the listener stops.
+pub fn updates(
+    receiver: broadcast::Receiver<LiveMessage>,
+    program: Option<Vec<u8>>,
+) -> impl Stream<Item = Arc<str>> + Send {
+    stream::unfold(receiver, move |mut receiver| {
+        let program = program.clone();
+        async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        if program.as_ref().is_some_and(|p| *p != message.program_id) {
+                            continue;
+                        }
+                        return Some((message.json, receiver));
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        let lagged = LiveUpdate::Lagged { missed };
+                        let json = serde_json::to_string(&lagged).expect("lagged serializes");
+                        return Some((json.into(), receiver));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    })
+}
+
+/// Sends the [`updates`] of `receiver` to an upgraded `GET /live` connection as text
+/// messages, until either side closes.
+pub async fn stream<S>(
+    mut socket: WebSocketStream<S>,
+    receiver: broadcast::Receiver<LiveMessage>,
+    program: Option<Vec<u8>>,
+    metrics: Arc<Metrics>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    metrics.live_connections.inc();
+    let mut updates = pin!(updates(receiver, program));
+    loop {
+        tokio::select! {
+            update = updates.next() => {
+                let Some(json) = update else {
+                    // The listener stopped.
+                    let _ = socket.close(None).await;
+                    break;
+                };
+                if socket.send(Message::Text(json.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            // Reading answers pings and the client's close; what clients send is ignored.
+            incoming = socket.next() => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    break;
+                }
+            }
+        }
+    }
+    metrics.live_connections.dec();
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::db::test_support::new_poll;
+    use crate::sink::MemorySink;
+
+    /// Refuses every poll, like a writer that stopped.
+    struct RefusingSink;
+
+    #[async_trait]
+    impl PollSink for RefusingSink {
+        async fn write_poll(&self, _poll: NewPoll) -> Result<()> {
+            anyhow::bail!("writer stopped")
+        }
+
+        async fn write_events(&self, _events: Vec<NewEvent>) -> Result<usize> {
+            Ok(0)
+        }
+    }
+
+    /// The `type` and `poll_id` of each update, or `missed` for `lagged` ones.
+    async fn summary(updates: impl Stream<Item = Arc<str>>) -> Vec<(String, i64)> {
+        updates
+            .map(|json| {
+                let update: Value = serde_json::from_str(&json).unwrap();
+                let number = update.get("poll_id").or(update.get("missed"));
+                (
+                    update["type"].as_str().unwrap().to_string(),
+                    number.and_then(Value::as_i64).unwrap(),
+                )
+            })
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn written_updates_reach_the_subscribers_of_their_program() {
+        let inner = Arc::new(MemorySink::default());
+        let (sink, live) = LiveSink::new(inner.clone());
+        let every_program = updates(live.subscribe().unwrap(), None);
+        let one_program = updates(live.subscribe().unwrap(), Some(vec![1; 32]));
+
+        sink.write_poll(new_poll(&[1; 32], 1, 100)).await.unwrap();
+        sink.write_poll(new_poll(&[2; 32], 2, 100)).await.unwrap();
+        drop(sink);
+
+        assert_eq!(inner.polls().len(), 2);
+        assert_eq!(
+            summary(every_program).await,
+            [("poll".to_string(), 1), ("poll".to_string(), 2)]
+        );
+        assert_eq!(summary(one_program).await, [("poll".to_string(), 1)]);
+        // The listener stopped: nothing to subscribe to anymore.
+        assert!(live.subscribe().is_none());
+    }
+
+    #[tokio::test]
+    async fn refused_updates_are_not_pushed() {
+        let (sink, live) = LiveSink::new(Arc::new(RefusingSink));
+        let receiver = live.subscribe().unwrap();
+
+        assert!(sink.write_poll(new_poll(&[1; 32], 1, 100)).await.is_err());
+        drop(sink);
+        assert!(summary(updates(receiver, None)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_are_told_how_many_updates_they_missed() {
+        let (sink, live) = LiveSink::new(Arc::new(MemorySink::default()));
+        let receiver = live.subscribe().unwrap();
+
+        let sent = CHANNEL_CAPACITY as i64 + 3;
+        for poll_id in 0..sent {
+            sink.write_poll(new_poll(&[1; 32], poll_id, 100))
+                .await
+                .unwrap();
+        }
+        drop(sink);
+
+        let received = summary(updates(receiver, None)).await;
+        assert_eq!(received[0], ("lagged".to_string(), 3));
+        assert_eq!(received[1], ("poll".to_string(), 3));
+        assert_eq!(received.len(), CHANNEL_CAPACITY + 1);
+    }
+}
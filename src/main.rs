@@ -1,205 +1,1189 @@
 use anyhow::{Context, Result};
-use futures::StreamExt;
-use solana_account_decoder::UiAccountEncoding;
-use solana_client::{
-    nonblocking::pubsub_client::PubsubClient,
-    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
-    rpc_response::{Response, RpcKeyedAccount},
+use chrono::{Datelike, NaiveDate, Utc};
+use clap::{CommandFactory, Parser, ValueEnum};
+use serde_json::Value;
+use solana_sdk::{
+    commitment_config::{CommitmentConfig, CommitmentLevel},
+    pubkey::Pubkey,
 };
-use solana_sdk::pubkey;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::{self, signal};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
-use voting_dapp_listener::db::db::{establish_pool, upsert_poll, PgPool};
-use voting_dapp_listener::db::models::NewPoll;
-use voting_dapp_listener::state::pool::Poll;
+use voting_dapp_listener::api::{self, ApiState, ListenerHealth};
+use voting_dapp_listener::bandwidth::{self, BandwidthMeter};
+use voting_dapp_listener::clock::Clock;
+use voting_dapp_listener::completeness::{self, CompletenessWeights, ScoreChanges};
+use voting_dapp_listener::components::ComponentRegistry;
+use voting_dapp_listener::config_audit::{self, ConfigSnapshot};
+use voting_dapp_listener::config_file;
+use voting_dapp_listener::db::db::{
+    advance_lifecycles, backfill_checksums, bandwidth_total_since, claim_unattributed_polls,
+    count_unattributed_polls, establish_pool_with, rebuild_poll_results, record_bandwidth, PgPool,
+};
+use voting_dapp_listener::db::migrations;
+use voting_dapp_listener::decode::VotingAccountType;
+use voting_dapp_listener::errors;
+use voting_dapp_listener::idl::{self, IdlLoad};
+use voting_dapp_listener::idl_decode::IdlDecoder;
+use voting_dapp_listener::listener::{self, AccountEncoding, Listener, ListenerExit};
+use voting_dapp_listener::live::LiveSink;
+use voting_dapp_listener::metrics::Metrics;
+use voting_dapp_listener::quota::{QuotaAction, QuotaConfig, StorageQuotas, TableLimits};
+use voting_dapp_listener::scheduler::{JobClass, Schedule, Scheduler};
+use voting_dapp_listener::setup::{DEFAULT_PROGRAM_ID, DEFAULT_WS_URL};
+use voting_dapp_listener::sink::{PollSink, PostgresSink, StdoutSink};
+use voting_dapp_listener::slot_clock::{self, SlotClock};
+use voting_dapp_listener::warmup::WarmupConfig;
+use voting_dapp_listener::writer::{self, log_lifecycle_transition, WriterConfig};
 
-// Descriminator obtained from the IDL
-const POLL_DISCRIMINATOR: [u8; 8] = [110, 234, 167, 188, 231, 136, 153, 111];
-const POOL_CANDIDATE_DISCRIMINATOR: [u8; 8] = [86, 69, 250, 96, 193, 10, 222, 123];
-const VOTE_DISCRIMINATOR: [u8; 8] = [241, 93, 35, 191, 254, 147, 17, 202];
-const URL: &'static str = "wss://api.devnet.solana.com/";
+// How often the shared slot clock samples (slot, block_time) from the RPC.
+const SLOT_CLOCK_INTERVAL: Duration = Duration::from_secs(60);
+// How often time-driven lifecycle transitions (upcoming → active → ended) are evaluated.
+const LIFECYCLE_INTERVAL: Duration = Duration::from_secs(30);
+// How long each component (other than the writer) gets to stop on shutdown.
+const COMPONENT_TIMEOUT: Duration = Duration::from_secs(5);
+// How often received-bytes counters are flushed to `bandwidth_usage`.
+const BANDWIDTH_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Step 1: Connect to Solana RPC WebSocket server using the async PubsubClient.
-    // This client manages a WebSocket connection to listen for events (e.g. account updates).
-    // Unlike the blocking version, this is fully async and cancelable
-    let client = PubsubClient::new(URL)
-        .await
-        .map_err(anyhow::Error::from)
-        .with_context(|| "Failed to connect to PubsubClient")?;
-
-    let db_pool = establish_pool()?;
-
-    // Step 2: Define the Program ID you want to listen to.
-    // This is the public key of the on-chain Solana program you're interested in (e.g. a voting dApp).
-    // Only accounts owned by this program will trigger updates via `program_subscribe`.
-    let program_id = pubkey!("HH6z4hgoYg2ZsSkceAUxPZUJdWt8hLqUm1SoEmWqYhPh");
-
-    // Step 3: Define the subscription config for program accounts.
-    // Without explicitly setting Base64 encoding, account data may come back as "legacy" format,
-    // or be inconsistently decoded (leading to decode errors).
-    // Other options (like filters, context, and sorting) are left default or None here.
-    let config = RpcProgramAccountsConfig {
-        filters: None,
-        account_config: RpcAccountInfoConfig {
-            encoding: Some(UiAccountEncoding::Base64),
-            ..Default::default()
-        },
-        with_context: None,
-        sort_results: None,
-    };
+/// Real-time listener that indexes a Solana voting program into PostgreSQL.
+///
+/// Every option can also be provided through the environment (or `.env`) or a TOML config
+/// file, and falls back to the devnet deployment when none of them sets it.
+#[derive(Parser)]
+#[command(name = "Voting DAPP Listener")]
+#[command(about = "Index on-chain poll accounts into PostgreSQL", long_about = None)]
+struct Args {
+    /// The on-chain programs whose accounts should be indexed (comma-separated).
+    /// Falls back to the legacy `PROGRAM_ID` variable, then to the devnet deployment
+    #[arg(
+        long = "program-ids",
+        visible_alias = "program-id",
+        env = "PROGRAM_IDS",
+        value_delimiter = ',',
+        value_parser = parse_pubkey
+    )]
+    program_ids: Vec<Pubkey>,
+
+    /// TOML file setting any of these options, keyed by flag name (`ws_url = "..."`).
+    /// Flags and the environment take precedence. Defaults to `listener.toml` if it exists
+    #[arg(long, env = "LISTENER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Anchor IDLs (JSON) of the indexed programs, comma-separated. Each is stored as a new
+    /// version when its content changed, and served at `/programs/{id}/idl`
+    #[arg(long, env = "IDL_PATHS", value_delimiter = ',')]
+    idl: Vec<PathBuf>,
+
+    /// Websocket endpoint used for `program_subscribe`
+    #[arg(long, env = "SOLANA_WS_URL", default_value = DEFAULT_WS_URL)]
+    ws_url: String,
+
+    /// Websocket endpoints to fail over to when the one in use disconnects, in order of
+    /// preference (comma-separated). Without any, the listener stops when `--ws-url` disconnects
+    #[arg(long, env = "SOLANA_FALLBACK_WS_URLS", value_delimiter = ',')]
+    fallback_ws_urls: Vec<String>,
+
+    /// Skip a websocket endpoint this many seconds after it failed, doubling with each failure
+    /// in a row (up to 8 times)
+    #[arg(long, env = "FAILOVER_COOLDOWN_SECS", default_value_t = 30)]
+    failover_cooldown_secs: u64,
+
+    /// HTTP endpoint used for the backfill and slot sampling (derived from `--ws-url` if omitted)
+    #[arg(long, env = "SOLANA_RPC_URL")]
+    rpc_url: Option<String>,
+
+    /// Commitment level for subscriptions and RPC calls: processed, confirmed or finalized
+    #[arg(long, env = "COMMITMENT", default_value = "finalized", value_parser = parse_commitment)]
+    commitment: CommitmentLevel,
+
+    /// Encoding of the account data sent by the RPC: base64, or base64+zstd to compress large
+    /// accounts (less bandwidth, a little CPU)
+    #[arg(long, env = "ACCOUNT_ENCODING", value_enum, default_value_t = AccountEncoding::Base64)]
+    account_encoding: AccountEncoding,
+
+    /// Where decoded accounts are written
+    #[arg(long, env = "SINK", value_enum, default_value_t = SinkKind::Postgres)]
+    sink: SinkKind,
+
+    /// Never write to the database. Requires `--sink stdout`
+    #[arg(long, env = "READ_ONLY")]
+    read_only: bool,
+
+    /// Apply pending database migrations at startup instead of refusing to run (fresh
+    /// deployments). Off by default, so a DBA can review them first (see `cli migrations`)
+    #[arg(long, env = "RUN_MIGRATIONS")]
+    run_migrations: bool,
+
+    /// Emit logs as JSON lines instead of human readable text (log level via `RUST_LOG`)
+    #[arg(long, env = "LOG_JSON")]
+    log_json: bool,
+
+    /// Only receive these account types, filtered server-side by discriminator
+    /// (comma-separated, e.g. `--only poll,candidate`). Defaults to all types.
+    #[arg(long, env = "ONLY", value_enum, value_delimiter = ',')]
+    only: Vec<VotingAccountType>,
+
+    /// With `--only`, still check the discriminator of this share (0-1) of the filtered updates,
+    /// to catch RPC providers that ignore filters; every update is checked once one does
+    #[arg(long, env = "FILTER_SAMPLE_RATE", default_value_t = 0.01, value_parser = parse_ratio)]
+    filter_sample_rate: f64,
+
+    /// Flush decoded polls to Postgres once this many are queued
+    #[arg(long, env = "BATCH_SIZE", default_value_t = 100)]
+    batch_size: usize,
+
+    /// Flush queued polls at most this many milliseconds after the first one arrived
+    #[arg(long, env = "BATCH_INTERVAL_MS", default_value_t = 250)]
+    batch_interval_ms: u64,
+
+    /// Fewest batches the writer flushes to Postgres at once
+    #[arg(long, env = "WRITER_MIN_CONCURRENCY", default_value_t = 1)]
+    writer_min_concurrency: usize,
+
+    /// Most batches the writer flushes at once while the queue is backed up
+    #[arg(long, env = "WRITER_MAX_CONCURRENCY", default_value_t = 4)]
+    writer_max_concurrency: usize,
+
+    /// Share of the connection pool (0..=1) the writer may hold at once
+    #[arg(long, env = "WRITER_POOL_SHARE", default_value_t = 0.5, value_parser = parse_ratio)]
+    writer_pool_share: f64,
+
+    /// Quarantine an account after this many writes of its data failed in a row (0 never does)
+    #[arg(long, env = "QUARANTINE_AFTER", default_value_t = 3)]
+    quarantine_after: u32,
+
+    /// On shutdown, wait at most this many seconds for queued DB writes (a second Ctrl+C skips the wait)
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS", default_value_t = 10)]
+    shutdown_timeout_secs: u64,
+
+    /// Serve the read-only HTTP API (`/polls`, `/polls/{poll_id}`, `/health`, `/metrics`) on this port
+    #[arg(long, env = "HTTP_PORT")]
+    http_port: Option<u16>,
+
+    /// Clock skew tolerance in seconds for poll lifecycle boundaries: a poll only starts or ends
+    /// once the boundary is this far in the past, and flaps back within it are suppressed
+    #[arg(long, env = "LIFECYCLE_SKEW_SECS", default_value_t = 5)]
+    lifecycle_skew_secs: u32,
+
+    /// Debugging aid: recompute row checksums on every HTTP API read and report mismatches
+    #[arg(long, env = "VERIFY_CHECKSUMS_ON_READ")]
+    verify_checksums_on_read: bool,
+
+    /// Don't include operator annotations (`cli annotate`) in HTTP API poll responses
+    #[arg(long, env = "HIDE_ANNOTATIONS")]
+    hide_annotations: bool,
+
+    /// Points each signal takes off a poll's completeness score on the HTTP API, as `name=points`
+    /// pairs (e.g. `critical_annotation=60,anomaly=5`); unlisted signals keep their default
+    #[arg(long, env = "COMPLETENESS_WEIGHTS", default_value_t = CompletenessWeights::default())]
+    completeness_weights: CompletenessWeights,
+
+    /// Log an event when a poll's completeness score served by the HTTP API moved by at least
+    /// this many points since it was last served
+    #[arg(long, env = "COMPLETENESS_EVENT_THRESHOLD", default_value_t = completeness::DEFAULT_CHANGE_THRESHOLD)]
+    completeness_event_threshold: u8,
+
+    /// Most entries in the HTTP API's Atom feeds (`/feed.atom`); `?limit=` can only ask for fewer
+    #[arg(long, env = "FEED_MAX_ENTRIES", default_value_t = 50)]
+    feed_max_entries: i64,
+
+    /// Run every HTTP API read on its own instead of sharing the queries of identical
+    /// concurrent reads
+    #[arg(long, env = "NO_READ_COALESCING")]
+    no_read_coalescing: bool,
+
+    /// Serve CPU profiles at `/debug/pprof/profile` on the HTTP API (needs a build with the
+    /// `profiling` feature)
+    #[arg(long, env = "ENABLE_PROFILING")]
+    enable_profiling: bool,
+
+    /// Also subscribe to the programs' transaction logs (`logsSubscribe`) and record the
+    /// instructions and events they contain in the `events` table
+    #[arg(long, env = "WITH_LOGS")]
+    with_logs: bool,
+
+    /// Remember the last account data of at most this many accounts to skip identical
+    /// redelivered updates (0 disables)
+    #[arg(long, env = "DEDUP_MAX_ENTRIES", default_value_t = 10_000)]
+    dedup_max_entries: usize,
+
+    /// Warm-up check: evaluate at most this many websocket messages per program after subscribing
+    #[arg(long, env = "WARMUP_MESSAGES", default_value_t = 50)]
+    warmup_messages: usize,
+
+    /// Warm-up check: evaluate the messages received within this many seconds at most
+    #[arg(long, env = "WARMUP_SECS", default_value_t = 60)]
+    warmup_secs: u64,
+
+    /// Warm-up check: minimum share (0-1) of messages that must be decodable voting accounts
+    #[arg(long, env = "WARMUP_MIN_RATIO", default_value_t = 0.5, value_parser = parse_ratio)]
+    warmup_min_ratio: f64,
+
+    /// Exit with a diagnostic when the warm-up check fails (e.g. a wrong program ID)
+    #[arg(long, env = "STRICT_WARMUP")]
+    strict_warmup: bool,
+
+    /// Monthly RPC bandwidth budget in bytes; alerts are logged at 80% and 100%
+    #[arg(long, env = "BANDWIDTH_BUDGET_BYTES")]
+    bandwidth_budget: Option<u64>,
+
+    /// Replace the websocket connection every this many minutes, without a gap (e.g. 360)
+    #[arg(long, env = "WS_REFRESH_MINS", value_parser = clap::value_parser!(u64).range(1..))]
+    ws_refresh_mins: Option<u64>,
+
+    /// Re-subscribe (failing over, with `--fallback-ws-urls`) after this many seconds without
+    /// a websocket message, e.g. 600. Keep it above the programs' longest quiet periods
+    #[arg(long, env = "WS_STALL_TIMEOUT_SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    ws_stall_timeout_secs: Option<u64>,
+
+    /// On restart, catch up on at most this many transactions since a program's checkpoint
+    /// instead of backfilling it in full (0 always backfills in full)
+    #[arg(long, env = "CATCH_UP_MAX_TRANSACTIONS", default_value_t = 1000)]
+    catch_up_max_transactions: usize,
+
+    /// Archive every raw account update in `account_raw_history`, so `cli replay` can rebuild
+    /// the derived tables after a schema or decoder change
+    #[arg(long, env = "ARCHIVE_RAW_ACCOUNTS")]
+    archive_raw_accounts: bool,
+
+    /// Background jobs mostly hitting the database (lifecycles, bandwidth flush) run at once
+    #[arg(long, env = "DB_JOB_CONCURRENCY", default_value_t = 1)]
+    db_job_concurrency: usize,
+
+    /// Background jobs mostly hitting the RPC provider (slot clock) run at once
+    #[arg(long, env = "RPC_JOB_CONCURRENCY", default_value_t = 1)]
+    rpc_job_concurrency: usize,
 
-    // Step 4: Subscribe to program-owned accounts using `program_subscribe`.
-    // Returns:
-    // - `stream`: a `futures::Stream` of account changes (as `RpcResponse<RpcKeyedAccount>`)
-    // - `_unsubscribe`: a closure to manually unsubscribe (not used here)
-    //
-    // If subscription fails (e.g. network issue, bad program ID), the error is wrapped in context.
-    let (mut stream, _unsubscribe) = client
-        .program_subscribe(&program_id, Some(config))
-        .await
-        .map_err(anyhow::Error::from)
-        .with_context(|| "Failed to subscribe to the program")?;
-
-    println!("Listening for state changes to program: {}", program_id);
-
-    // Step 5: Use `tokio::select!` to wait for either:
-    // 1. The `stream` finishing (due to RPC server closing connection)
-    // 2. The user pressing Ctrl+C (for graceful shutdown)
-    tokio::select! {
-        // Loop over incoming updates (stream is an async stream of account changes)
-        // As long as messages are coming in, this loop runs and processes them one by one.
-        _ = async {
-            while let Some(response) = stream.next().await {
-                // Process each account update (e.g. decode poll state and print info)
-                handle_response(response, &db_pool);
+    /// Storage quota: most rows each program may have in a table, as `table=rows` pairs
+    /// (tables: polls, delegations, events, idl_accounts, account_raw_history, decode_failures;
+    /// e.g. `events=1000000`)
+    #[arg(long, env = "QUOTA_ROWS")]
+    quota_rows: Option<TableLimits>,
+
+    /// Storage quota: most estimated bytes on disk each program may take in a table, as
+    /// `table=bytes` pairs
+    #[arg(long, env = "QUOTA_BYTES")]
+    quota_bytes: Option<TableLimits>,
+
+    /// What happens while a program is over a quota: warn, stop (drop its updates of that table)
+    /// or prune (delete the data of its polls that ended first, or its oldest rows)
+    #[arg(long, env = "QUOTA_ACTION", default_value_t = QuotaAction::Warn)]
+    quota_action: QuotaAction,
+
+    /// How often quota usage is recounted and the tables' size re-read
+    #[arg(long, env = "QUOTA_CHECK_SECS", default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+    quota_check_secs: u64,
+}
+
+/// Destination for decoded account updates, as selected on the command line.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SinkKind {
+    /// Upsert decoded accounts into PostgreSQL
+    Postgres,
+    /// Only print decoded accounts, never touch the database
+    Stdout,
+}
+
+impl Args {
+    /// Returns the programs to index, without duplicates and in the order given.
+    /// Single-program setups configured through `PROGRAM_ID` keep working unchanged.
+    fn program_ids(&self) -> Result<Vec<Pubkey>> {
+        let mut program_ids = self.program_ids.clone();
+        if program_ids.is_empty() {
+            let legacy =
+                std::env::var("PROGRAM_ID").unwrap_or_else(|_| DEFAULT_PROGRAM_ID.to_string());
+            program_ids.push(parse_pubkey(&legacy).map_err(anyhow::Error::msg)?);
+        }
+        let mut unique = Vec::with_capacity(program_ids.len());
+        for program_id in program_ids {
+            if !unique.contains(&program_id) {
+                unique.push(program_id);
             }
-        } => {}
-        // If Ctrl+C is received, we break the listener loop and begin shutdown.
-        _ = signal::ctrl_c() => {
-            println!("Ctrl+C received, shutting down...");
         }
+        Ok(unique)
     }
 
-    // Step 6: Drop the stream before shutting down the client.
-    // Important: the stream borrows from `client`, so we must drop it explicitly
-    // to avoid "cannot move out of borrowed value" compiler error.
-    drop(stream);
-    // Step 7: Gracefully shut down the WebSocket connection.
-    // This sends the shutdown signal to the internal WebSocket task spawned by `PubsubClient`.
-    client.shutdown().await?;
-    println!("Good Bye");
-    Ok(())
+    /// The storage quotas, empty when neither `--quota-rows` nor `--quota-bytes` is set.
+    fn quota_config(&self) -> QuotaConfig {
+        QuotaConfig {
+            max_rows: self.quota_rows.clone().unwrap_or_default(),
+            max_bytes: self.quota_bytes.clone().unwrap_or_default(),
+            action: self.quota_action,
+        }
+    }
+
+    /// Returns the HTTP RPC URL, deriving it from the websocket URL when not given explicitly
+    /// (see [`listener::rpc_url_for`]).
+    fn rpc_url(&self) -> String {
+        self.rpc_url
+            .clone()
+            .unwrap_or_else(|| listener::rpc_url_for(&self.ws_url))
+    }
+
+    /// The effective configuration as recorded in `config_changes`, with URLs masked.
+    /// Options left unset (no default) are omitted.
+    fn effective_config(&self, program_ids: &[Pubkey]) -> ConfigSnapshot {
+        let mut config = ConfigSnapshot::new();
+        let mut set = |key: &str, value: String| {
+            config.insert(key.to_string(), value);
+        };
+        let program_ids: Vec<String> = program_ids.iter().map(Pubkey::to_string).collect();
+        set("program_ids", program_ids.join(","));
+        if !self.idl.is_empty() {
+            let paths: Vec<String> = self.idl.iter().map(|p| p.display().to_string()).collect();
+            set("idl", paths.join(","));
+        }
+        set("ws_url", config_audit::mask_url(&self.ws_url));
+        if !self.fallback_ws_urls.is_empty() {
+            let urls: Vec<String> = self
+                .fallback_ws_urls
+                .iter()
+                .map(|url| config_audit::mask_url(url))
+                .collect();
+            set("fallback_ws_urls", urls.join(","));
+        }
+        set(
+            "failover_cooldown_secs",
+            self.failover_cooldown_secs.to_string(),
+        );
+        set("rpc_url", config_audit::mask_url(&self.rpc_url()));
+        if let Ok(database_url) = std::env::var("DATABASE_URL") {
+            set("database_url", config_audit::mask_url(&database_url));
+        }
+        set(
+            "commitment",
+            format!("{:?}", self.commitment).to_ascii_lowercase(),
+        );
+        set(
+            "account_encoding",
+            self.account_encoding.as_str().to_string(),
+        );
+        if let Some(sink) = self.sink.to_possible_value() {
+            set("sink", sink.get_name().to_string());
+        }
+        set("read_only", self.read_only.to_string());
+        set("run_migrations", self.run_migrations.to_string());
+        set("log_json", self.log_json.to_string());
+        let only: Vec<&str> = self.only.iter().map(VotingAccountType::as_str).collect();
+        set("only", only.join(","));
+        set("filter_sample_rate", self.filter_sample_rate.to_string());
+        set("batch_size", self.batch_size.to_string());
+        set("batch_interval_ms", self.batch_interval_ms.to_string());
+        set(
+            "writer_min_concurrency",
+            self.writer_min_concurrency.to_string(),
+        );
+        set(
+            "writer_max_concurrency",
+            self.writer_max_concurrency.to_string(),
+        );
+        set("writer_pool_share", self.writer_pool_share.to_string());
+        set("quarantine_after", self.quarantine_after.to_string());
+        set(
+            "shutdown_timeout_secs",
+            self.shutdown_timeout_secs.to_string(),
+        );
+        if let Some(port) = self.http_port {
+            set("http_port", port.to_string());
+        }
+        set("lifecycle_skew_secs", self.lifecycle_skew_secs.to_string());
+        set(
+            "verify_checksums_on_read",
+            self.verify_checksums_on_read.to_string(),
+        );
+        set("hide_annotations", self.hide_annotations.to_string());
+        set(
+            "completeness_weights",
+            self.completeness_weights.to_string(),
+        );
+        set(
+            "completeness_event_threshold",
+            self.completeness_event_threshold.to_string(),
+        );
+        set("feed_max_entries", self.feed_max_entries.to_string());
+        set("no_read_coalescing", self.no_read_coalescing.to_string());
+        set("enable_profiling", self.enable_profiling.to_string());
+        set("with_logs", self.with_logs.to_string());
+        set("dedup_max_entries", self.dedup_max_entries.to_string());
+        set("warmup_messages", self.warmup_messages.to_string());
+        set("warmup_secs", self.warmup_secs.to_string());
+        set("warmup_min_ratio", self.warmup_min_ratio.to_string());
+        set("strict_warmup", self.strict_warmup.to_string());
+        if let Some(budget) = self.bandwidth_budget {
+            set("bandwidth_budget", budget.to_string());
+        }
+        if let Some(minutes) = self.ws_refresh_mins {
+            set("ws_refresh_mins", minutes.to_string());
+        }
+        if let Some(secs) = self.ws_stall_timeout_secs {
+            set("ws_stall_timeout_secs", secs.to_string());
+        }
+        set(
+            "catch_up_max_transactions",
+            self.catch_up_max_transactions.to_string(),
+        );
+        set(
+            "archive_raw_accounts",
+            self.archive_raw_accounts.to_string(),
+        );
+        set("db_job_concurrency", self.db_job_concurrency.to_string());
+        set("rpc_job_concurrency", self.rpc_job_concurrency.to_string());
+        if let Some(limits) = &self.quota_rows {
+            set("quota_rows", limits.to_string());
+        }
+        if let Some(limits) = &self.quota_bytes {
+            set("quota_bytes", limits.to_string());
+        }
+        set("quota_action", self.quota_action.to_string());
+        set("quota_check_secs", self.quota_check_secs.to_string());
+        config
+    }
+}
+
+fn parse_pubkey(s: &str) -> Result<Pubkey, String> {
+    Pubkey::from_str(s).map_err(|e| format!("'{}' is not a valid base58 pubkey: {}", s, e))
+}
+
+/// A share between 0 and 1, both included.
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    let ratio: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("{} is not between 0 and 1", s));
+    }
+    Ok(ratio)
+}
+
+fn parse_commitment(s: &str) -> Result<CommitmentLevel, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "processed" => Ok(CommitmentLevel::Processed),
+        "confirmed" => Ok(CommitmentLevel::Confirmed),
+        "finalized" => Ok(CommitmentLevel::Finalized),
+        _ => Err(format!(
+            "unknown commitment '{}', expected one of: processed, confirmed, finalized",
+            s
+        )),
+    }
+}
+
+/// Records the effective config in `config_changes` when it differs from the last recorded one,
+/// so it's known what changed between runs, when and by whom.
+/// Auditing is best effort: a failure is logged and indexing goes on.
+fn audit_config(pool: &PgPool, config: &ConfigSnapshot) {
+    let changed_by = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    match config_audit::record_if_changed(pool, config, "startup", &changed_by) {
+        Ok(changes) if changes.is_empty() => debug!("Config unchanged since the last run"),
+        Ok(changes) => {
+            let options: Vec<&str> = changes.keys().map(String::as_str).collect();
+            info!(
+                event = "config_changed",
+                source = "startup",
+                %changed_by,
+                options = %options.join(","),
+                "Config changed since the last run"
+            );
+        }
+        Err(e) => warn!(error = %e, "Could not record the config in the audit trail"),
+    }
+}
+
+/// Reads the `--idl` files and matches each with the indexed program it describes: the one in
+/// its `address`, or the only indexed program when it has none.
+fn load_idls(paths: &[PathBuf], program_ids: &[Pubkey]) -> Result<Vec<(Pubkey, Value)>> {
+    let mut loaded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let idl = idl::read_idl(path)?;
+        let program_id = match (idl::idl_address(&idl), program_ids) {
+            (Some(address), _) if program_ids.contains(&address) => address,
+            (Some(address), _) => {
+                return Err(errors::coded(
+                    &errors::CONFIG_IDL_PROGRAM_MISMATCH,
+                    format!(
+                        "IDL {} describes {}, which isn't indexed",
+                        path.display(),
+                        address
+                    ),
+                ))
+            }
+            (None, [only]) => *only,
+            (None, _) => {
+                return Err(errors::coded(
+                    &errors::CONFIG_IDL_PROGRAM_MISMATCH,
+                    format!(
+                        "IDL {} has no address and several programs are indexed; add its `address`",
+                        path.display()
+                    ),
+                ))
+            }
+        };
+        loaded.push((program_id, idl));
+    }
+    Ok(loaded)
+}
+
+/// Builds a decoder from each IDL, for the accounts without a table of their own. Warns when an
+/// IDL gives a built-in account type another discriminator: those accounts won't be indexed.
+fn idl_decoders(idls: &[(Pubkey, Value)]) -> Result<Vec<(Pubkey, IdlDecoder)>> {
+    let mut decoders = Vec::with_capacity(idls.len());
+    for (program_id, idl) in idls {
+        let decoder = IdlDecoder::new(idl)
+            .with_context(|| format!("The IDL of {} can't be used for decoding", program_id))?;
+        for (account_type, discriminator) in decoder.mismatched_types() {
+            warn!(
+                %program_id,
+                account_type = account_type.as_str(),
+                idl_discriminator = ?discriminator,
+                built_in = ?account_type.discriminator(),
+                "The IDL's discriminator differs from the built-in one; these accounts won't be indexed"
+            );
+        }
+        info!(%program_id, accounts = decoder.len(), "Decoding accounts with the IDL");
+        decoders.push((*program_id, decoder));
+    }
+    Ok(decoders)
+}
+
+/// Stores each IDL as a new version when it differs from the program's latest one, and reports
+/// the accounts that changed. Best effort, like the config audit.
+fn record_idls(pool: &PgPool, idls: &[(Pubkey, Value)]) {
+    for (program_id, idl) in idls {
+        match idl::record_idl(pool, program_id, idl) {
+            Ok(IdlLoad::Unchanged(version)) => {
+                debug!(%program_id, version = version.id, "IDL unchanged since the last run")
+            }
+            Ok(IdlLoad::Stored {
+                version,
+                previous,
+                accounts,
+            }) => {
+                info!(%program_id, version = version.id, "Stored new IDL version");
+                if previous.is_some() && !accounts.is_empty() {
+                    warn!(
+                        event = "idl_accounts_changed",
+                        %program_id,
+                        version = version.id,
+                        added = %accounts.added.join(","),
+                        removed = %accounts.removed.join(","),
+                        changed = %accounts.changed.join(","),
+                        "IDL accounts changed since the previous version"
+                    );
+                }
+            }
+            Err(e) => warn!(%program_id, error = %e, "Could not store the IDL"),
+        }
+    }
 }
 
-/// Handles a single account update message received from the Solana websocket subscription.
-/// This function:
-/// 1. Decodes the raw account data.
-/// 2. Checks the account's type via its 8-byte Anchor discriminator.
-/// 3. If it's a `Poll`, it parses the full state and inserts or updates it in the SQL database.
-/// 4. Runs the Diesel DB operation in a blocking thread to avoid stalling the async stream.
+/// Sets up the global `tracing` subscriber.
 ///
-/// # Arguments
-/// - `response`: A Solana `RpcResponse` containing the updated account state.
-/// - `db_pool`: A reference to the Diesel PostgreSQL connection pool.
-fn handle_response(response: Response<RpcKeyedAccount>, db_pool: &PgPool) {
-    // Extract the inner Solana account info
-    let account = response.value.account;
-    // Decode the account data (Base64 → raw Vec<u8>)
-    let data = account.data.decode();
-
-    // Only proceed if the decoding worked and we got enough bytes to inspect
-    if let Some(acc_data) = data {
-        if acc_data.len() >= 8 {
-            // Determine the type of Solana account using the first 8 bytes (Anchor discriminator)
-            match match_voting_account_type(&acc_data[..8]) {
-                VotingAccountType::Poll => {
-                    // If it's a Poll account, try to deserialize the Poll struct
-                    if let Some(poll) = decode_poll(&acc_data) {
-                        // Build a `NewPoll` struct that matches your SQL schema
-                        // This maps the on-chain Poll to a format Diesel understands
-                        let new_poll = NewPoll {
-                            poll_id: poll.poll_id as i64, // Diesel uses i64 instead of u64
-                            poll_owner: poll.poll_owner.to_bytes().to_vec(),
-                            poll_name: poll.poll_name.clone(),
-                            poll_description: poll.poll_description.clone(),
-                            poll_start: poll.poll_start as i64,
-                            poll_end: poll.poll_end as i64,
-                            candidate_amount: poll.candidate_amount as i64,
-                            candidate_winner: poll.candidate_winner.to_bytes().to_vec(),
-                        };
-
-                        // Clone the r2d2 pool — this is cheap and encouraged.
-                        // The pool itself is internally wrapped in an Arc, so clones are safe.
-                        let db_pool_clone = db_pool.clone();
-
-                        // Offload DB write to a blocking thread
-                        // Diesel is synchronous and would block the async runtime if run here directly.
-                        // `spawn_blocking` tells Tokio: "Run this on a dedicated thread."
-                        tokio::task::spawn_blocking(move || {
-                            if let Err(e) = upsert_poll(&db_pool_clone, &new_poll) {
-                                eprintln!("DB insert failed: {:?}", e);
-                            }
-                        });
-
-                        // These logs are printed regardless of DB success (which is decoupled).
-                        println!("New Poll account updated:");
-                        println!("ID: {}", poll.poll_id);
-                        println!("Owner: {}", poll.poll_owner);
-                        println!("Name: {}", poll.poll_name);
-                        println!("Description: {}", poll.poll_description);
-                        println!("Start: {}", poll.poll_start);
-                        println!("End: {}", poll.poll_end);
-                        println!("Candidates: {}", poll.candidate_amount);
-                        println!("Winner: {}", poll.candidate_winner);
-                    } else {
-                        println!("Could not decode as Poll");
-                    }
+/// The level is controlled with `RUST_LOG` (e.g. `RUST_LOG=debug` or
+/// `RUST_LOG=voting_dapp_listener=debug`), defaulting to `info`.
+fn init_tracing(json: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+/// Read-only mode is for auditors pointing the binary at a production replica: the only way to
+/// run the listener is without a database writer at all.
+fn check_read_only(read_only: bool, sink: SinkKind) -> Result<()> {
+    if read_only && sink != SinkKind::Stdout {
+        return Err(errors::coded(
+            &errors::CONFIG_READ_ONLY_WRITER,
+            "--read-only refuses to start the database writer; use --sink stdout",
+        ));
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Step 0: Load `.env` before parsing, so env-backed flags (PROGRAM_IDS, SOLANA_WS_URL, ...)
+    // can be picked up from it. Invalid values are reported by clap here, before any connection is made.
+    // Then the config file fills in what neither the flags, the environment nor `.env` set.
+    dotenvy::dotenv().ok();
+    let config_file = config_file::resolve_path(std::env::args_os().skip(1));
+    let from_file = match &config_file {
+        Some(path) => config_file::apply(path, &Args::command())?,
+        None => Vec::new(),
+    };
+    let args = Args::parse();
+    init_tracing(args.log_json);
+    if let Some(path) = &config_file {
+        info!(path = %path.display(), set = %from_file.join(","), "Loaded config file");
+    }
+
+    check_read_only(args.read_only, args.sink)?;
+    if args.read_only {
+        info!("Running in read-only mode: no database writes will be made");
+    }
+    if args.enable_profiling {
+        if !cfg!(feature = "profiling") {
+            return Err(errors::coded(
+                &errors::CONFIG_PROFILING_UNAVAILABLE,
+                "--enable-profiling needs a build with `--features profiling`",
+            ));
+        }
+        if args.http_port.is_none() {
+            return Err(errors::coded(
+                &errors::CONFIG_PROFILING_UNAVAILABLE,
+                "--enable-profiling serves profiles on the HTTP API; set --http-port",
+            ));
+        }
+        warn!("CPU profiling is enabled on the HTTP API (/debug/pprof/profile)");
+    }
+    let commitment = CommitmentConfig {
+        commitment: args.commitment,
+    };
+
+    // Step 1: Define the Program IDs you want to listen to.
+    // These are the public keys of the on-chain Solana programs you're interested in (e.g. a voting dApp).
+    // Only accounts owned by these programs will trigger updates via `program_subscribe`.
+    let program_ids = args.program_ids()?;
+    let idls = load_idls(&args.idl, &program_ids)?;
+    let idl_decoders = idl_decoders(&idls)?;
+
+    // Prometheus metrics, updated by the listener, the decoder and the writer (`GET /metrics`).
+    let metrics = Arc::new(Metrics::new()?);
+
+    // Step 2: Pick the sink.
+    // The pool is only opened when we actually write to Postgres.
+    // Before writing anything, make sure the schema matches what this binary expects.
+    // Migrations are only applied with `--run-migrations`: the DB may be migrated by diesel or
+    // by a DBA.
+    // All poll writes go through a single batching writer task instead of one blocking task per update.
+    // The writer task is kept here (not handed to the listener) so shutdown can bound its drain.
+    // Storage quotas only guard what we write ourselves.
+    let quota_config = args.quota_config();
+    let quotas = (args.sink == SinkKind::Postgres && !quota_config.is_empty())
+        .then(|| Arc::new(StorageQuotas::new(quota_config, metrics.clone())));
+    let (sink, db_pool, writer_task, writer_stats): (Arc<dyn PollSink>, _, _, _) = match args.sink {
+        SinkKind::Postgres => {
+            let db_pool = establish_pool_with(args.read_only)?;
+            if args.run_migrations {
+                let applied = migrations::run_pending(&db_pool)?;
+                if !applied.is_empty() {
+                    info!(migrations = ?applied, "Applied pending migrations");
                 }
-                // These are stubs for now — you can later implement decoding + DB storage here too
-                VotingAccountType::Candidate => {
-                    println!("Candidate account update");
+            }
+            migrations::check_schema(&db_pool)?;
+            attribute_legacy_polls(&db_pool, &program_ids)?;
+            audit_config(&db_pool, &args.effective_config(&program_ids));
+            record_idls(&db_pool, &idls);
+            // Rows older than the checksum column (or just claimed) get theirs now.
+            let backfilled = backfill_checksums(&db_pool)?;
+            if backfilled > 0 {
+                info!(backfilled, "Computed checksums of previously indexed polls");
+            }
+            let results = rebuild_poll_results(&db_pool)?;
+            info!(results, "Rebuilt poll results");
+            let config = WriterConfig {
+                batch_size: args.batch_size,
+                flush_interval: Duration::from_millis(args.batch_interval_ms),
+                lifecycle_skew_secs: i64::from(args.lifecycle_skew_secs),
+                min_concurrency: args.writer_min_concurrency,
+                max_concurrency: args.writer_max_concurrency,
+                max_pool_share: args.writer_pool_share,
+                quarantine_after: args.quarantine_after,
+                retry_jitter_seed: None,
+                clock: Clock::system(),
+            };
+            let (writer, task) =
+                writer::spawn_poll_writer(db_pool.clone(), config, metrics.clone());
+            let stats = writer.stats();
+            let mut sink = PostgresSink::new(db_pool.clone(), writer).with_metrics(metrics.clone());
+            if let Some(quotas) = &quotas {
+                sink = sink.with_quotas(quotas.clone());
+            }
+            (Arc::new(sink), Some(db_pool), Some(task), Some(stats))
+        }
+        SinkKind::Stdout => (Arc::new(StdoutSink), None, None, None),
+    };
+    // With the HTTP API on, what the sink accepts is also pushed to `/live` websockets.
+    let (sink, live_updates): (Arc<dyn PollSink>, _) = match args.http_port {
+        Some(_) => {
+            let (sink, updates) = LiveSink::new(sink);
+            (Arc::new(sink), Some(updates))
+        }
+        None => (sink, None),
+    };
+
+    let health = Arc::new(ListenerHealth::default());
+
+    // Every byte received from the RPC provider is counted per endpoint, since that's what we're billed on.
+    // The HTTP client counts its own responses (see `CountingSender`), so every feature using it is included.
+    let meter = Arc::new(BandwidthMeter::default());
+    let rpc_client = Arc::new(bandwidth::counting_rpc_client(
+        args.rpc_url(),
+        commitment,
+        meter.clone(),
+    ));
+
+    // Optional read-only HTTP API over the indexed data. It shares the writer's pool; with the
+    // stdout sink there is none, so a read-only pool is opened just for the API.
+    let (api_shutdown, api_shutdown_rx) = tokio::sync::watch::channel(false);
+    let (api_task, api_pool) = match args.http_port {
+        Some(port) => {
+            let pool = match &db_pool {
+                Some(pool) => pool.clone(),
+                None => establish_pool_with(true)?,
+            };
+            let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+                .await
+                .with_context(|| format!("Failed to bind the HTTP API on port {}", port))?;
+            info!(port, "HTTP API listening");
+            let state = ApiState {
+                pool: pool.clone(),
+                health: health.clone(),
+                metrics: metrics.clone(),
+                read_only: args.read_only,
+                verify_checksums: args.verify_checksums_on_read,
+                show_annotations: !args.hide_annotations,
+                completeness_weights: args.completeness_weights,
+                completeness_changes: Arc::new(ScoreChanges::new(
+                    args.completeness_event_threshold,
+                )),
+                feed_max_entries: args.feed_max_entries.max(1),
+                coalescer: (!args.no_read_coalescing).then(Arc::default),
+                storage_quotas: quotas.clone(),
+                live: live_updates,
+                #[cfg(feature = "profiling")]
+                profiler: args.enable_profiling.then(Arc::default),
+            };
+            let mut shutdown_rx = api_shutdown_rx;
+            let task = tokio::spawn(async move {
+                let shutdown = async move {
+                    let _ = shutdown_rx.changed().await;
+                };
+                if let Err(e) = api::serve(listener, state, shutdown).await {
+                    error!(error = ?e, "HTTP API stopped");
                 }
-                VotingAccountType::Vote => {
-                    println!("Voter account update");
+            });
+            (Some(task), Some(pool))
+        }
+        None => (None, None),
+    };
+
+    // Every long-running subsystem registers itself (with what it depends on) so shutdown can
+    // stop them in reverse dependency order: nothing loses its database before it's done with it.
+    let mut components = ComponentRegistry::new();
+    if db_pool.is_some() || api_pool.is_some() {
+        // The pool has nothing to stop itself, it's dropped with the last user.
+        components.register("database", &[], COMPONENT_TIMEOUT, || async { Ok(()) });
+    }
+    if let Some(task) = api_task {
+        components.register(
+            "http-api",
+            &["database"],
+            COMPONENT_TIMEOUT,
+            move || async move {
+                // Stop accepting HTTP requests; in-flight ones are allowed to finish.
+                let _ = api_shutdown.send(true);
+                task.await?;
+                Ok(())
+            },
+        );
+    }
+    if let Some(task) = writer_task {
+        let timeout = Duration::from_secs(args.shutdown_timeout_secs);
+        components.register("writer", &["database"], timeout, move || async move {
+            // The listener drops the sink (and with it the channel's only sender) when it stops,
+            // so the writer flushes everything still queued and exits on its own.
+            task.await?;
+            Ok(())
+        });
+    }
+
+    // Periodic background work goes through one scheduler, which staggers the jobs and
+    // limits how many of each kind run at once so they don't starve the writer together.
+    let mut scheduler = Scheduler::new(db_pool.clone());
+    scheduler
+        .limit(JobClass::DbHeavy, args.db_job_concurrency)
+        .limit(JobClass::RpcHeavy, args.rpc_job_concurrency);
+
+    // The shared slot clock. Any slot ↔ wallclock conversion goes through it
+    // instead of calling `get_block_time` on demand. Its drift and sample age are reported
+    // after every run, failed ones included, so a sampler that keeps failing shows.
+    let slot_clock = Arc::new(SlotClock::default());
+    let (sampler_clock, sampler_rpc, sampler_metrics) =
+        (slot_clock.clone(), rpc_client.clone(), metrics.clone());
+    scheduler.register(
+        "slot-clock",
+        Schedule::Every(SLOT_CLOCK_INTERVAL),
+        JobClass::RpcHeavy,
+        move || {
+            let (clock, rpc_client, metrics) = (
+                sampler_clock.clone(),
+                sampler_rpc.clone(),
+                sampler_metrics.clone(),
+            );
+            async move {
+                let sampled = slot_clock::sample_into(&clock, &rpc_client).await;
+                clock.report(&metrics);
+                sampled
+            }
+        },
+    );
+
+    if let Some(pool) = &db_pool {
+        // Polls change state as time passes even without on-chain updates, so lifecycles are
+        // re-evaluated periodically (only when we own a database writer).
+        let skew = i64::from(args.lifecycle_skew_secs);
+        let (lifecycle_pool, lifecycle_metrics) = (pool.clone(), metrics.clone());
+        scheduler.register(
+            "lifecycle",
+            Schedule::Every(LIFECYCLE_INTERVAL),
+            JobClass::DbHeavy,
+            move || {
+                let (pool, metrics) = (lifecycle_pool.clone(), lifecycle_metrics.clone());
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        advance_lifecycles_job(&pool, skew, &metrics)
+                    })
+                    .await?
                 }
-                VotingAccountType::Unknown => {
-                    println!("Unknown account type.");
+            },
+        );
+
+        // Periodically persist the bandwidth counters and check the monthly budget.
+        let flusher = Arc::new(BandwidthFlusher {
+            pool: pool.clone(),
+            meter: meter.clone(),
+            budget: args.bandwidth_budget,
+            alerted: Mutex::new(None),
+        });
+        scheduler.register(
+            "bandwidth-flush",
+            Schedule::Every(BANDWIDTH_FLUSH_INTERVAL),
+            JobClass::DbHeavy,
+            move || {
+                let flusher = flusher.clone();
+                async move { tokio::task::spawn_blocking(move || flusher.run()).await? }
+            },
+        );
+    }
+
+    if let (Some(pool), Some(quotas)) = (&db_pool, &quotas) {
+        // Recount what each program stores and act on the quotas it's over.
+        let (quota_pool, quotas) = (pool.clone(), quotas.clone());
+        scheduler.register(
+            "storage-quotas",
+            Schedule::Every(Duration::from_secs(args.quota_check_secs)),
+            JobClass::DbHeavy,
+            move || {
+                let (pool, quotas) = (quota_pool.clone(), quotas.clone());
+                async move { tokio::task::spawn_blocking(move || quotas.check(&pool)).await? }
+            },
+        );
+    }
+
+    let scheduler_task = scheduler.spawn();
+    let (final_flush, depends_on): (_, &[&str]) = match &db_pool {
+        Some(pool) => (Some((pool.clone(), meter.clone())), &["database"]),
+        None => (None, &[]),
+    };
+    components.register(
+        "scheduler",
+        depends_on,
+        COMPONENT_TIMEOUT,
+        move || async move {
+            scheduler_task.abort();
+            // One last flush so the bytes counted since the previous run aren't lost.
+            match final_flush {
+                Some((pool, meter)) => {
+                    tokio::task::spawn_blocking(move || flush_bandwidth(&pool, &meter)).await?
                 }
+                None => Ok(()),
             }
+        },
+    );
+    info!(components = ?components.startup_order()?, "Components started");
+
+    // Step 3: Run the listener (subscriptions, backfill, warm-up check and the update loop)
+    // until Ctrl+C, or SIGTERM from an orchestrator, asks for a graceful shutdown.
+    let listener = Listener::builder()
+        .program_ids(program_ids)
+        .ws_url(args.ws_url.clone())
+        .fallback_ws_urls(args.fallback_ws_urls.clone())
+        .failover_cooldown(Duration::from_secs(args.failover_cooldown_secs))
+        .rpc_client(rpc_client)
+        .commitment(commitment)
+        .account_encoding(args.account_encoding)
+        .only(args.only.clone())
+        .with_logs(args.with_logs)
+        .dedup_max_entries(args.dedup_max_entries)
+        .filter_sample_rate(args.filter_sample_rate)
+        .warmup(WarmupConfig {
+            messages: args.warmup_messages,
+            window: Duration::from_secs(args.warmup_secs),
+            min_ratio: args.warmup_min_ratio,
+        })
+        .strict_warmup(args.strict_warmup)
+        .idl_decoders(idl_decoders)
+        .subscription_refresh(
+            args.ws_refresh_mins
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+        )
+        .stall_timeout(args.ws_stall_timeout_secs.map(Duration::from_secs))
+        .catch_up_max_transactions(args.catch_up_max_transactions)
+        .archive_raw_accounts(args.archive_raw_accounts)
+        .sink(sink)
+        .metrics(metrics)
+        .slot_clock(slot_clock)
+        .health(health)
+        .bandwidth_meter(meter)
+        .build()?;
+    let exit = listener
+        .run(async {
+            let signal_name = shutdown_signal().await;
+            info!(
+                signal = signal_name,
+                "Shutdown signal received, shutting down..."
+            );
+        })
+        .await?;
+    let warmup_failure = match exit {
+        ListenerExit::WarmupFailed(diagnostic) => Some(diagnostic),
+        ListenerExit::Shutdown | ListenerExit::StreamClosed => None,
+    };
+
+    // The listener has dropped the sink, which closed the writer channel.
+    let done_before = writer_stats.as_ref().map_or(0, |stats| stats.settled());
+
+    // Step 4: Stop every component in reverse dependency order. A second signal skips the wait.
+    let report = tokio::select! {
+        report = components.shutdown() => report?,
+        signal_name = shutdown_signal() => {
+            warn!(signal = signal_name, "Second shutdown signal, exiting immediately");
+            std::process::exit(130);
+        }
+    };
+    if let Some(stats) = writer_stats {
+        let completed = stats.settled() - done_before;
+        let abandoned = stats.pending();
+        if abandoned == 0 {
+            info!(completed, "All queued DB writes flushed");
+        } else {
+            warn!(completed, abandoned, "Abandoned queued DB writes");
+        }
+    }
+    let aborted = report.aborted();
+    if !aborted.is_empty() {
+        warn!(components = ?aborted, "Some components had to be abandoned during shutdown");
+    }
+
+    info!("Good Bye");
+    if !aborted.is_empty() {
+        // Returning would drop the runtime, which waits for stuck blocking tasks (e.g. a DB write)
+        // and defeats the shutdown timeouts. Exit right away instead.
+        std::process::exit(1);
+    }
+    if let Some(diagnostic) = warmup_failure {
+        return Err(errors::coded(&errors::CONFIG_WARMUP_FAILED, diagnostic));
+    }
+    Ok(())
+}
+
+/// Attributes polls indexed before program IDs were tracked.
+///
+/// With a single program there is only one possible owner, so those rows are claimed for it.
+/// With several programs they can't be attributed automatically; they are left alone (and not
+/// matched by new updates, which carry a program ID) and a warning explains how to fix them.
+fn attribute_legacy_polls(db_pool: &PgPool, program_ids: &[Pubkey]) -> Result<()> {
+    if let [program_id] = program_ids {
+        let claimed = claim_unattributed_polls(db_pool, &program_id.to_bytes())?;
+        if claimed > 0 {
+            info!(%program_id, claimed, "Attributed previously indexed polls to the program");
         }
+        return Ok(());
+    }
+    let unattributed = count_unattributed_polls(db_pool)?;
+    if unattributed > 0 {
+        warn!(
+            unattributed,
+            "Polls indexed before program IDs were tracked can't be attributed with several \
+             programs; start the listener once with only their program ID to claim them"
+        );
     }
+    Ok(())
 }
 
-pub enum VotingAccountType {
-    Poll,
-    Candidate,
-    Vote,
-    Unknown,
+/// Resolves on the first Ctrl+C (SIGINT) or, on unix, SIGTERM, which is what Kubernetes
+/// sends on a rolling restart. Returns the name of the signal.
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                warn!(error = ?e, "Could not install SIGTERM handler, only Ctrl+C will shut down");
+                let _ = signal::ctrl_c().await;
+                return "SIGINT";
+            }
+        };
+        tokio::select! {
+            _ = signal::ctrl_c() => "SIGINT",
+            _ = sigterm.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+        "SIGINT"
+    }
 }
-pub fn match_voting_account_type(data: &[u8]) -> VotingAccountType {
-    if data.len() < 8 {
-        return VotingAccountType::Unknown;
+
+/// Advances time-driven lifecycle transitions for all non-terminal polls.
+fn advance_lifecycles_job(db_pool: &PgPool, skew: i64, metrics: &Metrics) -> Result<()> {
+    for (key, transition) in advance_lifecycles(db_pool, skew, Clock::system().unix_now())? {
+        log_lifecycle_transition(&key, &transition, Some(metrics));
     }
+    Ok(())
+}
+
+/// The `bandwidth-flush` job: flushes the bandwidth counters and raises budget alerts.
+///
+/// Each alert level (see [`bandwidth::BUDGET_ALERT_LEVELS`]) fires once per calendar month (UTC).
+struct BandwidthFlusher {
+    pool: PgPool,
+    meter: Arc<BandwidthMeter>,
+    budget: Option<u64>,
+    /// (month, highest level already alerted for that month)
+    alerted: Mutex<Option<(NaiveDate, u8)>>,
+}
+
+impl BandwidthFlusher {
+    fn run(&self) -> Result<()> {
+        flush_bandwidth(&self.pool, &self.meter)?;
+        let month = month_start(Utc::now().date_naive());
+        let used = bandwidth_total_since(&self.pool, month)?;
+        debug!(month_bytes = used, "Bandwidth counters flushed");
 
-    let mut descriminator = [0u8; 8];
-    descriminator.copy_from_slice(data);
+        let Some(budget) = self.budget else {
+            return Ok(());
+        };
+        let Some(level) = bandwidth::budget_level(used, budget) else {
+            return Ok(());
+        };
+        let mut alerted = self.alerted.lock().unwrap();
+        let already = matches!(*alerted, Some((m, l)) if m == month && l >= level);
+        if already {
+            return Ok(());
+        }
+        *alerted = Some((month, level));
+        if level >= 100 {
+            error!(used, budget, "Monthly RPC bandwidth budget exceeded");
+        } else {
+            warn!(
+                used,
+                budget,
+                percent = level,
+                "Monthly RPC bandwidth budget almost used"
+            );
+        }
+        Ok(())
+    }
+}
 
-    match descriminator {
-        POLL_DISCRIMINATOR => VotingAccountType::Poll,
-        POOL_CANDIDATE_DISCRIMINATOR => VotingAccountType::Candidate,
-        VOTE_DISCRIMINATOR => VotingAccountType::Vote,
-        _ => VotingAccountType::Unknown,
+/// Writes the counters accumulated since the last flush to today's `bandwidth_usage` rows.
+/// On failure the counters are put back into the meter so they're retried.
+fn flush_bandwidth(db_pool: &PgPool, meter: &BandwidthMeter) -> Result<()> {
+    let deltas = meter.take();
+    if deltas.is_empty() {
+        return Ok(());
+    }
+    if let Err(e) = record_bandwidth(db_pool, Utc::now().date_naive(), &deltas) {
+        meter.restore(deltas);
+        return Err(e);
     }
+    Ok(())
 }
 
-fn decode_poll(data: &[u8]) -> Option<Poll> {
-    println!("started decoding");
-    if data.len() < 8 {
-        return None;
+/// First day of the month `day` falls in.
+fn month_start(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap_or(day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_refuses_the_database_writer() {
+        let err = check_read_only(true, SinkKind::Postgres).unwrap_err();
+        assert_eq!(
+            errors::classify(&err),
+            Some(&errors::CONFIG_READ_ONLY_WRITER)
+        );
+        assert!(check_read_only(true, SinkKind::Stdout).is_ok());
+        assert!(check_read_only(false, SinkKind::Postgres).is_ok());
     }
 
-    let (_discriminator, body) = data.split_at(8);
-    Poll::try_from_anchor_bytes(body)
+    #[test]
+    fn ratios_are_checked_when_parsing() {
+        let parse = |flag: &str, value: &str| {
+            Args::try_parse_from([
+                "voting-dapp-listener".to_string(),
+                format!("{flag}={value}"),
+            ])
+            .map_err(|e| e.kind())
+        };
+        for flag in [
+            "--warmup-min-ratio",
+            "--filter-sample-rate",
+            "--writer-pool-share",
+        ] {
+            for value in ["0", "0.25", "1"] {
+                assert!(parse(flag, value).is_ok(), "{flag} {value}");
+            }
+            for value in ["-0.1", "1.5", "NaN", "half"] {
+                assert_eq!(
+                    parse(flag, value).err(),
+                    Some(clap::error::ErrorKind::ValueValidation),
+                    "{flag} {value}"
+                );
+            }
+        }
+        let args = parse("--warmup-min-ratio", "0.25").unwrap();
+        assert_eq!(args.warmup_min_ratio, 0.25);
+        let args = parse("--filter-sample-rate", "0.25").unwrap();
+        assert_eq!(args.filter_sample_rate, 0.25);
+        let args = parse("--writer-pool-share", "0.25").unwrap();
+        assert_eq!(args.writer_pool_share, 0.25);
+    }
+
+    #[test]
+    fn config_file_keys_name_the_listener_flags() {
+        let vars = config_file::parse(
+            r#"
+            ws_url = "ws://localhost:8900"
+            program_ids = ["Vote111111111111111111111111111111111111111"]
+            filter-sample-rate = 0.5
+            "#,
+            &Args::command(),
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("FILTER_SAMPLE_RATE".to_string(), "0.5".to_string()),
+                (
+                    "PROGRAM_IDS".to_string(),
+                    "Vote111111111111111111111111111111111111111".to_string()
+                ),
+                (
+                    "SOLANA_WS_URL".to_string(),
+                    "ws://localhost:8900".to_string()
+                ),
+            ]
+        );
+    }
 }
@@ -0,0 +1,407 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signature, Signer};
+
+/// Format version of the manifest; bumped when its layout changes.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Everything a manifest vouches for. The signature covers this part as serialized JSON.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestBody {
+    pub version: u32,
+    /// RFC3339, when the export finished.
+    pub created_at: String,
+    pub build: BuildInfo,
+    pub export: ExportParams,
+    pub coverage: Coverage,
+    pub files: Vec<ManifestFile>,
+}
+
+/// The binary that wrote the export.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BuildInfo {
+    pub package: String,
+    pub version: String,
+    pub target: String,
+    pub profile: String,
+}
+
+impl BuildInfo {
+    /// This binary's build info.
+    pub fn current() -> Self {
+        Self {
+            package: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            target: env!("BUILD_TARGET").to_string(),
+            profile: env!("BUILD_PROFILE").to_string(),
+        }
+    }
+}
+
+/// The parameters the export was run with.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ExportParams {
+    pub table: String,
+    pub format: String,
+}
+
+/// What part of the chain the exported rows cover.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Coverage {
+    /// Programs with at least one exported row (base58).
+    pub programs: BTreeSet<String>,
+    /// Exported rows predating program tracking, not attributed to any program.
+    pub unattributed_rows: u64,
+    /// Lowest and highest slot the exported rows were observed at; `None` for an empty export.
+    pub min_slot: Option<i64>,
+    pub max_slot: Option<i64>,
+}
+
+impl Coverage {
+    /// Accounts for one exported row.
+    pub fn record(&mut self, program: Option<String>, slot: i64) {
+        match program {
+            Some(program) => {
+                self.programs.insert(program);
+            }
+            None => self.unattributed_rows += 1,
+        }
+        self.min_slot = Some(self.min_slot.map_or(slot, |min| min.min(slot)));
+        self.max_slot = Some(self.max_slot.map_or(slot, |max| max.max(slot)));
+    }
+}
+
+/// One exported file, by name relative to the export directory.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFile {
+    pub path: String,
+    /// Hex sha256 of the file's content.
+    pub sha256: String,
+    pub bytes: u64,
+    /// Data rows in the file (CSV header excluded).
+    pub rows: u64,
+}
+
+/// A manifest as written next to an export: the body, who signed it and the Ed25519 signature.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ExportManifest {
+    pub manifest: ManifestBody,
+    /// Public key of the signing keypair (base58).
+    pub signer: String,
+    /// Signature of the body (base58).
+    pub signature: String,
+}
+
+impl ExportManifest {
+    /// Signs `body` with `keypair`.
+    pub fn sign(body: ManifestBody, keypair: &Keypair) -> Result<Self> {
+        let signature = keypair.sign_message(&signed_bytes(&body)?);
+        Ok(Self {
+            manifest: body,
+            signer: keypair.pubkey().to_string(),
+            signature: signature.to_string(),
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("{} is not an export manifest", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut text = serde_json::to_string_pretty(self)?;
+        text.push('\n');
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether the signature is valid for the body and the `signer` it names.
+    pub fn signature_valid(&self) -> Result<bool> {
+        let (Ok(signer), Ok(signature)) = (
+            self.signer.parse::<Pubkey>(),
+            self.signature.parse::<Signature>(),
+        ) else {
+            return Ok(false);
+        };
+        Ok(signature.verify(signer.as_ref(), &signed_bytes(&self.manifest)?))
+    }
+}
+
+/// The bytes a signature covers: the body as compact JSON, fields in declaration order.
+fn signed_bytes(body: &ManifestBody) -> Result<Vec<u8>> {
+    serde_json::to_vec(body).context("Failed to serialize the manifest")
+}
+
+/// Loads an Ed25519 signing key from a Solana keypair file (JSON array of 64 bytes, as written
+/// by `solana-keygen`).
+pub fn load_signing_key(path: &Path) -> Result<Keypair> {
+    read_keypair_file(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read signing key {}: {}", path.display(), e))
+}
+
+/// Hex sha256 and size of a file, read in chunks.
+pub fn hash_file(path: &Path) -> io::Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut bytes = 0u64;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        bytes += read as u64;
+    }
+    let hex = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((hex, bytes))
+}
+
+/// The state of one listed file compared with the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileCheck {
+    Matches,
+    Missing,
+    /// The content changed since the export.
+    Differs {
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Result of [`verify_export`].
+#[derive(Debug)]
+pub struct Verification {
+    pub signature_valid: bool,
+    /// Whether the manifest was signed by the expected key; `None` when none was given.
+    pub signer_matches: Option<bool>,
+    /// Every file the manifest lists, in manifest order.
+    pub files: Vec<(String, FileCheck)>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.signature_valid
+            && self.signer_matches != Some(false)
+            && self
+                .files
+                .iter()
+                .all(|(_, check)| *check == FileCheck::Matches)
+    }
+}
+
+/// Checks an export against its manifest: the signature (and the signer, when `expected_signer`
+/// is given), then the hash of every listed file found in `dir`.
+pub fn verify_export(
+    manifest: &ExportManifest,
+    dir: &Path,
+    expected_signer: Option<&Pubkey>,
+) -> Result<Verification> {
+    let mut files = Vec::with_capacity(manifest.manifest.files.len());
+    for file in &manifest.manifest.files {
+        // Only plain names: a manifest must not make us read files outside the export.
+        let relative = Path::new(&file.path);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "Manifest lists '{}', which is outside the export",
+                file.path
+            );
+        }
+        let check = match hash_file(&dir.join(relative)) {
+            Ok((actual, _)) if actual == file.sha256 => FileCheck::Matches,
+            Ok((actual, _)) => FileCheck::Differs {
+                expected: file.sha256.clone(),
+                actual,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => FileCheck::Missing,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", file.path));
+            }
+        };
+        files.push((file.path.clone(), check));
+    }
+    Ok(Verification {
+        signature_valid: manifest.signature_valid()?,
+        signer_matches: expected_signer.map(|expected| manifest.signer == expected.to_string()),
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use solana_sdk::signature::write_keypair_file;
+
+    use super::*;
+
+    /// An empty scratch directory, unique to the test.
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("manifest-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn body(files: Vec<ManifestFile>) -> ManifestBody {
+        let mut coverage = Coverage::default();
+        coverage.record(Some("program".to_string()), 12);
+        coverage.record(None, 10);
+        ManifestBody {
+            version: MANIFEST_VERSION,
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            build: BuildInfo::current(),
+            export: ExportParams {
+                table: "polls".to_string(),
+                format: "csv".to_string(),
+            },
+            coverage,
+            files,
+        }
+    }
+
+    /// Writes `content` to `dir/name` and lists it the way the export does.
+    fn exported(dir: &Path, name: &str, content: &str) -> ManifestFile {
+        fs::write(dir.join(name), content).unwrap();
+        let (sha256, bytes) = hash_file(&dir.join(name)).unwrap();
+        ManifestFile {
+            path: name.to_string(),
+            sha256,
+            bytes,
+            rows: content.lines().count().saturating_sub(1) as u64,
+        }
+    }
+
+    #[test]
+    fn coverage_spans_every_recorded_row() {
+        let coverage = body(Vec::new()).coverage;
+
+        assert_eq!(coverage.programs.len(), 1);
+        assert_eq!(coverage.unattributed_rows, 1);
+        assert_eq!((coverage.min_slot, coverage.max_slot), (Some(10), Some(12)));
+    }
+
+    #[test]
+    fn signing_keys_load_from_keypair_files() {
+        let dir = scratch_dir("key");
+        let keypair = Keypair::new();
+        write_keypair_file(&keypair, dir.join("key.json")).unwrap();
+        fs::write(dir.join("broken.json"), "[1, 2, 3]").unwrap();
+
+        let loaded = load_signing_key(&dir.join("key.json")).unwrap();
+        let broken = load_signing_key(&dir.join("broken.json")).unwrap_err();
+        let missing = load_signing_key(&dir.join("missing.json")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.pubkey(), keypair.pubkey());
+        assert!(broken.to_string().contains("broken.json"));
+        assert!(missing.to_string().contains("missing.json"));
+    }
+
+    #[test]
+    fn signed_manifests_survive_a_round_trip() {
+        let dir = scratch_dir("round-trip");
+        let keypair = Keypair::new();
+        let manifest = ExportManifest::sign(body(Vec::new()), &keypair).unwrap();
+        manifest.write(&dir.join("manifest.json")).unwrap();
+
+        let read = ExportManifest::read(&dir.join("manifest.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.signer, keypair.pubkey().to_string());
+        assert!(read.signature_valid().unwrap());
+    }
+
+    #[test]
+    fn edited_manifests_fail_the_signature() {
+        let keypair = Keypair::new();
+        let manifest = ExportManifest::sign(body(Vec::new()), &keypair).unwrap();
+
+        let mut edited = manifest.clone();
+        edited.manifest.coverage.max_slot = Some(13);
+        let mut resigned_by = manifest.clone();
+        resigned_by.signer = Keypair::new().pubkey().to_string();
+        let mut garbled = manifest.clone();
+        garbled.signature = "not base58!".to_string();
+
+        assert!(!edited.signature_valid().unwrap());
+        assert!(!resigned_by.signature_valid().unwrap());
+        assert!(!garbled.signature_valid().unwrap());
+    }
+
+    #[test]
+    fn exports_verify_against_their_manifest() {
+        let dir = scratch_dir("verify");
+        let keypair = Keypair::new();
+        let files = vec![
+            exported(&dir, "polls.csv", "poll_id\n1\n2\n"),
+            exported(&dir, "votes.csv", "poll_id\n1\n"),
+        ];
+        assert_eq!(files[0].rows, 2);
+        let manifest = ExportManifest::sign(body(files), &keypair).unwrap();
+
+        let intact = verify_export(&manifest, &dir, Some(&keypair.pubkey())).unwrap();
+        let other_signer = verify_export(&manifest, &dir, Some(&Pubkey::new_unique())).unwrap();
+        fs::write(dir.join("polls.csv"), "poll_id\n1\n3\n").unwrap();
+        fs::remove_file(dir.join("votes.csv")).unwrap();
+        let tampered = verify_export(&manifest, &dir, None).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(intact.is_ok());
+        assert_eq!(intact.signer_matches, Some(true));
+        assert!(other_signer.signature_valid);
+        assert_eq!(other_signer.signer_matches, Some(false));
+        assert!(!other_signer.is_ok());
+        assert!(!tampered.is_ok());
+        assert!(matches!(tampered.files[0].1, FileCheck::Differs { .. }));
+        assert_eq!(tampered.files[1].1, FileCheck::Missing);
+    }
+
+    #[test]
+    fn manifests_cannot_point_outside_the_export() {
+        let dir = scratch_dir("escape");
+        let mut file = exported(&dir, "polls.csv", "poll_id\n");
+        file.path = "../polls.csv".to_string();
+        let manifest = ExportManifest::sign(body(vec![file]), &Keypair::new()).unwrap();
+
+        let result = verify_export(&manifest, &dir, None);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("outside the export"));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let dir = scratch_dir("unknown-field");
+        let manifest = ExportManifest::sign(body(Vec::new()), &Keypair::new()).unwrap();
+        let mut json = serde_json::to_value(&manifest).unwrap();
+        json["manifest"]["extra"] = serde_json::json!(true);
+        fs::write(dir.join("manifest.json"), json.to_string()).unwrap();
+
+        let result = ExportManifest::read(&dir.join("manifest.json"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+}
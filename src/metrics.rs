@@ -0,0 +1,287 @@
+use anyhow::Result;
+use prometheus::{
+    Encoder, Gauge, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics of the listener, served as text at `GET /metrics`.
+///
+/// One instance is created at startup and shared (`Arc<Metrics>`) with everything that updates
+/// it: the stream loop, the decoder and the batching writer. Every metric is prefixed with
+/// `voting_listener_`.
+pub struct Metrics {
+    registry: Registry,
+    /// Websocket updates received, labelled by `account_type` (poll, candidate, vote, unknown).
+    pub messages_received: IntCounterVec,
+    /// Account updates whose data couldn't be decoded.
+    pub decode_failures: IntCounter,
+    /// Account updates skipped because their data was identical to the last one seen.
+    pub duplicates_skipped: IntCounter,
+    /// Poll records flushed by the writer, labelled by `result` (ok, failed, quarantined,
+    /// stale).
+    pub db_upserts: IntCounterVec,
+    /// 1 while the websocket subscription is open, 0 otherwise.
+    pub websocket_connected: IntGauge,
+    /// Context slot of the latest websocket update.
+    pub last_processed_slot: IntGauge,
+    /// Seconds between the estimated time of the latest update's slot (see `SlotClock`) and
+    /// when it was processed.
+    pub slot_lag_seconds: IntGauge,
+    /// Fitted slot duration of the slot clock minus the nominal 400ms, in milliseconds.
+    pub slot_clock_drift_ms: Gauge,
+    /// Seconds since the slot clock's newest sample; grows while sampling fails.
+    pub slot_clock_sample_age_seconds: Gauge,
+    /// Scheduled subscription refreshes, labelled by `result`: `switched` (after the new
+    /// connection's first message), `timed_out` (switched without one), `closed` (the old
+    /// connection closed first) or `failed` (the old connection was kept).
+    pub subscription_refreshes: IntCounterVec,
+    /// Failovers to another websocket endpoint after the one in use disconnected, labelled by
+    /// the `endpoint` failed over to (without its query string).
+    pub websocket_failovers: IntCounterVec,
+    /// Websocket connections replaced for staying silent too long, labelled by `endpoint`.
+    pub websocket_stalls: IntCounterVec,
+    /// Records waiting in the writer channel.
+    pub writer_queue_depth: IntGauge,
+    /// Batches the writer may flush at once (see `ConcurrencyController`).
+    pub writer_concurrency: IntGauge,
+    /// Accounts whose updates the writer skips (see `Quarantine`).
+    pub quarantined_accounts: IntGauge,
+    /// Poll lifecycle transitions emitted, labelled by the state moved `to`.
+    pub lifecycle_transitions: IntCounterVec,
+    /// Lifecycle flaps suppressed by the clock skew tolerance (see `lifecycle::transition`).
+    pub lifecycle_flaps_suppressed: IntCounter,
+    /// Events decoded from transaction logs (`--with-logs`), labelled by `event_type`.
+    pub events_recorded: IntCounterVec,
+    /// Log lines of our program that matched no known event.
+    pub log_lines_unmatched: IntCounter,
+    /// Server-filtered messages whose discriminator didn't match the filter (see `FilterGuard`).
+    pub filter_mismatches: IntCounter,
+    /// Poll reads of the HTTP API, labelled by `outcome`: `executed` (ran its own queries) or
+    /// `coalesced` (shared those of an identical read in flight).
+    pub api_reads: IntCounterVec,
+    /// Open `GET /live` websocket connections.
+    pub live_connections: IntGauge,
+    /// Rows per table and program with a storage quota, labelled by `table` and `program`.
+    pub storage_quota_rows: IntGaugeVec,
+    /// Estimated on-disk bytes per table and program with a storage quota.
+    pub storage_quota_estimated_bytes: IntGaugeVec,
+    /// 1 while a table's quota is exceeded for a program, 0 otherwise.
+    pub storage_quota_exceeded: IntGaugeVec,
+    /// Updates dropped because their table's quota was exceeded (`--quota-action stop`),
+    /// labelled by `table`.
+    pub storage_quota_dropped: IntCounterVec,
+    /// Rows deleted to get back under quota (`--quota-action prune`), labelled by `table`.
+    pub storage_quota_pruned: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("voting_listener".to_string()), None)?;
+
+        let messages_received = IntCounterVec::new(
+            Opts::new("messages_received_total", "Websocket updates received"),
+            &["account_type"],
+        )?;
+        let decode_failures = IntCounter::new(
+            "decode_failures_total",
+            "Account updates that could not be decoded",
+        )?;
+        let duplicates_skipped = IntCounter::new(
+            "duplicates_skipped_total",
+            "Account updates skipped as identical to the previous one",
+        )?;
+        let db_upserts = IntCounterVec::new(
+            Opts::new("db_upserts_total", "Poll records flushed to the database"),
+            &["result"],
+        )?;
+        let websocket_connected = IntGauge::new(
+            "websocket_connected",
+            "Whether the websocket subscription is open",
+        )?;
+        let last_processed_slot = IntGauge::new(
+            "last_processed_slot",
+            "Context slot of the latest websocket update",
+        )?;
+        let slot_lag_seconds = IntGauge::new(
+            "slot_lag_seconds",
+            "Estimated age of the latest websocket update's slot when it was processed",
+        )?;
+        let slot_clock_drift_ms = Gauge::new(
+            "slot_clock_drift_ms",
+            "Fitted slot duration minus the nominal 400ms, in milliseconds",
+        )?;
+        let slot_clock_sample_age_seconds = Gauge::new(
+            "slot_clock_sample_age_seconds",
+            "Seconds since the slot clock's newest (slot, block time) sample",
+        )?;
+        let subscription_refreshes = IntCounterVec::new(
+            Opts::new(
+                "subscription_refreshes_total",
+                "Scheduled websocket subscription refreshes",
+            ),
+            &["result"],
+        )?;
+        let websocket_failovers = IntCounterVec::new(
+            Opts::new(
+                "websocket_failovers_total",
+                "Failovers to another websocket endpoint",
+            ),
+            &["endpoint"],
+        )?;
+        let websocket_stalls = IntCounterVec::new(
+            Opts::new(
+                "websocket_stalls_total",
+                "Websocket connections replaced after staying silent too long",
+            ),
+            &["endpoint"],
+        )?;
+        let writer_queue_depth = IntGauge::new(
+            "writer_queue_depth",
+            "Records waiting in the writer channel",
+        )?;
+        let writer_concurrency =
+            IntGauge::new("writer_concurrency", "Batches the writer may flush at once")?;
+        let quarantined_accounts = IntGauge::new(
+            "quarantined_accounts",
+            "Accounts quarantined after repeated write failures",
+        )?;
+
+        let lifecycle_transitions = IntCounterVec::new(
+            Opts::new(
+                "lifecycle_transitions_total",
+                "Poll lifecycle transitions emitted",
+            ),
+            &["to"],
+        )?;
+        let lifecycle_flaps_suppressed = IntCounter::new(
+            "lifecycle_flaps_suppressed_total",
+            "Poll lifecycle flaps suppressed by the clock skew tolerance",
+        )?;
+
+        let events_recorded = IntCounterVec::new(
+            Opts::new(
+                "events_recorded_total",
+                "Events decoded from transaction logs",
+            ),
+            &["event_type"],
+        )?;
+        let log_lines_unmatched = IntCounter::new(
+            "log_lines_unmatched_total",
+            "Program log lines that matched no known event",
+        )?;
+        let filter_mismatches = IntCounter::new(
+            "filter_mismatches_total",
+            "Server-filtered account updates that didn't match their filter",
+        )?;
+
+        let api_reads = IntCounterVec::new(
+            Opts::new("api_reads_total", "Poll reads served by the HTTP API"),
+            &["outcome"],
+        )?;
+        let live_connections = IntGauge::new(
+            "live_connections",
+            "Open websocket connections of the HTTP API's live updates",
+        )?;
+
+        let storage_quota_rows = IntGaugeVec::new(
+            Opts::new(
+                "storage_quota_rows",
+                "Rows per table and program with a storage quota",
+            ),
+            &["table", "program"],
+        )?;
+        let storage_quota_estimated_bytes = IntGaugeVec::new(
+            Opts::new(
+                "storage_quota_estimated_bytes",
+                "Estimated on-disk bytes per table and program with a storage quota",
+            ),
+            &["table", "program"],
+        )?;
+        let storage_quota_exceeded = IntGaugeVec::new(
+            Opts::new(
+                "storage_quota_exceeded",
+                "Whether a table's storage quota is exceeded for a program",
+            ),
+            &["table", "program"],
+        )?;
+        let storage_quota_dropped = IntCounterVec::new(
+            Opts::new(
+                "storage_quota_dropped_total",
+                "Updates dropped because their table's storage quota was exceeded",
+            ),
+            &["table"],
+        )?;
+        let storage_quota_pruned = IntCounterVec::new(
+            Opts::new(
+                "storage_quota_pruned_total",
+                "Rows deleted to get back under a storage quota",
+            ),
+            &["table"],
+        )?;
+
+        registry.register(Box::new(messages_received.clone()))?;
+        registry.register(Box::new(decode_failures.clone()))?;
+        registry.register(Box::new(duplicates_skipped.clone()))?;
+        registry.register(Box::new(db_upserts.clone()))?;
+        registry.register(Box::new(websocket_connected.clone()))?;
+        registry.register(Box::new(last_processed_slot.clone()))?;
+        registry.register(Box::new(slot_lag_seconds.clone()))?;
+        registry.register(Box::new(slot_clock_drift_ms.clone()))?;
+        registry.register(Box::new(slot_clock_sample_age_seconds.clone()))?;
+        registry.register(Box::new(subscription_refreshes.clone()))?;
+        registry.register(Box::new(websocket_failovers.clone()))?;
+        registry.register(Box::new(websocket_stalls.clone()))?;
+        registry.register(Box::new(writer_queue_depth.clone()))?;
+        registry.register(Box::new(writer_concurrency.clone()))?;
+        registry.register(Box::new(quarantined_accounts.clone()))?;
+        registry.register(Box::new(lifecycle_transitions.clone()))?;
+        registry.register(Box::new(lifecycle_flaps_suppressed.clone()))?;
+        registry.register(Box::new(events_recorded.clone()))?;
+        registry.register(Box::new(log_lines_unmatched.clone()))?;
+        registry.register(Box::new(filter_mismatches.clone()))?;
+        registry.register(Box::new(api_reads.clone()))?;
+        registry.register(Box::new(live_connections.clone()))?;
+        registry.register(Box::new(storage_quota_rows.clone()))?;
+        registry.register(Box::new(storage_quota_estimated_bytes.clone()))?;
+        registry.register(Box::new(storage_quota_exceeded.clone()))?;
+        registry.register(Box::new(storage_quota_dropped.clone()))?;
+        registry.register(Box::new(storage_quota_pruned.clone()))?;
+
+        Ok(Self {
+            registry,
+            messages_received,
+            decode_failures,
+            duplicates_skipped,
+            db_upserts,
+            websocket_connected,
+            last_processed_slot,
+            slot_lag_seconds,
+            slot_clock_drift_ms,
+            slot_clock_sample_age_seconds,
+            subscription_refreshes,
+            websocket_failovers,
+            websocket_stalls,
+            writer_queue_depth,
+            writer_concurrency,
+            quarantined_accounts,
+            lifecycle_transitions,
+            lifecycle_flaps_suppressed,
+            events_recorded,
+            log_lines_unmatched,
+            filter_mismatches,
+            api_reads,
+            live_connections,
+            storage_quota_rows,
+            storage_quota_estimated_bytes,
+            storage_quota_exceeded,
+            storage_quota_dropped,
+            storage_quota_pruned,
+        })
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
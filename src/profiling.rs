@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use pprof::protos::Message;
+use tokio::sync::Semaphore;
+
+/// Longest CPU profile that can be requested.
+pub const MAX_PROFILE_DURATION: Duration = Duration::from_secs(300);
+/// Samples per second. Slightly off 100 so sampling doesn't align with periodic work.
+const SAMPLE_FREQUENCY: i32 = 99;
+
+/// Output of [`Profiler::cpu_profile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// gzip-less pprof protobuf, for `go tool pprof` and compatible viewers.
+    Pprof,
+    /// Flamegraph as SVG.
+    Flamegraph,
+}
+
+/// On-demand CPU profiling of the running process (`profiling` feature).
+///
+/// Sampling is process-wide, so only one profile runs at a time; concurrent requests are
+/// refused rather than queued.
+pub struct Profiler {
+    running: Semaphore,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            running: Semaphore::new(1),
+        }
+    }
+}
+
+impl Profiler {
+    /// Samples the CPU for `duration` and returns the profile, or `None` when another profile
+    /// is already running.
+    pub async fn cpu_profile(
+        &self,
+        duration: Duration,
+        format: ProfileFormat,
+    ) -> Result<Option<Vec<u8>>> {
+        let Ok(_permit) = self.running.try_acquire() else {
+            return Ok(None);
+        };
+        // The sampler is driven by a signal timer, not by this task: sleeping on a blocking
+        // thread keeps the runtime's workers free for the code being profiled.
+        let body = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let guard = pprof::ProfilerGuardBuilder::default()
+                .frequency(SAMPLE_FREQUENCY)
+                .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+                .build()
+                .context("Failed to start the CPU profiler")?;
+            std::thread::sleep(duration);
+            let report = guard
+                .report()
+                .build()
+                .context("Failed to build the CPU profile")?;
+
+            let mut body = Vec::new();
+            match format {
+                ProfileFormat::Pprof => {
+                    body = report
+                        .pprof()
+                        .context("Failed to encode the CPU profile")?
+                        .write_to_bytes()?;
+                }
+                ProfileFormat::Flamegraph => report
+                    .flamegraph(&mut body)
+                    .context("Failed to render the flamegraph")?,
+            }
+            Ok(body)
+        })
+        .await??;
+        Ok(Some(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use pprof::protos::Profile;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn profiles_a_busy_process_in_both_formats() {
+        // Something to sample.
+        let stop = Arc::new(AtomicBool::new(false));
+        let busy = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut x = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    x = std::hint::black_box(x.wrapping_mul(31).wrapping_add(7));
+                }
+            }
+        });
+        let profiler = Arc::new(Profiler::default());
+
+        let running = tokio::spawn({
+            let profiler = profiler.clone();
+            async move {
+                profiler
+                    .cpu_profile(Duration::from_millis(300), ProfileFormat::Pprof)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // One at a time: a second request while the first samples is refused.
+        let refused = profiler
+            .cpu_profile(Duration::from_millis(10), ProfileFormat::Pprof)
+            .await
+            .unwrap();
+        assert!(refused.is_none());
+
+        let body = running
+            .await
+            .unwrap()
+            .unwrap()
+            .expect("the first profile runs");
+        let profile = Profile::parse_from_bytes(&body).unwrap();
+        assert!(!profile.sample_type.is_empty());
+        assert!(!profile.sample.is_empty(), "no samples of the busy thread");
+
+        let svg = profiler
+            .cpu_profile(Duration::from_millis(200), ProfileFormat::Flamegraph)
+            .await
+            .unwrap()
+            .expect("the previous profile finished");
+        stop.store(true, Ordering::Relaxed);
+        busy.join().unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("<svg"), "{}", &svg[..svg.len().min(200)]);
+    }
+}
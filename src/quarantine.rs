@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use tracing::{info, warn};
+
+use crate::clock::{Clock, Instant, Jitter};
+use crate::db::db::{list_quarantined, quarantine_account, release_quarantine, PgPool};
+use crate::db::models::{program_label, NewPoll, QuarantinedAccount};
+use crate::errors::DB_ACCOUNT_QUARANTINED;
+
+/// How often the quarantine list is reloaded, which is how long `cli quarantine release` takes
+/// to reach a running listener.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Times a batch is tried again after errors that aren't its data's fault, before its records
+/// are counted as failed.
+const MAX_RETRIES: u32 = 4;
+/// Delay before retrying such a batch, doubling with each of these errors in a row (from any
+/// flush), up to [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+/// Whether a write error comes from the data itself (it will fail again with the same data),
+/// rather than from the database or the connection (it may succeed later).
+///
+/// Constraint violations and the `Unknown` database errors (value too long, out of range, ...)
+/// count as data errors; lost connections, serialization failures, read-only transactions and
+/// pool errors don't.
+pub fn is_data_error(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<DieselError>() {
+        Some(DieselError::DatabaseError(kind, _)) => !matches!(
+            kind,
+            DatabaseErrorKind::SerializationFailure
+                | DatabaseErrorKind::ClosedConnection
+                | DatabaseErrorKind::ReadOnlyTransaction
+        ),
+        _ => false,
+    }
+}
+
+/// What the writer should do with an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Write,
+    /// The account is quarantined but its data changed on-chain, so it's tried again.
+    Retry,
+    /// The account is quarantined with this very data.
+    Skip,
+}
+
+/// Stops the writer from retrying accounts whose data keeps failing.
+///
+/// An account is quarantined after `threshold` consecutive data errors (see [`is_data_error`]):
+/// its updates are skipped, and the quarantine is persisted in `quarantined_accounts` with the
+/// last error, so it survives restarts. An update with different data is tried again: if it's
+/// written, the quarantine is lifted; if not, the account stays quarantined with the new data.
+/// Errors that aren't the data's fault never count: the batch is retried with a backoff instead
+/// (see [`Quarantine::interrupted`]).
+///
+/// Shared by the writer's concurrent flushes; every method is called from blocking threads.
+pub struct Quarantine {
+    threshold: u32,
+    clock: Clock,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Consecutive data errors of accounts not (yet) quarantined.
+    strikes: HashMap<Vec<u8>, u32>,
+    /// Quarantined accounts, by pubkey.
+    quarantined: HashMap<Vec<u8>, QuarantinedAccount>,
+    refreshed_at: Option<Instant>,
+    /// Errors that weren't the data's fault since the last successful write, from any flush.
+    interruptions: u32,
+    jitter: Jitter,
+}
+
+impl Quarantine {
+    /// Quarantines after `threshold` consecutive data errors; 0 turns quarantining off.
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            clock: Clock::system(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Stamps quarantined accounts with `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Spreads the retry delays with `jitter` instead of a system-seeded one.
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.state.get_mut().unwrap().jitter = jitter;
+        self
+    }
+
+    /// Number of accounts currently quarantined.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().quarantined.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reloads the quarantined accounts from the database when the last load is older than
+    /// [`REFRESH_INTERVAL`], so releases made with the CLI are picked up.
+    pub fn refresh(&self, pool: &PgPool) -> Result<()> {
+        if self.threshold == 0 {
+            return Ok(());
+        }
+        let due = self
+            .state
+            .lock()
+            .unwrap()
+            .refreshed_at
+            .is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL);
+        if !due {
+            return Ok(());
+        }
+        let accounts = list_quarantined(pool)?;
+        let mut state = self.state.lock().unwrap();
+        state.quarantined = accounts
+            .into_iter()
+            .map(|account| (account.account_pubkey.clone(), account))
+            .collect();
+        state.refreshed_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Decides whether `poll` is written.
+    pub fn admit(&self, poll: &NewPoll) -> Admission {
+        let state = self.state.lock().unwrap();
+        match state.quarantined.get(&poll.account_pubkey) {
+            None => Admission::Write,
+            Some(account) if account.data_checksum == poll.checksum() => Admission::Skip,
+            Some(_) => Admission::Retry,
+        }
+    }
+
+    /// Records a successful write of `poll`, lifting its account's quarantine if it had one.
+    pub fn succeeded(&self, pool: &PgPool, poll: &NewPoll) {
+        let lifted = {
+            let mut state = self.state.lock().unwrap();
+            state.interruptions = 0;
+            state.strikes.remove(&poll.account_pubkey);
+            state.quarantined.remove(&poll.account_pubkey).is_some()
+        };
+        if !lifted {
+            return;
+        }
+        info!(
+            event = "account_quarantine_lifted",
+            account = %program_label(&poll.account_pubkey),
+            program = %program_label(&poll.program_id),
+            poll_id = poll.poll_id,
+            "Changed data of a quarantined account was written, lifting its quarantine"
+        );
+        if let Err(e) = release_quarantine(pool, &poll.account_pubkey) {
+            warn!(error = ?e, "Failed to remove a lifted quarantine");
+        }
+    }
+
+    /// Records an error that isn't the data's fault (lost connection, ...) on try `attempt` of a
+    /// batch (0 for the first one), without blaming any account. Returns how long to wait
+    /// before trying the batch again, or `None` once it was retried [`MAX_RETRIES`] times.
+    ///
+    /// The delay grows with the errors in a row from every flush, so concurrent flushes back
+    /// off together while the database is unreachable, each by a jittered share of it so they
+    /// don't all come back at once.
+    pub fn interrupted(&self, attempt: u32) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        state.interruptions = state.interruptions.saturating_add(1);
+        if attempt >= MAX_RETRIES {
+            return None;
+        }
+        let doublings = (state.interruptions - 1).min(u32::BITS - 1);
+        let delay = RETRY_DELAY
+            .saturating_mul(1 << doublings)
+            .min(MAX_RETRY_DELAY);
+        Some(state.jitter.spread(delay))
+    }
+
+    /// Records a data error while writing `poll`, quarantining its account at the threshold.
+    pub fn failed(&self, pool: &PgPool, poll: &NewPoll, error: &anyhow::Error) {
+        // Rows written before account tracking have no pubkey to quarantine by.
+        if self.threshold == 0 || poll.account_pubkey.is_empty() {
+            return;
+        }
+        let account = {
+            let mut state = self.state.lock().unwrap();
+            let failures = match state.quarantined.get(&poll.account_pubkey) {
+                // A retry with changed data: stays quarantined, now with this data.
+                Some(quarantined) => quarantined.failures.saturating_add(1),
+                None => {
+                    let strikes = state
+                        .strikes
+                        .entry(poll.account_pubkey.clone())
+                        .or_default();
+                    *strikes += 1;
+                    if *strikes < self.threshold {
+                        return;
+                    }
+                    *strikes as i32
+                }
+            };
+            state.strikes.remove(&poll.account_pubkey);
+            let account = QuarantinedAccount {
+                account_pubkey: poll.account_pubkey.clone(),
+                program_id: poll.program_id.clone(),
+                poll_id: poll.poll_id,
+                failures,
+                last_error: format!("{:#}", error),
+                data_checksum: poll.checksum(),
+                quarantined_at: self.clock.now(),
+            };
+            state
+                .quarantined
+                .insert(poll.account_pubkey.clone(), account.clone());
+            account
+        };
+
+        warn!(
+            event = "account_quarantined",
+            code = %DB_ACCOUNT_QUARANTINED,
+            account = %program_label(&account.account_pubkey),
+            program = %program_label(&account.program_id),
+            poll_id = account.poll_id,
+            failures = account.failures,
+            error = %account.last_error,
+            "Quarantined an account whose data keeps failing to write; its updates are skipped \
+             until its data changes or it is released"
+        );
+        // Kept in memory either way, so the writer stops retrying even if this fails.
+        if let Err(e) = quarantine_account(pool, &account) {
+            warn!(error = ?e, "Failed to persist a quarantine");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::{new_poll, test_pool};
+
+    const PROGRAM: [u8; 32] = [0x44; 32];
+
+    fn database_error(kind: DatabaseErrorKind) -> anyhow::Error {
+        DieselError::DatabaseError(kind, Box::new("boom".to_string())).into()
+    }
+
+    #[test]
+    fn only_errors_of_the_data_count() {
+        assert!(is_data_error(&database_error(
+            DatabaseErrorKind::UniqueViolation
+        )));
+        assert!(is_data_error(&database_error(DatabaseErrorKind::Unknown)));
+        assert!(!is_data_error(&database_error(
+            DatabaseErrorKind::ClosedConnection
+        )));
+        assert!(!is_data_error(&database_error(
+            DatabaseErrorKind::SerializationFailure
+        )));
+        assert!(!is_data_error(&DieselError::NotFound.into()));
+        assert!(!is_data_error(&anyhow::anyhow!("pool timed out")));
+    }
+
+    #[test]
+    fn interrupted_batches_back_off_until_out_of_retries() {
+        let delays = |seed| {
+            let quarantine = Quarantine::new(3).with_jitter(Jitter::seeded(seed));
+            let mut delays: Vec<_> = (0..=MAX_RETRIES)
+                .map(|attempt| quarantine.interrupted(attempt))
+                .collect();
+            // Another flush failing meanwhile waits as long, up to the cap.
+            for _ in 0..100 {
+                delays.push(quarantine.interrupted(0));
+            }
+            delays
+        };
+
+        let first = delays(1);
+        assert_eq!(first, delays(1));
+        assert_eq!(first[MAX_RETRIES as usize], None);
+        let full = [1, 2, 4, 8, 0, 32, 32, 32]
+            .map(|doublings| (RETRY_DELAY * doublings).min(MAX_RETRY_DELAY));
+        for (delay, full) in first.iter().zip(full) {
+            if let Some(delay) = delay {
+                assert!(
+                    *delay >= full / 2 && *delay <= full,
+                    "{:?} of {:?}",
+                    delay,
+                    full
+                );
+            }
+        }
+        assert!(first[5..]
+            .iter()
+            .all(|delay| delay.unwrap() >= MAX_RETRY_DELAY / 2));
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn repeated_data_errors_quarantine_until_the_data_changes() {
+        let pool = test_pool();
+        let quarantine = Quarantine::new(3);
+        let poll = new_poll(&PROGRAM, 1, 10);
+        let error = database_error(DatabaseErrorKind::CheckViolation);
+
+        quarantine.failed(&pool, &poll, &error);
+        quarantine.failed(&pool, &poll, &error);
+        assert_eq!(quarantine.admit(&poll), Admission::Write);
+        quarantine.failed(&pool, &poll, &error);
+        assert_eq!(quarantine.admit(&poll), Admission::Skip);
+        assert_eq!(quarantine.len(), 1);
+        assert_eq!(list_quarantined(&pool).unwrap()[0].failures, 3);
+
+        let mut changed = poll.clone();
+        changed.poll_name = "Renamed".to_string();
+        assert_eq!(quarantine.admit(&changed), Admission::Retry);
+        // A retry that fails stays quarantined, now with the new data.
+        quarantine.failed(&pool, &changed, &error);
+        assert_eq!(quarantine.admit(&changed), Admission::Skip);
+        assert_eq!(quarantine.admit(&poll), Admission::Retry);
+        assert_eq!(list_quarantined(&pool).unwrap()[0].failures, 4);
+
+        quarantine.succeeded(&pool, &poll);
+        assert_eq!(quarantine.admit(&changed), Admission::Write);
+        assert!(list_quarantined(&pool).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn strikes_reset_on_success_and_quarantines_survive_restarts() {
+        let pool = test_pool();
+        let quarantine = Quarantine::new(2);
+        let (flaky, broken) = (new_poll(&PROGRAM, 1, 10), new_poll(&PROGRAM, 2, 10));
+        let error = database_error(DatabaseErrorKind::CheckViolation);
+
+        quarantine.failed(&pool, &flaky, &error);
+        quarantine.succeeded(&pool, &flaky);
+        quarantine.failed(&pool, &flaky, &error);
+        quarantine.failed(&pool, &broken, &error);
+        quarantine.failed(&pool, &broken, &error);
+        assert_eq!(quarantine.admit(&flaky), Admission::Write);
+        assert_eq!(quarantine.admit(&broken), Admission::Skip);
+
+        let restarted = Quarantine::new(2);
+        restarted.refresh(&pool).unwrap();
+        assert_eq!(restarted.admit(&broken), Admission::Skip);
+        assert_eq!(restarted.admit(&flaky), Admission::Write);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn a_threshold_of_zero_never_quarantines() {
+        let pool = test_pool();
+        let quarantine = Quarantine::new(0);
+        let poll = new_poll(&PROGRAM, 1, 10);
+
+        for _ in 0..10 {
+            quarantine.failed(&pool, &poll, &database_error(DatabaseErrorKind::Unknown));
+        }
+        assert_eq!(quarantine.admit(&poll), Admission::Write);
+        assert!(list_quarantined(&pool).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tracing::{info, warn};
+use voting_dapp_api_types::StorageQuotaHealth;
+
+use crate::db::db::{
+    count_rows_by_program, prune_ended_poll_delegations, prune_ended_polls,
+    prune_oldest_decode_failures, prune_oldest_events, prune_oldest_idl_accounts,
+    prune_oldest_raw_accounts, table_size_bytes, PgPool,
+};
+use crate::db::models::program_label;
+use crate::errors::DB_QUOTA_EXCEEDED;
+use crate::metrics::Metrics;
+
+/// A table a single indexed program can grow on its own, and that storage quotas apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QuotaTable {
+    Polls,
+    Delegations,
+    Events,
+    /// Accounts decoded with a program's IDL.
+    IdlAccounts,
+    /// Raw updates archived with `--archive-raw-accounts`.
+    RawHistory,
+    DecodeFailures,
+}
+
+impl QuotaTable {
+    pub const ALL: [QuotaTable; 6] = [
+        QuotaTable::Polls,
+        QuotaTable::Delegations,
+        QuotaTable::Events,
+        QuotaTable::IdlAccounts,
+        QuotaTable::RawHistory,
+        QuotaTable::DecodeFailures,
+    ];
+
+    /// The table's name in the database.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaTable::Polls => "polls",
+            QuotaTable::Delegations => "delegations",
+            QuotaTable::Events => "events",
+            QuotaTable::IdlAccounts => "idl_accounts",
+            QuotaTable::RawHistory => "account_raw_history",
+            QuotaTable::DecodeFailures => "decode_failures",
+        }
+    }
+}
+
+impl fmt::Display for QuotaTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QuotaTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QuotaTable::ALL
+            .into_iter()
+            .find(|table| table.as_str() == s)
+            .ok_or_else(|| {
+                let tables: Vec<_> = QuotaTable::ALL.iter().map(|t| t.as_str()).collect();
+                format!(
+                    "unknown table '{}', expected one of: {}",
+                    s,
+                    tables.join(", ")
+                )
+            })
+    }
+}
+
+/// What happens while a program is over a table's quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotaAction {
+    /// Only log and report it.
+    #[default]
+    Warn,
+    /// Drop the program's updates of that table until it's back under quota.
+    Stop,
+    /// Delete the program's oldest data of that table: that of the polls that ended first.
+    /// Tables not tied to polls lose their oldest rows: events and archived updates by slot,
+    /// IDL accounts closed first then least recently updated, decode failures by last failure.
+    Prune,
+}
+
+impl QuotaAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuotaAction::Warn => "warn",
+            QuotaAction::Stop => "stop",
+            QuotaAction::Prune => "prune",
+        }
+    }
+}
+
+impl fmt::Display for QuotaAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QuotaAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [QuotaAction::Warn, QuotaAction::Stop, QuotaAction::Prune]
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown quota action '{}', expected one of: warn, stop, prune",
+                    s
+                )
+            })
+    }
+}
+
+/// One limit per table, as `table=N` pairs (e.g. `events=1000000,polls=5000`). Each program
+/// gets the limit to itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableLimits(BTreeMap<QuotaTable, i64>);
+
+impl TableLimits {
+    pub fn get(&self, table: QuotaTable) -> Option<i64> {
+        self.0.get(&table).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for TableLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = BTreeMap::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (table, limit) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("'{}' is not a table=limit pair", pair))?;
+            let limit: i64 = limit
+                .trim()
+                .parse()
+                .ok()
+                .filter(|limit| *limit >= 0)
+                .ok_or_else(|| format!("'{}' is not a limit", limit.trim()))?;
+            limits.insert(table.trim().parse()?, limit);
+        }
+        Ok(Self(limits))
+    }
+}
+
+impl fmt::Display for TableLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .0
+            .iter()
+            .map(|(table, limit)| format!("{}={}", table, limit))
+            .collect();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+/// Storage quotas, see [`StorageQuotas`].
+#[derive(Debug, Clone, Default)]
+pub struct QuotaConfig {
+    pub max_rows: TableLimits,
+    /// Estimated bytes on disk, see [`StorageQuotas`].
+    pub max_bytes: TableLimits,
+    pub action: QuotaAction,
+}
+
+impl QuotaConfig {
+    /// No quota is set.
+    pub fn is_empty(&self) -> bool {
+        self.max_rows.is_empty() && self.max_bytes.is_empty()
+    }
+
+    /// Whether `table` has a quota.
+    pub fn applies_to(&self, table: QuotaTable) -> bool {
+        self.max_rows.get(table).is_some() || self.max_bytes.get(table).is_some()
+    }
+}
+
+type QuotaKey = (QuotaTable, Vec<u8>);
+
+#[derive(Default)]
+struct State {
+    rows: BTreeMap<QuotaKey, i64>,
+    /// Average on-disk bytes per row of each table, as of the last check.
+    bytes_per_row: BTreeMap<QuotaTable, f64>,
+    exceeded: BTreeSet<QuotaKey>,
+}
+
+impl State {
+    fn rows(&self, key: &QuotaKey) -> i64 {
+        self.rows.get(key).copied().unwrap_or(0)
+    }
+
+    /// The program's share of the table's size: its rows at the table's average row size.
+    fn estimated_bytes(&self, key: &QuotaKey) -> i64 {
+        let bytes_per_row = self.bytes_per_row.get(&key.0).copied().unwrap_or(0.0);
+        (self.rows(key) as f64 * bytes_per_row) as i64
+    }
+}
+
+/// Keeps a single program from filling the database: limits on the rows each program has in
+/// a table, and on their estimated size on disk.
+///
+/// Checking is cheap. Row counts are kept in memory: events and archived updates are counted
+/// as they're inserted, while the tables upserted in place (polls, delegations, IDL accounts,
+/// decode failures: they only grow with new accounts) are counted by [`StorageQuotas::check`],
+/// which also recounts the others. A program's size is
+/// estimated from its share of the rows and the table's `pg_total_relation_size`, re-read by
+/// every check; it includes indexes and space freed by deletes but not yet reused.
+///
+/// Shared by the sink (async) and the `storage-quotas` job (blocking thread).
+pub struct StorageQuotas {
+    config: QuotaConfig,
+    metrics: Arc<Metrics>,
+    state: Mutex<State>,
+}
+
+impl StorageQuotas {
+    pub fn new(config: QuotaConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            metrics,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether updates of `table` may be written for `program`. Only `--quota-action stop`
+    /// ever says no, while the program is over the table's quota.
+    pub fn admits(&self, table: QuotaTable, program: &[u8]) -> bool {
+        if self.config.action != QuotaAction::Stop {
+            return true;
+        }
+        let key = (table, program.to_vec());
+        let admitted = !self.state.lock().unwrap().exceeded.contains(&key);
+        if !admitted {
+            self.metrics
+                .storage_quota_dropped
+                .with_label_values(&[table.as_str()])
+                .inc();
+        }
+        admitted
+    }
+
+    /// Counts rows just inserted for `program`, so quotas are enforced between checks.
+    pub fn record(&self, table: QuotaTable, program: &[u8], inserted: usize) {
+        if inserted == 0 || !self.config.applies_to(table) {
+            return;
+        }
+        let key = (table, program.to_vec());
+        let mut state = self.state.lock().unwrap();
+        *state.rows.entry(key.clone()).or_default() += inserted as i64;
+        self.evaluate(&mut state, &key);
+    }
+
+    /// Recounts the rows of every table with a quota and re-reads its size, then evaluates
+    /// every program, pruning with `--quota-action prune`. Runs as the `storage-quotas` job.
+    pub fn check(&self, pool: &PgPool) -> Result<()> {
+        for table in QuotaTable::ALL {
+            if !self.config.applies_to(table) {
+                continue;
+            }
+            let counts = count_rows_by_program(pool, table.as_str())?;
+            let size = table_size_bytes(pool, table.as_str())?;
+            let total: i64 = counts.iter().map(|(_, rows)| rows).sum();
+
+            let over = {
+                let mut state = self.state.lock().unwrap();
+                let bytes_per_row = if total > 0 {
+                    size as f64 / total as f64
+                } else {
+                    0.0
+                };
+                state.bytes_per_row.insert(table, bytes_per_row);
+                // Programs that no longer have any row are evaluated too, to lift their quota.
+                let mut keys: BTreeSet<QuotaKey> = state
+                    .rows
+                    .keys()
+                    .filter(|(t, _)| *t == table)
+                    .cloned()
+                    .collect();
+                state.rows.retain(|(t, _), _| *t != table);
+                for (program, rows) in counts {
+                    let key = (table, program);
+                    state.rows.insert(key.clone(), rows);
+                    keys.insert(key);
+                }
+                for key in &keys {
+                    self.evaluate(&mut state, key);
+                }
+                state
+                    .exceeded
+                    .iter()
+                    .filter(|(t, _)| *t == table)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+
+            if self.config.action == QuotaAction::Prune {
+                for key in over {
+                    self.prune(pool, &key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the program's oldest data of the table, as much as it's over quota.
+    fn prune(&self, pool: &PgPool, key: &QuotaKey) -> Result<()> {
+        let excess = {
+            let state = self.state.lock().unwrap();
+            let rows = state.rows(key);
+            let over_rows = self.config.max_rows.get(key.0).map_or(0, |max| rows - max);
+            let over_bytes = match self.config.max_bytes.get(key.0) {
+                Some(max) => {
+                    let bytes_per_row = state.bytes_per_row.get(&key.0).copied().unwrap_or(0.0);
+                    let over = (state.estimated_bytes(key) - max) as f64;
+                    if bytes_per_row > 0.0 && over > 0.0 {
+                        (over / bytes_per_row).ceil() as i64
+                    } else {
+                        0
+                    }
+                }
+                None => 0,
+            };
+            over_rows.max(over_bytes)
+        };
+        if excess <= 0 {
+            return Ok(());
+        }
+
+        let (table, program) = key;
+        let deleted = match table {
+            QuotaTable::Polls => prune_ended_polls(pool, program, excess)?,
+            QuotaTable::Delegations => prune_ended_poll_delegations(pool, program, excess)?,
+            QuotaTable::Events => prune_oldest_events(pool, program, excess)?,
+            QuotaTable::IdlAccounts => prune_oldest_idl_accounts(pool, program, excess)?,
+            QuotaTable::RawHistory => prune_oldest_raw_accounts(pool, program, excess)?,
+            QuotaTable::DecodeFailures => prune_oldest_decode_failures(pool, program, excess)?,
+        };
+        if deleted == 0 {
+            warn!(
+                table = %table,
+                program = %program_label(program),
+                "Nothing left to prune: the program has no data of ended polls, and stays over \
+                 its storage quota"
+            );
+            return Ok(());
+        }
+        self.metrics
+            .storage_quota_pruned
+            .with_label_values(&[table.as_str()])
+            .inc_by(deleted as u64);
+        info!(
+            event = "quota_pruned",
+            table = %table,
+            program = %program_label(program),
+            deleted,
+            "Pruned the oldest data of a program over its storage quota"
+        );
+        let mut state = self.state.lock().unwrap();
+        if let Some(rows) = state.rows.get_mut(key) {
+            *rows = (*rows - deleted as i64).max(0);
+        }
+        self.evaluate(&mut state, key);
+        Ok(())
+    }
+
+    /// Compares a program's usage of a table with the quota, reporting it going over or
+    /// getting back under.
+    fn evaluate(&self, state: &mut State, key: &QuotaKey) {
+        let (table, program) = key;
+        let rows = state.rows(key);
+        let bytes = state.estimated_bytes(key);
+        let max_rows = self.config.max_rows.get(*table);
+        let max_bytes = self.config.max_bytes.get(*table);
+        let exceeded =
+            max_rows.is_some_and(|max| rows > max) || max_bytes.is_some_and(|max| bytes > max);
+
+        let label = program_label(program);
+        let labels = [table.as_str(), label.as_str()];
+        self.metrics
+            .storage_quota_rows
+            .with_label_values(&labels)
+            .set(rows);
+        self.metrics
+            .storage_quota_estimated_bytes
+            .with_label_values(&labels)
+            .set(bytes);
+        self.metrics
+            .storage_quota_exceeded
+            .with_label_values(&labels)
+            .set(i64::from(exceeded));
+
+        if exceeded && state.exceeded.insert(key.clone()) {
+            warn!(
+                event = "quota_exceeded",
+                code = %DB_QUOTA_EXCEEDED,
+                table = %table,
+                program = %label,
+                rows,
+                estimated_bytes = bytes,
+                max_rows,
+                max_bytes,
+                action = %self.config.action,
+                "A program went over a table's storage quota"
+            );
+        } else if !exceeded && state.exceeded.remove(key) {
+            info!(
+                event = "quota_recovered",
+                table = %table,
+                program = %label,
+                rows,
+                estimated_bytes = bytes,
+                "A program is back under a table's storage quota"
+            );
+        }
+    }
+
+    /// Usage of every table and program with a quota, for `/health`.
+    pub fn report(&self) -> Vec<StorageQuotaHealth> {
+        let state = self.state.lock().unwrap();
+        state
+            .rows
+            .keys()
+            .map(|key| {
+                let exceeded = state.exceeded.contains(key);
+                StorageQuotaHealth {
+                    table: key.0.to_string(),
+                    program: program_label(&key.1),
+                    rows: state.rows(key),
+                    estimated_bytes: state.estimated_bytes(key),
+                    max_rows: self.config.max_rows.get(key.0),
+                    max_bytes: self.config.max_bytes.get(key.0),
+                    exceeded,
+                    action: self.config.action.to_string(),
+                    indexing_stopped: exceeded && self.config.action == QuotaAction::Stop,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::{archive_raw_account, list_polls, upsert_polls};
+    use crate::db::models::NewRawAccount;
+    use crate::db::test_support::{new_poll, test_pool, NOW};
+
+    const SPAMMY: [u8; 32] = [0x51; 32];
+    const QUIET: [u8; 32] = [0x52; 32];
+
+    fn quotas(rows: &str, action: QuotaAction) -> StorageQuotas {
+        let config = QuotaConfig {
+            max_rows: rows.parse().unwrap(),
+            max_bytes: TableLimits::default(),
+            action,
+        };
+        StorageQuotas::new(config, Arc::new(Metrics::new().unwrap()))
+    }
+
+    fn raw(program: &[u8], slot: i64) -> NewRawAccount {
+        NewRawAccount {
+            program_id: program.to_vec(),
+            account_pubkey: vec![1; 32],
+            slot,
+            data: vec![0; 64],
+        }
+    }
+
+    #[test]
+    fn limits_parse_per_table() {
+        let limits: TableLimits = " events=10, account_raw_history=5,decode_failures=0"
+            .parse()
+            .unwrap();
+        assert_eq!(limits.get(QuotaTable::Events), Some(10));
+        assert_eq!(limits.get(QuotaTable::RawHistory), Some(5));
+        assert_eq!(limits.get(QuotaTable::DecodeFailures), Some(0));
+        assert_eq!(limits.get(QuotaTable::Polls), None);
+        assert_eq!(
+            limits.to_string(),
+            "events=10,account_raw_history=5,decode_failures=0"
+        );
+        assert_eq!(limits.to_string().parse::<TableLimits>(), Ok(limits));
+        assert!("".parse::<TableLimits>().unwrap().is_empty());
+
+        assert!("votes=10"
+            .parse::<TableLimits>()
+            .unwrap_err()
+            .contains("idl_accounts"));
+        assert!("events".parse::<TableLimits>().is_err());
+        assert!("events=-1".parse::<TableLimits>().is_err());
+        assert!("events=many".parse::<TableLimits>().is_err());
+        for table in QuotaTable::ALL {
+            assert_eq!(table.as_str().parse(), Ok(table));
+        }
+        assert_eq!("prune".parse(), Ok(QuotaAction::Prune));
+        assert!("delete".parse::<QuotaAction>().is_err());
+    }
+
+    #[test]
+    fn warnings_never_stop_indexing() {
+        let quotas = quotas("events=10", QuotaAction::Warn);
+        quotas.record(QuotaTable::Events, &SPAMMY, 11);
+
+        assert!(quotas.admits(QuotaTable::Events, &SPAMMY));
+        let report = quotas.report();
+        assert_eq!(report.len(), 1);
+        assert!(report[0].exceeded && !report[0].indexing_stopped);
+        assert_eq!((report[0].rows, report[0].max_rows), (11, Some(10)));
+    }
+
+    #[test]
+    fn stop_drops_the_offending_program_and_table_only() {
+        let quotas = quotas("events=10,account_raw_history=3", QuotaAction::Stop);
+        quotas.record(QuotaTable::Events, &SPAMMY, 10);
+        assert!(quotas.admits(QuotaTable::Events, &SPAMMY));
+
+        quotas.record(QuotaTable::Events, &SPAMMY, 1);
+        quotas.record(QuotaTable::Events, &QUIET, 5);
+        assert!(!quotas.admits(QuotaTable::Events, &SPAMMY));
+        assert!(quotas.admits(QuotaTable::Events, &QUIET));
+        assert!(quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+        assert_eq!(
+            quotas
+                .metrics
+                .storage_quota_dropped
+                .with_label_values(&["events"])
+                .get(),
+            1
+        );
+        let stopped: Vec<_> = quotas
+            .report()
+            .into_iter()
+            .filter(|usage| usage.indexing_stopped)
+            .map(|usage| (usage.table, usage.program))
+            .collect();
+        assert_eq!(stopped, [("events".to_string(), program_label(&SPAMMY))]);
+    }
+
+    #[test]
+    fn tables_without_a_quota_are_not_counted() {
+        let quotas = quotas("events=10", QuotaAction::Stop);
+        quotas.record(QuotaTable::RawHistory, &SPAMMY, 1_000);
+
+        assert!(quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+        assert!(quotas.report().is_empty());
+    }
+
+    #[test]
+    fn byte_quotas_use_the_average_row_size() {
+        let config = QuotaConfig {
+            max_bytes: "account_raw_history=1000".parse().unwrap(),
+            action: QuotaAction::Stop,
+            ..QuotaConfig::default()
+        };
+        let quotas = StorageQuotas::new(config, Arc::new(Metrics::new().unwrap()));
+        quotas
+            .state
+            .lock()
+            .unwrap()
+            .bytes_per_row
+            .insert(QuotaTable::RawHistory, 100.0);
+
+        quotas.record(QuotaTable::RawHistory, &SPAMMY, 10);
+        assert!(quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+        quotas.record(QuotaTable::RawHistory, &SPAMMY, 1);
+        assert!(!quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+        assert_eq!(quotas.report()[0].estimated_bytes, 1_100);
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn checks_recount_and_lift_a_stop() {
+        let pool = test_pool();
+        let quotas = quotas("account_raw_history=3", QuotaAction::Stop);
+        for slot in 0..4 {
+            archive_raw_account(&pool, &raw(&SPAMMY, slot)).unwrap();
+        }
+
+        quotas.check(&pool).unwrap();
+        assert!(!quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+        assert!(quotas.report()[0].estimated_bytes > 0);
+
+        prune_oldest_raw_accounts(&pool, &SPAMMY, 1).unwrap();
+        quotas.check(&pool).unwrap();
+        assert!(quotas.admits(QuotaTable::RawHistory, &SPAMMY));
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn prune_deletes_the_oldest_rows_until_under_quota() {
+        let pool = test_pool();
+        let quotas = quotas("account_raw_history=2", QuotaAction::Prune);
+        for slot in [30, 10, 20, 40] {
+            archive_raw_account(&pool, &raw(&SPAMMY, slot)).unwrap();
+        }
+        archive_raw_account(&pool, &raw(&QUIET, 1)).unwrap();
+
+        quotas.check(&pool).unwrap();
+        let left = |program: &[u8]| {
+            let rows = crate::db::db::list_raw_accounts(&pool, Some(program), (None, None), 0, 10);
+            let mut slots: Vec<_> = rows.unwrap().iter().map(|row| row.slot).collect();
+            slots.sort();
+            slots
+        };
+        assert_eq!(left(&SPAMMY), [30, 40]);
+        assert_eq!(left(&QUIET), [1]);
+        assert!(quotas.report().iter().all(|usage| !usage.exceeded));
+        assert_eq!(
+            quotas
+                .metrics
+                .storage_quota_pruned
+                .with_label_values(&["account_raw_history"])
+                .get(),
+            2
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn prune_deletes_the_polls_that_ended_first() {
+        let pool = test_pool();
+        let quotas = quotas("polls=2", QuotaAction::Prune);
+        let ended = |poll_id: i64, poll_end: i64| {
+            let mut poll = new_poll(&SPAMMY, poll_id, 10);
+            (poll.poll_start, poll.poll_end) = (1_000, poll_end);
+            poll
+        };
+        let batch = [
+            ended(1, 3_000),
+            ended(2, 2_000),
+            ended(3, 4_000),
+            new_poll(&SPAMMY, 4, 10),
+        ];
+        upsert_polls(&pool, &batch, 0, NOW).unwrap();
+
+        quotas.check(&pool).unwrap();
+        let mut left: Vec<_> = list_polls(&pool, Some(&SPAMMY))
+            .unwrap()
+            .iter()
+            .map(|poll| poll.poll_id)
+            .collect();
+        left.sort();
+        // Upcoming polls are never pruned.
+        assert_eq!(left, [3, 4]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{Context, Result};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::clock::Clock;
+use crate::db::db::{
+    latest_idl, upsert_delegation, upsert_idl_account, upsert_poll, PgPool, UpsertOutcome,
+};
+use crate::db::models::{NewDelegation, NewIdlAccount, NewPoll};
+use crate::decode::{decode_delegation, decode_poll, match_voting_account_type, VotingAccountType};
+use crate::idl_decode::IdlDecoder;
+
+/// What [`Replayer::replay`] wrote.
+#[derive(Debug)]
+pub enum Replayed {
+    Poll {
+        poll_id: u64,
+        outcome: UpsertOutcome,
+    },
+    /// `written` is false when the stored row was newer.
+    Delegation { poll_id: u64, written: bool },
+    /// An account decoded with its program's IDL, by its IDL name.
+    IdlAccount { name: String, written: bool },
+}
+
+impl fmt::Display for Replayed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stale = |written: bool| {
+            if written {
+                ""
+            } else {
+                " (stored row is newer)"
+            }
+        };
+        match self {
+            Replayed::Poll { poll_id, outcome } => match outcome {
+                UpsertOutcome::Written(_) => write!(f, "poll #{}", poll_id),
+                UpsertOutcome::Stale { .. } => write!(f, "poll #{}{}", poll_id, stale(false)),
+            },
+            Replayed::Delegation { poll_id, written } => {
+                write!(f, "delegation on poll #{}{}", poll_id, stale(*written))
+            }
+            Replayed::IdlAccount { name, written } => write!(f, "{}{}", name, stale(*written)),
+        }
+    }
+}
+
+/// Decodes raw account data again, with the decoders of this binary and the latest IDL stored
+/// for each program (`cli idl`), and writes the result like the listener would.
+///
+/// This is the listener's decode path without a websocket: it serves to write accounts that
+/// couldn't be decoded when they were received (see `decode_failures`), once the decoder is
+/// fixed. The `last_slot` guards apply, so replaying old data never overwrites newer rows.
+pub struct Replayer {
+    pool: PgPool,
+    /// Clock skew tolerance of lifecycle transitions, in seconds (see `lifecycle::transition`).
+    skew: i64,
+    /// The IDL decoder of each program seen so far, `None` when it has no IDL.
+    decoders: HashMap<Pubkey, Option<IdlDecoder>>,
+}
+
+impl Replayer {
+    pub fn new(pool: PgPool, skew: i64) -> Self {
+        Self {
+            pool,
+            skew,
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Decodes one account observed at `slot` and writes it. Fails when it still doesn't
+    /// decode (or the write fails), with the reason.
+    pub fn replay(
+        &mut self,
+        program_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+        slot: u64,
+    ) -> Result<Replayed> {
+        if data.len() < 8 {
+            anyhow::bail!(
+                "{} bytes of data, too short for a discriminator",
+                data.len()
+            );
+        }
+        // Built-in types first, then the IDL: the order `Listener::process_account` uses.
+        match match_voting_account_type(data) {
+            VotingAccountType::Poll => {
+                let poll = decode_poll(data).map_err(|e| anyhow::anyhow!("{}: {}", e.code(), e))?;
+                let row = NewPoll::from_account(&poll, slot, program_id, pubkey);
+                let now = Clock::system().unix_now();
+                let outcome = upsert_poll(&self.pool, &row, self.skew, now)?;
+                Ok(Replayed::Poll {
+                    poll_id: poll.poll_id,
+                    outcome,
+                })
+            }
+            VotingAccountType::Delegation => {
+                let delegation =
+                    decode_delegation(data).map_err(|e| anyhow::anyhow!("{}: {}", e.code(), e))?;
+                let row = NewDelegation::from_account(&delegation, slot, program_id, pubkey);
+                let written = upsert_delegation(&self.pool, &row)?;
+                Ok(Replayed::Delegation {
+                    poll_id: delegation.poll_id,
+                    written,
+                })
+            }
+            VotingAccountType::Candidate | VotingAccountType::Vote | VotingAccountType::Unknown => {
+                let Some(decoder) = self.decoder(program_id)? else {
+                    anyhow::bail!("Unknown account type, and no IDL is stored for the program");
+                };
+                let Some(decoded) = decoder.decode(data) else {
+                    anyhow::bail!(
+                        "Unknown account type: the program's IDL doesn't describe it either"
+                    );
+                };
+                let account = decoded?;
+                let row = NewIdlAccount {
+                    program_id: program_id.to_bytes().to_vec(),
+                    account_pubkey: pubkey.to_bytes().to_vec(),
+                    account_type: account.name.clone(),
+                    data: account.fields,
+                    last_slot: slot as i64,
+                };
+                let written = upsert_idl_account(&self.pool, &row)?;
+                Ok(Replayed::IdlAccount {
+                    name: account.name,
+                    written,
+                })
+            }
+        }
+    }
+
+    /// The decoder of the program's latest stored IDL, loaded once per program.
+    fn decoder(&mut self, program_id: &Pubkey) -> Result<Option<&IdlDecoder>> {
+        if !self.decoders.contains_key(program_id) {
+            let decoder = match latest_idl(&self.pool, &program_id.to_bytes())? {
+                Some(idl) => Some(IdlDecoder::new(&idl.idl).with_context(|| {
+                    format!("The IDL of {} can't be used for decoding", program_id)
+                })?),
+                None => None,
+            };
+            self.decoders.insert(*program_id, decoder);
+        }
+        Ok(self.decoders[program_id].as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::db::db::{
+        archive_raw_account, get_polls_by_id, list_delegations, list_idl_accounts,
+        list_raw_accounts,
+    };
+    use crate::db::models::NewRawAccount;
+    use crate::db::test_support::test_pool;
+    use crate::decode::{DELEGATION_DISCRIMINATOR, POLL_DISCRIMINATOR};
+    use crate::idl::record_idl;
+    use crate::idl_decode::account_discriminator;
+    use crate::state::anchor::{AnchorEncode, AnchorWriter};
+    use crate::state::delegation::Delegation;
+    use crate::state::pool::Poll;
+
+    const PROGRAM: Pubkey = Pubkey::new_from_array([0x40; 32]);
+
+    fn poll(name: &str) -> Vec<u8> {
+        let poll = Poll {
+            poll_id: 1,
+            poll_owner: Pubkey::new_from_array([1; 32]),
+            poll_name: name.to_string(),
+            poll_description: String::new(),
+            poll_start: 4_000_000_000,
+            poll_end: 4_000_086_400,
+            candidate_amount: 2,
+            candidate_winner: Pubkey::default(),
+        };
+        [&POLL_DISCRIMINATOR[..], &poll.encode_anchor_bytes()].concat()
+    }
+
+    fn raw(account: u8, slot: i64, data: Vec<u8>) -> NewRawAccount {
+        NewRawAccount {
+            program_id: PROGRAM.to_bytes().to_vec(),
+            account_pubkey: vec![account; 32],
+            slot,
+            data,
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn replaying_the_archive_rebuilds_the_tables() {
+        let pool = test_pool();
+        record_idl(
+            &pool,
+            &PROGRAM,
+            &json!({ "accounts": [{ "name": "Candidate", "type": { "kind": "struct", "fields": [
+                { "name": "poll_id", "type": "u64" },
+                { "name": "votes", "type": "u64" },
+            ] } }] }),
+        )
+        .unwrap();
+        let delegation = Delegation {
+            delegator: Pubkey::new_from_array([2; 32]),
+            delegate: Pubkey::new_from_array([3; 32]),
+            poll_id: 1,
+            expiry: None,
+        };
+        let mut candidate = AnchorWriter::default();
+        candidate.write_u64(1);
+        candidate.write_u64(9);
+        let archive = [
+            raw(10, 100, poll("First name")),
+            raw(
+                11,
+                101,
+                [
+                    &DELEGATION_DISCRIMINATOR[..],
+                    &delegation.encode_anchor_bytes(),
+                ]
+                .concat(),
+            ),
+            raw(
+                12,
+                102,
+                [
+                    &account_discriminator("Candidate")[..],
+                    &candidate.into_bytes(),
+                ]
+                .concat(),
+            ),
+            raw(13, 103, vec![9; 16]),
+            raw(10, 104, poll("Renamed")),
+        ];
+        for update in &archive {
+            assert!(archive_raw_account(&pool, update).unwrap());
+        }
+
+        let mut replayer = Replayer::new(pool.clone(), 0);
+        let mut results = Vec::new();
+        for raw in
+            list_raw_accounts(&pool, Some(&PROGRAM.to_bytes()), (None, None), 0, 100).unwrap()
+        {
+            let account = Pubkey::try_from(raw.account_pubkey.as_slice()).unwrap();
+            results.push(
+                replayer
+                    .replay(&PROGRAM, &account, &raw.data, raw.slot as u64)
+                    .map(|replayed| replayed.to_string())
+                    .map_err(|e| format!("{:#}", e)),
+            );
+        }
+        assert_eq!(results[0], Ok("poll #1".to_string()));
+        assert_eq!(results[1], Ok("delegation on poll #1".to_string()));
+        assert_eq!(results[2], Ok("Candidate".to_string()));
+        assert!(results[3]
+            .as_ref()
+            .unwrap_err()
+            .contains("Unknown account type"));
+        assert_eq!(results[4], Ok("poll #1".to_string()));
+
+        let polls = get_polls_by_id(&pool, 1, Some(&PROGRAM.to_bytes())).unwrap();
+        assert_eq!(
+            (polls[0].poll_name.as_str(), polls[0].last_slot),
+            ("Renamed", 104)
+        );
+        let delegations = list_delegations(&pool, Some(&PROGRAM.to_bytes()), 1).unwrap();
+        assert_eq!(delegations[0].delegate, [3; 32]);
+        let candidates = list_idl_accounts(&pool, &PROGRAM.to_bytes(), "Candidate").unwrap();
+        assert_eq!(candidates[0].data, json!({ "poll_id": 1, "votes": 9 }));
+
+        // Replaying an older update again changes nothing.
+        let again = replayer
+            .replay(
+                &PROGRAM,
+                &Pubkey::new_from_array([10; 32]),
+                &poll("First name"),
+                100,
+            )
+            .unwrap();
+        assert_eq!(again.to_string(), "poll #1 (stored row is newer)");
+        let polls = get_polls_by_id(&pool, 1, Some(&PROGRAM.to_bytes())).unwrap();
+        assert_eq!(polls[0].poll_name, "Renamed");
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn accounts_without_an_idl_or_a_discriminator_fail() {
+        let pool = test_pool();
+        let mut replayer = Replayer::new(pool, 0);
+        let account = Pubkey::new_unique();
+        let err = replayer.replay(&PROGRAM, &account, &[1; 4], 1).unwrap_err();
+        assert!(err.to_string().contains("too short"), "{}", err);
+        let err = replayer
+            .replay(&PROGRAM, &account, &[9; 16], 1)
+            .unwrap_err();
+        assert!(err.to_string().contains("no IDL is stored"), "{}", err);
+        // A poll cut short keeps its decode error.
+        let err = replayer
+            .replay(&PROGRAM, &account, &poll("Fruit")[..20], 1)
+            .unwrap_err();
+        assert!(err.to_string().starts_with('E'), "{}", err);
+    }
+}
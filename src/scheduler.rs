@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::clock::Clock;
+use crate::db::db::{list_jobs, record_job_finish, record_job_start, register_job, PgPool};
+use crate::db::models::JobRow;
+
+/// Jobs registered together start this far apart, so they don't all fire at the same moment.
+pub const DEFAULT_STAGGER: Duration = Duration::from_secs(2);
+
+/// How often the `jobs` table is checked for pauses and run requests made with the CLI.
+const CONTROL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Boxed future of one job run.
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+type JobFn = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+
+/// What a job mostly waits on. Jobs of a class share its concurrency limit, so e.g. the
+/// database-heavy ones never run more than that many at once next to the writer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobClass {
+    DbHeavy,
+    RpcHeavy,
+}
+
+impl JobClass {
+    pub const ALL: [JobClass; 2] = [JobClass::DbHeavy, JobClass::RpcHeavy];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobClass::DbHeavy => "db-heavy",
+            JobClass::RpcHeavy => "rpc-heavy",
+        }
+    }
+}
+
+impl fmt::Display for JobClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for JobClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        JobClass::ALL
+            .into_iter()
+            .find(|class| class.as_str() == s)
+            .ok_or_else(|| format!("unknown job class '{}'", s))
+    }
+}
+
+/// When a job runs.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// At a fixed interval, the first run right at startup (after the stagger).
+    Every(Duration),
+    /// At the times of a cron expression (seconds first, UTC), e.g. `0 */5 * * * *`.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Parses a cron expression, see [`Schedule::Cron`].
+    pub fn cron(expression: &str) -> Result<Self> {
+        let schedule = cron::Schedule::from_str(expression)
+            .map_err(|e| anyhow!("Invalid cron expression '{}': {}", expression, e))?;
+        Ok(Schedule::Cron(Box::new(schedule)))
+    }
+
+    /// The first run, for a job started `offset` after the others.
+    fn first_run(&self, now: DateTime<Utc>, offset: Duration) -> DateTime<Utc> {
+        match self {
+            Schedule::Every(_) => now + offset,
+            Schedule::Cron(_) => self.next_run(now, now, offset),
+        }
+    }
+
+    /// The run after the one planned at `planned`. Runs missed while the previous one was
+    /// running (or waiting for its class) are skipped rather than caught up on.
+    fn next_run(
+        &self,
+        planned: DateTime<Utc>,
+        now: DateTime<Utc>,
+        offset: Duration,
+    ) -> DateTime<Utc> {
+        match self {
+            Schedule::Every(interval) => {
+                let mut next = planned + *interval;
+                if next <= now {
+                    let missed =
+                        (now - next).num_milliseconds() / interval.as_millis().max(1) as i64;
+                    next += *interval * (missed as u32 + 1);
+                }
+                next
+            }
+            Schedule::Cron(schedule) => schedule
+                .after(&(now - offset))
+                .next()
+                .map_or(DateTime::<Utc>::MAX_UTC, |at| at + offset),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(schedule) => write!(f, "{}", schedule),
+        }
+    }
+}
+
+/// A registered job.
+struct Job {
+    name: &'static str,
+    schedule: Schedule,
+    class: JobClass,
+    run: JobFn,
+}
+
+/// Where a job stands in the scheduler loop.
+struct JobState {
+    next_run: DateTime<Utc>,
+    /// Delay of its first run, kept for cron schedules.
+    offset: Duration,
+    paused: bool,
+    /// Started (or waiting for its class) and not finished yet.
+    running: bool,
+    /// The last CLI run request started, so it isn't started twice.
+    served_request: Option<DateTime<Utc>>,
+}
+
+/// Runs the listener's periodic background tasks (lifecycle transitions, bandwidth flushes,
+/// slot clock samples, ...) so they don't all fire together and starve the writer.
+///
+/// - jobs registered together start [`DEFAULT_STAGGER`] apart, and keep that phase;
+/// - each [`JobClass`] has a concurrency limit: a due job waits for a free slot of its class;
+/// - a job never overlaps itself: runs that come due while it's still running are skipped.
+///
+/// With a database, every job has a row in `jobs` with its schedule, last run, last error and
+/// next run; `cli jobs pause` / `resume` / `run` set flags there, which the scheduler picks up
+/// within [`CONTROL_INTERVAL`]. A requested run starts even if the job is paused.
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    limits: HashMap<JobClass, usize>,
+    stagger: Duration,
+    pool: Option<PgPool>,
+    clock: Clock,
+}
+
+impl Scheduler {
+    /// A scheduler persisting to `pool`, or only keeping its state in memory without one.
+    /// Each class runs one job at a time until [`Scheduler::limit`] says otherwise.
+    pub fn new(pool: Option<PgPool>) -> Self {
+        Self {
+            jobs: Vec::new(),
+            limits: JobClass::ALL.into_iter().map(|class| (class, 1)).collect(),
+            stagger: DEFAULT_STAGGER,
+            pool,
+            clock: Clock::system(),
+        }
+    }
+
+    /// How many jobs of `class` may run at once (at least one).
+    pub fn limit(&mut self, class: JobClass, limit: usize) -> &mut Self {
+        self.limits.insert(class, limit.max(1));
+        self
+    }
+
+    /// Delay between the first runs of consecutive jobs.
+    pub fn stagger(&mut self, stagger: Duration) -> &mut Self {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Clock the runs are planned, started and recorded with.
+    pub fn clock(&mut self, clock: Clock) -> &mut Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds a job. A failed run is logged and recorded; the job keeps its schedule.
+    ///
+    /// # Panics
+    ///
+    /// If `schedule` is a zero interval.
+    pub fn register<F, Fut>(
+        &mut self,
+        name: &'static str,
+        schedule: Schedule,
+        class: JobClass,
+        run: F,
+    ) -> &mut Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        assert!(
+            !matches!(schedule, Schedule::Every(interval) if interval.is_zero()),
+            "job {} has a zero interval",
+            name
+        );
+        self.jobs.push(Job {
+            name,
+            schedule,
+            class,
+            run: Arc::new(move || Box::pin(run())),
+        });
+        self
+    }
+
+    /// Starts the scheduler loop. Aborting the task stops scheduling; runs already started
+    /// finish on their own.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(self) {
+        let clock = self.clock;
+        let jobs: Arc<Vec<Job>> = Arc::new(self.jobs);
+        let classes: HashMap<JobClass, Arc<Semaphore>> = self
+            .limits
+            .iter()
+            .map(|(class, limit)| (*class, Arc::new(Semaphore::new(*limit))))
+            .collect();
+
+        let now = clock.now();
+        let mut states: Vec<JobState> = jobs
+            .iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let offset = self.stagger * i as u32;
+                JobState {
+                    next_run: job.schedule.first_run(now, offset),
+                    offset,
+                    paused: false,
+                    running: false,
+                    served_request: None,
+                }
+            })
+            .collect();
+        if let Some(pool) = &self.pool {
+            register_all(pool, &jobs, &mut states).await;
+        }
+
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel::<usize>();
+        let mut control = tokio::time::interval(CONTROL_INTERVAL);
+        control.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            let now = clock.now();
+            for (i, state) in states.iter_mut().enumerate() {
+                if state.paused || state.running || state.next_run > now {
+                    continue;
+                }
+                let job = &jobs[i];
+                let planned = state.next_run;
+                state.next_run = job.schedule.next_run(planned, now, state.offset);
+                state.running = true;
+                start(
+                    &jobs,
+                    i,
+                    &classes,
+                    self.pool.clone(),
+                    clock,
+                    state.next_run,
+                    &done_tx,
+                );
+            }
+
+            let wake = states
+                .iter()
+                .filter(|state| !state.paused && !state.running)
+                .map(|state| state.next_run)
+                .min();
+            let sleep = wake.map_or(CONTROL_INTERVAL, |at| {
+                (at - clock.now()).to_std().unwrap_or_default()
+            });
+            tokio::select! {
+                _ = clock.sleep(sleep) => {}
+                Some(i) = done_rx.recv() => states[i].running = false,
+                _ = control.tick(), if self.pool.is_some() => {
+                    let Some(pool) = self.pool.clone() else { continue };
+                    let rows = match tokio::task::spawn_blocking(move || list_jobs(&pool)).await {
+                        Ok(Ok(rows)) => rows,
+                        Ok(Err(e)) => {
+                            warn!(error = ?e, "Failed to read job controls");
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!(error = ?e, "Job controls task panicked");
+                            continue;
+                        }
+                    };
+                    for row in rows {
+                        let Some(i) = jobs.iter().position(|job| job.name == row.name) else {
+                            continue;
+                        };
+                        let state = &mut states[i];
+                        apply_controls(jobs[i].name, state, &row);
+                        let requested = row
+                            .run_requested_at
+                            .filter(|at| state.served_request != Some(*at));
+                        if let (Some(at), false) = (requested, state.running) {
+                            info!(job = jobs[i].name, "Running job on request");
+                            state.served_request = Some(at);
+                            state.running = true;
+                            start(&jobs, i, &classes, self.pool.clone(), clock, state.next_run, &done_tx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Registers every job in `jobs`, and takes over the pause flags set with the CLI.
+async fn register_all(pool: &PgPool, jobs: &Arc<Vec<Job>>, states: &mut [JobState]) {
+    let registrations: Vec<_> = jobs
+        .iter()
+        .zip(states.iter())
+        .map(|(job, state)| {
+            (
+                job.name,
+                job.class.as_str(),
+                job.schedule.to_string(),
+                state.next_run,
+            )
+        })
+        .collect();
+    let pool = pool.clone();
+    let registered = tokio::task::spawn_blocking(move || {
+        registrations
+            .into_iter()
+            .map(|(name, class, schedule, next_run)| {
+                register_job(&pool, name, class, &schedule, next_run)
+            })
+            .collect::<Result<Vec<_>>>()
+    })
+    .await;
+    match registered {
+        Ok(Ok(rows)) => {
+            for (i, row) in rows.iter().enumerate() {
+                apply_controls(jobs[i].name, &mut states[i], row);
+            }
+        }
+        // The jobs still run, they just can't be listed or controlled with the CLI.
+        Ok(Err(e)) => warn!(error = ?e, "Failed to register jobs"),
+        Err(e) => warn!(error = ?e, "Job registration task panicked"),
+    }
+}
+
+/// Takes over the pause flag of a job's row.
+fn apply_controls(name: &str, state: &mut JobState, row: &JobRow) {
+    if state.paused != row.paused {
+        info!(
+            job = name,
+            paused = row.paused,
+            "Job {}",
+            if row.paused { "paused" } else { "resumed" }
+        );
+        state.paused = row.paused;
+    }
+}
+
+/// Runs job `i` in its own task once its class has a free slot, records the run, and reports
+/// back on `done` when it's over.
+fn start(
+    jobs: &Arc<Vec<Job>>,
+    i: usize,
+    classes: &HashMap<JobClass, Arc<Semaphore>>,
+    pool: Option<PgPool>,
+    clock: Clock,
+    next_run: DateTime<Utc>,
+    done: &mpsc::UnboundedSender<usize>,
+) {
+    let (jobs, done) = (jobs.clone(), done.clone());
+    let class = classes[&jobs[i].class].clone();
+    tokio::spawn(async move {
+        let job = &jobs[i];
+        let _permit = class.acquire_owned().await;
+        let started_at = clock.now();
+        debug!(job = job.name, class = %job.class, "Job started");
+        if let Some(pool) = pool.clone() {
+            let name = job.name;
+            let recorded =
+                tokio::task::spawn_blocking(move || record_job_start(&pool, name, started_at))
+                    .await;
+            if let Ok(Err(e)) = recorded {
+                warn!(job = job.name, error = ?e, "Failed to record job start");
+            }
+        }
+
+        // In a task of its own, so a panicking job is reported like a failing one.
+        let result = match tokio::spawn((job.run)()).await {
+            Ok(result) => result,
+            Err(e) => Err(anyhow!("job panicked: {}", e)),
+        };
+        let elapsed_ms = (clock.now() - started_at).num_milliseconds();
+        let error = match &result {
+            Ok(()) => {
+                debug!(job = job.name, elapsed_ms, "Job finished");
+                None
+            }
+            Err(e) => {
+                warn!(job = job.name, elapsed_ms, error = ?e, "Job failed");
+                Some(format!("{:#}", e))
+            }
+        };
+
+        if let Some(pool) = pool {
+            let name = job.name;
+            let recorded = tokio::task::spawn_blocking(move || {
+                record_job_finish(&pool, name, clock.now(), error.as_deref(), next_run)
+            })
+            .await;
+            if let Ok(Err(e)) = recorded {
+                warn!(job = job.name, error = ?e, "Failed to record job run");
+            }
+        }
+        let _ = done.send(i);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::clock::Jitter;
+    use crate::db::db::{request_job_run, set_job_paused};
+    use crate::db::test_support::test_pool;
+
+    fn at(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, 1, hour, minute, second)
+            .unwrap()
+    }
+
+    /// A clock starting at noon, driven by the test's paused time.
+    fn noon() -> Clock {
+        Clock::starting_at(at(12, 0, 0))
+    }
+
+    /// Milliseconds after noon.
+    fn ms(at_ms: i64) -> DateTime<Utc> {
+        at(12, 0, 0) + chrono::Duration::milliseconds(at_ms)
+    }
+
+    /// Counts runs of a job, when they started and how many of them overlapped at most.
+    struct Runs {
+        clock: Clock,
+        starts: Mutex<Vec<DateTime<Utc>>>,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    impl Runs {
+        fn new(clock: Clock) -> Arc<Runs> {
+            Arc::new(Runs {
+                clock,
+                starts: Mutex::default(),
+                running: AtomicUsize::new(0),
+                max_running: AtomicUsize::new(0),
+            })
+        }
+
+        /// A job that takes `took`, counted in `runs`.
+        fn job(runs: &Arc<Runs>, took: Duration) -> impl Fn() -> JobFuture + Send + Sync {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                Box::pin(async move {
+                    runs.starts.lock().unwrap().push(runs.clock.now());
+                    let running = runs.running.fetch_add(1, Ordering::SeqCst) + 1;
+                    runs.max_running.fetch_max(running, Ordering::SeqCst);
+                    runs.clock.sleep(took).await;
+                    runs.running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            }
+        }
+
+        fn started(&self) -> usize {
+            self.starts.lock().unwrap().len()
+        }
+
+        fn starts(&self) -> Vec<DateTime<Utc>> {
+            self.starts.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn intervals_skip_the_runs_they_missed() {
+        let every = Schedule::Every(Duration::from_secs(10));
+        let offset = Duration::ZERO;
+
+        assert_eq!(
+            every.first_run(at(12, 0, 0), Duration::from_secs(4)),
+            at(12, 0, 4)
+        );
+        assert_eq!(
+            every.next_run(at(12, 0, 0), at(12, 0, 3), offset),
+            at(12, 0, 10)
+        );
+        // Ran long: the runs due at :10, :20 and :30 are skipped, the phase is kept.
+        assert_eq!(
+            every.next_run(at(12, 0, 0), at(12, 0, 35), offset),
+            at(12, 0, 40)
+        );
+        assert_eq!(
+            every.next_run(at(12, 0, 0), at(12, 0, 40), offset),
+            at(12, 0, 50)
+        );
+    }
+
+    #[test]
+    fn cron_runs_keep_their_stagger() {
+        let minutely = Schedule::cron("0 * * * * *").unwrap();
+        let offset = Duration::from_secs(2);
+
+        assert_eq!(minutely.first_run(at(12, 0, 30), offset), at(12, 1, 2));
+        assert_eq!(
+            minutely.next_run(at(12, 1, 2), at(12, 1, 2), offset),
+            at(12, 2, 2)
+        );
+        assert_eq!(
+            minutely.next_run(at(12, 1, 2), at(12, 4, 10), offset),
+            at(12, 5, 2)
+        );
+        assert_eq!(minutely.to_string(), "0 * * * * *");
+        assert!(Schedule::cron("every minute").is_err());
+    }
+
+    #[test]
+    fn job_classes_parse_back() {
+        for class in JobClass::ALL {
+            assert_eq!(class.as_str().parse::<JobClass>(), Ok(class));
+        }
+        assert!("io-heavy".parse::<JobClass>().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "zero interval")]
+    fn zero_intervals_are_refused() {
+        Scheduler::new(None).register(
+            "spin",
+            Schedule::Every(Duration::ZERO),
+            JobClass::DbHeavy,
+            || async { Ok(()) },
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jobs_of_a_class_wait_for_its_limit() {
+        let clock = noon();
+        let (db, rpc) = (Runs::new(clock), Runs::new(clock));
+        let hourly = || Schedule::Every(Duration::from_secs(3600));
+        let took = Duration::from_millis(100);
+        let mut scheduler = Scheduler::new(None);
+        scheduler
+            .clock(clock)
+            .stagger(Duration::ZERO)
+            .limit(JobClass::RpcHeavy, 2);
+        for name in ["db-1", "db-2", "db-3"] {
+            scheduler.register(name, hourly(), JobClass::DbHeavy, Runs::job(&db, took));
+        }
+        for name in ["rpc-1", "rpc-2", "rpc-3"] {
+            scheduler.register(name, hourly(), JobClass::RpcHeavy, Runs::job(&rpc, took));
+        }
+        let task = scheduler.spawn();
+
+        clock.sleep(took * 5).await;
+        task.abort();
+        assert_eq!(db.starts(), [ms(0), ms(100), ms(200)]);
+        assert_eq!(rpc.starts(), [ms(0), ms(0), ms(100)]);
+        assert_eq!(db.max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(rpc.max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_job_never_overlaps_itself() {
+        let clock = noon();
+        let runs = Runs::new(clock);
+        let mut scheduler = Scheduler::new(None);
+        scheduler.clock(clock).register(
+            "slow",
+            Schedule::Every(Duration::from_millis(20)),
+            JobClass::DbHeavy,
+            Runs::job(&runs, Duration::from_millis(100)),
+        );
+        let task = scheduler.spawn();
+
+        clock.sleep(Duration::from_millis(450)).await;
+        task.abort();
+        assert_eq!(runs.max_running.load(Ordering::SeqCst), 1);
+        // The runs due while it was running are skipped, the 20ms phase is kept.
+        assert_eq!(runs.starts(), [ms(0), ms(100), ms(200), ms(300), ms(400)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_jobs_keep_their_schedule() {
+        let clock = noon();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new(None);
+        for (name, panics) in [("fails", false), ("panics", true)] {
+            let runs = runs.clone();
+            scheduler.register(
+                name,
+                Schedule::Every(Duration::from_millis(30)),
+                JobClass::DbHeavy,
+                move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        assert!(!panics, "job panicked");
+                        Err(anyhow!("job failed"))
+                    }
+                },
+            );
+        }
+        scheduler.clock(clock).stagger(Duration::ZERO);
+        let task = scheduler.spawn();
+
+        clock.sleep(Duration::from_millis(200)).await;
+        task.abort();
+        // Both at 0, 30, ..., 180ms.
+        assert_eq!(runs.load(Ordering::SeqCst), 14);
+    }
+
+    /// Five simulated minutes of jobs competing for a class, overlapping themselves, failing
+    /// and backing off with jitter; returns every start and finish, in order.
+    async fn simulated_minutes(seed: u64) -> String {
+        let clock = noon();
+        let log = Arc::new(Mutex::new(String::new()));
+        let jitter = Arc::new(Mutex::new(Jitter::seeded(seed)));
+        let job = |name: &'static str, took: Duration, fails: bool| {
+            let (log, jitter) = (log.clone(), jitter.clone());
+            move || {
+                let (log, jitter) = (log.clone(), jitter.clone());
+                async move {
+                    let started = clock.now().format("%H:%M:%S%.3f");
+                    writeln!(log.lock().unwrap(), "{} {} started", started, name).unwrap();
+                    clock.sleep(took).await;
+                    if fails {
+                        let backoff = jitter.lock().unwrap().spread(Duration::from_secs(4));
+                        clock.sleep(backoff).await;
+                    }
+                    let finished = clock.now().format("%H:%M:%S%.3f");
+                    writeln!(log.lock().unwrap(), "{} {} finished", finished, name).unwrap();
+                    if fails {
+                        Err(anyhow!("{} failed", name))
+                    } else {
+                        Ok(())
+                    }
+                }
+            }
+        };
+
+        let mut scheduler = Scheduler::new(None);
+        scheduler
+            .clock(clock)
+            .register(
+                "frequent",
+                Schedule::Every(Duration::from_secs(10)),
+                JobClass::DbHeavy,
+                job("frequent", Duration::from_secs(3), false),
+            )
+            .register(
+                "overruns",
+                Schedule::Every(Duration::from_secs(7)),
+                JobClass::DbHeavy,
+                job("overruns", Duration::from_secs(12), false),
+            )
+            .register(
+                "flaky",
+                Schedule::Every(Duration::from_secs(15)),
+                JobClass::RpcHeavy,
+                job("flaky", Duration::from_secs(1), true),
+            )
+            .register(
+                "minutely",
+                Schedule::cron("0 * * * * *").unwrap(),
+                JobClass::RpcHeavy,
+                job("minutely", Duration::from_secs(5), false),
+            );
+        let task = scheduler.spawn();
+        clock.sleep(Duration::from_secs(300)).await;
+        task.abort();
+
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_simulated_run_replays_identically() {
+        let first = simulated_minutes(42).await;
+        assert_eq!(first, simulated_minutes(42).await);
+        assert!(
+            first.starts_with("12:00:00.000 frequent started\n"),
+            "{}",
+            first
+        );
+        // Its class is busy until then.
+        assert!(
+            first.contains("12:00:03.000 overruns started\n"),
+            "{}",
+            first
+        );
+        // The jitter changes the flaky job's backoffs, and only with another seed.
+        assert_ne!(first, simulated_minutes(43).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn runs_are_recorded_and_controlled_from_the_table() {
+        let pool = test_pool();
+        let runs = Runs::new(Clock::system());
+        let mut scheduler = Scheduler::new(Some(pool.clone()));
+        scheduler.register(
+            "hourly",
+            Schedule::Every(Duration::from_secs(3600)),
+            JobClass::DbHeavy,
+            Runs::job(&runs, Duration::ZERO),
+        );
+        let task = scheduler.spawn();
+
+        let recorded = |pool: &PgPool| {
+            let rows = list_jobs(pool).unwrap();
+            assert_eq!(rows.len(), 1);
+            rows.into_iter().next().unwrap()
+        };
+        Clock::system().sleep(Duration::from_millis(500)).await;
+        let row = recorded(&pool);
+        assert_eq!(runs.started(), 1);
+        assert_eq!(
+            (row.class.as_str(), row.schedule.as_str()),
+            ("db-heavy", "every 3600s")
+        );
+        assert!(row.last_finished_at.is_some() && row.last_error.is_none());
+
+        // A paused job still runs on request, within a check of the controls.
+        set_job_paused(&pool, "hourly", true).unwrap();
+        request_job_run(&pool, "hourly").unwrap();
+        Clock::system()
+            .sleep(CONTROL_INTERVAL + Duration::from_millis(500))
+            .await;
+        task.abort();
+        assert_eq!(runs.started(), 2);
+        assert!(recorded(&pool).paused);
+    }
+}
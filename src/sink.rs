@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+use tracing::info;
+
+use crate::clock::Clock;
+use crate::db::db::{
+    archive_raw_account, get_checkpoint, mark_account_closed, record_decode_failure, record_events,
+    save_checkpoint, upsert_delegation, upsert_idl_account, PgPool,
+};
+use crate::db::models::{
+    program_label, NewDecodeFailure, NewDelegation, NewEvent, NewIdlAccount, NewPoll, NewRawAccount,
+};
+use crate::metrics::Metrics;
+use crate::quota::{QuotaTable, StorageQuotas};
+use crate::writer::{log_lifecycle_transition, PollWriter};
+
+/// Destination of everything the [`Listener`](crate::listener::Listener) decodes.
+///
+/// The listener only decides *what* to write; the sink decides where it goes. Decoded polls are
+/// logged by the listener whatever the sink, so a sink that drops them is a valid "print only"
+/// mode.
+#[async_trait]
+pub trait PollSink: Send + Sync {
+    /// Stores a decoded poll. May wait (backpressure), but shouldn't fail for a single bad row.
+    async fn write_poll(&self, poll: NewPoll) -> Result<()>;
+
+    /// Stores the events of one transaction, returning how many were new.
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize>;
+
+    /// Stores a decoded Delegation account (program v3). Dropped by default, so sinks written
+    /// before delegations existed keep working.
+    async fn write_delegation(&self, _delegation: NewDelegation) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stores an account decoded from its program's IDL (`--idl`). Dropped by default, like
+    /// delegations.
+    async fn write_idl_account(&self, _account: NewIdlAccount) -> Result<()> {
+        Ok(())
+    }
+
+    /// Keeps an account that couldn't be decoded, with its raw data, so it can be replayed once
+    /// the decoder is fixed. Dropped by default: the listener logs the failure anyway.
+    async fn write_decode_failure(&self, _failure: NewDecodeFailure) -> Result<()> {
+        Ok(())
+    }
+
+    /// Archives a raw account update before it's decoded, with
+    /// [`ListenerBuilder::archive_raw_accounts`](crate::listener::ListenerBuilder::archive_raw_accounts).
+    /// Dropped by default.
+    async fn write_raw_account(&self, _raw: NewRawAccount) -> Result<()> {
+        Ok(())
+    }
+
+    /// Records that an account of the program was closed at `slot` (it arrived with zero
+    /// lamports or no data), so its stored rows stop looking current. Returns whether it had
+    /// any. Dropped by default.
+    async fn mark_closed(
+        &self,
+        _program_id: &Pubkey,
+        _account: &Pubkey,
+        _slot: u64,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// The program's checkpoint, see [`PollSink::save_checkpoint`]. `None` by default: the
+    /// listener then backfills the program in full on every start.
+    async fn checkpoint(&self, _program_id: &Pubkey) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Records that every update of the program up to `slot` was written, so the next start
+    /// only catches up on what changed since. Must not be stored before what was written
+    /// before it is: it may wait for that. Dropped by default.
+    async fn save_checkpoint(&self, _program_id: &Pubkey, _slot: u64) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How long [`PostgresSink::save_checkpoint`] waits for the writer to flush the polls queued
+/// before it.
+const CHECKPOINT_FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Writes to PostgreSQL: polls go through the batching writer task (see
+/// [`spawn_poll_writer`](crate::writer::spawn_poll_writer)), events are inserted directly.
+///
+/// Dropping the sink drops its writer handle; once every handle is gone the writer task
+/// flushes what is still queued and exits.
+pub struct PostgresSink {
+    pool: PgPool,
+    writer: PollWriter,
+    quotas: Option<Arc<StorageQuotas>>,
+    metrics: Option<Arc<Metrics>>,
+    clock: Clock,
+    /// Failed poll writes of each program when its checkpoint was last saved.
+    failed_at_checkpoint: Mutex<HashMap<Pubkey, u64>>,
+}
+
+impl PostgresSink {
+    pub fn new(pool: PgPool, writer: PollWriter) -> Self {
+        Self {
+            pool,
+            writer,
+            quotas: None,
+            metrics: None,
+            clock: Clock::system(),
+            failed_at_checkpoint: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Enforces storage quotas: updates of a table a program is over quota for are dropped
+    /// with `--quota-action stop`, and inserted events and archived updates are counted.
+    pub fn with_quotas(mut self, quotas: Arc<StorageQuotas>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Counts the lifecycle transitions of closed polls. The writer counts those of the polls
+    /// it writes.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Clock of the lifecycle of closed polls and of the checkpoints' wait for the writer,
+    /// instead of the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn admits(&self, table: QuotaTable, program: &[u8]) -> bool {
+        self.quotas
+            .as_ref()
+            .is_none_or(|quotas| quotas.admits(table, program))
+    }
+}
+
+#[async_trait]
+impl PollSink for PostgresSink {
+    async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+        if !self.admits(QuotaTable::Polls, &poll.program_id) {
+            return Ok(());
+        }
+        // When the channel is full this waits for room instead of dropping the update.
+        self.writer.send(poll).await
+    }
+
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+        // Inserted per program, so each program's quota counts its own new events.
+        let mut by_program: BTreeMap<Vec<u8>, Vec<NewEvent>> = BTreeMap::new();
+        for event in events {
+            if self.admits(QuotaTable::Events, &event.program_id) {
+                by_program
+                    .entry(event.program_id.clone())
+                    .or_default()
+                    .push(event);
+            }
+        }
+        let mut inserted = 0;
+        for (program, events) in by_program {
+            let pool = self.pool.clone();
+            let count =
+                tokio::task::spawn_blocking(move || record_events(&pool, &events)).await??;
+            if let Some(quotas) = &self.quotas {
+                quotas.record(QuotaTable::Events, &program, count);
+            }
+            inserted += count;
+        }
+        Ok(inserted)
+    }
+
+    async fn write_delegation(&self, delegation: NewDelegation) -> Result<()> {
+        if !self.admits(QuotaTable::Delegations, &delegation.program_id) {
+            return Ok(());
+        }
+        // Delegations are rare next to poll updates, so they skip the batching writer.
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || upsert_delegation(&pool, &delegation)).await??;
+        Ok(())
+    }
+
+    async fn write_idl_account(&self, account: NewIdlAccount) -> Result<()> {
+        if !self.admits(QuotaTable::IdlAccounts, &account.program_id) {
+            return Ok(());
+        }
+        // Like delegations, one upsert per account: only programs given an IDL produce them.
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || upsert_idl_account(&pool, &account)).await??;
+        Ok(())
+    }
+
+    async fn write_decode_failure(&self, failure: NewDecodeFailure) -> Result<()> {
+        if !self.admits(QuotaTable::DecodeFailures, &failure.program_id) {
+            return Ok(());
+        }
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || record_decode_failure(&pool, &failure)).await??;
+        Ok(())
+    }
+
+    async fn write_raw_account(&self, raw: NewRawAccount) -> Result<()> {
+        if !self.admits(QuotaTable::RawHistory, &raw.program_id) {
+            return Ok(());
+        }
+        let pool = self.pool.clone();
+        let program = raw.program_id.clone();
+        let archived =
+            tokio::task::spawn_blocking(move || archive_raw_account(&pool, &raw)).await??;
+        if let Some(quotas) = &self.quotas {
+            quotas.record(QuotaTable::RawHistory, &program, usize::from(archived));
+        }
+        Ok(())
+    }
+
+    async fn mark_closed(&self, program_id: &Pubkey, account: &Pubkey, slot: u64) -> Result<bool> {
+        // Straight to the database, like delegations: closures are rare. A poll update still
+        // queued for the writer is older, so the `last_slot` guard drops it when it's flushed.
+        let pool = self.pool.clone();
+        let (program, account) = (program_id.to_bytes(), account.to_bytes());
+        let now = self.clock.unix_now();
+        let closed = tokio::task::spawn_blocking(move || {
+            mark_account_closed(&pool, &program, &account, slot as i64, now)
+        })
+        .await??;
+        for (key, transition) in &closed.polls {
+            log_lifecycle_transition(key, transition, self.metrics.as_deref());
+        }
+        Ok(!closed.is_empty())
+    }
+
+    async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+        let pool = self.pool.clone();
+        let program = program_id.to_bytes();
+        let slot = tokio::task::spawn_blocking(move || get_checkpoint(&pool, &program)).await??;
+        Ok(slot.map(|slot| slot as u64))
+    }
+
+    async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+        // Delegations and IDL accounts are stored by the time their write returns; polls only
+        // once the writer flushed them. A poll of the program that failed to commit since its
+        // last checkpoint (or since the start) is lost: the checkpoint then stays where it was
+        // for the rest of the run, so the next start catches up on it. Failures are counted
+        // per program, so one program's bad poll doesn't hold back the others.
+        let stats = self.writer.stats();
+        let queued = stats.queued();
+        let clock = self.clock;
+        let deadline = clock.instant() + CHECKPOINT_FLUSH_TIMEOUT;
+        while stats.settled() < queued {
+            if clock.instant() >= deadline {
+                anyhow::bail!("the poll writer didn't flush in time");
+            }
+            clock.sleep(Duration::from_millis(20)).await;
+        }
+        let program = program_id.to_bytes();
+        let failed = stats.failed_for(&program);
+        let watermark = self
+            .failed_at_checkpoint
+            .lock()
+            .unwrap()
+            .get(program_id)
+            .copied()
+            .unwrap_or(0);
+        if failed > watermark {
+            anyhow::bail!("polls failed to commit since the last checkpoint");
+        }
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || save_checkpoint(&pool, &program, slot as i64))
+            .await??;
+        self.failed_at_checkpoint
+            .lock()
+            .unwrap()
+            .insert(*program_id, failed);
+        Ok(())
+    }
+}
+
+/// Never touches a database: polls are only logged by the listener, events are logged here.
+pub struct StdoutSink;
+
+#[async_trait]
+impl PollSink for StdoutSink {
+    async fn write_poll(&self, _poll: NewPoll) -> Result<()> {
+        Ok(())
+    }
+
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+        for event in &events {
+            info!(
+                program = %program_label(&event.program_id),
+                signature = %event.signature,
+                slot = event.slot,
+                event_type = %event.event_type,
+                payload = %event.payload,
+                "Event"
+            );
+        }
+        Ok(events.len())
+    }
+}
+
+/// Keeps everything in memory, e.g. to run the full decode path in tests without a database.
+#[derive(Default)]
+pub struct MemorySink {
+    polls: Mutex<Vec<NewPoll>>,
+    events: Mutex<Vec<NewEvent>>,
+    delegations: Mutex<Vec<NewDelegation>>,
+    idl_accounts: Mutex<Vec<NewIdlAccount>>,
+    decode_failures: Mutex<Vec<NewDecodeFailure>>,
+    raw_accounts: Mutex<Vec<NewRawAccount>>,
+    closed: Mutex<Vec<(Pubkey, Pubkey, u64)>>,
+    checkpoints: Mutex<HashMap<Pubkey, u64>>,
+}
+
+impl MemorySink {
+    /// Every poll written so far, in order (updates of the same poll included).
+    pub fn polls(&self) -> Vec<NewPoll> {
+        self.polls.lock().unwrap().clone()
+    }
+
+    /// Every event written so far, in order.
+    pub fn events(&self) -> Vec<NewEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Every delegation written so far, in order.
+    pub fn delegations(&self) -> Vec<NewDelegation> {
+        self.delegations.lock().unwrap().clone()
+    }
+
+    /// Every IDL-decoded account written so far, in order.
+    pub fn idl_accounts(&self) -> Vec<NewIdlAccount> {
+        self.idl_accounts.lock().unwrap().clone()
+    }
+
+    /// Every decode failure written so far, in order.
+    pub fn decode_failures(&self) -> Vec<NewDecodeFailure> {
+        self.decode_failures.lock().unwrap().clone()
+    }
+
+    /// Every raw account update archived so far, in order.
+    pub fn raw_accounts(&self) -> Vec<NewRawAccount> {
+        self.raw_accounts.lock().unwrap().clone()
+    }
+
+    /// Every account closure recorded so far, as `(program, account, slot)`, in order.
+    pub fn closed(&self) -> Vec<(Pubkey, Pubkey, u64)> {
+        self.closed.lock().unwrap().clone()
+    }
+
+    /// The checkpoint of every program saved so far.
+    pub fn checkpoints(&self) -> HashMap<Pubkey, u64> {
+        self.checkpoints.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl PollSink for MemorySink {
+    async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+        self.polls.lock().unwrap().push(poll);
+        Ok(())
+    }
+
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+        let count = events.len();
+        self.events.lock().unwrap().extend(events);
+        Ok(count)
+    }
+
+    async fn write_delegation(&self, delegation: NewDelegation) -> Result<()> {
+        self.delegations.lock().unwrap().push(delegation);
+        Ok(())
+    }
+
+    async fn write_idl_account(&self, account: NewIdlAccount) -> Result<()> {
+        self.idl_accounts.lock().unwrap().push(account);
+        Ok(())
+    }
+    async fn write_decode_failure(&self, failure: NewDecodeFailure) -> Result<()> {
+        self.decode_failures.lock().unwrap().push(failure);
+        Ok(())
+    }
+
+    async fn write_raw_account(&self, raw: NewRawAccount) -> Result<()> {
+        self.raw_accounts.lock().unwrap().push(raw);
+        Ok(())
+    }
+
+    async fn mark_closed(&self, program_id: &Pubkey, account: &Pubkey, slot: u64) -> Result<bool> {
+        self.closed
+            .lock()
+            .unwrap()
+            .push((*program_id, *account, slot));
+        Ok(true)
+    }
+
+    async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+        Ok(self.checkpoints.lock().unwrap().get(program_id).copied())
+    }
+
+    async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+        let mut checkpoints = self.checkpoints.lock().unwrap();
+        let checkpoint = checkpoints.entry(*program_id).or_default();
+        *checkpoint = (*checkpoint).max(slot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::{new_poll, test_pool};
+    use crate::writer::{spawn_poll_writer, WriterConfig};
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_failure_between_checkpoints_holds_the_checkpoint_back() {
+        let pool = test_pool();
+        let (writer, task) = spawn_poll_writer(
+            pool.clone(),
+            WriterConfig::default(),
+            Arc::new(Metrics::new().unwrap()),
+        );
+        let stats = writer.stats();
+        let sink = PostgresSink::new(pool, writer);
+        let (failing, healthy) = (
+            Pubkey::new_from_array([0x44; 32]),
+            Pubkey::new_from_array([0x45; 32]),
+        );
+
+        sink.write_poll(new_poll(failing.as_ref(), 1, 100))
+            .await
+            .unwrap();
+        sink.save_checkpoint(&failing, 100).await.unwrap();
+
+        // Fails to commit (Postgres rejects NUL characters) before the next checkpoint starts.
+        let mut bad = new_poll(failing.as_ref(), 2, 150);
+        bad.poll_name = "bad\0name".to_string();
+        sink.write_poll(bad).await.unwrap();
+        sink.write_poll(new_poll(healthy.as_ref(), 1, 150))
+            .await
+            .unwrap();
+        while stats.pending() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            (
+                stats.failed_for(failing.as_ref()),
+                stats.failed_for(healthy.as_ref())
+            ),
+            (1, 0)
+        );
+
+        assert!(sink.save_checkpoint(&failing, 200).await.is_err());
+        assert!(sink.save_checkpoint(&failing, 300).await.is_err());
+        assert_eq!(sink.checkpoint(&failing).await.unwrap(), Some(100));
+        // The other program's checkpoint still moves.
+        sink.save_checkpoint(&healthy, 200).await.unwrap();
+        assert_eq!(sink.checkpoint(&healthy).await.unwrap(), Some(200));
+
+        drop(sink);
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::{Slot, UnixTimestamp};
+
+use crate::clock::Instant;
+use crate::metrics::Metrics;
+
+/// Nominal slot duration on Solana clusters (400ms).
+/// Used whenever there isn't enough history to fit a real rate.
+pub const NOMINAL_SECS_PER_SLOT: f64 = 0.4;
+
+/// Plausible range for the fitted slot duration.
+/// Anything outside of this is treated as a degraded fit (e.g. a cluster halt where slots stop
+/// advancing but wallclock time keeps moving), and the nominal rate is used instead.
+const MIN_SECS_PER_SLOT: f64 = 0.2;
+const MAX_SECS_PER_SLOT: f64 = 2.0;
+
+/// How many samples the rolling fit keeps by default.
+const DEFAULT_MAX_SAMPLES: usize = 32;
+
+/// A single `(slot, block_time)` observation taken from the RPC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotSample {
+    pub slot: Slot,
+    pub block_time: UnixTimestamp,
+}
+
+/// An estimated value together with a +/- confidence bound in the same unit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate<T> {
+    pub value: T,
+    pub error: T,
+    /// `true` when the answer came from the nominal rate rather than a healthy fit.
+    pub degraded: bool,
+}
+
+/// Linear fit of `block_time = intercept + secs_per_slot * slot` over the recent samples.
+#[derive(Debug, Clone, Copy)]
+struct Fit {
+    secs_per_slot: f64,
+    /// Anchor point of the fit (the newest sample), used to keep the numbers small.
+    anchor: SlotSample,
+    /// Largest absolute residual (seconds) of the samples against the fit.
+    max_residual: f64,
+    degraded: bool,
+}
+
+/// Shared slot ↔ wallclock translation service.
+///
+/// Instead of every feature calling `get_block_time` on its own, a single `SlotClock`
+/// is fed periodic samples (see [`sample_into`]) and answers conversions locally.
+/// It is cheap to clone behind an `Arc` and safe to use from any task.
+pub struct SlotClock {
+    samples: Mutex<VecDeque<(SlotSample, Instant)>>,
+    max_samples: usize,
+}
+
+impl Default for SlotClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SAMPLES)
+    }
+}
+
+impl SlotClock {
+    /// Creates an empty clock keeping at most `max_samples` observations for the fit.
+    pub fn new(max_samples: usize) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(max_samples)),
+            max_samples: max_samples.max(2),
+        }
+    }
+
+    /// Adds a new observation, evicting the oldest one when the window is full.
+    ///
+    /// Samples that go backwards in slot are ignored (they'd come from a lagging RPC node).
+    pub fn record(&self, sample: SlotSample) {
+        let mut samples = self.samples.lock().unwrap();
+        if let Some((last, _)) = samples.back() {
+            if sample.slot < last.slot {
+                return;
+            }
+        }
+        if samples.len() == self.max_samples {
+            samples.pop_front();
+        }
+        samples.push_back((sample, Instant::now()));
+    }
+
+    /// Estimates the unix timestamp (seconds) at which `slot` was (or will be) produced.
+    pub fn estimate_time(&self, slot: Slot) -> Option<Estimate<UnixTimestamp>> {
+        let fit = self.fit()?;
+        let slot_delta = slot as f64 - fit.anchor.slot as f64;
+        let value = fit.anchor.block_time as f64 + slot_delta * fit.secs_per_slot;
+        let error = fit.error_secs(slot_delta);
+
+        Some(Estimate {
+            value: value.round() as UnixTimestamp,
+            error: error.ceil() as UnixTimestamp,
+            degraded: fit.degraded,
+        })
+    }
+
+    /// Estimates which slot was (or will be) produced at unix timestamp `time`.
+    pub fn estimate_slot(&self, time: UnixTimestamp) -> Option<Estimate<Slot>> {
+        let fit = self.fit()?;
+        let time_delta = (time - fit.anchor.block_time) as f64;
+        let slot_delta = time_delta / fit.secs_per_slot;
+        let value = (fit.anchor.slot as f64 + slot_delta).max(0.0);
+        let error = fit.error_secs(slot_delta) / fit.secs_per_slot;
+
+        Some(Estimate {
+            value: value.round() as Slot,
+            error: error.ceil() as Slot,
+            degraded: fit.degraded,
+        })
+    }
+
+    /// Difference between the fitted slot duration and the nominal 400ms, in milliseconds.
+    /// Positive values mean the cluster is currently slower than nominal.
+    pub fn drift_ms_per_slot(&self) -> Option<f64> {
+        let fit = self.fit()?;
+        Some((fit.secs_per_slot - NOMINAL_SECS_PER_SLOT) * 1000.0)
+    }
+
+    /// Time elapsed since the newest sample was recorded.
+    pub fn sample_age(&self) -> Option<Duration> {
+        let samples = self.samples.lock().unwrap();
+        samples.back().map(|(_, taken_at)| taken_at.elapsed())
+    }
+
+    /// Publishes the drift and the sample age to `metrics`; nothing before the first sample.
+    pub fn report(&self, metrics: &Metrics) {
+        if let Some(drift) = self.drift_ms_per_slot() {
+            metrics.slot_clock_drift_ms.set(drift);
+        }
+        if let Some(age) = self.sample_age() {
+            metrics.slot_clock_sample_age_seconds.set(age.as_secs_f64());
+        }
+    }
+
+    /// Computes a least-squares fit over the current window.
+    fn fit(&self) -> Option<Fit> {
+        let samples = self.samples.lock().unwrap();
+        let (anchor, _) = *samples.back()?;
+
+        // Work relative to the anchor so large slot numbers don't lose precision.
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|(s, _)| {
+                (
+                    s.slot as f64 - anchor.slot as f64,
+                    (s.block_time - anchor.block_time) as f64,
+                )
+            })
+            .collect();
+
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let var_x = points
+            .iter()
+            .map(|(x, _)| (x - mean_x).powi(2))
+            .sum::<f64>();
+        let cov_xy = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum::<f64>();
+
+        // A single sample, or samples that all share the same slot (cluster halt),
+        // can't produce a rate. Fall back to the nominal one.
+        let fitted = if var_x > 0.0 {
+            cov_xy / var_x
+        } else {
+            f64::NAN
+        };
+        let degraded = !(MIN_SECS_PER_SLOT..=MAX_SECS_PER_SLOT).contains(&fitted);
+        let secs_per_slot = if degraded {
+            NOMINAL_SECS_PER_SLOT
+        } else {
+            fitted
+        };
+
+        // Residuals are measured against a line going through the anchor with the chosen rate.
+        let max_residual = points
+            .iter()
+            .map(|(x, y)| (y - x * secs_per_slot).abs())
+            .fold(0.0, f64::max);
+
+        Some(Fit {
+            secs_per_slot,
+            anchor,
+            max_residual,
+            degraded,
+        })
+    }
+}
+
+impl Fit {
+    /// Confidence bound (seconds) for a point `slot_delta` slots away from the anchor.
+    ///
+    /// Starts at the worst residual plus one second of `block_time` granularity, and grows
+    /// with the distance from the anchor since slot times drift over long ranges.
+    /// Degraded fits get a much wider bound.
+    fn error_secs(&self, slot_delta: f64) -> f64 {
+        let base = self.max_residual + 1.0;
+        let drift_factor = if self.degraded { 0.25 } else { 0.05 };
+        base + slot_delta.abs() * self.secs_per_slot * drift_factor
+    }
+}
+
+/// Samples `(slot, block_time)` from the RPC once and feeds it into `clock`. Run periodically by
+/// the listener's job scheduler.
+///
+/// Fails e.g. when the block time isn't available yet; the next run simply tries again.
+pub async fn sample_into(clock: &SlotClock, rpc_client: &RpcClient) -> anyhow::Result<()> {
+    clock.record(sample_once(rpc_client).await?);
+    Ok(())
+}
+
+/// Takes a single `(slot, block_time)` sample from the RPC.
+async fn sample_once(rpc_client: &RpcClient) -> anyhow::Result<SlotSample> {
+    let slot = rpc_client.get_slot().await?;
+    let block_time = rpc_client.get_block_time(slot).await?;
+    Ok(SlotSample { slot, block_time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+
+    fn sample(slot: Slot, block_time: UnixTimestamp) -> SlotSample {
+        SlotSample { slot, block_time }
+    }
+
+    #[test]
+    fn empty_clock_has_no_estimate() {
+        let clock = SlotClock::default();
+        assert!(clock.estimate_time(100).is_none());
+        assert!(clock.estimate_slot(100).is_none());
+        assert!(clock.drift_ms_per_slot().is_none());
+        assert!(clock.sample_age().is_none());
+    }
+
+    #[test]
+    fn single_sample_uses_nominal_rate() {
+        let clock = SlotClock::default();
+        clock.record(sample(1_000, 10_000));
+
+        let time = clock.estimate_time(1_010).unwrap();
+        assert_eq!(time.value, 10_004);
+        assert!(time.degraded);
+        assert_eq!(clock.estimate_slot(10_004).unwrap().value, 1_010);
+    }
+
+    #[test]
+    fn fits_the_observed_rate() {
+        let clock = SlotClock::default();
+        // 500ms slots, slower than nominal.
+        for i in 0..10 {
+            clock.record(sample(1_000 + i * 100, 10_000 + i as i64 * 50));
+        }
+
+        let time = clock.estimate_time(2_900).unwrap();
+        assert!(!time.degraded);
+        assert_eq!(time.value, 10_950);
+        assert_eq!(clock.estimate_slot(10_950).unwrap().value, 2_900);
+        assert!((clock.drift_ms_per_slot().unwrap() - 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ignores_samples_going_backwards() {
+        let clock = SlotClock::default();
+        clock.record(sample(1_000, 10_000));
+        clock.record(sample(1_100, 10_040));
+        clock.record(sample(900, 0));
+
+        assert_eq!(clock.estimate_time(1_100).unwrap().value, 10_040);
+    }
+
+    #[test]
+    fn halted_cluster_is_degraded() {
+        let clock = SlotClock::default();
+        // Slots stop advancing while wallclock time moves on.
+        clock.record(sample(1_000, 10_000));
+        clock.record(sample(1_000, 10_060));
+
+        let time = clock.estimate_time(1_000).unwrap();
+        assert!(time.degraded);
+        assert!(time.error >= 60);
+    }
+
+    #[test]
+    fn evicts_the_oldest_samples() {
+        let clock = SlotClock::new(2);
+        // An outlier that would skew the fit once it's out of the window.
+        clock.record(sample(0, 0));
+        clock.record(sample(1_000, 10_000));
+        clock.record(sample(1_100, 10_050));
+
+        let time = clock.estimate_time(1_200).unwrap();
+        assert!(!time.degraded);
+        assert_eq!(time.value, 10_100);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_drift_and_sample_age() {
+        let clock = SlotClock::default();
+        let metrics = Metrics::new().unwrap();
+        clock.report(&metrics);
+        assert_eq!(metrics.slot_clock_sample_age_seconds.get(), 0.0);
+
+        clock.record(sample(1_000, 10_000));
+        clock.record(sample(1_100, 10_050));
+        Clock::system().sleep(Duration::from_secs(30)).await;
+        clock.report(&metrics);
+        assert!((metrics.slot_clock_drift_ms.get() - 100.0).abs() < 1e-6);
+        assert_eq!(metrics.slot_clock_sample_age_seconds.get(), 30.0);
+    }
+
+    #[test]
+    fn error_grows_with_distance() {
+        let clock = SlotClock::default();
+        for i in 0..5 {
+            clock.record(sample(1_000 + i * 10, 10_000 + i as i64 * 4));
+        }
+
+        let near = clock.estimate_time(1_050).unwrap();
+        let far = clock.estimate_time(100_000).unwrap();
+        assert!(far.error > near.error);
+    }
+}
@@ -0,0 +1,667 @@
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use super::error::DecodeError;
+
+/// An account whose body (the bytes after the 8-byte discriminator) can be decoded from
+/// Anchor/Borsh layout.
+///
+/// Implementors only describe their fields in order through an [`AnchorReader`];
+/// bounds checks, string prefixes and error reporting are shared by every account type.
+pub trait AnchorDecode: Sized {
+    /// Reads the fields in on-chain order.
+    fn decode(reader: &mut AnchorReader<'_>) -> Result<Self, DecodeError>;
+
+    /// Decodes an account body. Trailing bytes (Anchor allocates `#[max_len]` space) are ignored.
+    fn try_from_anchor_bytes(data: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(&mut AnchorReader::new(data))
+    }
+
+    /// Checks what the layout alone can't express, like `#[max_len]`. The field reader does
+    /// it while reading; [`decode_borsh`] calls it after deserializing.
+    fn validate(&self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+}
+
+/// Decodes an account body with its derived `BorshDeserialize`. Trailing bytes are ignored.
+///
+/// Borsh errors don't say which field is wrong, so when it fails the body is read again with
+/// the hand-written [`AnchorDecode`] reader, whose error does. With the `manual-decode` feature
+/// only the reader is used.
+pub fn decode_borsh<T: BorshDeserialize + AnchorDecode>(data: &[u8]) -> Result<T, DecodeError> {
+    if cfg!(feature = "manual-decode") {
+        return T::try_from_anchor_bytes(data);
+    }
+    match T::deserialize(&mut &data[..]) {
+        Ok(value) => {
+            value.validate()?;
+            Ok(value)
+        }
+        Err(_) => T::try_from_anchor_bytes(data),
+    }
+}
+
+/// Counterpart of [`AnchorDecode`]: writes an account body in the same layout, so that
+/// `T::try_from_anchor_bytes(&x.encode_anchor_bytes()) == x`.
+pub trait AnchorEncode {
+    /// Writes the fields in on-chain order.
+    fn encode(&self, writer: &mut AnchorWriter);
+
+    /// Encodes the account body (without discriminator and without `#[max_len]` padding).
+    fn encode_anchor_bytes(&self) -> Vec<u8> {
+        let mut writer = AnchorWriter::default();
+        self.encode(&mut writer);
+        writer.into_bytes()
+    }
+}
+
+/// Cursor over an account body. Every read checks bounds against the whole buffer, so
+/// `Truncated { needed, got }` reports absolute positions.
+pub struct AnchorReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> AnchorReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// Number of bytes consumed so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the next `len` bytes, or `Truncated` if the buffer is too short.
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.offset.saturating_add(len);
+        let bytes = self
+            .data
+            .get(self.offset..end)
+            .ok_or(DecodeError::Truncated {
+                needed: end,
+                got: self.data.len(),
+            })?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// Number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    /// Returns the next `len` bytes as they are.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        self.take(len)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, DecodeError> {
+        let bytes = self.take(8)?;
+        Ok(i64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn read_pubkey(&mut self) -> Result<Pubkey, DecodeError> {
+        let bytes = self.take(32)?;
+        Ok(Pubkey::new_from_array(bytes.try_into().unwrap()))
+    }
+
+    /// Reads a Borsh `Option<i64>` (a 0/1 tag byte, followed by the value when 1).
+    pub fn read_option_i64(&mut self, field: &'static str) -> Result<Option<i64>, DecodeError> {
+        match self.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.read_i64()?)),
+            tag => Err(DecodeError::InvalidOptionTag { field, tag }),
+        }
+    }
+
+    /// Reads a Borsh/Anchor string (u32 little-endian length prefix + UTF-8 bytes).
+    ///
+    /// `max_len` is the on-chain `#[max_len]` in bytes; a larger prefix means the buffer isn't
+    /// what we think it is, and is reported before trying to read that many bytes.
+    pub fn read_string(
+        &mut self,
+        field: &'static str,
+        max_len: usize,
+    ) -> Result<String, DecodeError> {
+        let len = self.read_u32()? as usize;
+        if len > max_len {
+            return Err(DecodeError::StringTooLong {
+                field,
+                len,
+                max: max_len,
+            });
+        }
+
+        let string_bytes = self.take(len)?;
+        let s = std::str::from_utf8(string_bytes)
+            .map_err(|_| DecodeError::InvalidUtf8 { field })?
+            .to_string();
+        Ok(s)
+    }
+}
+
+/// Growable buffer writing fields in Anchor/Borsh layout, see [`AnchorEncode`].
+#[derive(Default)]
+pub struct AnchorWriter {
+    bytes: Vec<u8>,
+}
+
+impl AnchorWriter {
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_option_i64(&mut self, value: Option<i64>) {
+        match value {
+            Some(value) => {
+                self.write_u8(1);
+                self.write_i64(value);
+            }
+            None => self.write_u8(0),
+        }
+    }
+
+    pub fn write_pubkey(&mut self, value: &Pubkey) {
+        self.bytes.extend_from_slice(value.as_ref());
+    }
+
+    /// Writes a u32 length prefix followed by the UTF-8 bytes.
+    /// `#[max_len]` isn't enforced here; the decoder rejects oversized strings.
+    pub fn write_string(&mut self, value: &str) {
+        self.write_u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::decode::{
+        decode_delegation, decode_poll, match_voting_account_type, VotingAccountType,
+        POLL_DISCRIMINATOR, POOL_CANDIDATE_DISCRIMINATOR, VOTE_DISCRIMINATOR,
+    };
+    use crate::idl_decode::IdlDecoder;
+    use crate::state::delegation::Delegation;
+    use crate::state::pool::{Poll, POLL_DESCRIPTION_MAX_LEN, POLL_NAME_MAX_LEN};
+
+    /// `#[max_len]` of `candidate_name` in the test IDL.
+    const CANDIDATE_NAME_MAX_LEN: usize = 32;
+
+    fn pubkey() -> impl Strategy<Value = Pubkey> {
+        any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+    }
+
+    /// Strings of at most `max` bytes, empty and multi-byte UTF-8 ones included.
+    fn string_up_to(max: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(any::<char>(), 0..=max).prop_map(move |chars| {
+            let mut s = String::new();
+            for c in chars {
+                if s.len() + c.len_utf8() > max {
+                    break;
+                }
+                s.push(c);
+            }
+            s
+        })
+    }
+
+    prop_compose! {
+        fn poll()(
+            poll_id in any::<u64>(),
+            poll_owner in pubkey(),
+            poll_name in string_up_to(POLL_NAME_MAX_LEN),
+            poll_description in string_up_to(POLL_DESCRIPTION_MAX_LEN),
+            poll_start in any::<u64>(),
+            poll_end in any::<u64>(),
+            candidate_amount in any::<u64>(),
+            candidate_winner in pubkey(),
+        ) -> Poll {
+            Poll {
+                poll_id,
+                poll_owner,
+                poll_name,
+                poll_description,
+                poll_start,
+                poll_end,
+                candidate_amount,
+                candidate_winner,
+            }
+        }
+    }
+
+    prop_compose! {
+        fn delegation()(
+            delegator in pubkey(),
+            delegate in pubkey(),
+            poll_id in any::<u64>(),
+            expiry in any::<Option<i64>>(),
+        ) -> Delegation {
+            Delegation { delegator, delegate, poll_id, expiry }
+        }
+    }
+
+    /// An IDL describing the candidate and vote accounts, which have no struct of their own.
+    fn voting_idl() -> IdlDecoder {
+        IdlDecoder::new(&json!({
+            "accounts": [
+                { "name": "Candidate", "discriminator": POOL_CANDIDATE_DISCRIMINATOR },
+                { "name": "Vote", "discriminator": VOTE_DISCRIMINATOR },
+            ],
+            "types": [
+                { "name": "Candidate", "type": { "kind": "struct", "fields": [
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "candidate_name", "type": "string" },
+                    { "name": "candidate_votes", "type": "u64" },
+                ] } },
+                { "name": "Vote", "type": { "kind": "struct", "fields": [
+                    { "name": "voter", "type": "pubkey" },
+                    { "name": "poll_id", "type": "u64" },
+                    { "name": "candidate_name", "type": "string" },
+                ] } },
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// A whole account: discriminator, body, then `padding` zero bytes of `#[max_len]` space.
+    fn account(discriminator: [u8; 8], body: &[u8], padding: usize) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(body);
+        data.resize(data.len() + padding, 0);
+        data
+    }
+
+    /// Decodes through the IDL and returns the fields, or `None` for an unknown discriminator.
+    fn idl_fields(decoder: &IdlDecoder, data: &[u8]) -> Option<anyhow::Result<Value>> {
+        decoder
+            .decode(data)
+            .map(|decoded| decoded.map(|decoded| decoded.fields))
+    }
+
+    /// Decodes a body both ways [`decode_borsh`] can: with the derived `BorshDeserialize` (plus
+    /// [`AnchorDecode::validate`]) as by default, and with the reader alone as with
+    /// `manual-decode`.
+    fn both_ways<T: BorshDeserialize + AnchorDecode>(body: &[u8]) -> (Option<T>, Option<T>) {
+        let borsh = T::deserialize(&mut &body[..])
+            .ok()
+            .filter(|value| value.validate().is_ok());
+        (borsh, T::try_from_anchor_bytes(body).ok())
+    }
+
+    proptest! {
+        #[test]
+        fn poll_round_trips(poll in poll(), padding in 0..64usize) {
+            let body = poll.encode_anchor_bytes();
+            prop_assert_eq!(&Poll::try_from_anchor_bytes(&body).unwrap(), &poll);
+            // The whole account, as the listener receives it.
+            let data = account(POLL_DISCRIMINATOR, &body, padding);
+            prop_assert_eq!(match_voting_account_type(&data), VotingAccountType::Poll);
+            prop_assert_eq!(decode_poll(&data).unwrap(), poll);
+        }
+
+        #[test]
+        fn delegation_round_trips(delegation in delegation(), padding in 0..64usize) {
+            let body = delegation.encode_anchor_bytes();
+            prop_assert_eq!(&Delegation::try_from_anchor_bytes(&body).unwrap(), &delegation);
+            prop_assert_eq!(decode_delegation(&account([0; 8], &body, padding)).unwrap(), delegation);
+        }
+
+        #[test]
+        fn candidate_round_trips(
+            poll_id in any::<u64>(),
+            name in string_up_to(CANDIDATE_NAME_MAX_LEN),
+            votes in any::<u64>(),
+            padding in 0..64usize,
+        ) {
+            let mut writer = AnchorWriter::default();
+            writer.write_u64(poll_id);
+            writer.write_string(&name);
+            writer.write_u64(votes);
+            let data = account(POOL_CANDIDATE_DISCRIMINATOR, &writer.into_bytes(), padding);
+
+            prop_assert_eq!(match_voting_account_type(&data), VotingAccountType::Candidate);
+            prop_assert_eq!(
+                idl_fields(&voting_idl(), &data).unwrap().unwrap(),
+                json!({ "poll_id": poll_id, "candidate_name": name, "candidate_votes": votes })
+            );
+        }
+
+        #[test]
+        fn vote_round_trips(
+            voter in pubkey(),
+            poll_id in any::<u64>(),
+            name in string_up_to(CANDIDATE_NAME_MAX_LEN),
+            padding in 0..64usize,
+        ) {
+            let mut writer = AnchorWriter::default();
+            writer.write_pubkey(&voter);
+            writer.write_u64(poll_id);
+            writer.write_string(&name);
+            let data = account(VOTE_DISCRIMINATOR, &writer.into_bytes(), padding);
+
+            prop_assert_eq!(match_voting_account_type(&data), VotingAccountType::Vote);
+            prop_assert_eq!(
+                idl_fields(&voting_idl(), &data).unwrap().unwrap(),
+                json!({ "voter": voter.to_string(), "poll_id": poll_id, "candidate_name": name })
+            );
+        }
+
+        #[test]
+        fn truncated_polls_never_decode(poll in poll()) {
+            let body = poll.encode_anchor_bytes();
+            for len in 0..body.len() {
+                let is_truncated = matches!(
+                    Poll::try_from_anchor_bytes(&body[..len]),
+                    Err(DecodeError::Truncated { got, .. }) if got == len
+                );
+                prop_assert!(is_truncated, "decoded {} of {} bytes", len, body.len());
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn polls_decode_the_same_both_ways(
+            poll in poll(),
+            padding in 0..64usize,
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let mut body = poll.encode_anchor_bytes();
+            let truncated = body[..cut.index(body.len())].to_vec();
+            body.resize(body.len() + padding, 0);
+
+            prop_assert_eq!(both_ways::<Poll>(&body), (Some(poll.clone()), Some(poll)));
+            prop_assert_eq!(both_ways::<Poll>(&truncated), (None, None));
+        }
+
+        #[test]
+        fn delegations_decode_the_same_both_ways(
+            delegation in delegation(),
+            tag in 2..=u8::MAX,
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let body = delegation.encode_anchor_bytes();
+            let (borsh, manual) = both_ways::<Delegation>(&body);
+            prop_assert_eq!(borsh.as_ref(), Some(&delegation));
+            prop_assert_eq!(manual.as_ref(), Some(&delegation));
+            let truncated = &body[..cut.index(body.len())];
+            prop_assert_eq!(both_ways::<Delegation>(truncated), (None, None));
+
+            // The expiry's option tag follows the two pubkeys and the poll id.
+            let mut bad_tag = body.clone();
+            bad_tag[72] = tag;
+            prop_assert_eq!(both_ways::<Delegation>(&bad_tag), (None, None));
+        }
+
+        #[test]
+        fn garbage_decodes_the_same_both_ways(body in prop::collection::vec(any::<u8>(), 0..400)) {
+            let (borsh, manual) = both_ways::<Poll>(&body);
+            prop_assert_eq!(borsh, manual);
+            let (borsh, manual) = both_ways::<Delegation>(&body);
+            prop_assert_eq!(borsh, manual);
+        }
+    }
+
+    /// A poll whose strings are exactly at their `#[max_len]`.
+    fn longest_poll() -> Poll {
+        Poll {
+            poll_id: 7,
+            poll_owner: Pubkey::new_from_array([1; 32]),
+            // 2-byte characters, so the limit is in bytes and not in characters.
+            poll_name: "é".repeat(POLL_NAME_MAX_LEN / 2),
+            poll_description: "d".repeat(POLL_DESCRIPTION_MAX_LEN),
+            poll_start: 1,
+            poll_end: 2,
+            candidate_amount: 3,
+            candidate_winner: Pubkey::new_from_array([2; 32]),
+        }
+    }
+
+    #[test]
+    fn strings_at_max_len_decode() {
+        let poll = longest_poll();
+        let data = account(POLL_DISCRIMINATOR, &poll.encode_anchor_bytes(), 0);
+        assert_eq!(decode_poll(&data), Ok(poll));
+    }
+
+    #[test]
+    fn strings_over_max_len_are_rejected() {
+        let poll = Poll {
+            poll_name: "n".repeat(POLL_NAME_MAX_LEN + 1),
+            ..longest_poll()
+        };
+        let data = account(POLL_DISCRIMINATOR, &poll.encode_anchor_bytes(), 0);
+        assert_eq!(
+            decode_poll(&data),
+            Err(DecodeError::StringTooLong {
+                field: "poll_name",
+                len: POLL_NAME_MAX_LEN + 1,
+                max: POLL_NAME_MAX_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn max_len_is_enforced_both_ways() {
+        let poll = longest_poll();
+        let body = poll.encode_anchor_bytes();
+        assert_eq!(both_ways::<Poll>(&body), (Some(poll.clone()), Some(poll)));
+
+        for too_long in [
+            Poll {
+                poll_name: "n".repeat(POLL_NAME_MAX_LEN + 1),
+                ..longest_poll()
+            },
+            Poll {
+                poll_description: "d".repeat(POLL_DESCRIPTION_MAX_LEN + 1),
+                ..longest_poll()
+            },
+        ] {
+            let body = too_long.encode_anchor_bytes();
+            assert_eq!(both_ways::<Poll>(&body), (None, None));
+        }
+
+        // Invalid UTF-8 in the name.
+        let mut body = longest_poll().encode_anchor_bytes();
+        body[44] = 0xff;
+        assert_eq!(both_ways::<Poll>(&body), (None, None));
+    }
+
+    #[test]
+    fn truncated_at_every_field_boundary() {
+        let poll = longest_poll();
+        let body = poll.encode_anchor_bytes();
+        let name_end = 8 + 32 + 4 + POLL_NAME_MAX_LEN;
+        let description_end = name_end + 4 + POLL_DESCRIPTION_MAX_LEN;
+        // After poll_id, poll_owner, the name's prefix, the name, the description's prefix, the
+        // description, the three u64s, and one byte short of candidate_winner.
+        for (len, needed) in [
+            (8, 40),
+            (40, 44),
+            (44, name_end),
+            (name_end, name_end + 4),
+            (name_end + 4, description_end),
+            (description_end, description_end + 8),
+            (description_end + 8, description_end + 16),
+            (description_end + 16, description_end + 24),
+            (description_end + 24, description_end + 56),
+            (body.len() - 1, body.len()),
+        ] {
+            assert_eq!(
+                Poll::try_from_anchor_bytes(&body[..len]),
+                Err(DecodeError::Truncated { needed, got: len }),
+                "truncated to {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn length_prefix_beyond_the_buffer_is_truncated() {
+        let mut writer = AnchorWriter::default();
+        writer.write_u64(7);
+        writer.write_pubkey(&Pubkey::new_from_array([1; 32]));
+        // Within `#[max_len]`, but only 3 bytes follow.
+        writer.write_u32(20);
+        writer.write_u8(b'a');
+        writer.write_u8(b'b');
+        writer.write_u8(b'c');
+        let body = writer.into_bytes();
+
+        assert_eq!(
+            Poll::try_from_anchor_bytes(&body),
+            Err(DecodeError::Truncated {
+                needed: 64,
+                got: 47
+            })
+        );
+    }
+
+    #[test]
+    fn short_accounts_and_bad_discriminators() {
+        assert_eq!(
+            decode_poll(&[1, 2, 3]),
+            Err(DecodeError::Truncated { needed: 8, got: 3 })
+        );
+        assert_eq!(
+            decode_delegation(&[]),
+            Err(DecodeError::Truncated { needed: 8, got: 0 })
+        );
+        assert_eq!(
+            match_voting_account_type(&[1, 2, 3]),
+            VotingAccountType::Unknown
+        );
+
+        let poll = longest_poll().encode_anchor_bytes();
+        let mut bad = POLL_DISCRIMINATOR;
+        bad[0] ^= 0xff;
+        assert_eq!(
+            match_voting_account_type(&account(bad, &poll, 0)),
+            VotingAccountType::Unknown
+        );
+        assert!(idl_fields(&voting_idl(), &account(bad, &poll, 0)).is_none());
+        // A poll body under a candidate discriminator fails to decode instead of yielding
+        // garbage fields.
+        assert!(idl_fields(
+            &voting_idl(),
+            &account(POOL_CANDIDATE_DISCRIMINATOR, &[1; 8], 0)
+        )
+        .unwrap()
+        .is_err());
+    }
+
+    #[test]
+    fn reads_fields_in_order() {
+        let mut writer = AnchorWriter::default();
+        writer.write_u8(7);
+        writer.write_u64(42);
+        writer.write_i64(-1);
+        writer.write_string("hello");
+        writer.write_option_i64(Some(9));
+        writer.write_option_i64(None);
+        let bytes = writer.into_bytes();
+
+        let mut reader = AnchorReader::new(&bytes);
+        assert_eq!(reader.read_u8(), Ok(7));
+        assert_eq!(reader.read_u64(), Ok(42));
+        assert_eq!(reader.read_i64(), Ok(-1));
+        assert_eq!(reader.read_string("s", 8), Ok("hello".to_string()));
+        assert_eq!(reader.read_option_i64("o"), Ok(Some(9)));
+        assert_eq!(reader.read_option_i64("o"), Ok(None));
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(reader.offset(), bytes.len());
+    }
+
+    #[test]
+    fn truncated_reports_absolute_positions() {
+        let bytes = [0u8; 10];
+        let mut reader = AnchorReader::new(&bytes);
+        reader.read_u64().unwrap();
+
+        assert_eq!(
+            reader.read_u64(),
+            Err(DecodeError::Truncated {
+                needed: 16,
+                got: 10
+            })
+        );
+        // A failed read consumes nothing.
+        assert_eq!(reader.offset(), 8);
+    }
+
+    #[test]
+    fn string_too_long_is_reported_before_reading() {
+        let mut writer = AnchorWriter::default();
+        writer.write_u32(1_000_000);
+        let bytes = writer.into_bytes();
+
+        assert_eq!(
+            AnchorReader::new(&bytes).read_string("poll_name", 64),
+            Err(DecodeError::StringTooLong {
+                field: "poll_name",
+                len: 1_000_000,
+                max: 64
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_names_the_field() {
+        let mut writer = AnchorWriter::default();
+        writer.write_u32(2);
+        writer.write_u8(0xc3);
+        writer.write_u8(0x28);
+        let bytes = writer.into_bytes();
+
+        assert_eq!(
+            AnchorReader::new(&bytes).read_string("poll_name", 64),
+            Err(DecodeError::InvalidUtf8 { field: "poll_name" })
+        );
+    }
+
+    #[test]
+    fn invalid_option_tag_names_the_field() {
+        assert_eq!(
+            AnchorReader::new(&[2]).read_option_i64("expiry"),
+            Err(DecodeError::InvalidOptionTag {
+                field: "expiry",
+                tag: 2
+            })
+        );
+    }
+}
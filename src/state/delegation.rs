@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+
+use borsh::BorshDeserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use super::anchor::{AnchorDecode, AnchorEncode, AnchorReader, AnchorWriter};
+use super::error::DecodeError;
+
+/// Longest delegation chain followed by [`resolve_chain`] by default.
+pub const DEFAULT_MAX_CHAIN_DEPTH: usize = 16;
+
+/// A wallet handing its vote on one poll to another wallet (program v3). Fields are in
+/// on-chain order, for the derived `BorshDeserialize`.
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize)]
+pub struct Delegation {
+    pub delegator: Pubkey,
+    pub delegate: Pubkey,
+    pub poll_id: u64,
+    /// Unix timestamp after which the delegation no longer applies; `None` never expires.
+    pub expiry: Option<i64>,
+}
+
+impl AnchorDecode for Delegation {
+    fn decode(reader: &mut AnchorReader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            delegator: reader.read_pubkey()?,
+            delegate: reader.read_pubkey()?,
+            poll_id: reader.read_u64()?,
+            expiry: reader.read_option_i64("expiry")?,
+        })
+    }
+}
+
+impl AnchorEncode for Delegation {
+    fn encode(&self, writer: &mut AnchorWriter) {
+        writer.write_pubkey(&self.delegator);
+        writer.write_pubkey(&self.delegate);
+        writer.write_u64(self.poll_id);
+        writer.write_option_i64(self.expiry);
+    }
+}
+
+/// Where a wallet's vote on a poll ends up, see [`resolve_chain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelegationChain {
+    /// The wallet looked up, followed by each successive delegate.
+    pub wallets: Vec<Pubkey>,
+    /// The chain leads back to a wallet already in it; `wallets` stops before repeating it.
+    pub cycle: bool,
+    /// The chain was cut at the maximum depth.
+    pub truncated: bool,
+}
+
+impl DelegationChain {
+    /// The wallet expected to cast the vote: the end of the chain, or `None` when the chain
+    /// doesn't end (cycle or cut), in which case nobody can vote on behalf of the others.
+    pub fn effective_voter(&self) -> Option<&Pubkey> {
+        if self.cycle || self.truncated {
+            None
+        } else {
+            self.wallets.last()
+        }
+    }
+}
+
+/// Follows the delegations of one poll from `wallet`, at most `max_depth` hops.
+///
+/// `delegations` maps a delegator to its delegate; only delegations active at the time of the
+/// lookup should be included. A wallet that didn't delegate resolves to itself.
+pub fn resolve_chain(
+    wallet: Pubkey,
+    delegations: &HashMap<Pubkey, Pubkey>,
+    max_depth: usize,
+) -> DelegationChain {
+    let mut wallets = vec![wallet];
+    let mut seen = HashSet::from([wallet]);
+    let mut current = wallet;
+
+    while let Some(next) = delegations.get(&current) {
+        if !seen.insert(*next) {
+            return DelegationChain {
+                wallets,
+                cycle: true,
+                truncated: false,
+            };
+        }
+        if wallets.len() > max_depth {
+            return DelegationChain {
+                wallets,
+                cycle: false,
+                truncated: true,
+            };
+        }
+        wallets.push(*next);
+        current = *next;
+    }
+
+    DelegationChain {
+        wallets,
+        cycle: false,
+        truncated: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode_delegation, DELEGATION_DISCRIMINATOR};
+
+    fn wallet(n: u8) -> Pubkey {
+        Pubkey::new_from_array([n; 32])
+    }
+
+    /// `(delegator, delegate)` pairs as the map [`resolve_chain`] takes.
+    fn edges(pairs: &[(u8, u8)]) -> HashMap<Pubkey, Pubkey> {
+        pairs
+            .iter()
+            .map(|(from, to)| (wallet(*from), wallet(*to)))
+            .collect()
+    }
+
+    fn wallets(chain: &DelegationChain) -> Vec<u8> {
+        chain.wallets.iter().map(|w| w.to_bytes()[0]).collect()
+    }
+
+    #[test]
+    fn decodes_a_fixed_account() {
+        let mut data = DELEGATION_DISCRIMINATOR.to_vec();
+        data.extend([1; 32]);
+        data.extend([2; 32]);
+        data.extend(7u64.to_le_bytes());
+        let never = data.clone();
+        data.push(1);
+        data.extend(1_700_000_000i64.to_le_bytes());
+
+        let expiring = decode_delegation(&data).unwrap();
+        assert_eq!(
+            expiring,
+            Delegation {
+                delegator: wallet(1),
+                delegate: wallet(2),
+                poll_id: 7,
+                expiry: Some(1_700_000_000),
+            }
+        );
+        let mut never = never;
+        never.push(0);
+        assert_eq!(decode_delegation(&never).unwrap().expiry, None);
+
+        // The option tag must be 0 or 1.
+        let last = never.len() - 1;
+        never[last] = 2;
+        assert!(decode_delegation(&never).is_err());
+    }
+
+    #[test]
+    fn a_wallet_without_delegation_votes_itself() {
+        let chain = resolve_chain(wallet(1), &edges(&[(2, 3)]), DEFAULT_MAX_CHAIN_DEPTH);
+        assert_eq!(wallets(&chain), [1]);
+        assert_eq!(chain.effective_voter(), Some(&wallet(1)));
+    }
+
+    #[test]
+    fn follows_the_chain_to_its_end() {
+        let chain = resolve_chain(
+            wallet(1),
+            &edges(&[(1, 2), (2, 3), (4, 1)]),
+            DEFAULT_MAX_CHAIN_DEPTH,
+        );
+        assert_eq!(wallets(&chain), [1, 2, 3]);
+        assert!(!chain.cycle && !chain.truncated);
+        assert_eq!(chain.effective_voter(), Some(&wallet(3)));
+    }
+
+    #[test]
+    fn cycles_have_no_effective_voter() {
+        let chain = resolve_chain(
+            wallet(1),
+            &edges(&[(1, 2), (2, 3), (3, 2)]),
+            DEFAULT_MAX_CHAIN_DEPTH,
+        );
+        assert_eq!(wallets(&chain), [1, 2, 3]);
+        assert!(chain.cycle);
+        assert_eq!(chain.effective_voter(), None);
+
+        let to_itself = resolve_chain(wallet(1), &edges(&[(1, 1)]), DEFAULT_MAX_CHAIN_DEPTH);
+        assert_eq!(wallets(&to_itself), [1]);
+        assert!(to_itself.cycle);
+    }
+
+    #[test]
+    fn long_chains_are_cut_at_the_maximum_depth() {
+        let pairs = [(1, 2), (2, 3), (3, 4)];
+        let exact = resolve_chain(wallet(1), &edges(&pairs), 3);
+        assert_eq!(wallets(&exact), [1, 2, 3, 4]);
+        assert!(!exact.truncated);
+
+        let cut = resolve_chain(wallet(1), &edges(&pairs), 2);
+        assert_eq!(wallets(&cut), [1, 2, 3]);
+        assert!(cut.truncated && !cut.cycle);
+        assert_eq!(cut.effective_voter(), None);
+    }
+}
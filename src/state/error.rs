@@ -0,0 +1,40 @@
+use std::fmt;
+
+/// Reasons an Anchor account buffer can fail to decode.
+///
+/// `Truncated { needed, got }` is relative to the buffer being decoded, so it tells you exactly
+/// how many bytes were expected vs. received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer ended before a field could be fully read.
+    Truncated { needed: usize, got: usize },
+    /// A string's length prefix is larger than the max allowed by the on-chain `#[max_len]`.
+    StringTooLong {
+        field: &'static str,
+        len: usize,
+        max: usize,
+    },
+    /// A string field contains bytes that are not valid UTF-8.
+    InvalidUtf8 { field: &'static str },
+    /// A Borsh `Option` tag is neither 0 (`None`) nor 1 (`Some`).
+    InvalidOptionTag { field: &'static str, tag: u8 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, got } => {
+                write!(f, "buffer truncated: needed {} bytes, got {}", needed, got)
+            }
+            DecodeError::StringTooLong { field, len, max } => {
+                write!(f, "`{}` length {} exceeds max of {}", field, len, max)
+            }
+            DecodeError::InvalidUtf8 { field } => write!(f, "`{}` is not valid UTF-8", field),
+            DecodeError::InvalidOptionTag { field, tag } => {
+                write!(f, "`{}` has invalid option tag {}", field, tag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
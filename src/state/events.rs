@@ -0,0 +1,123 @@
+use base64::Engine;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Names of the Anchor events (`emit!`) the listener decodes from `Program data:` lines.
+///
+/// The deployed voting program doesn't emit any events yet; add the struct name here when it
+/// does. Matching is by discriminator (`sha256("event:<Name>")[..8]`), like accounts.
+pub const KNOWN_EVENTS: &[&str] = &[];
+
+/// Prefix of the log line Anchor writes at the start of every instruction handler.
+const INSTRUCTION_PREFIX: &str = "Program log: Instruction: ";
+const DATA_PREFIX: &str = "Program data: ";
+
+/// An event extracted from the logs of one transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramEvent {
+    /// An instruction of our program was executed (from Anchor's `Instruction: <Name>` log).
+    Instruction { name: String },
+    /// A known Anchor event was emitted. The layout isn't decoded; the payload keeps the raw bytes.
+    Emitted { name: &'static str, data: Vec<u8> },
+}
+
+impl ProgramEvent {
+    /// Value stored in the `event_type` column.
+    pub fn event_type(&self) -> String {
+        match self {
+            ProgramEvent::Instruction { name } => format!("instruction:{}", name),
+            ProgramEvent::Emitted { name, .. } => format!("event:{}", name),
+        }
+    }
+
+    /// Value stored in the `payload` column.
+    pub fn payload(&self) -> serde_json::Value {
+        match self {
+            ProgramEvent::Instruction { name } => json!({ "instruction": name }),
+            ProgramEvent::Emitted { name, data } => json!({
+                "event": name,
+                "data": base64::engine::general_purpose::STANDARD.encode(data),
+            }),
+        }
+    }
+}
+
+/// Result of [`parse_logs`].
+#[derive(Debug, Default)]
+pub struct ParsedLogs {
+    /// Recognized events, in log order, with their index within the transaction.
+    pub events: Vec<(usize, ProgramEvent)>,
+    /// `Program log:`/`Program data:` lines of our program that matched no known event.
+    pub unmatched: usize,
+}
+
+/// Anchor event discriminator: the first 8 bytes of `sha256("event:<name>")`.
+pub fn event_discriminator(name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("event:{}", name).as_bytes());
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+/// Extracts our program's events from a transaction's log messages.
+///
+/// Logs of all programs touched by the transaction are interleaved, so the invocation stack
+/// is tracked from the `Program <id> invoke [n]` / `Program <id> success|failed` lines, and only
+/// lines written while `program_id` is the innermost running program are considered.
+pub fn parse_logs(program_id: &str, logs: &[String]) -> ParsedLogs {
+    let known: Vec<([u8; 8], &'static str)> = KNOWN_EVENTS
+        .iter()
+        .map(|name| (event_discriminator(name), *name))
+        .collect();
+
+    let mut parsed = ParsedLogs::default();
+    let mut stack: Vec<&str> = Vec::new();
+    for line in logs {
+        if let Some(rest) = line.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => {
+                    stack.push(program);
+                    continue;
+                }
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    stack.pop();
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        if stack.last() != Some(&program_id) {
+            continue;
+        }
+
+        let index = parsed.events.len() + parsed.unmatched;
+        if let Some(name) = line.strip_prefix(INSTRUCTION_PREFIX) {
+            let name = name.trim().to_string();
+            parsed
+                .events
+                .push((index, ProgramEvent::Instruction { name }));
+        } else if let Some(data) = line.strip_prefix(DATA_PREFIX) {
+            let event = base64::engine::general_purpose::STANDARD
+                .decode(data.trim())
+                .ok()
+                .filter(|bytes| bytes.len() >= 8)
+                .and_then(|bytes| {
+                    known
+                        .iter()
+                        .find(|(discriminator, _)| bytes[..8] == discriminator[..])
+                        .map(|(_, name)| ProgramEvent::Emitted {
+                            name,
+                            data: bytes[8..].to_vec(),
+                        })
+                });
+            match event {
+                Some(event) => parsed.events.push((index, event)),
+                None => parsed.unmatched += 1,
+            }
+        } else if line.starts_with("Program log: ") {
+            parsed.unmatched += 1;
+        }
+    }
+    parsed
+}
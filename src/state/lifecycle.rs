@@ -0,0 +1,462 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Timestamps at or above this (in absolute value) are treated as milliseconds rather than
+/// seconds, since some clients store poll times in ms. As seconds, 1e11 is the year 5138; as
+/// milliseconds, March 1973. Every real poll time, in either unit, is on the right side of it.
+const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+/// The lifecycle of a poll, from creation to account closure.
+///
+/// This is the single source of truth for "is this poll active?": it is computed by
+/// [`transition`] and persisted in the `lifecycle` column, instead of every module
+/// recomputing it from timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PollLifecycle {
+    /// Created but no candidates registered yet, so it can't be voted on.
+    Draft,
+    /// Has candidates, voting hasn't started yet.
+    Upcoming,
+    /// Between `poll_start` and `poll_end`.
+    Active,
+    /// Past `poll_end`, no winner declared on-chain yet.
+    Ended,
+    /// Winner declared on-chain.
+    Finalized,
+    /// The poll account was closed on-chain.
+    Closed,
+}
+
+impl PollLifecycle {
+    pub const ALL: [PollLifecycle; 6] = [
+        PollLifecycle::Draft,
+        PollLifecycle::Upcoming,
+        PollLifecycle::Active,
+        PollLifecycle::Ended,
+        PollLifecycle::Finalized,
+        PollLifecycle::Closed,
+    ];
+
+    /// Value stored in the `lifecycle` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PollLifecycle::Draft => "draft",
+            PollLifecycle::Upcoming => "upcoming",
+            PollLifecycle::Active => "active",
+            PollLifecycle::Ended => "ended",
+            PollLifecycle::Finalized => "finalized",
+            PollLifecycle::Closed => "closed",
+        }
+    }
+
+    /// Position in the forward-only ordering of states.
+    fn rank(&self) -> u8 {
+        match self {
+            PollLifecycle::Draft => 0,
+            PollLifecycle::Upcoming => 1,
+            PollLifecycle::Active => 2,
+            PollLifecycle::Ended => 3,
+            PollLifecycle::Finalized => 4,
+            PollLifecycle::Closed => 5,
+        }
+    }
+
+    /// Whether moving from `self` to `next` is allowed.
+    ///
+    /// Lifecycles only move forward. Skipping states is fine (the listener may have been
+    /// offline while a poll started and ended), going back (e.g. Closed → Active) is not.
+    pub fn can_transition_to(&self, next: PollLifecycle) -> bool {
+        next.rank() >= self.rank()
+    }
+
+    /// Terminal states are never re-evaluated by the time-based scheduler.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, PollLifecycle::Finalized | PollLifecycle::Closed)
+    }
+}
+
+impl fmt::Display for PollLifecycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PollLifecycle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PollLifecycle::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| format!("unknown poll lifecycle '{}'", s))
+    }
+}
+
+/// The on-chain facts a lifecycle state is derived from.
+#[derive(Debug, Clone, Copy)]
+pub struct LifecycleFacts {
+    pub poll_start: i64,
+    pub poll_end: i64,
+    pub candidate_amount: i64,
+    pub winner_declared: bool,
+    pub closed: bool,
+}
+
+impl LifecycleFacts {
+    /// The state these facts imply at unix time `now` (seconds).
+    pub fn derive(&self, now: i64) -> PollLifecycle {
+        if self.closed {
+            PollLifecycle::Closed
+        } else if self.winner_declared {
+            PollLifecycle::Finalized
+        } else if now >= to_unix_seconds(self.poll_end) {
+            PollLifecycle::Ended
+        } else if self.candidate_amount == 0 {
+            PollLifecycle::Draft
+        } else if now >= to_unix_seconds(self.poll_start) {
+            PollLifecycle::Active
+        } else {
+            PollLifecycle::Upcoming
+        }
+    }
+}
+
+/// Result of evaluating a poll's lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    /// The state didn't change.
+    Unchanged(PollLifecycle),
+    /// A valid transition; `from` is `None` for a newly indexed poll.
+    Moved {
+        from: Option<PollLifecycle>,
+        to: PollLifecycle,
+    },
+    /// The facts imply an impossible transition. The stored state is kept as-is.
+    Rejected {
+        from: PollLifecycle,
+        to: PollLifecycle,
+    },
+    /// The facts imply going back to `derived`, but only because `now` is within the skew
+    /// tolerance of a time boundary (clock disagreement, not a real change). `kept` stays.
+    Suppressed {
+        kept: PollLifecycle,
+        derived: PollLifecycle,
+    },
+}
+
+impl Transition {
+    /// The state that should be persisted after this transition.
+    pub fn resulting_state(&self) -> PollLifecycle {
+        match self {
+            Transition::Unchanged(state) => *state,
+            Transition::Moved { to, .. } => *to,
+            Transition::Rejected { from, .. } => *from,
+            Transition::Suppressed { kept, .. } => *kept,
+        }
+    }
+}
+
+/// The one transition function: every writer and the scheduler go through it.
+///
+/// `skew` (seconds) is how far our clock may disagree with the chain's (or another listener's).
+/// Near a time boundary this prevents flapping:
+/// - a forward move only happens once the new state has held for `skew` seconds;
+/// - a move back that would be possible `skew` seconds later is [`Transition::Suppressed`]
+///   instead of rejected, since the stored state was most likely set by a clock running ahead.
+///
+/// With `skew = 0` every boundary is exact.
+pub fn transition(
+    current: Option<PollLifecycle>,
+    facts: &LifecycleFacts,
+    now: i64,
+    skew: i64,
+) -> Transition {
+    let next = facts.derive(now);
+    let Some(current) = current else {
+        return Transition::Moved {
+            from: None,
+            to: next,
+        };
+    };
+    if current == next {
+        return Transition::Unchanged(current);
+    }
+
+    if current.can_transition_to(next) {
+        // Move only as far as the state that has held for at least `skew` seconds.
+        let settled = facts.derive(now.saturating_sub(skew));
+        if settled.rank() > current.rank() {
+            Transition::Moved {
+                from: Some(current),
+                to: settled,
+            }
+        } else {
+            Transition::Unchanged(current)
+        }
+    } else if facts.derive(now.saturating_add(skew)).rank() >= current.rank() {
+        Transition::Suppressed {
+            kept: current,
+            derived: next,
+        }
+    } else {
+        Transition::Rejected {
+            from: current,
+            to: next,
+        }
+    }
+}
+
+/// Normalizes an on-chain timestamp to unix seconds (see [`MILLIS_THRESHOLD`]).
+pub fn to_unix_seconds(raw: i64) -> i64 {
+    if raw.abs() >= MILLIS_THRESHOLD {
+        raw / 1000
+    } else {
+        raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts(candidate_amount: i64) -> LifecycleFacts {
+        LifecycleFacts {
+            poll_start: 1_000,
+            poll_end: 2_000,
+            candidate_amount,
+            winner_declared: false,
+            closed: false,
+        }
+    }
+
+    #[test]
+    fn derives_the_state_from_the_facts() {
+        assert_eq!(facts(0).derive(500), PollLifecycle::Draft);
+        assert_eq!(facts(2).derive(500), PollLifecycle::Upcoming);
+        assert_eq!(facts(2).derive(1_000), PollLifecycle::Active);
+        assert_eq!(facts(2).derive(2_000), PollLifecycle::Ended);
+        assert_eq!(facts(0).derive(2_000), PollLifecycle::Ended);
+
+        let finalized = LifecycleFacts {
+            winner_declared: true,
+            ..facts(2)
+        };
+        assert_eq!(finalized.derive(1_500), PollLifecycle::Finalized);
+        let closed = LifecycleFacts {
+            closed: true,
+            ..finalized
+        };
+        assert_eq!(closed.derive(1_500), PollLifecycle::Closed);
+    }
+
+    #[test]
+    fn millisecond_timestamps_are_normalized() {
+        let millis = LifecycleFacts {
+            poll_start: 1_700_000_000_000,
+            poll_end: 1_700_000_100_000,
+            ..facts(2)
+        };
+        assert_eq!(millis.derive(1_700_000_050), PollLifecycle::Active);
+        assert_eq!(to_unix_seconds(1_700_000_000), 1_700_000_000);
+    }
+
+    #[test]
+    fn the_millisecond_threshold_separates_real_dates() {
+        // 5000-01-01 in seconds stays seconds; 2020-01-01 in milliseconds becomes seconds.
+        assert_eq!(to_unix_seconds(95_617_584_000), 95_617_584_000);
+        assert_eq!(to_unix_seconds(1_577_836_800_000), 1_577_836_800);
+        assert_eq!(to_unix_seconds(99_999_999_999), 99_999_999_999);
+        assert_eq!(to_unix_seconds(MILLIS_THRESHOLD), 100_000_000);
+        assert_eq!(to_unix_seconds(-MILLIS_THRESHOLD), -100_000_000);
+        assert_eq!(to_unix_seconds(0), 0);
+    }
+
+    #[test]
+    fn new_polls_move_to_any_state() {
+        assert_eq!(
+            transition(None, &facts(2), 2_500, 0),
+            Transition::Moved {
+                from: None,
+                to: PollLifecycle::Ended
+            }
+        );
+    }
+
+    #[test]
+    fn lifecycles_only_move_forward() {
+        // Skipping states is fine, e.g. when the listener was down for the whole vote.
+        assert_eq!(
+            transition(Some(PollLifecycle::Upcoming), &facts(2), 2_500, 0),
+            Transition::Moved {
+                from: Some(PollLifecycle::Upcoming),
+                to: PollLifecycle::Ended
+            }
+        );
+        assert_eq!(
+            transition(Some(PollLifecycle::Closed), &facts(2), 1_500, 0),
+            Transition::Rejected {
+                from: PollLifecycle::Closed,
+                to: PollLifecycle::Active
+            }
+        );
+        assert_eq!(
+            transition(Some(PollLifecycle::Active), &facts(2), 1_500, 0),
+            Transition::Unchanged(PollLifecycle::Active)
+        );
+    }
+
+    #[test]
+    fn skew_delays_forward_moves() {
+        let upcoming = Some(PollLifecycle::Upcoming);
+        assert_eq!(
+            transition(upcoming, &facts(2), 1_002, 5),
+            Transition::Unchanged(PollLifecycle::Upcoming)
+        );
+        assert_eq!(
+            transition(upcoming, &facts(2), 1_005, 5),
+            Transition::Moved {
+                from: upcoming,
+                to: PollLifecycle::Active
+            }
+        );
+    }
+
+    #[test]
+    fn skew_suppresses_moves_back_near_a_boundary() {
+        // Stored as active by a clock running a few seconds ahead.
+        let transition = transition(Some(PollLifecycle::Active), &facts(2), 997, 5);
+        assert_eq!(
+            transition,
+            Transition::Suppressed {
+                kept: PollLifecycle::Active,
+                derived: PollLifecycle::Upcoming
+            }
+        );
+        assert_eq!(transition.resulting_state(), PollLifecycle::Active);
+    }
+
+    /// Every combination of facts, at times before, at and after each boundary; in seconds and
+    /// in milliseconds.
+    fn all_facts() -> Vec<(LifecycleFacts, i64)> {
+        let mut all = Vec::new();
+        for unit in [1, 1_000] {
+            for candidate_amount in [0, 2] {
+                for winner_declared in [false, true] {
+                    for closed in [false, true] {
+                        let facts = LifecycleFacts {
+                            poll_start: 1_000 * unit,
+                            poll_end: 2_000 * unit,
+                            candidate_amount,
+                            winner_declared,
+                            closed,
+                        };
+                        for now in [500, 999, 1_000, 1_500, 1_999, 2_000, 2_500] {
+                            all.push((facts, now));
+                        }
+                    }
+                }
+            }
+        }
+        all
+    }
+
+    #[test]
+    fn every_state_and_fact_combination_transitions_as_specified() {
+        // Whether a stored state (row) may move to a derived one (column), written out rather
+        // than computed from `rank`: Draft, Upcoming, Active, Ended, Finalized, Closed.
+        const ALLOWED: [[bool; 6]; 6] = [
+            [true, true, true, true, true, true],
+            [false, true, true, true, true, true],
+            [false, false, true, true, true, true],
+            [false, false, false, true, true, true],
+            [false, false, false, false, true, true],
+            [false, false, false, false, false, true],
+        ];
+
+        let all = all_facts();
+        let mut derived_seen = Vec::new();
+        for (facts, now) in &all {
+            let derived = facts.derive(*now);
+            if !derived_seen.contains(&derived) {
+                derived_seen.push(derived);
+            }
+            assert_eq!(
+                transition(None, facts, *now, 0),
+                Transition::Moved {
+                    from: None,
+                    to: derived
+                }
+            );
+            for (row, current) in PollLifecycle::ALL.into_iter().enumerate() {
+                let column = PollLifecycle::ALL
+                    .iter()
+                    .position(|s| *s == derived)
+                    .unwrap();
+                let expected = if current == derived {
+                    Transition::Unchanged(current)
+                } else if ALLOWED[row][column] {
+                    Transition::Moved {
+                        from: Some(current),
+                        to: derived,
+                    }
+                } else {
+                    Transition::Rejected {
+                        from: current,
+                        to: derived,
+                    }
+                };
+                assert_eq!(
+                    transition(Some(current), facts, *now, 0),
+                    expected,
+                    "{:?} at {} from {}",
+                    facts,
+                    now,
+                    current
+                );
+                assert_eq!(
+                    current.can_transition_to(derived),
+                    ALLOWED[row][column],
+                    "{} -> {}",
+                    current,
+                    derived
+                );
+            }
+        }
+        // The facts above reach every state, so every cell of the matrix was checked.
+        for state in PollLifecycle::ALL {
+            assert!(derived_seen.contains(&state), "{} never derived", state);
+        }
+    }
+
+    #[test]
+    fn skew_never_moves_a_state_back() {
+        for (facts, now) in all_facts() {
+            for current in PollLifecycle::ALL {
+                for skew in [0, 1, 5, 600] {
+                    let transition = transition(Some(current), &facts, now, skew);
+                    assert!(
+                        current.can_transition_to(transition.resulting_state()),
+                        "{:?} at {} from {} with skew {}: {:?}",
+                        facts,
+                        now,
+                        current,
+                        skew,
+                        transition
+                    );
+                    // Skew only delays or suppresses: it never moves past the derived state.
+                    if let Transition::Moved { to, .. } = transition {
+                        assert!(to.can_transition_to(facts.derive(now)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stored_values_round_trip() {
+        for state in PollLifecycle::ALL {
+            assert_eq!(state.as_str().parse::<PollLifecycle>(), Ok(state));
+        }
+        assert!("open".parse::<PollLifecycle>().is_err());
+    }
+}
@@ -1 +1,6 @@
+pub mod anchor;
+pub mod delegation;
+pub mod error;
+pub mod events;
+pub mod lifecycle;
 pub mod pool;
@@ -1,5 +1,16 @@
+use borsh::BorshDeserialize;
 use solana_sdk::pubkey::Pubkey;
 
+use super::anchor::{AnchorDecode, AnchorEncode, AnchorReader, AnchorWriter};
+use super::error::DecodeError;
+
+/// On-chain `#[max_len]` of `poll_name`, in bytes.
+pub const POLL_NAME_MAX_LEN: usize = 64;
+/// On-chain `#[max_len]` of `poll_description`, in bytes.
+pub const POLL_DESCRIPTION_MAX_LEN: usize = 280;
+
+/// The Poll account body, in on-chain field order (the derived `BorshDeserialize` relies on it).
+#[derive(Debug, Clone, PartialEq, Eq, BorshDeserialize)]
 pub struct Poll {
     pub poll_id: u64,
     pub poll_owner: Pubkey,
@@ -11,81 +22,50 @@ pub struct Poll {
     pub candidate_winner: Pubkey,
 }
 
-impl Poll {
-    pub fn try_from_anchor_bytes(data: &[u8]) -> Option<Self> {
-        let mut offset = 0;
-
-        if data.len() < offset + 8 {
-            return None;
-        }
-        let poll_id = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-        offset += 8;
-
-        if data.len() < offset + 32 {
-            return None;
-        }
-        let poll_owner = Pubkey::new_from_array(data[offset..offset + 32].try_into().unwrap());
-        offset += 32;
-
-        let (poll_name, len) = read_anchor_string_manual(&data[offset..], 64)?;
-        offset += len;
-
-        let (poll_description, len) = read_anchor_string_manual(&data[offset..], 280)?;
-        offset += len;
-
-        if data.len() < offset + 8 {
-            return None;
-        }
-        let poll_start = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-        offset += 8;
-
-        if data.len() < offset + 8 {
-            return None;
-        }
-        let poll_end = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-        offset += 8;
-
-        if data.len() < offset + 8 {
-            return None;
-        }
-        let candidate_amount = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
-        offset += 8;
-
-        if data.len() < offset + 32 {
-            return None;
-        }
-        let candidate_winner =
-            Pubkey::new_from_array(data[offset..offset + 32].try_into().unwrap());
-
-        Some(Self {
-            poll_id,
-            poll_owner,
-            poll_name,
-            poll_description,
-            poll_start,
-            poll_end,
-            candidate_amount,
-            candidate_winner,
+impl AnchorDecode for Poll {
+    fn decode(reader: &mut AnchorReader<'_>) -> Result<Self, DecodeError> {
+        Ok(Self {
+            poll_id: reader.read_u64()?,
+            poll_owner: reader.read_pubkey()?,
+            poll_name: reader.read_string("poll_name", POLL_NAME_MAX_LEN)?,
+            poll_description: reader.read_string("poll_description", POLL_DESCRIPTION_MAX_LEN)?,
+            poll_start: reader.read_u64()?,
+            poll_end: reader.read_u64()?,
+            candidate_amount: reader.read_u64()?,
+            candidate_winner: reader.read_pubkey()?,
         })
     }
-}
-
-fn read_anchor_string_manual(data: &[u8], max_len: usize) -> Option<(String, usize)> {
-    if data.len() < 4 {
-        return None;
-    }
 
-    let len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
-    if len > max_len {
-        return None;
+    fn validate(&self) -> Result<(), DecodeError> {
+        for (field, value, max) in [
+            ("poll_name", &self.poll_name, POLL_NAME_MAX_LEN),
+            (
+                "poll_description",
+                &self.poll_description,
+                POLL_DESCRIPTION_MAX_LEN,
+            ),
+        ] {
+            if value.len() > max {
+                return Err(DecodeError::StringTooLong {
+                    field,
+                    len: value.len(),
+                    max,
+                });
+            }
+        }
+        Ok(())
     }
+}
 
-    if data.len() < 4 + len {
-        return None;
+impl AnchorEncode for Poll {
+    fn encode(&self, writer: &mut AnchorWriter) {
+        writer.write_u64(self.poll_id);
+        writer.write_pubkey(&self.poll_owner);
+        writer.write_string(&self.poll_name);
+        writer.write_string(&self.poll_description);
+        writer.write_u64(self.poll_start);
+        writer.write_u64(self.poll_end);
+        writer.write_u64(self.candidate_amount);
+        writer.write_pubkey(&self.candidate_winner);
     }
-
-    let string_bytes = &data[4..4 + len];
-    let s = std::str::from_utf8(string_bytes).ok()?.to_string();
-
-    Some((s, 4 + len))
 }
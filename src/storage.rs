@@ -0,0 +1,569 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::candidates::{self, Candidate};
+use crate::clock::Clock;
+use crate::db::db::{self, ClosedRows, PgPool, PollKey, UpsertOutcome};
+use crate::db::models::{
+    Delegation, IdlAccount, NewDelegation, NewEvent, NewIdlAccount, NewPoll, Poll,
+};
+use crate::metrics::Metrics;
+use crate::sink::PollSink;
+use crate::state::lifecycle::{self, PollLifecycle};
+use crate::writer::log_lifecycle_transition;
+
+/// Where the indexed data lives, for code that doesn't care which database it is.
+///
+/// The semantics are those of the Postgres tables: rows are keyed by `(program_id, poll_id)` for
+/// polls and `(program_id, account_pubkey)` for other accounts, an update older than the stored
+/// row (lower `last_slot`) is dropped, and closed accounts keep their rows with `deleted_at` set.
+///
+/// [`PostgresStorage`] runs the functions of [`db::db`](crate::db::db); [`MemoryStorage`] keeps
+/// everything in memory. [`StorageSink`] lets the listener write to either. Candidates and votes
+/// are IDL-decoded accounts (see `idl_decode`), so they go through `upsert_idl_account`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Inserts or updates a poll and re-evaluates its lifecycle (see [`db::upsert_poll`]).
+    async fn upsert_poll(&self, poll: &NewPoll) -> Result<UpsertOutcome>;
+
+    /// Inserts or updates an IDL-decoded account. Returns whether it was written.
+    async fn upsert_idl_account(&self, account: &NewIdlAccount) -> Result<bool>;
+
+    /// Inserts or updates a delegation (program v3). Returns whether it was written.
+    async fn upsert_delegation(&self, delegation: &NewDelegation) -> Result<bool>;
+
+    /// Stores transaction log events, skipping those already stored. Returns how many were new.
+    async fn record_events(&self, events: &[NewEvent]) -> Result<usize>;
+
+    /// Marks the rows of an account closed at `slot` deleted (see [`db::mark_account_closed`]).
+    async fn mark_account_closed(
+        &self,
+        program: &[u8],
+        account: &[u8],
+        slot: i64,
+    ) -> Result<ClosedRows>;
+
+    /// Every poll, optionally only those of one program, ordered by `poll_id`.
+    async fn list_polls(&self, program: Option<&[u8]>) -> Result<Vec<Poll>>;
+
+    /// The polls with the given on-chain id (one per program), optionally only that of
+    /// `program`.
+    async fn get_polls_by_id(&self, poll_id: i64, program: Option<&[u8]>) -> Result<Vec<Poll>>;
+
+    /// The open IDL-decoded accounts of one type (e.g. `Candidate`) of a program, in the order
+    /// they were first seen.
+    async fn list_idl_accounts(
+        &self,
+        program: &[u8],
+        account_type: &str,
+    ) -> Result<Vec<IdlAccount>>;
+
+    /// The open delegations of a poll, of one program or of all, ordered by delegator.
+    async fn list_delegations(&self, program: Option<&[u8]>, poll: i64) -> Result<Vec<Delegation>>;
+
+    /// The program's checkpoint, see [`PollSink::save_checkpoint`].
+    async fn checkpoint(&self, program: &[u8]) -> Result<Option<i64>>;
+
+    /// Moves the program's checkpoint to `slot`; it never goes back.
+    async fn save_checkpoint(&self, program: &[u8], slot: i64) -> Result<()>;
+
+    /// The candidates of `poll`, most votes first (see [`candidates::poll_candidates`]).
+    async fn list_candidates(&self, poll: &Poll) -> Result<Vec<Candidate>> {
+        let accounts = self
+            .list_idl_accounts(&poll.program_id, candidates::CANDIDATE_ACCOUNT)
+            .await?;
+        Ok(candidates::poll_candidates(&accounts, poll))
+    }
+}
+
+/// The Postgres backend: each call takes a pooled connection on the blocking thread pool.
+///
+/// Polls are upserted one by one; the listener's [`PostgresSink`](crate::sink::PostgresSink)
+/// batches them instead.
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+    lifecycle_skew_secs: i64,
+    clock: Clock,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            lifecycle_skew_secs: 0,
+            clock: Clock::system(),
+        }
+    }
+
+    /// Clock skew tolerance of the lifecycle transitions, in seconds (see
+    /// [`lifecycle::transition`]).
+    pub fn lifecycle_skew_secs(mut self, skew: i64) -> Self {
+        self.lifecycle_skew_secs = skew;
+        self
+    }
+
+    /// Clock the lifecycle transitions are evaluated with (the system's by default).
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Runs `f` with the pool on the blocking thread pool.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&PgPool) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || f(&pool)).await?
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn upsert_poll(&self, poll: &NewPoll) -> Result<UpsertOutcome> {
+        let (poll, skew, now) = (
+            poll.clone(),
+            self.lifecycle_skew_secs,
+            self.clock.unix_now(),
+        );
+        self.blocking(move |pool| db::upsert_poll(pool, &poll, skew, now))
+            .await
+    }
+
+    async fn upsert_idl_account(&self, account: &NewIdlAccount) -> Result<bool> {
+        let account = account.clone();
+        self.blocking(move |pool| db::upsert_idl_account(pool, &account))
+            .await
+    }
+
+    async fn upsert_delegation(&self, delegation: &NewDelegation) -> Result<bool> {
+        let delegation = delegation.clone();
+        self.blocking(move |pool| db::upsert_delegation(pool, &delegation))
+            .await
+    }
+
+    async fn record_events(&self, events: &[NewEvent]) -> Result<usize> {
+        let events = events.to_vec();
+        self.blocking(move |pool| db::record_events(pool, &events))
+            .await
+    }
+
+    async fn mark_account_closed(
+        &self,
+        program: &[u8],
+        account: &[u8],
+        slot: i64,
+    ) -> Result<ClosedRows> {
+        let (program, account, now) = (program.to_vec(), account.to_vec(), self.clock.unix_now());
+        self.blocking(move |pool| db::mark_account_closed(pool, &program, &account, slot, now))
+            .await
+    }
+
+    async fn list_polls(&self, program: Option<&[u8]>) -> Result<Vec<Poll>> {
+        let program = program.map(<[u8]>::to_vec);
+        self.blocking(move |pool| db::list_polls(pool, program.as_deref()))
+            .await
+    }
+
+    async fn get_polls_by_id(&self, poll_id: i64, program: Option<&[u8]>) -> Result<Vec<Poll>> {
+        let program = program.map(<[u8]>::to_vec);
+        self.blocking(move |pool| db::get_polls_by_id(pool, poll_id, program.as_deref()))
+            .await
+    }
+
+    async fn list_idl_accounts(
+        &self,
+        program: &[u8],
+        account_type: &str,
+    ) -> Result<Vec<IdlAccount>> {
+        let (program, account_type) = (program.to_vec(), account_type.to_string());
+        self.blocking(move |pool| db::list_idl_accounts(pool, &program, &account_type))
+            .await
+    }
+
+    async fn list_delegations(&self, program: Option<&[u8]>, poll: i64) -> Result<Vec<Delegation>> {
+        let program = program.map(<[u8]>::to_vec);
+        self.blocking(move |pool| db::list_delegations(pool, program.as_deref(), poll))
+            .await
+    }
+
+    async fn checkpoint(&self, program: &[u8]) -> Result<Option<i64>> {
+        let program = program.to_vec();
+        self.blocking(move |pool| db::get_checkpoint(pool, &program))
+            .await
+    }
+
+    async fn save_checkpoint(&self, program: &[u8], slot: i64) -> Result<()> {
+        let program = program.to_vec();
+        self.blocking(move |pool| db::save_checkpoint(pool, &program, slot))
+            .await
+    }
+}
+
+/// Keeps the indexed data in memory, e.g. for tests or a short-lived embedded listener.
+/// Nothing survives the process.
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+    lifecycle_skew_secs: i64,
+    clock: Clock,
+}
+
+#[derive(Default)]
+struct Tables {
+    /// Surrogate keys, shared by every table like they'd be unique per table.
+    next_id: i32,
+    polls: BTreeMap<PollKey, Poll>,
+    idl_accounts: BTreeMap<(Vec<u8>, Vec<u8>), IdlAccount>,
+    delegations: BTreeMap<(Vec<u8>, Vec<u8>), Delegation>,
+    events: Vec<NewEvent>,
+    /// `(signature, log_index)` of the events stored.
+    event_keys: HashSet<(String, i32)>,
+    checkpoints: HashMap<Vec<u8>, i64>,
+}
+
+impl Tables {
+    fn next_id(&mut self) -> i32 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clock skew tolerance of the lifecycle transitions, in seconds (see
+    /// [`lifecycle::transition`]).
+    pub fn lifecycle_skew_secs(mut self, skew: i64) -> Self {
+        self.lifecycle_skew_secs = skew;
+        self
+    }
+
+    /// Clock of the lifecycle transitions and the rows' timestamps (the system's by default).
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn upsert_poll(&self, poll: &NewPoll) -> Result<UpsertOutcome> {
+        let mut tables = self.tables.lock().unwrap();
+        let key = (poll.program_id.clone(), poll.poll_id);
+        let now = self.clock.now();
+        let current = match tables.polls.get(&key) {
+            Some(stored) if stored.last_slot > poll.last_slot => {
+                return Ok(UpsertOutcome::Stale {
+                    incoming_slot: poll.last_slot,
+                    stored_slot: stored.last_slot,
+                });
+            }
+            // A re-created poll starts its lifecycle over, like a new one.
+            Some(stored) if stored.deleted_at.is_none() => {
+                stored.lifecycle.parse::<PollLifecycle>().ok()
+            }
+            _ => None,
+        };
+        let transition = lifecycle::transition(
+            current,
+            &poll.lifecycle_facts(),
+            now.timestamp(),
+            self.lifecycle_skew_secs,
+        );
+        let (id, first_seen_at) = match tables.polls.get(&key) {
+            Some(stored) => (stored.id, stored.first_seen_at),
+            None => (tables.next_id(), now),
+        };
+        tables.polls.insert(
+            key,
+            Poll {
+                id,
+                poll_id: poll.poll_id,
+                poll_owner: poll.poll_owner.clone(),
+                poll_name: poll.poll_name.clone(),
+                poll_description: poll.poll_description.clone(),
+                poll_start: poll.poll_start,
+                poll_end: poll.poll_end,
+                candidate_amount: poll.candidate_amount,
+                candidate_winner: poll.candidate_winner.clone(),
+                lifecycle: transition.resulting_state().to_string(),
+                last_slot: poll.last_slot,
+                program_id: poll.program_id.clone(),
+                checksum: Some(poll.checksum()),
+                account_pubkey: poll.account_pubkey.clone(),
+                first_seen_at,
+                last_updated_at: now,
+                deleted_at: None,
+            },
+        );
+        Ok(UpsertOutcome::Written(transition))
+    }
+
+    async fn upsert_idl_account(&self, account: &NewIdlAccount) -> Result<bool> {
+        let mut tables = self.tables.lock().unwrap();
+        let key = (account.program_id.clone(), account.account_pubkey.clone());
+        let now = self.clock.now();
+        let (id, first_seen_at) = match tables.idl_accounts.get(&key) {
+            Some(stored) if stored.last_slot > account.last_slot => return Ok(false),
+            Some(stored) => (stored.id, stored.first_seen_at),
+            None => (tables.next_id(), now),
+        };
+        tables.idl_accounts.insert(
+            key,
+            IdlAccount {
+                id,
+                program_id: account.program_id.clone(),
+                account_pubkey: account.account_pubkey.clone(),
+                account_type: account.account_type.clone(),
+                data: account.data.clone(),
+                last_slot: account.last_slot,
+                first_seen_at,
+                last_updated_at: now,
+                deleted_at: None,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn upsert_delegation(&self, delegation: &NewDelegation) -> Result<bool> {
+        let mut tables = self.tables.lock().unwrap();
+        let key = (
+            delegation.program_id.clone(),
+            delegation.account_pubkey.clone(),
+        );
+        let now = self.clock.now();
+        let (id, first_seen_at) = match tables.delegations.get(&key) {
+            Some(stored) if stored.last_slot > delegation.last_slot => return Ok(false),
+            Some(stored) => (stored.id, stored.first_seen_at),
+            None => (tables.next_id(), now),
+        };
+        tables.delegations.insert(
+            key,
+            Delegation {
+                id,
+                program_id: delegation.program_id.clone(),
+                account_pubkey: delegation.account_pubkey.clone(),
+                poll_id: delegation.poll_id,
+                delegator: delegation.delegator.clone(),
+                delegate: delegation.delegate.clone(),
+                expiry: delegation.expiry,
+                last_slot: delegation.last_slot,
+                first_seen_at,
+                last_updated_at: now,
+                deleted_at: None,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn record_events(&self, events: &[NewEvent]) -> Result<usize> {
+        let mut tables = self.tables.lock().unwrap();
+        let mut inserted = 0;
+        for event in events {
+            if tables
+                .event_keys
+                .insert((event.signature.clone(), event.log_index))
+            {
+                tables.events.push(event.clone());
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn mark_account_closed(
+        &self,
+        program: &[u8],
+        account: &[u8],
+        slot: i64,
+    ) -> Result<ClosedRows> {
+        let mut tables = self.tables.lock().unwrap();
+        let now = self.clock.now();
+        let mut closed = ClosedRows::default();
+        for (key, row) in tables.polls.iter_mut() {
+            if row.program_id != program
+                || row.account_pubkey != account
+                || row.last_slot > slot
+                || row.deleted_at.is_some()
+            {
+                continue;
+            }
+            row.last_slot = slot;
+            row.deleted_at = Some(now);
+            // With `deleted_at` set, the row's facts say it's closed, whatever the time.
+            let transition = lifecycle::transition(
+                row.lifecycle.parse::<PollLifecycle>().ok(),
+                &row.lifecycle_facts(),
+                now.timestamp(),
+                0,
+            );
+            row.lifecycle = transition.resulting_state().to_string();
+            closed.polls.push((key.clone(), transition));
+        }
+        let key = (program.to_vec(), account.to_vec());
+        if let Some(row) = tables.delegations.get_mut(&key) {
+            if row.last_slot <= slot && row.deleted_at.is_none() {
+                row.last_slot = slot;
+                row.deleted_at = Some(now);
+                closed.delegations = 1;
+            }
+        }
+        if let Some(row) = tables.idl_accounts.get_mut(&key) {
+            if row.last_slot <= slot && row.deleted_at.is_none() {
+                row.last_slot = slot;
+                row.deleted_at = Some(now);
+                closed.idl_accounts = 1;
+            }
+        }
+        Ok(closed)
+    }
+
+    async fn list_polls(&self, program: Option<&[u8]>) -> Result<Vec<Poll>> {
+        let tables = self.tables.lock().unwrap();
+        let mut polls: Vec<Poll> = tables
+            .polls
+            .values()
+            .filter(|poll| program.is_none_or(|program| poll.program_id == program))
+            .cloned()
+            .collect();
+        polls.sort_by(|a, b| (a.poll_id, &a.program_id).cmp(&(b.poll_id, &b.program_id)));
+        Ok(polls)
+    }
+
+    async fn get_polls_by_id(&self, poll_id: i64, program: Option<&[u8]>) -> Result<Vec<Poll>> {
+        let tables = self.tables.lock().unwrap();
+        Ok(tables
+            .polls
+            .values()
+            .filter(|poll| poll.poll_id == poll_id)
+            .filter(|poll| program.is_none_or(|program| poll.program_id == program))
+            .cloned()
+            .collect())
+    }
+
+    async fn list_idl_accounts(
+        &self,
+        program: &[u8],
+        account_type: &str,
+    ) -> Result<Vec<IdlAccount>> {
+        let tables = self.tables.lock().unwrap();
+        let mut accounts: Vec<IdlAccount> = tables
+            .idl_accounts
+            .values()
+            .filter(|account| account.program_id == program)
+            .filter(|account| account.account_type == account_type)
+            .filter(|account| account.deleted_at.is_none())
+            .cloned()
+            .collect();
+        accounts.sort_by_key(|account| account.id);
+        Ok(accounts)
+    }
+
+    async fn list_delegations(&self, program: Option<&[u8]>, poll: i64) -> Result<Vec<Delegation>> {
+        let tables = self.tables.lock().unwrap();
+        let mut delegations: Vec<Delegation> = tables
+            .delegations
+            .values()
+            .filter(|delegation| delegation.poll_id == poll)
+            .filter(|delegation| delegation.deleted_at.is_none())
+            .filter(|delegation| program.is_none_or(|program| delegation.program_id == program))
+            .cloned()
+            .collect();
+        delegations.sort_by(|a, b| (&a.delegator, a.id).cmp(&(&b.delegator, b.id)));
+        Ok(delegations)
+    }
+
+    async fn checkpoint(&self, program: &[u8]) -> Result<Option<i64>> {
+        Ok(self
+            .tables
+            .lock()
+            .unwrap()
+            .checkpoints
+            .get(program)
+            .copied())
+    }
+
+    async fn save_checkpoint(&self, program: &[u8], slot: i64) -> Result<()> {
+        let mut tables = self.tables.lock().unwrap();
+        let checkpoint = tables.checkpoints.entry(program.to_vec()).or_insert(slot);
+        *checkpoint = (*checkpoint).max(slot);
+        Ok(())
+    }
+}
+
+/// Writes what the listener decodes straight to a [`Storage`], one call per update, e.g. to
+/// index into a [`MemoryStorage`]. Decode failures and raw accounts are dropped.
+pub struct StorageSink {
+    storage: Arc<dyn Storage>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl StorageSink {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            metrics: None,
+        }
+    }
+
+    /// Counts the lifecycle transitions and suppressed flaps of what it writes.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
+
+#[async_trait]
+impl PollSink for StorageSink {
+    async fn write_poll(&self, poll: NewPoll) -> Result<()> {
+        let key = (poll.program_id.clone(), poll.poll_id);
+        if let UpsertOutcome::Written(transition) = self.storage.upsert_poll(&poll).await? {
+            log_lifecycle_transition(&key, &transition, self.metrics.as_deref());
+        }
+        Ok(())
+    }
+
+    async fn write_events(&self, events: Vec<NewEvent>) -> Result<usize> {
+        self.storage.record_events(&events).await
+    }
+
+    async fn write_delegation(&self, delegation: NewDelegation) -> Result<()> {
+        self.storage.upsert_delegation(&delegation).await?;
+        Ok(())
+    }
+
+    async fn write_idl_account(&self, account: NewIdlAccount) -> Result<()> {
+        self.storage.upsert_idl_account(&account).await?;
+        Ok(())
+    }
+
+    async fn mark_closed(&self, program_id: &Pubkey, account: &Pubkey, slot: u64) -> Result<bool> {
+        let closed = self
+            .storage
+            .mark_account_closed(&program_id.to_bytes(), &account.to_bytes(), slot as i64)
+            .await?;
+        for (key, transition) in &closed.polls {
+            log_lifecycle_transition(key, transition, self.metrics.as_deref());
+        }
+        Ok(!closed.is_empty())
+    }
+
+    async fn checkpoint(&self, program_id: &Pubkey) -> Result<Option<u64>> {
+        let slot = self.storage.checkpoint(&program_id.to_bytes()).await?;
+        Ok(slot.map(|slot| slot as u64))
+    }
+
+    async fn save_checkpoint(&self, program_id: &Pubkey, slot: u64) -> Result<()> {
+        self.storage
+            .save_checkpoint(&program_id.to_bytes(), slot as i64)
+            .await
+    }
+}
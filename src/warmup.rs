@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub use voting_dapp_api_types::{DiscriminatorCount, WarmupReport, WarmupState};
+
+use crate::clock::Instant;
+
+/// How many of the most common discriminators a failed warm-up reports.
+const TOP_DISCRIMINATORS: usize = 5;
+
+/// Thresholds of the warm-up phase (`--warmup-messages`, `--warmup-secs`, `--warmup-min-ratio`).
+#[derive(Debug, Clone, Copy)]
+pub struct WarmupConfig {
+    /// The phase ends after this many messages...
+    pub messages: usize,
+    /// ...or after this long, whichever comes first.
+    pub window: Duration,
+    /// Minimum share of messages with a known discriminator that decoded successfully.
+    pub min_ratio: f64,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            messages: 50,
+            window: Duration::from_secs(60),
+            min_ratio: 0.5,
+        }
+    }
+}
+
+/// Strict evaluation of the first messages of a subscription.
+///
+/// A wrong program ID (pointing at some other Anchor program) otherwise runs "successfully"
+/// while only producing unknown-discriminator noise. The caller feeds every message to
+/// [`Warmup::record`] and calls [`Warmup::expire`] at [`Warmup::deadline`]; whichever
+/// completes the phase first returns the report, exactly once.
+#[derive(Debug)]
+pub struct Warmup {
+    config: WarmupConfig,
+    started: Instant,
+    messages: usize,
+    recognized: usize,
+    unrecognized: HashMap<[u8; 8], usize>,
+    finished: bool,
+}
+
+impl Warmup {
+    pub fn new(config: WarmupConfig, started: Instant) -> Self {
+        Self {
+            config,
+            started,
+            messages: 0,
+            recognized: 0,
+            unrecognized: HashMap::new(),
+            finished: false,
+        }
+    }
+
+    /// When the phase ends if fewer than `messages` arrive.
+    pub fn deadline(&self) -> Instant {
+        self.started + self.config.window
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Records one message: its discriminator (if it had 8 bytes of data) and whether it was
+    /// recognized and decoded. Returns the report when this message completes the phase.
+    pub fn record(
+        &mut self,
+        discriminator: Option<[u8; 8]>,
+        recognized: bool,
+        now: Instant,
+    ) -> Option<WarmupReport> {
+        if self.finished {
+            return None;
+        }
+        self.messages += 1;
+        if recognized {
+            self.recognized += 1;
+        } else if let Some(discriminator) = discriminator {
+            *self.unrecognized.entry(discriminator).or_default() += 1;
+        }
+        if self.messages >= self.config.messages || now >= self.deadline() {
+            return Some(self.finish());
+        }
+        None
+    }
+
+    /// Ends the phase at the deadline. Returns `None` if it already ended.
+    pub fn expire(&mut self) -> Option<WarmupReport> {
+        if self.finished {
+            return None;
+        }
+        Some(self.finish())
+    }
+
+    /// The current state, without ending the phase.
+    pub fn report(&self) -> WarmupReport {
+        let ratio = if self.messages == 0 {
+            0.0
+        } else {
+            self.recognized as f64 / self.messages as f64
+        };
+        let state = if !self.finished {
+            WarmupState::Pending
+        } else if self.messages == 0 {
+            WarmupState::Inconclusive
+        } else if ratio >= self.config.min_ratio {
+            WarmupState::Passed
+        } else {
+            WarmupState::Failed
+        };
+
+        let mut unrecognized_discriminators: Vec<DiscriminatorCount> = self
+            .unrecognized
+            .iter()
+            .map(|(discriminator, count)| DiscriminatorCount {
+                discriminator: *discriminator,
+                count: *count,
+            })
+            .collect();
+        unrecognized_discriminators.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(a.discriminator.cmp(&b.discriminator))
+        });
+        unrecognized_discriminators.truncate(TOP_DISCRIMINATORS);
+
+        WarmupReport {
+            state,
+            messages: self.messages,
+            recognized: self.recognized,
+            ratio,
+            unrecognized_discriminators,
+        }
+    }
+
+    fn finish(&mut self) -> WarmupReport {
+        self.finished = true;
+        self.report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KNOWN: Option<[u8; 8]> = Some([1; 8]);
+
+    fn warmup(messages: usize) -> (Warmup, Instant) {
+        let start = Instant::now();
+        let config = WarmupConfig {
+            messages,
+            ..WarmupConfig::default()
+        };
+        (Warmup::new(config, start), start)
+    }
+
+    /// Feeds `known` recognized updates, then `unknown` ones with each discriminator in turn.
+    fn feed(
+        warmup: &mut Warmup,
+        now: Instant,
+        known: usize,
+        unknown: &[([u8; 8], usize)],
+    ) -> Option<WarmupReport> {
+        let mut report = None;
+        for _ in 0..known {
+            report = report.or(warmup.record(KNOWN, true, now));
+        }
+        for (discriminator, count) in unknown {
+            for _ in 0..*count {
+                report = report.or(warmup.record(Some(*discriminator), false, now));
+            }
+        }
+        report
+    }
+
+    #[test]
+    fn passes_when_enough_updates_decode() {
+        let (mut warmup, now) = warmup(10);
+        assert!(feed(&mut warmup, now, 6, &[([9; 8], 3)]).is_none());
+        assert_eq!(warmup.report().state, WarmupState::Pending);
+
+        // The tenth message ends the phase: 6 of 10 is above the default 50%.
+        let report = warmup.record(KNOWN, true, now).unwrap();
+        assert_eq!(report.state, WarmupState::Passed);
+        assert_eq!((report.messages, report.recognized), (10, 7));
+        assert!(warmup.is_finished());
+    }
+
+    #[test]
+    fn fails_with_the_most_common_unknown_discriminators() {
+        let (mut warmup, now) = warmup(20);
+        let unknown = [
+            ([2; 8], 1),
+            ([3; 8], 4),
+            ([4; 8], 2),
+            ([5; 8], 4),
+            ([6; 8], 1),
+            ([7; 8], 3),
+        ];
+        let report = feed(&mut warmup, now, 5, &unknown).unwrap();
+
+        assert_eq!(report.state, WarmupState::Failed);
+        assert_eq!(report.ratio, 0.25);
+        // Most frequent first, ties by discriminator, capped at the top five.
+        let listed: Vec<_> = report
+            .unrecognized_discriminators
+            .iter()
+            .map(|d| (d.discriminator[0], d.count))
+            .collect();
+        assert_eq!(listed, [(3, 4), (5, 4), (7, 3), (4, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn updates_without_a_discriminator_count_against_the_ratio() {
+        let (mut warmup, now) = warmup(2);
+        warmup.record(KNOWN, true, now);
+        let report = warmup.record(None, false, now).unwrap();
+        assert_eq!(report.state, WarmupState::Passed);
+        assert_eq!(report.ratio, 0.5);
+        assert!(report.unrecognized_discriminators.is_empty());
+    }
+
+    #[test]
+    fn a_late_message_ends_the_phase() {
+        let (mut warmup, start) = warmup(50);
+        assert!(warmup.record(KNOWN, true, start).is_none());
+
+        let report = warmup
+            .record(KNOWN, true, warmup.deadline())
+            .expect("past the window");
+        assert_eq!(report.state, WarmupState::Passed);
+        assert_eq!(report.messages, 2);
+    }
+
+    #[test]
+    fn expiring_without_messages_is_inconclusive() {
+        let (mut warmup, _) = warmup(50);
+        let report = warmup.expire().unwrap();
+        assert_eq!(report.state, WarmupState::Inconclusive);
+    }
+
+    #[test]
+    fn reports_exactly_once() {
+        let (mut warmup, now) = warmup(1);
+        assert!(warmup.record(KNOWN, true, now).is_some());
+        assert!(warmup.record(KNOWN, true, now).is_none());
+        assert!(warmup.expire().is_none());
+        // Later messages no longer count.
+        assert_eq!(warmup.report().messages, 1);
+    }
+}
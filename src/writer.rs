@@ -0,0 +1,892 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, error, info, warn};
+
+use crate::clock::{Clock, Instant, Jitter};
+use crate::db::db::{upsert_polls, PgPool, PollKey, UpsertOutcome};
+use crate::db::models::{program_label, NewPoll};
+use crate::errors;
+use crate::metrics::Metrics;
+use crate::quarantine::{is_data_error, Admission, Quarantine};
+use crate::state::lifecycle::Transition;
+
+/// Batching knobs for the poll writer.
+#[derive(Debug, Clone, Copy)]
+pub struct WriterConfig {
+    /// Flush as soon as this many records are queued.
+    pub batch_size: usize,
+    /// Flush at most this long after the first record of a batch arrived.
+    pub flush_interval: Duration,
+    /// Clock skew tolerance for lifecycle transitions, in seconds (see `lifecycle::transition`).
+    pub lifecycle_skew_secs: i64,
+    /// Fewest batches flushed at once.
+    pub min_concurrency: usize,
+    /// Most batches flushed at once, see [`ConcurrencyController`].
+    pub max_concurrency: usize,
+    /// Share of the connection pool the writer may hold at once; caps `max_concurrency` so the
+    /// HTTP API and the other tasks always find a connection.
+    pub max_pool_share: f64,
+    /// Consecutive data errors after which an account is quarantined, see [`Quarantine`];
+    /// 0 never quarantines.
+    pub quarantine_after: u32,
+    /// Seed of the jitter spreading retry delays, for the same delays on every run; `None`
+    /// seeds it from the system.
+    pub retry_jitter_seed: Option<u64>,
+    /// Clock of the retry delays, and of the lifecycle evaluation of the polls written.
+    pub clock: Clock,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            flush_interval: Duration::from_millis(250),
+            lifecycle_skew_secs: 0,
+            min_concurrency: 1,
+            max_concurrency: 4,
+            max_pool_share: 0.5,
+            quarantine_after: 3,
+            retry_jitter_seed: None,
+            clock: Clock::system(),
+        }
+    }
+}
+
+/// Queue depth checks in a row that must agree before the concurrency goes up.
+const SCALE_UP_STREAK: u32 = 3;
+/// Queue depth checks in a row that must agree before the concurrency goes down. Longer than
+/// [`SCALE_UP_STREAK`], so a short lull in a burst doesn't give the connections back.
+const SCALE_DOWN_STREAK: u32 = 20;
+
+/// Decides how many batches the writer flushes at once, between a minimum and a maximum.
+///
+/// Checked once per batch: while at least a full batch keeps waiting in the channel, one more
+/// flush is allowed in parallel, unless flushes have become slow (`latency_ceiling`), in which
+/// case the database is the bottleneck and more writers would only add lock contention. Once
+/// the channel keeps being empty, one flush less is allowed. Both directions need a streak of
+/// agreeing checks, and scaling down needs a much longer one, so the level doesn't oscillate.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyController {
+    min: usize,
+    max: usize,
+    current: usize,
+    high_watermark: usize,
+    latency_ceiling: Duration,
+    busy_streak: u32,
+    idle_streak: u32,
+}
+
+impl ConcurrencyController {
+    /// Starts at `min`. A queue of `high_watermark` records or more counts as backed up.
+    pub fn new(min: usize, max: usize, high_watermark: usize, latency_ceiling: Duration) -> Self {
+        let min = min.max(1);
+        Self {
+            min,
+            max: max.max(min),
+            current: min,
+            high_watermark: high_watermark.max(1),
+            latency_ceiling,
+            busy_streak: 0,
+            idle_streak: 0,
+        }
+    }
+
+    /// Batches that may be flushed at once.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Feeds the current queue depth and the latency of recent flushes, and returns the new
+    /// concurrency when it changed.
+    pub fn observe(&mut self, queue_depth: usize, latency: Duration) -> Option<usize> {
+        if queue_depth >= self.high_watermark && latency <= self.latency_ceiling {
+            self.busy_streak += 1;
+            self.idle_streak = 0;
+        } else if queue_depth == 0 {
+            self.idle_streak += 1;
+            self.busy_streak = 0;
+        } else {
+            self.busy_streak = 0;
+            self.idle_streak = 0;
+        }
+
+        if self.busy_streak >= SCALE_UP_STREAK && self.current < self.max {
+            self.busy_streak = 0;
+            self.current += 1;
+            Some(self.current)
+        } else if self.idle_streak >= SCALE_DOWN_STREAK && self.current > self.min {
+            self.idle_streak = 0;
+            self.current -= 1;
+            Some(self.current)
+        } else {
+            None
+        }
+    }
+}
+
+/// Counters shared between the senders and the writer task.
+#[derive(Debug, Default)]
+pub struct WriterStats {
+    queued: AtomicU64,
+    written: AtomicU64,
+    failed: AtomicU64,
+    skipped: AtomicU64,
+    stale: AtomicU64,
+    /// `failed`, by program.
+    failed_by_program: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl WriterStats {
+    /// Records handed to the writer so far.
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Records written to their row.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Records that failed to commit.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Records of `program` that failed to commit. The writer is shared by every program, so
+    /// this is what tells whether one of them lost an update.
+    pub fn failed_for(&self, program: &[u8]) -> u64 {
+        self.failed_by_program
+            .lock()
+            .unwrap()
+            .get(program)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Records of quarantined accounts, not written.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Records not written because their row already holds newer data, or because a newer
+    /// record of the same poll was in their batch.
+    pub fn stale(&self) -> u64 {
+        self.stale.load(Ordering::Relaxed)
+    }
+
+    /// Records the writer is done with: written, failed, skipped or stale.
+    pub fn settled(&self) -> u64 {
+        self.written() + self.failed() + self.skipped() + self.stale()
+    }
+
+    /// Records queued but not yet flushed (either way).
+    pub fn pending(&self) -> u64 {
+        self.queued().saturating_sub(self.settled())
+    }
+}
+
+/// Sending half of the poll writer.
+#[derive(Clone)]
+pub struct PollWriter {
+    tx: mpsc::Sender<NewPoll>,
+    stats: Arc<WriterStats>,
+}
+
+impl PollWriter {
+    /// Queues a poll for the next batch, waiting for room when the channel is full.
+    /// Fails only when the writer task is gone.
+    pub async fn send(&self, poll: NewPoll) -> anyhow::Result<()> {
+        if self.tx.send(poll).await.is_err() {
+            anyhow::bail!("poll writer task has stopped");
+        }
+        // Counted once it's actually in the channel: a send cancelled mid-wait never got there.
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Counters shared with the writer task, e.g. to report progress while draining.
+    pub fn stats(&self) -> Arc<WriterStats> {
+        self.stats.clone()
+    }
+}
+
+/// Spawns the task that owns all poll writes.
+///
+/// Decoded polls are sent over the returned bounded channel. The task accumulates them into
+/// batches (up to `batch_size` records or `flush_interval`, whichever comes first) and writes
+/// each batch with one multi-row upsert in a single transaction, instead of one blocking task
+/// and one tiny transaction per message.
+///
+/// Up to [`ConcurrencyController::current`] batches are flushed at once, so bursts (backfills)
+/// get more connections and quiet periods hold a single one. Lowering the concurrency only
+/// stops new flushes from starting: batches already being written always complete. Concurrent
+/// flushes can't deadlock (rows are locked in key order) and the slot guard keeps an older
+/// batch finishing last from overwriting newer data.
+///
+/// When the channel is full, senders wait (`send().await`) rather than dropping updates.
+/// Once every sender is dropped, the task flushes whatever is still queued and exits, so
+/// awaiting the returned handle after dropping the senders guarantees nothing is lost.
+/// Flushed records and the channel depth are reported to `metrics`.
+///
+/// A batch that fails because of its data is written again record by record, so one bad account
+/// doesn't hold back the others, and accounts that keep failing are quarantined (see
+/// [`Quarantine`]).
+pub fn spawn_poll_writer(
+    pool: PgPool,
+    config: WriterConfig,
+    metrics: Arc<Metrics>,
+) -> (PollWriter, JoinHandle<()>) {
+    let batch_size = config.batch_size.max(1);
+    // A few batches worth of headroom absorbs bursts without unbounded memory growth.
+    let (tx, mut rx) = mpsc::channel::<NewPoll>(batch_size * 4);
+    let stats = Arc::new(WriterStats::default());
+    let task_stats = stats.clone();
+
+    // Never hold more than the allowed share of the pool, but always at least one connection.
+    let pool_cap = ((pool.max_size() as f64 * config.max_pool_share) as usize).max(1);
+    let mut controller = ConcurrencyController::new(
+        config.min_concurrency,
+        config.max_concurrency.min(pool_cap),
+        batch_size,
+        config.flush_interval * 4,
+    );
+    metrics.writer_concurrency.set(controller.current() as i64);
+    let jitter = config
+        .retry_jitter_seed
+        .map_or_else(Jitter::default, Jitter::seeded);
+    let quarantine = Arc::new(
+        Quarantine::new(config.quarantine_after)
+            .with_jitter(jitter)
+            .with_clock(config.clock),
+    );
+
+    let handle = tokio::spawn(async move {
+        let mut in_flight: JoinSet<Duration> = JoinSet::new();
+        // Average flush duration, smoothed over the last few batches.
+        let mut latency = Duration::ZERO;
+        let record_latency = |latency: &mut Duration, result: Result<Duration, _>| {
+            if let Ok(took) = result {
+                *latency = (*latency * 3 + took) / 4;
+            }
+        };
+
+        // Wait for the first record of the next batch; `None` means every sender is gone
+        // and the channel is fully drained.
+        while let Some(first) = rx.recv().await {
+            let mut batch = Vec::with_capacity(batch_size);
+            batch.push(first);
+
+            let deadline = Instant::now() + config.flush_interval;
+            while batch.len() < batch_size {
+                match tokio::time::timeout_at(deadline, rx.recv()).await {
+                    Ok(Some(poll)) => batch.push(poll),
+                    // Channel closed or deadline reached: flush what we have.
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            metrics.writer_queue_depth.set(rx.len() as i64);
+
+            while let Some(result) = in_flight.try_join_next() {
+                record_latency(&mut latency, result);
+            }
+            let previous = controller.current();
+            if let Some(current) = controller.observe(rx.len(), latency) {
+                metrics.writer_concurrency.set(current as i64);
+                info!(
+                    from = previous,
+                    to = current,
+                    queue_depth = rx.len(),
+                    latency_ms = latency.as_millis() as u64,
+                    "Writer concurrency {}",
+                    if current > previous {
+                        "raised"
+                    } else {
+                        "lowered"
+                    }
+                );
+            }
+            // Wait for a free slot; batches in flight always run to completion.
+            while in_flight.len() >= controller.current() {
+                match in_flight.join_next().await {
+                    Some(result) => record_latency(&mut latency, result),
+                    None => break,
+                }
+            }
+
+            let (pool, stats, metrics) = (pool.clone(), task_stats.clone(), metrics.clone());
+            let quarantine = quarantine.clone();
+            let (skew, clock) = (config.lifecycle_skew_secs, config.clock);
+            in_flight.spawn(async move {
+                let started = Instant::now();
+                flush_batch(&pool, batch, skew, clock, quarantine, &stats, &metrics).await;
+                started.elapsed()
+            });
+        }
+        while in_flight.join_next().await.is_some() {}
+        debug!("Poll writer drained, exiting");
+    });
+
+    (PollWriter { tx, stats }, handle)
+}
+
+/// What became of one batch.
+#[derive(Default)]
+struct BatchResult {
+    outcomes: Vec<(PollKey, UpsertOutcome)>,
+    written: usize,
+    failed: usize,
+    skipped: usize,
+    stale: usize,
+    /// `failed`, by program.
+    failed_by_program: HashMap<Vec<u8>, u64>,
+    /// Records to try again: the batch failed for reasons other than its data.
+    retry: Vec<NewPoll>,
+    /// Last error, if any record failed.
+    error: Option<anyhow::Error>,
+}
+
+impl BatchResult {
+    /// Counts the records of a successful upsert, by outcome. `admitted` records without an
+    /// outcome were superseded by a newer record of the same poll, so are stale too.
+    fn upserted(&mut self, admitted: usize, outcomes: Vec<(PollKey, UpsertOutcome)>) {
+        let written = outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, UpsertOutcome::Written(_)))
+            .count();
+        self.written += written;
+        self.stale += admitted - written;
+        self.outcomes.extend(outcomes);
+    }
+
+    /// Counts a record of `program` that failed to commit.
+    fn fail(&mut self, program: &[u8]) {
+        self.failed += 1;
+        *self.failed_by_program.entry(program.to_vec()).or_default() += 1;
+    }
+}
+
+/// Writes one batch on a blocking thread (Diesel is synchronous) and logs the outcomes.
+///
+/// A batch that fails for reasons other than its data is tried again after the delay
+/// [`Quarantine::interrupted`] gives, until it runs out of retries.
+async fn flush_batch(
+    pool: &PgPool,
+    mut batch: Vec<NewPoll>,
+    skew: i64,
+    clock: Clock,
+    quarantine: Arc<Quarantine>,
+    stats: &WriterStats,
+    metrics: &Metrics,
+) {
+    let mut attempt = 0;
+    loop {
+        let size = batch.len();
+        let programs: Vec<Vec<u8>> = batch.iter().map(|poll| poll.program_id.clone()).collect();
+        let task_pool = pool.clone();
+        let task_quarantine = quarantine.clone();
+        let mut result = match tokio::task::spawn_blocking(move || {
+            write_batch(&task_pool, batch, skew, clock.unix_now(), &task_quarantine)
+        })
+        .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                let mut result = BatchResult {
+                    error: Some(anyhow::anyhow!("DB batch upsert task panicked: {}", e)),
+                    ..Default::default()
+                };
+                for program in &programs {
+                    result.fail(program);
+                }
+                result
+            }
+        };
+
+        let retry = std::mem::take(&mut result.retry);
+        let delay = if retry.is_empty() {
+            None
+        } else {
+            quarantine.interrupted(attempt)
+        };
+        match delay {
+            Some(delay) => warn!(
+                size,
+                retry = attempt + 1,
+                error = ?result.error,
+                "DB batch upsert failed, retrying in {:?}", delay
+            ),
+            // Out of retries: the records are given up.
+            None => {
+                for poll in &retry {
+                    result.fail(&poll.program_id);
+                }
+            }
+        }
+        record_batch(&result, size, &quarantine, stats, metrics);
+
+        let Some(delay) = delay else {
+            return;
+        };
+        clock.sleep(delay).await;
+        batch = retry;
+        attempt += 1;
+    }
+}
+
+/// Counts and logs what became of a batch of `size` records.
+fn record_batch(
+    result: &BatchResult,
+    size: usize,
+    quarantine: &Quarantine,
+    stats: &WriterStats,
+    metrics: &Metrics,
+) {
+    // Before the totals, so whoever sees the records settled also sees which program failed.
+    if !result.failed_by_program.is_empty() {
+        let mut failed = stats.failed_by_program.lock().unwrap();
+        for (program, count) in &result.failed_by_program {
+            *failed.entry(program.clone()).or_default() += count;
+        }
+    }
+    for (counter, count) in [
+        (&stats.written, result.written),
+        (&stats.failed, result.failed),
+        (&stats.skipped, result.skipped),
+        (&stats.stale, result.stale),
+    ] {
+        counter.fetch_add(count as u64, Ordering::Relaxed);
+    }
+    for (label, count) in [
+        ("ok", result.written),
+        ("failed", result.failed),
+        ("quarantined", result.skipped),
+        ("stale", result.stale),
+    ] {
+        if count > 0 {
+            metrics
+                .db_upserts
+                .with_label_values(&[label])
+                .inc_by(count as u64);
+        }
+    }
+    metrics.quarantined_accounts.set(quarantine.len() as i64);
+
+    debug!(
+        size,
+        written = result.written,
+        skipped = result.skipped,
+        stale = result.stale,
+        polls = result.outcomes.len(),
+        "Flushed poll batch"
+    );
+    if let (Some(e), true) = (&result.error, result.failed > 0) {
+        let code = errors::classify(e).unwrap_or(&errors::INTERNAL);
+        error!(size, failed = result.failed, error = ?e, %code, "DB batch upsert failed");
+    }
+    for (key, outcome) in &result.outcomes {
+        log_upsert_outcome(key, outcome, Some(metrics));
+    }
+}
+
+/// Writes a batch, leaving out the records of quarantined accounts, with their lifecycle
+/// evaluated at unix time `now`.
+///
+/// When the batch fails because of its data, the records are written one at a time, so only the
+/// bad ones fail and count towards their account's quarantine. Other errors (connection lost,
+/// ...) hand the whole batch back for a retry, without blaming any account.
+fn write_batch(
+    pool: &PgPool,
+    batch: Vec<NewPoll>,
+    skew: i64,
+    now: i64,
+    quarantine: &Quarantine,
+) -> BatchResult {
+    if let Err(e) = quarantine.refresh(pool) {
+        warn!(error = ?e, "Failed to reload quarantined accounts");
+    }
+    let mut result = BatchResult::default();
+    let mut admitted = Vec::with_capacity(batch.len());
+    for poll in batch {
+        match quarantine.admit(&poll) {
+            Admission::Write => admitted.push(poll),
+            Admission::Retry => {
+                debug!(
+                    account = %program_label(&poll.account_pubkey),
+                    "Data of a quarantined account changed, trying it again"
+                );
+                admitted.push(poll);
+            }
+            Admission::Skip => result.skipped += 1,
+        }
+    }
+
+    match upsert_polls(pool, &admitted, skew, now) {
+        Ok(outcomes) => {
+            for poll in &admitted {
+                quarantine.succeeded(pool, poll);
+            }
+            result.upserted(admitted.len(), outcomes);
+        }
+        Err(e) if is_data_error(&e) => {
+            for poll in &admitted {
+                match upsert_polls(pool, std::slice::from_ref(poll), skew, now) {
+                    Ok(outcomes) => {
+                        quarantine.succeeded(pool, poll);
+                        result.upserted(1, outcomes);
+                    }
+                    Err(e) => {
+                        if is_data_error(&e) {
+                            quarantine.failed(pool, poll, &e);
+                        }
+                        result.fail(&poll.program_id);
+                        result.error = Some(e);
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            result.retry = admitted;
+            result.error = Some(e);
+        }
+    }
+    result
+}
+
+/// Emits the event for one upserted poll: its lifecycle transition, or the skipped stale update.
+pub fn log_upsert_outcome(key: &PollKey, outcome: &UpsertOutcome, metrics: Option<&Metrics>) {
+    let (program, poll_id) = key;
+    match outcome {
+        UpsertOutcome::Written(transition) => log_lifecycle_transition(key, transition, metrics),
+        UpsertOutcome::Stale {
+            incoming_slot,
+            stored_slot,
+        } => debug!(
+            program = %program_label(program),
+            poll_id,
+            "Skipped stale update at slot {} (have {})", incoming_slot, stored_slot
+        ),
+    }
+}
+
+/// Emits a lifecycle event for a poll transition, and counts it in `metrics` if given.
+/// Unchanged states are silent; rejected transitions have already been stored as anomalies;
+/// suppressed flaps are only counted and logged at debug level.
+pub fn log_lifecycle_transition(key: &PollKey, transition: &Transition, metrics: Option<&Metrics>) {
+    let (program, poll_id) = key;
+    match transition {
+        Transition::Unchanged(_) => {}
+        Transition::Moved { from, to } => {
+            if let Some(metrics) = metrics {
+                metrics
+                    .lifecycle_transitions
+                    .with_label_values(&[to.as_str()])
+                    .inc();
+            }
+            info!(
+                program = %program_label(program),
+                poll_id,
+                from = from.map(|state| state.as_str()).unwrap_or("new"),
+                to = %to,
+                "Poll lifecycle transition"
+            )
+        }
+        Transition::Rejected { from, to } => warn!(
+            program = %program_label(program),
+            poll_id,
+            from = %from,
+            to = %to,
+            "Impossible poll lifecycle transition rejected"
+        ),
+        Transition::Suppressed { kept, derived } => {
+            if let Some(metrics) = metrics {
+                metrics.lifecycle_flaps_suppressed.inc();
+            }
+            debug!(
+                program = %program_label(program),
+                poll_id,
+                kept = %kept,
+                derived = %derived,
+                "Lifecycle flap within clock skew tolerance suppressed"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::{list_polls, list_quarantined};
+    use crate::db::test_support::{new_poll, test_pool, NOW};
+
+    const FAST: Duration = Duration::from_millis(10);
+    const SLOW: Duration = Duration::from_secs(2);
+
+    fn controller() -> ConcurrencyController {
+        ConcurrencyController::new(1, 4, 100, Duration::from_secs(1))
+    }
+
+    /// Feeds `checks` identical observations; returns the concurrency after each one.
+    fn observe(
+        controller: &mut ConcurrencyController,
+        checks: u32,
+        queue_depth: usize,
+        latency: Duration,
+    ) -> Vec<usize> {
+        (0..checks)
+            .map(|_| {
+                controller.observe(queue_depth, latency);
+                controller.current()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scales_up_after_a_streak_of_backed_up_checks() {
+        let mut controller = controller();
+        assert_eq!(controller.current(), 1);
+        assert_eq!(observe(&mut controller, 6, 100, FAST), [1, 1, 2, 2, 2, 3]);
+        // Never above the maximum.
+        observe(&mut controller, 20, 500, FAST);
+        assert_eq!(controller.current(), 4);
+    }
+
+    #[test]
+    fn slow_flushes_keep_the_concurrency() {
+        let mut controller = controller();
+        assert_eq!(observe(&mut controller, 10, 500, SLOW), [1; 10]);
+    }
+
+    #[test]
+    fn scales_down_only_after_a_long_idle_streak() {
+        let mut controller = controller();
+        observe(&mut controller, 9, 100, FAST);
+        assert_eq!(controller.current(), 4);
+
+        // A short lull in a burst gives nothing back.
+        observe(&mut controller, SCALE_DOWN_STREAK - 1, 0, FAST);
+        observe(&mut controller, 1, 50, FAST);
+        observe(&mut controller, SCALE_DOWN_STREAK - 1, 0, FAST);
+        assert_eq!(controller.current(), 4);
+
+        observe(&mut controller, 1, 0, FAST);
+        assert_eq!(controller.current(), 3);
+        // Never below the minimum.
+        observe(&mut controller, SCALE_DOWN_STREAK * 10, 0, FAST);
+        assert_eq!(controller.current(), 1);
+    }
+
+    #[test]
+    fn bounds_are_sanitized() {
+        let controller = ConcurrencyController::new(0, 0, 0, FAST);
+        assert_eq!(
+            (controller.min, controller.max, controller.current()),
+            (1, 1, 1)
+        );
+        let controller = ConcurrencyController::new(3, 2, 100, FAST);
+        assert_eq!((controller.min, controller.max), (3, 3));
+    }
+
+    /// A burst drained by batches of `BATCH`: each tick, `current()` batches are flushed, then
+    /// the controller sees what is left in the queue.
+    #[test]
+    fn simulated_burst_scales_up_then_back_down_and_writes_everything() {
+        const BATCH: usize = 100;
+        let mut controller = ConcurrencyController::new(1, 4, BATCH, Duration::from_secs(1));
+        let mut queue = 0;
+        let (mut received, mut written) = (0, 0);
+        let mut levels = Vec::new();
+
+        for tick in 0..200 {
+            // 350 records per tick for 40 ticks, then a trickle, then nothing.
+            let arriving = match tick {
+                0..40 => 350,
+                40..60 => 20,
+                _ => 0,
+            };
+            queue += arriving;
+            received += arriving;
+
+            let flushed = queue.min(controller.current() * BATCH);
+            queue -= flushed;
+            written += flushed;
+            controller.observe(queue, FAST);
+            levels.push(controller.current());
+        }
+
+        assert_eq!(levels.iter().max(), Some(&4));
+        assert!(levels[..40].windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(levels.last(), Some(&1));
+        assert_eq!((queue, written), (0, received));
+    }
+
+    /// Readings of a clock a few seconds off either way around `poll_start`, one a second, e.g.
+    /// listeners whose clocks disagree re-evaluating the same poll.
+    #[tokio::test(start_paused = true)]
+    async fn a_jittery_clock_at_a_boundary_moves_and_notifies_once() {
+        use crate::state::lifecycle::{transition, PollLifecycle};
+
+        let metrics = Metrics::new().unwrap();
+        let poll = new_poll(&[0x46; 32], 1, 100);
+        let facts = poll.lifecycle_facts();
+        let key = (poll.program_id.clone(), poll.poll_id);
+        let clock =
+            Clock::starting_at(chrono::DateTime::from_timestamp(poll.poll_start - 20, 0).unwrap());
+
+        let mut state = PollLifecycle::Upcoming;
+        let mut moves = 0;
+        for tick in 0..40 {
+            let jitter = [4, -4, 1, -2, 0, 3, -3][tick % 7];
+            let now = clock.now().timestamp() + jitter;
+            let transition = transition(Some(state), &facts, now, 5);
+            log_lifecycle_transition(&key, &transition, Some(&metrics));
+            if transition.resulting_state() != state {
+                moves += 1;
+                state = transition.resulting_state();
+            }
+            clock.sleep(Duration::from_secs(1)).await;
+        }
+
+        assert_eq!((moves, state), (1, PollLifecycle::Active));
+        let notified: u64 = PollLifecycle::ALL
+            .iter()
+            .map(|to| {
+                metrics
+                    .lifecycle_transitions
+                    .with_label_values(&[to.as_str()])
+                    .get()
+            })
+            .sum();
+        assert_eq!(notified, 1);
+        assert!(metrics.lifecycle_flaps_suppressed.get() > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_burst_is_written_in_full() {
+        let pool = test_pool();
+        let program = [0x40; 32];
+        let config = WriterConfig {
+            batch_size: 10,
+            ..WriterConfig::default()
+        };
+        let (writer, task) =
+            spawn_poll_writer(pool.clone(), config, Arc::new(Metrics::new().unwrap()));
+        let stats = writer.stats();
+
+        for poll_id in 0..250 {
+            writer.send(new_poll(&program, poll_id, 100)).await.unwrap();
+        }
+        drop(writer);
+        task.await.unwrap();
+
+        assert_eq!(
+            (stats.queued(), stats.written(), stats.pending()),
+            (250, 250, 0)
+        );
+        assert_eq!(list_polls(&pool, Some(&program)).unwrap().len(), 250);
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_partial_batch_is_flushed_after_the_interval() {
+        let pool = test_pool();
+        let program = [0x43; 32];
+        let config = WriterConfig::default();
+        let (writer, task) =
+            spawn_poll_writer(pool.clone(), config, Arc::new(Metrics::new().unwrap()));
+        let stats = writer.stats();
+        let clock = config.clock;
+        let started = clock.instant();
+
+        for poll_id in 0..3 {
+            writer.send(new_poll(&program, poll_id, 100)).await.unwrap();
+        }
+        clock
+            .sleep(config.flush_interval - Duration::from_millis(1))
+            .await;
+        assert_eq!(stats.written(), 0);
+        // The write itself holds virtual time still.
+        while stats.written() < 3 {
+            clock.sleep(Duration::from_millis(1)).await;
+        }
+        let waited = started.elapsed() - config.flush_interval;
+        assert!(waited <= Duration::from_millis(2), "{:?}", waited);
+
+        drop(writer);
+        task.await.unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    fn stale_records_are_not_counted_as_written() {
+        let pool = test_pool();
+        let program = [0x41; 32];
+        let quarantine = Quarantine::new(3);
+
+        let first = write_batch(&pool, vec![new_poll(&program, 1, 200)], 0, NOW, &quarantine);
+        let second = write_batch(
+            &pool,
+            vec![
+                new_poll(&program, 1, 100),
+                new_poll(&program, 2, 100),
+                new_poll(&program, 2, 150),
+            ],
+            0,
+            NOW,
+            &quarantine,
+        );
+
+        assert_eq!((first.written, first.stale), (1, 0));
+        // Poll 1 is older than the stored row, and the first record of poll 2 is superseded.
+        assert_eq!((second.written, second.stale, second.failed), (1, 2, 0));
+        assert_eq!(second.outcomes.len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in TEST_DATABASE_URL"]
+    async fn a_failing_account_is_quarantined_while_others_flow() {
+        let pool = test_pool();
+        let program = [0x42; 32];
+        let config = WriterConfig {
+            batch_size: 5,
+            quarantine_after: 2,
+            ..WriterConfig::default()
+        };
+        let (writer, task) =
+            spawn_poll_writer(pool.clone(), config, Arc::new(Metrics::new().unwrap()));
+        let stats = writer.stats();
+
+        for slot in 0..4 {
+            for poll_id in 0..5 {
+                let mut poll = new_poll(&program, poll_id, 100 + slot);
+                if poll_id == 3 {
+                    // Postgres rejects NUL characters in text columns.
+                    poll.poll_name = "bad\0name".to_string();
+                }
+                writer.send(poll).await.unwrap();
+            }
+        }
+        drop(writer);
+        task.await.unwrap();
+
+        // The bad account fails twice, then its (unchanged) updates are skipped.
+        assert_eq!(
+            (
+                stats.written(),
+                stats.failed(),
+                stats.skipped(),
+                stats.pending()
+            ),
+            (16, 2, 2, 0)
+        );
+        assert_eq!(list_polls(&pool, Some(&program)).unwrap().len(), 4);
+        let quarantined = list_quarantined(&pool).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].poll_id, 3);
+    }
+}